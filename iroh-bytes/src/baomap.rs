@@ -1,5 +1,11 @@
 //! Traits for in-memory or persistent maps of blob with bao encoded outboards.
-use std::{collections::BTreeSet, io, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     collection::CollectionParser,
@@ -7,20 +13,77 @@ use crate::{
         progress::{IdGenerator, ProgressSender},
         BlobFormat, HashAndFormat, RpcError, Tag,
     },
-    Hash,
+    Hash, IROH_BLOCK_SIZE,
+};
+use anyhow::{bail, Context};
+use bao_tree::{
+    blake3,
+    io::fsm::{Outboard, OutboardMut},
+    ByteNum, ChunkNum, ChunkRanges, TreeNode,
 };
-use bao_tree::{blake3, ChunkNum};
 use bytes::Bytes;
-use futures::{future::BoxFuture, stream::LocalBoxStream, StreamExt};
-use genawaiter::rc::{Co, Gen};
-use iroh_io::AsyncSliceReader;
+use futures::{
+    future::{self, BoxFuture, LocalBoxFuture},
+    stream::LocalBoxStream,
+    Future, FutureExt, Stream, StreamExt,
+};
+use genawaiter::{
+    rc::{Co, Gen},
+    GeneratorState,
+};
+use iroh_io::{AsyncSliceReader, AsyncSliceWriter};
 use range_collections::RangeSet2;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
+use tokio_util::task::LocalPoolHandle;
 
 pub use bao_tree;
 pub use range_collections;
 
+/// How long [`Map::data_written`] waits at most, for stores that do not know when data is
+/// written to their partial entries.
+pub const DATA_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Default number of bytes between progress updates of a validation, 16 MiB.
+pub const DEFAULT_VALIDATE_PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// How [`ValidateProgress::Progress`] updates are delivered to a consumer that is behind.
+///
+/// All other messages, in particular [`ValidateProgress::Done`] and
+/// [`ValidateProgress::AllDone`], are always delivered, waiting for the consumer if needed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidateProgressPolicy {
+    /// Skip progress updates while the consumer is behind, so validation never waits for
+    /// them. Since the offsets of an entry only grow, the next update that is delivered
+    /// covers the skipped ones.
+    #[default]
+    Coalesce,
+    /// Wait until the consumer has room for each progress update, so validation is slowed
+    /// down to the pace of the consumer.
+    Backpressure,
+}
+
+/// Options for [`ReadableStore::validate_with_options`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidateOptions {
+    /// The maximum number of blobs validated at the same time.
+    pub concurrency: usize,
+    /// The number of bytes of a blob between progress updates.
+    pub progress_interval: u64,
+    /// How progress updates are delivered to a slow consumer.
+    pub progress: ValidateProgressPolicy,
+}
+
+impl Default for ValidateOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: num_cpus::get(),
+            progress_interval: DEFAULT_VALIDATE_PROGRESS_INTERVAL,
+            progress: ValidateProgressPolicy::default(),
+        }
+    }
+}
+
 /// The availability status of an entry in a store.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum EntryStatus {
@@ -51,6 +114,9 @@ pub trait MapEntry<D: Map>: Clone + Send + Sync + 'static {
     fn is_complete(&self) -> bool;
     /// Compute the available ranges.
     ///
+    /// For incomplete entries, these must be covered by both the data and the outboard, in
+    /// whole chunk groups, since the provider sends exactly these ranges of partial blobs.
+    ///
     /// Depending on the implementation, this may be an expensive operation.
     ///
     /// It can also only ever be a best effort, since the underlying data may
@@ -61,6 +127,59 @@ pub trait MapEntry<D: Map>: Clone + Send + Sync + 'static {
     fn outboard(&self) -> BoxFuture<'_, io::Result<D::Outboard>>;
     /// A future that resolves to a reader that can be used to read the data
     fn data_reader(&self) -> BoxFuture<'_, io::Result<D::DataReader>>;
+    /// Summarize the shape of the outboard tree of this entry, for debugging.
+    ///
+    /// If `levels` is true, the hash pairs of all nodes stored in the outboard
+    /// are loaded as well, grouped by level from the root downwards. Nodes that
+    /// are not yet available in a partial outboard are skipped.
+    fn tree_info(&self, levels: bool) -> LocalBoxFuture<'_, io::Result<TreeInfo>> {
+        async move {
+            let mut outboard = self.outboard().await?;
+            let tree = outboard.tree();
+            let mut by_level = BTreeMap::<u32, Vec<TreeNode>>::new();
+            for node in tree.pre_order_nodes_iter() {
+                by_level.entry(node.level()).or_default().push(node);
+            }
+            let levels = if levels {
+                let mut res = Vec::with_capacity(by_level.len());
+                for nodes in by_level.values().rev() {
+                    let mut pairs = Vec::with_capacity(nodes.len());
+                    for node in nodes {
+                        if let Some((l, r)) = outboard.load(*node).await? {
+                            pairs.push((l.into(), r.into()));
+                        }
+                    }
+                    res.push(pairs);
+                }
+                Some(res)
+            } else {
+                None
+            };
+            Ok(TreeInfo {
+                root: outboard.root().into(),
+                size: tree.size().0,
+                chunks: tree.chunks().0,
+                depth: by_level.len() as u32,
+                levels,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Information about the outboard tree of a blob, see [`MapEntry::tree_info`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TreeInfo {
+    /// The root hash of the tree.
+    pub root: Hash,
+    /// The size of the blob in bytes.
+    pub size: u64,
+    /// The number of chunks of the blob.
+    pub chunks: u64,
+    /// The number of levels of hash pairs stored in the outboard.
+    pub depth: u32,
+    /// The hash pairs of the stored nodes, level by level starting at the root.
+    pub levels: Option<Vec<Vec<(Hash, Hash)>>>,
 }
 
 /// A generic collection of blobs with precomputed outboards
@@ -91,6 +210,16 @@ pub trait Map: Clone + Send + Sync + 'static {
     /// Note that this does not actually verify the on-disc data, but only checks in which section
     /// of the store the entry is present.
     fn contains(&self, hash: &Hash) -> EntryStatus;
+
+    /// Wait until data might have been written to the entry for `hash`.
+    ///
+    /// This is used to serve partial entries while they are being written, see
+    /// [`crate::provider::send_live_blob`]. Spurious wakeups are allowed, callers check what
+    /// is available after waking up. The default implementation waits for
+    /// [`DATA_POLL_INTERVAL`], so callers poll the entry.
+    fn data_written(&self, _hash: &Hash) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(DATA_POLL_INTERVAL).boxed()
+    }
 }
 
 /// A partial entry
@@ -128,6 +257,12 @@ pub trait PartialMap: Map {
 
     /// Upgrade a partial entry to a complete entry.
     fn insert_complete(&self, entry: Self::PartialEntry) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Wake up tasks waiting in [`Map::data_written`] for `hash`.
+    ///
+    /// Writers of partial entries call this after writing data. The default implementation
+    /// does nothing.
+    fn notify_data_written(&self, _hash: &Hash) {}
 }
 
 /// Extension of BaoMap to add misc methods used by the rpc calls.
@@ -147,9 +282,49 @@ pub trait ReadableStore: Map {
     /// Temp tags
     fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static>;
 
-    /// Validate the database
+    /// list all pins that have not expired yet, with their remaining time to live
+    ///
+    /// A `None` time to live means the pin is permanent.
+    ///
+    /// This function should not block to perform io. The knowledge about
+    /// existing pins must be present in memory.
+    fn pins(
+        &self,
+    ) -> Box<dyn Iterator<Item = (HashAndFormat, Option<Duration>)> + Send + Sync + 'static>;
+
+    /// Validate the database, with the default [`ValidateOptions`].
+    ///
+    /// [`ValidateProgress::Done`] and [`ValidateProgress::AllDone`] are never dropped, so the
+    /// consumer always sees completion. See [`ValidateProgressPolicy`] for how progress
+    /// updates are delivered.
     fn validate(&self, tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>>;
 
+    /// Validate the database, with at most `concurrency` blobs validated at the same time.
+    ///
+    /// See [`ReadableStore::validate`]. The default implementation ignores `concurrency`, for
+    /// stores that can not validate blobs concurrently.
+    fn validate_with_concurrency(
+        &self,
+        concurrency: usize,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let _ = concurrency;
+        self.validate(tx)
+    }
+
+    /// Validate the database with the given `options`.
+    ///
+    /// See [`ReadableStore::validate`]. The default implementation only uses the concurrency
+    /// of `options`, see [`ReadableStore::validate_with_concurrency`], and sends no progress
+    /// updates.
+    fn validate_with_options(
+        &self,
+        options: ValidateOptions,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.validate_with_concurrency(options.concurrency, tx)
+    }
+
     /// list partial blobs in the database
     fn partial_blobs(&self) -> Box<dyn Iterator<Item = Hash> + Send + Sync + 'static>;
 
@@ -166,6 +341,102 @@ pub trait ReadableStore: Map {
         mode: ExportMode,
         progress: impl Fn(u64) -> io::Result<()> + Send + Sync + 'static,
     ) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Find all tagged collections that contain the given blob.
+    ///
+    /// Only collections that are the target of a tag are searched. Collections that are only
+    /// kept alive by other means, e.g. temp tags, pins or the entries of documents, are not
+    /// found, and neither are collections nested in other collections.
+    ///
+    /// There is no index from blobs to the collections referencing them, so
+    /// this parses every tagged collection in the store and is O(collections).
+    /// Collections that are missing, partial or fail to parse are skipped.
+    fn collections_containing<'a>(
+        &'a self,
+        cp: impl CollectionParser + 'a,
+        child: Hash,
+    ) -> LocalBoxFuture<'a, Vec<Hash>> {
+        async move {
+            let collections = self
+                .tags()
+                .filter(|(_, haf)| haf.1.is_collection())
+                .map(|(_, HashAndFormat(hash, _))| hash)
+                .collect::<BTreeSet<_>>();
+            let mut res = Vec::new();
+            for hash in collections {
+                let Some(entry) = self.get(&hash) else {
+                    continue;
+                };
+                if !entry.is_complete() {
+                    continue;
+                }
+                let Ok(reader) = entry.data_reader().await else {
+                    continue;
+                };
+                let Ok((mut iter, _stats)) = cp.parse(reader).await else {
+                    continue;
+                };
+                while let Ok(Some(item)) = iter.next().await {
+                    if item == child {
+                        res.push(hash);
+                        break;
+                    }
+                }
+            }
+            res
+        }
+        .boxed_local()
+    }
+
+    /// Export the data of the complete blob `hash` for zero-copy reading in this process.
+    ///
+    /// Stores that keep the data of a blob in a file return a read-only memory map of it,
+    /// after verifying that the file still contains the blob. Data the store keeps in memory
+    /// is returned as [`Bytes`], see [`MappedBlob`]. The default implementation reads the
+    /// data into memory.
+    #[cfg(feature = "mmap")]
+    fn export_mmap(&self, hash: Hash) -> LocalBoxFuture<'_, io::Result<MappedBlob>> {
+        async move {
+            let entry = self
+                .get(&hash)
+                .filter(|entry| entry.is_complete())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "hash not found"))?;
+            let mut reader = entry.data_reader().await?;
+            let data = iroh_io::AsyncSliceReaderExt::read_to_end(&mut reader).await?;
+            Ok(MappedBlob::Bytes(data))
+        }
+        .boxed_local()
+    }
+
+    /// The total size of the children of the collection `hash`, in bytes.
+    ///
+    /// The collection is parsed with `cp` and the sizes of its children are looked up in
+    /// the store, so this is the amount of data a get request for the whole collection
+    /// transfers, not counting the collection blob itself. Children that are only partially
+    /// stored count with their full size.
+    ///
+    /// Returns `None` if the collection is not complete in the store, fails to parse, or any
+    /// of its children is missing, since then the size is not known.
+    fn collection_total_size<'a>(
+        &'a self,
+        cp: impl CollectionParser + 'a,
+        hash: Hash,
+    ) -> LocalBoxFuture<'a, Option<u64>> {
+        async move {
+            let entry = self.get(&hash)?;
+            if !entry.is_complete() {
+                return None;
+            }
+            let reader = entry.data_reader().await.ok()?;
+            let (mut iter, _stats) = cp.parse(reader).await.ok()?;
+            let mut total = 0u64;
+            while let Some(child) = iter.next().await.ok()? {
+                total = total.checked_add(self.get(&child)?.size())?;
+            }
+            Some(total)
+        }
+        .boxed_local()
+    }
 }
 
 /// The mutable part of a BaoDb
@@ -194,6 +465,33 @@ pub trait Store: ReadableStore + PartialMap {
     /// It is a special case of `import` that does not use the file system.
     fn import_bytes(&self, bytes: Bytes, format: BlobFormat) -> BoxFuture<'_, io::Result<TempTag>>;
 
+    /// This trait method imports data from a stream of bytes.
+    ///
+    /// It is a special case of `import` for data that is not available as a file, e.g. because
+    /// it is received over the network. Persistent stores write the data to their own storage
+    /// as it arrives instead of holding it in memory as a whole. If the stream yields an error,
+    /// nothing is imported and the error is returned.
+    ///
+    /// There is no [`ImportProgress::Found`] message, since the data has no path. A
+    /// [`ImportProgress::CopyProgress`] message is sent for every item of the stream, waiting
+    /// for the receiver, followed by the usual size and outboard messages.
+    ///
+    /// Returns the temp tag of the imported data and its size.
+    fn import_stream(
+        &self,
+        data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> BoxFuture<'_, io::Result<(TempTag, u64)>>;
+
+    /// The number of bytes that can still be written to this store, if known.
+    ///
+    /// Imports and downloads check this up front, see [`ensure_space`]. Stores that can not
+    /// tell return `None`, which skips the check.
+    fn available_space(&self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+
     /// Set a tag
     fn set_tag(&self, name: Tag, hash: Option<HashAndFormat>) -> BoxFuture<'_, io::Result<()>>;
 
@@ -203,6 +501,20 @@ pub trait Store: ReadableStore + PartialMap {
     /// Create a temporary pin for this store
     fn temp_tag(&self, value: HashAndFormat) -> TempTag;
 
+    /// Pin a blob or collection, protecting it from gc until `expiry`.
+    ///
+    /// A `None` expiry pins the content until it is unpinned. Pinning content that is
+    /// already pinned replaces the expiry. Expired pins are ignored by gc. Unlike temp tags,
+    /// pins are persisted by stores that persist their tags.
+    fn pin(
+        &self,
+        value: HashAndFormat,
+        expiry: Option<SystemTime>,
+    ) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Remove a pin created with [`Store::pin`].
+    fn unpin(&self, value: HashAndFormat) -> BoxFuture<'_, io::Result<()>>;
+
     /// Traverse all roots recursively and mark them as live.
     ///
     /// Poll this stream to completion to perform a full gc mark phase.
@@ -226,6 +538,32 @@ pub trait Store: ReadableStore + PartialMap {
         .boxed_local()
     }
 
+    /// List the blobs that are not reachable from any root.
+    ///
+    /// These are the blobs that a gc run with the same `extra_roots` would delete. The roots
+    /// and the traversal of collections are the same as for [`Self::gc_mark`], but the live
+    /// set is not changed, so this can be used to inspect the garbage before collecting it.
+    fn unreferenced_blobs<'a>(
+        &'a self,
+        cp: impl CollectionParser + 'a,
+        extra_roots: impl IntoIterator<Item = io::Result<HashAndFormat>> + 'a,
+    ) -> LocalBoxFuture<'a, anyhow::Result<Vec<Hash>>> {
+        async move {
+            let mut mark = Gen::new(|co| async move { live_set(self, cp, extra_roots, &co).await });
+            let live = loop {
+                match mark.async_resume().await {
+                    GeneratorState::Yielded(GcMarkEvent::CustomWarning(text, _)) => {
+                        tracing::warn!("{}", text);
+                    }
+                    GeneratorState::Yielded(_) => {}
+                    GeneratorState::Complete(live) => break live?,
+                }
+            };
+            Ok(self.blobs().filter(|hash| !live.contains(hash)).collect())
+        }
+        .boxed_local()
+    }
+
     /// Remove all blobs that are not marked as live.
     ///
     /// Poll this stream to completion to perform a full gc sweep. Not polling this stream
@@ -264,6 +602,17 @@ pub trait Store: ReadableStore + PartialMap {
 
     /// physically delete the given hash from the store.
     fn delete(&self, hash: &Hash) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Import a complete blob from another store, e.g. one in another data directory.
+    ///
+    /// See [`import_from_store`].
+    fn import_from_store<'a, O: Map>(
+        &'a self,
+        other: &'a O,
+        hash: Hash,
+    ) -> LocalBoxFuture<'a, io::Result<()>> {
+        import_from_store(self, other, hash).boxed_local()
+    }
 }
 
 /// A trait for things that can track liveness of blobs and collections.
@@ -352,6 +701,23 @@ async fn gc_mark_task<'a>(
     extra_roots: impl IntoIterator<Item = io::Result<HashAndFormat>> + 'a,
     co: &Co<GcMarkEvent>,
 ) -> anyhow::Result<()> {
+    let live = live_set(store, cp, extra_roots, co).await?;
+    co.yield_(GcMarkEvent::CustomInfo(format!(
+        "gc mark done. found {} live blobs",
+        live.len()
+    )))
+    .await;
+    store.add_live(live);
+    Ok(())
+}
+
+/// Collect the roots of the store and traverse them to find all live blobs.
+async fn live_set<'a>(
+    store: &'a impl Store,
+    cp: impl CollectionParser + 'a,
+    extra_roots: impl IntoIterator<Item = io::Result<HashAndFormat>> + 'a,
+    co: &Co<GcMarkEvent>,
+) -> anyhow::Result<BTreeSet<Hash>> {
     macro_rules! info {
         ($($arg:tt)*) => {
             co.yield_(GcMarkEvent::CustomInfo(format!($($arg)*))).await;
@@ -368,6 +734,11 @@ async fn gc_mark_task<'a>(
         info!("adding root {:?} {:?}", name, haf);
         roots.insert(haf);
     }
+    info!("traversing pins");
+    for (haf, ttl) in store.pins() {
+        info!("adding pin {:?} {:?}", haf, ttl);
+        roots.insert(haf);
+    }
     info!("traversing temp roots");
     for haf in store.temp_tags() {
         info!("adding temp pin {:?}", haf);
@@ -419,8 +790,404 @@ async fn gc_mark_task<'a>(
             }
         }
     }
-    info!("gc mark done. found {} live blobs", live.len());
-    store.add_live(live);
+    Ok(live)
+}
+
+/// Size of the pieces in which data is copied by [`import_from_store`].
+const IMPORT_CHUNK_SIZE: usize = 1024 * 64;
+
+/// Copy the complete blob `hash` from `other` into `db`.
+///
+/// The data and the outboard are copied as they are, so the outboard is not computed again.
+/// Before the blob is marked as complete, the copied data is validated against the copied
+/// outboard and the outboard against `hash`, so a corrupted source is not imported. Does
+/// nothing if the blob is already complete in `db`.
+pub async fn import_from_store<D: Store, O: Map>(db: &D, other: &O, hash: Hash) -> io::Result<()> {
+    let entry = other
+        .get(&hash)
+        .filter(|entry| entry.is_complete())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "complete entry not found"))?;
+    if db.contains(&hash) == EntryStatus::Complete {
+        return Ok(());
+    }
+    let size = entry.size();
+    let target = db.get_or_create_partial(hash, size)?;
+    let mut reader = entry.data_reader().await?;
+    let mut writer = target.data_writer().await?;
+    let mut offset = 0u64;
+    while offset < size {
+        let chunk = reader.read_at(offset, IMPORT_CHUNK_SIZE).await?;
+        if chunk.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "data ended before the size of the entry",
+            ));
+        }
+        let len = chunk.len() as u64;
+        writer.write_bytes_at(offset, chunk).await?;
+        offset += len;
+    }
+    writer.sync().await?;
+    let mut outboard = entry.outboard().await?;
+    let mut outboard_mut = target.outboard_mut().await?;
+    for node in outboard.tree().pre_order_nodes_iter() {
+        if let Some(pair) = outboard.load(node).await? {
+            outboard_mut.save(node, &pair).await?;
+        }
+    }
+    outboard_mut.sync().await?;
+    // validate what was written, the source is not trusted to match its hash
+    let mut data = target.data_reader().await?;
+    let mut outboard = target.outboard().await?;
+    bao_tree::io::fsm::encode_ranges_validated(
+        &mut data,
+        &mut outboard,
+        &ChunkRanges::all(),
+        tokio::io::sink(),
+    )
+    .await?;
+    db.insert_complete(target).await
+}
+
+/// The data of a blob exported with [`ReadableStore::export_mmap`].
+///
+/// Derefs to the data of the blob.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub enum MappedBlob {
+    /// A read-only memory map of the file the store keeps the data in.
+    ///
+    /// The mapping reflects the file, it must not be held across a deletion of the entry.
+    /// Once the entry is deleted, the file might be removed, truncated or reused, and reading
+    /// the mapping can return other data or crash the process.
+    Mmap(memmap2::Mmap),
+    /// The data of a blob the store keeps in memory, or has decoded because its file does
+    /// not hold the data as is, e.g. when it is compressed.
+    Bytes(Bytes),
+}
+
+#[cfg(feature = "mmap")]
+impl MappedBlob {
+    /// Maps `file` read-only and checks that it contains exactly the blob `hash`.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the file content does not match the hash.
+    pub fn map_verified(file: &std::fs::File, hash: &Hash) -> io::Result<Self> {
+        // SAFETY: the store owns the file, or was told that it does not change for external
+        // files. The contract of holding the mapping is documented on `MappedBlob::Mmap`.
+        let mmap = unsafe { memmap2::Mmap::map(file)? };
+        if Hash::from(blake3::hash(&mmap)) != *hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file content does not match the hash",
+            ));
+        }
+        Ok(Self::Mmap(mmap))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MappedBlob {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mmap(mmap) => mmap,
+            Self::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+/// Error returned when a [`Store`] does not have enough space left for an import or download.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("insufficient space: {required} bytes required, but only {available} bytes available")]
+pub struct InsufficientSpace {
+    /// The number of bytes that would be written.
+    pub required: u64,
+    /// The number of bytes that can still be written to the store.
+    pub available: u64,
+}
+
+/// The number of bytes needed to store a blob of `size` bytes, including its outboard.
+pub fn blob_space(size: u64) -> u64 {
+    size.saturating_add(bao_tree::io::outboard_size(size, IROH_BLOCK_SIZE))
+}
+
+/// Check that `required` bytes can be written to the store.
+///
+/// Fails with an [`InsufficientSpace`] error if the store reports less available space, see
+/// [`Store::available_space`]. Checking before an import or download avoids failing with an
+/// I/O error partway through and leaving a partial entry behind.
+pub fn ensure_space<D: Store>(db: &D, required: u64) -> io::Result<()> {
+    match db.available_space()? {
+        Some(available) if available < required => Err(io::Error::new(
+            io::ErrorKind::Other,
+            InsufficientSpace {
+                required,
+                available,
+            },
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Validate the data of a complete entry against its outboard.
+///
+/// This encodes the whole blob and discards the result, so every chunk of the data is
+/// checked against the hash tree, which in turn is checked against the hash of the entry.
+pub async fn validate_bao<D: Map>(entry: &impl MapEntry<D>) -> io::Result<()> {
+    validate_bao_with_progress(entry, u64::MAX, |_| future::ready(Ok(()))).await
+}
+
+/// Validate the data of a complete entry against its outboard, reporting progress.
+///
+/// Like [`validate_bao`], but the blob is validated in batches of `progress_interval` bytes,
+/// rounded down to whole chunk groups. After each batch `progress` is called with the number
+/// of bytes validated so far, the last call is with the size of the blob, and the returned
+/// future is awaited before the next batch. If it resolves to an error, validation stops and
+/// returns the error.
+pub async fn validate_bao_with_progress<D: Map, F: Future<Output = io::Result<()>>>(
+    entry: &impl MapEntry<D>,
+    progress_interval: u64,
+    mut progress: impl FnMut(u64) -> F,
+) -> io::Result<()> {
+    if !entry.is_complete() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "entry is incomplete",
+        ));
+    }
+    let group = IROH_BLOCK_SIZE.bytes() as u64;
+    let batch = progress_interval.max(group) / group * group;
+    let size = entry.size();
+    let mut outboard = entry.outboard().await?;
+    let mut data = entry.data_reader().await?;
+    let mut offset = 0u64;
+    loop {
+        let end = offset.saturating_add(batch);
+        // the last batch is open ended, so that the whole tree is validated, even if empty
+        let ranges = if end >= size {
+            ChunkRanges::from(ByteNum(offset).chunks()..)
+        } else {
+            ChunkRanges::from(ByteNum(offset).chunks()..ByteNum(end).chunks())
+        };
+        bao_tree::io::fsm::encode_ranges_validated(
+            &mut data,
+            &mut outboard,
+            &ranges,
+            tokio::io::sink(),
+        )
+        .await?;
+        offset = end.min(size);
+        progress(offset).await?;
+        if offset == size {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Send a [`ValidateProgress::Progress`] update for the entry `id` according to `policy`.
+///
+/// Fails if the receiver was dropped.
+fn send_validate_progress(
+    tx: &mpsc::Sender<ValidateProgress>,
+    policy: ValidateProgressPolicy,
+    id: u64,
+    offset: u64,
+) -> BoxFuture<'static, io::Result<()>> {
+    let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "progress receiver dropped");
+    let msg = ValidateProgress::Progress { id, offset };
+    match policy {
+        ValidateProgressPolicy::Coalesce => future::ready(match tx.try_send(msg) {
+            Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => Ok(()),
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(closed()),
+        })
+        .boxed(),
+        ValidateProgressPolicy::Backpressure => {
+            let tx = tx.clone();
+            async move { tx.send(msg).await.map_err(|_| closed()) }.boxed()
+        }
+    }
+}
+
+/// Validate the given blobs of a store, see [`validate_bao`].
+///
+/// Up to `options.concurrency` blobs are validated at the same time, each in its own task on
+/// `local_pool`, since the readers of an entry don't have to be `Send`. Progress is sent to
+/// `tx`, starting with [`ValidateProgress::Starting`], followed by an `Entry`, `Progress`
+/// messages every `options.progress_interval` bytes and a `Done` message for each blob, and
+/// finally [`ValidateProgress::AllDone`]. The ids are the positions of the blobs in
+/// `hashes`, the `Done` messages arrive in completion order. Blobs that are not in the store
+/// are skipped.
+pub async fn validate_blobs<D: Map>(
+    db: &D,
+    hashes: Vec<Hash>,
+    options: ValidateOptions,
+    local_pool: &LocalPoolHandle,
+    tx: mpsc::Sender<ValidateProgress>,
+) -> anyhow::Result<()> {
+    tx.send(ValidateProgress::Starting {
+        total: hashes.len() as u64,
+    })
+    .await?;
+    futures::stream::iter((0u64..).zip(hashes))
+        .map(|(id, hash)| {
+            let db = db.clone();
+            let tx = tx.clone();
+            let task = local_pool.spawn_pinned(move || async move {
+                let Some(entry) = db.get(&hash) else {
+                    return anyhow::Ok(());
+                };
+                tx.send(ValidateProgress::Entry {
+                    id,
+                    hash,
+                    path: None,
+                    size: entry.size(),
+                })
+                .await?;
+                let error =
+                    validate_bao_with_progress(&entry, options.progress_interval, |offset| {
+                        send_validate_progress(&tx, options.progress, id, offset)
+                    })
+                    .await
+                    .err()
+                    .map(|cause| cause.to_string());
+                tx.send(ValidateProgress::Done { id, error }).await?;
+                Ok(())
+            });
+            async move { task.await? }
+        })
+        .buffer_unordered(options.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<()>>>()?;
+    tx.send(ValidateProgress::AllDone).await?;
+    Ok(())
+}
+
+/// Validate a single blob, reporting fine grained progress.
+///
+/// This is meant for large blobs, for which the progress of [`validate_blobs`] is too
+/// coarse. Progress is sent to `tx`, starting with [`ValidateProgress::Starting`] and an
+/// `Entry` message with id 0 and the size of the blob. While the blob is validated, a
+/// `Progress` message is sent after every `options.progress_interval` bytes, see
+/// [`validate_bao_with_progress`], and delivered according to `options.progress`. The last
+/// messages are `Done` and [`ValidateProgress::AllDone`]. The concurrency of `options` is
+/// not used.
+///
+/// If the receiver is dropped, validation stops before reading the next batch.
+///
+/// Fails if the blob is not in the store.
+pub async fn validate_one<D: Map>(
+    db: &D,
+    hash: Hash,
+    options: ValidateOptions,
+    tx: mpsc::Sender<ValidateProgress>,
+) -> anyhow::Result<()> {
+    let entry = db.get(&hash).context("blob not found")?;
+    tx.send(ValidateProgress::Starting { total: 1 }).await?;
+    tx.send(ValidateProgress::Entry {
+        id: 0,
+        hash,
+        path: None,
+        size: entry.size(),
+    })
+    .await?;
+    let res = validate_bao_with_progress(&entry, options.progress_interval, |offset| {
+        send_validate_progress(&tx, options.progress, 0, offset)
+    })
+    .await;
+    let error = res.err().map(|cause| cause.to_string());
+    tx.send(ValidateProgress::Done { id: 0, error }).await?;
+    tx.send(ValidateProgress::AllDone).await?;
+    Ok(())
+}
+
+/// Validate a collection and all its children.
+///
+/// This checks that the collection will be fully served from this store: the collection
+/// blob and each child are present, complete and valid, see [`validate_bao`]. Progress is
+/// sent to `tx`, starting with [`ValidateProgress::Starting`], followed by an `Entry` and a
+/// `Done` message for the collection blob with id 0 and for each child in order. Children
+/// that are missing are reported with a size of 0 and an error. The last messages are a
+/// [`ValidateProgress::CollectionSummary`] and [`ValidateProgress::AllDone`].
+///
+/// Fails if the collection blob itself is missing, invalid or can not be parsed.
+pub async fn validate_collection<D: Map, C: CollectionParser>(
+    db: &D,
+    hash: Hash,
+    cp: C,
+    tx: mpsc::Sender<ValidateProgress>,
+) -> anyhow::Result<()> {
+    let entry = db.get(&hash).context("collection not found")?;
+    let size = entry.size();
+    if let Err(cause) = validate_bao(&entry).await {
+        bail!("collection {hash} is invalid: {cause}");
+    }
+    let (mut links, _stats) = cp.parse(entry.data_reader().await?).await?;
+    let mut children = Vec::new();
+    while let Some(child) = links.next().await? {
+        children.push(child);
+    }
+
+    tx.send(ValidateProgress::Starting {
+        total: children.len() as u64 + 1,
+    })
+    .await?;
+    tx.send(ValidateProgress::Entry {
+        id: 0,
+        hash,
+        path: None,
+        size,
+    })
+    .await?;
+    tx.send(ValidateProgress::Done { id: 0, error: None })
+        .await?;
+
+    let mut present = 0;
+    let mut valid = 0;
+    for (id, child) in (1..).zip(children.iter().copied()) {
+        let error = match db.get(&child) {
+            Some(entry) => {
+                present += 1;
+                tx.send(ValidateProgress::Entry {
+                    id,
+                    hash: child,
+                    path: None,
+                    size: entry.size(),
+                })
+                .await?;
+                match validate_bao(&entry).await {
+                    Ok(()) => {
+                        valid += 1;
+                        None
+                    }
+                    Err(cause) => Some(cause.to_string()),
+                }
+            }
+            None => {
+                tx.send(ValidateProgress::Entry {
+                    id,
+                    hash: child,
+                    path: None,
+                    size: 0,
+                })
+                .await?;
+                Some("not found".to_string())
+            }
+        };
+        tx.send(ValidateProgress::Done { id, error }).await?;
+    }
+
+    tx.send(ValidateProgress::CollectionSummary {
+        children: children.len() as u64,
+        present,
+        valid,
+        missing: children.len() as u64 - present,
+    })
+    .await?;
+    tx.send(ValidateProgress::AllDone).await?;
     Ok(())
 }
 
@@ -589,4 +1356,211 @@ pub enum ValidateProgress {
     AllDone,
     /// We got an error and need to abort.
     Abort(RpcError),
+    /// Summary of validating a collection, sent before [`ValidateProgress::AllDone`].
+    CollectionSummary {
+        /// The number of children of the collection.
+        children: u64,
+        /// The number of children that are in the store, complete or not.
+        present: u64,
+        /// The number of children that are complete and valid.
+        valid: u64,
+        /// The number of children that are not in the store.
+        missing: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use bao_tree::io::outboard::PreOrderMemOutboard;
+
+    use super::*;
+
+    /// A map whose data reads take a while, and that tracks how many reads overlap.
+    #[derive(Debug, Clone, Default)]
+    struct SlowMap {
+        blobs: Arc<BTreeMap<Hash, (PreOrderMemOutboard<Bytes>, Bytes)>>,
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+
+    impl SlowMap {
+        fn new(blobs: impl IntoIterator<Item = Vec<u8>>) -> Self {
+            let blobs = blobs
+                .into_iter()
+                .map(|data| {
+                    let outboard = PreOrderMemOutboard::create(&data, IROH_BLOCK_SIZE)
+                        .map_data(Bytes::from)
+                        .unwrap();
+                    (outboard.root().into(), (outboard, data.into()))
+                })
+                .collect();
+            Self {
+                blobs: Arc::new(blobs),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct SlowEntry {
+        map: SlowMap,
+        hash: Hash,
+    }
+
+    impl MapEntry<SlowMap> for SlowEntry {
+        fn hash(&self) -> blake3::Hash {
+            self.hash.into()
+        }
+
+        fn size(&self) -> u64 {
+            self.map.blobs[&self.hash].1.len() as u64
+        }
+
+        fn is_complete(&self) -> bool {
+            true
+        }
+
+        fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<ChunkNum>>> {
+            futures::future::ok(RangeSet2::all()).boxed()
+        }
+
+        fn outboard(&self) -> BoxFuture<'_, io::Result<PreOrderMemOutboard<Bytes>>> {
+            futures::future::ok(self.map.blobs[&self.hash].0.clone()).boxed()
+        }
+
+        fn data_reader(&self) -> BoxFuture<'_, io::Result<SlowReader>> {
+            futures::future::ok(SlowReader {
+                map: self.map.clone(),
+                data: self.map.blobs[&self.hash].1.clone(),
+            })
+            .boxed()
+        }
+    }
+
+    #[derive(Debug)]
+    struct SlowReader {
+        map: SlowMap,
+        data: Bytes,
+    }
+
+    impl AsyncSliceReader for SlowReader {
+        type ReadAtFuture<'a> = LocalBoxFuture<'a, io::Result<Bytes>>;
+
+        fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+            async move {
+                let active = self.map.active.fetch_add(1, Ordering::SeqCst) + 1;
+                self.map.max_active.fetch_max(active, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                self.map.active.fetch_sub(1, Ordering::SeqCst);
+                let start = (offset as usize).min(self.data.len());
+                let end = start.saturating_add(len).min(self.data.len());
+                Ok(self.data.slice(start..end))
+            }
+            .boxed_local()
+        }
+
+        type LenFuture<'a> = futures::future::Ready<io::Result<u64>>;
+
+        fn len(&mut self) -> Self::LenFuture<'_> {
+            futures::future::ok(self.data.len() as u64)
+        }
+    }
+
+    impl Map for SlowMap {
+        type Outboard = PreOrderMemOutboard<Bytes>;
+        type DataReader = SlowReader;
+        type Entry = SlowEntry;
+
+        fn get(&self, hash: &Hash) -> Option<Self::Entry> {
+            self.blobs.contains_key(hash).then(|| SlowEntry {
+                map: self.clone(),
+                hash: *hash,
+            })
+        }
+
+        fn contains(&self, hash: &Hash) -> EntryStatus {
+            if self.blobs.contains_key(hash) {
+                EntryStatus::Complete
+            } else {
+                EntryStatus::NotFound
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_blobs_concurrency() {
+        let local_pool = LocalPoolHandle::new(4);
+        for concurrency in [1, 3] {
+            let db = SlowMap::new((0..6u8).map(|i| vec![i; 1024]));
+            let hashes = db.blobs.keys().copied().collect();
+            let (tx, mut rx) = mpsc::channel(64);
+            let options = ValidateOptions {
+                concurrency,
+                ..Default::default()
+            };
+            validate_blobs(&db, hashes, options, &local_pool, tx)
+                .await
+                .unwrap();
+            assert_eq!(db.max_active.load(Ordering::SeqCst), concurrency);
+            let mut done = 0;
+            while let Some(msg) = rx.recv().await {
+                if let ValidateProgress::Done { error, .. } = msg {
+                    assert_eq!(error, None);
+                    done += 1;
+                }
+            }
+            assert_eq!(done, 6);
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_blobs_progress_policy() {
+        let local_pool = LocalPoolHandle::new(2);
+        for policy in [
+            ValidateProgressPolicy::Coalesce,
+            ValidateProgressPolicy::Backpressure,
+        ] {
+            let db = SlowMap::new((0..4u8).map(|i| vec![i; 64 * 1024]));
+            let hashes = db.blobs.keys().copied().collect();
+            let options = ValidateOptions {
+                concurrency: 2,
+                progress_interval: 16 * 1024,
+                progress: policy,
+            };
+            let (tx, mut rx) = mpsc::channel(1);
+            // a consumer that is slower than the validation
+            let consume = async {
+                let mut msgs = Vec::new();
+                while let Some(msg) = rx.recv().await {
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    msgs.push(msg);
+                }
+                msgs
+            };
+            let (res, msgs) = futures::future::join(
+                validate_blobs(&db, hashes, options, &local_pool, tx),
+                consume,
+            )
+            .await;
+            res.unwrap();
+
+            // completion is never dropped
+            let done = msgs
+                .iter()
+                .filter(|msg| matches!(msg, ValidateProgress::Done { error: None, .. }))
+                .count();
+            assert_eq!(done, 4);
+            assert!(matches!(msgs.last(), Some(ValidateProgress::AllDone)));
+            let progress = msgs
+                .iter()
+                .filter(|msg| matches!(msg, ValidateProgress::Progress { .. }))
+                .count();
+            match policy {
+                ValidateProgressPolicy::Coalesce => assert!(progress <= 16),
+                ValidateProgressPolicy::Backpressure => assert_eq!(progress, 16),
+            }
+        }
+    }
 }
@@ -1,9 +1,13 @@
 //! traits related to collections of blobs
 use crate::util::Hash;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures::{future::LocalBoxFuture, FutureExt};
 use iroh_io::{AsyncSliceReader, AsyncSliceReaderExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::io;
+use std::sync::Arc;
 
 /// A custom collection parser that allows the user to define what a collection is.
 ///
@@ -18,6 +22,10 @@ use std::fmt::Debug;
 ///
 /// For collections that do allow efficient random access, the [`LinkStream::skip`] method can be
 /// used to move some internal offset.
+///
+/// Collections are parsed on both the provider and the getter side, so their content may come
+/// from untrusted peers. Parsers should reject collections with more links than they are willing
+/// to handle before allocating memory for them, see [`LinkSeqCollectionParser::with_max_children`].
 pub trait CollectionParser: Send + Debug + Clone + 'static {
     /// Parse a collection with this parser
     fn parse<'a, R: AsyncSliceReader + 'a>(
@@ -172,9 +180,35 @@ pub struct CollectionStats {
     pub total_blob_size: Option<u64>,
 }
 
+/// Default for the maximum number of links in a collection, see
+/// [`LinkSeqCollectionParser::with_max_children`].
+///
+/// This limits the size of a parsed collection to 32 MiB.
+pub const DEFAULT_MAX_CHILDREN: u64 = 1 << 20;
+
 /// A collection parser that parses a sequence of links.
 #[derive(Debug, Clone)]
-pub struct LinkSeqCollectionParser;
+pub struct LinkSeqCollectionParser {
+    max_children: u64,
+}
+
+impl Default for LinkSeqCollectionParser {
+    fn default() -> Self {
+        Self {
+            max_children: DEFAULT_MAX_CHILDREN,
+        }
+    }
+}
+
+impl LinkSeqCollectionParser {
+    /// Sets the maximum number of links a collection may have.
+    ///
+    /// Larger collections are rejected before they are read into memory. Defaults to
+    /// [`DEFAULT_MAX_CHILDREN`].
+    pub fn with_max_children(self, max_children: u64) -> Self {
+        Self { max_children }
+    }
+}
 
 impl CollectionParser for LinkSeqCollectionParser {
     fn parse<'a, R: AsyncSliceReader + 'a>(
@@ -182,6 +216,13 @@ impl CollectionParser for LinkSeqCollectionParser {
         mut reader: R,
     ) -> LocalBoxFuture<'a, anyhow::Result<(Box<dyn LinkStream>, CollectionStats)>> {
         async move {
+            let len = reader.len().await?;
+            anyhow::ensure!(
+                len / 32 <= self.max_children,
+                "collection has {} links, more than the maximum of {}",
+                len / 32,
+                self.max_children
+            );
             let bytes = reader.read_to_end().await?;
             let links = LinkSeq::try_from(bytes)?;
             let num_blobs = links.len().saturating_sub(1);
@@ -197,3 +238,244 @@ impl CollectionParser for LinkSeqCollectionParser {
         .boxed_local()
     }
 }
+
+/// Magic bytes at the start of a collection that carries a [`CollectionFormat`] marker.
+pub const COLLECTION_MAGIC: [u8; 4] = *b"irc\0";
+
+/// Length of a collection format marker, the [`COLLECTION_MAGIC`] followed by the format byte.
+pub const MARKER_LEN: usize = COLLECTION_MAGIC.len() + 1;
+
+/// The format of a collection, as given by the marker at its start.
+///
+/// Collections without a marker are legacy collections, their format is whatever the
+/// fallback parser of a [`CollectionParserRegistry`] expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct CollectionFormat(pub u8);
+
+impl CollectionFormat {
+    /// A sequence of links, see [`LinkSeqCollectionParser`].
+    pub const LINK_SEQ: Self = Self(0);
+    /// A tar archive.
+    pub const TAR: Self = Self(1);
+    /// A CAR (content addressable archive) file.
+    pub const CAR: Self = Self(2);
+
+    /// The marker for collections of this format.
+    pub fn marker(self) -> [u8; MARKER_LEN] {
+        let mut marker = [0u8; MARKER_LEN];
+        marker[..COLLECTION_MAGIC.len()].copy_from_slice(&COLLECTION_MAGIC);
+        marker[COLLECTION_MAGIC.len()] = self.0;
+        marker
+    }
+
+    /// Prepends the marker of this format to the `data` of a collection.
+    pub fn mark(self, data: &[u8]) -> Bytes {
+        let mut res = BytesMut::with_capacity(MARKER_LEN + data.len());
+        res.extend_from_slice(&self.marker());
+        res.extend_from_slice(data);
+        res.freeze()
+    }
+
+    /// Reads the format marker at the start of a collection.
+    ///
+    /// Returns `None` for legacy collections without a marker.
+    pub async fn read<R: AsyncSliceReader>(reader: &mut R) -> io::Result<Option<Self>> {
+        let marker = reader.read_at(0, MARKER_LEN).await?;
+        Ok(match marker.strip_prefix(&COLLECTION_MAGIC[..]) {
+            Some([format]) => Some(Self(*format)),
+            _ => None,
+        })
+    }
+}
+
+/// A collection parser that selects the parser for a collection by its [`CollectionFormat`].
+///
+/// The parser registered for the format of a marked collection is given the collection
+/// without its marker. Legacy collections without a marker are given to the fallback
+/// parser as they are. Marked collections of a format without a registered parser are
+/// rejected, so e.g. a tar archive is never parsed as a sequence of links.
+#[derive(Debug, Clone)]
+pub struct CollectionParserRegistry {
+    parsers: BTreeMap<CollectionFormat, Arc<dyn DynCollectionParser>>,
+    fallback: Arc<dyn DynCollectionParser>,
+}
+
+impl Default for CollectionParserRegistry {
+    /// A registry for [`CollectionFormat::LINK_SEQ`], which also parses legacy collections
+    /// as sequences of links.
+    fn default() -> Self {
+        Self::new(LinkSeqCollectionParser::default()).register(
+            CollectionFormat::LINK_SEQ,
+            LinkSeqCollectionParser::default(),
+        )
+    }
+}
+
+impl CollectionParserRegistry {
+    /// Creates a registry without formats, parsing legacy collections with `fallback`.
+    pub fn new(fallback: impl CollectionParser + Sync) -> Self {
+        Self {
+            parsers: BTreeMap::new(),
+            fallback: Arc::new(fallback),
+        }
+    }
+
+    /// Registers the parser for collections of `format`, replacing any previous parser.
+    pub fn register(
+        mut self,
+        format: CollectionFormat,
+        parser: impl CollectionParser + Sync,
+    ) -> Self {
+        self.parsers.insert(format, Arc::new(parser));
+        self
+    }
+}
+
+impl CollectionParser for CollectionParserRegistry {
+    fn parse<'a, R: AsyncSliceReader + 'a>(
+        &'a self,
+        mut reader: R,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Box<dyn LinkStream>, CollectionStats)>> {
+        async move {
+            match CollectionFormat::read(&mut reader).await? {
+                Some(format) => {
+                    let parser = self.parsers.get(&format).ok_or_else(|| {
+                        anyhow::anyhow!("no parser for collection format {}", format.0)
+                    })?;
+                    let reader = OffsetReader {
+                        inner: Box::new(reader),
+                        offset: MARKER_LEN as u64,
+                    };
+                    parser.parse_dyn(reader).await
+                }
+                None => {
+                    let reader = OffsetReader {
+                        inner: Box::new(reader),
+                        offset: 0,
+                    };
+                    self.fallback.parse_dyn(reader).await
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
+
+/// Object safe version of [`CollectionParser`], to keep parsers of different types.
+trait DynCollectionParser: Debug + Send + Sync {
+    fn parse_dyn<'a>(
+        &'a self,
+        reader: OffsetReader<'a>,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Box<dyn LinkStream>, CollectionStats)>>;
+}
+
+impl<C: CollectionParser + Sync> DynCollectionParser for C {
+    fn parse_dyn<'a>(
+        &'a self,
+        reader: OffsetReader<'a>,
+    ) -> LocalBoxFuture<'a, anyhow::Result<(Box<dyn LinkStream>, CollectionStats)>> {
+        self.parse(reader)
+    }
+}
+
+/// Object safe version of [`AsyncSliceReader`].
+trait DynSliceReader {
+    fn read_at(&mut self, offset: u64, len: usize) -> LocalBoxFuture<'_, io::Result<Bytes>>;
+    fn len(&mut self) -> LocalBoxFuture<'_, io::Result<u64>>;
+}
+
+impl<R: AsyncSliceReader> DynSliceReader for R {
+    fn read_at(&mut self, offset: u64, len: usize) -> LocalBoxFuture<'_, io::Result<Bytes>> {
+        AsyncSliceReader::read_at(self, offset, len).boxed_local()
+    }
+
+    fn len(&mut self) -> LocalBoxFuture<'_, io::Result<u64>> {
+        AsyncSliceReader::len(self).boxed_local()
+    }
+}
+
+/// A reader of a collection that skips the first `offset` bytes, i.e. its marker.
+struct OffsetReader<'a> {
+    inner: Box<dyn DynSliceReader + 'a>,
+    offset: u64,
+}
+
+impl AsyncSliceReader for OffsetReader<'_> {
+    type ReadAtFuture<'b>
+        = LocalBoxFuture<'b, io::Result<Bytes>>
+    where
+        Self: 'b;
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+        self.inner.read_at(offset.saturating_add(self.offset), len)
+    }
+
+    type LenFuture<'b>
+        = LocalBoxFuture<'b, io::Result<u64>>
+    where
+        Self: 'b;
+
+    fn len(&mut self) -> Self::LenFuture<'_> {
+        let offset = self.offset;
+        let len = self.inner.len();
+        async move { Ok(len.await?.saturating_sub(offset)) }.boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn link_seq_max_children() {
+        let links: LinkSeq = (0..10u8).map(|i| Hash::new([i])).collect();
+        let bytes = links.into_inner();
+
+        let parser = LinkSeqCollectionParser::default().with_max_children(10);
+        let (_, stats) = parser.parse(bytes.clone()).await.unwrap();
+        assert_eq!(stats.num_blobs, Some(9));
+
+        let parser = LinkSeqCollectionParser::default().with_max_children(9);
+        assert!(parser.parse(bytes).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn registry_selects_parser_by_marker() {
+        let links: LinkSeq = (0..3u8).map(|i| Hash::new([i])).collect();
+        let legacy = links.clone().into_inner();
+        let marked = CollectionFormat::LINK_SEQ.mark(&legacy);
+        let registry = CollectionParserRegistry::default();
+
+        async fn hashes(mut stream: Box<dyn LinkStream>) -> Vec<Hash> {
+            let mut res = Vec::new();
+            while let Some(hash) = stream.next().await.unwrap() {
+                res.push(hash);
+            }
+            res
+        }
+        let expected = links.iter().collect::<Vec<_>>();
+        let (stream, stats) = registry.parse(legacy.clone()).await.unwrap();
+        assert_eq!(stats.num_blobs, Some(2));
+        assert_eq!(hashes(stream).await, expected);
+        let (stream, stats) = registry.parse(marked.clone()).await.unwrap();
+        assert_eq!(stats.num_blobs, Some(2));
+        assert_eq!(hashes(stream).await, expected);
+
+        // a marked collection is not parsed as legacy links
+        assert!(LinkSeqCollectionParser::default()
+            .parse(marked)
+            .await
+            .is_err());
+        // and a format without a parser is rejected
+        let tar = CollectionFormat::TAR.mark(&legacy);
+        assert_eq!(
+            CollectionFormat::read(&mut tar.clone()).await.unwrap(),
+            Some(CollectionFormat::TAR)
+        );
+        assert!(registry.parse(tar).await.is_err());
+        assert_eq!(
+            CollectionFormat::read(&mut legacy.clone()).await.unwrap(),
+            None
+        );
+    }
+}
@@ -16,13 +16,19 @@ use std::time::{Duration, Instant};
 
 use crate::util::Hash;
 use anyhow::Result;
-use bao_tree::io::fsm::BaoContentItem;
-use bao_tree::ChunkNum;
+use bao_tree::io::fsm::{BaoContentItem, ResponseDecoderReadingNext, ResponseDecoderStart};
+use bao_tree::{ByteNum, ChunkNum, ChunkRanges};
+use bytes::BytesMut;
+use futures::Stream;
+use iroh_io::AsyncSliceWriter;
 use quinn::RecvStream;
 use range_collections::RangeSet2;
 use tracing::{debug, error};
 
-use crate::protocol::RangeSpecSeq;
+use crate::protocol::{
+    read_lp, read_lp_with_capacity, BlobSetDiffRequest, LiveGetRequest, PartialGetRequest,
+    RangeSpec, RangeSpecSeq, RawGetRequest, Request, MAX_MESSAGE_SIZE,
+};
 use crate::util::io::{TrackingReader, TrackingWriter};
 use crate::IROH_BLOCK_SIZE;
 
@@ -51,23 +57,19 @@ impl Stats {
 pub mod fsm {
     use std::{io, result};
 
-    use crate::protocol::{GetRequest, NonEmptyRequestRangeSpecIter, Request, MAX_MESSAGE_SIZE};
+    use crate::protocol::{GetRequest, NonEmptyRequestRangeSpecIter};
 
     use super::*;
 
     use bao_tree::{
         blake3,
         io::{
-            fsm::{
-                OutboardMut, ResponseDecoderReading, ResponseDecoderReadingNext,
-                ResponseDecoderStart,
-            },
+            fsm::{OutboardMut, ResponseDecoderReading},
             StartDecodeError,
         },
         TreeNode,
     };
     use derive_more::From;
-    use iroh_io::AsyncSliceWriter;
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     self_cell::self_cell! {
@@ -175,6 +177,11 @@ pub mod fsm {
         /// The serialized request is too long to be sent
         #[error("request too big")]
         RequestTooBig,
+        /// Live, partial and blob set difference requests can not be handled by the state
+        /// machine, use [`super::get_live_blob`], [`super::get_partial_blob`] or
+        /// [`super::get_blob_set_diff`]
+        #[error("request not supported")]
+        UnsupportedRequest,
         /// Error when writing the request to the [`quinn::SendStream`]
         #[error("write: {0}")]
         Write(#[from] quinn::WriteError),
@@ -245,6 +252,15 @@ pub mod fsm {
                 mut writer,
                 request,
             } = self;
+            if matches!(
+                request,
+                Request::LiveGet(_)
+                    | Request::PartialGet(_)
+                    | Request::BlobSetDiff(_)
+                    | Request::RawGet(_)
+            ) {
+                return Err(ConnectedNextError::UnsupportedRequest);
+            }
             // 1. Send Request
             {
                 debug!("sending request");
@@ -295,6 +311,12 @@ pub mod fsm {
                     postcard::from_bytes::<GetRequest>(&response)
                         .map_err(ConnectedNextError::PostcardDe)?
                 }
+                Request::LiveGet(_)
+                | Request::PartialGet(_)
+                | Request::BlobSetDiff(_)
+                | Request::RawGet(_) => {
+                    unreachable!("checked above")
+                }
             };
             let hash = request.hash;
             let ranges_iter = RangesIter::new(request.ranges);
@@ -454,14 +476,22 @@ pub mod fsm {
     impl AtBlobHeader {
         /// Read the size header, returning it and going into the `Content` state.
         pub async fn next(self) -> Result<(AtBlobContent, u64), AtBlobHeaderNextError> {
+            let ranges = self.stream.ranges().clone();
             match self.stream.next().await {
-                Ok((stream, size)) => Ok((
-                    AtBlobContent {
-                        stream,
-                        misc: self.misc,
-                    },
-                    size,
-                )),
+                Ok((stream, size)) => {
+                    // chunks past the end of the blob can not be sent, the provider sends the
+                    // last chunk instead to prove the size
+                    let requested = ranges & ChunkRanges::from(..ByteNum(size).chunks());
+                    Ok((
+                        AtBlobContent {
+                            stream,
+                            requested,
+                            received: ChunkRanges::empty(),
+                            misc: self.misc,
+                        },
+                        size,
+                    ))
+                }
                 Err(cause) => Err(match cause {
                     StartDecodeError::NotFound => AtBlobHeaderNextError::NotFound,
                     StartDecodeError::Io(cause) => {
@@ -489,6 +519,7 @@ pub mod fsm {
                     }
                     BlobContentNext::More((_, Err(e))) => return Err(e),
                     BlobContentNext::Done(end) => {
+                        end.check_complete()?;
                         return Ok(end);
                     }
                 }
@@ -518,6 +549,7 @@ pub mod fsm {
                     }
                 }
             };
+            done.check_complete()?;
             Ok((done, res))
         }
 
@@ -564,6 +596,10 @@ pub mod fsm {
     #[derive(Debug)]
     pub struct AtBlobContent {
         stream: ResponseDecoderReading<TrackingReader<RecvStream>>,
+        /// The requested ranges, limited to the size of the blob
+        requested: ChunkRanges,
+        /// The ranges for which we have received a leaf so far
+        received: ChunkRanges,
         misc: Box<Misc>,
     }
 
@@ -605,6 +641,16 @@ pub mod fsm {
         /// The hash of a leaf did not match the expected hash
         #[error("leaf hash mismatch: {0}")]
         LeafHashMismatch(ChunkNum),
+        /// The response ended before all requested ranges were received
+        ///
+        /// This is a defensive check. The bao decoder reads exactly the requested ranges and
+        /// already fails with [`DecodeError::ParentNotFound`] or [`DecodeError::LeafNotFound`]
+        /// when the stream ends early, so this is not expected to occur.
+        #[error("truncated, missing {missing:?}")]
+        Truncated {
+            /// The requested ranges that were not received
+            missing: ChunkRanges,
+        },
         /// Error when reading from the stream
         #[error("read: {0}")]
         Read(quinn::ReadError),
@@ -630,6 +676,9 @@ pub mod fsm {
                     io::Error::new(io::ErrorKind::UnexpectedEof, cause)
                 }
                 DecodeError::LeafNotFound(_) => io::Error::new(io::ErrorKind::UnexpectedEof, cause),
+                DecodeError::Truncated { .. } => {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, cause)
+                }
                 DecodeError::Read(cause) => cause.into(),
                 DecodeError::Io(cause) => cause,
                 _ => io::Error::new(io::ErrorKind::Other, cause),
@@ -675,12 +724,17 @@ pub mod fsm {
         pub async fn next(self) -> BlobContentNext {
             match self.stream.next().await {
                 ResponseDecoderReadingNext::More((stream, res)) => {
-                    let next = Self { stream, ..self };
+                    let mut next = Self { stream, ..self };
+                    if let Ok(BaoContentItem::Leaf(leaf)) = &res {
+                        let end = ByteNum(leaf.offset.0 + leaf.data.len() as u64);
+                        next.received |= ChunkRanges::from(leaf.offset.full_chunks()..end.chunks());
+                    }
                     let res = res.map_err(DecodeError::from);
                     BlobContentNext::More((next, res))
                 }
                 ResponseDecoderReadingNext::Done(stream) => BlobContentNext::Done(AtEndBlob {
                     stream,
+                    missing: self.requested.difference(&self.received),
                     misc: self.misc,
                 }),
             }
@@ -726,6 +780,7 @@ pub mod fsm {
                         }
                     }
                     BlobContentNext::Done(end) => {
+                        end.check_complete()?;
                         return Ok(end);
                     }
                 }
@@ -750,6 +805,7 @@ pub mod fsm {
                         }
                     }
                     BlobContentNext::Done(end) => {
+                        end.check_complete()?;
                         return Ok(end);
                     }
                 }
@@ -761,6 +817,8 @@ pub mod fsm {
     #[derive(Debug)]
     pub struct AtEndBlob {
         stream: TrackingReader<RecvStream>,
+        /// The requested ranges that were not received
+        missing: ChunkRanges,
         misc: Box<Misc>,
     }
 
@@ -774,6 +832,28 @@ pub mod fsm {
     }
 
     impl AtEndBlob {
+        /// The requested ranges of the blob that were not received.
+        ///
+        /// This is empty unless the response ended early. Ranges past the end of the blob are
+        /// not counted as missing.
+        pub fn missing(&self) -> &ChunkRanges {
+            &self.missing
+        }
+
+        /// Check that all requested ranges of the blob were received.
+        ///
+        /// Returns [`DecodeError::Truncated`] otherwise. This only guards against the decoder
+        /// ending early, a stream that is cut short already fails while reading the content.
+        pub fn check_complete(&self) -> result::Result<(), DecodeError> {
+            if self.missing.is_empty() {
+                Ok(())
+            } else {
+                Err(DecodeError::Truncated {
+                    missing: self.missing.clone(),
+                })
+            }
+        }
+
         /// Read the next child, or finish
         pub fn next(mut self) -> EndBlobNext {
             if let Some((offset, ranges)) = self.misc.ranges_iter.next() {
@@ -830,6 +910,188 @@ pub mod fsm {
     }
 }
 
+/// Capacity of the buffer for the range specs of a live get response.
+///
+/// A live response consists of many small range specs, which are read into the same buffer.
+const FRAME_CAPACITY: usize = 1024;
+
+/// Get a blob that may still be written on the provider side, using a
+/// [`Request::LiveGet`] request.
+///
+/// The data is written to `target` as it arrives. This returns once all requested ranges
+/// have been received, with the size of the blob, or `None` if the provider does not have
+/// the blob.
+pub async fn get_live_blob<W: AsyncSliceWriter>(
+    connection: &quinn::Connection,
+    request: LiveGetRequest,
+    mut target: W,
+) -> Result<Option<u64>> {
+    let hash = request.hash;
+    let mut reader = send_request(connection, &Request::LiveGet(request)).await?;
+    let mut buffer = BytesMut::new();
+    let mut size = None;
+    // the response is a sequence of frames, each a range spec followed by the ranges
+    while let Some(frame) = read_lp_with_capacity(&mut reader, &mut buffer, FRAME_CAPACITY).await? {
+        let ranges: RangeSpec = postcard::from_bytes(&frame)?;
+        let (next, frame_size) =
+            read_frame(reader, hash, ranges.to_chunk_ranges(), &mut target).await?;
+        reader = next;
+        size = Some(frame_size);
+    }
+    target.sync().await?;
+    Ok(size)
+}
+
+/// The result of a [`get_partial_blob`] call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialBlob {
+    /// The size of the blob
+    pub size: u64,
+    /// The requested ranges that the provider sent
+    pub sent: ChunkRanges,
+    /// The requested ranges that the provider does not have
+    pub missing: ChunkRanges,
+}
+
+/// Get the ranges of a blob that the provider has, using a [`Request::PartialGet`] request.
+///
+/// The data is written to `target`. This returns which of the requested ranges were sent and
+/// which are missing at the provider, or `None` if the provider does not have the blob at all.
+pub async fn get_partial_blob<W: AsyncSliceWriter>(
+    connection: &quinn::Connection,
+    request: PartialGetRequest,
+    mut target: W,
+) -> Result<Option<PartialBlob>> {
+    let hash = request.hash;
+    let requested = request.ranges.to_chunk_ranges();
+    let mut reader = send_request(connection, &Request::PartialGet(request)).await?;
+    let mut buffer = BytesMut::new();
+    // the response is a single frame, or nothing if the provider does not have the blob
+    let Some(frame) = read_lp(&mut reader, &mut buffer).await? else {
+        return Ok(None);
+    };
+    let sent = postcard::from_bytes::<RangeSpec>(&frame)?.to_chunk_ranges();
+    anyhow::ensure!(
+        sent.is_subset(&requested),
+        "provider sent ranges that were not requested"
+    );
+    let (_, size) = read_frame(reader, hash, sent.clone(), &mut target).await?;
+    target.sync().await?;
+    let missing = requested.difference(&sent) & ChunkRanges::from(..ByteNum(size).chunks());
+    Ok(Some(PartialBlob {
+        size,
+        sent,
+        missing,
+    }))
+}
+
+/// Get the hashes of the blobs that the provider has and the requester does not, using a
+/// [`Request::BlobSetDiff`] request.
+///
+/// The hashes are yielded as they arrive, in no particular order.
+pub async fn get_blob_set_diff(
+    connection: &quinn::Connection,
+    request: BlobSetDiffRequest,
+) -> Result<impl Stream<Item = Result<Hash>>> {
+    let reader = send_request(connection, &Request::BlobSetDiff(request)).await?;
+    Ok(futures::stream::try_unfold(
+        reader,
+        |mut reader| async move {
+            let mut hash = [0u8; 32];
+            let mut filled = 0;
+            while filled < hash.len() {
+                let Some(n) = reader.read(&mut hash[filled..]).await? else {
+                    anyhow::ensure!(filled == 0, "response ended in the middle of a hash");
+                    return Ok(None);
+                };
+                filled += n;
+            }
+            Ok(Some((Hash::from(hash), reader)))
+        },
+    ))
+}
+
+/// Get the complete data of a blob without bao encoding, using a [`Request::RawGet`] request.
+///
+/// This is for getters that can not verify bao encoded data as it arrives. The data is
+/// written to `target` as it arrives, and verified against the hash once all of it was
+/// received. If this fails, `target` may contain data that was not verified.
+///
+/// Returns the size of the blob, or `None` if the provider does not have the complete blob.
+pub async fn get_raw_blob<W: AsyncSliceWriter>(
+    connection: &quinn::Connection,
+    request: RawGetRequest,
+    mut target: W,
+) -> Result<Option<u64>> {
+    let hash = request.hash;
+    let mut reader = send_request(connection, &Request::RawGet(request)).await?;
+    let mut header = [0u8; 8];
+    let mut filled = 0;
+    while filled < header.len() {
+        let Some(n) = reader.read(&mut header[filled..]).await? else {
+            // the provider does not have the blob
+            anyhow::ensure!(filled == 0, "response ended in the size header");
+            return Ok(None);
+        };
+        filled += n;
+    }
+    let size = u64::from_le_bytes(header);
+    let mut hasher = bao_tree::blake3::Hasher::new();
+    let mut offset = 0;
+    while let Some(chunk) = reader.read_chunk(usize::MAX, true).await? {
+        anyhow::ensure!(
+            offset + chunk.bytes.len() as u64 <= size,
+            "provider sent more data than the size of the blob"
+        );
+        hasher.update(&chunk.bytes);
+        let len = chunk.bytes.len() as u64;
+        target.write_bytes_at(offset, chunk.bytes).await?;
+        offset += len;
+    }
+    anyhow::ensure!(offset == size, "response ended before the end of the blob");
+    anyhow::ensure!(
+        Hash::from(hasher.finalize()) == hash,
+        "data does not match the requested hash"
+    );
+    target.sync().await?;
+    Ok(Some(size))
+}
+
+/// Send a request that is not handled by the state machine and return the stream to read
+/// the response from.
+async fn send_request(connection: &quinn::Connection, request: &Request) -> Result<RecvStream> {
+    let (mut writer, reader) = connection.open_bi().await?;
+    let request_bytes = postcard::to_stdvec(request)?;
+    anyhow::ensure!(request_bytes.len() <= MAX_MESSAGE_SIZE, "request too big");
+    writer.write_all(&request_bytes).await?;
+    writer.finish().await?;
+    Ok(reader)
+}
+
+/// Read the bao encoded `ranges` of the blob `hash` and write the data to `target`.
+///
+/// Returns the stream for reading further frames, and the size of the blob.
+async fn read_frame<W: AsyncSliceWriter>(
+    reader: RecvStream,
+    hash: Hash,
+    ranges: ChunkRanges,
+    target: &mut W,
+) -> Result<(RecvStream, u64)> {
+    let start = ResponseDecoderStart::new(hash.into(), ranges, IROH_BLOCK_SIZE, reader);
+    let (mut reading, size) = start.next().await?;
+    loop {
+        match reading.next().await {
+            ResponseDecoderReadingNext::More((next, item)) => {
+                if let BaoContentItem::Leaf(leaf) = item? {
+                    target.write_bytes_at(leaf.offset.0, leaf.data).await?;
+                }
+                reading = next;
+            }
+            ResponseDecoderReadingNext::Done(reader) => return Ok((reader, size)),
+        }
+    }
+}
+
 /// Error when processing a response
 #[derive(thiserror::Error, Debug)]
 pub enum GetResponseError {
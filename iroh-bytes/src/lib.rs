@@ -6,6 +6,8 @@
 pub mod baomap;
 pub mod collection;
 pub mod get;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod protocol;
 pub mod provider;
 pub mod util;
@@ -0,0 +1,27 @@
+//! Metrics for iroh-bytes
+
+use iroh_metrics::{
+    core::{Gauge, Metric},
+    struct_iterable::Iterable,
+};
+
+/// Metrics for iroh-bytes
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Iterable)]
+pub struct Metrics {
+    pub transfer_memory_in_use: Gauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            transfer_memory_in_use: Gauge::new("Bytes currently held by transfer buffers"),
+        }
+    }
+}
+
+impl Metric for Metrics {
+    fn name() -> &'static str {
+        "iroh-bytes"
+    }
+}
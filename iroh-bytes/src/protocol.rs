@@ -87,6 +87,44 @@
 //! the same format as the getter defined requests, followed by the bao encoded
 //! data. From then on the protocol is the same as for getter defined requests.
 //!
+//! ## Live requests
+//!
+//! A [`LiveGetRequest`] is for a single blob that the provider might still be
+//! writing, e.g. because it is still downloading it itself. Instead of closing the
+//! connection when some data is not available, the provider sends what it has and
+//! then waits for more. The response is a sequence of frames, each consisting of the
+//! [`RangeSpec`] of the ranges in the frame, followed by the bao encoded data for
+//! these ranges. The provider closes the stream once all requested ranges are sent.
+//!
+//! ## Partial requests
+//!
+//! A [`PartialGetRequest`] is for a byte range of a single blob that the provider
+//! might only have partially. The response is a single frame in the same format as
+//! for live requests. The [`RangeSpec`] of the frame tells which of the requested
+//! ranges the provider sends, the getter can request the remaining ranges from
+//! other providers.
+//!
+//! ## Raw requests
+//!
+//! A [`RawGetRequest`] is for the complete data of a single blob, for getters that
+//! can not verify bao encoded data as it arrives. The response is the size of the
+//! blob followed by the raw data, without the interleaved hashes of the bao encoding.
+//! The getter can only verify the data once it has received all of it, by comparing
+//! its blake3 hash to the requested hash. The provider still reads the data from its
+//! store, so a well behaved provider only sends valid data.
+//!
+//! Use a [`GetRequest`] for partial or streaming use cases, where the data needs to
+//! be verified as it arrives.
+//!
+//! ## Blob set difference requests
+//!
+//! A [`BlobSetDiffRequest`] is not for a specific blob. It contains the 8 byte prefixes
+//! of the hashes of all blobs the getter has, so its size grows linearly with the blob
+//! set of the getter, up to [`MAX_BLOB_SET_DIFF_PREFIXES`]. The response is the
+//! concatenation of the 32 byte hashes of all complete blobs of the provider that are
+//! not in the request and that the provider's
+//! [`crate::provider::RequestAuthorizationHandler::list_blob`] allows to list.
+//!
 //! ## Specifying the required data
 //!
 //! A [`GetRequest`] contains a hash and a specification of what data related to
@@ -334,6 +372,10 @@
 //! sequences that are associated with a single request. Applications can use
 //! request tokens to implement request level authorization.
 //!
+//! A [`StructuredToken`] stores signed claims in a request token, e.g. an expiry time
+//! or the hashes a requester may fetch. It still travels as the bytes of a
+//! [`RequestToken`], so it does not change the wire format.
+//!
 //! # Requesting multiple unrelated blobs
 //!
 //! Currently, the protocol does not support requesting multiple unrelated blobs
@@ -355,7 +397,7 @@ use std::io;
 use std::str::FromStr;
 
 use anyhow::{bail, ensure, Context, Result};
-use bao_tree::ChunkNum;
+use bao_tree::{ByteNum, ChunkNum};
 use bytes::{Bytes, BytesMut};
 use derive_more::From;
 use quinn::VarInt;
@@ -364,6 +406,11 @@ use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 mod range_spec;
 pub use range_spec::{NonEmptyRequestRangeSpecIter, RangeSpec, RangeSpecSeq};
+mod token;
+pub use token::{
+    ClaimValue, RevocationList, RevokedToken, StructuredToken, CLAIM_EXPIRES, CLAIM_HASH_PREFIX,
+    CLAIM_MAX_BYTES, CLAIM_TOKEN_ID,
+};
 
 use crate::util::Hash;
 
@@ -434,6 +481,14 @@ pub enum Request {
     Get(GetRequest),
     /// A get request that allows the receiver to create a collection
     CustomGet(CustomGetRequest),
+    /// A get request for a blob that may still be written at the provider
+    LiveGet(LiveGetRequest),
+    /// A get request for the ranges of a blob that the provider has
+    PartialGet(PartialGetRequest),
+    /// A request for the hashes of the blobs that the provider has and the requester does not
+    BlobSetDiff(BlobSetDiffRequest),
+    /// A get request for the raw data of a blob, without bao encoding
+    RawGet(RawGetRequest),
 }
 
 impl Request {
@@ -442,6 +497,10 @@ impl Request {
         match self {
             Request::Get(get) => get.token(),
             Request::CustomGet(get) => get.token.as_ref(),
+            Request::LiveGet(get) => get.token.as_ref(),
+            Request::PartialGet(get) => get.token.as_ref(),
+            Request::BlobSetDiff(diff) => diff.token.as_ref(),
+            Request::RawGet(get) => get.token.as_ref(),
         }
     }
 
@@ -450,6 +509,10 @@ impl Request {
         match &mut self {
             Request::Get(get) => get.token = value,
             Request::CustomGet(get) => get.token = value,
+            Request::LiveGet(get) => get.token = value,
+            Request::PartialGet(get) => get.token = value,
+            Request::BlobSetDiff(diff) => diff.token = value,
+            Request::RawGet(get) => get.token = value,
         }
         self
     }
@@ -466,6 +529,147 @@ pub struct CustomGetRequest {
     pub data: Bytes,
 }
 
+/// A get request for a single blob, that is served while the blob is still being written
+///
+/// The provider sends the requested ranges that it has right away, and then keeps sending
+/// more as they are written, until all requested ranges are sent. See
+/// [`crate::provider::send_live_blob`] for the format of the response.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct LiveGetRequest {
+    /// The optional request token
+    pub token: Option<RequestToken>,
+    /// blake3 hash
+    pub hash: Hash,
+    /// The ranges of the blob to request
+    pub ranges: RangeSpec,
+}
+
+impl LiveGetRequest {
+    /// Request the entire blob
+    pub fn all(hash: Hash) -> Self {
+        Self {
+            token: None,
+            hash,
+            ranges: RangeSpec::all(),
+        }
+    }
+}
+
+/// A get request for the ranges of a single blob that the provider has
+///
+/// Unlike a [`GetRequest`], this does not fail if the provider only has some of the requested
+/// ranges. The provider tells which ranges it sends instead, see
+/// [`crate::provider::send_partial_blob`] for the format of the response.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct PartialGetRequest {
+    /// The optional request token
+    pub token: Option<RequestToken>,
+    /// blake3 hash
+    pub hash: Hash,
+    /// The ranges of the blob to request
+    pub ranges: RangeSpec,
+}
+
+impl PartialGetRequest {
+    /// Request the given ranges of a blob
+    pub fn new(hash: Hash, ranges: RangeSpec) -> Self {
+        Self {
+            token: None,
+            hash,
+            ranges,
+        }
+    }
+
+    /// Request the chunks containing the given byte range of a blob
+    pub fn bytes(hash: Hash, range: std::ops::Range<u64>) -> Self {
+        let start = ByteNum(range.start).full_chunks();
+        let end = ByteNum(range.end).chunks();
+        Self::new(hash, RangeSpec::new(RangeSet2::from(start..end)))
+    }
+}
+
+/// A get request for the raw data of a single complete blob
+///
+/// The response does not contain the bao encoding, so the getter can only verify the data
+/// once it has received all of it, see [`crate::provider::send_raw_blob`] for the format.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct RawGetRequest {
+    /// The optional request token
+    pub token: Option<RequestToken>,
+    /// blake3 hash
+    pub hash: Hash,
+}
+
+impl RawGetRequest {
+    /// Request the data of a blob
+    pub fn new(hash: Hash) -> Self {
+        Self { token: None, hash }
+    }
+}
+
+/// The maximum number of hash prefixes in a [`BlobSetDiffRequest`] a provider accepts.
+///
+/// This bounds the work of a single request to 8 MiB of prefixes.
+pub const MAX_BLOB_SET_DIFF_PREFIXES: usize = 1024 * 1024;
+
+/// A request for the hashes of all complete blobs of the provider that the requester lacks
+///
+/// The requester describes the blobs it has by the sorted 8 byte prefixes of their hashes,
+/// which is a quarter of the size of the full hashes. The provider responds with the full
+/// hashes of all its complete blobs whose prefix is not in the set, see
+/// [`crate::provider::send_blob_set_diff`]. A blob is only missed if its prefix collides
+/// with the prefix of a different blob of the requester, which is unlikely unless the blob
+/// sets are very large.
+///
+/// To learn the difference in both directions, both nodes send this request to each other.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct BlobSetDiffRequest {
+    /// The optional request token
+    pub token: Option<RequestToken>,
+    /// The sorted and deduplicated prefixes of the hashes the requester has
+    prefixes: Vec<[u8; 8]>,
+}
+
+impl BlobSetDiffRequest {
+    /// Request the blobs that are not in `hashes`
+    pub fn new(hashes: impl IntoIterator<Item = Hash>) -> Self {
+        let mut prefixes = hashes
+            .into_iter()
+            .map(|hash| hash_prefix(&hash))
+            .collect::<Vec<_>>();
+        prefixes.sort_unstable();
+        prefixes.dedup();
+        Self {
+            token: None,
+            prefixes,
+        }
+    }
+
+    /// True if the requester has a blob with the same hash prefix as `hash`
+    ///
+    /// The prefixes are received from the requester, so if they are not sorted this only
+    /// gives wrong answers to the requester itself.
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.prefixes.binary_search(&hash_prefix(hash)).is_ok()
+    }
+
+    /// The number of distinct hash prefixes in the request
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    /// True if the requester does not have any blobs
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+}
+
+fn hash_prefix(hash: &Hash) -> [u8; 8] {
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&hash.as_bytes()[..8]);
+    prefix
+}
+
 /// A request
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 pub struct GetRequest {
@@ -541,6 +745,53 @@ impl GetRequest {
     pub fn token(&self) -> Option<&RequestToken> {
         self.token.as_ref()
     }
+
+    /// Split the request into at most `max_parts` requests for disjoint parts of the data.
+    ///
+    /// Each part covers a group of contiguous chunk ranges, in request order, and keeps
+    /// the token of this request. A provider handles every stream in its own task, so
+    /// sending the parts on separate streams of one connection allows a getter to fetch
+    /// different parts of a large blob or collection in parallel. Providers limit the
+    /// number of requests they handle concurrently per connection and reject the streams
+    /// beyond that with [`Closed::RateLimited`], so `max_parts` should not exceed that
+    /// request limit.
+    ///
+    /// Requests that select data from an unbounded number of children can not be split
+    /// and are returned unchanged.
+    pub fn split(&self, max_parts: usize) -> Vec<GetRequest> {
+        if max_parts <= 1 || !self.ranges.is_finite() {
+            return vec![self.clone()];
+        }
+        let units = self
+            .ranges
+            .iter_non_empty()
+            .flat_map(|(offset, spec)| {
+                spec.to_chunk_ranges()
+                    .iter()
+                    .map(|range| (offset, RangeSet2::from(range.cloned())))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if units.len() <= 1 {
+            return vec![self.clone()];
+        }
+        let part_len = (units.len() + max_parts - 1) / max_parts;
+        units
+            .chunks(part_len)
+            .map(|part| {
+                let last = part.last().map(|(offset, _)| *offset).unwrap_or_default();
+                let mut ranges = vec![RangeSet2::<ChunkNum>::empty(); last as usize + 1];
+                for (offset, range) in part {
+                    ranges[*offset as usize] |= range.clone();
+                }
+                GetRequest {
+                    token: self.token.clone(),
+                    hash: self.hash,
+                    ranges: RangeSpecSeq::from_ranges(ranges),
+                }
+            })
+            .collect()
+    }
 }
 
 /// Write the given data to the provider sink, with a unsigned varint length prefix.
@@ -566,8 +817,25 @@ pub async fn write_lp<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> Res
 /// The message as raw bytes.  If the end of the stream is reached and there is no partial
 /// message, returns `None`.
 pub async fn read_lp(
+    reader: impl AsyncRead + Unpin,
+    buffer: &mut BytesMut,
+) -> Result<Option<Bytes>> {
+    read_lp_with_capacity(reader, buffer, 0).await
+}
+
+/// Reads a length prefixed message, growing `buffer` to at least `capacity` bytes.
+///
+/// The message is split off the front of `buffer`, so the remaining capacity is used for
+/// the next messages when the same buffer is passed again. With a `capacity` larger than a
+/// typical message, a sequence of messages is read with few allocations instead of at
+/// least one per message. Once the returned messages are dropped, the allocation is
+/// reused instead of shrinking.
+///
+/// See [`read_lp`] for the return value.
+pub async fn read_lp_with_capacity(
     mut reader: impl AsyncRead + Unpin,
     buffer: &mut BytesMut,
+    capacity: usize,
 ) -> Result<Option<Bytes>> {
     let size = match reader.read_u64_le().await {
         Ok(size) => size,
@@ -576,13 +844,14 @@ pub async fn read_lp(
     };
 
     let reader = reader.take(size);
-    read_fixed_size(reader, buffer, size).await
+    read_fixed_size(reader, buffer, size, capacity).await
 }
 
 pub(crate) async fn read_fixed_size(
     reader: impl AsyncRead + Unpin,
     buffer: &mut BytesMut,
     size: u64,
+    capacity: usize,
 ) -> Result<Option<Bytes>> {
     if size > MAX_MESSAGE_SIZE as u64 {
         bail!("Incoming message exceeds MAX_MESSAGE_SIZE");
@@ -591,7 +860,9 @@ pub(crate) async fn read_fixed_size(
     let mut reader = reader.take(size);
     let size = usize::try_from(size).context("frame larger than usize")?;
 
-    buffer.reserve(size);
+    if buffer.capacity() - buffer.len() < size {
+        buffer.reserve(size.max(capacity));
+    }
     loop {
         let r = reader.read_buf(buffer).await?;
         if r == 0 {
@@ -626,6 +897,17 @@ pub enum Closed {
     /// Only a single request is allowed on a stream, if more data is received after this a
     /// provider may send this error code in a STOP_STREAM frame.
     RequestReceived = 2,
+    /// The provider is overloaded and rejected the request.
+    ///
+    /// A provider bounds the number of requests it handles concurrently.  Streams arriving
+    /// while this limit is reached are rejected with this error code instead of being
+    /// queued.  The requester may retry later.
+    RateLimited = 3,
+    /// The provider cancelled the transfer.
+    ///
+    /// The response was aborted before it was complete, e.g. because an operator cancelled
+    /// it or the provider is shutting down.  The data received so far is valid.
+    Cancelled = 4,
 }
 
 impl Closed {
@@ -635,6 +917,8 @@ impl Closed {
             Closed::StreamDropped => b"stream dropped",
             Closed::ProviderTerminating => b"provider terminating",
             Closed::RequestReceived => b"request received",
+            Closed::RateLimited => b"rate limited",
+            Closed::Cancelled => b"cancelled",
         }
     }
 }
@@ -658,6 +942,8 @@ impl TryFrom<VarInt> for Closed {
             0 => Ok(Self::StreamDropped),
             1 => Ok(Self::ProviderTerminating),
             2 => Ok(Self::RequestReceived),
+            3 => Ok(Self::RateLimited),
+            4 => Ok(Self::Cancelled),
             val => Err(UnknownErrorCode(val)),
         }
     }
@@ -665,10 +951,58 @@ impl TryFrom<VarInt> for Closed {
 
 #[cfg(test)]
 mod tests {
-    use bytes::Bytes;
+    use bytes::{Bytes, BytesMut};
     use iroh_test::{assert_eq_hex, hexdump::parse_hexdump};
 
-    use super::{CustomGetRequest, GetRequest, Request, RequestToken};
+    use bao_tree::ChunkNum;
+    use range_collections::RangeSet2;
+
+    use super::{
+        read_lp, read_lp_with_capacity, write_lp, BlobSetDiffRequest, CustomGetRequest, GetRequest,
+        RangeSpecSeq, Request, RequestToken,
+    };
+
+    #[tokio::test]
+    async fn read_lp_capacity() {
+        let messages = [&b"hello"[..], b"", b"world"];
+        let mut data = Vec::new();
+        for message in messages {
+            write_lp(&mut data, message).await.unwrap();
+        }
+
+        // without a hint, the buffer is only as large as the message
+        let mut buffer = BytesMut::new();
+        let mut reader = &data[..];
+        let first = read_lp(&mut reader, &mut buffer).await.unwrap().unwrap();
+        assert_eq!(first, messages[0]);
+        assert!(buffer.capacity() < 1024);
+
+        // with a hint, later messages reuse the allocation of the first one
+        let mut buffer = BytesMut::new();
+        let mut reader = &data[..];
+        let first = read_lp_with_capacity(&mut reader, &mut buffer, 1024)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, messages[0]);
+        let capacity = buffer.capacity();
+        assert!(capacity >= 1024 - first.len());
+        for expected in &messages[1..] {
+            let message = read_lp_with_capacity(&mut reader, &mut buffer, 1024)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(message, expected);
+        }
+        assert_eq!(
+            buffer.capacity(),
+            capacity - messages[1..].iter().map(|m| m.len()).sum::<usize>()
+        );
+        assert!(read_lp_with_capacity(&mut reader, &mut buffer, 1024)
+            .await
+            .unwrap()
+            .is_none());
+    }
 
     #[test]
     fn request_wire_format() {
@@ -730,6 +1064,16 @@ mod tests {
                     68 65 6c 6c 6f # value content 'hello'
             ",
             ),
+            (
+                Request::from(BlobSetDiffRequest::new([[0xdb; 32].into(), hash, hash])),
+                r"
+                    04 # enum variant for BlobSetDiffRequest
+                    00 # no token
+                    02 # two prefixes
+                    dadadadadadadada # sorted prefixes
+                    dbdbdbdbdbdbdbdb
+            ",
+            ),
         ];
         for (case, expected_hex) in cases {
             let expected = parse_hexdump(expected_hex).unwrap();
@@ -737,4 +1081,43 @@ mod tests {
             assert_eq_hex!(bytes, expected);
         }
     }
+
+    #[test]
+    fn get_request_split() {
+        let hash = [0xda; 32].into();
+        let ranges = |r: std::ops::Range<u64>| RangeSet2::from(ChunkNum(r.start)..ChunkNum(r.end));
+        let request = GetRequest::new(
+            hash,
+            RangeSpecSeq::from_ranges([
+                ranges(0..1),
+                ranges(0..4) | ranges(8..12),
+                RangeSet2::empty(),
+                ranges(2..3),
+            ]),
+        );
+        let parts = request.split(2);
+        assert_eq!(
+            parts,
+            vec![
+                GetRequest::new(
+                    hash,
+                    RangeSpecSeq::from_ranges([ranges(0..1), ranges(0..4)])
+                ),
+                GetRequest::new(
+                    hash,
+                    RangeSpecSeq::from_ranges([
+                        RangeSet2::empty(),
+                        ranges(8..12),
+                        RangeSet2::empty(),
+                        ranges(2..3),
+                    ])
+                ),
+            ]
+        );
+        // splitting into more parts than there are ranges yields one part per range
+        assert_eq!(request.split(10).len(), 4);
+        // single stream requests and open ended requests are left unchanged
+        assert_eq!(request.split(1), vec![request.clone()]);
+        assert_eq!(GetRequest::all(hash).split(4), vec![GetRequest::all(hash)]);
+    }
 }
@@ -183,6 +183,17 @@ impl RangeSpecSeq {
         }
     }
 
+    /// True if only a finite number of blobs in the sequence have selected chunks.
+    ///
+    /// This is the case if the sequence ends on an empty [`RangeSpec`], and means that
+    /// [`RangeSpecSeq::iter_non_empty`] will terminate.
+    pub fn is_finite(&self) -> bool {
+        self.0
+            .last()
+            .map(|(_, spec)| spec.is_empty())
+            .unwrap_or(true)
+    }
+
     /// A [`RangeSpecSeq`] containing all chunks from all blobs.
     ///
     /// [`RangeSpecSeq::iter`], will return a full range forever.
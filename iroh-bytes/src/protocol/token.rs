@@ -0,0 +1,465 @@
+//! Request tokens carrying signed claims.
+//!
+//! A [`StructuredToken`] is a set of named claims together with a signature over them. It
+//! is serialized into the bytes of a [`RequestToken`], so it can be used wherever a request
+//! token is accepted.
+//!
+//! The signature is a keyed blake3 hash of the claims, so tokens can only be issued and
+//! verified by parties that know the secret key. A provider typically issues tokens for
+//! its own content, and verifies them in its
+//! [`RequestAuthorizationHandler`](crate::provider::RequestAuthorizationHandler).
+//!
+//! Some claims have a well known meaning, see [`StructuredToken::check_claims`]. Other
+//! claims are ignored by it and can be used by applications for their own checks.
+//!
+//! Tokens issued with [`StructuredToken::issue`] carry a unique id, so they can be revoked
+//! before they expire by adding them to a [`RevocationList`].
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, ensure, Context, Result};
+use bao_tree::blake3;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use super::{RangeSpec, RangeSpecSeq, Request, RequestToken};
+
+/// Claim for the time after which the token is no longer valid.
+///
+/// The value is a [`ClaimValue::U64`] with the number of seconds since the unix epoch.
+pub const CLAIM_EXPIRES: &str = "exp";
+
+/// Claim for the prefix that the hash of a request must start with.
+///
+/// The value is a [`ClaimValue::Bytes`]. A full hash restricts the token to a single blob,
+/// or to a collection and its children.
+pub const CLAIM_HASH_PREFIX: &str = "hash_prefix";
+
+/// Claim for the maximum number of bytes a single request may ask for.
+///
+/// The value is a [`ClaimValue::U64`]. Requests are measured by the chunks they select, so
+/// requests for open ended ranges or for all children of a collection are rejected.
+pub const CLAIM_MAX_BYTES: &str = "max_bytes";
+
+/// Claim for the unique id of the token.
+///
+/// The value is a [`ClaimValue::Bytes`]. It identifies the token when it is revoked, see
+/// [`RevocationList`]. Tokens without an id can not be revoked.
+pub const CLAIM_TOKEN_ID: &str = "jti";
+
+/// Length of the random ids added by [`StructuredToken::issue`].
+const TOKEN_ID_LEN: usize = 16;
+
+/// Size of a bao chunk in bytes.
+const CHUNK_SIZE: u64 = 1024;
+
+/// The value of a claim in a [`StructuredToken`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClaimValue {
+    /// A boolean flag
+    Bool(bool),
+    /// An unsigned integer
+    U64(u64),
+    /// A string
+    String(String),
+    /// Arbitrary bytes
+    Bytes(Bytes),
+}
+
+/// A request token consisting of signed claims.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StructuredToken {
+    claims: BTreeMap<String, ClaimValue>,
+    signature: [u8; 32],
+}
+
+impl StructuredToken {
+    /// Creates a token for the given claims, signed with `key`.
+    pub fn sign(claims: BTreeMap<String, ClaimValue>, key: &[u8; 32]) -> Self {
+        let signature = signature(&claims, key);
+        Self { claims, signature }
+    }
+
+    /// Creates a token for the given claims with a random [`CLAIM_TOKEN_ID`], signed with
+    /// `key`.
+    ///
+    /// An id that is already part of the claims is kept.
+    pub fn issue(mut claims: BTreeMap<String, ClaimValue>, key: &[u8; 32]) -> Self {
+        claims.entry(CLAIM_TOKEN_ID.to_string()).or_insert_with(|| {
+            ClaimValue::Bytes(rand::random::<[u8; TOKEN_ID_LEN]>().to_vec().into())
+        });
+        Self::sign(claims, key)
+    }
+
+    /// Parses a token from the bytes of a [`RequestToken`].
+    ///
+    /// This does not verify the signature, see [`Self::verify`].
+    pub fn from_request_token(token: &RequestToken) -> Result<Self> {
+        postcard::from_bytes(token.as_bytes()).context("invalid structured token")
+    }
+
+    /// Serializes the token into a [`RequestToken`].
+    ///
+    /// Fails if the claims are too large to fit into a request token.
+    pub fn to_request_token(&self) -> Result<RequestToken> {
+        RequestToken::new(postcard::to_stdvec(self)?)
+    }
+
+    /// The claims of this token.
+    pub fn claims(&self) -> &BTreeMap<String, ClaimValue> {
+        &self.claims
+    }
+
+    /// Gets a single claim.
+    pub fn claim(&self, name: &str) -> Option<&ClaimValue> {
+        self.claims.get(name)
+    }
+
+    /// The id of this token, if it has a [`CLAIM_TOKEN_ID`] claim.
+    pub fn id(&self) -> Option<&Bytes> {
+        match self.claim(CLAIM_TOKEN_ID)? {
+            ClaimValue::Bytes(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The expiry of this token in seconds since the unix epoch, if it has a
+    /// [`CLAIM_EXPIRES`] claim.
+    pub fn expires(&self) -> Option<u64> {
+        match self.claim(CLAIM_EXPIRES)? {
+            ClaimValue::U64(expires) => Some(*expires),
+            _ => None,
+        }
+    }
+
+    /// Verifies that the token was signed with `key`.
+    pub fn verify(&self, key: &[u8; 32]) -> Result<()> {
+        let expected = signature(&self.claims, key);
+        ensure!(
+            bool::from(self.signature.ct_eq(&expected)),
+            "invalid token signature"
+        );
+        Ok(())
+    }
+
+    /// Checks the well known claims against a request.
+    ///
+    /// - [`CLAIM_EXPIRES`] must be after `now`.
+    /// - [`CLAIM_HASH_PREFIX`] must be a prefix of the requested hash. Custom get requests
+    ///   have no hash, so they are rejected if this claim is present.
+    /// - [`CLAIM_MAX_BYTES`] must not be exceeded by the requested ranges. Raw get requests
+    ///   are for complete blobs of any size, so they are rejected if this claim is present.
+    ///
+    /// Claims that are not present are not checked. This does not verify the signature.
+    pub fn check_claims(&self, request: &Request, now: SystemTime) -> Result<()> {
+        if let Some(value) = self.claim(CLAIM_EXPIRES) {
+            let ClaimValue::U64(expires) = value else {
+                bail!("invalid {CLAIM_EXPIRES} claim");
+            };
+            let now = now.duration_since(UNIX_EPOCH)?.as_secs();
+            ensure!(now < *expires, "token expired");
+        }
+        if let Some(value) = self.claim(CLAIM_HASH_PREFIX) {
+            let ClaimValue::Bytes(prefix) = value else {
+                bail!("invalid {CLAIM_HASH_PREFIX} claim");
+            };
+            let hash = match request {
+                Request::Get(get) => get.hash,
+                Request::LiveGet(get) => get.hash,
+                Request::PartialGet(get) => get.hash,
+                Request::RawGet(get) => get.hash,
+                Request::CustomGet(_) | Request::BlobSetDiff(_) => {
+                    bail!("token is restricted to hashes")
+                }
+            };
+            ensure!(
+                hash.as_bytes().starts_with(prefix),
+                "token does not allow {hash}"
+            );
+        }
+        if let Some(value) = self.claim(CLAIM_MAX_BYTES) {
+            let ClaimValue::U64(max_bytes) = value else {
+                bail!("invalid {CLAIM_MAX_BYTES} claim");
+            };
+            let requested = match request {
+                Request::Get(get) => seq_bytes(&get.ranges, *max_bytes),
+                Request::LiveGet(get) => spec_bytes(&get.ranges),
+                Request::PartialGet(get) => spec_bytes(&get.ranges),
+                // the complete blob is requested, whatever its size
+                Request::RawGet(_) | Request::CustomGet(_) | Request::BlobSetDiff(_) => None,
+            };
+            ensure!(
+                requested.is_some_and(|requested| requested <= *max_bytes),
+                "request exceeds the {max_bytes} bytes allowed by the token"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A token that was revoked before its expiry, see [`RevocationList`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokedToken {
+    /// The [`CLAIM_TOKEN_ID`] of the token.
+    pub id: Bytes,
+    /// The [`CLAIM_EXPIRES`] of the token, or `None` if it never expires.
+    pub expires: Option<u64>,
+}
+
+impl RevokedToken {
+    /// The revocation of `token`, or `None` if it has no id.
+    pub fn of(token: &StructuredToken) -> Option<Self> {
+        Some(Self {
+            id: token.id()?.clone(),
+            expires: token.expires(),
+        })
+    }
+}
+
+/// A bounded set of revoked tokens.
+///
+/// Revoked tokens are rejected even if they are otherwise valid. Once the natural expiry
+/// of a revoked token has passed, it is rejected by [`StructuredToken::check_claims`]
+/// anyway, so its entry is pruned. Entries of tokens that never expire are kept forever.
+///
+/// The list holds at most [`RevocationList::MAX_LEN`] entries. Revoking fails if it is
+/// still full after pruning, since evicting an entry would make a revoked token valid
+/// again. Tokens should therefore be issued with an expiry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevocationList {
+    // the expiry of each revoked token id
+    revoked: BTreeMap<Bytes, Option<u64>>,
+}
+
+impl RevocationList {
+    /// The maximum number of revoked tokens.
+    pub const MAX_LEN: usize = 16 * 1024;
+
+    /// Revokes a token.
+    ///
+    /// Returns whether the list changed. Tokens that are already revoked or expired are
+    /// not added again.
+    pub fn revoke(&mut self, token: RevokedToken, now: SystemTime) -> Result<bool> {
+        let now = now.duration_since(UNIX_EPOCH)?.as_secs();
+        let pruned = self.prune_secs(now);
+        if self.revoked.contains_key(&token.id)
+            || token.expires.is_some_and(|expires| expires <= now)
+        {
+            return Ok(pruned > 0);
+        }
+        ensure!(
+            self.revoked.len() < Self::MAX_LEN,
+            "too many revoked tokens, at most {} are allowed",
+            Self::MAX_LEN
+        );
+        self.revoked.insert(token.id, token.expires);
+        Ok(true)
+    }
+
+    /// Whether the token with the given id was revoked.
+    pub fn is_revoked(&self, id: &[u8]) -> bool {
+        self.revoked.contains_key(id)
+    }
+
+    /// Removes the tokens that expired before `now`, and returns how many were removed.
+    pub fn prune(&mut self, now: SystemTime) -> usize {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        self.prune_secs(now)
+    }
+
+    fn prune_secs(&mut self, now: u64) -> usize {
+        let len = self.revoked.len();
+        self.revoked
+            .retain(|_, expires| expires.map_or(true, |expires| expires > now));
+        len - self.revoked.len()
+    }
+
+    /// The revoked tokens, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = RevokedToken> + '_ {
+        self.revoked.iter().map(|(id, expires)| RevokedToken {
+            id: id.clone(),
+            expires: *expires,
+        })
+    }
+
+    /// The number of revoked tokens.
+    pub fn len(&self) -> usize {
+        self.revoked.len()
+    }
+
+    /// Whether no tokens are revoked.
+    pub fn is_empty(&self) -> bool {
+        self.revoked.is_empty()
+    }
+}
+
+fn signature(claims: &BTreeMap<String, ClaimValue>, key: &[u8; 32]) -> [u8; 32] {
+    let bytes = postcard::to_stdvec(claims).expect("claims can always be serialized");
+    *blake3::keyed_hash(key, &bytes).as_bytes()
+}
+
+/// Upper bound of the bytes selected by a [`RangeSpec`], or `None` if it is open ended.
+fn spec_bytes(spec: &RangeSpec) -> Option<u64> {
+    let ranges = spec.to_chunk_ranges();
+    let boundaries = ranges.boundaries();
+    if boundaries.len() % 2 != 0 {
+        return None;
+    }
+    boundaries.chunks(2).try_fold(0u64, |total, range| {
+        let chunks = range[1].0 - range[0].0;
+        total.checked_add(chunks.checked_mul(CHUNK_SIZE)?)
+    })
+}
+
+/// Upper bound of the bytes selected by a [`RangeSpecSeq`], or `None` if it is unbounded.
+///
+/// Stops counting once `limit` is exceeded, since every non empty spec selects at least a
+/// chunk, so this terminates quickly for sequences with many entries.
+fn seq_bytes(seq: &RangeSpecSeq, limit: u64) -> Option<u64> {
+    if !seq.is_finite() {
+        return None;
+    }
+    let mut total = 0u64;
+    for (_, spec) in seq.iter_non_empty() {
+        total = total.checked_add(spec_bytes(spec)?)?;
+        if total > limit {
+            break;
+        }
+    }
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bao_tree::ChunkNum;
+    use range_collections::RangeSet2;
+
+    use super::*;
+    use crate::protocol::{GetRequest, LiveGetRequest, PartialGetRequest};
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    fn token(claims: impl IntoIterator<Item = (&'static str, ClaimValue)>) -> StructuredToken {
+        let claims = claims
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        StructuredToken::sign(claims, &KEY)
+    }
+
+    #[test]
+    fn structured_token_roundtrip() {
+        let token = token([
+            (CLAIM_EXPIRES, ClaimValue::U64(100)),
+            ("user", ClaimValue::String("alice".into())),
+        ]);
+        let request_token = token.to_request_token().unwrap();
+        let parsed = StructuredToken::from_request_token(&request_token).unwrap();
+        assert_eq!(parsed, token);
+        parsed.verify(&KEY).unwrap();
+        assert!(parsed.verify(&[8u8; 32]).is_err());
+
+        // tampering with the claims invalidates the signature
+        let mut tampered = parsed.clone();
+        tampered
+            .claims
+            .insert(CLAIM_EXPIRES.to_string(), ClaimValue::U64(200));
+        assert!(tampered.verify(&KEY).is_err());
+
+        // opaque tokens are not structured tokens
+        assert!(StructuredToken::from_request_token(&RequestToken::generate()).is_err());
+    }
+
+    #[test]
+    fn structured_token_claims() {
+        let hash = [0xda; 32].into();
+        let other = [0x01; 32].into();
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let get = Request::from(GetRequest::single(hash));
+
+        token([]).check_claims(&get, now).unwrap();
+
+        let expires = token([(CLAIM_EXPIRES, ClaimValue::U64(1001))]);
+        expires.check_claims(&get, now).unwrap();
+        assert!(expires
+            .check_claims(&get, now + Duration::from_secs(1))
+            .is_err());
+
+        let prefix = token([(
+            CLAIM_HASH_PREFIX,
+            ClaimValue::Bytes(vec![0xda, 0xda].into()),
+        )]);
+        prefix.check_claims(&get, now).unwrap();
+        assert!(prefix
+            .check_claims(&GetRequest::single(other).into(), now)
+            .is_err());
+
+        let max_bytes = token([(CLAIM_MAX_BYTES, ClaimValue::U64(4096))]);
+        assert!(max_bytes.check_claims(&get, now).is_err());
+        max_bytes
+            .check_claims(&PartialGetRequest::bytes(hash, 0..4096).into(), now)
+            .unwrap();
+        assert!(max_bytes
+            .check_claims(&PartialGetRequest::bytes(hash, 0..4097).into(), now)
+            .is_err());
+        assert!(max_bytes
+            .check_claims(&LiveGetRequest::all(hash).into(), now)
+            .is_err());
+        let ranges = RangeSpecSeq::from_ranges([
+            RangeSet2::from(ChunkNum(0)..ChunkNum(2)),
+            RangeSet2::from(ChunkNum(0)..ChunkNum(2)),
+        ]);
+        max_bytes
+            .check_claims(&GetRequest::new(hash, ranges).into(), now)
+            .unwrap();
+        assert!(max_bytes
+            .check_claims(&GetRequest::all(hash).into(), now)
+            .is_err());
+
+        let invalid = token([(CLAIM_EXPIRES, ClaimValue::String("never".into()))]);
+        assert!(invalid.check_claims(&get, now).is_err());
+    }
+
+    #[test]
+    fn revocation_list() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let claims = BTreeMap::from([(CLAIM_EXPIRES.to_string(), ClaimValue::U64(1010))]);
+        let a = StructuredToken::issue(claims.clone(), &KEY);
+        let b = StructuredToken::issue(claims, &KEY);
+        assert_ne!(a.id(), b.id());
+        assert_eq!(a.id().map(|id| id.len()), Some(TOKEN_ID_LEN));
+        assert_eq!(a.expires(), Some(1010));
+        assert!(RevokedToken::of(&token([])).is_none());
+
+        let mut list = RevocationList::default();
+        let revoked = RevokedToken::of(&a).unwrap();
+        assert!(list.revoke(revoked.clone(), now).unwrap());
+        assert!(!list.revoke(revoked.clone(), now).unwrap());
+        assert!(list.is_revoked(a.id().unwrap()));
+        assert!(!list.is_revoked(b.id().unwrap()));
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![revoked]);
+
+        // tokens without expiry are kept, expired ones are pruned
+        let forever = RevokedToken {
+            id: Bytes::from_static(b"forever"),
+            expires: None,
+        };
+        assert!(list.revoke(forever.clone(), now).unwrap());
+        assert_eq!(list.prune(now + Duration::from_secs(10)), 1);
+        assert_eq!(list.iter().collect::<Vec<_>>(), vec![forever]);
+
+        // revoking an expired token is a no-op
+        let expired = RevokedToken {
+            id: Bytes::from_static(b"expired"),
+            expires: Some(1000),
+        };
+        assert!(!list.revoke(expired, now).unwrap());
+        assert_eq!(list.len(), 1);
+    }
+}
@@ -1,25 +1,56 @@
 //! The server side API
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::time::Duration;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Result};
 use bao_tree::io::fsm::{encode_ranges_validated, Outboard};
+use bao_tree::{ByteNum, ChunkNum, ChunkRanges};
 use bytes::Bytes;
 use futures::future::BoxFuture;
+use iroh_io::AsyncSliceReader;
+use range_collections::range_set::RangeSetRange;
 use serde::{Deserialize, Serialize};
-use tokio::io::AsyncWrite;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, debug_span, warn};
 use tracing_futures::Instrument;
 
 use crate::baomap::*;
 use crate::collection::CollectionParser;
-use crate::protocol::{write_lp, CustomGetRequest, GetRequest, RangeSpec, Request, RequestToken};
+use crate::protocol::{
+    write_lp, BlobSetDiffRequest, Closed, CustomGetRequest, GetRequest, LiveGetRequest,
+    PartialGetRequest, RangeSpec, RawGetRequest, Request, RequestToken, RevokedToken,
+    MAX_BLOB_SET_DIFF_PREFIXES,
+};
+use crate::util::io::{ReadAheadReader, TrackingWriter};
 use crate::util::{BlobFormat, RpcError, Tag};
-use crate::Hash;
+use crate::{Hash, IROH_BLOCK_SIZE};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+#[cfg(feature = "metrics")]
+use iroh_metrics::core::Metric;
+
+/// Size of the reads of [`send_raw_blob`], 16 chunk groups.
+const RAW_READ_SIZE: usize = 1024 * 256;
+
+/// Number of hashes that [`send_blob_set_diff`] writes at once.
+const BLOB_SET_DIFF_BATCH_SIZE: usize = 1024;
+
+/// How long [`send_live_blob`] waits for new data of the blob before it gives up.
+pub const LIVE_BLOB_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Events emitted by the provider informing about the current status.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
     /// A new collection or tagged blob has been added
     TaggedBlobAdded {
@@ -57,6 +88,17 @@ pub enum Event {
         /// The size of the custom get request.
         len: usize,
     },
+    /// A request for the difference of the blob sets was received from a client.
+    BlobSetDiffRequestReceived {
+        /// An unique connection id.
+        connection_id: u64,
+        /// An identifier uniquely identifying this transfer request.
+        request_id: u64,
+        /// Token requester gve for this request, if any
+        token: Option<RequestToken>,
+        /// The number of hash prefixes in the request.
+        len: usize,
+    },
     /// A collection has been found and is being transferred.
     TransferCollectionStarted {
         /// An unique connection id.
@@ -88,7 +130,21 @@ pub enum Event {
         /// The size of the blob transferred.
         size: u64,
     },
-    /// A request was aborted because the client disconnected.
+    /// Data of a blob was sent to a client.
+    ///
+    /// This is emitted once for every blob of a request that was sent completely, including
+    /// the root of a collection and blobs of live and partial requests.
+    BlobServed {
+        /// An unique connection id.
+        connection_id: u64,
+        /// An identifier uniquely identifying this transfer request.
+        request_id: u64,
+        /// The hash of the blob.
+        hash: Hash,
+        /// The number of bytes written, including the size header and the outboard data.
+        bytes_sent: u64,
+    },
+    /// A request was aborted because the client disconnected or the transfer was cancelled.
     TransferAborted {
         /// The quic connection id.
         connection_id: u64,
@@ -136,6 +192,11 @@ pub enum AddProgress {
     ///
     /// This will be the last message in the stream.
     Abort(RpcError),
+    /// We ran out of disk space and need to abort.
+    ///
+    /// Temporary files created by the import have been removed. This will be the
+    /// last message in the stream.
+    DiskFull,
 }
 
 /// Progress updates for the get operation.
@@ -219,6 +280,29 @@ pub trait RequestAuthorizationHandler: Send + Sync + Debug + 'static {
         token: Option<RequestToken>,
         request: &Request,
     ) -> BoxFuture<'static, anyhow::Result<()>>;
+
+    /// Revoke a token before its expiry, so it is rejected by [`Self::authorize`].
+    ///
+    /// Returns whether the revoked tokens changed. The default implementation does not
+    /// support revoking tokens and fails.
+    fn revoke(&self, token: RevokedToken) -> anyhow::Result<bool> {
+        anyhow::bail!("revoking {:?} is not supported", token.id)
+    }
+
+    /// The tokens that were revoked and have not expired yet.
+    fn revoked(&self) -> Vec<RevokedToken> {
+        Vec::new()
+    }
+
+    /// Whether `hash` may be listed in the response to an authorized
+    /// [`Request::BlobSetDiff`].
+    ///
+    /// Unlike the other requests, a blob set diff reveals blobs the requester did not ask
+    /// for by hash. Blobs for which this returns false are left out of the response. The
+    /// default implementation lists all complete blobs.
+    fn list_blob(&self, _token: Option<&RequestToken>, _hash: &Hash) -> bool {
+        true
+    }
 }
 
 /// A custom get request handler that allows the user to make up a get request
@@ -238,10 +322,14 @@ pub trait CustomGetHandler: Send + Sync + Debug + 'static {
 /// contains more data than the Request, or if no valid request is sent.
 ///
 /// When successful, the buffer is empty after this function call.
-pub async fn read_request(mut reader: quinn::RecvStream) -> Result<Request> {
-    let payload = reader
-        .read_to_end(crate::protocol::MAX_MESSAGE_SIZE)
+pub async fn read_request<R: AsyncRead + Unpin>(reader: R) -> Result<Request> {
+    let max = crate::protocol::MAX_MESSAGE_SIZE;
+    let mut payload = Vec::new();
+    reader
+        .take(max as u64 + 1)
+        .read_to_end(&mut payload)
         .await?;
+    anyhow::ensure!(payload.len() <= max, "request exceeds {max} bytes");
     let request: Request = postcard::from_bytes(&payload)?;
     Ok(request)
 }
@@ -257,19 +345,41 @@ pub async fn read_request(mut reader: quinn::RecvStream) -> Result<Request> {
 /// If a blob from the collection cannot be found in the database, the transfer will gracefully
 /// close the writer, and return with `Ok(SentStatus::NotFound)`.
 ///
+/// The cancellation token of the writer is checked before each child and while encoding.
+/// If it is cancelled, this returns with `Ok(SentStatus::Cancelled)` without closing the
+/// writer.
+///
 /// If the transfer does _not_ end in error, the buffer will be empty and the writer is gracefully closed.
-pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser>(
+pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser, W: ResponseStream>(
     request: GetRequest,
     // Store from which to fetch blobs.
     db: &D,
-    // Response writer, containing the quinn stream.
-    writer: &mut ResponseWriter<E>,
+    // Response writer, containing the stream to the getter.
+    writer: &mut ResponseWriter<E, W>,
     // the collection to transfer
     mut outboard: D::Outboard,
-    mut data: D::DataReader,
+    data: D::DataReader,
     collection_parser: C,
 ) -> Result<SentStatus> {
     let hash = request.hash;
+    // the collection is read while its children are sent, so this needs two buffers
+    let readers = if request
+        .ranges
+        .iter_non_empty()
+        .any(|(offset, _)| offset > 0)
+    {
+        2
+    } else {
+        1
+    };
+    let memory = writer
+        .transfer_memory
+        .acquire(readers * buffer_size(writer.read_ahead));
+    let Some(_memory) = cancellable(&writer.cancel, memory).await else {
+        debug!("cancelled while waiting for transfer memory");
+        return Ok(SentStatus::Cancelled);
+    };
+    let mut data = ReadAheadReader::new(data, writer.read_ahead);
 
     // if the request is just for the root, we don't need to deserialize the collection
     let just_root = matches!(request.ranges.as_single(), Some((0, _)));
@@ -295,19 +405,42 @@ pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser>(
         if offset == 0 {
             debug!("writing ranges '{:?}' of collection {}", ranges, hash);
             // send the root
-            encode_ranges_validated(
-                &mut data,
-                &mut outboard,
-                &ranges.to_chunk_ranges(),
-                &mut writer.inner,
+            let mut inner = TrackingWriter::new(&mut writer.inner);
+            let res = cancellable(
+                &writer.cancel,
+                encode_ranges_validated(
+                    &mut data,
+                    &mut outboard,
+                    &ranges.to_chunk_ranges(),
+                    &mut inner,
+                ),
             )
-            .await?;
+            .await;
+            let Some(res) = res else {
+                debug!("cancelled writing collection {}", hash);
+                return Ok(SentStatus::Cancelled);
+            };
+            res?;
+            let bytes_sent = inner.bytes_written();
+            writer
+                .events
+                .send(Event::BlobServed {
+                    connection_id: writer.connection_id(),
+                    request_id: writer.request_id(),
+                    hash,
+                    bytes_sent,
+                })
+                .await;
             debug!(
                 "finished writing ranges '{:?}' of collection {}",
                 ranges, hash
             );
         } else {
             let c = c.as_mut().context("collection parser not available")?;
+            if writer.cancel.is_cancelled() {
+                debug!("cancelled before child {}", offset);
+                return Ok(SentStatus::Cancelled);
+            }
             debug!("wrtiting ranges '{:?}' of child {}", ranges, offset);
             // skip to the next blob if there is a gap
             if prev < offset - 1 {
@@ -315,10 +448,22 @@ pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser>(
             }
             if let Some(hash) = c.next().await? {
                 tokio::task::yield_now().await;
-                let (status, size) = send_blob(db, hash, ranges, &mut writer.inner).await?;
-                if SentStatus::NotFound == status {
-                    writer.inner.finish().await?;
-                    return Ok(status);
+                let stats = send_blob(
+                    db,
+                    hash,
+                    ranges,
+                    writer.read_ahead,
+                    &writer.cancel,
+                    &mut writer.inner,
+                )
+                .await?;
+                match stats.status {
+                    SentStatus::Sent => {}
+                    SentStatus::NotFound => {
+                        writer.inner.shutdown().await?;
+                        return Ok(stats.status);
+                    }
+                    SentStatus::Cancelled => return Ok(stats.status),
                 }
 
                 writer
@@ -328,7 +473,16 @@ pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser>(
                         request_id: writer.request_id(),
                         hash,
                         index: offset - 1,
-                        size,
+                        size: stats.size,
+                    })
+                    .await;
+                writer
+                    .events
+                    .send(Event::BlobServed {
+                        connection_id: writer.connection_id(),
+                        request_id: writer.request_id(),
+                        hash,
+                        bytes_sent: stats.bytes_sent,
                     })
                     .await;
             } else {
@@ -340,10 +494,20 @@ pub async fn transfer_collection<D: Map, E: EventSender, C: CollectionParser>(
     }
 
     debug!("done writing");
-    writer.inner.finish().await?;
+    writer.inner.shutdown().await?;
     Ok(SentStatus::Sent)
 }
 
+/// Run `fut` to completion, unless `cancel` is cancelled first, in which case `None` is
+/// returned.
+async fn cancellable<F: Future>(cancel: &CancellationToken, fut: F) -> Option<F::Output> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => None,
+        res = fut => Some(res),
+    }
+}
+
 /// Trait for sending events.
 pub trait EventSender: Clone + Sync + Send + 'static {
     /// Send an event.
@@ -351,7 +515,26 @@ pub trait EventSender: Clone + Sync + Send + 'static {
 }
 
 /// Handle a single connection.
-pub async fn handle_connection<D: Map, E: EventSender, C: CollectionParser>(
+///
+/// Each request is handled in its own task, which holds a permit from `request_limit`
+/// while it runs.  The semaphore can be shared between connections to bound the total
+/// number of requests in flight.  Streams that arrive while no permit is available are
+/// rejected with [`Closed::RateLimited`] instead of being queued.
+///
+/// At most `max_connection_requests` requests of this connection are handled at the same
+/// time, so a single peer can not take all permits of a shared `request_limit`. Streams
+/// beyond this are rejected with [`Closed::RateLimited`] as well.
+///
+/// Data of complete blobs is read in reads of at least `read_ahead` bytes, see
+/// [`ReadAheadReader`]. A value of 0 reads exactly what is needed to encode the response.
+/// The buffers of these reads are accounted for in `transfer_memory`, see [`TransferMemory`].
+///
+/// Cancelling `cancel` aborts the get requests in flight on this connection.  Their streams
+/// are reset with [`Closed::Cancelled`] and [`Event::TransferAborted`] is emitted.
+///
+/// The requests are registered in `transfers` while they are handled, see [`Transfers`].
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_connection<D: ReadableStore, E: EventSender, C: CollectionParser>(
     connecting: quinn::Connecting,
     db: D,
     events: E,
@@ -359,6 +542,12 @@ pub async fn handle_connection<D: Map, E: EventSender, C: CollectionParser>(
     custom_get_handler: Arc<dyn CustomGetHandler>,
     authorization_handler: Arc<dyn RequestAuthorizationHandler>,
     rt: crate::util::runtime::Handle,
+    request_limit: Arc<Semaphore>,
+    max_connection_requests: usize,
+    read_ahead: usize,
+    transfer_memory: TransferMemory,
+    transfers: Transfers,
+    cancel: CancellationToken,
 ) {
     let remote_addr = connecting.remote_address();
     let connection = match connecting.await {
@@ -368,19 +557,82 @@ pub async fn handle_connection<D: Map, E: EventSender, C: CollectionParser>(
             return;
         }
     };
+    handle_established_connection(
+        connection,
+        db,
+        events,
+        collection_parser,
+        custom_get_handler,
+        authorization_handler,
+        rt,
+        request_limit,
+        max_connection_requests,
+        read_ahead,
+        transfer_memory,
+        transfers,
+        cancel,
+    )
+    .await
+}
+
+/// Handle a single connection that is already established.
+///
+/// This is [`handle_connection`] for callers that need the connection before requests are
+/// served on it, e.g. to learn the peer id of the remote.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_established_connection<
+    D: ReadableStore,
+    E: EventSender,
+    C: CollectionParser,
+>(
+    connection: quinn::Connection,
+    db: D,
+    events: E,
+    collection_parser: C,
+    custom_get_handler: Arc<dyn CustomGetHandler>,
+    authorization_handler: Arc<dyn RequestAuthorizationHandler>,
+    rt: crate::util::runtime::Handle,
+    request_limit: Arc<Semaphore>,
+    max_connection_requests: usize,
+    read_ahead: usize,
+    transfer_memory: TransferMemory,
+    transfers: Transfers,
+    cancel: CancellationToken,
+) {
+    let remote_addr = connection.remote_address();
     let connection_id = connection.stable_id() as u64;
     let span = debug_span!("connection", connection_id, %remote_addr);
+    let connection_limit = Arc::new(Semaphore::new(max_connection_requests));
     async move {
-        while let Ok((writer, reader)) = connection.accept_bi().await {
+        while let Ok((mut writer, mut reader)) = connection.accept_bi().await {
             // The stream ID index is used to identify this request.  Requests only arrive in
             // bi-directional RecvStreams initiated by the client, so this uniquely identifies them.
             let request_id = reader.id().index();
             let span = debug_span!("stream", stream_id = %request_id);
-            let writer = ResponseWriter {
-                connection_id,
-                events: events.clone(),
-                inner: writer,
+            let Ok(connection_permit) = connection_limit.clone().try_acquire_owned() else {
+                debug!(stream_id = %request_id, "too many requests on this connection, rejecting stream");
+                let error_code = Closed::RateLimited;
+                writer.reset(error_code.into()).ok();
+                reader.stop(error_code.into()).ok();
+                continue;
             };
+            let Ok(permit) = request_limit.clone().try_acquire_owned() else {
+                debug!(stream_id = %request_id, "too many requests in flight, rejecting stream");
+                let error_code = Closed::RateLimited;
+                writer.reset(error_code.into()).ok();
+                reader.stop(error_code.into()).ok();
+                continue;
+            };
+            let writer = ResponseWriter::new(
+                writer,
+                events.clone(),
+                connection_id,
+                request_id,
+                read_ahead,
+                transfer_memory.clone(),
+                cancel.child_token(),
+            )
+            .track(&transfers, remote_addr);
             events.send(Event::ClientConnected { connection_id }).await;
             let db = db.clone();
             let custom_get_handler = custom_get_handler.clone();
@@ -400,6 +652,8 @@ pub async fn handle_connection<D: Map, E: EventSender, C: CollectionParser>(
                     {
                         warn!("error: {err:#?}",);
                     }
+                    drop(permit);
+                    drop(connection_permit);
                 }
                 .instrument(span)
             });
@@ -409,14 +663,26 @@ pub async fn handle_connection<D: Map, E: EventSender, C: CollectionParser>(
     .await
 }
 
-async fn handle_stream<D: Map, E: EventSender, C: CollectionParser>(
+/// Handle a single request read from `reader`, writing the response to `writer`.
+///
+/// This is what [`handle_connection`] does for each stream of a connection. It works on any
+/// pair of streams, e.g. an in-process [`tokio::io::duplex`] to test the provider without
+/// networking.
+pub async fn handle_stream<D, E, C, R, W>(
     db: D,
-    reader: quinn::RecvStream,
-    writer: ResponseWriter<E>,
+    reader: R,
+    writer: ResponseWriter<E, W>,
     custom_get_handler: Arc<dyn CustomGetHandler>,
     authorization_handler: Arc<dyn RequestAuthorizationHandler>,
     collection_parser: C,
-) -> Result<()> {
+) -> Result<()>
+where
+    D: ReadableStore,
+    E: EventSender,
+    C: CollectionParser,
+    R: AsyncRead + Unpin,
+    W: ResponseStream,
+{
     // 1. Decode the request.
     debug!("reading request");
     let request = match read_request(reader).await {
@@ -442,12 +708,18 @@ async fn handle_stream<D: Map, E: EventSender, C: CollectionParser>(
         Request::CustomGet(request) => {
             handle_custom_get(db, request, writer, custom_get_handler, collection_parser).await
         }
+        Request::LiveGet(request) => handle_live_get(db, request, writer).await,
+        Request::PartialGet(request) => handle_partial_get(db, request, writer).await,
+        Request::BlobSetDiff(request) => {
+            handle_blob_set_diff(db, request, writer, authorization_handler).await
+        }
+        Request::RawGet(request) => handle_raw_get(db, request, writer).await,
     }
 }
-async fn handle_custom_get<E: EventSender, D: Map, C: CollectionParser>(
+async fn handle_custom_get<E: EventSender, D: Map, C: CollectionParser, W: ResponseStream>(
     db: D,
     request: CustomGetRequest,
-    mut writer: ResponseWriter<E>,
+    mut writer: ResponseWriter<E, W>,
     custom_get_handler: Arc<dyn CustomGetHandler>,
     collection_parser: C,
 ) -> Result<()> {
@@ -472,11 +744,11 @@ async fn handle_custom_get<E: EventSender, D: Map, C: CollectionParser>(
 }
 
 /// Handle a single standard get request.
-pub async fn handle_get<D: Map, E: EventSender, C: CollectionParser>(
+pub async fn handle_get<D: Map, E: EventSender, C: CollectionParser, W: ResponseStream>(
     db: D,
     request: GetRequest,
     collection_parser: C,
-    mut writer: ResponseWriter<E>,
+    mut writer: ResponseWriter<E, W>,
 ) -> Result<()> {
     let hash = request.hash;
     debug!(%hash, "received request");
@@ -489,6 +761,7 @@ pub async fn handle_get<D: Map, E: EventSender, C: CollectionParser>(
             token: request.token().cloned(),
         })
         .await;
+    writer.set_hash(hash);
 
     // 4. Attempt to find hash
     match db.get(&hash) {
@@ -511,6 +784,10 @@ pub async fn handle_get<D: Map, E: EventSender, C: CollectionParser>(
                 Ok(SentStatus::NotFound) => {
                     writer.notify_transfer_aborted().await;
                 }
+                Ok(SentStatus::Cancelled) => {
+                    debug!("cancelled response");
+                    writer.cancel_transfer().await;
+                }
                 Err(e) => {
                     writer.notify_transfer_aborted().await;
                     return Err(e);
@@ -522,28 +799,320 @@ pub async fn handle_get<D: Map, E: EventSender, C: CollectionParser>(
         None => {
             debug!("not found {}", hash);
             writer.notify_transfer_aborted().await;
-            writer.inner.finish().await?;
+            writer.inner.shutdown().await?;
         }
     };
 
     Ok(())
 }
 
-/// A helper struct that combines a quinn::SendStream with auxiliary information
+/// Handle a get request for a blob that may still be written.
+async fn handle_live_get<D: Map, E: EventSender, W: ResponseStream>(
+    db: D,
+    request: LiveGetRequest,
+    mut writer: ResponseWriter<E, W>,
+) -> Result<()> {
+    let hash = request.hash;
+    debug!(%hash, "received live request");
+    writer
+        .events
+        .send(Event::GetRequestReceived {
+            hash,
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+            token: request.token.clone(),
+        })
+        .await;
+    writer.set_hash(hash);
+    let Some(memory) = writer.acquire_memory(buffer_size(0)).await else {
+        return Ok(());
+    };
+    let send = send_live_blob(&db, hash, &request.ranges, &mut writer.inner);
+    let res = cancellable(&writer.cancel, send)
+        .await
+        .unwrap_or_else(|| Ok(TransferStats::cancelled()));
+    drop(memory);
+    finish_single_blob(hash, res, writer).await
+}
+
+/// Handle a get request for the available ranges of a blob.
+async fn handle_partial_get<D: Map, E: EventSender, W: ResponseStream>(
+    db: D,
+    request: PartialGetRequest,
+    mut writer: ResponseWriter<E, W>,
+) -> Result<()> {
+    let hash = request.hash;
+    debug!(%hash, "received partial request");
+    writer
+        .events
+        .send(Event::GetRequestReceived {
+            hash,
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+            token: request.token.clone(),
+        })
+        .await;
+    writer.set_hash(hash);
+    let Some(memory) = writer.acquire_memory(buffer_size(0)).await else {
+        return Ok(());
+    };
+    let send = send_partial_blob(&db, hash, &request.ranges, &mut writer.inner);
+    let res = cancellable(&writer.cancel, send)
+        .await
+        .unwrap_or_else(|| Ok(TransferStats::cancelled()));
+    drop(memory);
+    finish_single_blob(hash, res, writer).await
+}
+
+/// Handle a get request for the raw data of a blob.
+async fn handle_raw_get<D: Map, E: EventSender, W: ResponseStream>(
+    db: D,
+    request: RawGetRequest,
+    mut writer: ResponseWriter<E, W>,
+) -> Result<()> {
+    let hash = request.hash;
+    debug!(%hash, "received raw request");
+    writer
+        .events
+        .send(Event::GetRequestReceived {
+            hash,
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+            token: request.token.clone(),
+        })
+        .await;
+    writer.set_hash(hash);
+    let Some(memory) = writer.acquire_memory(RAW_READ_SIZE).await else {
+        return Ok(());
+    };
+    let send = send_raw_blob(&db, hash, &mut writer.inner);
+    let res = cancellable(&writer.cancel, send)
+        .await
+        .unwrap_or_else(|| Ok(TransferStats::cancelled()));
+    drop(memory);
+    finish_single_blob(hash, res, writer).await
+}
+
+/// Handle a request for the blobs that the requester does not have.
+async fn handle_blob_set_diff<D: ReadableStore, E: EventSender, W: ResponseStream>(
+    db: D,
+    request: BlobSetDiffRequest,
+    mut writer: ResponseWriter<E, W>,
+    authorization_handler: Arc<dyn RequestAuthorizationHandler>,
+) -> Result<()> {
+    debug!(len = request.len(), "received blob set diff request");
+    writer
+        .events
+        .send(Event::BlobSetDiffRequestReceived {
+            len: request.len(),
+            connection_id: writer.connection_id(),
+            request_id: writer.request_id(),
+            token: request.token.clone(),
+        })
+        .await;
+    if request.len() > MAX_BLOB_SET_DIFF_PREFIXES {
+        writer.notify_transfer_aborted().await;
+        anyhow::bail!(
+            "blob set diff request with {} prefixes exceeds the limit of {}",
+            request.len(),
+            MAX_BLOB_SET_DIFF_PREFIXES
+        );
+    }
+    let Some(_memory) = writer.acquire_memory(BLOB_SET_DIFF_BATCH_SIZE * 32).await else {
+        return Ok(());
+    };
+    let filter = |hash: &Hash| authorization_handler.list_blob(request.token.as_ref(), hash);
+    let send = send_blob_set_diff(&db, &request, filter, &mut writer.inner);
+    match cancellable(&writer.cancel, send).await {
+        None => {
+            debug!("cancelled blob set diff");
+            writer.cancel_transfer().await;
+            Ok(())
+        }
+        Some(Ok(count)) => {
+            debug!(count, "sent blob set diff");
+            writer.inner.shutdown().await?;
+            writer.notify_transfer_completed().await;
+            Ok(())
+        }
+        Some(Err(e)) => {
+            writer.notify_transfer_aborted().await;
+            Err(e)
+        }
+    }
+}
+
+/// Finish the response to a request for a single blob and emit the matching event.
+async fn finish_single_blob<E: EventSender, W: ResponseStream>(
+    hash: Hash,
+    res: Result<TransferStats>,
+    mut writer: ResponseWriter<E, W>,
+) -> Result<()> {
+    match res {
+        Ok(stats) => {
+            match stats.status {
+                SentStatus::Sent => {
+                    writer
+                        .events
+                        .send(Event::BlobServed {
+                            connection_id: writer.connection_id(),
+                            request_id: writer.request_id(),
+                            hash,
+                            bytes_sent: stats.bytes_sent,
+                        })
+                        .await;
+                    writer.inner.shutdown().await?;
+                    writer.notify_transfer_completed().await;
+                }
+                SentStatus::NotFound => {
+                    writer.inner.shutdown().await?;
+                    writer.notify_transfer_aborted().await;
+                }
+                SentStatus::Cancelled => writer.cancel_transfer().await,
+            }
+            Ok(())
+        }
+        Err(e) => {
+            writer.notify_transfer_aborted().await;
+            Err(e)
+        }
+    }
+}
+
+/// A stream the response to a request is written to.
+pub trait ResponseStream: AsyncWrite + Unpin + Send + 'static {
+    /// Abort the response, so the getter can tell it apart from a complete response.
+    fn reset(&mut self, code: Closed);
+}
+
+impl ResponseStream for quinn::SendStream {
+    fn reset(&mut self, code: Closed) {
+        quinn::SendStream::reset(self, code.into()).ok();
+    }
+}
+
+/// An in-process stream, e.g. for tests. It can not be reset, the getter only sees the
+/// response end early once the writer is dropped.
+impl ResponseStream for tokio::io::DuplexStream {
+    fn reset(&mut self, _code: Closed) {}
+}
+
+/// A [`ResponseStream`] that counts the bytes written to it, so that they can be read while
+/// the response is written.
+#[derive(Debug)]
+struct CountingStream<W> {
+    inner: W,
+    bytes_sent: Arc<AtomicU64>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingStream<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(size)) = res {
+            this.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<W: ResponseStream> ResponseStream for CountingStream<W> {
+    fn reset(&mut self, code: Closed) {
+        self.inner.reset(code)
+    }
+}
+
+/// A helper struct that combines a [`ResponseStream`] with auxiliary information
 #[derive(Debug)]
-pub struct ResponseWriter<E> {
-    inner: quinn::SendStream,
+pub struct ResponseWriter<E, W = quinn::SendStream> {
+    inner: CountingStream<W>,
     events: E,
     connection_id: u64,
+    request_id: u64,
+    read_ahead: usize,
+    transfer_memory: TransferMemory,
+    cancel: CancellationToken,
+    transfer: Option<TransferGuard>,
 }
 
-impl<E: EventSender> ResponseWriter<E> {
+impl<E: EventSender, W: ResponseStream> ResponseWriter<E, W> {
+    /// Create a writer for the response to the request `request_id` on connection
+    /// `connection_id`, which are used in the emitted events.
+    ///
+    /// See [`handle_connection`] for `read_ahead`, `transfer_memory` and `cancel`.
+    pub fn new(
+        inner: W,
+        events: E,
+        connection_id: u64,
+        request_id: u64,
+        read_ahead: usize,
+        transfer_memory: TransferMemory,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self {
+            inner: CountingStream {
+                inner,
+                bytes_sent: Default::default(),
+            },
+            events,
+            connection_id,
+            request_id,
+            read_ahead,
+            transfer_memory,
+            cancel,
+            transfer: None,
+        }
+    }
+
+    /// Register the response in `transfers` until the writer is dropped.
+    ///
+    /// `remote_addr` is the address of the getter. The transfer can then be listed and
+    /// cancelled with [`Transfers::list`] and [`Transfers::cancel`].
+    pub fn track(mut self, transfers: &Transfers, remote_addr: SocketAddr) -> Self {
+        self.transfer = Some(transfers.insert(
+            (self.connection_id, self.request_id),
+            Transfer {
+                remote_addr,
+                hash: None,
+                bytes_sent: self.inner.bytes_sent.clone(),
+                started_at: SystemTime::now(),
+                cancel: self.cancel.clone(),
+            },
+        ));
+        self
+    }
+
+    /// Record the hash of the requested data for the registered transfer, if any.
+    fn set_hash(&self, hash: Hash) {
+        if let Some(transfer) = &self.transfer {
+            transfer.set_hash(hash);
+        }
+    }
+
     fn connection_id(&self) -> u64 {
         self.connection_id
     }
 
     fn request_id(&self) -> u64 {
-        self.inner.id().index()
+        self.request_id
     }
 
     async fn notify_transfer_completed(&self) {
@@ -563,6 +1132,254 @@ impl<E: EventSender> ResponseWriter<E> {
             })
             .await;
     }
+
+    /// Reset the stream after the transfer was cancelled.
+    async fn cancel_transfer(&mut self) {
+        self.inner.reset(Closed::Cancelled);
+        self.notify_transfer_aborted().await;
+    }
+
+    /// Acquire `bytes` from the transfer memory budget for the buffers of this response.
+    ///
+    /// Returns `None` and cancels the transfer if the connection is cancelled while waiting.
+    async fn acquire_memory(&mut self, bytes: usize) -> Option<TransferMemoryPermit> {
+        let memory = self.transfer_memory.acquire(bytes);
+        let permit = cancellable(&self.cancel, memory).await;
+        if permit.is_none() {
+            debug!("cancelled while waiting for transfer memory");
+            self.cancel_transfer().await;
+        }
+        permit
+    }
+}
+
+/// A budget in bytes for the buffers of the transfers of a provider.
+///
+/// Clones share the budget, it is usually shared by all connections of a node. Before a
+/// transfer allocates its read buffers, it acquires their size from the budget, and waits
+/// while the budget is exhausted. This bounds the memory used by transfers, no matter how
+/// many of them run at the same time.
+#[derive(Debug, Clone)]
+pub struct TransferMemory {
+    permits: Arc<Semaphore>,
+    budget: usize,
+}
+
+impl Default for TransferMemory {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl TransferMemory {
+    /// Create a budget of `budget` bytes.
+    pub fn new(budget: usize) -> Self {
+        let budget = budget.min(Semaphore::MAX_PERMITS);
+        Self {
+            permits: Arc::new(Semaphore::new(budget)),
+            budget,
+        }
+    }
+
+    /// Create a budget that never makes transfers wait.
+    pub fn unlimited() -> Self {
+        Self::new(Semaphore::MAX_PERMITS)
+    }
+
+    /// The size of the budget in bytes.
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// The number of bytes currently held by transfers.
+    pub fn in_use(&self) -> usize {
+        self.budget - self.permits.available_permits()
+    }
+
+    /// Acquire `bytes` from the budget, waiting until they are available.
+    ///
+    /// The bytes are returned to the budget when the permit is dropped. A transfer that
+    /// needs more than the whole budget waits until it has the budget to itself.
+    pub async fn acquire(&self, bytes: usize) -> TransferMemoryPermit {
+        let bytes = bytes.min(self.budget).min(u32::MAX as usize);
+        let permit = self
+            .permits
+            .clone()
+            .acquire_many_owned(bytes as u32)
+            .await
+            .expect("transfer memory semaphore is never closed");
+        #[cfg(feature = "metrics")]
+        Metrics::with_metric(|m| m.transfer_memory_in_use.inc_by(bytes as i64));
+        TransferMemoryPermit {
+            _permit: permit,
+            bytes,
+        }
+    }
+}
+
+/// Memory held by a transfer, see [`TransferMemory::acquire`].
+#[derive(Debug)]
+pub struct TransferMemoryPermit {
+    _permit: OwnedSemaphorePermit,
+    bytes: usize,
+}
+
+impl TransferMemoryPermit {
+    /// The number of bytes held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for TransferMemoryPermit {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        Metrics::with_metric(|m| m.transfer_memory_in_use.dec_by(self.bytes as i64));
+    }
+}
+
+/// The transfers a provider is currently serving.
+///
+/// Clones share the registry, it is usually shared by all connections of a node. A transfer
+/// is registered when its request arrives and removed once the response is finished, no
+/// matter whether it was sent completely.
+#[derive(Debug, Clone, Default)]
+pub struct Transfers {
+    inner: Arc<Mutex<BTreeMap<(u64, u64), Transfer>>>,
+}
+
+#[derive(Debug)]
+struct Transfer {
+    remote_addr: SocketAddr,
+    hash: Option<Hash>,
+    bytes_sent: Arc<AtomicU64>,
+    started_at: SystemTime,
+    cancel: CancellationToken,
+}
+
+/// Information about a transfer in flight, see [`Transfers::list`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferInfo {
+    /// The id of the connection, as in the provider [`Event`]s.
+    pub connection_id: u64,
+    /// The id of the request on the connection, as in the provider [`Event`]s.
+    pub request_id: u64,
+    /// The address of the getter.
+    pub remote_addr: SocketAddr,
+    /// The hash of the requested data, `None` until the request was read and for requests
+    /// that are not for a single hash.
+    pub hash: Option<Hash>,
+    /// The number of bytes of the response written so far.
+    pub bytes_sent: u64,
+    /// When the request arrived.
+    pub started_at: SystemTime,
+}
+
+/// The number of transfers in flight on a connection, see
+/// [`Transfers::transfers_per_connection`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionTransfers {
+    /// The id of the connection, as in the provider [`Event`]s.
+    pub connection_id: u64,
+    /// The address of the getter.
+    pub remote_addr: SocketAddr,
+    /// The number of transfers in flight.
+    pub transfers: usize,
+}
+
+impl Transfers {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The transfers in flight, ordered by connection id and request id.
+    pub fn list(&self) -> Vec<TransferInfo> {
+        let transfers = self.inner.lock().unwrap();
+        transfers
+            .iter()
+            .map(|(&(connection_id, request_id), transfer)| TransferInfo {
+                connection_id,
+                request_id,
+                remote_addr: transfer.remote_addr,
+                hash: transfer.hash,
+                bytes_sent: transfer.bytes_sent.load(Ordering::Relaxed),
+                started_at: transfer.started_at,
+            })
+            .collect()
+    }
+
+    /// The number of transfers in flight per connection, ordered by connection id.
+    ///
+    /// Each transfer has its own stream, but streams of requests that were rejected or that
+    /// are still being read are not counted, so this is not the number of open QUIC streams.
+    /// Connections without transfers in flight are not listed.
+    pub fn transfers_per_connection(&self) -> Vec<ConnectionTransfers> {
+        let transfers = self.inner.lock().unwrap();
+        let mut res: Vec<ConnectionTransfers> = Vec::new();
+        for (&(connection_id, _), transfer) in transfers.iter() {
+            match res.last_mut() {
+                Some(last) if last.connection_id == connection_id => last.transfers += 1,
+                _ => res.push(ConnectionTransfers {
+                    connection_id,
+                    remote_addr: transfer.remote_addr,
+                    transfers: 1,
+                }),
+            }
+        }
+        res
+    }
+
+    /// Cancel the transfer of request `request_id` on connection `connection_id`.
+    ///
+    /// The transfer is aborted as if the `cancel` token of [`handle_connection`] was cancelled.
+    /// Returns `false` if no such transfer is in flight.
+    pub fn cancel(&self, connection_id: u64, request_id: u64) -> bool {
+        let transfers = self.inner.lock().unwrap();
+        match transfers.get(&(connection_id, request_id)) {
+            Some(transfer) => {
+                transfer.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(&self, key: (u64, u64), transfer: Transfer) -> TransferGuard {
+        self.inner.lock().unwrap().insert(key, transfer);
+        TransferGuard {
+            transfers: self.clone(),
+            key,
+        }
+    }
+}
+
+/// Removes a transfer from its registry when dropped.
+#[derive(Debug)]
+struct TransferGuard {
+    transfers: Transfers,
+    key: (u64, u64),
+}
+
+impl TransferGuard {
+    fn set_hash(&self, hash: Hash) {
+        if let Some(transfer) = self.transfers.inner.lock().unwrap().get_mut(&self.key) {
+            transfer.hash = Some(hash);
+        }
+    }
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.transfers.inner.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// The size of the buffers of a reader with a read-ahead window of `read_ahead` bytes.
+///
+/// Even without read-ahead, data is read in whole chunk groups.
+fn buffer_size(read_ahead: usize) -> usize {
+    read_ahead.max(IROH_BLOCK_SIZE.bytes())
 }
 
 /// Status  of a send operation
@@ -572,35 +1389,306 @@ pub enum SentStatus {
     Sent,
     /// The requested data was not found
     NotFound,
+    /// The transfer was cancelled before all requested data was sent
+    Cancelled,
+}
+
+/// Statistics about sending a single blob
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransferStats {
+    /// Whether the blob was sent or not found
+    pub status: SentStatus,
+    /// The size of the blob
+    pub size: u64,
+    /// The number of bytes written, including the size header and the outboard data
+    pub bytes_sent: u64,
+    /// The number of chunks of data that were sent
+    pub chunks_sent: u64,
+    /// The time it took to send the blob
+    pub duration: Duration,
 }
 
-/// Send a
+impl TransferStats {
+    fn not_found() -> Self {
+        Self {
+            status: SentStatus::NotFound,
+            size: 0,
+            bytes_sent: 0,
+            chunks_sent: 0,
+            duration: Duration::ZERO,
+        }
+    }
+
+    fn cancelled() -> Self {
+        Self {
+            status: SentStatus::Cancelled,
+            ..Self::not_found()
+        }
+    }
+}
+
+/// Send the requested ranges of the blob `name`, in verified streaming format.
+///
+/// The data is read with a [`ReadAheadReader`] with a window of `read_ahead` bytes.
+///
+/// If `cancel` is cancelled while encoding, this stops writing and returns with
+/// [`SentStatus::Cancelled`]. The returned stats then only count the bytes written so far,
+/// and no chunks.
 pub async fn send_blob<D: Map, W: AsyncWrite + Unpin + Send + 'static>(
     db: &D,
     name: Hash,
     ranges: &RangeSpec,
+    read_ahead: usize,
+    cancel: &CancellationToken,
     writer: &mut W,
-) -> Result<(SentStatus, u64)> {
+) -> Result<TransferStats> {
     match db.get(&name) {
         Some(entry) => {
+            let start = Instant::now();
             let outboard = entry.outboard().await?;
-            let size = outboard.tree().size().0;
-            let mut file_reader = entry.data_reader().await?;
-            let res = bao_tree::io::fsm::encode_ranges_validated(
-                &mut file_reader,
-                outboard,
-                &ranges.to_chunk_ranges(),
-                writer,
+            let tree = outboard.tree();
+            let ranges = ranges.to_chunk_ranges();
+            let mut file_reader = ReadAheadReader::new(entry.data_reader().await?, read_ahead);
+            let mut writer = TrackingWriter::new(writer);
+            let res = cancellable(
+                cancel,
+                bao_tree::io::fsm::encode_ranges_validated(
+                    &mut file_reader,
+                    outboard,
+                    &ranges,
+                    &mut writer,
+                ),
             )
             .await;
+            let Some(res) = res else {
+                debug!("cancelled sending blob {}", name);
+                return Ok(TransferStats {
+                    status: SentStatus::Cancelled,
+                    size: tree.size().0,
+                    bytes_sent: writer.bytes_written(),
+                    chunks_sent: 0,
+                    duration: start.elapsed(),
+                });
+            };
             debug!("done sending blob {} {:?}", name, res);
             res?;
 
-            Ok((SentStatus::Sent, size))
+            Ok(TransferStats {
+                status: SentStatus::Sent,
+                size: tree.size().0,
+                bytes_sent: writer.bytes_written(),
+                chunks_sent: count_chunks(&ranges, tree.chunks()),
+                duration: start.elapsed(),
+            })
         }
         _ => {
             debug!("blob not found {}", hex::encode(name));
-            Ok((SentStatus::NotFound, 0))
+            Ok(TransferStats::not_found())
         }
     }
 }
+
+/// Send the requested ranges of the blob `name`, which may still be written to.
+///
+/// Unlike [`send_blob`], this does not fail if the requested data is not available yet.
+/// Instead the response is split into frames. Each frame consists of the length prefixed,
+/// postcard encoded [`RangeSpec`] of the ranges in the frame, followed by these ranges in
+/// verified streaming format. The available ranges are sent right away, and then this waits
+/// with [`Map::data_written`] for more data, until all requested ranges are sent. The
+/// available ranges are those reported by [`MapEntry::available_ranges`].
+///
+/// Fails if no new data of the blob arrives within [`LIVE_BLOB_IDLE_TIMEOUT`].
+///
+/// If the store does not have the blob, nothing is written.
+pub async fn send_live_blob<D: Map, W: AsyncWrite + Unpin>(
+    db: &D,
+    name: Hash,
+    ranges: &RangeSpec,
+    writer: &mut W,
+) -> Result<TransferStats> {
+    let start = Instant::now();
+    let mut writer = TrackingWriter::new(writer);
+    let mut remaining = ranges.to_chunk_ranges();
+    let mut size = None;
+    let mut chunks_sent = 0;
+    let mut idle_deadline = tokio::time::Instant::now() + LIVE_BLOB_IDLE_TIMEOUT;
+    loop {
+        let Some(entry) = db.get(&name) else {
+            anyhow::ensure!(size.is_none(), "blob {name} was removed while sending");
+            debug!("blob not found {}", hex::encode(name));
+            return Ok(TransferStats::not_found());
+        };
+        let outboard = entry.outboard().await?;
+        let tree = outboard.tree();
+        size = Some(tree.size().0);
+        remaining &= ChunkRanges::from(..tree.chunks());
+        if remaining.is_empty() {
+            break;
+        }
+        let ranges = &remaining & &entry.available_ranges().await?;
+        if ranges.is_empty() {
+            tokio::time::timeout_at(idle_deadline, db.data_written(&name))
+                .await
+                .with_context(|| format!("no new data of blob {name} was written in time"))?;
+            continue;
+        }
+        debug!("sending ranges {:?} of live blob {}", ranges, name);
+        send_frame::<D, _>(&entry, outboard, &ranges, &mut writer).await?;
+        chunks_sent += count_chunks(&ranges, tree.chunks());
+        remaining = remaining.difference(&ranges);
+        idle_deadline = tokio::time::Instant::now() + LIVE_BLOB_IDLE_TIMEOUT;
+    }
+    Ok(TransferStats {
+        status: SentStatus::Sent,
+        size: size.unwrap_or_default(),
+        bytes_sent: writer.bytes_written(),
+        chunks_sent,
+        duration: start.elapsed(),
+    })
+}
+
+/// Send the requested ranges of the blob `name` that are available, in a single frame.
+///
+/// The frame has the same format as the frames sent by [`send_live_blob`]: the [`RangeSpec`]
+/// of the ranges that are actually sent, followed by these ranges in verified streaming format.
+/// Requested ranges that are not available are left out, so that the receiver can request them
+/// from other peers.
+///
+/// If the store does not have the blob, nothing is written.
+pub async fn send_partial_blob<D: Map, W: AsyncWrite + Unpin>(
+    db: &D,
+    name: Hash,
+    ranges: &RangeSpec,
+    writer: &mut W,
+) -> Result<TransferStats> {
+    let Some(entry) = db.get(&name) else {
+        debug!("blob not found {}", hex::encode(name));
+        return Ok(TransferStats::not_found());
+    };
+    let start = Instant::now();
+    let mut writer = TrackingWriter::new(writer);
+    let outboard = entry.outboard().await?;
+    let tree = outboard.tree();
+    let ranges = ranges.to_chunk_ranges()
+        & entry.available_ranges().await?
+        & ChunkRanges::from(..tree.chunks());
+    debug!("sending ranges {:?} of partial blob {}", ranges, name);
+    send_frame::<D, _>(&entry, outboard, &ranges, &mut writer).await?;
+    Ok(TransferStats {
+        status: SentStatus::Sent,
+        size: tree.size().0,
+        bytes_sent: writer.bytes_written(),
+        chunks_sent: count_chunks(&ranges, tree.chunks()),
+        duration: start.elapsed(),
+    })
+}
+
+/// Send the complete data of the blob `name`, without bao encoding.
+///
+/// The response is the size of the blob as a little endian u64, followed by the data. The
+/// data is hashed while it is sent, and an error is returned if it does not match `name`.
+/// The getter has to verify the hash as well, since it receives the data before that.
+///
+/// If the store does not have the complete blob, nothing is written.
+pub async fn send_raw_blob<D: Map, W: AsyncWrite + Unpin>(
+    db: &D,
+    name: Hash,
+    writer: &mut W,
+) -> Result<TransferStats> {
+    let entry = match db.get(&name) {
+        Some(entry) if entry.is_complete() => entry,
+        _ => {
+            debug!("complete blob not found {}", hex::encode(name));
+            return Ok(TransferStats::not_found());
+        }
+    };
+    let start = Instant::now();
+    let mut writer = TrackingWriter::new(writer);
+    let size = entry.size();
+    writer.write_all(&size.to_le_bytes()).await?;
+    let mut reader = entry.data_reader().await?;
+    let mut hasher = bao_tree::blake3::Hasher::new();
+    let mut offset = 0;
+    while offset < size {
+        let len = (size - offset).min(RAW_READ_SIZE as u64) as usize;
+        let data = reader.read_at(offset, len).await?;
+        anyhow::ensure!(
+            !data.is_empty(),
+            "data of blob {name} is shorter than its size"
+        );
+        hasher.update(&data);
+        writer.write_all(&data).await?;
+        offset += data.len() as u64;
+    }
+    anyhow::ensure!(
+        Hash::from(hasher.finalize()) == name,
+        "data of blob {name} does not match its hash"
+    );
+    Ok(TransferStats {
+        status: SentStatus::Sent,
+        size,
+        bytes_sent: writer.bytes_written(),
+        chunks_sent: ByteNum(size).chunks().0,
+        duration: start.elapsed(),
+    })
+}
+
+/// Send the hashes of all complete blobs in `db` that are not in the set of the requester.
+///
+/// Only blobs for which `filter` returns true are sent. The response is just the
+/// concatenation of the 32 byte hashes, in no particular order. Returns the number of
+/// hashes sent.
+pub async fn send_blob_set_diff<D: ReadableStore, W: AsyncWrite + Unpin>(
+    db: &D,
+    request: &BlobSetDiffRequest,
+    filter: impl Fn(&Hash) -> bool,
+    writer: &mut W,
+) -> Result<u64> {
+    // write hashes in batches to avoid a write call per hash
+    let mut buffer = Vec::with_capacity(BLOB_SET_DIFF_BATCH_SIZE * 32);
+    let mut count = 0;
+    for (i, hash) in db.blobs().enumerate() {
+        // yield regularly, even if nothing is written, to not block the runtime on large stores
+        if i > 0 && i % BLOB_SET_DIFF_BATCH_SIZE == 0 {
+            tokio::task::yield_now().await;
+        }
+        if request.contains(&hash) || !filter(&hash) {
+            continue;
+        }
+        buffer.extend_from_slice(hash.as_bytes());
+        count += 1;
+        if buffer.len() >= BLOB_SET_DIFF_BATCH_SIZE * 32 {
+            writer.write_all(&buffer).await?;
+            buffer.clear();
+        }
+    }
+    writer.write_all(&buffer).await?;
+    Ok(count)
+}
+
+/// Write a single frame of a live or partial response.
+async fn send_frame<D: Map, W: AsyncWrite + Unpin>(
+    entry: &D::Entry,
+    outboard: D::Outboard,
+    ranges: &ChunkRanges,
+    writer: &mut W,
+) -> Result<()> {
+    let spec = postcard::to_stdvec(&RangeSpec::new(ranges))?;
+    write_lp(&mut *writer, &spec).await?;
+    let data = entry.data_reader().await?;
+    encode_ranges_validated(data, outboard, ranges, writer).await?;
+    Ok(())
+}
+
+/// Count the chunks in `ranges` that are below `end`.
+fn count_chunks(ranges: &ChunkRanges, end: ChunkNum) -> u64 {
+    let ranges = ranges & &ChunkRanges::from(..end);
+    ranges
+        .iter()
+        .map(|range| match range {
+            RangeSetRange::Range(range) => range.end.0 - range.start.0,
+            // can not happen, since we intersected with a bounded range
+            RangeSetRange::RangeFrom(_) => 0,
+        })
+        .sum()
+}
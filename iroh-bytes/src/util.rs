@@ -170,18 +170,64 @@ impl Hash {
     /// - raw codec
     /// - blake3 hash function
     /// - 32 byte hash size
+    ///
+    /// CIDs using any other hash function are rejected, since the content they address can
+    /// not be verified by iroh.
     pub fn from_cid_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            bytes.len() >= CID_PREFIX.len(),
+            "invalid cid length, expected 36, got {}",
+            bytes.len()
+        );
+        anyhow::ensure!(
+            bytes[0] == CID_PREFIX[0],
+            "unsupported cid version, only CIDv1 is supported"
+        );
+        anyhow::ensure!(
+            bytes[1] == CID_PREFIX[1],
+            "unsupported cid codec 0x{:02x}, only the raw codec is supported",
+            bytes[1]
+        );
+        anyhow::ensure!(
+            bytes[2] == CID_PREFIX[2],
+            "unsupported cid hash function 0x{:02x}, only blake3 is supported",
+            bytes[2]
+        );
+        anyhow::ensure!(
+            bytes[3] == CID_PREFIX[3],
+            "unsupported cid hash size {}, only 32 byte blake3 hashes are supported",
+            bytes[3]
+        );
         anyhow::ensure!(
             bytes.len() == 36,
             "invalid cid length, expected 36, got {}",
             bytes.len()
         );
-        anyhow::ensure!(bytes[0..4] == CID_PREFIX, "invalid cid prefix");
         let mut hash = [0u8; 32];
         hash.copy_from_slice(&bytes[4..36]);
         Ok(Self::from(hash))
     }
 
+    /// Convert the hash to a CIDv1 with the raw codec and the blake3 hash function.
+    ///
+    /// The string representation of the returned [`cid::Cid`] is the same as the one of the
+    /// hash itself.
+    #[cfg(feature = "cid")]
+    pub fn to_cid(&self) -> cid::Cid {
+        let digest = cid::multihash::Multihash::wrap(CID_PREFIX[2].into(), self.as_bytes())
+            .expect("32 bytes fit into a multihash");
+        cid::Cid::new_v1(CID_PREFIX[1].into(), digest)
+    }
+
+    /// Try to create a hash from a [`cid::Cid`].
+    ///
+    /// Only CIDv1 with the raw codec and a 32 byte blake3 hash are supported, see
+    /// [`Self::from_cid_bytes`].
+    #[cfg(feature = "cid")]
+    pub fn from_cid(cid: &cid::Cid) -> anyhow::Result<Self> {
+        Self::from_cid_bytes(&cid.to_bytes())
+    }
+
     /// Convert the hash to a hex string.
     pub fn to_hex(&self) -> String {
         self.0.to_hex().to_string()
@@ -224,6 +270,22 @@ impl From<&[u8; 32]> for Hash {
     }
 }
 
+#[cfg(feature = "cid")]
+impl From<Hash> for cid::Cid {
+    fn from(value: Hash) -> Self {
+        value.to_cid()
+    }
+}
+
+#[cfg(feature = "cid")]
+impl TryFrom<cid::Cid> for Hash {
+    type Error = anyhow::Error;
+
+    fn try_from(value: cid::Cid) -> Result<Self, Self::Error> {
+        Self::from_cid(&value)
+    }
+}
+
 impl PartialOrd for Hash {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.0.as_bytes().cmp(other.0.as_bytes()))
@@ -369,6 +431,25 @@ impl From<std::io::Error> for RpcError {
 #[allow(dead_code)]
 pub type RpcResult<T> = result::Result<T, RpcError>;
 
+/// Returns true if the error indicates that the disk is full.
+///
+/// `io::ErrorKind::StorageFull` is not stable on our msrv, so this checks the raw os
+/// error instead. Errors wrapping another `io::Error` are inspected recursively.
+pub fn is_disk_full(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    const DISK_FULL: &[i32] = &[28]; // ENOSPC
+    #[cfg(windows)]
+    const DISK_FULL: &[i32] = &[39, 112]; // ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+    #[cfg(not(any(unix, windows)))]
+    const DISK_FULL: &[i32] = &[];
+    if let Some(code) = e.raw_os_error() {
+        return DISK_FULL.contains(&code);
+    }
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<std::io::Error>())
+        .is_some_and(is_disk_full)
+}
+
 /// A non-sendable marker type
 #[derive(Debug)]
 pub(crate) struct NonSend {
@@ -393,6 +474,22 @@ mod tests {
 
     use serde_test::{assert_tokens, Token};
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_disk_full() {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .open("/dev/full")
+            .unwrap();
+        let err = file.write_all(b"hello").unwrap_err();
+        assert!(is_disk_full(&err));
+        let wrapped = std::io::Error::new(std::io::ErrorKind::Other, err);
+        assert!(is_disk_full(&wrapped));
+        let other = std::io::Error::new(std::io::ErrorKind::Other, "other");
+        assert!(!is_disk_full(&other));
+    }
+
     #[test]
     fn test_hash() {
         let data = b"hello world";
@@ -402,6 +499,34 @@ mod tests {
         assert_eq!(encoded.parse::<Hash>().unwrap(), hash);
     }
 
+    #[test]
+    fn test_hash_rejects_other_cids() {
+        // a CIDv1 with the raw codec and a sha2-256 hash
+        let mut bytes = vec![0x01, 0x55, 0x12, 0x20];
+        bytes.extend_from_slice(&[0xab; 32]);
+        let encoded = multibase::encode(multibase::Base::Base32Lower, &bytes);
+        let err = encoded.parse::<Hash>().unwrap_err();
+        assert!(err.to_string().contains("only blake3 is supported"));
+
+        // a CIDv1 with the dag-pb codec and a blake3 hash
+        let mut bytes = vec![0x01, 0x70, 0x1e, 0x20];
+        bytes.extend_from_slice(&[0xab; 32]);
+        let encoded = multibase::encode(multibase::Base::Base58Btc, &bytes);
+        let err = encoded.parse::<Hash>().unwrap_err();
+        assert!(err.to_string().contains("only the raw codec is supported"));
+    }
+
+    #[cfg(feature = "cid")]
+    #[test]
+    fn test_hash_cid() {
+        let hash = Hash::new("hello");
+        let cid = hash.to_cid();
+        assert_eq!(cid.to_string(), hash.to_string());
+        assert_eq!(Hash::from_cid(&cid).unwrap(), hash);
+        let parsed: cid::Cid = hash.to_string().parse().unwrap();
+        assert_eq!(Hash::try_from(parsed).unwrap(), hash);
+    }
+
     #[test]
     fn hash_wire_format() {
         let hash = Hash::from([0xab; 32]);
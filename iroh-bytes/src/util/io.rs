@@ -1,6 +1,10 @@
 //! Utilities for working with tokio io
 
 use std::{io, pin::Pin, task::Poll};
+
+use bytes::Bytes;
+use futures::{future::LocalBoxFuture, FutureExt};
+use iroh_io::AsyncSliceReader;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 /// A reader that tracks the number of bytes read
@@ -101,3 +105,285 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for TrackingWriter<W> {
         Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
+
+/// A slice reader that reads ahead of small reads.
+///
+/// Reads smaller than the window are served from a buffer, which is filled with a single
+/// read of `window` bytes from the inner reader whenever a read is not covered by it. This
+/// turns the many small sequential reads of encoding a blob into few large ones, which
+/// helps for storage with a high latency per read, e.g. spinning disks or network storage.
+///
+/// Whenever a full buffer is filled, the read of the following window is started right
+/// away and only awaited by the first read that needs it. Readers that do their I/O in the
+/// background, like [`iroh_io::File`], then fetch the next window while the current one is
+/// consumed, so sequential reads do not stall on every refill.
+///
+/// The buffer is never invalidated, so this must only be used for data that does not
+/// change. A window of 0 disables the read-ahead.
+pub struct ReadAheadReader<R> {
+    /// The inner reader, `None` while it is owned by the prefetch.
+    inner: Option<R>,
+    window: usize,
+    buffer_offset: u64,
+    buffer: Bytes,
+    /// The running read of the next window.
+    prefetch: Option<Prefetch<R>>,
+    /// The next window, if it was read before it was needed.
+    next: Option<(u64, Bytes)>,
+}
+
+type Prefetch<R> = LocalBoxFuture<'static, (R, u64, io::Result<Bytes>)>;
+
+impl<R: std::fmt::Debug> std::fmt::Debug for ReadAheadReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadAheadReader")
+            .field("inner", &self.inner)
+            .field("window", &self.window)
+            .field("buffer_offset", &self.buffer_offset)
+            .field("buffer", &self.buffer.len())
+            .field("prefetch", &self.prefetch.is_some())
+            .finish()
+    }
+}
+
+impl<R: AsyncSliceReader + 'static> ReadAheadReader<R> {
+    /// Wrap a reader, reading ahead up to `window` bytes
+    pub fn new(inner: R, window: usize) -> Self {
+        Self {
+            inner: Some(inner),
+            window,
+            buffer_offset: 0,
+            buffer: Bytes::new(),
+            prefetch: None,
+            next: None,
+        }
+    }
+
+    /// Get the inner reader, waiting for a running prefetch
+    pub async fn into_inner(mut self) -> R {
+        self.settle().await;
+        self.inner.expect("inner reader is back after settle")
+    }
+
+    /// The buffered bytes for a read, if the buffer covers it
+    fn buffered(&self, offset: u64, len: usize) -> Option<Bytes> {
+        let start = usize::try_from(offset.checked_sub(self.buffer_offset)?).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.buffer.len()).then(|| self.buffer.slice(start..end))
+    }
+
+    /// Wait for a running prefetch, and get the inner reader back.
+    ///
+    /// An error of the prefetch is dropped, the read is repeated when the data is needed.
+    async fn settle(&mut self) -> &mut R {
+        if let Some(prefetch) = self.prefetch.take() {
+            let (inner, offset, res) = prefetch.await;
+            self.inner = Some(inner);
+            if let Ok(data) = res {
+                self.next = Some((offset, data));
+            }
+        }
+        self.inner
+            .as_mut()
+            .expect("inner reader is back after settle")
+    }
+
+    /// Make `data` at `offset` the buffer, and start reading the following window if
+    /// there might be more data.
+    async fn set_buffer(&mut self, offset: u64, data: Bytes) {
+        let full = data.len() >= self.window;
+        self.buffer_offset = offset;
+        self.buffer = data;
+        if !full || self.prefetch.is_some() {
+            return;
+        }
+        let Some(mut inner) = self.inner.take() else {
+            return;
+        };
+        let offset = self.buffer_offset + self.buffer.len() as u64;
+        let window = self.window;
+        let mut prefetch = async move {
+            let res = inner.read_at(offset, window).await;
+            (inner, offset, res)
+        }
+        .boxed_local();
+        // poll once, so the read is started before this returns
+        match futures::poll!(&mut prefetch) {
+            Poll::Ready((inner, offset, res)) => {
+                self.inner = Some(inner);
+                if let Ok(data) = res {
+                    self.next = Some((offset, data));
+                }
+            }
+            Poll::Pending => self.prefetch = Some(prefetch),
+        }
+    }
+}
+
+impl<R: AsyncSliceReader + 'static> AsyncSliceReader for ReadAheadReader<R> {
+    type ReadAtFuture<'a> = LocalBoxFuture<'a, io::Result<Bytes>>;
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+        async move {
+            if len >= self.window {
+                return self.settle().await.read_at(offset, len).await;
+            }
+            if let Some(data) = self.buffered(offset, len) {
+                return Ok(data);
+            }
+            self.settle().await;
+            if let Some((next_offset, next)) = self.next.take() {
+                // a short window is the end of the data, it covers everything after it
+                let end = |start: usize| match start.checked_add(len) {
+                    Some(end) if end <= next.len() => Some(end),
+                    _ if next.len() < self.window && start <= next.len() => Some(next.len()),
+                    _ => None,
+                };
+                let range = offset
+                    .checked_sub(next_offset)
+                    .and_then(|start| usize::try_from(start).ok())
+                    .and_then(|start| Some(start..end(start)?));
+                if let Some(range) = range {
+                    let data = next.slice(range);
+                    self.set_buffer(next_offset, next).await;
+                    return Ok(data);
+                }
+            }
+            let window = self.window;
+            let data = self.settle().await.read_at(offset, window).await?;
+            self.set_buffer(offset, data.clone()).await;
+            Ok(data.slice(..len.min(data.len())))
+        }
+        .boxed_local()
+    }
+
+    type LenFuture<'a> = LocalBoxFuture<'a, io::Result<u64>>;
+
+    fn len(&mut self) -> Self::LenFuture<'_> {
+        async move { self.settle().await.len().await }.boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_io::AsyncSliceReaderExt;
+
+    use super::*;
+
+    /// A reader over a byte slice that counts the reads
+    #[derive(Debug)]
+    struct CountingReader {
+        data: Bytes,
+        reads: usize,
+    }
+
+    impl AsyncSliceReader for CountingReader {
+        type ReadAtFuture<'a> = futures::future::Ready<io::Result<Bytes>>;
+
+        fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+            self.reads += 1;
+            let start = (offset as usize).min(self.data.len());
+            let end = start.saturating_add(len).min(self.data.len());
+            futures::future::ok(self.data.slice(start..end))
+        }
+
+        type LenFuture<'a> = futures::future::Ready<io::Result<u64>>;
+
+        fn len(&mut self) -> Self::LenFuture<'_> {
+            futures::future::ok(self.data.len() as u64)
+        }
+    }
+
+    #[tokio::test]
+    async fn read_ahead_reader() {
+        let data = Bytes::from((0..100u8).collect::<Vec<_>>());
+        let inner = CountingReader {
+            data: data.clone(),
+            reads: 0,
+        };
+        let mut reader = ReadAheadReader::new(inner, 32);
+
+        // sequential small reads are served from the buffer
+        for offset in (0..100).step_by(8) {
+            let res = reader.read_at(offset, 8).await.unwrap();
+            let end = (offset as usize + 8).min(100);
+            assert_eq!(res, data.slice(offset as usize..end));
+        }
+        assert_eq!(reader.inner.as_ref().unwrap().reads, 4);
+
+        // reads larger than the window and the length go to the inner reader
+        assert_eq!(reader.read_to_end().await.unwrap(), data);
+        assert_eq!(reader.len().await.unwrap(), 100);
+        assert_eq!(reader.inner.as_ref().unwrap().reads, 5);
+
+        // without a window every read goes to the inner reader
+        let mut reader = ReadAheadReader::new(reader.into_inner().await, 0);
+        reader.read_at(0, 8).await.unwrap();
+        reader.read_at(8, 8).await.unwrap();
+        assert_eq!(reader.inner.as_ref().unwrap().reads, 7);
+    }
+
+    /// A reader over a byte slice, where every read takes `delay` in a background task
+    #[derive(Debug)]
+    struct SlowReader {
+        data: Bytes,
+        delay: std::time::Duration,
+    }
+
+    impl AsyncSliceReader for SlowReader {
+        type ReadAtFuture<'a> = LocalBoxFuture<'a, io::Result<Bytes>>;
+
+        fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+            let start = (offset as usize).min(self.data.len());
+            let end = start.saturating_add(len).min(self.data.len());
+            let data = self.data.slice(start..end);
+            let delay = self.delay;
+            let read = tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                data
+            });
+            async move {
+                read.await
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
+            .boxed_local()
+        }
+
+        type LenFuture<'a> = futures::future::Ready<io::Result<u64>>;
+
+        fn len(&mut self) -> Self::LenFuture<'_> {
+            futures::future::ok(self.data.len() as u64)
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_ahead_reader_prefetch() {
+        use std::time::Duration;
+        use tokio::time::Instant;
+
+        let data = Bytes::from((0..100u8).collect::<Vec<_>>());
+        let delay = Duration::from_millis(100);
+        let inner = SlowReader {
+            data: data.clone(),
+            delay,
+        };
+        let mut reader = ReadAheadReader::new(inner, 32);
+
+        // the first read has to wait for the inner reader
+        let start = Instant::now();
+        assert_eq!(reader.read_at(0, 8).await.unwrap(), data.slice(..8));
+        assert_eq!(start.elapsed(), delay);
+
+        // while the data is consumed, the next window is read in the background
+        tokio::time::sleep(delay).await;
+        let start = Instant::now();
+        assert_eq!(reader.read_at(32, 8).await.unwrap(), data.slice(32..40));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // without consuming, a read has to wait for the rest of the prefetch
+        tokio::time::sleep(delay / 2).await;
+        let start = Instant::now();
+        assert_eq!(reader.read_at(64, 8).await.unwrap(), data.slice(64..72));
+        assert_eq!(start.elapsed(), delay / 2);
+    }
+}
@@ -71,6 +71,104 @@ impl Counter {
     }
 }
 
+/// Open Metrics [`Gauge`] to measure a current value.
+///
+/// Single value metric that can go up and down.
+#[derive(Debug, Clone)]
+pub struct Gauge {
+    /// The actual prometheus gauge.
+    #[cfg(feature = "metrics")]
+    pub gauge: prometheus_client::metrics::gauge::Gauge,
+    /// What this gauge measures.
+    pub description: &'static str,
+}
+
+impl Gauge {
+    /// Constructs a new gauge, based on the given `description`.
+    pub fn new(description: &'static str) -> Self {
+        Gauge {
+            #[cfg(feature = "metrics")]
+            gauge: Default::default(),
+            description,
+        }
+    }
+
+    /// Increase the [`Gauge`] by `i64`, returning the previous value.
+    #[cfg(feature = "metrics")]
+    pub fn inc_by(&self, v: i64) -> i64 {
+        self.gauge.inc_by(v)
+    }
+
+    /// Increase the [`Gauge`] by `i64`, returning the previous value.
+    #[cfg(not(feature = "metrics"))]
+    pub fn inc_by(&self, _v: i64) -> i64 {
+        0
+    }
+
+    /// Decrease the [`Gauge`] by `i64`, returning the previous value.
+    #[cfg(feature = "metrics")]
+    pub fn dec_by(&self, v: i64) -> i64 {
+        self.gauge.dec_by(v)
+    }
+
+    /// Decrease the [`Gauge`] by `i64`, returning the previous value.
+    #[cfg(not(feature = "metrics"))]
+    pub fn dec_by(&self, _v: i64) -> i64 {
+        0
+    }
+
+    /// Get the current value of the [`Gauge`].
+    pub fn get(&self) -> i64 {
+        #[cfg(feature = "metrics")]
+        {
+            self.gauge.get()
+        }
+        #[cfg(not(feature = "metrics"))]
+        0
+    }
+}
+
+/// Open Metrics [`Histogram`] to measure the distribution of values.
+///
+/// Counts the observed values in buckets, and tracks their number and sum.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    /// The actual prometheus histogram.
+    #[cfg(feature = "metrics")]
+    pub histogram: prometheus_client::metrics::histogram::Histogram,
+    /// What this histogram measures.
+    pub description: &'static str,
+}
+
+impl Histogram {
+    /// Constructs a new histogram, based on the given `description` and the upper bounds of
+    /// its `buckets`.
+    #[cfg(feature = "metrics")]
+    pub fn new(description: &'static str, buckets: impl Iterator<Item = f64>) -> Self {
+        Histogram {
+            histogram: prometheus_client::metrics::histogram::Histogram::new(buckets),
+            description,
+        }
+    }
+
+    /// Constructs a new histogram, based on the given `description` and the upper bounds of
+    /// its `buckets`.
+    #[cfg(not(feature = "metrics"))]
+    pub fn new(description: &'static str, _buckets: impl Iterator<Item = f64>) -> Self {
+        Histogram { description }
+    }
+
+    /// Record an observed value in the [`Histogram`].
+    #[cfg(feature = "metrics")]
+    pub fn observe(&self, v: f64) {
+        self.histogram.observe(v)
+    }
+
+    /// Record an observed value in the [`Histogram`].
+    #[cfg(not(feature = "metrics"))]
+    pub fn observe(&self, _v: f64) {}
+}
+
 /// Description of a group of metrics.
 pub trait Metric:
     Default + struct_iterable::Iterable + Sized + std::fmt::Debug + 'static + Send + Sync
@@ -84,6 +182,10 @@ pub trait Metric:
         for (metric, counter) in this.iter() {
             if let Some(counter) = counter.downcast_ref::<Counter>() {
                 sub_registry.register(metric, counter.description, counter.counter.clone());
+            } else if let Some(gauge) = counter.downcast_ref::<Gauge>() {
+                sub_registry.register(metric, gauge.description, gauge.gauge.clone());
+            } else if let Some(histogram) = counter.downcast_ref::<Histogram>() {
+                sub_registry.register(metric, histogram.description, histogram.histogram.clone());
             }
         }
         this
@@ -5,6 +5,7 @@ pub mod metrics;
 
 /// Expose core types and traits
 pub mod core;
+pub mod sink;
 
 /// Expose iroh metrics
 #[cfg(feature = "metrics")]
@@ -17,14 +18,39 @@ pub use struct_iterable;
 #[macro_export]
 macro_rules! inc {
     ($m:ty, $f:ident) => {
-        <$m as $crate::core::Metric>::with_metric(|m| m.$f.inc());
+        $crate::inc_by!($m, $f, 1);
     };
 }
 
 /// Increment the given counter `n`.
+///
+/// The update is recorded in the registered metric group and forwarded to all
+/// [`crate::sink::MetricsSink`]s.
 #[macro_export]
 macro_rules! inc_by {
-    ($m:ty, $f:ident, $n:expr) => {
-        <$m as $crate::core::Metric>::with_metric(|m| m.$f.inc_by($n));
-    };
+    ($m:ty, $f:ident, $n:expr) => {{
+        let n: u64 = $n;
+        <$m as $crate::core::Metric>::with_metric(|m| m.$f.inc_by(n));
+        $crate::sink::counter_inc_by(
+            <$m as $crate::core::Metric>::name(),
+            ::std::stringify!($f),
+            n,
+        );
+    }};
+}
+
+/// Record an observed value in the given histogram.
+///
+/// Like [`inc_by`], the update is also forwarded to all [`crate::sink::MetricsSink`]s.
+#[macro_export]
+macro_rules! observe {
+    ($m:ty, $f:ident, $v:expr) => {{
+        let v: f64 = $v;
+        <$m as $crate::core::Metric>::with_metric(|m| m.$f.observe(v));
+        $crate::sink::histogram_observe(
+            <$m as $crate::core::Metric>::name(),
+            ::std::stringify!($f),
+            v,
+        );
+    }};
 }
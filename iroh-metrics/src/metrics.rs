@@ -10,6 +10,7 @@
 //! - To increment a **counter** by 1, use the [`crate::inc_by`] macro.
 //!
 //! To expose the metrics, start the metrics service with `start_metrics_server()`.
+//! To route the metrics to another backend, add a [`crate::sink::MetricsSink`].
 //!
 //! # Example:
 //! ```rust
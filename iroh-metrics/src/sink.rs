@@ -0,0 +1,124 @@
+//! Pluggable destinations for metric updates.
+//!
+//! Every update made through the [`crate::inc`], [`crate::inc_by`] and [`crate::observe`] macros
+//! is recorded in the metrics of the registered metric groups, which back the Prometheus
+//! endpoint. In addition, the update is forwarded to all sinks added with [`add_sink`]. This
+//! allows routing metrics to StatsD, OpenTelemetry or a custom aggregator.
+//!
+//! Sinks receive updates whether or not the `metrics` feature is enabled, and whether or not
+//! the metric group was registered with [`crate::core::Core`].
+//!
+//! # Example:
+//! ```rust
+//! use std::sync::{
+//!     atomic::{AtomicU64, Ordering},
+//!     Arc,
+//! };
+//! use iroh_metrics::core::{Counter, Metric};
+//! use iroh_metrics::inc_by;
+//! use iroh_metrics::sink::{add_sink, MetricsSink};
+//! use struct_iterable::Iterable;
+//!
+//! #[derive(Debug, Clone, Iterable)]
+//! pub struct Metrics {
+//!     pub things_added: Counter,
+//! }
+//!
+//! impl Default for Metrics {
+//!     fn default() -> Self {
+//!         Self {
+//!             things_added: Counter::new("things_added tracks the number of things we have added"),
+//!         }
+//!     }
+//! }
+//!
+//! impl Metric for Metrics {
+//!    fn name() -> &'static str {
+//!         "my_metrics"
+//!    }
+//! }
+//!
+//! /// Sums up all counter updates, a real sink would send them to an aggregator.
+//! #[derive(Debug, Default, Clone)]
+//! struct Total(Arc<AtomicU64>);
+//!
+//! impl MetricsSink for Total {
+//!     fn counter_inc_by(&self, group: &'static str, counter: &'static str, value: u64) {
+//!         assert_eq!((group, counter), ("my_metrics", "things_added"));
+//!         self.0.fetch_add(value, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let total = Total::default();
+//! add_sink(total.clone());
+//! inc_by!(Metrics, things_added, 2);
+//! assert_eq!(total.0.load(Ordering::Relaxed), 2);
+//! ```
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+static SINKS: RwLock<Vec<Arc<dyn MetricsSink>>> = RwLock::new(Vec::new());
+/// Whether any sinks are registered, so that updates skip the lock when there are none.
+static HAS_SINKS: AtomicBool = AtomicBool::new(false);
+
+/// A destination for metric updates, in addition to the built-in Prometheus registry.
+pub trait MetricsSink: Debug + Send + Sync + 'static {
+    /// Called when the counter named `counter` of the metric group `group` is increased by
+    /// `value`.
+    ///
+    /// This is called on the hot path of the code being measured, so implementations should
+    /// not block.
+    fn counter_inc_by(&self, group: &'static str, counter: &'static str, value: u64);
+
+    /// Called when `value` is observed in the histogram named `histogram` of the metric group
+    /// `group`.
+    ///
+    /// Ignores the value by default.
+    fn histogram_observe(&self, _group: &'static str, _histogram: &'static str, _value: f64) {}
+}
+
+/// Add a sink that receives all future metric updates.
+pub fn add_sink(sink: impl MetricsSink) {
+    let mut sinks = SINKS.write().expect("poisoned");
+    sinks.push(Arc::new(sink));
+    HAS_SINKS.store(true, Ordering::Release);
+}
+
+/// Remove all sinks added with [`add_sink`].
+pub fn clear_sinks() {
+    let mut sinks = SINKS.write().expect("poisoned");
+    sinks.clear();
+    HAS_SINKS.store(false, Ordering::Release);
+}
+
+/// Forward a counter update to all sinks.
+///
+/// Used by the [`crate::inc`] and [`crate::inc_by`] macros.
+#[doc(hidden)]
+pub fn counter_inc_by(group: &'static str, counter: &'static str, value: u64) {
+    if !HAS_SINKS.load(Ordering::Acquire) {
+        return;
+    }
+    for sink in SINKS.read().expect("poisoned").iter() {
+        sink.counter_inc_by(group, counter, value);
+    }
+}
+
+/// Forward a histogram observation to all sinks.
+///
+/// Used by the [`crate::observe`] macro.
+#[doc(hidden)]
+pub fn histogram_observe(group: &'static str, histogram: &'static str, value: f64) {
+    if !HAS_SINKS.load(Ordering::Acquire) {
+        return;
+    }
+    for sink in SINKS.read().expect("poisoned").iter() {
+        sink.histogram_observe(group, histogram, value);
+    }
+}
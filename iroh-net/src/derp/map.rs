@@ -49,6 +49,7 @@ impl DerpMap {
         Arc::get_mut(&mut self.regions).and_then(|r| r.get_mut(&region_id))
     }
 
+    /// Get a mutable reference to the node `node_idx` of a region, for tests.
     #[cfg(test)]
     pub fn get_node_mut(&mut self, region_id: u16, node_idx: usize) -> Option<&mut DerpNode> {
         Arc::get_mut(&mut self.regions)
@@ -1,4 +1,22 @@
 //! An endpoint that leverages a [quinn::Endpoint] backed by a [magicsock::MagicSock].
+//!
+//! # Connection migration
+//!
+//! When a peer changes networks, e.g. a phone switching from Wi-Fi to cellular, its address
+//! changes. Most of these changes never reach QUIC: the magic socket maps every peer to a
+//! stable address and switches between direct paths and DERP underneath. In the remaining
+//! cases quinn sees packets of an existing connection arrive from a new address. With
+//! connection migration enabled, quinn validates the new path and keeps the connection,
+//! instead of dropping the packets until the connection times out.
+//!
+//! Migration is enabled by default, and can be turned off with
+//! [`MagicEndpointBuilder::migration`]. Only the accepting side decides whether migration is
+//! allowed, so the setting matters for providers.
+//!
+//! To check that a transfer survives a real network switch, fetch a large blob from a
+//! provider with `RUST_LOG=iroh_net=debug iroh get`, and switch the network of the fetching
+//! machine while the download is running. The download stalls for a few seconds and then
+//! continues, while the debug log shows the magic socket picking a new path for the provider.
 
 use std::{collections::HashSet, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
@@ -17,6 +35,7 @@ use crate::{
 };
 
 pub use super::magicsock::EndpointInfo as ConnectionInfo;
+pub use super::magicsock::{DerpRegionInfo, DerpStatus};
 
 /// A peer and it's addressing information.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -116,10 +135,15 @@ pub struct MagicEndpointBuilder {
     alpn_protocols: Vec<Vec<u8>>,
     transport_config: Option<quinn::TransportConfig>,
     concurrent_connections: Option<u32>,
+    migration: bool,
     keylog: bool,
     callbacks: Callbacks,
     /// Path for known peers. See [`MagicEndpointBuilder::peers_data_path`].
     peers_path: Option<PathBuf>,
+    /// Local addresses to bind to. See [`MagicEndpointBuilder::bind_addrs`].
+    bind_addrs: Vec<SocketAddr>,
+    /// Already bound sockets to use. See [`MagicEndpointBuilder::sockets`].
+    sockets: Vec<std::net::UdpSocket>,
 }
 
 impl Default for MagicEndpointBuilder {
@@ -130,9 +154,12 @@ impl Default for MagicEndpointBuilder {
             alpn_protocols: Default::default(),
             transport_config: Default::default(),
             concurrent_connections: Default::default(),
+            migration: true,
             keylog: Default::default(),
             callbacks: Default::default(),
             peers_path: None,
+            bind_addrs: Vec::new(),
+            sockets: Vec::new(),
         }
     }
 }
@@ -212,6 +239,19 @@ impl MagicEndpointBuilder {
         self
     }
 
+    /// Whether to allow peers to migrate incoming connections to a new network path.
+    ///
+    /// When enabled, a connection survives a change of the remote address, e.g. when a mobile
+    /// peer switches from Wi-Fi to cellular, instead of being aborted. Most path changes are
+    /// already hidden from QUIC by the magic socket, see the
+    /// [module docs](self#connection-migration).
+    ///
+    /// Enabled by default.
+    pub fn migration(mut self, migration: bool) -> Self {
+        self.migration = migration;
+        self
+    }
+
     /// Optionally set a callback function to be called when endpoints change.
     #[allow(clippy::type_complexity)]
     pub fn on_endpoints(
@@ -237,6 +277,19 @@ impl MagicEndpointBuilder {
         self
     }
 
+    /// Optionally set a callback function to be called when the connectivity to the home DERP
+    /// region changes.
+    ///
+    /// While the home region is [`DerpStatus::Unavailable`] the endpoint keeps working over
+    /// direct connections, but peers behind NATs which need relaying can not reach it.
+    pub fn on_derp_status(
+        mut self,
+        on_derp_status: Box<dyn Fn(DerpStatus) + Send + Sync + 'static>,
+    ) -> Self {
+        self.callbacks.on_derp_status = Some(on_derp_status);
+        self
+    }
+
     /// Optionally set the path where peer info should be stored.
     ///
     /// If the file exists, it will be used to populate an initial set of peers. Peers will be
@@ -246,12 +299,56 @@ impl MagicEndpointBuilder {
         self
     }
 
+    /// Bind only to the given local addresses instead of all interfaces.
+    ///
+    /// The list may contain one IPv4 and one IPv6 address. When no IPv6 address is given, the
+    /// endpoint does not use IPv6 at all. When no IPv4 address is given, the endpoint binds
+    /// its IPv4 socket to the loopback address, so it is only reachable over IPv6 from other
+    /// hosts. An address with port `0` uses the port passed to [`bind`].
+    ///
+    /// When bound to specific addresses, only these are reported as local endpoints. Addresses
+    /// discovered via STUN or port mapping are still reported.
+    ///
+    /// [`bind`]: MagicEndpointBuilder::bind
+    pub fn bind_addrs(mut self, addrs: Vec<SocketAddr>) -> Self {
+        self.bind_addrs = addrs;
+        self
+    }
+
+    /// Use already bound UDP sockets instead of binding new ones.
+    ///
+    /// This allows the caller, e.g. a service manager like systemd, to own the sockets and
+    /// keep their ports across restarts. The list must contain one IPv4 socket and may contain
+    /// one IPv6 socket, and can not be combined with [`bind_addrs`]. The sockets are never
+    /// re-bound, and the port passed to [`bind`] is ignored.
+    ///
+    /// [`bind_addrs`]: MagicEndpointBuilder::bind_addrs
+    /// [`bind`]: MagicEndpointBuilder::bind
+    pub fn sockets(mut self, sockets: Vec<std::net::UdpSocket>) -> Self {
+        self.sockets = sockets;
+        self
+    }
+
+    /// Use the UDP sockets passed in by systemd socket activation, if there are any.
+    ///
+    /// See [`crate::net::socket_activation`] for the file descriptor passing contract. When
+    /// the process was not socket activated this does nothing, and the endpoint binds its own
+    /// sockets as usual.
+    #[cfg(all(unix, feature = "socket-activation"))]
+    pub fn socket_activation(self) -> Result<Self> {
+        let sockets = crate::net::socket_activation::listen_udp_sockets()?;
+        if sockets.is_empty() {
+            return Ok(self);
+        }
+        Ok(self.sockets(sockets))
+    }
+
     /// Bind the magic endpoint on the specified socket address.
     ///
     /// The *bind_port* is the port that should be bound locally.
     /// The port will be used to bind an IPv4 and, if supported, and IPv6 socket.
     /// You can pass `0` to let the operating system choose a free port for you.
-    /// NOTE: This will be improved soon to add support for binding on specific addresses.
+    /// To bind to specific addresses, see [`MagicEndpointBuilder::bind_addrs`].
     pub async fn bind(self, bind_port: u16) -> Result<MagicEndpoint> {
         ensure!(
             self.derp_map
@@ -270,8 +367,11 @@ impl MagicEndpointBuilder {
         if let Some(c) = self.concurrent_connections {
             server_config.concurrent_connections(c);
         }
+        server_config.migration(self.migration);
         let msock_opts = magicsock::Options {
             port: bind_port,
+            bind_addrs: self.bind_addrs,
+            sockets: self.sockets,
             secret_key,
             derp_map: self.derp_map.unwrap_or_default(),
             callbacks: self.callbacks,
@@ -376,6 +476,21 @@ impl MagicEndpoint {
         self.msock.my_derp().await
     }
 
+    /// Get the status of all configured DERP regions.
+    ///
+    /// For each region this includes the latency measured by the last net check, whether it
+    /// is our home region and whether we are currently connected to it.
+    pub async fn derp_regions(&self) -> Result<Vec<DerpRegionInfo>> {
+        self.msock.derp_regions().await
+    }
+
+    /// Get the connectivity to the home DERP region.
+    ///
+    /// See [`MagicEndpointBuilder::on_derp_status`] to be notified of changes.
+    pub fn derp_status(&self) -> DerpStatus {
+        self.msock.derp_status()
+    }
+
     /// Get the [`PeerAddr`] for this endpoint.
     // TODO: We can save an async call by exposing this on the msock.
     pub async fn my_addr(&self) -> Result<PeerAddr> {
@@ -564,6 +679,63 @@ mod tests {
 
     const TEST_ALPN: &[u8] = b"n0/iroh/test";
 
+    #[tokio::test]
+    async fn magic_endpoint_derp_regions() {
+        let _guard = iroh_test::logging::setup();
+        let (derp_map, region_id, _guard) = run_derper().await.unwrap();
+        let ep = MagicEndpoint::builder()
+            .enable_derp(derp_map)
+            .bind(0)
+            .await
+            .unwrap();
+
+        // wait for the net check to select the home region
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while ep.my_derp().await.is_none() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let regions = ep.derp_regions().await.unwrap();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].region_id, region_id);
+        assert!(regions[0].home);
+        assert!(regions[0].connected);
+    }
+
+    #[tokio::test]
+    async fn magic_endpoint_derp_status() {
+        let _guard = iroh_test::logging::setup();
+        let (derp_map, region_id, derp_guard) = run_derper().await.unwrap();
+        let (status_send, status_recv) = flume::unbounded();
+        let ep = MagicEndpoint::builder()
+            .enable_derp(derp_map)
+            .on_derp_status(Box::new(move |status| {
+                status_send.send(status).ok();
+            }))
+            .bind(0)
+            .await
+            .unwrap();
+        assert_eq!(ep.derp_status(), DerpStatus::Inactive);
+
+        let status = tokio::time::timeout(Duration::from_secs(10), status_recv.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status, DerpStatus::Connected(region_id));
+
+        // stop the derp server, the endpoint notices it lost its relay
+        drop(derp_guard);
+        let status = tokio::time::timeout(Duration::from_secs(10), status_recv.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(status, DerpStatus::Unavailable(region_id));
+        assert_eq!(ep.derp_status(), DerpStatus::Unavailable(region_id));
+    }
+
     #[ignore]
     #[tokio::test]
     async fn magic_endpoint_connect_close() {
@@ -702,6 +874,154 @@ mod tests {
         drop(tempdir);
     }
 
+    /// Test that a connection survives a change of the local port of a peer, even with
+    /// migration disabled, as the magic socket hides the new path from QUIC.
+    #[tokio::test]
+    async fn magic_endpoint_path_change() {
+        let _guard = iroh_test::logging::setup();
+        let bind_addr: SocketAddr = (std::net::Ipv4Addr::LOCALHOST, 0).into();
+        let server = MagicEndpoint::builder()
+            .disable_derp()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind_addrs(vec![bind_addr])
+            .migration(false)
+            .bind(0)
+            .await
+            .unwrap();
+        let client = MagicEndpoint::builder()
+            .disable_derp()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind_addrs(vec![bind_addr])
+            .bind(0)
+            .await
+            .unwrap();
+
+        let server_addr =
+            PeerAddr::new(server.peer_id()).with_direct_addresses([server.local_addr().unwrap().0]);
+        let echo = tokio::spawn(async move {
+            let conn = server.accept().await.unwrap();
+            let (_peer_id, _alpn, conn) = accept_conn(conn).await.unwrap();
+            while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+                let data = recv.read_to_end(64).await.unwrap();
+                send.write_all(&data).await.unwrap();
+                send.finish().await.unwrap();
+            }
+        });
+
+        let conn = client.connect(server_addr, TEST_ALPN).await.unwrap();
+        let roundtrip = |msg: &'static [u8]| {
+            let conn = conn.clone();
+            async move {
+                let (mut send, mut recv) = conn.open_bi().await.unwrap();
+                send.write_all(msg).await.unwrap();
+                send.finish().await.unwrap();
+                recv.read_to_end(64).await.unwrap()
+            }
+        };
+        assert_eq!(roundtrip(b"before").await, b"before");
+
+        // move the client to a new port
+        let old_addr = client.local_addr().unwrap().0;
+        let port = std::net::UdpSocket::bind(bind_addr)
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        client.msock.set_preferred_port(port).await;
+        assert_ne!(client.local_addr().unwrap().0, old_addr);
+
+        let res = tokio::time::timeout(Duration::from_secs(10), roundtrip(b"after")).await;
+        assert_eq!(res.unwrap(), b"after");
+
+        conn.close(0u8.into(), b"done");
+        echo.await.unwrap();
+    }
+
+    /// Test that an endpoint bound to specific addresses only reports those as local endpoints
+    #[tokio::test]
+    async fn bind_addrs() {
+        let _guard = iroh_test::logging::setup();
+        let bind_addr: SocketAddr = (std::net::Ipv4Addr::LOCALHOST, 0).into();
+        let (endpoints_send, endpoints_recv) = flume::unbounded();
+        let endpoint = MagicEndpoint::builder()
+            .disable_derp()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .bind_addrs(vec![bind_addr])
+            .on_endpoints(Box::new(move |eps| {
+                endpoints_send.send(eps.to_vec()).ok();
+            }))
+            .bind(0)
+            .await
+            .unwrap();
+
+        let (addr4, addr6) = endpoint.local_addr().unwrap();
+        assert_eq!(addr4.ip(), bind_addr.ip());
+        assert_ne!(addr4.port(), 0);
+        assert!(addr6.is_none());
+
+        let endpoints = tokio::time::timeout(Duration::from_secs(30), endpoints_recv.recv_async())
+            .await
+            .unwrap()
+            .unwrap();
+        let local: Vec<_> = endpoints
+            .into_iter()
+            .filter(|ep| ep.typ == config::EndpointType::Local)
+            .map(|ep| ep.addr)
+            .collect();
+        assert_eq!(local, vec![addr4]);
+
+        // two addresses of the same family can not be bound
+        let res = MagicEndpoint::builder()
+            .disable_derp()
+            .bind_addrs(vec![bind_addr, bind_addr])
+            .bind(0)
+            .await;
+        assert!(res.is_err());
+
+        // an IPv6 address alone is enough, the IPv4 socket then only listens on loopback
+        let bind_addr6: SocketAddr = (std::net::Ipv6Addr::LOCALHOST, 0).into();
+        if std::net::UdpSocket::bind(bind_addr6).is_ok() {
+            let endpoint = MagicEndpoint::builder()
+                .disable_derp()
+                .bind_addrs(vec![bind_addr6])
+                .bind(0)
+                .await
+                .unwrap();
+            let (addr4, addr6) = endpoint.local_addr().unwrap();
+            assert!(addr4.ip().is_loopback());
+            assert_eq!(addr6.map(|addr| addr.ip()), Some(bind_addr6.ip()));
+        }
+    }
+
+    /// Test that an endpoint uses already bound sockets as they are
+    #[tokio::test]
+    async fn bound_sockets() {
+        let _guard = iroh_test::logging::setup();
+        let socket = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let socket_addr = socket.local_addr().unwrap();
+        let endpoint = MagicEndpoint::builder()
+            .disable_derp()
+            .alpns(vec![TEST_ALPN.to_vec()])
+            .sockets(vec![socket])
+            .bind(0)
+            .await
+            .unwrap();
+
+        let (addr4, addr6) = endpoint.local_addr().unwrap();
+        assert_eq!(addr4, socket_addr);
+        assert!(addr6.is_none());
+
+        // sockets can not be combined with bind addresses
+        let socket = std::net::UdpSocket::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let res = MagicEndpoint::builder()
+            .disable_derp()
+            .bind_addrs(vec![(std::net::Ipv4Addr::LOCALHOST, 0).into()])
+            .sockets(vec![socket])
+            .bind(0)
+            .await;
+        assert!(res.is_err());
+    }
+
     // #[tokio::test]
     // async fn magic_endpoint_bidi_send_recv() {
     //     setup_logging();
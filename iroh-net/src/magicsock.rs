@@ -32,7 +32,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Context as _, Result};
+use anyhow::{bail, ensure, Context as _, Result};
 use bytes::Bytes;
 use futures::{future::BoxFuture, FutureExt};
 use iroh_metrics::{inc, inc_by};
@@ -71,6 +71,7 @@ mod rebinding_conn;
 mod timer;
 mod udp_actor;
 
+pub use self::derp_actor::{DerpRegionInfo, DerpStatus};
 pub use self::endpoint::ConnectionType;
 pub use self::endpoint::EndpointInfo;
 pub use self::metrics::Metrics;
@@ -139,6 +140,21 @@ pub struct Options {
     /// Zero means to pick one automatically.
     pub port: u16,
 
+    /// Local addresses to bind to, instead of the unspecified addresses.
+    ///
+    /// May contain one IPv4 and one IPv6 address. If no IPv6 address is given, no IPv6 socket
+    /// is bound. If no IPv4 address is given, the IPv4 socket is bound to the loopback
+    /// address, so it is not reachable from other hosts. An address with port zero uses
+    /// [`Options::port`]. Empty means to bind on all interfaces.
+    pub bind_addrs: Vec<SocketAddr>,
+
+    /// UDP sockets that are already bound, to use instead of binding new ones.
+    ///
+    /// Must contain one IPv4 socket and may contain one IPv6 socket, like
+    /// [`Options::bind_addrs`], which must be empty if sockets are given. The sockets are
+    /// never re-bound. Empty means to bind new sockets.
+    pub sockets: Vec<std::net::UdpSocket>,
+
     /// Secret key for this node.
     pub secret_key: SecretKey,
 
@@ -167,12 +183,19 @@ pub struct Callbacks {
     /// A callback that provides a `config::NetInfo` when discovered network conditions change.
     #[debug("on_net_info: Option<Box<..>>")]
     pub on_net_info: Option<Box<dyn Fn(config::NetInfo) + Send + Sync + 'static>>,
+
+    /// Optionally provides a func to be called when the connectivity to the home DERP region
+    /// changes.
+    #[debug("on_derp_status: Option<Box<..>>")]
+    pub on_derp_status: Option<Box<dyn Fn(DerpStatus) + Send + Sync + 'static>>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Options {
             port: 0,
+            bind_addrs: Vec::new(),
+            sockets: Vec::new(),
             secret_key: SecretKey::generate(),
             derp_map: Default::default(),
             callbacks: Default::default(),
@@ -213,6 +236,9 @@ struct Inner {
     /// A callback that provides a `config::NetInfo` when discovered network conditions change.
     #[debug("on_net_info: Option<Box<..>>")]
     on_net_info: Option<Box<dyn Fn(config::NetInfo) + Send + Sync + 'static>>,
+    /// A callback that provides the [`DerpStatus`] when it changes.
+    #[debug("on_derp_status: Option<Box<..>>")]
+    on_derp_status: Option<Box<dyn Fn(DerpStatus) + Send + Sync + 'static>>,
 
     /// Used for receiving DERP messages.
     network_recv_ch: flume::Receiver<NetworkReadResult>,
@@ -240,6 +266,8 @@ struct Inner {
     derp_map: DerpMap,
     /// Nearest DERP region ID; 0 means none/unknown.
     my_derp: AtomicU16,
+    /// Connectivity to the home DERP region.
+    derp_status: std::sync::Mutex<DerpStatus>,
 }
 
 impl Inner {
@@ -257,6 +285,44 @@ impl Inner {
         self.my_derp.store(my_derp, Ordering::Relaxed);
     }
 
+    /// Returns the connectivity to the home DERP region.
+    fn derp_status(&self) -> DerpStatus {
+        *self.derp_status.lock().unwrap()
+    }
+
+    /// Sets the connectivity to the home DERP region, notifying the callback on changes.
+    ///
+    /// When a connected home region becomes unavailable, a re-STUN is triggered so that our
+    /// direct endpoints are up to date, as peers now have to rely on them to reach us. If the
+    /// region was never connected, the endpoints were determined without it already.
+    fn set_derp_status(&self, status: DerpStatus) {
+        let previous = {
+            let mut current = self.derp_status.lock().unwrap();
+            if *current == status {
+                return;
+            }
+            std::mem::replace(&mut *current, status)
+        };
+        match status {
+            DerpStatus::Unavailable(region_id) => {
+                warn!("home derp-{region_id} unavailable, relying on direct connections");
+                inc!(MagicsockMetrics, derp_home_unavailable);
+                if matches!(previous, DerpStatus::Connected(_)) {
+                    self.actor_sender
+                        .try_send(ActorMessage::ReStun("derp-unavailable"))
+                        .ok();
+                }
+            }
+            DerpStatus::Connected(region_id) => {
+                info!("home derp-{region_id} connected");
+            }
+            DerpStatus::Inactive => {}
+        }
+        if let Some(ref on_derp_status) = self.on_derp_status {
+            on_derp_status(status);
+        }
+    }
+
     /// Returns `true` if we have DERP configuration for the given DERP `region`.
     async fn has_derp_region(&self, region: u16) -> bool {
         self.derp_map.contains_region(region)
@@ -332,6 +398,8 @@ impl MagicSock {
 
         let Options {
             port,
+            bind_addrs,
+            sockets,
             secret_key,
             derp_map,
             callbacks:
@@ -339,6 +407,7 @@ impl MagicSock {
                     on_endpoints,
                     on_derp_active,
                     on_net_info,
+                    on_derp_status,
                 },
             peers_path,
         } = opts;
@@ -357,7 +426,7 @@ impl MagicSock {
 
         let (network_recv_ch_sender, network_recv_ch_receiver) = flume::bounded(128);
 
-        let (pconn4, pconn6) = bind(port).await?;
+        let (pconn4, pconn6) = bind(port, &bind_addrs, sockets).await?;
         let port = pconn4.port();
 
         // NOTE: we can end up with a zero port if `std::net::UdpSocket::socket_addr` fails
@@ -379,6 +448,7 @@ impl MagicSock {
             on_endpoints,
             on_derp_active,
             on_net_info,
+            on_derp_status,
             port: AtomicU16::new(port),
             secret_key,
             local_addrs: std::sync::RwLock::new((ipv4_addr, ipv6_addr)),
@@ -392,6 +462,7 @@ impl MagicSock {
             ipv6_reported: Arc::new(AtomicBool::new(false)),
             derp_map,
             my_derp: AtomicU16::new(0),
+            derp_status: std::sync::Mutex::new(DerpStatus::Inactive),
         });
 
         let udp_state = quinn_udp::UdpState::default();
@@ -455,6 +526,7 @@ impl MagicSock {
                     on_endpoint_refreshed: HashMap::new(),
                     periodic_re_stun_timer: new_re_stun_timer(false),
                     net_info_last: None,
+                    derp_latency: Default::default(),
                     disco_info: HashMap::new(),
                     peer_map,
                     peers_path,
@@ -609,6 +681,27 @@ impl MagicSock {
         }
     }
 
+    /// Returns the connectivity to the home DERP region.
+    ///
+    /// While the home region is [`DerpStatus::Unavailable`], peers can only reach us over
+    /// direct connections. See [`Callbacks::on_derp_status`] to be notified of changes.
+    pub fn derp_status(&self) -> DerpStatus {
+        self.inner.derp_status()
+    }
+
+    /// Returns the status of all configured DERP regions.
+    ///
+    /// The latencies are the ones measured by the last net check.
+    pub async fn derp_regions(&self) -> Result<Vec<DerpRegionInfo>> {
+        let (s, r) = sync::oneshot::channel();
+        self.inner
+            .actor_sender
+            .send(ActorMessage::DerpRegions(s))
+            .await?;
+        let res = r.await?;
+        Ok(res)
+    }
+
     #[instrument(skip_all, fields(self.name = %self.inner.name))]
     /// Add addresses for a node to the magic socket's addresbook.
     pub async fn add_peer_addr(&self, addr: PeerAddr) -> Result<()> {
@@ -880,6 +973,7 @@ enum ActorMessage {
     TrackedEndpoints(sync::oneshot::Sender<Vec<EndpointInfo>>),
     TrackedEndpoint(PublicKey, sync::oneshot::Sender<Option<EndpointInfo>>),
     LocalEndpoints(sync::oneshot::Sender<Vec<config::Endpoint>>),
+    DerpRegions(sync::oneshot::Sender<Vec<DerpRegionInfo>>),
     GetMappingAddr(PublicKey, sync::oneshot::Sender<Option<QuicMappedAddr>>),
     SetPreferredPort(u16, sync::oneshot::Sender<()>),
     RebindAll(sync::oneshot::Sender<()>),
@@ -926,6 +1020,8 @@ struct Actor {
     periodic_re_stun_timer: time::Interval,
     /// The `NetInfo` provided in the last call to `net_info_func`. It's used to deduplicate calls to netInfoFunc.
     net_info_last: Option<config::NetInfo>,
+    /// The DERP region latencies measured by the last net check.
+    derp_latency: netcheck::RegionLatencies,
     /// The state for an active DiscoKey.
     disco_info: HashMap<PublicKey, DiscoInfo>,
     /// Tracks the networkmap node entity for each peer discovery key.
@@ -1132,6 +1228,24 @@ impl Actor {
                 let eps: Vec<_> = self.last_endpoints.clone();
                 let _ = s.send(eps);
             }
+            ActorMessage::DerpRegions(s) => {
+                let my_derp = self.inner.my_derp();
+                let derp_map = &self.inner.derp_map;
+                let infos = derp_map
+                    .region_ids()
+                    .into_iter()
+                    .filter_map(|id| derp_map.get_region(id))
+                    .map(|region| DerpRegionInfo {
+                        region_id: region.region_id,
+                        region_code: region.region_code.clone(),
+                        latency: self.derp_latency.get(region.region_id),
+                        home: region.region_id == my_derp,
+                        connected: false,
+                    })
+                    .collect();
+                // only the derp actor knows which regions we are connected to
+                self.send_derp_actor(DerpActorMessage::RegionInfos(infos, s));
+            }
             ActorMessage::GetMappingAddr(node_key, s) => {
                 let res = self
                     .peer_map
@@ -1734,6 +1848,7 @@ impl Actor {
             self.no_v4_send, !r.ipv4_can_send
         );
         self.no_v4_send = !r.ipv4_can_send;
+        self.derp_latency = r.region_latency.clone();
 
         let have_port_map = self.port_mapper.watch_external_address().borrow().is_some();
         let mut ni = config::NetInfo {
@@ -1956,7 +2071,7 @@ impl Actor {
             let port = conn.port();
             trace!("IPv6 rebind {} {:?}", port, cur_port_fate);
             // If we were not able to bind ipv6 at program start, dont retry
-            if let Err(err) = conn.rebind(port, Network::Ipv6, cur_port_fate).await {
+            if let Err(err) = conn.rebind(port, cur_port_fate).await {
                 info!("rebind ignoring IPv6 bind failure: {:?}", err);
             } else {
                 ipv6_addr = conn.local_addr().ok();
@@ -1965,7 +2080,7 @@ impl Actor {
 
         let port = self.local_port_v4();
         self.pconn4
-            .rebind(port, Network::Ipv4, cur_port_fate)
+            .rebind(port, cur_port_fate)
             .await
             .context("rebind IPv4 failed")?;
 
@@ -2421,8 +2536,65 @@ fn new_re_stun_timer(initial_delay: bool) -> time::Interval {
 }
 
 /// Initial connection setup.
-async fn bind(port: u16) -> Result<(RebindingUdpConn, Option<RebindingUdpConn>)> {
+async fn bind(
+    port: u16,
+    bind_addrs: &[SocketAddr],
+    sockets: Vec<std::net::UdpSocket>,
+) -> Result<(RebindingUdpConn, Option<RebindingUdpConn>)> {
+    if !sockets.is_empty() {
+        ensure!(
+            bind_addrs.is_empty(),
+            "can not use bind addresses together with bound sockets"
+        );
+        let addrs = sockets
+            .iter()
+            .map(|socket| socket.local_addr())
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let (addr4, _) = split_bind_addrs(&addrs)?;
+        ensure!(addr4.is_some(), "bound sockets must include an IPv4 socket");
+        let mut pconn4 = None;
+        let mut pconn6 = None;
+        for (socket, addr) in sockets.into_iter().zip(addrs) {
+            debug!("using bound socket {addr}");
+            let conn = RebindingUdpConn::from_std(socket)
+                .with_context(|| format!("failed to use socket bound to {addr}"))?;
+            match addr {
+                SocketAddr::V4(_) => pconn4 = Some(conn),
+                SocketAddr::V6(_) => pconn6 = Some(conn),
+            }
+        }
+        let pconn4 = pconn4.expect("checked above");
+        return Ok((pconn4, pconn6));
+    }
+
     let ip6_port = if port != 0 { port + 1 } else { 0 };
+    if !bind_addrs.is_empty() {
+        let (addr4, addr6) = split_bind_addrs(bind_addrs)?;
+        let with_port = |mut addr: SocketAddr, port: u16| {
+            if addr.port() == 0 {
+                addr.set_port(port);
+            }
+            addr
+        };
+        let pconn6 = match addr6 {
+            Some(addr) => Some(
+                RebindingUdpConn::bind_addr(with_port(addr, ip6_port))
+                    .await
+                    .with_context(|| format!("failed to bind IPv6 address {addr}"))?,
+            ),
+            None => None,
+        };
+        // without an IPv4 address, the IPv4 socket is only reachable from this host
+        let addr4 = match addr4 {
+            Some(addr) => with_port(addr, port),
+            None => SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        };
+        let pconn4 = RebindingUdpConn::bind_addr(addr4)
+            .await
+            .with_context(|| format!("failed to bind IPv4 address {addr4}"))?;
+        return Ok((pconn4, pconn6));
+    }
+
     let pconn6 = match RebindingUdpConn::bind(ip6_port, Network::Ipv6).await {
         Ok(conn) => Some(conn),
         Err(err) => {
@@ -2438,6 +2610,25 @@ async fn bind(port: u16) -> Result<(RebindingUdpConn, Option<RebindingUdpConn>)>
     Ok((pconn4, pconn6))
 }
 
+/// Splits the configured bind addresses into the IPv4 and the IPv6 address.
+///
+/// At least one of them must be given.
+fn split_bind_addrs(bind_addrs: &[SocketAddr]) -> Result<(Option<SocketAddr>, Option<SocketAddr>)> {
+    let mut addr4 = None;
+    let mut addr6 = None;
+    for addr in bind_addrs {
+        let slot = match addr {
+            SocketAddr::V4(_) => &mut addr4,
+            SocketAddr::V6(_) => &mut addr6,
+        };
+        if let Some(existing) = slot.replace(*addr) {
+            bail!("can only bind one address per IP family, got {existing} and {addr}");
+        }
+    }
+    ensure!(addr4.is_some() || addr6.is_some(), "no address to bind to");
+    Ok((addr4, addr6))
+}
+
 fn log_endpoint_change(endpoints: &[config::Endpoint]) {
     debug!("endpoints changed: {}", {
         let mut s = String::new();
@@ -8,7 +8,11 @@ use std::{
 use backoff::backoff::Backoff;
 use bytes::{Bytes, BytesMut};
 use iroh_metrics::{inc, inc_by};
-use tokio::{sync::mpsc, time};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time,
+};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, trace, warn};
 
@@ -42,9 +46,41 @@ pub(super) enum DerpActorMessage {
     },
     NotePreferred(u16),
     MaybeCloseDerpsOnRebind(Vec<IpAddr>),
+    /// Fill in [`DerpRegionInfo::connected`] and send the infos back.
+    RegionInfos(Vec<DerpRegionInfo>, oneshot::Sender<Vec<DerpRegionInfo>>),
     Shutdown,
 }
 
+/// Status of a configured DERP region, see [`super::MagicSock::derp_regions`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DerpRegionInfo {
+    /// The id of the region.
+    pub region_id: u16,
+    /// The short code of the region.
+    pub region_code: String,
+    /// The latency to the region measured by the last net check, if any.
+    pub latency: Option<Duration>,
+    /// Whether this is our home region, at which other nodes can reach us.
+    pub home: bool,
+    /// Whether we currently have a connection to the region.
+    pub connected: bool,
+}
+
+/// Connectivity to our home DERP region, see [`super::MagicSock::derp_status`].
+///
+/// While the home DERP region is unavailable, peers can only reach us over direct
+/// connections, which might not be possible behind some NATs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DerpStatus {
+    /// No connection to a home DERP region was established yet, or DERP is disabled.
+    Inactive,
+    /// Connected to the home DERP region with the given id.
+    Connected(u16),
+    /// The connection to the home DERP region with the given id was lost or could not be
+    /// established. Reconnecting is retried in the background.
+    Unavailable(u16),
+}
+
 /// Contains fields for an active DERP connection.
 #[derive(Debug)]
 struct ActiveDerp {
@@ -114,6 +150,12 @@ impl DerpActor {
                         DerpActorMessage::MaybeCloseDerpsOnRebind(ifs) => {
                             self.maybe_close_derps_on_rebind(&ifs).await;
                         }
+                        DerpActorMessage::RegionInfos(mut infos, s) => {
+                            for info in infos.iter_mut() {
+                                info.connected = self.active_derp.contains_key(&info.region_id);
+                            }
+                            let _ = s.send(infos);
+                        }
                         DerpActorMessage::Shutdown => {
                             debug!("shutting down");
                             self.close_all_derp("conn-close").await;
@@ -142,6 +184,7 @@ impl DerpActor {
                             self.msg_sender.send(ActorMessage::ReceiveDerp(read_result)).await.ok();
                         }
                     }
+                    self.update_derp_status();
                 }
                 _ = cleanup_timer.tick() => {
                     trace!("tick: cleanup");
@@ -425,6 +468,22 @@ impl DerpActor {
         }
     }
 
+    /// Updates the [`DerpStatus`] from the state of the connection to our home region.
+    fn update_derp_status(&self) {
+        let my_derp = self.conn.my_derp();
+        if my_derp == 0 {
+            // no home region chosen yet, nothing changed
+            return;
+        }
+        let status = match self.active_derp.get(&my_derp).map(|ad| ad.reader.connected) {
+            Some(Some(true)) => DerpStatus::Connected(my_derp),
+            Some(Some(false)) | None => DerpStatus::Unavailable(my_derp),
+            // still connecting
+            Some(None) => return,
+        };
+        self.conn.set_derp_status(status);
+    }
+
     fn log_active_derp(&self) {
         let now = Instant::now();
         debug!("{} active derp conns{}", self.active_derp.len(), {
@@ -508,6 +567,8 @@ struct ReaderState {
     backoff: backoff::exponential::ExponentialBackoff<backoff::SystemClock>,
     last_packet_time: Option<Instant>,
     last_packet_src: Option<PublicKey>,
+    /// Whether the connection is up, `None` before it was first established or failed.
+    connected: Option<bool>,
     cancel: CancellationToken,
 }
 
@@ -546,10 +607,25 @@ impl ReaderState {
                 .build(),
             last_packet_time: None,
             last_packet_src: None,
+            connected: None,
         }
     }
 
     async fn recv(&mut self) -> (ReadResult, ReadAction) {
+        if self.connected != Some(true) {
+            // The server info is consumed by the handshake and the server might not send
+            // anything else until a peer does, so report the connection once it is up.
+            let res = tokio::select! {
+                res = self.derp_client.connect() => res,
+                _ = self.cancel.cancelled() => {
+                    return (ReadResult::Break, ReadAction::None);
+                }
+            };
+            if res.is_ok() {
+                self.connected = Some(true);
+                return (ReadResult::Continue, ReadAction::None);
+            }
+        }
         let msg = tokio::select! {
             msg = self.derp_client.recv_detail() => {
                 msg
@@ -560,6 +636,7 @@ impl ReaderState {
         };
         debug!(region_id=%self.region, ?msg, "derp.recv received");
 
+        self.connected = Some(msg.is_ok());
         match msg {
             Err(err) => {
                 debug!(
@@ -584,9 +661,8 @@ impl ReaderState {
                 }
 
                 // If our DERP connection broke, it might be because our network
-                // conditions changed. Start that check.
-                // TODO:
-                // self.re_stun("derp-recv-error").await;
+                // conditions changed. This is checked when the home connection is marked as
+                // unavailable, see `Inner::set_derp_status`.
 
                 // Back off a bit before reconnecting.
                 match self.backoff.next_backoff() {
@@ -57,6 +57,8 @@ pub struct Metrics {
 
     // How many times our DERP home region DI has changed from non-zero to a different non-zero.
     pub derp_home_change: Counter,
+    // How many times the connection to our DERP home region became unavailable.
+    pub derp_home_unavailable: Counter,
 
     /*
      * Connection Metrics
@@ -124,6 +126,9 @@ impl Default for Metrics {
             // How many times our DERP home region DI has changed from non-zero to a different non-zero.
             derp_home_change: Counter::new("derp_home_change"),
 
+            // How many times the connection to our DERP home region became unavailable.
+            derp_home_unavailable: Counter::new("derp_home_unavailable"),
+
             num_direct_conns_added: Counter::new(
                 "number of direct connections to a peer we have added",
             ),
@@ -1,7 +1,7 @@
 use std::{
     fmt::Debug,
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     sync::Arc,
     task::{Context, Poll},
 };
@@ -23,6 +23,10 @@ const SOCKET_BUFFER_SIZE: usize = 7 << 20;
 pub struct RebindingUdpConn {
     io: Arc<tokio::net::UdpSocket>,
     state: Arc<quinn_udp::UdpSocketState>,
+    /// The local IP address this socket binds to, kept across rebinds.
+    ip: IpAddr,
+    /// Whether the socket was bound by the caller, in which case it is never re-bound.
+    external: bool,
 }
 
 impl RebindingUdpConn {
@@ -33,7 +37,6 @@ impl RebindingUdpConn {
     pub(super) async fn rebind(
         &mut self,
         port: u16,
-        network: Network,
         cur_port_fate: CurrentPortFate,
     ) -> anyhow::Result<()> {
         trace!(
@@ -47,8 +50,13 @@ impl RebindingUdpConn {
         if self.port() == port && cur_port_fate == CurrentPortFate::Keep {
             return Ok(());
         }
+        // A socket bound by the caller is kept, it might not be possible to bind it again.
+        if self.external {
+            debug!("not rebinding externally bound socket");
+            return Ok(());
+        }
 
-        let sock = bind(Some(&self.io), port, network, cur_port_fate).await?;
+        let sock = bind(Some(&self.io), self.ip, port, cur_port_fate).await?;
         self.io = Arc::new(tokio::net::UdpSocket::from_std(sock)?);
         self.state = Default::default();
 
@@ -56,10 +64,32 @@ impl RebindingUdpConn {
     }
 
     pub(super) async fn bind(port: u16, network: Network) -> anyhow::Result<Self> {
-        let sock = bind(None, port, network, CurrentPortFate::Keep).await?;
+        Self::bind_addr(SocketAddr::new(network.default_addr(), port)).await
+    }
+
+    /// Binds to a specific local address, which is also used for any later rebinds.
+    pub(super) async fn bind_addr(addr: SocketAddr) -> anyhow::Result<Self> {
+        let sock = bind(None, addr.ip(), addr.port(), CurrentPortFate::Keep).await?;
+        Ok(Self {
+            io: Arc::new(tokio::net::UdpSocket::from_std(sock)?),
+            state: Default::default(),
+            ip: addr.ip(),
+            external: false,
+        })
+    }
+
+    /// Uses a socket that was bound by the caller, e.g. passed in by systemd.
+    ///
+    /// The socket is used as it is for the lifetime of the connection, it is never re-bound.
+    pub(super) fn from_std(sock: std::net::UdpSocket) -> anyhow::Result<Self> {
+        sock.set_nonblocking(true)?;
+        quinn_udp::UdpSocketState::configure((&sock).into())?;
+        let ip = sock.local_addr()?.ip();
         Ok(Self {
             io: Arc::new(tokio::net::UdpSocket::from_std(sock)?),
             state: Default::default(),
+            ip,
+            external: true,
         })
     }
 
@@ -133,13 +163,14 @@ impl AsyncUdpSocket for RebindingUdpConn {
 
 async fn bind(
     inner: Option<&tokio::net::UdpSocket>,
+    ip: IpAddr,
     port: u16,
-    network: Network,
     cur_port_fate: CurrentPortFate,
 ) -> anyhow::Result<std::net::UdpSocket> {
+    let network = Network::from(ip);
     debug!(
-        "bind_socket: network={:?} cur_port_fate={:?}",
-        network, cur_port_fate
+        "bind_socket: network={:?} ip={} cur_port_fate={:?}",
+        network, ip, cur_port_fate
     );
 
     // Build a list of preferred ports.
@@ -168,7 +199,7 @@ async fn bind(
             // TODO: inner.close()
         }
         // Open a new one with the desired port.
-        match listen_packet(network, *port).await {
+        match listen_packet(SocketAddr::new(ip, *port)).await {
             Ok(pconn) => {
                 let local_addr = pconn.local_addr().context("UDP socket not bound")?;
                 debug!("bind_socket: successfully bound {network:?} {local_addr}");
@@ -189,8 +220,8 @@ async fn bind(
 }
 
 /// Opens a packet listener.
-async fn listen_packet(network: Network, port: u16) -> std::io::Result<std::net::UdpSocket> {
-    let addr = SocketAddr::new(network.default_addr(), port);
+async fn listen_packet(addr: SocketAddr) -> std::io::Result<std::net::UdpSocket> {
+    let network = Network::from(addr.ip());
     let socket = socket2::Socket::new(
         network.into(),
         socket2::Type::DGRAM,
@@ -3,3 +3,5 @@
 pub mod interfaces;
 pub mod ip;
 pub mod netmon;
+#[cfg(all(unix, feature = "socket-activation"))]
+pub mod socket_activation;
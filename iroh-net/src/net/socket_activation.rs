@@ -0,0 +1,134 @@
+//! Support for systemd socket activation.
+//!
+//! With socket activation the service manager binds the sockets and passes them to the
+//! process on startup, which allows to start the process on demand and to restart it
+//! without losing its port.
+//!
+//! The file descriptors are passed following the `sd_listen_fds(3)` contract:
+//!
+//! - `LISTEN_PID` is set to the pid of the process the sockets are meant for.
+//! - `LISTEN_FDS` is set to the number of passed file descriptors.
+//! - The passed file descriptors start at `3` and are consecutive.
+//!
+//! Only UDP sockets are used, other passed file descriptors are left alone. The environment
+//! is not modified, as that is not safe while other threads might read it. Child processes
+//! ignore the variables, as `LISTEN_PID` does not match their pid, and do not inherit the
+//! sockets, which are closed on exec.
+//!
+//! A matching systemd socket unit looks like this:
+//!
+//! ```ini
+//! [Socket]
+//! ListenDatagram=0.0.0.0:11204
+//! ListenDatagram=[::]:11205
+//! ```
+
+use std::{
+    net::UdpSocket,
+    os::fd::{FromRawFd, RawFd},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{Context, Result};
+use tracing::{debug, warn};
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Whether the passed sockets were taken already, they can only be owned once.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Takes the UDP sockets passed in by systemd socket activation.
+///
+/// Returns an empty list if the process was not socket activated. The sockets are only
+/// returned by the first call, later calls return an empty list.
+pub fn listen_udp_sockets() -> Result<Vec<UdpSocket>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    let Some(count) = listen_fds(pid.as_deref(), fds.as_deref(), std::process::id())? else {
+        return Ok(Vec::new());
+    };
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        debug!("activated sockets were taken already");
+        return Ok(Vec::new());
+    }
+    let mut sockets = Vec::new();
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        if !is_udp_socket(fd) {
+            debug!("ignoring activated file descriptor {fd}, it is not a udp socket");
+            continue;
+        }
+        // Do not leak the socket into spawned processes.
+        // SAFETY: fcntl on a file descriptor we were passed, no memory is involved.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("failed to configure activated socket {fd}"));
+        }
+        // SAFETY: the service manager passed us ownership of the file descriptor, and it is
+        // checked to be a udp socket.
+        let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+        debug!("using activated socket {:?}", socket.local_addr());
+        sockets.push(socket);
+    }
+    if sockets.is_empty() {
+        warn!("socket activated, but no udp sockets were passed");
+    }
+    Ok(sockets)
+}
+
+/// Whether the file descriptor is a datagram socket.
+fn is_udp_socket(fd: RawFd) -> bool {
+    let mut typ: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `typ` and `len` are valid for writes and `len` is the size of `typ`.
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut typ as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    res == 0 && typ == libc::SOCK_DGRAM
+}
+
+/// Parses the `LISTEN_PID` and `LISTEN_FDS` environment variables.
+///
+/// Returns the number of passed file descriptors, or `None` if they are not meant for the
+/// process with pid `me`.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, me: u32) -> Result<Option<RawFd>> {
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(None);
+    };
+    let pid: u32 = pid.parse().context("invalid LISTEN_PID")?;
+    if pid != me {
+        debug!("LISTEN_PID {pid} is not this process, ignoring activated sockets");
+        return Ok(None);
+    }
+    let fds: RawFd = fds.parse().context("invalid LISTEN_FDS")?;
+    anyhow::ensure!(fds >= 0, "invalid LISTEN_FDS: {fds}");
+    Ok(Some(fds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        // not socket activated
+        assert_eq!(listen_fds(None, None, 42).unwrap(), None);
+        assert_eq!(listen_fds(Some("42"), None, 42).unwrap(), None);
+        assert_eq!(listen_fds(None, Some("2"), 42).unwrap(), None);
+
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42).unwrap(), Some(2));
+        assert_eq!(listen_fds(Some("42"), Some("0"), 42).unwrap(), Some(0));
+        // the sockets are meant for another process, e.g. our parent
+        assert_eq!(listen_fds(Some("41"), Some("2"), 42).unwrap(), None);
+
+        assert!(listen_fds(Some("me"), Some("2"), 42).is_err());
+        assert!(listen_fds(Some("42"), Some("two"), 42).is_err());
+        assert!(listen_fds(Some("42"), Some("-1"), 42).is_err());
+    }
+}
@@ -139,7 +139,8 @@ impl RegionLatencies {
         self.0.is_empty()
     }
 
-    fn get(&self, index: u16) -> Option<Duration> {
+    /// Returns the latency of a region, if it was measured.
+    pub fn get(&self, index: u16) -> Option<Duration> {
         self.0.get(&index).copied()
     }
 }
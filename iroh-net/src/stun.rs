@@ -143,6 +143,7 @@ pub fn parse_response(b: &[u8]) -> Result<(TransactionId, SocketAddr), Error> {
     Err(Error::MalformedAttrs)
 }
 
+/// STUN servers for tests.
 #[cfg(test)]
 pub mod test {
     use std::{
@@ -163,17 +164,20 @@ pub mod test {
     };
     use tracing::{debug, trace};
 
+    /// The number of requests a test STUN server received.
     // (read_ipv4, read_ipv5)
     #[derive(Debug, Default, Clone)]
     pub struct StunStats(Arc<Mutex<(usize, usize)>>);
 
     impl StunStats {
+        /// The number of requests over IPv4 and IPv6.
         pub async fn total(&self) -> usize {
             let s = self.0.lock().await;
             s.0 + s.1
         }
     }
 
+    /// A DERP map with a region for each of the `stun` servers.
     pub fn derp_map_of(stun: impl Iterator<Item = SocketAddr>) -> DerpMap {
         let regions = stun.enumerate().map(|(i, addr)| {
             let region_id = (i + 1) as u16;
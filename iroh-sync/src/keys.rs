@@ -159,6 +159,59 @@ impl NamespacePublicKey {
     }
 }
 
+/// A secret shared by the participants of a namespace, to authenticate their gossip messages.
+///
+/// The gossip swarm of a namespace is open to anyone who knows the namespace id. Entries in
+/// gossip messages are signed by their author and namespace, so nobody without write access
+/// can forge entries, but checking that is comparatively expensive. If the participants of a
+/// namespace share a [`GossipSecret`], every gossip message carries a MAC of its content
+/// keyed with the secret, and messages from peers that do not know the secret are dropped
+/// before they are decoded and their entries verified.
+///
+/// This does not protect against peers that know the secret, e.g. everyone who received a
+/// ticket for the namespace, and it does not hide the content of the messages. It only
+/// applies to gossip, not to the set reconciliation with single peers.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipSecret([u8; 32]);
+
+impl GossipSecret {
+    /// Length of the MAC created with [`Self::mac`].
+    pub const MAC_LEN: usize = 32;
+
+    /// Create a new random [`GossipSecret`].
+    pub fn new<R: CryptoRngCore + ?Sized>(rng: &mut R) -> Self {
+        let mut bytes = [0u8; 32];
+        rng.fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+
+    /// Create a [`GossipSecret`] from a byte array.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        Self(*bytes)
+    }
+
+    /// Returns the [`GossipSecret`] byte representation.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Create the MAC for `message`.
+    pub fn mac(&self, message: &[u8]) -> [u8; 32] {
+        blake3::keyed_hash(&self.0, message).into()
+    }
+
+    /// Check that `mac` was created with this secret for `message`, in constant time.
+    pub fn verify(&self, message: &[u8], mac: &[u8; 32]) -> bool {
+        blake3::keyed_hash(&self.0, message) == blake3::Hash::from(*mac)
+    }
+}
+
+impl fmt::Debug for GossipSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GossipSecret(..)")
+    }
+}
+
 impl fmt::Display for Author {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", base32::fmt(self.to_bytes()))
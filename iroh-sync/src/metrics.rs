@@ -1,10 +1,15 @@
 //! Metrics for iroh-sync
 
 use iroh_metrics::{
-    core::{Counter, Metric},
+    core::{Counter, Histogram, Metric},
     struct_iterable::Iterable,
 };
 
+/// Upper bounds of the buckets of [`Metrics::sync_propagation_ms`].
+const PROPAGATION_BUCKETS_MS: [f64; 12] = [
+    5., 10., 25., 50., 100., 250., 500., 1000., 2500., 5000., 10000., 60000.,
+];
+
 /// Metrics for iroh-sync
 #[allow(missing_docs)]
 #[derive(Debug, Clone, Iterable)]
@@ -13,6 +18,8 @@ pub struct Metrics {
     pub new_entries_remote: Counter,
     pub new_entries_local_size: Counter,
     pub new_entries_remote_size: Counter,
+    pub new_entries_remote_future: Counter,
+    pub sync_propagation_ms: Histogram,
     pub sync_via_connect_success: Counter,
     pub sync_via_connect_failure: Counter,
     pub sync_via_accept_success: Counter,
@@ -26,6 +33,13 @@ impl Default for Metrics {
             new_entries_remote: Counter::new("Number of document entries added by peers"),
             new_entries_local_size: Counter::new("Total size of entry contents added locally"),
             new_entries_remote_size: Counter::new("Total size of entry contents added by peers"),
+            new_entries_remote_future: Counter::new(
+                "Number of entries added by peers with a timestamp ahead of the local clock",
+            ),
+            sync_propagation_ms: Histogram::new(
+                "Time in ms from the write on the origin node until entries added by peers arrived",
+                PROPAGATION_BUCKETS_MS.into_iter(),
+            ),
             sync_via_accept_success: Counter::new("Number of successfull syncs (via accept)"),
             sync_via_accept_failure: Counter::new("Number of failed syncs (via accept)"),
             sync_via_connect_success: Counter::new("Number of successfull syncs (via connect)"),
@@ -19,7 +19,21 @@ use crate::metrics::Metrics;
 use iroh_metrics::inc;
 
 /// The ALPN identifier for the iroh-sync protocol
-pub const SYNC_ALPN: &[u8] = b"/iroh-sync/1";
+///
+/// Version 2 added the protocol version and capabilities to the init message, which peers
+/// speaking version 1 can not decode. Later protocol changes are negotiated in the init message.
+pub const SYNC_ALPN: &[u8] = b"/iroh-sync/2";
+
+/// The version of the sync protocol spoken by this implementation.
+///
+/// Sent in the init message of a sync request. Two peers speak the lower of their versions.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// The oldest version of the sync protocol this implementation can still speak.
+///
+/// Sync requests from peers with an older version are aborted with
+/// [`AbortReason::IncompatibleVersion`].
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
 
 mod codec;
 
@@ -189,6 +203,53 @@ pub enum AbortReason {
     NotAvailable,
     /// We are already syncing this namespace.
     AlreadySyncing,
+    /// The peer speaks a protocol version we do not support.
+    IncompatibleVersion {
+        /// The oldest version we support.
+        min: u16,
+        /// The newest version we support.
+        max: u16,
+    },
+}
+
+/// Optional features of the sync protocol, as a set of flags.
+///
+/// Each peer advertises the features it supports in the init message of a sync request, and
+/// a feature is only used if both peers advertise it. Flags that are unknown to this
+/// implementation are ignored, so new features can be added without breaking older peers.
+///
+/// No optional features are defined yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// The features supported by this implementation.
+    pub const SUPPORTED: Self = Self::empty();
+
+    /// No features.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The features in both `self` and `other`.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// The features in `self`, `other` or both.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns true if all features in `other` are in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The raw flags.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
 }
 
 impl AcceptError {
@@ -8,10 +8,13 @@ use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_stream::StreamExt;
 use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
-use tracing::trace;
+use tracing::{debug, trace};
 
 use crate::{
-    net::{AbortReason, AcceptError, AcceptOutcome, ConnectError},
+    net::{
+        AbortReason, AcceptError, AcceptOutcome, Capabilities, ConnectError, MIN_PROTOCOL_VERSION,
+        PROTOCOL_VERSION,
+    },
     store, NamespaceId, Replica,
 };
 
@@ -20,6 +23,18 @@ struct SyncCodec;
 
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024 * 1024; // This is likely too large, but lets have some restrictions
 
+/// Initial capacity of the read and write buffers of a sync connection.
+///
+/// Sync messages for large ranges are much larger than the 8 KiB default of the framed
+/// streams. The buffers are reused for all messages of a connection and never shrink.
+const BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Maximum space reserved up front for a frame whose length prefix was received.
+///
+/// The length prefix alone can announce up to [`MAX_MESSAGE_SIZE`], so larger frames grow the
+/// buffer as their data arrives.
+const MAX_RESERVE: usize = 64 * 1024;
+
 impl Decoder for SyncCodec {
     type Item = Message;
     type Error = anyhow::Error;
@@ -35,6 +50,9 @@ impl Decoder for SyncCodec {
             frame_len
         );
         if src.len() < 4 + frame_len {
+            // grow the buffer for the whole frame, instead of repeatedly while reading, but
+            // not beyond what a peer can make us allocate without sending the data
+            src.reserve((4 + frame_len - src.len()).min(MAX_RESERVE));
             return Ok(None);
         }
 
@@ -56,11 +74,11 @@ impl Encoder<Message> for SyncCodec {
             len
         );
 
+        let start = dst.len();
+        dst.reserve(4 + len);
         dst.put_u32(u32::try_from(len).expect("already checked"));
-        if dst.len() < 4 + len {
-            dst.resize(4 + len, 0u8);
-        }
-        postcard::to_slice(&item, &mut dst[4..])?;
+        dst.resize(start + 4 + len, 0u8);
+        postcard::to_slice(&item, &mut dst[start + 4..])?;
 
         Ok(())
     }
@@ -68,7 +86,9 @@ impl Encoder<Message> for SyncCodec {
 
 /// Sync Protocol
 ///
-/// - Init message: signals which namespace is being synced
+/// - Init message: signals which namespace is being synced, and the protocol version and
+///   capabilities of the dialing peer
+/// - Accept message: the protocol version and capabilities of the accepting peer
 /// - N Sync messages
 ///
 /// On any error and on success the substream is closed.
@@ -80,6 +100,17 @@ enum Message {
         namespace: NamespaceId,
         /// Initial message
         message: crate::sync::ProtocolMessage,
+        /// Protocol version of the dialing peer
+        version: u16,
+        /// Optional features supported by the dialing peer
+        capabilities: Capabilities,
+    },
+    /// Accept message (sent by the accepting peer in reply to the init message)
+    Accept {
+        /// Protocol version of the accepting peer
+        version: u16,
+        /// Optional features supported by the accepting peer
+        capabilities: Capabilities,
     },
     /// Sync messages (sent by both peers)
     Sync(crate::sync::ProtocolMessage),
@@ -87,6 +118,17 @@ enum Message {
     Abort { reason: AbortReason },
 }
 
+/// Wraps the streams of a sync connection, with buffers of [`BUFFER_CAPACITY`].
+fn framed<R: AsyncRead, W: AsyncWrite>(
+    reader: R,
+    writer: W,
+) -> (FramedRead<R, SyncCodec>, FramedWrite<W, SyncCodec>) {
+    let reader = FramedRead::with_capacity(reader, SyncCodec, BUFFER_CAPACITY);
+    let mut writer = FramedWrite::new(writer, SyncCodec);
+    writer.write_buffer_mut().reserve(BUFFER_CAPACITY);
+    (reader, writer)
+}
+
 /// Runs the initiator side of the sync protocol.
 pub(super) async fn run_alice<S: store::Store, R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
     writer: &mut W,
@@ -95,14 +137,17 @@ pub(super) async fn run_alice<S: store::Store, R: AsyncRead + Unpin, W: AsyncWri
     other_peer_id: PublicKey,
 ) -> Result<(), ConnectError> {
     let other_peer_id = *other_peer_id.as_bytes();
-    let mut reader = FramedRead::new(reader, SyncCodec);
-    let mut writer = FramedWrite::new(writer, SyncCodec);
+    let (mut reader, mut writer) = framed(reader, writer);
 
     // Init message
 
     let init_message = Message::Init {
         namespace: alice.namespace(),
-        message: alice.sync_initial_message().map_err(ConnectError::sync)?,
+        message: alice
+            .sync_resume_message(other_peer_id)
+            .map_err(ConnectError::sync)?,
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::SUPPORTED,
     };
     trace!("alice -> bob: {:#?}", init_message);
     writer
@@ -111,12 +156,36 @@ pub(super) async fn run_alice<S: store::Store, R: AsyncRead + Unpin, W: AsyncWri
         .map_err(ConnectError::sync)?;
 
     // Sync message loop
+    let mut accepted = false;
     while let Some(msg) = reader.next().await {
         let msg = msg.map_err(ConnectError::sync)?;
         match msg {
             Message::Init { .. } => {
                 return Err(ConnectError::sync(anyhow!("unexpected init message")));
             }
+            Message::Accept { .. } if accepted => {
+                return Err(ConnectError::sync(anyhow!("double accept message")));
+            }
+            Message::Accept {
+                version,
+                capabilities,
+            } => {
+                if version < MIN_PROTOCOL_VERSION {
+                    return Err(ConnectError::sync(anyhow!(
+                        "peer speaks incompatible protocol version {version}, \
+                         supported are {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION}"
+                    )));
+                }
+                let version = version.min(PROTOCOL_VERSION);
+                let capabilities = capabilities.intersection(Capabilities::SUPPORTED);
+                debug!(?version, ?capabilities, "alice: negotiated protocol");
+                accepted = true;
+            }
+            Message::Sync(_) if !accepted => {
+                return Err(ConnectError::sync(anyhow!(
+                    "unexpected sync message before accept"
+                )));
+            }
             Message::Sync(msg) => {
                 if let Some(msg) = alice
                     .sync_process_message(msg, other_peer_id)
@@ -136,6 +205,9 @@ pub(super) async fn run_alice<S: store::Store, R: AsyncRead + Unpin, W: AsyncWri
             }
         }
     }
+    alice
+        .sync_finished(other_peer_id)
+        .map_err(ConnectError::sync)?;
 
     Ok(())
 }
@@ -187,14 +259,29 @@ impl<S: store::Store> BobState<S> {
         F: Fn(NamespaceId, PublicKey) -> Fut,
         Fut: Future<Output = anyhow::Result<AcceptOutcome<S>>>,
     {
-        let mut reader = FramedRead::new(reader, SyncCodec);
-        let mut writer = FramedWrite::new(writer, SyncCodec);
+        let (mut reader, mut writer) = framed(reader, writer);
         while let Some(msg) = reader.next().await {
             let msg = msg.map_err(|e| self.fail(e))?;
             let next = match (msg, self.replica.as_ref()) {
-                (Message::Init { namespace, message }, None) => {
-                    let accept = accept_cb(namespace, self.peer).await;
-                    let accept = accept.map_err(|e| self.fail(e))?;
+                (
+                    Message::Init {
+                        namespace,
+                        message,
+                        version,
+                        capabilities,
+                    },
+                    None,
+                ) => {
+                    let accept = if version < MIN_PROTOCOL_VERSION {
+                        Err(AbortReason::IncompatibleVersion {
+                            min: MIN_PROTOCOL_VERSION,
+                            max: PROTOCOL_VERSION,
+                        })
+                    } else {
+                        accept_cb(namespace, self.peer)
+                            .await
+                            .map_err(|e| self.fail(e))?
+                    };
                     let replica = match accept {
                         Ok(replica) => replica,
                         Err(reason) => {
@@ -209,6 +296,16 @@ impl<S: store::Store> BobState<S> {
                             });
                         }
                     };
+                    let version = version.min(PROTOCOL_VERSION);
+                    let capabilities = capabilities.intersection(Capabilities::SUPPORTED);
+                    debug!(?namespace, peer = ?self.peer, ?version, ?capabilities, "run_bob: negotiated protocol");
+                    writer
+                        .send(Message::Accept {
+                            version: PROTOCOL_VERSION,
+                            capabilities: Capabilities::SUPPORTED,
+                        })
+                        .await
+                        .map_err(|e| self.fail(e))?;
                     trace!(?namespace, peer = ?self.peer, "run_bob: recv initial message {message:#?}");
                     let next = replica.sync_process_message(message, *self.peer.as_bytes());
                     self.replica = Some(replica);
@@ -227,6 +324,9 @@ impl<S: store::Store> BobState<S> {
                 (Message::Abort { reason }, _) => {
                     return Err(self.fail(anyhow!("unexpected abort message ({reason:?})")))
                 }
+                (Message::Accept { .. }, _) => {
+                    return Err(self.fail(anyhow!("unexpected accept message")))
+                }
             };
             let next = next.map_err(|e| self.fail(e))?;
             match next {
@@ -242,6 +342,11 @@ impl<S: store::Store> BobState<S> {
         }
 
         trace!(namespace = ?self.namespace().unwrap(), peer = ?self.peer, "run_bob: finished");
+        if let Some(replica) = self.replica.as_ref() {
+            replica
+                .sync_finished(*self.peer.as_bytes())
+                .map_err(|e| self.fail(e))?;
+        }
 
         self.namespace()
             .ok_or_else(|| self.fail(anyhow!("Stream closed before init message")))
@@ -365,6 +470,116 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_sync_incompatible_version() -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let alice_peer_id = SecretKey::from_bytes(&[1u8; 32]).public();
+        let bob_peer_id = SecretKey::from_bytes(&[2u8; 32]).public();
+        let namespace = Namespace::new(&mut rng);
+        let alice_replica_store = store::memory::Store::default();
+        let alice_replica = alice_replica_store.new_replica(namespace.clone())?;
+        let bob_replica_store = store::memory::Store::default();
+        bob_replica_store.new_replica(namespace.clone())?;
+
+        let (alice, bob) = tokio::io::duplex(1024);
+        let (alice_reader, alice_writer) = tokio::io::split(alice);
+        let mut alice_reader = FramedRead::new(alice_reader, SyncCodec);
+        let mut alice_writer = FramedWrite::new(alice_writer, SyncCodec);
+        let (mut bob_reader, mut bob_writer) = tokio::io::split(bob);
+        let bob_task = tokio::task::spawn(async move {
+            run_bob::<store::memory::Store, _, _, _, _>(
+                &mut bob_writer,
+                &mut bob_reader,
+                |namespace, _| {
+                    futures::future::ready(
+                        bob_replica_store
+                            .open_replica(&namespace)
+                            .map(|r| r.ok_or(AbortReason::NotAvailable)),
+                    )
+                },
+                alice_peer_id,
+            )
+            .await
+        });
+
+        alice_writer
+            .send(super::Message::Init {
+                namespace: namespace.id(),
+                message: alice_replica.sync_resume_message(*bob_peer_id.as_bytes())?,
+                version: MIN_PROTOCOL_VERSION - 1,
+                capabilities: Capabilities::SUPPORTED,
+            })
+            .await?;
+        let expected = AbortReason::IncompatibleVersion {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        };
+        assert!(matches!(
+            alice_reader.next().await.transpose()?,
+            Some(super::Message::Abort { reason }) if reason == expected
+        ));
+        assert!(matches!(
+            bob_task.await?,
+            Err(AcceptError::Abort { reason, .. }) if reason == expected
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_codec_buffers() -> Result<()> {
+        let abort = |reason| super::Message::Abort { reason };
+        let mut buffer = BytesMut::new();
+        // encoding appends to pending messages
+        SyncCodec.encode(abort(AbortReason::NotAvailable), &mut buffer)?;
+        SyncCodec.encode(abort(AbortReason::AlreadySyncing), &mut buffer)?;
+        let encoded = buffer.clone();
+
+        // a partial frame reserves the space for the whole frame
+        let mut partial = BytesMut::from(&encoded[..5]);
+        assert!(SyncCodec.decode(&mut partial)?.is_none());
+        let frame_len = u32::from_be_bytes(encoded[..4].try_into().unwrap()) as usize;
+        assert!(partial.capacity() >= 4 + frame_len);
+
+        // the reservation for a huge frame is capped
+        let mut huge = BytesMut::new();
+        huge.extend_from_slice(&(MAX_MESSAGE_SIZE as u32).to_be_bytes());
+        assert!(SyncCodec.decode(&mut huge)?.is_none());
+        assert!(huge.capacity() < 4 * MAX_RESERVE);
+
+        assert!(matches!(
+            SyncCodec.decode(&mut buffer)?,
+            Some(super::Message::Abort {
+                reason: AbortReason::NotAvailable
+            })
+        ));
+        assert!(matches!(
+            SyncCodec.decode(&mut buffer)?,
+            Some(super::Message::Abort {
+                reason: AbortReason::AlreadySyncing
+            })
+        ));
+        assert!(buffer.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let ours = Capabilities(1).union(Capabilities(1 << 2));
+        // a newer peer may advertise flags we do not know about
+        let theirs = Capabilities(1 << 31).union(Capabilities(1 << 2));
+        let both = ours.intersection(theirs);
+        assert_eq!(both, Capabilities(1 << 2));
+        assert!(ours.contains(both));
+        assert!(!both.contains(Capabilities(1)));
+        assert_eq!(Capabilities::empty().bits(), 0);
+        // nothing is enabled with a peer that supports everything
+        let all = Capabilities(u32::MAX);
+        assert_eq!(
+            all.intersection(Capabilities::SUPPORTED),
+            Capabilities::empty()
+        );
+    }
+
     #[tokio::test]
     async fn test_sync_many_authors_memory() -> Result<()> {
         let _guard = iroh_test::logging::setup();
@@ -184,11 +184,37 @@ impl<E: RangeEntry> Message<E> {
         Ok(Message { parts: vec![part] })
     }
 
+    /// Construct a message requesting reconciliation of the given ranges.
+    fn for_ranges<S: Store<E>>(store: &S, ranges: &[Range<E::Key>]) -> Result<Self, S::Error> {
+        let parts = ranges
+            .iter()
+            .map(|range| {
+                let fingerprint = store.get_fingerprint(range)?;
+                Ok(MessagePart::RangeFingerprint(RangeFingerprint {
+                    range: range.clone(),
+                    fingerprint,
+                }))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Message { parts })
+    }
+
     pub fn parts(&self) -> &[MessagePart<E>] {
         &self.parts
     }
+
+    /// The ranges covered by the parts of this message.
+    pub fn ranges(&self) -> impl Iterator<Item = &Range<E::Key>> {
+        self.parts.iter().map(|part| match part {
+            MessagePart::RangeFingerprint(RangeFingerprint { range, .. }) => range,
+            MessagePart::RangeItem(RangeItem { range, .. }) => range,
+        })
+    }
 }
 
+/// The ranges not yet reconciled in an interrupted sync, `None` if there is no checkpoint.
+pub type Checkpoint<K> = Option<Vec<Range<K>>>;
+
 pub trait Store<E: RangeEntry>: Sized {
     type Error: Debug + Send + Sync + Into<anyhow::Error>;
 
@@ -203,6 +229,17 @@ pub trait Store<E: RangeEntry>: Sized {
     /// Insert the given key value pair.
     fn put(&mut self, entry: E) -> Result<(), Self::Error>;
 
+    /// Insert all given entries.
+    ///
+    /// The default inserts them one by one. Persistent stores should override this to write
+    /// them at once.
+    fn put_many(&mut self, entries: Vec<E>) -> Result<(), Self::Error> {
+        for entry in entries {
+            self.put(entry)?;
+        }
+        Ok(())
+    }
+
     type RangeIterator<'a>: Iterator<Item = Result<E, Self::Error>>
     where
         Self: 'a,
@@ -216,6 +253,23 @@ pub trait Store<E: RangeEntry>: Sized {
 
     /// Remove an entry from the store.
     fn remove(&mut self, key: &E::Key) -> Result<Option<E>, Self::Error>;
+
+    /// Get the ranges that were not yet reconciled when the last sync with `peer` was
+    /// interrupted.
+    ///
+    /// The default keeps no checkpoints, so every sync starts from scratch.
+    fn sync_checkpoint(&self, _peer: &[u8; 32]) -> Result<Checkpoint<E::Key>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Set or, if `ranges` is `None`, clear the sync checkpoint for `peer`.
+    fn set_sync_checkpoint(
+        &mut self,
+        _peer: &[u8; 32],
+        _ranges: Option<&[Range<E::Key>]>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -265,6 +319,20 @@ where
         Message::init(&self.store)
     }
 
+    /// Generates an initial message that only covers the given ranges.
+    ///
+    /// Falls back to [`Self::initial_message`] if `ranges` is empty.
+    pub fn initial_message_for_ranges(
+        &self,
+        ranges: &[Range<E::Key>],
+    ) -> Result<Message<E>, S::Error> {
+        if ranges.is_empty() {
+            Message::init(&self.store)
+        } else {
+            Message::for_ranges(&self.store, ranges)
+        }
+    }
+
     /// Processes an incoming message and produces a response.
     /// If terminated, returns `None`
     ///
@@ -499,6 +567,11 @@ where
         self.store.put(entry)
     }
 
+    /// Insert several key value pairs at once.
+    pub fn put_many(&mut self, entries: Vec<E>) -> Result<(), S::Error> {
+        self.store.put_many(entries)
+    }
+
     /// List all existing key value pairs.
     // currently unused outside of tests
     #[cfg(test)]
@@ -510,15 +583,20 @@ where
     // pub fn get(&self, k: &K) -> Result<Option<V>, S::Error> {
     //     self.store.get(k)
     // }
-    // /// Remove the given key.
-    // pub fn remove(&mut self, k: &K) -> Result<Vec<V>, S::Error> {
-    //     self.store.remove(k)
-    // }
+    /// Remove the entry for the given key.
+    pub fn remove(&mut self, key: &E::Key) -> Result<Option<E>, S::Error> {
+        self.store.remove(key)
+    }
 
     /// Returns a refernce to the underlying store.
     pub(crate) fn store(&self) -> &S {
         &self.store
     }
+
+    /// Returns a mutable reference to the underlying store.
+    pub(crate) fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
 }
 
 #[cfg(test)]
@@ -1,14 +1,17 @@
 //! Storage trait and implementation for iroh-sync documents
 
-use anyhow::Result;
+use std::{cmp::Reverse, collections::BinaryHeap, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
 use iroh_bytes::Hash;
+use parking_lot::RwLock;
 use rand_core::CryptoRngCore;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     ranger,
-    sync::{Author, Namespace, Replica, SignedEntry},
-    AuthorId, NamespaceId,
+    sync::{Author, Capability, InsertOrigin, Replica, SignedEntry},
+    AuthorId, GossipSecret, NamespaceId,
 };
 
 #[cfg(feature = "fs-store")]
@@ -43,7 +46,13 @@ pub trait Store: std::fmt::Debug + Clone + Send + Sync + 'static {
         Self: 'a;
 
     /// Create a new replica for `namespace` and persist in this store.
-    fn new_replica(&self, namespace: Namespace) -> Result<Replica<Self::Instance>>;
+    ///
+    /// Passing a [`NamespaceId`] or [`Capability::Read`] creates a read-only replica.
+    ///
+    /// If a replica for the namespace already exists, the existing instance is returned and its
+    /// entries, subscriptions and sync state are kept. Passing a [`crate::Namespace`] for an
+    /// existing read-only replica upgrades it to write access in place.
+    fn new_replica(&self, namespace: impl Into<Capability>) -> Result<Replica<Self::Instance>>;
 
     /// List all replica namespaces in this store.
     fn list_namespaces(&self) -> Result<Self::NamespaceIter<'_>>;
@@ -78,6 +87,97 @@ pub trait Store: std::fmt::Debug + Clone + Send + Sync + 'static {
     /// Get an author key from the store.
     fn get_author(&self, author: &AuthorId) -> Result<Option<Author>>;
 
+    /// Remove an author key from the store.
+    ///
+    /// Entries signed by the author stay valid, but without the key they can no longer be updated
+    /// from this store. Therefore this fails if the author has written entries to any replica in
+    /// the store, or is the default author of a replica, unless `force` is set. With `force`,
+    /// the replicas the author is the default author of are left without a default author.
+    fn remove_author(&self, author: &AuthorId, force: bool) -> Result<()>;
+
+    /// Set the [`GossipSecret`] of a replica, or remove it with `None`.
+    ///
+    /// If set, the gossip messages of the replica are authenticated with the secret.
+    fn set_gossip_secret(
+        &self,
+        namespace: &NamespaceId,
+        secret: Option<GossipSecret>,
+    ) -> Result<()>;
+
+    /// Get the [`GossipSecret`] of a replica, if set.
+    fn gossip_secret(&self, namespace: &NamespaceId) -> Result<Option<GossipSecret>>;
+
+    /// Set the default author of a replica, or remove it with `None`.
+    ///
+    /// The default author is used for writes that do not name an author. Changing it does
+    /// not affect existing entries.
+    fn set_default_author(&self, namespace: &NamespaceId, author: Option<AuthorId>) -> Result<()>;
+
+    /// Get the default author of a replica, if set.
+    fn default_author(&self, namespace: &NamespaceId) -> Result<Option<AuthorId>>;
+
+    /// Get the default author of a replica, or create a new author with `rng` and set it as the
+    /// default author if none is set.
+    ///
+    /// The lookup and the creation happen atomically, so concurrent calls for the same replica
+    /// return the same author. Fails if the default author is set but its key is not in the
+    /// store.
+    fn get_or_create_default_author<R: CryptoRngCore + ?Sized>(
+        &self,
+        namespace: &NamespaceId,
+        rng: &mut R,
+    ) -> Result<Author>;
+
+    /// Set how long the entries of a replica are kept, or keep them forever with `None`.
+    ///
+    /// Entries older than the retention are pruned with [`Self::prune_expired`].
+    fn set_retention(&self, namespace: &NamespaceId, retention: Option<Duration>) -> Result<()>;
+
+    /// Get how long the entries of a replica are kept, if set.
+    fn retention(&self, namespace: &NamespaceId) -> Result<Option<Duration>>;
+
+    /// Prune the entries of a replica that are older than its retention.
+    ///
+    /// Expired entries of authors in this store are deleted, see [`Replica::delete`], so the
+    /// deletion reaches other peers on sync. Expired entries of other authors are removed
+    /// from the replica, see [`Replica::remove`]. Deletions are kept, whatever their age, so
+    /// that they keep overriding older entries of peers that did not prune them yet.
+    ///
+    /// Entries older than the retention are rejected when they are received again from peers,
+    /// so pruned entries do not come back.
+    ///
+    /// Returns the number of pruned entries. Does nothing if the replica does not exist or has
+    /// no retention. This scans all entries of the replica, so it should not be called on an
+    /// async runtime.
+    fn prune_expired(&self, namespace: &NamespaceId) -> Result<usize> {
+        let Some(retention) = self.retention(namespace)? else {
+            return Ok(0);
+        };
+        let Some(replica) = self.open_replica(namespace)? else {
+            return Ok(0);
+        };
+        let cutoff = crate::sync::system_time_now().saturating_sub(retention.as_micros() as u64);
+        let mut expired = Vec::new();
+        for entry in self.get_many(*namespace, GetFilter::All)? {
+            let entry = entry?;
+            if entry.timestamp() < cutoff && !entry.entry().record().is_empty() {
+                expired.push(entry);
+            }
+        }
+        for entry in &expired {
+            let id = entry.entry().id();
+            match self.get_author(&id.author())? {
+                Some(author) if replica.secret_key().is_some() => {
+                    replica.delete(id.key(), &author)?
+                }
+                _ => {
+                    replica.remove(id).map_err(Into::into)?;
+                }
+            }
+        }
+        Ok(expired.len())
+    }
+
     /// Get an iterator over entries of a replica.
     ///
     /// The [`GetFilter`] has several methods of filtering the returned entries.
@@ -91,8 +191,172 @@ pub trait Store: std::fmt::Debug + Clone + Send + Sync + 'static {
         key: impl AsRef<[u8]>,
     ) -> Result<Option<SignedEntry>>;
 
+    /// Get the `limit` most recently modified entries of a replica, newest first.
+    ///
+    /// Entries are ordered by their timestamp, ties are broken by the [`RecordIdentifier`]
+    /// order. Deletions are included, as they are modifications too.
+    ///
+    /// This scans all entries of the replica, but only keeps the `limit` newest ones in
+    /// memory at a time.
+    ///
+    /// [`RecordIdentifier`]: crate::sync::RecordIdentifier
+    fn get_recent(&self, namespace: NamespaceId, limit: usize) -> Result<Vec<SignedEntry>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        // min-heap of the newest entries seen so far, on equal timestamps the entry with the
+        // greatest identifier is the smallest
+        let mut recent = BinaryHeap::with_capacity(limit + 1);
+        for entry in self.get_many(namespace, GetFilter::All)? {
+            let entry = entry?;
+            recent.push(Reverse((entry.timestamp(), Reverse(entry))));
+            if recent.len() > limit {
+                recent.pop();
+            }
+        }
+        Ok(recent
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((_timestamp, Reverse(entry)))| entry)
+            .collect())
+    }
+
+    /// Get an iterator over the entries of a replica that changed after `since`.
+    ///
+    /// `since` is a timestamp in microseconds since the unix epoch, as returned by
+    /// [`SignedEntry::timestamp`]. Only entries with a timestamp strictly greater than `since`
+    /// are returned. Deletions are included, as they are modifications too.
+    ///
+    /// This is not a way to catch up on all changes since a previous call. The timestamps are
+    /// set by the authors of the entries, not by this store: an entry that is received from a
+    /// peer after a call, e.g. from an author that was offline or whose clock is behind, can
+    /// have a timestamp older than the newest one seen in that call, and is then missed by a
+    /// later call with that timestamp. To be notified of every change, use
+    /// [`Self::subscribe_all`] or [`Replica::subscribe`], and list the entries with
+    /// [`Self::get_many`] to start from a complete state.
+    ///
+    /// Entries are returned in the order of [`Self::get_many`], not ordered by timestamp.
+    /// Entries that were overwritten after `since` are only returned in their latest version.
+    /// This iterates over all entries of the replica.
+    fn diff(&self, namespace: NamespaceId, since: u64) -> Result<DiffIter<'_, Self>> {
+        let inner = self.get_many(namespace, GetFilter::All)?;
+        Ok(DiffIter { inner, since })
+    }
+
     /// Get all content hashes of all replicas in the store.
     fn content_hashes(&self) -> Result<Self::ContentHashesIter<'_>>;
+
+    /// Subscribe to insert events of all replicas in this store.
+    ///
+    /// Unlike [`Replica::subscribe`], any number of subscriptions can be active at a time,
+    /// and each of them receives the inserts into every replica opened from this store.
+    /// Dropping the receiver ends the subscription.
+    ///
+    /// Inserts never wait for a subscriber. A subscriber that falls too far behind is
+    /// disconnected: it receives the events that were queued, after which the
+    /// [`flume::Receiver`] reports that it is disconnected. Use
+    /// [`flume::Receiver::into_stream`] to consume the events as a stream.
+    fn subscribe_all(&self) -> flume::Receiver<(NamespaceId, InsertOrigin, SignedEntry)>;
+
+    /// Move the entry of `author` at key `from` to key `to`.
+    ///
+    /// This inserts an entry at `to` with the same content as the entry at `from`, and
+    /// deletes the entry at `from`, in a single [`Transaction`](crate::sync::Transaction). The
+    /// content is not copied, both entries refer to the same hash. Either both entries are
+    /// inserted or none, and they are broadcast to peers together.
+    ///
+    /// Note that this is not an atomic move: peers see an insert and a delete, which are
+    /// resolved by last-writer-wins like any other entry. If another author concurrently
+    /// writes to `from`, or someone writes to `to`, the newer entry wins for each key
+    /// independently.
+    ///
+    /// Fails if the replica or the author does not exist in this store, or if there is no
+    /// entry of `author` at `from`.
+    fn move_key(
+        &self,
+        namespace: NamespaceId,
+        author: AuthorId,
+        from: &[u8],
+        to: &[u8],
+    ) -> Result<()> {
+        let replica = self
+            .open_replica(&namespace)?
+            .ok_or_else(|| anyhow!("replica not found"))?;
+        let author = self
+            .get_author(&author)?
+            .ok_or_else(|| anyhow!("author not found"))?;
+        let entry = self
+            .get_one(namespace, author.id(), from)?
+            .filter(|entry| !entry.entry().record().is_empty())
+            .ok_or_else(|| anyhow!("no entry at the key to move"))?;
+        if from == to {
+            return Ok(());
+        }
+        let mut tx = replica.transaction();
+        tx.put(to, &author, entry.content_hash(), entry.content_len())?;
+        tx.delete(from, &author)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// The number of insert events a subscriber of [`Store::subscribe_all`] may lag behind before
+/// it is disconnected.
+const SUBSCRIBER_CAPACITY: usize = 256;
+
+/// The subscriptions created with [`Store::subscribe_all`].
+///
+/// Stores hand a clone of this to every replica they create, the replicas send their insert
+/// events to all subscribers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InsertSubscribers(
+    #[allow(clippy::type_complexity)]
+    Arc<RwLock<Vec<flume::Sender<(NamespaceId, InsertOrigin, SignedEntry)>>>>,
+);
+
+impl InsertSubscribers {
+    /// Add a subscriber.
+    pub(crate) fn subscribe(&self) -> flume::Receiver<(NamespaceId, InsertOrigin, SignedEntry)> {
+        let (s, r) = flume::bounded(SUBSCRIBER_CAPACITY);
+        self.0.write().push(s);
+        r
+    }
+
+    /// Send an insert event to all subscribers.
+    ///
+    /// This never blocks. Subscribers that went away or whose channel is full are dropped, so
+    /// a lagging subscriber sees its channel disconnected instead of missing events silently.
+    pub(crate) fn send(&self, namespace: NamespaceId, origin: &InsertOrigin, entry: &SignedEntry) {
+        if self.0.read().is_empty() {
+            return;
+        }
+        self.0.write().retain(|sender| {
+            sender
+                .try_send((namespace, origin.clone(), entry.clone()))
+                .is_ok()
+        });
+    }
+}
+
+/// Fail if `author` has written entries to any replica in `store`, or is the default author of
+/// any replica.
+///
+/// This checks one namespace at a time and stops at the first reference found.
+fn ensure_author_unused<S: Store>(store: &S, author: &AuthorId) -> Result<()> {
+    for namespace in store.list_namespaces()? {
+        let namespace = namespace?;
+        if store.default_author(&namespace)? == Some(*author) {
+            anyhow::bail!("author {author} is the default author of document {namespace}");
+        }
+        if store
+            .get_many(namespace, GetFilter::Author(*author))?
+            .next()
+            .is_some()
+        {
+            anyhow::bail!("author {author} has written entries to document {namespace}");
+        }
+    }
+    Ok(())
 }
 
 /// Filter a get query onto a namespace
@@ -127,3 +391,25 @@ impl GetFilter {
         }
     }
 }
+
+/// Iterator over the entries of a replica that changed after a timestamp, returned from
+/// [`Store::diff`].
+#[derive(derive_more::Debug)]
+pub struct DiffIter<'a, S: Store + 'a> {
+    #[debug("GetIter")]
+    inner: S::GetIter<'a>,
+    since: u64,
+}
+
+impl<'a, S: Store + 'a> Iterator for DiffIter<'a, S> {
+    type Item = Result<SignedEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.inner.next()? {
+                Ok(entry) if entry.timestamp() <= self.since => continue,
+                res => return Some(res),
+            }
+        }
+    }
+}
@@ -1,28 +1,45 @@
 //! On disk storage for replicas.
 
-use std::{cmp::Ordering, collections::HashMap, path::Path, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Result;
 use derive_more::From;
 use ed25519_dalek::{SignatureError, VerifyingKey};
 use iroh_bytes::Hash;
 use ouroboros::self_referencing;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use rand_core::CryptoRngCore;
 use redb::{
-    Database, Range as TableRange, ReadOnlyTable, ReadTransaction, ReadableTable, StorageError,
-    TableDefinition,
+    Database, Durability, Range as TableRange, ReadOnlyTable, ReadTransaction, ReadableTable,
+    StorageError, Table, TableDefinition,
 };
 
 use crate::{
-    ranger::{Fingerprint, Range, RangeEntry},
+    ranger::{Checkpoint, Fingerprint, Range, RangeEntry},
     store::Store as _,
     sync::{
-        Author, Entry, EntrySignature, Namespace, Record, RecordIdentifier, Replica, SignedEntry,
+        Author, Capability, Entry, EntrySignature, InsertOrigin, Namespace, Record,
+        RecordIdentifier, Replica, SignedEntry,
     },
-    AuthorId, NamespaceId,
+    AuthorId, GossipSecret, NamespaceId,
 };
 
-use super::{pubkeys::MemPublicKeyStore, PublicKeyStore};
+use super::{pubkeys::MemPublicKeyStore, InsertSubscribers, PublicKeyStore};
+
+mod wal;
+
+pub use wal::WalSync;
+use wal::{Wal, WalOp};
+
+/// Number of records in the write-ahead log after which the database is synced and the log
+/// is truncated.
+const CHECKPOINT_RECORDS: usize = 1024;
 
 /// Manages the replicas and authors for an instance.
 #[derive(Debug, Clone)]
@@ -30,6 +47,8 @@ pub struct Store {
     db: Arc<Database>,
     replicas: Arc<RwLock<HashMap<NamespaceId, Replica<StoreInstance>>>>,
     pubkeys: MemPublicKeyStore,
+    subscribers: InsertSubscribers,
+    wal: Option<Arc<Mutex<Wal>>>,
 }
 
 // Table Definitions
@@ -47,6 +66,40 @@ const AUTHORS_TABLE: TableDefinition<&[u8; 32], &[u8; 32]> = TableDefinition::ne
 const NAMESPACES_TABLE: TableDefinition<&[u8; 32], &[u8; 32]> =
     TableDefinition::new("namespaces-1");
 
+// Read-only namespaces, for which we don't have the secret key
+// Table
+// Key: [u8; 32] # NamespaceId
+// Value: ()
+const READ_ONLY_NAMESPACES_TABLE: TableDefinition<&[u8; 32], ()> =
+    TableDefinition::new("read-only-namespaces-1");
+
+// Gossip secrets of namespaces, see [`GossipSecret`]
+// Table
+// Key: [u8; 32] # NamespaceId
+// Value: [u8; 32] # GossipSecret
+const GOSSIP_SECRETS_TABLE: TableDefinition<&[u8; 32], &[u8; 32]> =
+    TableDefinition::new("gossip-secrets-1");
+
+// Default authors of namespaces
+// Table
+// Key: [u8; 32] # NamespaceId
+// Value: [u8; 32] # AuthorId
+const DEFAULT_AUTHORS_TABLE: TableDefinition<&[u8; 32], &[u8; 32]> =
+    TableDefinition::new("default-authors-1");
+
+// Retentions of namespaces
+// Table
+// Key: [u8; 32] # NamespaceId
+// Value: u64 # retention in microseconds
+const RETENTIONS_TABLE: TableDefinition<&[u8; 32], u64> = TableDefinition::new("retentions-1");
+
+// Sync checkpoints, the ranges not yet reconciled when a sync with a peer was interrupted
+// Table
+// Key: ([u8; 32], [u8; 32]) # (NamespaceId, PeerId)
+// Value: Vec<u8> # postcard encoded Vec<Range<RecordIdentifier>>
+const SYNC_CHECKPOINTS_TABLE: TableDefinition<(&[u8; 32], &[u8; 32]), &[u8]> =
+    TableDefinition::new("sync-checkpoints-1");
+
 // Records
 // Table
 // Key: ([u8; 32], [u8; 32], Vec<u8>) # (NamespaceId, AuthorId, Key)
@@ -58,6 +111,7 @@ type RecordsId<'a> = (&'a [u8; 32], &'a [u8; 32], &'a [u8]);
 type RecordsValue<'a> = (u64, &'a [u8; 64], &'a [u8; 64], u64, &'a [u8; 32]);
 type RecordsRange<'a> = TableRange<'a, RecordsId<'static>, RecordsValue<'static>>;
 type RecordsTable<'a> = ReadOnlyTable<'a, RecordsId<'static>, RecordsValue<'static>>;
+type RecordsTableMut<'db, 'txn> = Table<'db, 'txn, RecordsId<'static>, RecordsValue<'static>>;
 type DbResult<T> = Result<T, StorageError>;
 
 const RECORDS_TABLE: TableDefinition<RecordsId, RecordsValue> = TableDefinition::new("records-1");
@@ -74,7 +128,12 @@ impl Store {
         {
             let _table = write_tx.open_table(RECORDS_TABLE)?;
             let _table = write_tx.open_table(NAMESPACES_TABLE)?;
+            let _table = write_tx.open_table(READ_ONLY_NAMESPACES_TABLE)?;
             let _table = write_tx.open_table(AUTHORS_TABLE)?;
+            let _table = write_tx.open_table(GOSSIP_SECRETS_TABLE)?;
+            let _table = write_tx.open_table(DEFAULT_AUTHORS_TABLE)?;
+            let _table = write_tx.open_table(RETENTIONS_TABLE)?;
+            let _table = write_tx.open_table(SYNC_CHECKPOINTS_TABLE)?;
         }
         write_tx.commit()?;
 
@@ -82,15 +141,116 @@ impl Store {
             db: Arc::new(db),
             replicas: Default::default(),
             pubkeys: Default::default(),
+            subscribers: Default::default(),
+            wal: None,
         })
     }
 
+    /// Create or open a store with a write-ahead log for the records of its replicas.
+    ///
+    /// Inserts and removals of records are appended to the log at `path` with a `.wal`
+    /// extension and synced according to `sync`, before they are committed to the database
+    /// without waiting for it to sync. So a write is in the log before it is visible to
+    /// readers. Writes whose commit fails are removed from the log again. The database is
+    /// synced and the log truncated periodically, and on [`Store::flush`]. Writes in the log
+    /// are replayed when the store is opened, so a crash loses no write that was synced to
+    /// the log.
+    pub fn with_wal(path: impl AsRef<Path>, sync: WalSync) -> Result<Self> {
+        let mut store = Self::new(&path)?;
+        let (mut wal, ops) = Wal::open(wal_path(path.as_ref()), sync)?;
+        if !ops.is_empty() {
+            tracing::debug!("replaying {} writes from the write-ahead log", ops.len());
+            let write_tx = store.db.begin_write()?;
+            {
+                let mut table = write_tx.open_table(RECORDS_TABLE)?;
+                for op in &ops {
+                    apply_op(&mut table, op)?;
+                }
+            }
+            write_tx.commit()?;
+        }
+        wal.truncate()?;
+        let wal = Arc::new(Mutex::new(wal));
+        wal::spawn_flusher(&wal)?;
+        store.wal = Some(wal);
+        Ok(store)
+    }
+
+    /// Sync all writes to the database and truncate the write-ahead log, if there is one.
+    pub fn flush(&self) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            self.checkpoint(&mut wal.lock())?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&self, wal: &mut Wal) -> Result<()> {
+        // a commit with the default durability also persists the preceding commits
+        self.db.begin_write()?.commit()?;
+        wal.truncate()
+    }
+
+    /// Apply `op` to the records table, through the write-ahead log if there is one.
+    fn write_record(&self, op: WalOp) -> Result<Option<SignedEntry>> {
+        let mut res = self.write_records(std::slice::from_ref(&op))?;
+        Ok(res.pop().flatten())
+    }
+
+    /// Apply `ops` to the records table in a single commit, through the write-ahead log if
+    /// there is one.
+    fn write_records(&self, ops: &[WalOp]) -> Result<Vec<Option<SignedEntry>>> {
+        let mut wal = self.wal.as_ref().map(|wal| wal.lock());
+        let mut write_tx = self.db.begin_write()?;
+        if wal.is_some() {
+            // the log makes the write durable, the database is synced at checkpoints
+            write_tx.set_durability(Durability::None);
+        }
+        let res = {
+            let mut table = write_tx.open_table(RECORDS_TABLE)?;
+            ops.iter()
+                .map(|op| apply_op(&mut table, op))
+                .collect::<Result<Vec<_>>>()?
+        };
+        let Some(wal) = wal.as_mut() else {
+            write_tx.commit()?;
+            return Ok(res);
+        };
+        // log the write before it becomes visible, and remove it again if it is not
+        // committed, so that a failed write is not replayed
+        let end = wal.end();
+        if let Err(err) = wal
+            .append_all(ops)
+            .and_then(|()| write_tx.commit().map_err(Into::into))
+        {
+            wal.rollback(end)?;
+            return Err(err);
+        }
+        if wal.len() >= CHECKPOINT_RECORDS {
+            self.checkpoint(wal)?;
+        }
+        Ok(res)
+    }
+
     /// Stores a new namespace
     fn insert_namespace(&self, namespace: Namespace) -> Result<()> {
         let write_tx = self.db.begin_write()?;
         {
             let mut namespace_table = write_tx.open_table(NAMESPACES_TABLE)?;
             namespace_table.insert(namespace.id().as_bytes(), &namespace.to_bytes())?;
+            let mut read_only_table = write_tx.open_table(READ_ONLY_NAMESPACES_TABLE)?;
+            read_only_table.remove(namespace.id().as_bytes())?;
+        }
+        write_tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Stores a new namespace without its secret key
+    fn insert_read_only_namespace(&self, namespace: NamespaceId) -> Result<()> {
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut read_only_table = write_tx.open_table(READ_ONLY_NAMESPACES_TABLE)?;
+            read_only_table.insert(namespace.as_bytes(), ())?;
         }
         write_tx.commit()?;
 
@@ -123,11 +283,19 @@ impl super::Store for Store {
 
         let read_tx = self.db.begin_read()?;
         let namespace_table = read_tx.open_table(NAMESPACES_TABLE)?;
-        let Some(namespace) = namespace_table.get(namespace_id.as_bytes())? else {
+        let read_only_table = read_tx.open_table(READ_ONLY_NAMESPACES_TABLE)?;
+        let capability = if let Some(namespace) = namespace_table.get(namespace_id.as_bytes())? {
+            Capability::Write(Namespace::from_bytes(namespace.value()))
+        } else if read_only_table.get(namespace_id.as_bytes())?.is_some() {
+            Capability::Read(*namespace_id)
+        } else {
             return Ok(None);
         };
-        let namespace = Namespace::from_bytes(namespace.value());
-        let replica = Replica::new(namespace, StoreInstance::new(*namespace_id, self.clone()));
+        let retention = read_tx.open_table(RETENTIONS_TABLE)?;
+        let retention = retention.get(namespace_id.as_bytes())?;
+        let replica = Replica::new(capability, StoreInstance::new(*namespace_id, self.clone()))
+            .with_store_subscribers(self.subscribers.clone());
+        replica.set_retention(retention.map(|retention| Duration::from_micros(retention.value())));
         self.replicas.write().insert(*namespace_id, replica.clone());
         Ok(Some(replica))
     }
@@ -142,13 +310,18 @@ impl super::Store for Store {
         // TODO: avoid collect
         let read_tx = self.db.begin_read()?;
         let namespace_table = read_tx.open_table(NAMESPACES_TABLE)?;
-        let namespaces: Vec<_> = namespace_table
+        let read_only_table = read_tx.open_table(READ_ONLY_NAMESPACES_TABLE)?;
+        let mut namespaces: Vec<_> = namespace_table
             .iter()?
             .map(|res| match res {
                 Ok((_key, value)) => Ok(Namespace::from_bytes(value.value()).id()),
                 Err(err) => Err(err.into()),
             })
             .collect();
+        namespaces.extend(read_only_table.iter()?.map(|res| match res {
+            Ok((key, _value)) => Ok(NamespaceId::from(key.value())),
+            Err(err) => Err(err.into()),
+        }));
         Ok(namespaces.into_iter())
     }
 
@@ -168,6 +341,145 @@ impl super::Store for Store {
         Ok(())
     }
 
+    fn set_gossip_secret(
+        &self,
+        namespace: &NamespaceId,
+        secret: Option<GossipSecret>,
+    ) -> Result<()> {
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut secrets_table = write_tx.open_table(GOSSIP_SECRETS_TABLE)?;
+            match secret {
+                Some(secret) => {
+                    secrets_table.insert(namespace.as_bytes(), &secret.to_bytes())?;
+                }
+                None => {
+                    secrets_table.remove(namespace.as_bytes())?;
+                }
+            }
+        }
+        write_tx.commit()?;
+        Ok(())
+    }
+
+    fn gossip_secret(&self, namespace: &NamespaceId) -> Result<Option<GossipSecret>> {
+        let read_tx = self.db.begin_read()?;
+        let secrets_table = read_tx.open_table(GOSSIP_SECRETS_TABLE)?;
+        let secret = secrets_table.get(namespace.as_bytes())?;
+        Ok(secret.map(|secret| GossipSecret::from_bytes(secret.value())))
+    }
+
+    fn set_default_author(&self, namespace: &NamespaceId, author: Option<AuthorId>) -> Result<()> {
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut authors_table = write_tx.open_table(DEFAULT_AUTHORS_TABLE)?;
+            match author {
+                Some(author) => {
+                    authors_table.insert(namespace.as_bytes(), author.as_bytes())?;
+                }
+                None => {
+                    authors_table.remove(namespace.as_bytes())?;
+                }
+            }
+        }
+        write_tx.commit()?;
+        Ok(())
+    }
+
+    fn default_author(&self, namespace: &NamespaceId) -> Result<Option<AuthorId>> {
+        let read_tx = self.db.begin_read()?;
+        let authors_table = read_tx.open_table(DEFAULT_AUTHORS_TABLE)?;
+        let author = authors_table.get(namespace.as_bytes())?;
+        Ok(author.map(|author| AuthorId::from(author.value())))
+    }
+
+    fn get_or_create_default_author<R: CryptoRngCore + ?Sized>(
+        &self,
+        namespace: &NamespaceId,
+        rng: &mut R,
+    ) -> Result<Author> {
+        let write_tx = self.db.begin_write()?;
+        let author = {
+            let mut default_authors_table = write_tx.open_table(DEFAULT_AUTHORS_TABLE)?;
+            let mut author_table = write_tx.open_table(AUTHORS_TABLE)?;
+            let default = default_authors_table
+                .get(namespace.as_bytes())?
+                .map(|author| AuthorId::from(author.value()));
+            match default {
+                Some(author_id) => {
+                    let author = author_table
+                        .get(author_id.as_bytes())?
+                        .ok_or_else(|| anyhow::anyhow!("default author {author_id} not found"))?;
+                    return Ok(Author::from_bytes(author.value()));
+                }
+                None => {
+                    let author = Author::new(rng);
+                    author_table.insert(author.id().as_bytes(), &author.to_bytes())?;
+                    default_authors_table.insert(namespace.as_bytes(), author.id().as_bytes())?;
+                    author
+                }
+            }
+        };
+        write_tx.commit()?;
+        Ok(author)
+    }
+
+    fn set_retention(&self, namespace: &NamespaceId, retention: Option<Duration>) -> Result<()> {
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut retentions_table = write_tx.open_table(RETENTIONS_TABLE)?;
+            match retention {
+                Some(retention) => {
+                    retentions_table.insert(namespace.as_bytes(), retention.as_micros() as u64)?;
+                }
+                None => {
+                    retentions_table.remove(namespace.as_bytes())?;
+                }
+            }
+        }
+        write_tx.commit()?;
+        if let Some(replica) = self.replicas.read().get(namespace) {
+            replica.set_retention(retention);
+        }
+        Ok(())
+    }
+
+    fn retention(&self, namespace: &NamespaceId) -> Result<Option<Duration>> {
+        let read_tx = self.db.begin_read()?;
+        let retentions_table = read_tx.open_table(RETENTIONS_TABLE)?;
+        let retention = retentions_table.get(namespace.as_bytes())?;
+        Ok(retention.map(|retention| Duration::from_micros(retention.value())))
+    }
+
+    fn remove_author(&self, author: &AuthorId, force: bool) -> Result<()> {
+        if !force {
+            super::ensure_author_unused(self, author)?;
+        }
+        let write_tx = self.db.begin_write()?;
+        {
+            let mut author_table = write_tx.open_table(AUTHORS_TABLE)?;
+            if author_table.remove(author.as_bytes())?.is_none() {
+                anyhow::bail!("author {author} not found");
+            }
+            let mut default_authors_table = write_tx.open_table(DEFAULT_AUTHORS_TABLE)?;
+            let namespaces = default_authors_table
+                .iter()?
+                .filter_map(|res| match res {
+                    Ok((namespace, default)) if default.value() == author.as_bytes() => {
+                        Some(Ok(*namespace.value()))
+                    }
+                    Ok(_) => None,
+                    Err(err) => Some(Err(err)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for namespace in namespaces {
+                default_authors_table.remove(&namespace)?;
+            }
+        }
+        write_tx.commit()?;
+        Ok(())
+    }
+
     fn list_authors(&self) -> Result<Self::AuthorsIter<'_>> {
         // TODO: avoid collect
         let read_tx = self.db.begin_read()?;
@@ -183,11 +495,22 @@ impl super::Store for Store {
         Ok(authors.into_iter())
     }
 
-    fn new_replica(&self, namespace: Namespace) -> Result<Replica<Self::Instance>> {
-        let id = namespace.id();
-        self.insert_namespace(namespace.clone())?;
+    fn new_replica(&self, namespace: impl Into<Capability>) -> Result<Replica<Self::Instance>> {
+        let capability = namespace.into();
+        let id = capability.id();
+        if let Capability::Write(namespace) = &capability {
+            self.insert_namespace(namespace.clone())?;
+        }
+        if let Some(replica) = self.open_replica(&id)? {
+            if let Capability::Write(namespace) = capability {
+                replica.upgrade(namespace);
+            }
+            return Ok(replica);
+        }
 
-        let replica = Replica::new(namespace, StoreInstance::new(id, self.clone()));
+        self.insert_read_only_namespace(id)?;
+        let replica = Replica::new(capability, StoreInstance::new(id, self.clone()))
+            .with_store_subscribers(self.subscribers.clone());
 
         self.replicas.write().insert(id, replica.clone());
         Ok(replica)
@@ -237,6 +560,10 @@ impl super::Store for Store {
     fn content_hashes(&self) -> Result<Self::ContentHashesIter<'_>> {
         ContentHashesIterator::create(&self.db)
     }
+
+    fn subscribe_all(&self) -> flume::Receiver<(NamespaceId, InsertOrigin, SignedEntry)> {
+        self.subscribers.subscribe()
+    }
 }
 
 impl Store {
@@ -345,6 +672,46 @@ impl StoreInstance {
     }
 }
 
+fn wal_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".wal");
+    path.into()
+}
+
+/// Apply a write to the records table, returning the removed entry for removals.
+fn apply_op(table: &mut RecordsTableMut, op: &WalOp) -> Result<Option<SignedEntry>> {
+    match op {
+        WalOp::Put(e) => {
+            let key = (
+                &e.id().namespace().to_bytes(),
+                &e.id().author().to_bytes(),
+                e.id().key(),
+            );
+            let hash = e.content_hash();
+            let value = (
+                e.timestamp(),
+                &e.signature().namespace_signature().to_bytes(),
+                &e.signature().author_signature().to_bytes(),
+                e.content_len(),
+                hash.as_bytes(),
+            );
+            table.insert(key, value)?;
+            Ok(None)
+        }
+        WalOp::Remove(k) => {
+            let key = (&k.namespace().to_bytes(), &k.author().to_bytes(), k.key());
+            let record = table.remove(key)?;
+            Ok(record.map(|record| {
+                let (timestamp, namespace_sig, author_sig, len, hash) = record.value();
+                let record = Record::new(hash.into(), len, timestamp);
+                let entry = Entry::new(k.clone(), record);
+                let entry_signature = EntrySignature::from_parts(namespace_sig, author_sig);
+                SignedEntry::new(entry_signature, entry)
+            }))
+        }
+    }
+}
+
 fn range_start(namespace: &NamespaceId) -> RecordsId {
     (namespace.as_bytes(), &[u8::MIN; 32], &[][..])
 }
@@ -414,25 +781,13 @@ impl crate::ranger::Store<SignedEntry> for StoreInstance {
     }
 
     fn put(&mut self, e: SignedEntry) -> Result<()> {
-        let write_tx = self.store.db.begin_write()?;
-        {
-            let mut record_table = write_tx.open_table(RECORDS_TABLE)?;
-            let key = (
-                &e.id().namespace().to_bytes(),
-                &e.id().author().to_bytes(),
-                e.id().key(),
-            );
-            let hash = e.content_hash();
-            let value = (
-                e.timestamp(),
-                &e.signature().namespace_signature().to_bytes(),
-                &e.signature().author_signature().to_bytes(),
-                e.content_len(),
-                hash.as_bytes(),
-            );
-            record_table.insert(key, value)?;
-        }
-        write_tx.commit()?;
+        self.store.write_record(WalOp::Put(e))?;
+        Ok(())
+    }
+
+    fn put_many(&mut self, entries: Vec<SignedEntry>) -> Result<()> {
+        let ops = entries.into_iter().map(WalOp::Put).collect::<Vec<_>>();
+        self.store.write_records(&ops)?;
         Ok(())
     }
 
@@ -491,21 +846,7 @@ impl crate::ranger::Store<SignedEntry> for StoreInstance {
     }
 
     fn remove(&mut self, k: &RecordIdentifier) -> Result<Option<SignedEntry>> {
-        let write_tx = self.store.db.begin_write()?;
-        let res = {
-            let mut records_table = write_tx.open_table(RECORDS_TABLE)?;
-            let key = (&k.namespace().to_bytes(), &k.author().to_bytes(), k.key());
-            let record = records_table.remove(key)?;
-            record.map(|record| {
-                let (timestamp, namespace_sig, author_sig, len, hash) = record.value();
-                let record = Record::new(hash.into(), len, timestamp);
-                let entry = Entry::new(k.clone(), record);
-                let entry_signature = EntrySignature::from_parts(namespace_sig, author_sig);
-                SignedEntry::new(entry_signature, entry)
-            })
-        };
-        write_tx.commit()?;
-        Ok(res)
+        self.store.write_record(WalOp::Remove(k.clone()))
     }
 
     fn all(&self) -> Result<Self::RangeIterator<'_>> {
@@ -513,6 +854,39 @@ impl crate::ranger::Store<SignedEntry> for StoreInstance {
         let iter2 = RangeIterator::empty(&self.store.db)?;
         Ok(iter.chain(iter2))
     }
+
+    fn sync_checkpoint(&self, peer: &[u8; 32]) -> Result<Checkpoint<RecordIdentifier>> {
+        let read_tx = self.store.db.begin_read()?;
+        let checkpoints_table = read_tx.open_table(SYNC_CHECKPOINTS_TABLE)?;
+        let Some(ranges) = checkpoints_table.get((self.namespace.as_bytes(), peer))? else {
+            return Ok(None);
+        };
+        let ranges = postcard::from_bytes(ranges.value())?;
+        Ok(Some(ranges))
+    }
+
+    fn set_sync_checkpoint(
+        &mut self,
+        peer: &[u8; 32],
+        ranges: Option<&[Range<RecordIdentifier>]>,
+    ) -> Result<()> {
+        let write_tx = self.store.db.begin_write()?;
+        {
+            let mut checkpoints_table = write_tx.open_table(SYNC_CHECKPOINTS_TABLE)?;
+            let key = (self.namespace.as_bytes(), peer);
+            match ranges {
+                Some(ranges) => {
+                    let ranges = postcard::to_stdvec(ranges)?;
+                    checkpoints_table.insert(key, ranges.as_slice())?;
+                }
+                None => {
+                    checkpoints_table.remove(key)?;
+                }
+            }
+        }
+        write_tx.commit()?;
+        Ok(())
+    }
 }
 
 /// Iterator over all content hashes for the fs store.
@@ -761,4 +1135,94 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sync_checkpoint_persisted() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let alice_store = Store::new(dbfile.path())?;
+        let bob_store = crate::store::memory::Store::default();
+        let alice_peer_id = [1u8; 32];
+        let bob_peer_id = [2u8; 32];
+
+        let author = alice_store.new_author(&mut rand::thread_rng())?;
+        let namespace = Namespace::new(&mut rand::thread_rng());
+        let alice = alice_store.new_replica(namespace.clone())?;
+        let bob = bob_store.new_replica(namespace.clone())?;
+        for i in 0..50 {
+            alice.hash_and_insert(format!("alice-{i}"), &author, b"alice")?;
+            bob.hash_and_insert(format!("bob-{i}"), &author, b"bob")?;
+        }
+
+        // Do a single round trip, then interrupt the sync.
+        let msg = alice.sync_initial_message()?;
+        let msg = bob.sync_process_message(msg, alice_peer_id)?.unwrap();
+        alice.sync_process_message(msg, bob_peer_id)?.unwrap();
+        let resume = alice.sync_resume_message(bob_peer_id)?;
+        assert_ne!(resume, alice.sync_initial_message()?);
+        alice_store.close_replica(&namespace.id());
+        drop(alice);
+        drop(alice_store);
+
+        // The checkpoint survives reopening the store.
+        let alice_store = Store::new(dbfile.path())?;
+        let alice = alice_store.open_replica(&namespace.id())?.unwrap();
+        assert_eq!(alice.sync_resume_message(bob_peer_id)?, resume);
+
+        alice.sync_finished(bob_peer_id)?;
+        assert_eq!(
+            alice.sync_resume_message(bob_peer_id)?,
+            alice.sync_initial_message()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("docs.redb");
+        let author = Author::new(&mut rand::thread_rng());
+        let namespace = Namespace::new(&mut rand::thread_rng());
+        let entry = |key: &str| {
+            let id = RecordIdentifier::new(namespace.id(), author.id(), key);
+            let entry = Entry::new(id, Record::current_from_data(key));
+            SignedEntry::from_entry(entry, &namespace, &author)
+        };
+        let keys = |store: &Store| -> Result<Vec<Vec<u8>>> {
+            store
+                .get_all(namespace.id())?
+                .map(|entry| Ok(entry?.key().to_vec()))
+                .collect()
+        };
+
+        // writes left in the log by a crash are replayed, up to a torn record
+        let (mut wal, _) = Wal::open(wal_path(&path), WalSync::Immediate)?;
+        wal.append_all(&[WalOp::Put(entry("a")), WalOp::Put(entry("b"))])?;
+        wal.append_all(&[WalOp::Remove(entry("a").id().clone())])?;
+        drop(wal);
+        let mut data = std::fs::read(wal_path(&path))?;
+        let len = data.len();
+        data.extend_from_within(..len / 3);
+        std::fs::write(wal_path(&path), data)?;
+        let sync = WalSync::Batched {
+            max_records: 2,
+            max_delay: Duration::from_secs(60),
+        };
+        let store = Store::with_wal(&path, sync)?;
+        assert_eq!(keys(&store)?, vec![b"b".to_vec()]);
+        assert_eq!(std::fs::metadata(wal_path(&path))?.len(), 0);
+
+        // writes go through the log until the store is flushed
+        let mut instance = StoreInstance::new(namespace.id(), store.clone());
+        instance.put(entry("c"))?;
+        instance.put(entry("d"))?;
+        instance.remove(entry("b").id())?;
+        assert!(std::fs::metadata(wal_path(&path))?.len() > 0);
+        assert_eq!(keys(&store)?, vec![b"c".to_vec(), b"d".to_vec()]);
+        instance.put_many(vec![entry("e"), entry("f")])?;
+        store.flush()?;
+        assert_eq!(std::fs::metadata(wal_path(&path))?.len(), 0);
+        let expected = ["c", "d", "e", "f"].map(|key| key.as_bytes().to_vec());
+        assert_eq!(keys(&store)?, expected);
+        Ok(())
+    }
 }
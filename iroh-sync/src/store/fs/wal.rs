@@ -0,0 +1,285 @@
+//! Write-ahead log for the records of a [`Store`](super::Store).
+//!
+//! Each log record is framed as the length of the payload (u32 little endian), the first 4
+//! bytes of the blake3 hash of the payload, and the postcard encoded [`WalOp`]. A record that
+//! is truncated or does not match its checksum ends the log, it is the torn tail of a write
+//! that was interrupted by a crash.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::sync::{RecordIdentifier, SignedEntry};
+
+const HEADER_LEN: usize = 8;
+
+/// When the write-ahead log of a [`Store`](super::Store) is synced to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalSync {
+    /// Sync the log after every write, before the write returns.
+    ///
+    /// No acknowledged write is lost on a crash.
+    #[default]
+    Immediate,
+    /// Sync the log once `max_records` writes were appended since the last sync, or at the
+    /// latest `max_delay` after the first write that is not synced yet.
+    ///
+    /// Writes that were appended since the last sync are lost on a crash.
+    Batched {
+        /// Maximum number of writes between syncs.
+        max_records: usize,
+        /// Maximum time between syncs.
+        max_delay: Duration,
+    },
+}
+
+/// The end of a [`Wal`] at some point, to roll back to with [`Wal::rollback`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct WalEnd {
+    len: usize,
+    size: u64,
+}
+
+/// A write to the records table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) enum WalOp {
+    Put(SignedEntry),
+    Remove(RecordIdentifier),
+}
+
+#[derive(Debug)]
+pub(super) struct Wal {
+    path: PathBuf,
+    file: File,
+    sync: WalSync,
+    /// Number of records in the log.
+    len: usize,
+    /// Size of the log in bytes.
+    size: u64,
+    /// Number of records appended since the last sync.
+    unsynced: usize,
+    last_sync: Instant,
+}
+
+impl Wal {
+    /// Opens the log at `path`, returning it together with the writes it contains.
+    pub fn open(path: impl AsRef<Path>, sync: WalSync) -> Result<(Self, Vec<WalOp>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let ops = decode(&data);
+        let wal = Self {
+            path,
+            file,
+            sync,
+            len: ops.len(),
+            size: data.len() as u64,
+            unsynced: 0,
+            last_sync: Instant::now(),
+        };
+        Ok((wal, ops))
+    }
+
+    /// The number of records in the log.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends all `ops` to the log in one write, syncing them according to the [`WalSync`]
+    /// of the log.
+    pub fn append_all(&mut self, ops: &[WalOp]) -> Result<()> {
+        let mut records = Vec::new();
+        for op in ops {
+            let payload = postcard::to_stdvec(op)?;
+            let len = u32::try_from(payload.len())?;
+            records.extend_from_slice(&len.to_le_bytes());
+            records.extend_from_slice(&blake3::hash(&payload).as_bytes()[..4]);
+            records.extend_from_slice(&payload);
+        }
+        self.file.write_all(&records)?;
+        self.size += records.len() as u64;
+        self.len += ops.len();
+        self.unsynced += ops.len();
+        let due = match self.sync {
+            WalSync::Immediate => true,
+            WalSync::Batched {
+                max_records,
+                max_delay,
+            } => self.unsynced >= max_records || self.last_sync.elapsed() >= max_delay,
+        };
+        if due {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// The current end of the log.
+    pub fn end(&self) -> WalEnd {
+        WalEnd {
+            len: self.len,
+            size: self.size,
+        }
+    }
+
+    /// Removes the records that were appended after `end`, e.g. those of a write that failed
+    /// to commit, also if they were only written in part.
+    pub fn rollback(&mut self, end: WalEnd) -> Result<()> {
+        self.file.set_len(end.size)?;
+        self.len = end.len;
+        self.size = end.size;
+        self.sync()
+    }
+
+    /// Syncs the appended records if they are not synced for longer than the `max_delay` of
+    /// a [`WalSync::Batched`] log.
+    fn sync_if_due(&mut self) -> Result<()> {
+        if let WalSync::Batched { max_delay, .. } = self.sync {
+            if self.unsynced > 0 && self.last_sync.elapsed() >= max_delay {
+                self.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Syncs the appended records to disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Removes all records, once they are persisted in the database.
+    pub fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.len = 0;
+        self.size = 0;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        tracing::trace!(path = %self.path.display(), "truncated write-ahead log");
+        Ok(())
+    }
+}
+
+/// Spawns a thread that syncs a [`WalSync::Batched`] log once its writes are due, even if no
+/// further writes are appended.
+///
+/// The thread ends once the log is dropped.
+pub(super) fn spawn_flusher(wal: &Arc<Mutex<Wal>>) -> Result<()> {
+    let WalSync::Batched { max_delay, .. } = wal.lock().sync else {
+        return Ok(());
+    };
+    let wal: Weak<Mutex<Wal>> = Arc::downgrade(wal);
+    std::thread::Builder::new()
+        .name("iroh-sync-wal".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(max_delay);
+            let Some(wal) = wal.upgrade() else {
+                break;
+            };
+            let mut wal = wal.lock();
+            if let Err(err) = wal.sync_if_due() {
+                tracing::warn!(path = %wal.path.display(), "failed to sync write-ahead log: {err:#}");
+            }
+        })?;
+    Ok(())
+}
+
+/// Decodes the records in `data`, up to the first torn or corrupt record.
+fn decode(mut data: &[u8]) -> Vec<WalOp> {
+    let mut ops = Vec::new();
+    while data.len() >= HEADER_LEN {
+        let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+        let checksum = &data[4..HEADER_LEN];
+        let Some(payload) = data[HEADER_LEN..].get(..len) else {
+            break;
+        };
+        if &blake3::hash(payload).as_bytes()[..4] != checksum {
+            break;
+        }
+        let Ok(op) = postcard::from_bytes(payload) else {
+            break;
+        };
+        ops.push(op);
+        data = &data[HEADER_LEN + len..];
+    }
+    if !data.is_empty() {
+        tracing::warn!(
+            "ignoring {} bytes at the end of the write-ahead log",
+            data.len()
+        );
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{Author, Namespace};
+
+    #[test]
+    fn test_batched_sync_timer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sync = WalSync::Batched {
+            max_records: 100,
+            max_delay: Duration::from_millis(100),
+        };
+        let (wal, _) = Wal::open(dir.path().join("docs.wal"), sync)?;
+        let wal = Arc::new(Mutex::new(wal));
+        spawn_flusher(&wal)?;
+        let namespace = Namespace::new(&mut rand::thread_rng());
+        let author = Author::new(&mut rand::thread_rng());
+        let id = RecordIdentifier::new(namespace.id(), author.id(), "a");
+        wal.lock().append_all(&[WalOp::Remove(id)])?;
+        assert_eq!(wal.lock().unsynced, 1);
+
+        // the write is synced without any further writes
+        std::thread::sleep(Duration::from_millis(500));
+        assert_eq!(wal.lock().unsynced, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("docs.wal");
+        let (mut wal, _) = Wal::open(&path, WalSync::Immediate)?;
+        let namespace = Namespace::new(&mut rand::thread_rng());
+        let author = Author::new(&mut rand::thread_rng());
+        let id = |key: &str| RecordIdentifier::new(namespace.id(), author.id(), key);
+        wal.append_all(&[WalOp::Remove(id("a"))])?;
+        let end = wal.end();
+        wal.append_all(&[WalOp::Remove(id("b")), WalOp::Remove(id("c"))])?;
+        assert_eq!(wal.len(), 3);
+
+        // the records after the end are gone, and later records follow the earlier ones
+        wal.rollback(end)?;
+        assert_eq!(wal.len(), 1);
+        wal.append_all(&[WalOp::Remove(id("d"))])?;
+        drop(wal);
+        let (_, ops) = Wal::open(&path, WalSync::Immediate)?;
+        let keys = ops
+            .iter()
+            .map(|op| match op {
+                WalOp::Remove(id) => id.key().to_vec(),
+                WalOp::Put(_) => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [b"a".to_vec(), b"d".to_vec()]);
+        Ok(())
+    }
+}
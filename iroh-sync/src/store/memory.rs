@@ -4,20 +4,22 @@ use std::{
     collections::{BTreeMap, HashMap},
     convert::Infallible,
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::Result;
 use ed25519_dalek::{SignatureError, VerifyingKey};
 use iroh_bytes::Hash;
 use parking_lot::{RwLock, RwLockReadGuard};
+use rand_core::CryptoRngCore;
 
 use crate::{
-    ranger::{Fingerprint, Range, RangeEntry},
-    sync::{Author, Namespace, RecordIdentifier, Replica, SignedEntry},
-    AuthorId, NamespaceId,
+    ranger::{Checkpoint, Fingerprint, Range, RangeEntry},
+    sync::{Author, Capability, InsertOrigin, RecordIdentifier, Replica, SignedEntry},
+    AuthorId, GossipSecret, NamespaceId,
 };
 
-use super::{pubkeys::MemPublicKeyStore, PublicKeyStore};
+use super::{pubkeys::MemPublicKeyStore, InsertSubscribers, PublicKeyStore};
 
 /// Manages the replicas and authors for an instance.
 #[derive(Debug, Clone, Default)]
@@ -27,12 +29,18 @@ pub struct Store {
     /// Stores records by namespace -> identifier + timestamp
     replica_records: Arc<RwLock<ReplicaRecordsOwned>>,
     pubkeys: MemPublicKeyStore,
+    subscribers: InsertSubscribers,
+    gossip_secrets: Arc<RwLock<HashMap<NamespaceId, GossipSecret>>>,
+    default_authors: Arc<RwLock<HashMap<NamespaceId, AuthorId>>>,
+    retentions: Arc<RwLock<HashMap<NamespaceId, Duration>>>,
+    sync_checkpoints: Arc<RwLock<SyncCheckpoints>>,
 }
 
 type Rid = (AuthorId, Vec<u8>);
 type Rvalue = SignedEntry;
 type RecordMap = BTreeMap<Rid, Rvalue>;
 type ReplicaRecordsOwned = BTreeMap<NamespaceId, RecordMap>;
+type SyncCheckpoints = HashMap<(NamespaceId, [u8; 32]), Vec<Range<RecordIdentifier>>>;
 
 impl super::Store for Store {
     type Instance = ReplicaStoreInstance;
@@ -74,6 +82,88 @@ impl super::Store for Store {
         Ok(())
     }
 
+    fn set_gossip_secret(
+        &self,
+        namespace: &NamespaceId,
+        secret: Option<GossipSecret>,
+    ) -> Result<()> {
+        let mut secrets = self.gossip_secrets.write();
+        match secret {
+            Some(secret) => secrets.insert(*namespace, secret),
+            None => secrets.remove(namespace),
+        };
+        Ok(())
+    }
+
+    fn gossip_secret(&self, namespace: &NamespaceId) -> Result<Option<GossipSecret>> {
+        Ok(self.gossip_secrets.read().get(namespace).copied())
+    }
+
+    fn set_default_author(&self, namespace: &NamespaceId, author: Option<AuthorId>) -> Result<()> {
+        let mut authors = self.default_authors.write();
+        match author {
+            Some(author) => authors.insert(*namespace, author),
+            None => authors.remove(namespace),
+        };
+        Ok(())
+    }
+
+    fn default_author(&self, namespace: &NamespaceId) -> Result<Option<AuthorId>> {
+        Ok(self.default_authors.read().get(namespace).copied())
+    }
+
+    fn get_or_create_default_author<R: CryptoRngCore + ?Sized>(
+        &self,
+        namespace: &NamespaceId,
+        rng: &mut R,
+    ) -> Result<Author> {
+        // lock the default authors first, like `remove_author` does
+        let mut default_authors = self.default_authors.write();
+        let mut authors = self.authors.write();
+        match default_authors.get(namespace) {
+            Some(author_id) => authors
+                .get(author_id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("default author {author_id} not found")),
+            None => {
+                let author = Author::new(rng);
+                authors.insert(author.id(), author.clone());
+                default_authors.insert(*namespace, author.id());
+                Ok(author)
+            }
+        }
+    }
+
+    fn set_retention(&self, namespace: &NamespaceId, retention: Option<Duration>) -> Result<()> {
+        // lock the replicas first, like `new_replica` does
+        let replicas = self.replicas.read();
+        let mut retentions = self.retentions.write();
+        match retention {
+            Some(retention) => retentions.insert(*namespace, retention),
+            None => retentions.remove(namespace),
+        };
+        if let Some(replica) = replicas.get(namespace) {
+            replica.set_retention(retention);
+        }
+        Ok(())
+    }
+
+    fn retention(&self, namespace: &NamespaceId) -> Result<Option<Duration>> {
+        Ok(self.retentions.read().get(namespace).copied())
+    }
+
+    fn remove_author(&self, author: &AuthorId, force: bool) -> Result<()> {
+        if !force {
+            super::ensure_author_unused(self, author)?;
+        }
+        let mut default_authors = self.default_authors.write();
+        if self.authors.write().remove(author).is_none() {
+            anyhow::bail!("author {author} not found");
+        }
+        default_authors.retain(|_namespace, default| default != author);
+        Ok(())
+    }
+
     fn list_authors(&self) -> Result<Self::AuthorsIter<'_>> {
         // TODO: avoid collect?
         Ok(self
@@ -86,12 +176,23 @@ impl super::Store for Store {
             .into_iter())
     }
 
-    fn new_replica(&self, namespace: Namespace) -> Result<Replica<ReplicaStoreInstance>> {
-        let id = namespace.id();
-        let replica = Replica::new(namespace, ReplicaStoreInstance::new(id, self.clone()));
-        self.replicas
-            .write()
-            .insert(replica.namespace(), replica.clone());
+    fn new_replica(
+        &self,
+        namespace: impl Into<Capability>,
+    ) -> Result<Replica<ReplicaStoreInstance>> {
+        let capability = namespace.into();
+        let id = capability.id();
+        let mut replicas = self.replicas.write();
+        if let Some(existing) = replicas.get(&id) {
+            if let Capability::Write(namespace) = capability {
+                existing.upgrade(namespace);
+            }
+            return Ok(existing.clone());
+        }
+        let replica = Replica::new(capability, ReplicaStoreInstance::new(id, self.clone()))
+            .with_store_subscribers(self.subscribers.clone());
+        replica.set_retention(self.retentions.read().get(&id).copied());
+        replicas.insert(id, replica.clone());
         Ok(replica)
     }
 
@@ -135,6 +236,10 @@ impl super::Store for Store {
             record_i: 0,
         })
     }
+
+    fn subscribe_all(&self) -> flume::Receiver<(NamespaceId, InsertOrigin, SignedEntry)> {
+        self.subscribers.subscribe()
+    }
 }
 
 impl Store {
@@ -472,6 +577,27 @@ impl crate::ranger::Store<SignedEntry> for ReplicaStoreInstance {
             range: None,
         })
     }
+
+    fn sync_checkpoint(
+        &self,
+        peer: &[u8; 32],
+    ) -> Result<Checkpoint<RecordIdentifier>, Self::Error> {
+        let checkpoints = self.store.sync_checkpoints.read();
+        Ok(checkpoints.get(&(self.namespace, *peer)).cloned())
+    }
+
+    fn set_sync_checkpoint(
+        &mut self,
+        peer: &[u8; 32],
+        ranges: Option<&[Range<RecordIdentifier>]>,
+    ) -> Result<(), Self::Error> {
+        let mut checkpoints = self.store.sync_checkpoints.write();
+        match ranges {
+            Some(ranges) => checkpoints.insert((self.namespace, *peer), ranges.to_vec()),
+            None => checkpoints.remove(&(self.namespace, *peer)),
+        };
+        Ok(())
+    }
 }
 
 /// Range iterator for a [`ReplicaStoreInstance`]
@@ -7,6 +7,7 @@
 // This is going to change!
 
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::Debug,
     sync::Arc,
     time::{Duration, SystemTime},
@@ -17,18 +18,19 @@ use crate::metrics::Metrics;
 use bytes::{Bytes, BytesMut};
 use derive_more::Deref;
 #[cfg(feature = "metrics")]
-use iroh_metrics::{inc, inc_by};
+use iroh_metrics::{inc, inc_by, observe};
 
 use parking_lot::RwLock;
 
-use ed25519_dalek::{Signature, SignatureError};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use ed25519_dalek::{Signature, SignatureError, VerifyingKey};
 use iroh_bytes::Hash;
 use serde::{Deserialize, Serialize};
 
 use crate::store;
 use crate::{
     ranger::{self, Fingerprint, Peer, RangeEntry, RangeKey},
-    store::PublicKeyStore,
+    store::{InsertSubscribers, PublicKeyStore},
 };
 
 pub use crate::keys::*;
@@ -51,6 +53,11 @@ pub const MAX_TIMESTAMP_FUTURE_SHIFT: u64 = 10 * 60 * Duration::from_secs(1).as_
 pub enum InsertOrigin {
     /// The entry was inserted locally.
     Local,
+    /// The entry was inserted locally, as part of a [`Transaction`].
+    Transaction {
+        /// How many entries of the same transaction follow this one.
+        remaining: usize,
+    },
     /// The entry was received from the remote peer identified by [`PeerIdBytes`].
     Sync {
         /// The peer from which we received this entry.
@@ -60,6 +67,13 @@ pub enum InsertOrigin {
     },
 }
 
+impl InsertOrigin {
+    /// Whether the entry was inserted locally, either on its own or as part of a transaction.
+    pub fn is_local(&self) -> bool {
+        matches!(self, Self::Local | Self::Transaction { .. })
+    }
+}
+
 /// Whether the content status is available on a node.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ContentStatus {
@@ -71,12 +85,57 @@ pub enum ContentStatus {
     Missing,
 }
 
+/// The access a [`Replica`] has to its namespace.
+///
+/// Entries are signed with the namespace key, so whoever holds the [`Namespace`] secret key can
+/// create new entries in the namespace. The [`NamespaceId`] is the public part of that key, it
+/// is enough to verify and store entries received from others, but not to create new ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Capability {
+    /// Write access, holds the [`Namespace`] secret key.
+    Write(Namespace),
+    /// Read-only access, holds only the [`NamespaceId`].
+    Read(NamespaceId),
+}
+
+impl Capability {
+    /// Get the namespace identifier.
+    pub fn id(&self) -> NamespaceId {
+        match self {
+            Capability::Write(namespace) => namespace.id(),
+            Capability::Read(id) => *id,
+        }
+    }
+
+    /// Get the namespace secret key, if this is a write capability.
+    pub fn secret_key(&self) -> Option<&Namespace> {
+        match self {
+            Capability::Write(namespace) => Some(namespace),
+            Capability::Read(_) => None,
+        }
+    }
+}
+
+impl From<Namespace> for Capability {
+    fn from(namespace: Namespace) -> Self {
+        Capability::Write(namespace)
+    }
+}
+
+impl From<NamespaceId> for Capability {
+    fn from(id: NamespaceId) -> Self {
+        Capability::Read(id)
+    }
+}
+
 /// Local representation of a mutable, synchronizable key-value store.
 #[derive(derive_more::Debug, Clone)]
 pub struct Replica<S: ranger::Store<SignedEntry> + PublicKeyStore> {
     inner: Arc<RwLock<InnerReplica<S>>>,
     #[allow(clippy::type_complexity)]
     on_insert_sender: Arc<RwLock<Option<flume::Sender<(InsertOrigin, SignedEntry)>>>>,
+    /// Subscriptions to the inserts of all replicas of the store this replica belongs to.
+    store_subscribers: InsertSubscribers,
 
     #[allow(clippy::type_complexity)]
     #[debug("ContentStatusCallback")]
@@ -86,8 +145,10 @@ pub struct Replica<S: ranger::Store<SignedEntry> + PublicKeyStore> {
 
 #[derive(derive_more::Debug)]
 struct InnerReplica<S: ranger::Store<SignedEntry> + PublicKeyStore> {
-    namespace: Namespace,
+    capability: Capability,
     peer: Peer<SignedEntry, S>,
+    /// Entries older than this are rejected, see [`store::Store::set_retention`].
+    retention: Option<Duration>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -98,18 +159,29 @@ struct ReplicaData {
 
 impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
     /// Create a new replica.
-    // TODO: make read only replicas possible
-    pub fn new(namespace: Namespace, store: S) -> Self {
+    ///
+    /// Passing a [`NamespaceId`] instead of a [`Namespace`] creates a read-only replica, which
+    /// accepts entries from peers but rejects local inserts.
+    pub fn new(capability: impl Into<Capability>, store: S) -> Self {
         Replica {
             inner: Arc::new(RwLock::new(InnerReplica {
-                namespace,
+                capability: capability.into(),
                 peer: Peer::from_store(store),
+                retention: None,
             })),
             on_insert_sender: Arc::new(RwLock::new(None)),
+            store_subscribers: Default::default(),
             content_status_cb: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Send insert events also to the subscribers of the store, see
+    /// [`store::Store::subscribe_all`].
+    pub(crate) fn with_store_subscribers(mut self, subscribers: InsertSubscribers) -> Self {
+        self.store_subscribers = subscribers;
+        self
+    }
+
     /// Subscribe to insert events.
     ///
     /// Only one subscription can be active at a time. If a previous subscription was created, this
@@ -159,7 +231,8 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
     /// The entry will by signed by the provided `author`.
     /// The `len` must be the byte length of the data identified by `hash`.
     ///
-    /// Returns an error either if the entry failed to validate or if a store operation failed.
+    /// Returns an error if the replica is read-only, if the entry failed to validate or if a
+    /// store operation failed.
     pub fn insert(
         &self,
         key: impl AsRef<[u8]>,
@@ -170,7 +243,11 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
         let id = RecordIdentifier::new(self.namespace(), author.id(), key);
         let record = Record::new_current(hash, len);
         let entry = Entry::new(id, record);
-        let signed_entry = entry.sign(&self.inner.read().namespace, author);
+        let signed_entry = {
+            let inner = self.inner.read();
+            let namespace = inner.capability.secret_key().ok_or(InsertError::ReadOnly)?;
+            entry.sign(namespace, author)
+        };
         self.insert_entry(signed_entry, InsertOrigin::Local)
     }
 
@@ -200,18 +277,24 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
         #[cfg(feature = "metrics")]
         let len = entry.content_len();
 
+        let now = system_time_now();
         let mut inner = self.inner.write();
         let store = inner.peer.store();
+        let verify_signature = !origin.is_local();
+        let retention = inner.retention;
         validate_entry(
-            system_time_now(),
+            now,
             store,
             expected_namespace,
             &entry,
-            &origin,
+            verify_signature,
+            retention,
         )?;
         inner.peer.put(entry.clone()).map_err(InsertError::Store)?;
         drop(inner);
 
+        self.store_subscribers
+            .send(expected_namespace, &origin, &entry);
         if let Some(sender) = self.on_insert_sender.read().as_ref() {
             sender.send((origin.clone(), entry)).ok();
         }
@@ -219,7 +302,7 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
         #[cfg(feature = "metrics")]
         {
             match origin {
-                InsertOrigin::Local => {
+                InsertOrigin::Local | InsertOrigin::Transaction { .. } => {
                     inc!(Metrics, new_entries_local);
                     inc_by!(Metrics, new_entries_local_size, len);
                 }
@@ -237,22 +320,136 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
     ///
     /// This does not store the content, just the record of it.
     /// Returns the calculated hash.
+    ///
+    /// Like [`Self::insert`], this fails with [`InsertError::ReadOnly`] if the replica is
+    /// read-only.
     pub fn hash_and_insert(
         &self,
         key: impl AsRef<[u8]>,
         author: &Author,
         data: impl AsRef<[u8]>,
-    ) -> anyhow::Result<Hash> {
+    ) -> Result<Hash, InsertError<S>> {
         let len = data.as_ref().len() as u64;
         let hash = Hash::new(data);
         self.insert(key, author, hash, len)?;
         Ok(hash)
     }
 
+    /// Delete the entry of `author` at `key`.
+    ///
+    /// This inserts an empty entry, see [`Record::is_empty`], which replaces older entries at
+    /// the key when synced to other peers.
+    pub fn delete(&self, key: impl AsRef<[u8]>, author: &Author) -> Result<(), InsertError<S>> {
+        self.insert(key, author, Hash::new([]), 0)
+    }
+
+    /// Start a [`Transaction`] of local puts and deletes, which are applied as a unit.
+    ///
+    /// Nothing is inserted until [`Transaction::commit`] is called.
+    pub fn transaction(&self) -> Transaction<'_, S> {
+        Transaction {
+            replica: self,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Remove the entry with `id` from this replica, without replacing it with an empty entry.
+    ///
+    /// Unlike [`Self::delete`], this is not propagated to other peers, and the entry comes back
+    /// if it is synced again from a peer that still has it.
+    pub fn remove(&self, id: &RecordIdentifier) -> Result<Option<SignedEntry>, S::Error> {
+        self.inner.write().peer.remove(id)
+    }
+
+    /// Preview which changes inserting the `incoming` entries, e.g. from a sync, would make,
+    /// without changing the replica.
+    ///
+    /// Each entry is validated like an entry received from a peer. Of several incoming entries
+    /// for the same author and key, only the newest one is applied.
+    pub fn preview_merge(&self, incoming: &[SignedEntry]) -> Result<MergePreview, S::Error> {
+        let now = system_time_now();
+        let namespace = self.namespace();
+        let inner = self.inner.read();
+        let store = inner.peer.store();
+
+        let mut preview = MergePreview::default();
+        let mut winners: BTreeMap<RecordIdentifier, (SignedEntry, Option<SignedEntry>)> =
+            BTreeMap::new();
+        for entry in incoming {
+            if let Err(reason) = validate_entry(now, store, namespace, entry, true, inner.retention)
+            {
+                preview.ignored.push((entry.clone(), reason));
+                continue;
+            }
+            match winners.get_mut(entry.id()) {
+                Some((winner, _)) if winner.timestamp() >= entry.timestamp() => {
+                    let reason = ValidationFailure::OlderThanExisting;
+                    preview.ignored.push((entry.clone(), reason));
+                }
+                Some((winner, _)) => {
+                    let older = std::mem::replace(winner, entry.clone());
+                    let reason = ValidationFailure::OlderThanExisting;
+                    preview.ignored.push((older, reason));
+                }
+                None => {
+                    let existing = store.get(entry.id())?;
+                    winners.insert(entry.id().clone(), (entry.clone(), existing));
+                }
+            }
+        }
+
+        // The latest local entry of each key that an applied entry is written to.
+        let mut latest: HashMap<Vec<u8>, Option<SignedEntry>> =
+            winners.keys().map(|id| (id.key().to_vec(), None)).collect();
+        for entry in store.all()? {
+            let entry = entry?;
+            if let Some(latest) = latest.get_mut(entry.key()) {
+                if latest
+                    .as_ref()
+                    .map_or(true, |latest| latest.timestamp() < entry.timestamp())
+                {
+                    *latest = Some(entry);
+                }
+            }
+        }
+        drop(inner);
+
+        let mut changed: BTreeMap<Vec<u8>, SignedEntry> = BTreeMap::new();
+        for (_id, (entry, existing)) in winners {
+            let previous = latest.get(entry.key()).and_then(Option::as_ref);
+            let newer = match (changed.get(entry.key()), previous) {
+                (Some(change), _) => change.timestamp() < entry.timestamp(),
+                (None, Some(previous)) => previous.timestamp() < entry.timestamp(),
+                (None, None) => true,
+            };
+            if newer {
+                changed.insert(entry.key().to_vec(), entry.clone());
+            }
+            match existing {
+                Some(_) => preview.updated.push(entry),
+                None => preview.added.push(entry),
+            }
+        }
+        for (key, entry) in changed {
+            let previous = latest.remove(&key).flatten();
+            let unchanged = matches!(&previous, Some(previous)
+                if previous.content_hash() == entry.content_hash()
+                    && previous.content_len() == entry.content_len());
+            if !unchanged {
+                preview.changed_keys.push(KeyChange {
+                    key,
+                    previous,
+                    entry,
+                });
+            }
+        }
+        Ok(preview)
+    }
+
     /// Get the identifier for an entry in this replica.
     pub fn id(&self, key: impl AsRef<[u8]>, author: &Author) -> RecordIdentifier {
         let inner = self.inner.read();
-        RecordIdentifier::new(inner.namespace.id(), author.id(), key)
+        RecordIdentifier::new(inner.capability.id(), author.id(), key)
     }
 
     /// Create the initial message for the set reconciliation flow with a remote peer.
@@ -260,9 +457,41 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
         self.inner.read().peer.initial_message()
     }
 
+    /// Create the initial message for the set reconciliation flow with `peer`.
+    ///
+    /// If a previous sync with `peer` was interrupted, the message only covers the ranges that
+    /// were not reconciled at that point, so the resumed sync skips the ranges both sides
+    /// already agreed on. Entries inserted into those ranges after the interruption are not
+    /// picked up by the resumed sync, but by gossip or the next full sync.
+    ///
+    /// Checkpoints are kept in the store, see [`ranger::Store::sync_checkpoint`].
+    pub fn sync_resume_message(
+        &self,
+        peer: PeerIdBytes,
+    ) -> Result<crate::ranger::Message<SignedEntry>, S::Error> {
+        let inner = self.inner.read();
+        let ranges = inner.peer.store().sync_checkpoint(&peer)?;
+        inner
+            .peer
+            .initial_message_for_ranges(ranges.as_deref().unwrap_or_default())
+    }
+
+    /// Mark the sync with `peer` as completed, discarding its checkpoint.
+    pub fn sync_finished(&self, peer: PeerIdBytes) -> Result<(), S::Error> {
+        self.inner
+            .write()
+            .peer
+            .store_mut()
+            .set_sync_checkpoint(&peer, None)
+    }
+
     /// Process a set reconciliation message from a remote peer.
     ///
     /// Returns the next message to be sent to the peer, if any.
+    ///
+    /// The ranges of the returned message are kept as a checkpoint until the sync with the
+    /// peer finishes, so that an interrupted sync can be resumed with
+    /// [`Self::sync_resume_message`]. The checkpoint is only written if its ranges changed.
     pub fn sync_process_message(
         &self,
         message: crate::ranger::Message<SignedEntry>,
@@ -270,14 +499,48 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
     ) -> Result<Option<crate::ranger::Message<SignedEntry>>, S::Error> {
         let expected_namespace = self.namespace();
         let now = system_time_now();
-        let reply = self.inner.write().peer.process_message(
+        let mut inner = self.inner.write();
+        // verify the signatures of all incoming entries in one batch, which is a lot faster
+        // than verifying them one by one.
+        let entries = message
+            .parts()
+            .iter()
+            .filter_map(|part| part.values())
+            .flatten()
+            .map(|(entry, _)| entry)
+            .collect::<Vec<_>>();
+        let verified = SignedEntry::verify_batch(&entries, inner.peer.store());
+        let verified = entries
+            .into_iter()
+            .zip(verified)
+            .filter(|(_, res)| res.is_ok())
+            .map(|(entry, _)| (entry.id().clone(), entry.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let retention = inner.retention;
+        let reply = inner.peer.process_message(
             message,
             |store, entry, content_status| {
                 let origin = InsertOrigin::Sync {
                     from: from_peer,
                     content_status,
                 };
-                if validate_entry(now, store, expected_namespace, entry, &origin).is_ok() {
+                // entries that failed the batch were already found to be invalid,
+                // they will fail again here.
+                let verify_signature = verified.get(entry.id()) != Some(entry);
+                if validate_entry(
+                    now,
+                    store,
+                    expected_namespace,
+                    entry,
+                    verify_signature,
+                    retention,
+                )
+                .is_ok()
+                {
+                    #[cfg(feature = "metrics")]
+                    record_propagation(now, entry.timestamp());
+                    self.store_subscribers
+                        .send(expected_namespace, &origin, entry);
                     if let Some(sender) = self.on_insert_sender.read().as_ref() {
                         sender.send((origin, entry.clone())).ok();
                     }
@@ -294,26 +557,75 @@ impl<S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Replica<S> {
                 }
             },
         )?;
+        let ranges = reply
+            .as_ref()
+            .map(|reply| reply.ranges().cloned().collect::<Vec<_>>());
+        // persistent stores commit a transaction per checkpoint, so only write it on changes
+        let store = inner.peer.store_mut();
+        if store.sync_checkpoint(&from_peer)? != ranges {
+            store.set_sync_checkpoint(&from_peer, ranges.as_deref())?;
+        }
 
         Ok(reply)
     }
 
     /// Get the namespace identifier for this [`Replica`].
     pub fn namespace(&self) -> NamespaceId {
-        self.inner.read().namespace.id()
+        self.inner.read().capability.id()
+    }
+
+    /// Get the [`Capability`] of this replica.
+    pub fn capability(&self) -> Capability {
+        self.inner.read().capability.clone()
+    }
+
+    /// Returns `true` if this replica only has read access to its namespace.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.inner.read().capability, Capability::Read(_))
+    }
+
+    /// Returns `true` if this replica holds the [`Namespace`] secret key and can sign new
+    /// entries.
+    ///
+    /// Inserting into a replica that is not writable fails with [`InsertError::ReadOnly`].
+    pub fn is_writable(&self) -> bool {
+        !self.is_read_only()
+    }
+
+    /// Reject entries older than `retention` from now on, or accept all entries with `None`.
+    pub(crate) fn set_retention(&self, retention: Option<Duration>) {
+        self.inner.write().retention = retention;
+    }
+
+    /// Upgrade this replica to write access by handing it the [`Namespace`] secret key.
+    ///
+    /// The replica keeps its state and subscriptions. Does nothing if `namespace` belongs to a
+    /// different namespace.
+    pub(crate) fn upgrade(&self, namespace: Namespace) {
+        let mut inner = self.inner.write();
+        if inner.capability.id() == namespace.id() {
+            inner.capability = Capability::Write(namespace);
+        }
     }
 
     /// Get the byte represenation of the [`Namespace`] key for this replica.
+    ///
+    /// Returns `None` if the replica is read-only.
     // TODO: Why return [u8; 32] and not `Namespace` here?
-    pub fn secret_key(&self) -> [u8; 32] {
-        self.inner.read().namespace.to_bytes()
+    pub fn secret_key(&self) -> Option<[u8; 32]> {
+        self.inner
+            .read()
+            .capability
+            .secret_key()
+            .map(Namespace::to_bytes)
     }
 }
 
 /// Validate a [`SignedEntry`] if it's fit to be inserted.
 ///
 /// This validates that
-/// * the entry's author and namespace signatures are correct
+/// * the entry's author and namespace signatures are correct, unless `verify_signature` is
+///   false because they were verified already
 /// * the entry's namespace matches the current replica
 /// * the entry's timestamp is not more than 10 minutes in the future of our system time
 /// * the entry is newer than an existing entry for the same key and author, if such exists.
@@ -322,7 +634,8 @@ fn validate_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
     store: &S,
     expected_namespace: NamespaceId,
     entry: &SignedEntry,
-    origin: &InsertOrigin,
+    verify_signature: bool,
+    retention: Option<Duration>,
 ) -> Result<(), ValidationFailure> {
     // Verify the namespace
     if entry.namespace() != expected_namespace {
@@ -330,7 +643,7 @@ fn validate_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
     }
 
     // Verify signature for non-local entries.
-    if !matches!(origin, InsertOrigin::Local) && entry.verify(store).is_err() {
+    if verify_signature && entry.verify(store).is_err() {
         return Err(ValidationFailure::BadSignature);
     }
 
@@ -339,6 +652,13 @@ fn validate_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
         return Err(ValidationFailure::TooFarInTheFuture);
     }
 
+    // Verify that the entry has not expired, otherwise pruned entries come back on sync.
+    if let Some(retention) = retention {
+        if entry.timestamp() < now.saturating_sub(retention.as_micros() as u64) {
+            return Err(ValidationFailure::Expired);
+        }
+    }
+
     // If an existing entry exists, make sure it's older than the new entry.
     let existing = store.get(entry.id());
     if let Ok(Some(existing)) = existing {
@@ -349,12 +669,167 @@ fn validate_entry<S: ranger::Store<SignedEntry> + PublicKeyStore>(
     Ok(())
 }
 
+/// A set of local puts and deletes that is applied to a [`Replica`] as a unit, see
+/// [`Replica::transaction`].
+///
+/// The entries are signed when they are added, but only inserted into the replica on
+/// [`Self::commit`]. Dropping the transaction, or calling [`Self::rollback`], discards them.
+///
+/// This gives local all-or-nothing semantics only: peers receive the entries like any other
+/// entries, and resolve each of them by last-writer-wins on their own.
+#[derive(derive_more::Debug)]
+pub struct Transaction<'a, S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> {
+    #[debug("Replica")]
+    replica: &'a Replica<S>,
+    entries: Vec<SignedEntry>,
+}
+
+impl<'a, S: ranger::Store<SignedEntry> + PublicKeyStore + 'static> Transaction<'a, S> {
+    /// Add a new record at the given key, see [`Replica::insert`].
+    ///
+    /// An earlier put or delete of the same `author` and `key` in this transaction is replaced.
+    ///
+    /// Fails with [`InsertError::ReadOnly`] if the replica is read-only.
+    pub fn put(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        author: &Author,
+        hash: Hash,
+        len: u64,
+    ) -> Result<(), InsertError<S>> {
+        let inner = self.replica.inner.read();
+        let namespace = inner.capability.secret_key().ok_or(InsertError::ReadOnly)?;
+        let id = RecordIdentifier::new(namespace.id(), author.id(), key);
+        let entry = Entry::new(id, Record::new_current(hash, len)).sign(namespace, author);
+        drop(inner);
+        self.entries.retain(|e| e.id() != entry.id());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Delete the entry of `author` at `key`, see [`Replica::delete`].
+    pub fn delete(&mut self, key: impl AsRef<[u8]>, author: &Author) -> Result<(), InsertError<S>> {
+        self.put(key, author, Hash::new([]), 0)
+    }
+
+    /// The number of entries in this transaction.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether this transaction has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Insert all entries of this transaction into the replica.
+    ///
+    /// All entries are validated before any of them is inserted, and the replica is locked
+    /// while they are inserted. If any entry fails to validate, e.g. because a newer entry
+    /// was inserted for its key and author in the meantime, none are inserted. The entries
+    /// are written to the store at once.
+    ///
+    /// The insert events of the entries are emitted after all of them were inserted, with
+    /// [`InsertOrigin::Transaction`], so that they can be broadcast to peers together.
+    ///
+    /// Returns the number of inserted entries.
+    pub fn commit(self) -> Result<usize, InsertError<S>> {
+        let Transaction { replica, entries } = self;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+        let expected_namespace = replica.namespace();
+        let now = system_time_now();
+        let mut inner = replica.inner.write();
+        let retention = inner.retention;
+        for entry in &entries {
+            validate_entry(
+                now,
+                inner.peer.store(),
+                expected_namespace,
+                entry,
+                false,
+                retention,
+            )?;
+        }
+        inner
+            .peer
+            .put_many(entries.clone())
+            .map_err(InsertError::Store)?;
+        drop(inner);
+
+        let count = entries.len();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let origin = InsertOrigin::Transaction {
+                remaining: count - i - 1,
+            };
+            #[cfg(feature = "metrics")]
+            {
+                inc!(Metrics, new_entries_local);
+                inc_by!(Metrics, new_entries_local_size, entry.content_len());
+            }
+            replica
+                .store_subscribers
+                .send(expected_namespace, &origin, &entry);
+            if let Some(sender) = replica.on_insert_sender.read().as_ref() {
+                sender.send((origin, entry)).ok();
+            }
+        }
+        Ok(count)
+    }
+
+    /// Discard all entries of this transaction.
+    ///
+    /// This is the same as dropping the transaction.
+    pub fn rollback(self) {}
+}
+
+/// Changes that inserting a set of entries into a [`Replica`] would make, see
+/// [`Replica::preview_merge`].
+#[derive(Debug, Default)]
+pub struct MergePreview {
+    /// Entries that would be inserted, because there is no entry for their author and key yet.
+    pub added: Vec<SignedEntry>,
+    /// Entries that would replace an older entry of the same author and key.
+    pub updated: Vec<SignedEntry>,
+    /// Entries that would be ignored, with the reason why.
+    pub ignored: Vec<(SignedEntry, ValidationFailure)>,
+    /// Keys whose latest entry of all authors would change to a different content.
+    pub changed_keys: Vec<KeyChange>,
+}
+
+impl MergePreview {
+    /// The entries that would be applied, i.e. both the added and updated entries.
+    pub fn applied(&self) -> impl Iterator<Item = &SignedEntry> {
+        self.added.iter().chain(self.updated.iter())
+    }
+
+    /// Whether applying the entries would not change the replica.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty()
+    }
+}
+
+/// A key whose latest entry would change, see [`MergePreview::changed_keys`].
+#[derive(Debug, Clone)]
+pub struct KeyChange {
+    /// The key
+    pub key: Vec<u8>,
+    /// The current latest entry at the key of all authors, if any
+    pub previous: Option<SignedEntry>,
+    /// The incoming entry that would become the latest entry at the key
+    pub entry: SignedEntry,
+}
+
 /// Error emitted when inserting entries into a [`Replica`] failed
 #[derive(thiserror::Error, derive_more::Debug)]
 pub enum InsertError<S: ranger::Store<SignedEntry>> {
     /// Storage error
     #[error("storage error")]
     Store(S::Error),
+    /// The replica is read-only and cannot sign new entries
+    #[error("replica is read-only")]
+    ReadOnly,
     /// Validation failure
     #[error("validation failure")]
     Validation(#[from] ValidationFailure),
@@ -375,6 +850,9 @@ pub enum ValidationFailure {
     /// Entry timestamp is too far in the future.
     #[error("Entry timestamp is too far in the future.")]
     TooFarInTheFuture,
+    /// Entry timestamp is older than the retention of the replica.
+    #[error("Entry timestamp is older than the retention of the replica.")]
+    Expired,
 }
 
 /// A signed entry.
@@ -435,6 +913,59 @@ impl SignedEntry {
         )
     }
 
+    /// Verify the signatures on many entries at once.
+    ///
+    /// Returns one result per entry, in the same order as `entries`.
+    ///
+    /// The signatures are checked with ed25519 batch verification, which is considerably faster
+    /// than verifying them one by one. If the batch fails, each entry is verified on its own to
+    /// find the invalid ones. The result is always the same as calling [`Self::verify`] on each
+    /// entry.
+    pub fn verify_batch<S: store::PublicKeyStore>(
+        entries: &[&SignedEntry],
+        store: &S,
+    ) -> Vec<Result<(), SignatureError>> {
+        let mut batch = Vec::with_capacity(entries.len());
+        let mut in_batch = vec![false; entries.len()];
+        for (entry, in_batch) in entries.iter().zip(in_batch.iter_mut()) {
+            let (Ok(namespace), Ok(author)) = (
+                store.public_key(entry.namespace().as_bytes()),
+                store.public_key(entry.author().as_bytes()),
+            ) else {
+                continue;
+            };
+            let signature = &entry.signature;
+            if strict_batchable(&namespace, &signature.namespace_signature)
+                && strict_batchable(&author, &signature.author_signature)
+            {
+                batch.push((entry.entry.to_vec(), namespace, author, signature));
+                *in_batch = true;
+            }
+        }
+        let batch_ok = !batch.is_empty() && {
+            let mut messages = Vec::with_capacity(batch.len() * 2);
+            let mut signatures = Vec::with_capacity(batch.len() * 2);
+            let mut keys = Vec::with_capacity(batch.len() * 2);
+            for (bytes, namespace, author, signature) in &batch {
+                messages.extend([bytes.as_slice(), bytes.as_slice()]);
+                signatures.extend([signature.namespace_signature, signature.author_signature]);
+                keys.extend([*namespace, *author]);
+            }
+            ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok()
+        };
+        entries
+            .iter()
+            .zip(in_batch)
+            .map(|(entry, in_batch)| {
+                if batch_ok && in_batch {
+                    Ok(())
+                } else {
+                    entry.verify(store)
+                }
+            })
+            .collect()
+    }
+
     /// Get the signature.
     pub fn signature(&self) -> &EntrySignature {
         &self.signature
@@ -563,6 +1094,26 @@ impl EntrySignature {
     }
 }
 
+/// Whether batch verification of `signature` gives the same result as [`VerifyingKey::verify_strict`].
+///
+/// Batch verification accepts weak keys and small order or non-canonical `R` components, which
+/// strict verification rejects. If the key or `R` has a small order component, the batch equation
+/// can also hold for a signature that fails strict verification, depending on the random batch
+/// coefficients. Only signatures where both are prime order points are batched, the others are
+/// verified on their own.
+fn strict_batchable(key: &VerifyingKey, signature: &Signature) -> bool {
+    if key.is_weak() {
+        return false;
+    }
+    let prime_order = |bytes: [u8; 32]| {
+        let compressed = CompressedEdwardsY(bytes);
+        compressed.decompress().is_some_and(|point| {
+            !point.is_small_order() && point.is_torsion_free() && point.compress() == compressed
+        })
+    };
+    prime_order(key.to_bytes()) && prime_order(*signature.r_bytes())
+}
+
 /// A single entry in a [`Replica`]
 ///
 /// An entry is identified by a key, its [`Author`], and the [`Replica`]'s
@@ -650,7 +1201,21 @@ impl Debug for RecordIdentifier {
 
 impl RangeKey for RecordIdentifier {}
 
-fn system_time_now() -> u64 {
+/// Record the time from the creation of an entry received through set reconciliation until
+/// now, both in microseconds.
+///
+/// Set reconciliation exchanges no clock information, so the delay is measured from the
+/// timestamp of the entry, which was set by the clock of the author. Entries that appear to
+/// come from the future are counted instead.
+#[cfg(feature = "metrics")]
+fn record_propagation(now: u64, timestamp: u64) {
+    match now.checked_sub(timestamp) {
+        Some(delay) => observe!(Metrics, sync_propagation_ms, delay as f64 / 1000.),
+        None => inc!(Metrics, new_entries_remote_future),
+    }
+}
+
+pub(crate) fn system_time_now() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .expect("time drift")
@@ -764,6 +1329,13 @@ impl Record {
         self.timestamp
     }
 
+    /// Whether this record has no content.
+    ///
+    /// Empty records are used to mark deleted entries, see [`Replica::delete`].
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && self.hash == Hash::new([])
+    }
+
     #[cfg(test)]
     pub(crate) fn current_from_data(data: impl AsRef<[u8]>) -> Self {
         let len = data.as_ref().len() as u64;
@@ -1142,6 +1714,17 @@ mod tests {
         let res = store.get_one(namespace.id(), author.id(), key)?.unwrap();
         assert_eq!(res, entry);
 
+        // delivering the same entry again does not change the stored entry
+        let res = replica.insert_remote_entry(entry.clone(), [0u8; 32], ContentStatus::Complete);
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(
+                ValidationFailure::OlderThanExisting
+            ))
+        ));
+        let res = store.get_one(namespace.id(), author.id(), key)?.unwrap();
+        assert_eq!(res, entry);
+
         Ok(())
     }
 
@@ -1193,6 +1776,761 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_replica_sync_resume() -> Result<()> {
+        let alice_store = store::memory::Store::default();
+        let bob_store = store::memory::Store::default();
+        let alice_peer_id = [1u8; 32];
+        let bob_peer_id = [2u8; 32];
+
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let myspace = Namespace::new(&mut rng);
+        let alice = alice_store.new_replica(myspace.clone())?;
+        let bob = bob_store.new_replica(myspace.clone())?;
+        let alice_set = (0..50).map(|i| format!("alice-{i}")).collect::<Vec<_>>();
+        let bob_set = (0..50).map(|i| format!("bob-{i}")).collect::<Vec<_>>();
+        for el in &alice_set {
+            alice.hash_and_insert(el, &author, el.as_bytes())?;
+        }
+        for el in &bob_set {
+            bob.hash_and_insert(el, &author, el.as_bytes())?;
+        }
+
+        // Do a single round trip, then interrupt the sync.
+        let msg = alice.sync_initial_message()?;
+        let msg = bob.sync_process_message(msg, alice_peer_id)?.unwrap();
+        let msg = alice.sync_process_message(msg, bob_peer_id)?.unwrap();
+        let resume = alice.sync_resume_message(bob_peer_id)?;
+        assert_eq!(resume.parts().len(), msg.parts().len());
+        assert!(resume
+            .parts()
+            .iter()
+            .all(|part| part.is_range_fingerprint()));
+
+        // Resume the sync from the checkpoint.
+        let mut next_to_bob = Some(resume);
+        let mut rounds = 0;
+        while let Some(msg) = next_to_bob.take() {
+            assert!(rounds < 100, "too many rounds");
+            rounds += 1;
+            if let Some(msg) = bob.sync_process_message(msg, alice_peer_id)? {
+                next_to_bob = alice.sync_process_message(msg, bob_peer_id)?;
+            }
+        }
+        alice.sync_finished(bob_peer_id)?;
+        bob.sync_finished(alice_peer_id)?;
+
+        let alice_set = alice_set.iter().map(String::as_str).collect::<Vec<_>>();
+        let bob_set = bob_set.iter().map(String::as_str).collect::<Vec<_>>();
+        check_entries(&alice_store, &myspace.id(), &author, &alice_set)?;
+        check_entries(&alice_store, &myspace.id(), &author, &bob_set)?;
+        check_entries(&bob_store, &myspace.id(), &author, &alice_set)?;
+        check_entries(&bob_store, &myspace.id(), &author, &bob_set)?;
+
+        // Once finished, the next sync starts from scratch.
+        assert_eq!(
+            alice.sync_resume_message(bob_peer_id)?,
+            alice.sync_initial_message()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_read_only_memory() -> Result<()> {
+        let alice_store = store::memory::Store::default();
+        let bob_store = store::memory::Store::default();
+
+        test_replica_read_only(alice_store, bob_store)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_replica_read_only_fs() -> Result<()> {
+        let alice_dbfile = tempfile::NamedTempFile::new()?;
+        let alice_store = store::fs::Store::new(alice_dbfile.path())?;
+        let bob_dbfile = tempfile::NamedTempFile::new()?;
+        let bob_store = store::fs::Store::new(bob_dbfile.path())?;
+        test_replica_read_only(alice_store, bob_store)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_existing_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_import_existing(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_import_existing_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_import_existing(store)
+    }
+
+    fn test_import_existing<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let namespace = Namespace::new(&mut rng);
+        let replica = store.new_replica(namespace.clone())?;
+        replica.hash_and_insert("foo", &author, "foo")?;
+        let events = replica.subscribe().unwrap();
+
+        // importing the namespace again reuses the existing replica
+        let replica = store.new_replica(namespace.clone())?;
+        check_entries(&store, &namespace.id(), &author, &["foo"])?;
+        replica.hash_and_insert("bar", &author, "bar")?;
+        assert_eq!(events.try_recv()?.1.key(), b"bar");
+        check_entries(&store, &namespace.id(), &author, &["foo", "bar"])?;
+
+        // upgrading a read-only replica keeps it as well
+        let namespace = Namespace::new(&mut rng);
+        let replica = store.new_replica(namespace.id())?;
+        let events = replica.subscribe().unwrap();
+        let replica = store.new_replica(namespace.clone())?;
+        assert!(replica.is_writable());
+        replica.hash_and_insert("foo", &author, "foo")?;
+        assert_eq!(events.try_recv()?.1.key(), b"foo");
+        check_entries(&store, &namespace.id(), &author, &["foo"])?;
+
+        Ok(())
+    }
+
+    fn test_replica_read_only<S: store::Store>(alice_store: S, bob_store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let namespace = Namespace::new(&mut rng);
+        let alice = alice_store.new_replica(namespace.clone())?;
+        alice.hash_and_insert("foo", &author, "foo")?;
+
+        // bob only gets the namespace id
+        let bob = bob_store.new_replica(namespace.id())?;
+        assert!(bob.is_read_only());
+        assert!(!bob.is_writable());
+        assert_eq!(bob.secret_key(), None);
+        let res = bob.hash_and_insert("bar", &author, "bar");
+        assert!(matches!(res, Err(InsertError::ReadOnly)));
+        let res = bob.insert("bar", &author, Hash::new("bar"), 3);
+        assert!(matches!(res, Err(InsertError::ReadOnly)));
+
+        // bob can still receive and verify entries from alice
+        sync::<S>(&alice, &bob)?;
+        check_entries(&bob_store, &namespace.id(), &author, &["foo"])?;
+        assert!(bob_store
+            .list_namespaces()?
+            .any(|id| id.ok() == Some(namespace.id())));
+
+        // the capability survives reopening the replica
+        bob_store.close_replica(&namespace.id());
+        let bob = bob_store.open_replica(&namespace.id())?.unwrap();
+        assert!(bob.is_read_only());
+
+        // importing the read capability again does not downgrade a writable replica
+        let alice = alice_store.new_replica(namespace.id())?;
+        assert!(!alice.is_read_only());
+
+        // importing the secret key upgrades the read-only replica
+        let bob = bob_store.new_replica(namespace.clone())?;
+        assert!(bob.is_writable());
+        bob.hash_and_insert("bar", &author, "bar")?;
+        bob_store.close_replica(&namespace.id());
+        let bob = bob_store.open_replica(&namespace.id())?.unwrap();
+        assert!(!bob.is_read_only());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_author_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_remove_author(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_remove_author_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_remove_author(store)
+    }
+
+    fn test_remove_author<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let unused = store.new_author(&mut rng)?;
+        let used = store.new_author(&mut rng)?;
+        let replica = store.new_replica(Namespace::new(&mut rng))?;
+        replica.hash_and_insert("foo", &used, "bar")?;
+
+        store.remove_author(&unused.id(), false)?;
+        assert!(store.get_author(&unused.id())?.is_none());
+        assert!(store.remove_author(&unused.id(), false).is_err());
+
+        // authors with entries are only removed when forced
+        assert!(store.remove_author(&used.id(), false).is_err());
+        assert!(store.get_author(&used.id())?.is_some());
+        store.remove_author(&used.id(), true)?;
+        assert_eq!(store.list_authors()?.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_key_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_move_key(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_move_key_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_move_key(store)
+    }
+
+    fn test_move_key<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let author = store.new_author(&mut rng)?;
+        let replica = store.new_replica(Namespace::new(&mut rng))?;
+        let namespace = replica.namespace();
+        let hash = replica.hash_and_insert("foo", &author, "bar")?;
+
+        let events = replica.subscribe().unwrap();
+        store.move_key(namespace, author.id(), b"foo", b"baz")?;
+        let moved = store.get_one(namespace, author.id(), "baz")?.unwrap();
+        assert_eq!(moved.content_hash(), hash);
+        assert_eq!(moved.content_len(), 3);
+        let old = store.get_one(namespace, author.id(), "foo")?.unwrap();
+        assert!(old.entry().record().is_empty());
+        assert!(old.timestamp() >= moved.timestamp());
+
+        // both entries are inserted in one transaction, the insert first
+        let (origins, keys): (Vec<_>, Vec<_>) = events
+            .drain()
+            .map(|(origin, e)| (origin, e.key().to_vec()))
+            .unzip();
+        assert_eq!(keys, vec![b"baz".to_vec(), b"foo".to_vec()]);
+        assert!(matches!(
+            origins[..],
+            [
+                InsertOrigin::Transaction { remaining: 1 },
+                InsertOrigin::Transaction { remaining: 0 }
+            ]
+        ));
+
+        // moving a deleted or missing key fails
+        assert!(store
+            .move_key(namespace, author.id(), b"foo", b"qux")
+            .is_err());
+        assert!(store
+            .move_key(namespace, author.id(), b"missing", b"qux")
+            .is_err());
+        assert!(store.get_one(namespace, author.id(), "qux")?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gossip_secret_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_gossip_secret(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_gossip_secret_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_gossip_secret(store)
+    }
+
+    fn test_gossip_secret<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = store.new_replica(Namespace::new(&mut rng))?.namespace();
+        assert_eq!(store.gossip_secret(&namespace)?, None);
+
+        let secret = GossipSecret::new(&mut rng);
+        store.set_gossip_secret(&namespace, Some(secret))?;
+        assert_eq!(store.gossip_secret(&namespace)?, Some(secret));
+
+        let mac = secret.mac(b"message");
+        assert!(secret.verify(b"message", &mac));
+        assert!(!secret.verify(b"other message", &mac));
+        assert!(!GossipSecret::new(&mut rng).verify(b"message", &mac));
+
+        store.set_gossip_secret(&namespace, None)?;
+        assert_eq!(store.gossip_secret(&namespace)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_author_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_default_author(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_default_author_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_default_author(store)
+    }
+
+    fn test_default_author<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = store.new_replica(Namespace::new(&mut rng))?.namespace();
+        let other = store.new_replica(Namespace::new(&mut rng))?.namespace();
+        assert_eq!(store.default_author(&namespace)?, None);
+
+        let author = store.new_author(&mut rng)?.id();
+        store.set_default_author(&namespace, Some(author))?;
+        assert_eq!(store.default_author(&namespace)?, Some(author));
+        assert_eq!(store.default_author(&other)?, None);
+
+        // rotating the author
+        let author = store.new_author(&mut rng)?.id();
+        store.set_default_author(&namespace, Some(author))?;
+        assert_eq!(store.default_author(&namespace)?, Some(author));
+
+        // the default author can only be removed with force, which unsets it
+        assert!(store.remove_author(&author, false).is_err());
+        store.remove_author(&author, true)?;
+        assert_eq!(store.default_author(&namespace)?, None);
+
+        // creating a default author if none is set
+        let created = store.get_or_create_default_author(&namespace, &mut rng)?;
+        assert_eq!(store.default_author(&namespace)?, Some(created.id()));
+        assert!(store.get_author(&created.id())?.is_some());
+        let again = store.get_or_create_default_author(&namespace, &mut rng)?;
+        assert_eq!(again.id(), created.id());
+
+        store.set_default_author(&namespace, None)?;
+        assert_eq!(store.default_author(&namespace)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_expired_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_prune_expired(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_prune_expired_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_prune_expired(store)
+    }
+
+    fn test_prune_expired<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let local = store.new_author(&mut rng)?;
+        let remote = store.new_author(&mut rng)?;
+        let replica = store.new_replica(Namespace::new(&mut rng))?;
+        let namespace = replica.namespace();
+        replica.hash_and_insert("local", &local, "foo")?;
+        replica.hash_and_insert("remote", &remote, "bar")?;
+        store.remove_author(&remote.id(), true)?;
+
+        let remote_entry = store.get_one(namespace, remote.id(), "remote")?.unwrap();
+
+        // nothing is pruned without a retention
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.prune_expired(&namespace)?, 0);
+
+        let retention = Duration::from_millis(50);
+        store.set_retention(&namespace, Some(retention))?;
+        assert_eq!(store.retention(&namespace)?, Some(retention));
+        replica.hash_and_insert("fresh", &local, "baz")?;
+        assert_eq!(store.prune_expired(&namespace)?, 2);
+
+        // entries of local authors are deleted, other entries are removed
+        let deleted = store.get_one(namespace, local.id(), "local")?.unwrap();
+        assert!(deleted.entry().record().is_empty());
+        assert!(store.get_one(namespace, remote.id(), "remote")?.is_none());
+        assert!(store.get_one(namespace, local.id(), "fresh")?.is_some());
+
+        // removed entries do not come back from peers that did not prune them
+        let res = replica.insert_remote_entry(remote_entry, [0u8; 32], ContentStatus::Complete);
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(ValidationFailure::Expired))
+        ));
+        assert!(store.get_one(namespace, remote.id(), "remote")?.is_none());
+
+        // expired deletions are kept
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.prune_expired(&namespace)?, 1);
+        let deleted = store.get_one(namespace, local.id(), "local")?.unwrap();
+        assert!(deleted.entry().record().is_empty());
+        let deleted = store.get_one(namespace, local.id(), "fresh")?.unwrap();
+        assert!(deleted.entry().record().is_empty());
+        assert_eq!(store.prune_expired(&namespace)?, 0);
+
+        store.set_retention(&namespace, None)?;
+        assert_eq!(store.retention(&namespace)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_preview_merge_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_preview_merge(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_preview_merge_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_preview_merge(store)
+    }
+
+    fn test_preview_merge<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = Namespace::new(&mut rng);
+        let alice = Author::new(&mut rng);
+        let bob = Author::new(&mut rng);
+        let replica = store.new_replica(namespace.clone())?;
+        let entry = |author: &Author, key: &str, value: &str, timestamp: u64| {
+            let record = Record::new(Hash::new(value), value.len() as u64, timestamp);
+            SignedEntry::from_parts(&namespace, author, key, record)
+        };
+        let now = system_time_now();
+        replica.insert_entry(entry(&alice, "a", "1", now - 100), InsertOrigin::Local)?;
+        replica.insert_entry(entry(&alice, "b", "1", now - 100), InsertOrigin::Local)?;
+
+        let incoming = vec![
+            // overwrites the local entry of alice at a
+            entry(&bob, "a", "2", now - 50),
+            // older than the local entry of alice at b
+            entry(&alice, "b", "0", now - 200),
+            // the later of two entries for bob at c is applied
+            entry(&bob, "c", "1", now - 20),
+            entry(&bob, "c", "2", now - 10),
+            // newer, but with the same content as the latest entry at b
+            entry(&bob, "b", "1", now - 10),
+        ];
+        let preview = replica.preview_merge(&incoming).map_err(Into::into)?;
+        let keys = |entries: &[SignedEntry]| {
+            let mut keys: Vec<_> = entries.iter().map(|e| e.key().to_vec()).collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(
+            keys(&preview.added),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+        assert!(preview.updated.is_empty());
+        assert_eq!(preview.applied().count(), 3);
+        assert_eq!(preview.ignored.len(), 2);
+        assert!(preview
+            .ignored
+            .iter()
+            .all(|(_, reason)| matches!(reason, ValidationFailure::OlderThanExisting)));
+
+        let changed: Vec<_> = preview.changed_keys.iter().map(|c| &c.key[..]).collect();
+        assert_eq!(changed, vec![&b"a"[..], &b"c"[..]]);
+        let change = &preview.changed_keys[0];
+        assert_eq!(change.previous.as_ref().unwrap().author(), alice.id());
+        assert_eq!(change.entry.author(), bob.id());
+        assert!(preview.changed_keys[1].previous.is_none());
+        assert_eq!(preview.changed_keys[1].entry.timestamp(), now - 10);
+
+        // the replica is not changed
+        assert!(store.get_one(namespace.id(), bob.id(), "a")?.is_none());
+
+        // an update of an existing entry of the same author
+        let preview = replica
+            .preview_merge(&[entry(&alice, "a", "3", now)])
+            .map_err(Into::into)?;
+        assert!(preview.added.is_empty());
+        assert_eq!(keys(&preview.updated), vec![b"a".to_vec()]);
+        assert_eq!(preview.changed_keys.len(), 1);
+        assert!(!preview.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_recent_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_get_recent(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_get_recent_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_get_recent(store)
+    }
+
+    fn test_get_recent<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = Namespace::new(&mut rng);
+        let alice = Author::new(&mut rng);
+        let bob = Author::new(&mut rng);
+        let replica = store.new_replica(namespace.clone())?;
+        let insert = |author: &Author, key: &str, timestamp: u64| -> Result<()> {
+            let record = Record::new(Hash::new(key), key.len() as u64, timestamp);
+            let entry = SignedEntry::from_parts(&namespace, author, key, record);
+            replica.insert_remote_entry(entry, [0u8; 32], ContentStatus::Complete)?;
+            Ok(())
+        };
+        insert(&alice, "a", 3)?;
+        insert(&bob, "b", 7)?;
+        insert(&alice, "c", 1)?;
+        insert(&bob, "d", 5)?;
+        // overwriting a key makes it recent
+        insert(&alice, "c", 9)?;
+
+        let recent = |limit| -> Result<Vec<(String, u64)>> {
+            Ok(store
+                .get_recent(namespace.id(), limit)?
+                .into_iter()
+                .map(|e| (String::from_utf8(e.key().to_vec()).unwrap(), e.timestamp()))
+                .collect())
+        };
+        let all = vec![
+            ("c".to_string(), 9),
+            ("b".to_string(), 7),
+            ("d".to_string(), 5),
+            ("a".to_string(), 3),
+        ];
+        assert_eq!(recent(2)?, all[..2]);
+        assert_eq!(recent(10)?, all);
+        assert!(recent(0)?.is_empty());
+        let other = store.new_replica(Namespace::new(&mut rng))?.namespace();
+        assert!(store.get_recent(other, 10)?.is_empty());
+
+        // entries with equal timestamps are ordered by their identifier
+        insert(&alice, "y", 11)?;
+        insert(&alice, "e", 12)?;
+        insert(&alice, "z", 11)?;
+        insert(&alice, "x", 11)?;
+        let all = vec![
+            ("e".to_string(), 12),
+            ("x".to_string(), 11),
+            ("y".to_string(), 11),
+            ("z".to_string(), 11),
+        ];
+        assert_eq!(recent(4)?, all);
+        assert_eq!(recent(3)?, all[..3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_diff(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_diff_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_diff(store)
+    }
+
+    fn test_diff<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let namespace = Namespace::new(&mut rng);
+        let author = Author::new(&mut rng);
+        let replica = store.new_replica(namespace.clone())?;
+        let insert = |key: &str, timestamp: u64| -> Result<()> {
+            let record = Record::new(Hash::new(key), key.len() as u64, timestamp);
+            let entry = SignedEntry::from_parts(&namespace, &author, key, record);
+            replica.insert_remote_entry(entry, [0u8; 32], ContentStatus::Complete)?;
+            Ok(())
+        };
+        insert("a", 3)?;
+        insert("b", 7)?;
+        insert("c", 1)?;
+        // overwriting a key makes it part of the diff
+        insert("c", 9)?;
+        // a deletion is a change too
+        let deletion = Record::new(Hash::new([]), 0, 5);
+        replica.insert_remote_entry(
+            SignedEntry::from_parts(&namespace, &author, "a", deletion),
+            [0u8; 32],
+            ContentStatus::Complete,
+        )?;
+
+        let diff = |since| -> Result<Vec<(String, u64)>> {
+            let mut entries = store
+                .diff(namespace.id(), since)?
+                .map(|e| e.map(|e| (String::from_utf8(e.key().to_vec()).unwrap(), e.timestamp())))
+                .collect::<Result<Vec<_>>>()?;
+            entries.sort();
+            Ok(entries)
+        };
+        assert_eq!(
+            diff(0)?,
+            vec![
+                ("a".to_string(), 5),
+                ("b".to_string(), 7),
+                ("c".to_string(), 9)
+            ]
+        );
+        assert_eq!(diff(5)?, vec![("b".to_string(), 7), ("c".to_string(), 9)]);
+        assert_eq!(diff(7)?, vec![("c".to_string(), 9)]);
+        assert!(diff(9)?.is_empty());
+        // entries are selected by their timestamp, not by when they were inserted
+        insert("d", 2)?;
+        assert!(diff(9)?.is_empty());
+        let other = store.new_replica(Namespace::new(&mut rng))?.namespace();
+        assert_eq!(store.diff(other, 0)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_subscribe_all_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_subscribe_all(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_subscribe_all_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_subscribe_all(store)
+    }
+
+    fn test_subscribe_all<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let author = store.new_author(&mut rng)?;
+        let events = store.subscribe_all();
+        let events2 = store.subscribe_all();
+        let replica1 = store.new_replica(Namespace::new(&mut rng))?;
+        let replica2 = store.new_replica(Namespace::new(&mut rng))?;
+        // per replica subscriptions keep working
+        let replica_events = replica1.subscribe().unwrap();
+
+        replica1.hash_and_insert("foo", &author, "1")?;
+        replica2.hash_and_insert("bar", &author, "2")?;
+
+        let expected = vec![
+            (replica1.namespace(), b"foo".to_vec()),
+            (replica2.namespace(), b"bar".to_vec()),
+        ];
+        for events in [&events, &events2] {
+            let received: Vec<_> = events
+                .drain()
+                .map(|(namespace, origin, entry)| {
+                    assert!(matches!(origin, InsertOrigin::Local));
+                    assert_eq!(entry.namespace(), namespace);
+                    (namespace, entry.key().to_vec())
+                })
+                .collect();
+            assert_eq!(received, expected);
+        }
+        assert_eq!(replica_events.drain().count(), 1);
+
+        // dropped subscribers are removed, the others keep receiving
+        drop(events2);
+        replica2.hash_and_insert("baz", &author, "3")?;
+        let received: Vec<_> = events.drain().map(|(_, _, e)| e.key().to_vec()).collect();
+        assert_eq!(received, vec![b"baz".to_vec()]);
+
+        // a subscriber that stops receiving does not block inserts, but is disconnected,
+        // while the subscribers that keep up receive all events
+        let stalled = store.subscribe_all();
+        let mut received = 0;
+        for i in 0..300 {
+            replica2.hash_and_insert(format!("key{i}"), &author, "4")?;
+            received += events.drain().count();
+        }
+        assert_eq!(received, 300);
+        assert_eq!(stalled.drain().count(), 256);
+        assert!(stalled.is_disconnected());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_memory() -> Result<()> {
+        let store = store::memory::Store::default();
+        test_transaction(store)
+    }
+
+    #[cfg(feature = "fs-store")]
+    #[test]
+    fn test_transaction_fs() -> Result<()> {
+        let dbfile = tempfile::NamedTempFile::new()?;
+        let store = store::fs::Store::new(dbfile.path())?;
+        test_transaction(store)
+    }
+
+    fn test_transaction<S: store::Store>(store: S) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let namespace = Namespace::new(&mut rng);
+        let replica = store.new_replica(namespace.clone())?;
+        let events = replica.subscribe().unwrap();
+        replica.hash_and_insert("a", &author, "0")?;
+
+        let mut tx = replica.transaction();
+        tx.put("b", &author, Hash::new("1"), 1)?;
+        tx.delete("a", &author)?;
+        // a later put replaces the earlier one
+        tx.put("b", &author, Hash::new("2"), 1)?;
+        assert_eq!(tx.len(), 2);
+        // nothing is inserted before the commit
+        assert!(store.get_one(namespace.id(), author.id(), "b")?.is_none());
+        assert_eq!(tx.commit()?, 2);
+        assert_eq!(
+            get_content_hash(&store, namespace.id(), author.id(), b"b")?,
+            Hash::new("2")
+        );
+        let a = store.get_one(namespace.id(), author.id(), "a")?.unwrap();
+        assert!(a.entry().record().is_empty());
+
+        let origins: Vec<_> = events.drain().map(|(origin, _)| origin).collect();
+        assert!(matches!(
+            origins[..],
+            [
+                InsertOrigin::Local,
+                InsertOrigin::Transaction { remaining: 1 },
+                InsertOrigin::Transaction { remaining: 0 }
+            ]
+        ));
+
+        // rolled back entries are discarded
+        let mut tx = replica.transaction();
+        tx.put("c", &author, Hash::new("3"), 1)?;
+        tx.rollback();
+        assert!(store.get_one(namespace.id(), author.id(), "c")?.is_none());
+
+        // if one entry fails to validate, none are inserted
+        let mut tx = replica.transaction();
+        tx.put("d", &author, Hash::new("4"), 1)?;
+        tx.put("e", &author, Hash::new("5"), 1)?;
+        replica.hash_and_insert("e", &author, "newer")?;
+        let res = tx.commit();
+        assert!(matches!(
+            res,
+            Err(InsertError::Validation(
+                ValidationFailure::OlderThanExisting
+            ))
+        ));
+        assert!(store.get_one(namespace.id(), author.id(), "d")?.is_none());
+        assert_eq!(events.drain().count(), 1);
+
+        // read-only replicas can't stage entries
+        let read_only = store.new_replica(Namespace::new(&mut rng).id())?;
+        let res = read_only.transaction().put("f", &author, Hash::new("6"), 1);
+        assert!(matches!(res, Err(InsertError::ReadOnly)));
+        Ok(())
+    }
+
     #[test]
     fn test_replica_timestamp_sync_memory() -> Result<()> {
         let alice_store = store::memory::Store::default();
@@ -1297,6 +2635,126 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_verify_batch() {
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let namespace = Namespace::new(&mut rng);
+        let mut entries = (0..10)
+            .map(|i| {
+                let record = Record::current_from_data(format!("data {i}"));
+                SignedEntry::from_parts(&namespace, &author, format!("key {i}"), record)
+            })
+            .collect::<Vec<_>>();
+
+        let refs = entries.iter().collect::<Vec<_>>();
+        let res = SignedEntry::verify_batch(&refs, &());
+        assert!(res.iter().all(Result::is_ok));
+        assert!(SignedEntry::verify_batch(&[], &()).is_empty());
+
+        // a single bad signature is found by falling back to verifying each entry
+        entries[3].signature = entries[4].signature.clone();
+        let refs = entries.iter().collect::<Vec<_>>();
+        let res = SignedEntry::verify_batch(&refs, &());
+        for (i, res) in res.iter().enumerate() {
+            assert_eq!(res.is_ok(), i != 3, "entry {i}");
+        }
+    }
+
+    /// Creates an entry for `key` in `namespace`, signed by a random author whose public key has
+    /// the small order point `key_torsion` added, and with `r_torsion` added to `R`.
+    ///
+    /// Unless both torsion points are the identity, the signature fails strict verification.
+    fn sign_mixed_order(
+        rng: &mut impl rand_core::CryptoRngCore,
+        namespace: &Namespace,
+        key: &[u8],
+        key_torsion: curve25519_dalek::EdwardsPoint,
+        r_torsion: curve25519_dalek::EdwardsPoint,
+    ) -> SignedEntry {
+        use curve25519_dalek::{constants::ED25519_BASEPOINT_POINT, Scalar};
+        use sha2::{Digest, Sha512};
+
+        let random_scalar = |rng: &mut dyn rand_core::CryptoRngCore| {
+            let mut bytes = [0u8; 64];
+            rng.fill_bytes(&mut bytes);
+            Scalar::from_bytes_mod_order_wide(&bytes)
+        };
+        let secret = random_scalar(rng);
+        let public = (ED25519_BASEPOINT_POINT * secret + key_torsion).compress();
+        let author = AuthorId::from(public.to_bytes());
+        let record = Record::current_from_data("mixed order");
+        let entry = Entry::new(RecordIdentifier::new(namespace.id(), author, key), record);
+        let bytes = entry.to_vec();
+        loop {
+            let nonce = random_scalar(rng);
+            let r = (ED25519_BASEPOINT_POINT * nonce + r_torsion).compress();
+            let k = Scalar::from_bytes_mod_order_wide(
+                &Sha512::new()
+                    .chain_update(r.as_bytes())
+                    .chain_update(public.as_bytes())
+                    .chain_update(&bytes)
+                    .finalize()
+                    .into(),
+            );
+            // with k a multiple of 8, the torsion of the key cancels out
+            if key_torsion != curve25519_dalek::EdwardsPoint::default() && k.as_bytes()[0] % 8 == 0
+            {
+                continue;
+            }
+            let s = nonce + k * secret;
+            let mut signature = [0u8; 64];
+            signature[..32].copy_from_slice(r.as_bytes());
+            signature[32..].copy_from_slice(s.as_bytes());
+            let signature = EntrySignature {
+                author_signature: Signature::from_bytes(&signature),
+                namespace_signature: namespace.sign(&bytes),
+            };
+            return SignedEntry::new(signature, entry);
+        }
+    }
+
+    #[test]
+    fn test_verify_batch_mixed_order() {
+        use curve25519_dalek::constants::EIGHT_TORSION;
+
+        let mut rng = rand::thread_rng();
+        let author = Author::new(&mut rng);
+        let namespace = Namespace::new(&mut rng);
+        let good = (0..4)
+            .map(|i| {
+                let record = Record::current_from_data(format!("data {i}"));
+                SignedEntry::from_parts(&namespace, &author, format!("key {i}"), record)
+            })
+            .collect::<Vec<_>>();
+        let identity = curve25519_dalek::EdwardsPoint::default();
+        let plain = sign_mixed_order(&mut rng, &namespace, b"plain", identity, identity);
+        assert!(plain.verify(&()).is_ok());
+
+        for (key_torsion, r_torsion) in [(EIGHT_TORSION[1], identity), (identity, EIGHT_TORSION[1])]
+        {
+            // find a signature that plain batch verification accepts, which depends on the
+            // batch coefficient derived from the signature
+            let mixed = (0..1000)
+                .map(|_| sign_mixed_order(&mut rng, &namespace, b"mixed", key_torsion, r_torsion))
+                .find(|mixed| {
+                    let bytes = mixed.entry().to_vec();
+                    let key = VerifyingKey::from_bytes(mixed.entry().author().as_bytes()).unwrap();
+                    let signature = mixed.signature.author_signature;
+                    ed25519_dalek::verify_batch(&[&bytes], &[signature], &[key]).is_ok()
+                })
+                .expect("batch verification accepts some mixed order signatures");
+            assert!(mixed.verify(&()).is_err());
+
+            let mut refs = good.iter().collect::<Vec<_>>();
+            refs.insert(2, &mixed);
+            let res = SignedEntry::verify_batch(&refs, &());
+            for (i, res) in res.iter().enumerate() {
+                assert_eq!(res.is_ok(), i != 2, "entry {i}");
+            }
+        }
+    }
+
     fn get_entry<S: store::Store>(
         store: &S,
         namespace: NamespaceId,
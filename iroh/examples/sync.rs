@@ -23,7 +23,8 @@ use futures::StreamExt;
 use indicatif::HumanBytes;
 use iroh::{
     downloader::Downloader,
-    sync_engine::{LiveEvent, SyncEngine, SYNC_ALPN},
+    get::DEFAULT_MAX_BLOB_SIZE,
+    sync_engine::{LiveEvent, NoDiscovery, SyncEngine, DEFAULT_GOSSIP_DEDUP_CAPACITY, SYNC_ALPN},
 };
 use iroh_bytes::{
     baomap::{ImportMode, Map, MapEntry, Store as BaoStore},
@@ -230,11 +231,18 @@ async fn run(args: Args) -> anyhow::Result<()> {
     std::fs::create_dir_all(&blob_path)?;
     let db = iroh::baomap::flat::Store::load(&blob_path, &blob_path, &blob_path, &rt).await?;
 
-    let collection_parser = LinkSeqCollectionParser;
+    let collection_parser = LinkSeqCollectionParser::default();
 
     // create the live syncer
-    let downloader =
-        Downloader::new(db.clone(), collection_parser, endpoint.clone(), rt.clone()).await;
+    let downloader = Downloader::new(
+        db.clone(),
+        collection_parser,
+        DEFAULT_MAX_BLOB_SIZE,
+        endpoint.clone(),
+        rt.clone(),
+        Default::default(),
+    )
+    .await;
     let live_sync = SyncEngine::spawn(
         rt.clone(),
         endpoint.clone(),
@@ -242,6 +250,12 @@ async fn run(args: Args) -> anyhow::Result<()> {
         docs.clone(),
         db.clone(),
         downloader,
+        true,
+        Arc::new(NoDiscovery),
+        DEFAULT_GOSSIP_DEDUP_CAPACITY,
+        None,
+        Default::default(),
+        None,
     );
 
     // construct the state that is passed to the endpoint loop and from there cloned
@@ -1004,6 +1018,13 @@ mod iroh_bytes_handlers {
         protocol::{GetRequest, RequestToken},
         provider::{CustomGetHandler, EventSender, RequestAuthorizationHandler},
     };
+    use tokio::sync::Semaphore;
+    use tokio_util::sync::CancellationToken;
+
+    /// Limit on the iroh-bytes requests handled concurrently.
+    const MAX_CONCURRENT_REQUESTS: usize = 1024;
+    /// Limit on the iroh-bytes requests handled concurrently per connection.
+    const MAX_CONNECTION_REQUESTS: usize = 5;
 
     #[derive(Debug, Clone)]
     pub struct IrohBytesHandlers {
@@ -1012,6 +1033,7 @@ mod iroh_bytes_handlers {
         event_sender: NoopEventSender,
         get_handler: Arc<NoopCustomGetHandler>,
         auth_handler: Arc<NoopRequestAuthorizationHandler>,
+        request_limit: Arc<Semaphore>,
     }
     impl IrohBytesHandlers {
         pub fn new(rt: iroh_bytes::util::runtime::Handle, db: iroh::baomap::flat::Store) -> Self {
@@ -1021,6 +1043,7 @@ mod iroh_bytes_handlers {
                 event_sender: NoopEventSender,
                 get_handler: Arc::new(NoopCustomGetHandler),
                 auth_handler: Arc::new(NoopRequestAuthorizationHandler),
+                request_limit: Arc::new(Semaphore::new(MAX_CONCURRENT_REQUESTS)),
             }
         }
         pub async fn handle_connection(&self, conn: quinn::Connecting) -> anyhow::Result<()> {
@@ -1028,10 +1051,16 @@ mod iroh_bytes_handlers {
                 conn,
                 self.db.clone(),
                 self.event_sender.clone(),
-                LinkSeqCollectionParser,
+                LinkSeqCollectionParser::default(),
                 self.get_handler.clone(),
                 self.auth_handler.clone(),
                 self.rt.clone(),
+                self.request_limit.clone(),
+                MAX_CONNECTION_REQUESTS,
+                0,
+                Default::default(),
+                Default::default(),
+                CancellationToken::new(),
             )
             .await;
             Ok(())
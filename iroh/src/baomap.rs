@@ -5,6 +5,7 @@ pub mod flat;
 pub mod mem;
 
 pub mod readonly_mem;
+pub mod tiered;
 
 #[cfg(any(feature = "mem-db", feature = "flat-db"))]
 fn flatten_to_io<T>(
@@ -15,3 +16,52 @@ fn flatten_to_io<T>(
         Err(cause) => Err(std::io::Error::new(std::io::ErrorKind::Other, cause)),
     }
 }
+
+/// Compute the ranges of a partial entry that are available, from its outboard and the size of
+/// the data written so far.
+///
+/// Unless the data is complete, only whole chunk groups are counted, since the leaves are
+/// validated per chunk group. This reads the whole outboard synchronously, so callers that
+/// read from disk should run it on a blocking thread.
+#[cfg(any(feature = "mem-db", feature = "flat-db"))]
+fn partial_available_ranges(
+    outboard: &impl bao_tree::io::sync::Outboard,
+    data_size: u64,
+) -> std::io::Result<iroh_bytes::baomap::range_collections::RangeSet2<bao_tree::ChunkNum>> {
+    use bao_tree::{ByteNum, ChunkNum};
+    use iroh_bytes::baomap::range_collections::RangeSet2;
+
+    let size = outboard.tree().size().0;
+    let chunks = outboard.tree().chunks();
+    let mut from_outboard = bao_tree::io::sync::valid_ranges(outboard)?;
+    // the sync validator stops at the last chunk, while available ranges are open ended
+    if chunks.0 > 0 && from_outboard.contains(&ChunkNum(chunks.0 - 1)) {
+        from_outboard |= RangeSet2::from(chunks..);
+    }
+    if data_size >= size {
+        return Ok(from_outboard);
+    }
+    let group = 1u64 << iroh_bytes::IROH_BLOCK_SIZE.0;
+    let end = ChunkNum(ByteNum(data_size).full_chunks().0 / group * group);
+    Ok(from_outboard & RangeSet2::from(..end))
+}
+
+/// The pins that have not expired at `now`, with their remaining time to live.
+#[cfg(any(feature = "mem-db", feature = "flat-db"))]
+fn live_pins(
+    pins: &std::collections::BTreeMap<
+        iroh_bytes::util::HashAndFormat,
+        Option<std::time::SystemTime>,
+    >,
+    now: std::time::SystemTime,
+) -> Vec<(iroh_bytes::util::HashAndFormat, Option<std::time::Duration>)> {
+    pins.iter()
+        .filter_map(|(value, expiry)| match expiry {
+            None => Some((*value, None)),
+            Some(expiry) => match expiry.duration_since(now) {
+                Ok(ttl) if !ttl.is_zero() => Some((*value, Some(ttl))),
+                _ => None,
+            },
+        })
+        .collect()
+}
@@ -26,10 +26,29 @@
 //!
 //! These files can become quite large and make up the vast majority of the disk usage.
 //!
+//! ### Compressed data files
+//!
+//! With [`Store::set_compress_data`], data files are stored zstd compressed instead. They
+//! have the extension `.zdata`, and start with a format header, followed by a single byte
+//! for the compression. A hash has either a `.data` or a `.zdata` file, never both.
+//!
+//! Data of entries that are small enough to be cached in memory is compressed as a whole,
+//! with compression byte `1`. It is decompressed once on load and then served from memory.
+//!
+//! Larger data is compressed in independent blocks, with compression byte `2`, so it can be
+//! read at any offset by decompressing just the blocks around it. The compression byte is
+//! followed by the little endian `u64` size of the data, the little endian `u32` size of the
+//! blocks, the compressed blocks, and an index of the little endian `u64` end offsets of the
+//! compressed blocks, relative to the end of the block size.
+//!
+//! Text heavy blobs such as collections compress well, while the overhead for other blobs
+//! is small.
+//!
 //! ### Path files
 //!
 //! Path files have as name the hex encoded blake3 hash of the data, and the extension
-//! `.paths`. They contain a postcard serialized list of absolute paths to the data file.
+//! `.paths`. They start with a format header, see [Format versions](#format-versions),
+//! followed by a postcard serialized list of absolute paths to the data file.
 //! The paths are stored in sorted order and do not contain duplicates.
 //!
 //! Path files are used for when data is stored externally. If any of the files listed in
@@ -91,11 +110,31 @@
 //! ### Temp files
 //!
 //! When copying data into the database, we first copy the data into a temporary file to
-//! ensure that the data is not modified while we compute the outboard. These files have
+//! ensure that the data is not modified while we compute the outboard. Data imported from a
+//! stream is written to such a file as it arrives. These files have
 //! just a hex encoded 16 byte random uuid as name, and the extension `.temp`.
 //!
 //! We don't know the hash of the data yet. These files are fully ephemeral, and can
-//! be deleted on restart.
+//! be deleted on restart. [`Store::cleanup_orphans`] removes them, together with
+//! partial files that don't belong to a tracked partial entry.
+//!
+//! ## Format versions
+//!
+//! The format header consists of the 4 magic bytes `\0ifs`, followed by the little endian
+//! encoded `u16` format version, currently [`FORMAT_VERSION`]. The file `version` in the
+//! meta directory contains just the header, and describes the layout of the whole store.
+//!
+//! Stores written before the header was introduced have no `version` file and headerless
+//! path files. They are treated as version 0, which has the same layout otherwise, so
+//! headerless path files are still read, and the `version` file is written on load.
+//! Loading a store or a path file with a version newer than [`FORMAT_VERSION`] fails with
+//! an [`UnsupportedDbVersion`] error instead of misinterpreting the data.
+//!
+//! Version 2 added compressed data files. A store is only upgraded to version 2 when the
+//! first compressed data file is written, so stores without compressed data files can still
+//! be opened by older versions of iroh, while stores with compressed data files can not be
+//! opened by a version of iroh that would silently ignore them. All other files are still
+//! written with version 1.
 //!
 //! # File lifecycle
 //!
@@ -121,14 +160,36 @@
 //!
 //! Once the download is complete, the partial data and partial outboard files are renamed
 //! to the final partial data and partial outboard files.
+//!
+//! ## Adding a hash that is already complete
+//!
+//! When data is added for a hash that is already complete, the new location is merged
+//! into the existing entry, e.g. an external path is added to the paths file. The existing
+//! locations are trusted to contain the same data, since they have the same hash. With
+//! [`Store::set_verify_merges`], the existing locations are hashed again before merging,
+//! and the insert fails if any of them does not match, e.g. because it was modified or
+//! corrupted on disk.
+//!
+//! ## Reading complete data
+//!
+//! Complete data files are never modified, so readers of the same file share one open
+//! handle and read from it at explicit offsets. At most [`MAX_OPEN_FILES`] handles are open
+//! at any time; the least recently used idle handles are closed to make room for new ones,
+//! and readers wait if all handles are in use.
+//!
+//! The outboards of complete entries are read into memory when they are first needed, and
+//! shared by all readers of the entry. The total size of the outboards kept in memory is
+//! bounded, see [`Store::set_outboard_cache_size`], so the least recently used ones are
+//! dropped and read again from their files when needed.
 #![allow(clippy::mutable_key_type)]
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
-use std::io::{self, BufReader, Write};
+use std::io::{self, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use bao_tree::io::outboard::{PostOrderMemOutboard, PreOrderOutboard};
 use bao_tree::io::sync::ReadAt;
@@ -137,21 +198,28 @@ use bao_tree::{BaoTree, ByteNum};
 use bytes::Bytes;
 use futures::future::BoxFuture;
 use futures::future::Either;
-use futures::{Future, FutureExt};
+#[cfg(feature = "mmap")]
+use futures::future::LocalBoxFuture;
+use futures::{Future, FutureExt, Stream, StreamExt};
 use iroh_bytes::baomap::range_collections::RangeSet2;
 use iroh_bytes::baomap::{
     self, EntryStatus, ExportMode, ImportMode, ImportProgress, LivenessTracker, Map, MapEntry,
-    PartialMap, PartialMapEntry, ReadableStore, TempTag, ValidateProgress,
+    PartialMap, PartialMapEntry, ReadableStore, TempTag, ValidateOptions, ValidateProgress,
+    DATA_POLL_INTERVAL,
 };
 use iroh_bytes::util::progress::{IdGenerator, ProgressSender};
 use iroh_bytes::util::{BlobFormat, HashAndFormat, Tag};
 use iroh_bytes::{Hash, IROH_BLOCK_SIZE};
 use iroh_io::{AsyncSliceReader, AsyncSliceWriter, File};
+use lru_cache::LruCache;
 use rand::Rng;
-use tokio::sync::mpsc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::LocalPoolHandle;
 use tracing::trace_span;
 
-use super::flatten_to_io;
+use super::{flatten_to_io, live_pins};
 
 #[derive(Debug, Default)]
 struct State {
@@ -159,8 +227,6 @@ struct State {
     complete: BTreeMap<Hash, CompleteEntry>,
     // partial entries
     partial: BTreeMap<Hash, PartialEntryData>,
-    // outboard data, cached for all complete entries
-    outboard: BTreeMap<Hash, Bytes>,
     // data, cached for all complete entries that are small enough
     data: BTreeMap<Hash, Bytes>,
     // in memory tracking of live set
@@ -169,12 +235,14 @@ struct State {
     temp: BTreeMap<HashAndFormat, u64>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 struct CompleteEntry {
     // size of the data
     size: u64,
     // true means we own the data, false means it is stored externally
     owned_data: bool,
+    // true means the owned data is stored in a compressed data file
+    compressed: bool,
     // external storage locations
     external: BTreeSet<PathBuf>,
 }
@@ -185,15 +253,17 @@ impl CompleteEntry {
     }
 
     fn external_to_bytes(&self) -> Vec<u8> {
-        postcard::to_stdvec(&self.external).unwrap()
+        encode_versioned(&postcard::to_stdvec(&self.external).unwrap())
     }
 
-    // create a new complete entry with the given size
+    // create a new complete entry with the given size, owned data that is optionally
+    // compressed
     //
     // the generated entry will have no data or outboard data yet
-    fn new_default(size: u64) -> Self {
+    fn new_owned(size: u64, compressed: bool) -> Self {
         Self {
             owned_data: true,
+            compressed,
             external: Default::default(),
             size,
         }
@@ -205,6 +275,7 @@ impl CompleteEntry {
     fn new_external(size: u64, path: PathBuf) -> Self {
         Self {
             owned_data: false,
+            compressed: false,
             external: [path].into_iter().collect(),
             size,
         }
@@ -220,6 +291,9 @@ impl CompleteEntry {
             return Err(io::Error::new(io::ErrorKind::InvalidInput, "size mismatch"));
         }
         self.size = new.size;
+        if new.owned_data {
+            self.compressed = new.compressed;
+        }
         self.owned_data |= new.owned_data;
         self.external.extend(new.external);
         Ok(())
@@ -241,6 +315,58 @@ impl PartialEntryData {
     }
 }
 
+/// When content inserted into a [`Store`] is flushed to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DurabilityMode {
+    /// Leave it to the operating system when to write inserted data to disk.
+    ///
+    /// This is the fastest option, but content that was inserted shortly before a crash or
+    /// power loss might be lost or, in the worst case, has to be detected as invalid by
+    /// validation.
+    #[default]
+    Buffered,
+    /// Flush data and outboard of each insert to disk before it becomes visible as complete.
+    ///
+    /// This applies to imports as well as to completed downloads.
+    Immediate,
+}
+
+/// Statistics about a cleanup of orphaned temp files, see [`Store::cleanup_orphans`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CleanupStats {
+    /// Number of files that were removed
+    pub files: u64,
+    /// Total size of the removed files in bytes
+    pub bytes: u64,
+}
+
+/// Progress of loading a [`Store`] from disk, see [`Store::load_with_progress`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// Number of entries that were loaded so far
+    pub done: u64,
+    /// Total number of entries found on disk
+    pub total: u64,
+}
+
+impl LoadProgress {
+    /// How often progress is reported while loading entries.
+    const INTERVAL: u64 = 1024;
+
+    /// The percentage of entries that were loaded.
+    pub fn percent(&self) -> u64 {
+        (self.done * 100).checked_div(self.total).unwrap_or(100)
+    }
+
+    /// Counts a loaded entry, and reports progress every few entries and at the end.
+    fn inc(&mut self, progress: &dyn Fn(LoadProgress)) {
+        self.done += 1;
+        if self.done % Self::INTERVAL == 0 || self.done == self.total {
+            progress(*self);
+        }
+    }
+}
+
 impl MapEntry<Store> for PartialEntry {
     fn hash(&self) -> blake3::Hash {
         self.hash
@@ -251,7 +377,13 @@ impl MapEntry<Store> for PartialEntry {
     }
 
     fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<ChunkNum>>> {
-        futures::future::ok(RangeSet2::all()).boxed()
+        file_available_ranges(
+            self.hash,
+            self.size,
+            self.data_path.clone(),
+            self.outboard_path.clone(),
+        )
+        .boxed()
     }
 
     fn outboard(&self) -> BoxFuture<'_, io::Result<<Store as Map>::Outboard>> {
@@ -366,10 +498,18 @@ impl PartialMap for Store {
         self.0
             .options
             .rt
-            .spawn_blocking(move || this.insert_complete_sync(entry))
+            .spawn_blocking(move || {
+                let res = this.insert_complete_sync(entry);
+                this.0.data_written.notify_waiters();
+                res
+            })
             .map(flatten_to_io)
             .boxed()
     }
+
+    fn notify_data_written(&self, _hash: &Hash) {
+        self.0.data_written.notify_waiters();
+    }
 }
 
 #[derive(Debug)]
@@ -380,6 +520,7 @@ struct Options {
     move_threshold: u64,
     inline_threshold: u64,
     rt: tokio::runtime::Handle,
+    local_pool: LocalPoolHandle,
 }
 
 impl Options {
@@ -397,6 +538,11 @@ impl Options {
         self.complete_path.join(FileName::Data(*hash).to_string())
     }
 
+    fn compressed_data_path(&self, hash: &Hash) -> PathBuf {
+        self.complete_path
+            .join(FileName::CompressedData(*hash).to_string())
+    }
+
     fn owned_outboard_path(&self, hash: &Hash) -> PathBuf {
         self.complete_path
             .join(FileName::Outboard(*hash).to_string())
@@ -417,11 +563,29 @@ struct Inner {
     options: Options,
     state: RwLock<State>,
     tags: RwLock<BTreeMap<Tag, HashAndFormat>>,
+    // pins and their expiry, persisted like the tags
+    pins: RwLock<BTreeMap<HashAndFormat, Option<SystemTime>>>,
     // mutex for async access to complete files
     //
     // complete files are never written to. They come into existence when a partial
     // entry is completed, and are deleted as a whole.
     complete_io_mutex: Mutex<()>,
+    // temp files of stream imports that are still being written, see `Store::import_stream`
+    stream_imports: Mutex<BTreeSet<PathBuf>>,
+    // when to flush inserted content to disk
+    durability: RwLock<DurabilityMode>,
+    // whether to verify existing locations when adding data for a complete entry
+    verify_merges: AtomicBool,
+    // whether to compress the data files of new entries
+    compress_data: AtomicBool,
+    // the format version in the version file of the store
+    format_version: Mutex<u16>,
+    // notified whenever data is written to a partial entry, or an entry is completed
+    data_written: Notify,
+    // open handles to complete data files
+    file_handles: Arc<FileHandles>,
+    // outboards of complete entries that are kept in memory
+    outboards: Arc<OutboardCache>,
 }
 
 /// Flat file database implementation.
@@ -451,7 +615,15 @@ impl MapEntry<Store> for Entry {
     }
 
     fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<ChunkNum>>> {
-        futures::future::ok(RangeSet2::all()).boxed()
+        match (&self.entry.data, &self.entry.outboard) {
+            (Either::Right((data_path, size)), Either::Right(outboard_path))
+                if !self.is_complete =>
+            {
+                file_available_ranges(self.hash, *size, data_path.clone(), outboard_path.clone())
+                    .boxed()
+            }
+            _ => futures::future::ok(RangeSet2::all()).boxed(),
+        }
     }
 
     fn outboard(&self) -> BoxFuture<'_, io::Result<PreOrderOutboard<MemOrFile>>> {
@@ -488,6 +660,12 @@ struct EntryData {
     data: Either<Bytes, (PathBuf, u64)>,
     /// The bao outboard data.
     outboard: Either<Bytes, PathBuf>,
+    /// Whether the data file is block compressed.
+    compressed: bool,
+    /// Shared handles for the data file, if it is complete.
+    handles: Option<Arc<FileHandles>>,
+    /// The hash and the shared cache for the outboard, if it is complete.
+    outboards: Option<(Hash, Arc<OutboardCache>)>,
 }
 
 /// A reader for either a file or a byte slice.
@@ -499,31 +677,356 @@ pub enum MemOrFile {
     Mem(Bytes),
     /// An iroh_io::File
     File(File),
+    /// A complete data file, read through a shared handle
+    Shared(SharedFile),
+    /// A complete block compressed data file
+    Compressed(CompressedFile),
 }
 
 impl AsyncSliceReader for MemOrFile {
     type ReadAtFuture<'a> = futures::future::Either<
-        <Bytes as AsyncSliceReader>::ReadAtFuture<'a>,
-        <File as AsyncSliceReader>::ReadAtFuture<'a>,
+        futures::future::Either<
+            <Bytes as AsyncSliceReader>::ReadAtFuture<'a>,
+            <File as AsyncSliceReader>::ReadAtFuture<'a>,
+        >,
+        BoxFuture<'a, io::Result<Bytes>>,
     >;
 
     fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
         match self {
-            MemOrFile::Mem(mem) => Either::Left(mem.read_at(offset, len)),
-            MemOrFile::File(file) => Either::Right(file.read_at(offset, len)),
+            MemOrFile::Mem(mem) => Either::Left(Either::Left(mem.read_at(offset, len))),
+            MemOrFile::File(file) => Either::Left(Either::Right(file.read_at(offset, len))),
+            MemOrFile::Shared(file) => Either::Right(file.read_at(offset, len)),
+            MemOrFile::Compressed(file) => Either::Right(file.read_at(offset, len)),
         }
     }
 
     type LenFuture<'a> = futures::future::Either<
-        <Bytes as AsyncSliceReader>::LenFuture<'a>,
-        <File as AsyncSliceReader>::LenFuture<'a>,
+        futures::future::Either<
+            <Bytes as AsyncSliceReader>::LenFuture<'a>,
+            <File as AsyncSliceReader>::LenFuture<'a>,
+        >,
+        futures::future::Ready<io::Result<u64>>,
     >;
 
     fn len(&mut self) -> Self::LenFuture<'_> {
         match self {
-            MemOrFile::Mem(mem) => Either::Left(mem.len()),
-            MemOrFile::File(file) => Either::Right(file.len()),
+            MemOrFile::Mem(mem) => Either::Left(Either::Left(mem.len())),
+            MemOrFile::File(file) => Either::Left(Either::Right(file.len())),
+            MemOrFile::Shared(file) => Either::Right(file.len()),
+            MemOrFile::Compressed(file) => Either::Right(file.len()),
+        }
+    }
+}
+
+/// Maximum number of complete data files that are open for reading at the same time.
+const MAX_OPEN_FILES: usize = 256;
+
+/// A bounded cache of open handles to complete data files.
+///
+/// Every open handle holds a permit of `permits`, also after it was evicted from the cache
+/// while a reader still uses it, so the number of open files never exceeds the limit.
+#[derive(Debug)]
+struct FileHandles {
+    cache: Mutex<LruCache<PathBuf, Arc<FileHandle>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl FileHandles {
+    fn new(max_open: usize) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(max_open)),
+            permits: Arc::new(Semaphore::new(max_open)),
+        }
+    }
+
+    /// Get a reader for the file at `path`, reusing an open handle if there is one.
+    async fn open(&self, path: PathBuf) -> io::Result<SharedFile> {
+        if let Some(handle) = self.cache.lock().unwrap().get_mut(&path) {
+            return Ok(SharedFile(handle.clone()));
+        }
+        let permit = self.acquire().await?;
+        let handle = tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::open(&path)?;
+            let len = file.metadata()?.len();
+            io::Result::Ok(FileHandle {
+                path,
+                file,
+                len,
+                _permit: Some(permit),
+            })
+        })
+        .await;
+        let handle = Arc::new(flatten_to_io(handle)?);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(handle.path.clone(), handle.clone());
+        Ok(SharedFile(handle))
+    }
+
+    /// Close the cached handle for `path`, once its current readers are done.
+    ///
+    /// Must be called when the file is removed or replaced.
+    fn invalidate(&self, path: &Path) {
+        self.cache.lock().unwrap().remove(path);
+    }
+
+    async fn acquire(&self) -> io::Result<OwnedSemaphorePermit> {
+        loop {
+            match self.permits.clone().try_acquire_owned() {
+                Ok(permit) => return Ok(permit),
+                Err(TryAcquireError::NoPermits) => {}
+                Err(TryAcquireError::Closed) => break,
+            }
+            // close the least recently used handle that is not in use
+            let mut cache = self.cache.lock().unwrap();
+            let idle = cache
+                .iter()
+                .find(|(_, handle)| Arc::strong_count(handle) == 1)
+                .map(|(path, _)| path.clone());
+            match idle {
+                Some(path) => cache.remove(&path),
+                None => break,
+            };
+        }
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|cause| io::Error::new(io::ErrorKind::Other, cause))
+    }
+}
+
+#[derive(Debug)]
+struct FileHandle {
+    path: PathBuf,
+    file: std::fs::File,
+    len: u64,
+    /// The permit of a shared handle, see [`FileHandles`].
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl FileHandle {
+    /// Open a handle that is not shared, and does not count towards the open files.
+    fn open_unshared(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            len,
+            _permit: None,
+        })
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> io::Result<Bytes> {
+        let len = self.len.saturating_sub(offset).min(len as u64) as usize;
+        let mut buf = vec![0u8; len];
+        let mut read = 0;
+        while read < len {
+            match self.file.read_at(offset + read as u64, &mut buf[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(cause) if cause.kind() == io::ErrorKind::Interrupted => {}
+                Err(cause) => return Err(cause),
+            }
+        }
+        buf.truncate(read);
+        Ok(buf.into())
+    }
+}
+
+/// A reader for a complete data file.
+///
+/// Readers of the same file share one handle, and read from it at explicit offsets.
+#[derive(Debug, Clone)]
+pub struct SharedFile(Arc<FileHandle>);
+
+impl AsyncSliceReader for SharedFile {
+    type ReadAtFuture<'a> = BoxFuture<'a, io::Result<Bytes>>;
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+        let handle = self.0.clone();
+        tokio::task::spawn_blocking(move || handle.read_at(offset, len))
+            .map(flatten_to_io)
+            .boxed()
+    }
+
+    type LenFuture<'a> = futures::future::Ready<io::Result<u64>>;
+
+    fn len(&mut self) -> Self::LenFuture<'_> {
+        futures::future::ok(self.0.len)
+    }
+}
+
+/// A reader for a complete block compressed data file.
+///
+/// Reads only decompress the blocks they overlap.
+#[derive(Debug, Clone)]
+pub struct CompressedFile {
+    file: SharedFile,
+    index: Arc<BlockIndex>,
+}
+
+impl CompressedFile {
+    async fn open(handles: &FileHandles, path: PathBuf) -> io::Result<Self> {
+        let file = handles.open(path).await?;
+        let handle = file.0.clone();
+        let index = tokio::task::spawn_blocking(move || {
+            BlockIndex::read(&handle)?
+                .ok_or_else(|| invalid_data("data file is not block compressed"))
+        })
+        .await;
+        Ok(Self {
+            file,
+            index: Arc::new(flatten_to_io(index)?),
+        })
+    }
+}
+
+impl AsyncSliceReader for CompressedFile {
+    type ReadAtFuture<'a> = BoxFuture<'a, io::Result<Bytes>>;
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Self::ReadAtFuture<'_> {
+        let this = self.clone();
+        tokio::task::spawn_blocking(move || this.index.read_at(&this.file.0, offset, len))
+            .map(flatten_to_io)
+            .boxed()
+    }
+
+    type LenFuture<'a> = futures::future::Ready<io::Result<u64>>;
+
+    fn len(&mut self) -> Self::LenFuture<'_> {
+        futures::future::ok(self.index.size)
+    }
+}
+
+/// Default for the maximum total size of the outboards kept in memory, 64 MiB.
+///
+/// The outboard of a blob is about 1/256 of its size, so this covers the outboards of
+/// 16 GiB of frequently read data.
+pub const DEFAULT_OUTBOARD_CACHE_SIZE: u64 = 1024 * 1024 * 64;
+
+/// A size bounded cache of the outboards of complete entries, shared by all readers.
+///
+/// Outboards are read from their files on a miss. Concurrent misses for the same outboard
+/// wait for the first one to read it. The least recently used outboards are dropped once
+/// the total size of the cached outboards exceeds the capacity. Outboards that are larger
+/// than the capacity are not cached at all, but read from their files.
+#[derive(Debug)]
+struct OutboardCache(Mutex<OutboardCacheInner>);
+
+#[derive(Debug)]
+struct OutboardCacheInner {
+    cache: LruCache<Hash, Bytes>,
+    /// Locks for the outboards that are currently being read.
+    loading: BTreeMap<Hash, Arc<tokio::sync::Mutex<()>>>,
+    size: u64,
+    capacity: u64,
+}
+
+impl OutboardCacheInner {
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, outboard)) = self.cache.remove_lru() else {
+                break;
+            };
+            self.size -= outboard.len() as u64;
+        }
+    }
+}
+
+/// Removes the load lock of an outboard when the load is done or cancelled.
+struct OutboardLoad<'a> {
+    cache: &'a OutboardCache,
+    hash: Hash,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for OutboardLoad<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.cache.0.lock().unwrap();
+        if let Some(lock) = inner.loading.get(&self.hash) {
+            if Arc::ptr_eq(lock, &self.lock) {
+                inner.loading.remove(&self.hash);
+            }
+        }
+    }
+}
+
+impl OutboardCache {
+    fn new(capacity: u64) -> Self {
+        Self(Mutex::new(OutboardCacheInner {
+            cache: LruCache::new(usize::MAX),
+            loading: BTreeMap::new(),
+            size: 0,
+            capacity,
+        }))
+    }
+
+    fn get(&self, hash: &Hash) -> Option<Bytes> {
+        self.0.lock().unwrap().cache.get_mut(hash).cloned()
+    }
+
+    /// Get the outboard for `hash`, reading it from `path` if it is not cached.
+    ///
+    /// Outboards that are too large for the cache are not read into memory.
+    async fn load(&self, hash: Hash, path: PathBuf) -> io::Result<MemOrFile> {
+        let load = {
+            let mut inner = self.0.lock().unwrap();
+            if let Some(outboard) = inner.cache.get_mut(&hash) {
+                return Ok(MemOrFile::Mem(outboard.clone()));
+            }
+            let lock = inner.loading.entry(hash).or_default().clone();
+            OutboardLoad {
+                cache: self,
+                hash,
+                lock,
+            }
+        };
+        let _guard = load.lock.lock().await;
+        // another reader might have loaded it while we were waiting
+        if let Some(outboard) = self.get(&hash) {
+            return Ok(MemOrFile::Mem(outboard));
+        }
+        let size = tokio::fs::metadata(&path).await?.len();
+        if size > self.capacity() {
+            return Ok(MemOrFile::File(File::open(path).await?));
         }
+        let outboard: Bytes =
+            flatten_to_io(tokio::task::spawn_blocking(move || std::fs::read(path)).await)?.into();
+        self.insert(hash, outboard.clone());
+        Ok(MemOrFile::Mem(outboard))
+    }
+
+    fn insert(&self, hash: Hash, outboard: Bytes) {
+        let mut inner = self.0.lock().unwrap();
+        if outboard.len() as u64 > inner.capacity {
+            return;
+        }
+        inner.size += outboard.len() as u64;
+        if let Some(old) = inner.cache.insert(hash, outboard) {
+            inner.size -= old.len() as u64;
+        }
+        inner.evict();
+    }
+
+    fn remove(&self, hash: &Hash) {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(old) = inner.cache.remove(hash) {
+            inner.size -= old.len() as u64;
+        }
+    }
+
+    fn set_capacity(&self, capacity: u64) {
+        let mut inner = self.0.lock().unwrap();
+        inner.capacity = capacity;
+        inner.evict();
+    }
+
+    fn capacity(&self) -> u64 {
+        self.0.lock().unwrap().capacity
     }
 }
 
@@ -531,10 +1034,14 @@ impl EntryData {
     /// Get the outboard data for this entry, as a `Bytes`.
     pub fn outboard_reader(&self) -> impl Future<Output = io::Result<MemOrFile>> + 'static {
         let outboard = self.outboard.clone();
+        let outboards = self.outboards.clone();
         async move {
-            Ok(match outboard {
-                Either::Left(mem) => MemOrFile::Mem(mem),
-                Either::Right(path) => MemOrFile::File(File::open(path).await?),
+            Ok(match (outboard, outboards) {
+                (Either::Left(mem), _) => MemOrFile::Mem(mem),
+                (Either::Right(path), Some((hash, outboards))) => {
+                    outboards.load(hash, path).await?
+                }
+                (Either::Right(path), None) => MemOrFile::File(File::open(path).await?),
             })
         }
     }
@@ -542,10 +1049,18 @@ impl EntryData {
     /// A reader for the data.
     pub fn data_reader(&self) -> impl Future<Output = io::Result<MemOrFile>> + 'static {
         let data = self.data.clone();
+        let compressed = self.compressed;
+        let handles = self.handles.clone();
         async move {
-            Ok(match data {
-                Either::Left(mem) => MemOrFile::Mem(mem),
-                Either::Right((path, _)) => MemOrFile::File(File::open(path).await?),
+            Ok(match (data, handles) {
+                (Either::Left(mem), _) => MemOrFile::Mem(mem),
+                (Either::Right((path, _)), Some(handles)) if compressed => {
+                    MemOrFile::Compressed(CompressedFile::open(&handles, path).await?)
+                }
+                (Either::Right((path, _)), Some(handles)) => {
+                    MemOrFile::Shared(handles.open(path).await?)
+                }
+                (Either::Right((path, _)), None) => MemOrFile::File(File::open(path).await?),
             })
         }
     }
@@ -559,6 +1074,36 @@ impl EntryData {
     }
 }
 
+/// Compute the ranges of a partial entry that are available from its files.
+async fn file_available_ranges(
+    hash: blake3::Hash,
+    size: u64,
+    data_path: PathBuf,
+    outboard_path: PathBuf,
+) -> io::Result<RangeSet2<ChunkNum>> {
+    let res = tokio::task::spawn_blocking(move || {
+        let (data, outboard) = match (
+            std::fs::File::open(data_path),
+            std::fs::File::open(outboard_path),
+        ) {
+            (Ok(data), Ok(outboard)) => (data, outboard),
+            // nothing was written yet
+            (Err(e), _) | (_, Err(e)) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(RangeSet2::empty())
+            }
+            (Err(e), _) | (_, Err(e)) => return Err(e),
+        };
+        let outboard = PreOrderOutboard {
+            root: hash,
+            tree: BaoTree::new(ByteNum(size), IROH_BLOCK_SIZE),
+            data: outboard,
+        };
+        super::partial_available_ranges(&outboard, data.metadata()?.len())
+    })
+    .await;
+    flatten_to_io(res)
+}
+
 fn needs_outboard(size: u64) -> bool {
     size > (IROH_BLOCK_SIZE.bytes() as u64)
 }
@@ -580,7 +1125,12 @@ impl Map for Store {
         let state = self.0.state.read().unwrap();
         if let Some(entry) = state.complete.get(hash) {
             tracing::trace!("got complete: {} {}", hash, entry.size);
-            let outboard = state.load_outboard(entry.size, hash)?;
+            // for small entries the outboard consists of just the le encoded size
+            let outboard = if needs_outboard(entry.size) {
+                Either::Right(self.owned_outboard_path(hash))
+            } else {
+                Either::Left(Bytes::from(entry.size.to_le_bytes().to_vec()))
+            };
             // check if we have the data cached
             let data = state.data.get(hash).cloned();
             Some(Entry {
@@ -591,7 +1141,10 @@ impl Map for Store {
                         Either::Left(data)
                     } else {
                         // get the data path
-                        let path = if entry.owned_data {
+                        let path = if entry.owned_data && entry.compressed {
+                            // only large data is not cached, it is block compressed
+                            self.0.options.compressed_data_path(hash)
+                        } else if entry.owned_data {
                             // use the path for the data in the default location
                             self.owned_data_path(hash)
                         } else {
@@ -601,7 +1154,10 @@ impl Map for Store {
                         };
                         Either::Right((path, entry.size))
                     },
-                    outboard: Either::Left(outboard),
+                    outboard,
+                    compressed: entry.owned_data && entry.compressed,
+                    handles: Some(self.0.file_handles.clone()),
+                    outboards: Some((*hash, self.0.outboards.clone())),
                 },
             })
         } else if let Some(entry) = state.partial.get(hash) {
@@ -619,6 +1175,9 @@ impl Map for Store {
                 entry: EntryData {
                     data: Either::Right((data_path, entry.size)),
                     outboard: Either::Right(outboard_path),
+                    compressed: false,
+                    handles: None,
+                    outboards: None,
                 },
             })
         } else {
@@ -637,6 +1196,16 @@ impl Map for Store {
             EntryStatus::NotFound
         }
     }
+
+    fn data_written(&self, _hash: &Hash) -> BoxFuture<'static, ()> {
+        let inner = self.0.clone();
+        async move {
+            tokio::time::timeout(DATA_POLL_INTERVAL, inner.data_written.notified())
+                .await
+                .ok();
+        }
+        .boxed()
+    }
 }
 
 impl ReadableStore for Store {
@@ -652,6 +1221,13 @@ impl ReadableStore for Store {
         Box::new(items.into_iter())
     }
 
+    fn pins(
+        &self,
+    ) -> Box<dyn Iterator<Item = (HashAndFormat, Option<Duration>)> + Send + Sync + 'static> {
+        let items = live_pins(&self.0.pins.read().unwrap(), SystemTime::now());
+        Box::new(items.into_iter())
+    }
+
     fn tags(&self) -> Box<dyn Iterator<Item = (Tag, HashAndFormat)> + Send + Sync + 'static> {
         let inner = self.0.tags.read().unwrap();
         let items = inner
@@ -661,8 +1237,29 @@ impl ReadableStore for Store {
         Box::new(items.into_iter())
     }
 
-    fn validate(&self, _tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>> {
-        unimplemented!()
+    fn validate(&self, tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.validate_with_options(Default::default(), tx)
+    }
+
+    fn validate_with_concurrency(
+        &self,
+        concurrency: usize,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let options = ValidateOptions {
+            concurrency,
+            ..Default::default()
+        };
+        self.validate_with_options(options, tx)
+    }
+
+    fn validate_with_options(
+        &self,
+        options: ValidateOptions,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let hashes = self.blobs().collect();
+        baomap::validate_blobs(self, hashes, options, &self.0.options.local_pool, tx).boxed()
     }
 
     fn partial_blobs(&self) -> Box<dyn Iterator<Item = Hash> + Send + Sync + 'static> {
@@ -686,6 +1283,17 @@ impl ReadableStore for Store {
             .map(flatten_to_io)
             .boxed()
     }
+
+    #[cfg(feature = "mmap")]
+    fn export_mmap(&self, hash: Hash) -> LocalBoxFuture<'_, io::Result<baomap::MappedBlob>> {
+        let this = self.clone();
+        self.0
+            .options
+            .rt
+            .spawn_blocking(move || this.export_mmap_sync(hash))
+            .map(flatten_to_io)
+            .boxed_local()
+    }
 }
 
 impl baomap::Store for Store {
@@ -715,6 +1323,25 @@ impl baomap::Store for Store {
             .boxed()
     }
 
+    fn import_stream(
+        &self,
+        data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> BoxFuture<'_, io::Result<(TempTag, u64)>> {
+        self.clone()
+            .import_stream_impl(data, format, progress)
+            .boxed()
+    }
+
+    fn available_space(&self) -> io::Result<Option<u64>> {
+        // data is written to the partial directory first, and then moved into the complete
+        // directory, which might be on a different file system
+        let partial = fs2::available_space(&self.0.options.partial_path)?;
+        let complete = fs2::available_space(&self.0.options.complete_path)?;
+        Ok(Some(partial.min(complete)))
+    }
+
     fn create_tag(&self, value: HashAndFormat) -> BoxFuture<'_, io::Result<Tag>> {
         let this = self.clone();
         self.0
@@ -739,6 +1366,30 @@ impl baomap::Store for Store {
         TempTag::new(inner, Some(self.0.clone()))
     }
 
+    fn pin(
+        &self,
+        value: HashAndFormat,
+        expiry: Option<SystemTime>,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        let this = self.clone();
+        self.0
+            .options
+            .rt
+            .spawn_blocking(move || this.set_pin_sync(value, Some(expiry)))
+            .map(flatten_to_io)
+            .boxed()
+    }
+
+    fn unpin(&self, value: HashAndFormat) -> BoxFuture<'_, io::Result<()>> {
+        let this = self.clone();
+        self.0
+            .options
+            .rt
+            .spawn_blocking(move || this.set_pin_sync(value, None))
+            .map(flatten_to_io)
+            .boxed()
+    }
+
     fn clear_live(&self) {
         let mut state = self.0.state.write().unwrap();
         state.live.clear();
@@ -789,20 +1440,6 @@ impl LivenessTracker for Inner {
     }
 }
 
-impl State {
-    /// Gets or creates the outboard data for the given hash.
-    ///
-    /// For small entries the outboard consists of just the le encoded size,
-    /// so we create it on demand.
-    fn load_outboard(&self, size: u64, hash: &Hash) -> Option<Bytes> {
-        if needs_outboard(size) {
-            self.outboard.get(hash).cloned()
-        } else {
-            Some(Bytes::from(size.to_le_bytes().to_vec()))
-        }
-    }
-}
-
 impl Store {
     fn import_sync(
         self,
@@ -829,7 +1466,7 @@ impl Store {
             id,
             path: path.clone(),
         })?;
-        let (tag, new, outboard) = match mode {
+        let (tag, new, outboard, inline_data) = match mode {
             ImportMode::TryReference => {
                 // compute outboard and hash from the data in place, since we assume that it is stable
                 let size = path.metadata()?.len();
@@ -841,66 +1478,179 @@ impl Store {
                 progress.blocking_send(ImportProgress::OutboardDone { id, hash })?;
                 use baomap::Store;
                 let tag = self.temp_tag(HashAndFormat(hash, format));
-                (tag, CompleteEntry::new_external(size, path), outboard)
+                (tag, CompleteEntry::new_external(size, path), outboard, None)
             }
             ImportMode::Copy => {
-                let uuid = new_uuid();
-                let temp_data_path = self
-                    .0
-                    .options
-                    .partial_path
-                    .join(format!("{}.temp", hex::encode(uuid)));
+                let temp_data = TempFile::new(self.new_import_temp_path());
                 // copy the data, since it is not stable
                 progress.try_send(ImportProgress::CopyProgress { id, offset: 0 })?;
-                let size = std::fs::copy(&path, &temp_data_path)?;
+                let size = std::fs::copy(&path, temp_data.path())?;
                 // report the size only after the copy is done
                 progress.blocking_send(ImportProgress::Size { id, size })?;
-                // compute outboard and hash from the temp file that we own
-                let progress2 = progress.clone();
-                let (hash, outboard) = compute_outboard(&temp_data_path, size, move |offset| {
-                    Ok(progress2.try_send(ImportProgress::OutboardProgress { id, offset })?)
-                })?;
-                progress.blocking_send(ImportProgress::OutboardDone { id, hash })?;
-                let data_path = self.owned_data_path(&hash);
-                use baomap::Store;
-                // the blob must be pinned before we move the file, otherwise there is a race condition
-                // where it might be deleted here.
-                let tag = self.temp_tag(HashAndFormat(hash, BlobFormat::RAW));
-                std::fs::rename(temp_data_path, data_path)?;
-                (tag, CompleteEntry::new_default(size), outboard)
+                self.import_temp_file_locked(id, temp_data, size, format, progress)?
             }
         };
-        // all writes here are protected by the temp tag
-        let hash = *tag.hash();
-        if let Some(outboard) = outboard.as_ref() {
-            let outboard_path = self.owned_outboard_path(&hash);
-            std::fs::write(outboard_path, outboard)?;
-        }
-        let size = new.size;
-        let mut state = self.0.state.write().unwrap();
-        let entry = state.complete.entry(hash).or_default();
-        let n = entry.external.len();
-        entry.union_with(new)?;
-        if entry.external.len() != n {
-            let temp_path = self.0.options.temp_paths_path(hash, &new_uuid());
-            let final_path = self.0.options.paths_path(hash);
-            write_atomic(&temp_path, &final_path, &entry.external_to_bytes())?;
-        }
-        if let Some(outboard) = outboard {
-            state.outboard.insert(hash, outboard.into());
-        }
+        let res = self.finish_import_locked(tag, new, outboard, inline_data)?;
         drop(complete_io_guard);
-        Ok((tag, size))
+        Ok(res)
     }
 
-    fn set_tag_sync(&self, name: Tag, value: Option<HashAndFormat>) -> io::Result<()> {
-        tracing::info!("set_tag {} {:?}", name, value);
-        let mut tags = self.0.tags.write().unwrap();
-        let mut new_tags = tags.clone();
-        let changed = if let Some(value) = value {
-            if let Some(old_value) = new_tags.insert(name, value) {
-                value != old_value
-            } else {
+    async fn import_stream_impl(
+        self,
+        mut data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, u64)> {
+        let id = progress.new_id();
+        let (registration, temp_data) = {
+            // imports create their temp files while holding this lock
+            let _complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
+            let path = self.new_import_temp_path();
+            // the file is written without holding the lock, so keep cleanup from removing it
+            self.0.stream_imports.lock().unwrap().insert(path.clone());
+            let registration = StreamImport {
+                store: self.clone(),
+                path: path.clone(),
+            };
+            (registration, TempFile::new(path))
+        };
+        let mut file = tokio::fs::File::create(temp_data.path()).await?;
+        let mut size = 0;
+        while let Some(chunk) = data.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+            progress
+                .send(ImportProgress::CopyProgress { id, offset: size })
+                .await?;
+        }
+        file.flush().await?;
+        drop(file);
+        progress.send(ImportProgress::Size { id, size }).await?;
+        let this = self.clone();
+        self.0
+            .options
+            .rt
+            .spawn_blocking(move || {
+                let complete_io_guard = this.0.complete_io_mutex.lock().unwrap();
+                let (tag, new, outboard, inline_data) =
+                    this.import_temp_file_locked(id, temp_data, size, format, progress)?;
+                // the temp file has been moved or removed at this point
+                drop(registration);
+                let res = this.finish_import_locked(tag, new, outboard, inline_data)?;
+                drop(complete_io_guard);
+                Ok(res)
+            })
+            .map(flatten_to_io)
+            .await
+    }
+
+    /// Path of a new temp file for an import, in the partial directory.
+    fn new_import_temp_path(&self) -> PathBuf {
+        self.0
+            .options
+            .partial_path
+            .join(format!("{}.temp", hex::encode(new_uuid())))
+    }
+
+    /// Compute the outboard of a temp file that we own and move it into the complete directory.
+    ///
+    /// Must be called with the complete io mutex held.
+    #[allow(clippy::type_complexity)]
+    fn import_temp_file_locked(
+        &self,
+        id: u64,
+        temp_data: TempFile,
+        size: u64,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> io::Result<(TempTag, CompleteEntry, Option<Vec<u8>>, Option<Bytes>)> {
+        // compute outboard and hash from the temp file that we own
+        let progress2 = progress.clone();
+        let (hash, outboard) = compute_outboard(temp_data.path(), size, move |offset| {
+            Ok(progress2.try_send(ImportProgress::OutboardProgress { id, offset })?)
+        })?;
+        progress.blocking_send(ImportProgress::OutboardDone { id, hash })?;
+        let data_path = self.owned_data_path(&hash);
+        use baomap::Store;
+        // the blob must be pinned before we move the file, otherwise there is a race condition
+        // where it might be deleted here.
+        let tag = self.temp_tag(HashAndFormat(hash, format));
+        let compressed = self.should_compress(&hash);
+        let mut inline_data = None;
+        if compressed {
+            // the temp file is removed when it goes out of scope
+            let data = std::fs::File::open(temp_data.path())?;
+            inline_data = self.write_compressed_data(&hash, data, size)?;
+        } else {
+            if self.is_durable() {
+                sync_file(temp_data.path())?;
+            }
+            temp_data.persist(&data_path)?;
+        }
+        Ok((
+            tag,
+            CompleteEntry::new_owned(size, compressed),
+            outboard,
+            inline_data,
+        ))
+    }
+
+    /// Add an imported entry to the complete section of the store.
+    ///
+    /// Must be called with the complete io mutex held.
+    fn finish_import_locked(
+        &self,
+        tag: TempTag,
+        new: CompleteEntry,
+        outboard: Option<Vec<u8>>,
+        inline_data: Option<Bytes>,
+    ) -> io::Result<(TempTag, u64)> {
+        // all writes here are protected by the temp tag
+        let hash = *tag.hash();
+        self.verify_merge(&hash, &new)?;
+        let durable = self.is_durable();
+        if let Some(outboard) = outboard.as_ref() {
+            let outboard_path = self.owned_outboard_path(&hash);
+            std::fs::write(&outboard_path, outboard)?;
+            if durable {
+                sync_file(&outboard_path)?;
+            }
+        }
+        let size = new.size;
+        let mut state = self.0.state.write().unwrap();
+        let entry = state.complete.entry(hash).or_default();
+        let n = entry.external.len();
+        entry.union_with(new)?;
+        if entry.external.len() != n {
+            let temp_path = self.0.options.temp_paths_path(hash, &new_uuid());
+            let final_path = self.0.options.paths_path(hash);
+            write_atomic(&temp_path, &final_path, &entry.external_to_bytes())?;
+            if durable {
+                sync_file(&final_path)?;
+            }
+        }
+        if let Some(outboard) = outboard {
+            self.0.outboards.insert(hash, outboard.into());
+        }
+        if let Some(data) = inline_data {
+            state.data.insert(hash, data);
+        }
+        drop(state);
+        if durable {
+            sync_dir(&self.0.options.complete_path)?;
+        }
+        Ok((tag, size))
+    }
+
+    fn set_tag_sync(&self, name: Tag, value: Option<HashAndFormat>) -> io::Result<()> {
+        tracing::info!("set_tag {} {:?}", name, value);
+        let mut tags = self.0.tags.write().unwrap();
+        let mut new_tags = tags.clone();
+        let changed = if let Some(value) = value {
+            if let Some(old_value) = new_tags.insert(name, value) {
+                value != old_value
+            } else {
                 true
             }
         } else {
@@ -940,23 +1690,71 @@ impl Store {
         Ok(tag)
     }
 
+    fn set_pin_sync(
+        &self,
+        value: HashAndFormat,
+        expiry: Option<Option<SystemTime>>,
+    ) -> io::Result<()> {
+        tracing::info!("set_pin {:?} {:?}", value, expiry);
+        let mut pins = self.0.pins.write().unwrap();
+        let mut new_pins = pins.clone();
+        // drop expired pins, so they don't accumulate on disk
+        let now = SystemTime::now();
+        new_pins.retain(|_, expiry| expiry.map_or(true, |expiry| expiry > now));
+        if let Some(expiry) = expiry {
+            new_pins.insert(value, expiry);
+        } else {
+            new_pins.remove(&value);
+        }
+        if new_pins != *pins {
+            let serialized = postcard::to_stdvec(&new_pins).unwrap();
+            let temp_path = self
+                .0
+                .options
+                .meta_path
+                .join(format!("pins-{}.meta", hex::encode(new_uuid())));
+            let final_path = self.0.options.meta_path.join("pins.meta");
+            write_atomic(&temp_path, &final_path, &serialized)?;
+            *pins = new_pins;
+        }
+        drop(pins);
+        Ok(())
+    }
+
     fn import_bytes_sync(&self, data: Bytes, format: BlobFormat) -> io::Result<TempTag> {
         let complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
         let (outboard, hash) = bao_tree::io::outboard(&data, IROH_BLOCK_SIZE);
         let hash = hash.into();
         use baomap::Store;
         let tag = self.temp_tag(HashAndFormat(hash, format));
-        let data_path = self.owned_data_path(&hash);
-        std::fs::write(data_path, &data)?;
+        let size = data.len() as u64;
+        let compressed = self.should_compress(&hash);
+        let new = CompleteEntry::new_owned(size, compressed);
+        self.verify_merge(&hash, &new)?;
+        let durable = self.is_durable();
+        if compressed {
+            self.write_compressed_data(&hash, &data[..], size)?;
+        } else {
+            let data_path = self.owned_data_path(&hash);
+            std::fs::write(&data_path, &data)?;
+            if durable {
+                sync_file(&data_path)?;
+            }
+        }
         if outboard.len() > 8 {
             let outboard_path = self.owned_outboard_path(&hash);
-            std::fs::write(outboard_path, &outboard)?;
+            std::fs::write(&outboard_path, &outboard)?;
+            if durable {
+                sync_file(&outboard_path)?;
+            }
+        }
+        if durable {
+            sync_dir(&self.0.options.complete_path)?;
         }
-        let size = data.len() as u64;
         let mut state = self.0.state.write().unwrap();
         let entry = state.complete.entry(hash).or_default();
-        entry.union_with(CompleteEntry::new_default(size))?;
-        state.outboard.insert(hash, outboard.into());
+        entry.union_with(new)?;
+        self.0.outboards.insert(hash, outboard.into());
         if size < self.0.options.inline_threshold {
             state.data.insert(hash, data.to_vec().into());
         }
@@ -973,7 +1771,9 @@ impl Store {
         let complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
         let mut state = self.0.state.write().unwrap();
         if let Some(entry) = state.complete.remove(&hash) {
-            if entry.owned_data {
+            if entry.owned_data && entry.compressed {
+                data = Some(self.0.options.compressed_data_path(&hash));
+            } else if entry.owned_data {
                 data = Some(self.owned_data_path(&hash));
             }
             if needs_outboard(entry.size) {
@@ -982,6 +1782,9 @@ impl Store {
             if !entry.external.is_empty() {
                 external = Some(self.0.options.paths_path(hash));
             }
+            for path in &entry.external {
+                self.0.file_handles.invalidate(path);
+            }
         }
         if let Some(partial) = state.partial.remove(&hash) {
             partial_data = Some(self.0.options.partial_data_path(hash, &partial.uuid));
@@ -989,10 +1792,11 @@ impl Store {
                 partial_outboard = Some(self.0.options.partial_outboard_path(hash, &partial.uuid));
             }
         }
-        state.outboard.remove(&hash);
+        self.0.outboards.remove(&hash);
         state.data.remove(&hash);
         drop(state);
         if let Some(data) = data {
+            self.0.file_handles.invalidate(&data);
             if let Err(cause) = std::fs::remove_file(data) {
                 tracing::warn!("failed to delete data file: {}", cause);
             }
@@ -1029,10 +1833,30 @@ impl Store {
         let size = entry.size;
         let temp_data_path = entry.data_path;
         let temp_outboard_path = entry.outboard_path;
+        let durable = self.is_durable();
+        if durable {
+            // make sure the content is on disk before it becomes visible as complete
+            sync_file(&temp_data_path)?;
+            if temp_outboard_path.exists() {
+                sync_file(&temp_outboard_path)?;
+            }
+        }
         let complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
+        let compressed = self.should_compress(&hash);
+        let new = CompleteEntry::new_owned(size, compressed);
+        self.verify_merge(&hash, &new)?;
         // for a short time we will have neither partial nor complete
         self.0.state.write().unwrap().partial.remove(&hash);
-        std::fs::rename(temp_data_path, data_path)?;
+        let inline_data = if compressed {
+            let data = std::fs::File::open(&temp_data_path)?;
+            let inline_data = self.write_compressed_data(&hash, data, size)?;
+            std::fs::remove_file(temp_data_path)?;
+            inline_data
+        } else {
+            self.0.file_handles.invalidate(&data_path);
+            std::fs::rename(temp_data_path, data_path)?;
+            None
+        };
         let outboard = if temp_outboard_path.exists() {
             let outboard_path = self.0.options.owned_outboard_path(&hash);
             std::fs::rename(temp_outboard_path, &outboard_path)?;
@@ -1040,11 +1864,17 @@ impl Store {
         } else {
             None
         };
+        if durable {
+            sync_dir(&self.0.options.complete_path)?;
+        }
         let mut state = self.0.state.write().unwrap();
         let entry = state.complete.entry(hash).or_default();
-        entry.union_with(CompleteEntry::new_default(size))?;
+        entry.union_with(new)?;
         if let Some(outboard) = outboard {
-            state.outboard.insert(hash, outboard);
+            self.0.outboards.insert(hash, outboard);
+        }
+        if let Some(data) = inline_data {
+            state.data.insert(hash, data);
         }
         drop(complete_io_guard);
         Ok(())
@@ -1073,12 +1903,21 @@ impl Store {
         })?;
         // create the directory in which the target file is
         std::fs::create_dir_all(parent)?;
-        let (source, size, owned) = {
+        let (source, size, owned, compressed, inline_data) = {
             let state = self.0.state.read().unwrap();
             let entry = state.complete.get(&hash).ok_or_else(|| {
                 io::Error::new(io::ErrorKind::NotFound, "hash not found in database")
             })?;
-            let source = if entry.owned_data {
+            let compressed = entry.owned_data && entry.compressed;
+            // small compressed data is available in memory
+            let inline_data = if compressed {
+                state.data.get(&hash).cloned()
+            } else {
+                None
+            };
+            let source = if compressed {
+                self.0.options.compressed_data_path(&hash)
+            } else if entry.owned_data {
                 self.owned_data_path(&hash)
             } else {
                 entry
@@ -1089,12 +1928,15 @@ impl Store {
                     .clone()
             };
             let size = entry.size;
-            (source, size, entry.owned_data)
+            (source, size, entry.owned_data, compressed, inline_data)
         };
         // copy all the things
         let stable = mode == ExportMode::TryReference;
+        // compressed data has no file that could be moved
+        let owned = owned && !compressed;
         let path_bytes = if size >= self.0.options.move_threshold && stable && owned {
             tracing::info!("moving {} to {}", source.display(), target.display());
+            self.0.file_handles.invalidate(&source);
             if let Err(e) = std::fs::rename(source, &target) {
                 tracing::error!("rename failed: {}", e);
                 return Err(e)?;
@@ -1113,7 +1955,13 @@ impl Store {
             tracing::info!("copying {} to {}", source.display(), target.display());
             progress(0)?;
             // todo: progress
-            std::fs::copy(&source, &target)?;
+            if let Some(data) = inline_data {
+                std::fs::write(&target, data)?;
+            } else if compressed {
+                decode_compressed_file(&source, std::fs::File::create(&target)?)?;
+            } else {
+                std::fs::copy(&source, &target)?;
+            }
             progress(size)?;
             let mut state = self.0.state.write().unwrap();
             let Some(entry) = state.complete.get_mut(&hash) else {
@@ -1136,13 +1984,62 @@ impl Store {
         Ok(())
     }
 
+    #[cfg(feature = "mmap")]
+    fn export_mmap_sync(&self, hash: Hash) -> io::Result<baomap::MappedBlob> {
+        let source = {
+            let state = self.0.state.read().unwrap();
+            let entry = state.complete.get(&hash).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "hash not found in database")
+            })?;
+            // small data is kept in memory, there is no file to map
+            if let Some(data) = state.data.get(&hash) {
+                return Ok(baomap::MappedBlob::Bytes(data.clone()));
+            }
+            if entry.owned_data && entry.compressed {
+                // the file does not hold the data itself, so decode it like verify_merge
+                let path = self.0.options.compressed_data_path(&hash);
+                drop(state);
+                let mut data = Vec::new();
+                decode_compressed_file(&path, &mut data)?;
+                if Hash::from(blake3::hash(&data)) != hash {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "decoded data does not match the hash",
+                    ));
+                }
+                return Ok(baomap::MappedBlob::Bytes(data.into()));
+            }
+            if entry.owned_data {
+                self.owned_data_path(&hash)
+            } else {
+                entry
+                    .external
+                    .iter()
+                    .next()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no valid path found"))?
+                    .clone()
+            }
+        };
+        tracing::trace!("mapping {} from {}", hash, source.display());
+        let file = std::fs::File::open(&source)?;
+        baomap::MappedBlob::map_verified(&file, &hash)
+    }
+
     /// scan a directory for data
+    ///
+    /// Stops with an error as soon as `cancel` is cancelled.
     pub(crate) fn load_sync(
         complete_path: PathBuf,
         partial_path: PathBuf,
         meta_path: PathBuf,
         rt: iroh_bytes::util::runtime::Handle,
+        progress: &dyn Fn(LoadProgress),
+        cancel: &CancellationToken,
     ) -> anyhow::Result<Self> {
+        let check_cancelled = || {
+            anyhow::ensure!(!cancel.is_cancelled(), "loading the store was cancelled");
+            Ok(())
+        };
         tracing::info!(
             "loading database from {} {}",
             complete_path.display(),
@@ -1151,12 +2048,22 @@ impl Store {
         std::fs::create_dir_all(&complete_path)?;
         std::fs::create_dir_all(&partial_path)?;
         std::fs::create_dir_all(&meta_path)?;
+        let version = read_format_version(&meta_path)?;
+        let version = migrate(&meta_path, version)?;
         let mut partial_index =
             BTreeMap::<Hash, BTreeMap<[u8; 16], (Option<PathBuf>, Option<PathBuf>)>>::new();
-        let mut full_index =
-            BTreeMap::<Hash, (Option<PathBuf>, Option<PathBuf>, Option<PathBuf>)>::new();
-        let mut outboard = BTreeMap::new();
+        let mut full_index = BTreeMap::<
+            Hash,
+            (
+                Option<PathBuf>,
+                Option<PathBuf>,
+                Option<PathBuf>,
+                Option<PathBuf>,
+            ),
+        >::new();
+        let mut data = BTreeMap::new();
         for entry in std::fs::read_dir(&partial_path)? {
+            check_cancelled()?;
             let entry = entry?;
             let path = entry.path();
             if path.is_file() {
@@ -1189,6 +2096,7 @@ impl Store {
         }
 
         for entry in std::fs::read_dir(&complete_path)? {
+            check_cancelled()?;
             let entry = entry?;
             let path = entry.path();
             if path.is_file() {
@@ -1203,17 +2111,21 @@ impl Store {
                 if let Ok(purpose) = FileName::from_str(name) {
                     match purpose {
                         FileName::Data(hash) => {
-                            let (data, _, _) = full_index.entry(hash).or_default();
+                            let (data, _, _, _) = full_index.entry(hash).or_default();
                             *data = Some(path);
                         }
                         FileName::Outboard(hash) => {
-                            let (_, outboard, _) = full_index.entry(hash).or_default();
+                            let (_, outboard, _, _) = full_index.entry(hash).or_default();
                             *outboard = Some(path);
                         }
                         FileName::Paths(hash) => {
-                            let (_, _, paths) = full_index.entry(hash).or_default();
+                            let (_, _, paths, _) = full_index.entry(hash).or_default();
                             *paths = Some(path);
                         }
+                        FileName::CompressedData(hash) => {
+                            let (_, _, _, compressed) = full_index.entry(hash).or_default();
+                            *compressed = Some(path);
+                        }
                         _ => {
                             // silently ignore other files, there could be a valid reason for them
                         }
@@ -1221,16 +2133,24 @@ impl Store {
                 }
             }
         }
+        let mut load_progress = LoadProgress {
+            done: 0,
+            total: (full_index.len() + partial_index.len()) as u64,
+        };
+        progress(load_progress);
         // figure out what we have completely
         let mut complete = BTreeMap::new();
-        for (hash, (data_path, outboard_path, paths_path)) in full_index {
+        for (hash, (data_path, outboard_path, paths_path, compressed_path)) in full_index {
+            check_cancelled()?;
+            load_progress.inc(progress);
             let external: BTreeSet<PathBuf> = if let Some(paths_path) = paths_path {
                 let paths = std::fs::read(paths_path)?;
-                postcard::from_bytes(&paths)?
+                postcard::from_bytes(decode_versioned(&paths)?)?
             } else {
                 Default::default()
             };
-            let owned_data = data_path.is_some();
+            let owned_data = data_path.is_some() || compressed_path.is_some();
+            let mut inline_data = None;
             let size = if let Some(data_path) = &data_path {
                 let Ok(meta) = std::fs::metadata(data_path) else {
                     tracing::warn!(
@@ -1241,6 +2161,17 @@ impl Store {
                     continue;
                 };
                 meta.len()
+            } else if let Some(compressed_path) = &compressed_path {
+                let Ok((size, decoded)) = load_compressed(compressed_path) else {
+                    tracing::warn!(
+                        "unable to read compressed data file {}. removing {}",
+                        compressed_path.display(),
+                        hex::encode(hash)
+                    );
+                    continue;
+                };
+                inline_data = decoded;
+                size
             } else if let Some(external) = external.iter().next() {
                 let Ok(meta) = std::fs::metadata(external) else {
                     tracing::warn!(
@@ -1258,20 +2189,21 @@ impl Store {
                 );
                 continue;
             };
-            if needs_outboard(size) {
-                if let Some(outboard_path) = outboard_path {
-                    let outboard_data = std::fs::read(outboard_path)?;
-                    outboard.insert(hash, outboard_data.into());
-                } else {
-                    tracing::error!("missing outboard file for {}", hex::encode(hash));
-                    // we could delete the data file here
-                    continue;
-                }
+            // outboards are read when they are first needed
+            if needs_outboard(size) && outboard_path.is_none() {
+                tracing::error!("missing outboard file for {}", hex::encode(hash));
+                // we could delete the data file here
+                continue;
+            }
+            let compressed = data_path.is_none() && compressed_path.is_some();
+            if let Some(inline_data) = inline_data {
+                data.insert(hash, inline_data.into());
             }
             complete.insert(
                 hash,
                 CompleteEntry {
                     owned_data,
+                    compressed,
                     external,
                     size,
                 },
@@ -1305,6 +2237,8 @@ impl Store {
         });
         let mut partial = BTreeMap::new();
         for (hash, entries) in partial_index {
+            check_cancelled()?;
+            load_progress.inc(progress);
             let best = if !complete.contains_key(&hash) {
                 entries
                     .iter()
@@ -1370,6 +2304,11 @@ impl Store {
                 }
             }
         }
+        // partial entries without data or outboard were dropped without counting them
+        if load_progress.done < load_progress.total {
+            load_progress.done = load_progress.total;
+            progress(load_progress);
+        }
         for hash in complete.keys() {
             tracing::info!("complete {}", hash);
             partial.remove(hash);
@@ -1384,16 +2323,23 @@ impl Store {
             tags = postcard::from_bytes(&data)?;
             tracing::info!("loaded tags. {} entries", tags.len());
         };
+        let pins_path = meta_path.join("pins.meta");
+        let mut pins = BTreeMap::new();
+        if pins_path.exists() {
+            let data = std::fs::read(pins_path)?;
+            pins = postcard::from_bytes(&data)?;
+            tracing::info!("loaded pins. {} entries", pins.len());
+        };
         Ok(Self(Arc::new(Inner {
             state: RwLock::new(State {
                 complete,
                 partial,
-                outboard,
-                data: Default::default(),
+                data,
                 live: Default::default(),
                 temp: Default::default(),
             }),
             tags: RwLock::new(tags),
+            pins: RwLock::new(pins),
             options: Options {
                 complete_path,
                 partial_path,
@@ -1401,8 +2347,17 @@ impl Store {
                 move_threshold: 1024 * 128,
                 inline_threshold: 1024 * 16,
                 rt: rt.main().clone(),
+                local_pool: rt.local_pool().clone(),
             },
             complete_io_mutex: Mutex::new(()),
+            stream_imports: Default::default(),
+            durability: Default::default(),
+            verify_merges: AtomicBool::new(false),
+            compress_data: AtomicBool::new(false),
+            format_version: Mutex::new(version),
+            data_written: Notify::new(),
+            file_handles: Arc::new(FileHandles::new(MAX_OPEN_FILES)),
+            outboards: Arc::new(OutboardCache::new(DEFAULT_OUTBOARD_CACHE_SIZE)),
         })))
     }
 
@@ -1417,7 +2372,14 @@ impl Store {
         let partial_path = partial_path.as_ref().to_path_buf();
         let meta_path = meta_path.as_ref().to_path_buf();
         let rt = rt.clone();
-        let db = Self::load_sync(complete_path, partial_path, meta_path, rt)?;
+        let db = Self::load_sync(
+            complete_path,
+            partial_path,
+            meta_path,
+            rt,
+            &|_| {},
+            &CancellationToken::new(),
+        )?;
         Ok(db)
     }
 
@@ -1427,6 +2389,30 @@ impl Store {
         partial_path: impl AsRef<Path>,
         meta_path: impl AsRef<Path>,
         rt: &iroh_bytes::util::runtime::Handle,
+    ) -> anyhow::Result<Self> {
+        Self::load_with_progress(
+            complete_path,
+            partial_path,
+            meta_path,
+            rt,
+            |_| {},
+            CancellationToken::new(),
+        )
+        .await
+    }
+
+    /// Load a database from disk, reporting progress and allowing to abort.
+    ///
+    /// `progress` is called once the directories are scanned, and then every few loaded
+    /// entries, so it should return quickly. Loading stops with an error once `cancel` is
+    /// cancelled.
+    pub async fn load_with_progress(
+        complete_path: impl AsRef<Path>,
+        partial_path: impl AsRef<Path>,
+        meta_path: impl AsRef<Path>,
+        rt: &iroh_bytes::util::runtime::Handle,
+        progress: impl Fn(LoadProgress) + Send + 'static,
+        cancel: CancellationToken,
     ) -> anyhow::Result<Self> {
         let complete_path = complete_path.as_ref().to_path_buf();
         let partial_path = partial_path.as_ref().to_path_buf();
@@ -1434,11 +2420,257 @@ impl Store {
         let rtc = rt.clone();
         let db = rt
             .main()
-            .spawn_blocking(move || Self::load_sync(complete_path, partial_path, meta_path, rtc))
+            .spawn_blocking(move || {
+                Self::load_sync(
+                    complete_path,
+                    partial_path,
+                    meta_path,
+                    rtc,
+                    &progress,
+                    &cancel,
+                )
+            })
             .await??;
         Ok(db)
     }
 
+    /// Set when inserted content is flushed to disk.
+    ///
+    /// This applies to all inserts started after the call.
+    pub fn set_durability(&self, mode: DurabilityMode) {
+        *self.0.durability.write().unwrap() = mode;
+    }
+
+    /// Get the current [`DurabilityMode`].
+    pub fn durability(&self) -> DurabilityMode {
+        *self.0.durability.read().unwrap()
+    }
+
+    fn is_durable(&self) -> bool {
+        self.durability() == DurabilityMode::Immediate
+    }
+
+    /// Set whether to verify existing data when the same hash is added again.
+    ///
+    /// When enabled, the data of an already complete entry is hashed before a new location
+    /// is merged into it, and the insert fails with [`io::ErrorKind::InvalidData`] if it does
+    /// not match. This is expensive for large entries, so it is disabled by default.
+    pub fn set_verify_merges(&self, verify: bool) {
+        self.0.verify_merges.store(verify, Ordering::Relaxed);
+    }
+
+    /// Whether existing data is verified when the same hash is added again.
+    pub fn verify_merges(&self) -> bool {
+        self.0.verify_merges.load(Ordering::Relaxed)
+    }
+
+    /// Set whether to compress the data files of new entries.
+    ///
+    /// When enabled, the data of new entries is written to a zstd compressed data file. Data
+    /// that is small enough to be kept in memory is decompressed on load, larger data is
+    /// decompressed block by block when it is read. Existing entries keep their format, and
+    /// stores with compressed and uncompressed data files can be loaded regardless of this
+    /// setting. Writing the first compressed data file upgrades the store to
+    /// [`FORMAT_VERSION`] 2. Disabled by default.
+    pub fn set_compress_data(&self, compress: bool) {
+        self.0.compress_data.store(compress, Ordering::Relaxed);
+    }
+
+    /// Whether the data files of new entries are compressed.
+    pub fn compress_data(&self) -> bool {
+        self.0.compress_data.load(Ordering::Relaxed)
+    }
+
+    /// Set the maximum total size in bytes of the outboards kept in memory.
+    ///
+    /// The outboards of complete entries are shared by all readers, so that serving many
+    /// requests for the same blob does not read its outboard again each time. The least
+    /// recently used outboards are dropped when the limit is reached. Defaults to
+    /// [`DEFAULT_OUTBOARD_CACHE_SIZE`].
+    pub fn set_outboard_cache_size(&self, size: u64) {
+        self.0.outboards.set_capacity(size);
+    }
+
+    /// The maximum total size in bytes of the outboards kept in memory.
+    pub fn outboard_cache_size(&self) -> u64 {
+        self.0.outboards.capacity()
+    }
+
+    /// Whether the owned data for `hash` should be stored compressed.
+    ///
+    /// An entry that already has owned data keeps its format, so the data file of a
+    /// complete entry is never replaced by a file of the other kind.
+    fn should_compress(&self, hash: &Hash) -> bool {
+        match self.0.state.read().unwrap().complete.get(hash) {
+            Some(entry) if entry.owned_data => entry.compressed,
+            _ => self.compress_data(),
+        }
+    }
+
+    /// Write the compressed data file for `hash`, reading `size` bytes from `data`.
+    ///
+    /// Data that is small enough to be kept in memory is compressed as a whole and returned,
+    /// larger data is compressed in blocks.
+    fn write_compressed_data(
+        &self,
+        hash: &Hash,
+        mut data: impl io::Read,
+        size: u64,
+    ) -> io::Result<Option<Bytes>> {
+        self.require_format_version(COMPRESSED_DATA_FORMAT_VERSION)?;
+        let path = self.0.options.compressed_data_path(hash);
+        self.0.file_handles.invalidate(&path);
+        let inline_data = if size < self.0.options.inline_threshold {
+            let mut buf = Vec::with_capacity(size as usize);
+            data.read_to_end(&mut buf)?;
+            std::fs::write(&path, encode_compressed(&buf)?)?;
+            Some(buf.into())
+        } else {
+            let file = std::fs::File::create(&path)?;
+            if let Err(cause) = encode_compressed_blocks(data, size, &file) {
+                drop(file);
+                std::fs::remove_file(&path).ok();
+                return Err(cause);
+            }
+            None
+        };
+        if self.is_durable() {
+            sync_file(&path)?;
+        }
+        Ok(inline_data)
+    }
+
+    /// Make sure the format version of the store is at least `version`.
+    ///
+    /// Must be called before writing a file that was added in `version`, so older versions
+    /// of iroh refuse to open the store instead of ignoring the file.
+    fn require_format_version(&self, version: u16) -> io::Result<()> {
+        let mut current = self.0.format_version.lock().unwrap();
+        if *current < version {
+            tracing::info!("upgrading database format from version {current} to {version}");
+            write_format_version(&self.0.options.meta_path, version)?;
+            *current = version;
+        }
+        Ok(())
+    }
+
+    /// Check that the existing locations of a complete entry contain the data for `hash`.
+    ///
+    /// Locations that are replaced by `new` are skipped.
+    fn verify_merge(&self, hash: &Hash, new: &CompleteEntry) -> io::Result<()> {
+        if !self.verify_merges() {
+            return Ok(());
+        }
+        let Some(existing) = self.0.state.read().unwrap().complete.get(hash).cloned() else {
+            return Ok(());
+        };
+        let mut paths = existing
+            .external
+            .difference(&new.external)
+            .map(|path| (path.clone(), false))
+            .collect::<Vec<_>>();
+        if existing.owned_data && existing.compressed && !new.owned_data {
+            paths.push((self.0.options.compressed_data_path(hash), true));
+        } else if existing.owned_data && !new.owned_data {
+            paths.push((self.owned_data_path(hash), false));
+        }
+        for (path, compressed) in paths {
+            let mut hasher = blake3::Hasher::new();
+            if compressed {
+                decode_compressed_file(&path, &mut hasher)?;
+            } else {
+                let mut file = std::fs::File::open(&path)?;
+                std::io::copy(&mut file, &mut hasher)?;
+            }
+            if Hash::from(hasher.finalize()) != *hash {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "existing data for {} at {} does not match its hash",
+                        hash,
+                        path.display()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// List temporary files that are not associated with any tracked entry.
+    ///
+    /// These are left behind when the process crashes in the middle of an import or download.
+    /// Partial data and outboard files are only considered orphaned if they don't belong to the
+    /// current partial entry for their hash, so files of an in-progress download are never
+    /// listed.
+    pub fn orphaned_temp_files(&self) -> io::Result<Vec<PathBuf>> {
+        // imports create their temp files while holding this lock
+        let _complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
+        let state = self.0.state.read().unwrap();
+        self.orphaned_temp_files_locked(&state.partial)
+    }
+
+    /// Delete temporary files that are not associated with any tracked entry.
+    ///
+    /// See [`Self::orphaned_temp_files`] for which files are considered orphaned.
+    pub fn cleanup_orphans(&self) -> io::Result<CleanupStats> {
+        let _complete_io_guard = self.0.complete_io_mutex.lock().unwrap();
+        // hold the state lock so no new partial entry can be created for an orphaned uuid
+        let state = self.0.state.read().unwrap();
+        let mut stats = CleanupStats::default();
+        for path in self.orphaned_temp_files_locked(&state.partial)? {
+            let size = std::fs::metadata(&path)
+                .map(|m| m.len())
+                .unwrap_or_default();
+            tracing::info!("removing orphaned temp file {}", path.display());
+            match std::fs::remove_file(&path) {
+                Ok(()) => {
+                    stats.files += 1;
+                    stats.bytes += size;
+                }
+                Err(cause) if cause.kind() == io::ErrorKind::NotFound => {}
+                Err(cause) => return Err(cause),
+            }
+        }
+        Ok(stats)
+    }
+
+    fn orphaned_temp_files_locked(
+        &self,
+        partial: &BTreeMap<Hash, PartialEntryData>,
+    ) -> io::Result<Vec<PathBuf>> {
+        let mut res = Vec::new();
+        let stream_imports = self.0.stream_imports.lock().unwrap();
+        let mut dirs = vec![&self.0.options.partial_path];
+        if self.0.options.complete_path != self.0.options.partial_path {
+            dirs.push(&self.0.options.complete_path);
+        }
+        for dir in dirs {
+            for entry in std::fs::read_dir(dir)? {
+                let path = entry?.path();
+                if !path.is_file() {
+                    continue;
+                }
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let orphaned = match FileName::from_str(name) {
+                    Ok(
+                        FileName::PartialData(hash, uuid) | FileName::PartialOutboard(hash, uuid),
+                    ) => partial.get(&hash).map(|entry| entry.uuid) != Some(uuid),
+                    Ok(purpose) => purpose.temporary(),
+                    // copy imports use a temp file named after a random uuid
+                    Err(_) => name
+                        .strip_suffix(".temp")
+                        .is_some_and(|uuid| uuid.len() == 32 && hex::decode(uuid).is_ok()),
+                };
+                if orphaned && !stream_imports.contains(&path) {
+                    res.push(path);
+                }
+            }
+        }
+        Ok(res)
+    }
+
     fn owned_data_path(&self, hash: &Hash) -> PathBuf {
         self.0.options.owned_data_path(hash)
     }
@@ -1526,6 +2758,8 @@ pub enum FileName {
     PartialData(Hash, [u8; 16]),
     /// File is storing data for the hash
     Data(Hash),
+    /// File is storing compressed data for the hash
+    CompressedData(Hash),
     /// File is storing a partial outboard
     PartialOutboard(Hash, [u8; 16]),
     /// File is storing an outboard
@@ -1557,6 +2791,9 @@ impl FileName {
 /// size of 4, unlike the bao crate which uses 0.
 const OUTBOARD_EXT: &str = "obao4";
 
+/// The extension for compressed data files.
+const COMPRESSED_DATA_EXT: &str = "zdata";
+
 impl fmt::Display for FileName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -1579,6 +2816,9 @@ impl fmt::Display for FileName {
                 write!(f, "{}.paths", hex::encode(hash))
             }
             Self::Data(hash) => write!(f, "{}.data", hex::encode(hash)),
+            Self::CompressedData(hash) => {
+                write!(f, "{}.{}", hex::encode(hash), COMPRESSED_DATA_EXT)
+            }
             Self::Outboard(hash) => write!(f, "{}.{}", hex::encode(hash), OUTBOARD_EXT),
             Self::Meta(name) => write!(f, "{}.meta", hex::encode(name)),
         }
@@ -1605,6 +2845,9 @@ impl FromStr for FileName {
             } else if ext == OUTBOARD_EXT {
                 hex::decode_to_slice(base, &mut hash).map_err(|_| ())?;
                 Ok(Self::PartialOutboard(hash.into(), uuid))
+            } else if ext == "paths" {
+                hex::decode_to_slice(base, &mut hash).map_err(|_| ())?;
+                Ok(Self::TempPaths(hash.into(), uuid))
             } else {
                 Err(())
             }
@@ -1615,6 +2858,8 @@ impl FromStr for FileName {
             hex::decode_to_slice(base, &mut hash).map_err(|_| ())?;
             if ext == "data" {
                 Ok(Self::Data(hash.into()))
+            } else if ext == COMPRESSED_DATA_EXT {
+                Ok(Self::CompressedData(hash.into()))
             } else if ext == OUTBOARD_EXT {
                 Ok(Self::Outboard(hash.into()))
             } else if ext == "paths" {
@@ -1626,16 +2871,415 @@ impl FromStr for FileName {
     }
 }
 
+/// Magic bytes at the start of the format header of versioned files.
+const FORMAT_MAGIC: [u8; 4] = *b"\0ifs";
+
+/// The current version of the on-disk format of the store.
+///
+/// Version 0 is the format without headers, which is still supported for reading.
+/// Version 2 added compressed data files.
+pub const FORMAT_VERSION: u16 = 2;
+
+/// The format version of stores without compressed data files.
+///
+/// Files whose format did not change since are written with this version, so they can still
+/// be read by older versions of iroh.
+const BASE_FORMAT_VERSION: u16 = 1;
+
+/// The format version that added compressed data files.
+const COMPRESSED_DATA_FORMAT_VERSION: u16 = 2;
+
+/// Name of the file in the meta directory that contains the format version of the store.
+const VERSION_FILE: &str = "version";
+
+/// The store or one of its files was written by a newer version of iroh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("unsupported database format version {found}, the newest supported version is {supported}")]
+pub struct UnsupportedDbVersion {
+    /// The version that was found on disk.
+    pub found: u16,
+    /// The newest version this build supports.
+    pub supported: u16,
+}
+
+fn format_header(version: u16) -> [u8; 6] {
+    let mut header = [0u8; 6];
+    header[..4].copy_from_slice(&FORMAT_MAGIC);
+    header[4..].copy_from_slice(&version.to_le_bytes());
+    header
+}
+
+/// Parse the format header at the start of `data`.
+///
+/// Returns the version and the remaining data, or `None` if there is no header.
+fn parse_format_header(data: &[u8]) -> Option<(u16, &[u8])> {
+    let rest = data.strip_prefix(&FORMAT_MAGIC)?;
+    let version = u16::from_le_bytes(rest.get(..2)?.try_into().unwrap());
+    Some((version, &rest[2..]))
+}
+
+/// Prefix `payload` with the format header.
+fn encode_versioned(payload: &[u8]) -> Vec<u8> {
+    let mut res = format_header(BASE_FORMAT_VERSION).to_vec();
+    res.extend_from_slice(payload);
+    res
+}
+
+/// Strip the format header from `data`, returning the payload.
+///
+/// Data without a header was written in format version 0, and is returned unchanged. A
+/// postcard serialized list never starts with the magic bytes, since a list that starts
+/// with a 0 length is complete after the first byte.
+fn decode_versioned(data: &[u8]) -> Result<&[u8], UnsupportedDbVersion> {
+    match parse_format_header(data) {
+        Some((found, _)) if found > FORMAT_VERSION => Err(UnsupportedDbVersion {
+            found,
+            supported: FORMAT_VERSION,
+        }),
+        Some((_, payload)) => Ok(payload),
+        None => Ok(data),
+    }
+}
+
+/// Compression byte of compressed data files for data that is stored as is.
+const COMPRESSION_NONE: u8 = 0;
+
+/// Compression byte of compressed data files for zstd compressed data.
+const COMPRESSION_ZSTD: u8 = 1;
+
+/// Compression byte of compressed data files for data that is zstd compressed in
+/// independent blocks.
+const COMPRESSION_ZSTD_BLOCKS: u8 = 2;
+
+/// Size of the blocks of block compressed data files, before compression.
+const COMPRESSION_BLOCK_SIZE: u64 = 1024 * 256;
+
+/// Length of the prefix of block compressed data files, up to and including the block size.
+const BLOCKS_PREFIX_LEN: u64 = 19;
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Encode data as the content of a compressed data file.
+fn encode_compressed(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut res = format_header(COMPRESSED_DATA_FORMAT_VERSION).to_vec();
+    res.push(COMPRESSION_ZSTD);
+    zstd::stream::copy_encode(data, &mut res, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    Ok(res)
+}
+
+/// Write `size` bytes of `data` to `target` as the content of a block compressed data file.
+fn encode_compressed_blocks(
+    mut data: impl io::Read,
+    size: u64,
+    target: impl io::Write,
+) -> io::Result<()> {
+    let mut target = io::BufWriter::new(target);
+    target.write_all(&format_header(COMPRESSED_DATA_FORMAT_VERSION))?;
+    target.write_all(&[COMPRESSION_ZSTD_BLOCKS])?;
+    target.write_all(&size.to_le_bytes())?;
+    target.write_all(&(COMPRESSION_BLOCK_SIZE as u32).to_le_bytes())?;
+    let mut index = Vec::new();
+    let mut block = Vec::with_capacity(COMPRESSION_BLOCK_SIZE as usize);
+    let mut read = 0;
+    let mut end = 0u64;
+    while read < size {
+        block.clear();
+        (&mut data)
+            .take(COMPRESSION_BLOCK_SIZE)
+            .read_to_end(&mut block)?;
+        if block.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "data is shorter than its size",
+            ));
+        }
+        read += block.len() as u64;
+        let compressed = zstd::stream::encode_all(&block[..], zstd::DEFAULT_COMPRESSION_LEVEL)?;
+        target.write_all(&compressed)?;
+        end += compressed.len() as u64;
+        index.extend_from_slice(&end.to_le_bytes());
+    }
+    target.write_all(&index)?;
+    target.flush()
+}
+
+/// Parse the start of a compressed data file.
+///
+/// Returns the compression byte and the remaining data.
+fn parse_compressed(data: &[u8]) -> io::Result<(u8, &[u8])> {
+    let Some((found, rest)) = parse_format_header(data) else {
+        return Err(invalid_data("compressed data file without format header"));
+    };
+    if found > FORMAT_VERSION {
+        let err = UnsupportedDbVersion {
+            found,
+            supported: FORMAT_VERSION,
+        };
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+    }
+    match rest.split_first() {
+        Some((&compression, rest)) => Ok((compression, rest)),
+        None => Err(invalid_data("compressed data file without compression")),
+    }
+}
+
+/// Decode the content of a compressed data file, returning the uncompressed data.
+///
+/// Block compressed data is not supported, since it is too large to be decoded at once, see
+/// [`decode_compressed_file`].
+fn decode_compressed(data: &[u8]) -> io::Result<Vec<u8>> {
+    match parse_compressed(data)? {
+        (COMPRESSION_NONE, payload) => Ok(payload.to_vec()),
+        (COMPRESSION_ZSTD, payload) => zstd::stream::decode_all(payload),
+        (compression, _) => Err(invalid_data(format!("unknown compression {compression}"))),
+    }
+}
+
+/// Decode the compressed data file at `path` to `target`.
+fn decode_compressed_file(path: &Path, mut target: impl io::Write) -> io::Result<()> {
+    let file = FileHandle::open_unshared(path)?;
+    match BlockIndex::read(&file)? {
+        Some(index) => {
+            for block in 0..index.ends.len() {
+                target.write_all(&index.read_block(&file, block)?)?;
+            }
+        }
+        None => target.write_all(&decode_compressed(&std::fs::read(path)?)?)?,
+    }
+    Ok(())
+}
+
+/// Load the compressed data file at `path`.
+///
+/// Returns the size of the data and, unless it is block compressed, the data itself.
+fn load_compressed(path: &Path) -> io::Result<(u64, Option<Vec<u8>>)> {
+    let file = FileHandle::open_unshared(path)?;
+    match BlockIndex::read(&file)? {
+        Some(index) => Ok((index.size, None)),
+        None => {
+            let data = decode_compressed(&std::fs::read(path)?)?;
+            Ok((data.len() as u64, Some(data)))
+        }
+    }
+}
+
+/// The index of a block compressed data file.
+#[derive(Debug)]
+struct BlockIndex {
+    /// The size of the uncompressed data.
+    size: u64,
+    /// The size of the uncompressed blocks.
+    block_size: u64,
+    /// The end offsets of the compressed blocks, relative to [`BLOCKS_PREFIX_LEN`].
+    ends: Vec<u64>,
+}
+
+impl BlockIndex {
+    /// Read the index of a compressed data file.
+    ///
+    /// Returns `None` if the data is not block compressed.
+    fn read(file: &FileHandle) -> io::Result<Option<Self>> {
+        let prefix = file.read_at(0, BLOCKS_PREFIX_LEN as usize)?;
+        let rest = match parse_compressed(&prefix)? {
+            (COMPRESSION_ZSTD_BLOCKS, rest) if rest.len() == 12 => rest,
+            (COMPRESSION_ZSTD_BLOCKS, _) => {
+                return Err(invalid_data("truncated block compressed data file"))
+            }
+            _ => return Ok(None),
+        };
+        let size = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let block_size = u32::from_le_bytes(rest[8..].try_into().unwrap()) as u64;
+        if block_size == 0 {
+            return Err(invalid_data("block compressed data file with empty blocks"));
+        }
+        let blocks = size / block_size + u64::from(size % block_size != 0);
+        let index_len = blocks
+            .checked_mul(8)
+            .ok_or_else(|| invalid_data("invalid block compressed data file size"))?;
+        let index_start = file
+            .len
+            .checked_sub(index_len)
+            .filter(|start| *start >= BLOCKS_PREFIX_LEN)
+            .ok_or_else(|| invalid_data("truncated block compressed data file"))?;
+        let index = file.read_at(index_start, index_len as usize)?;
+        let ends = index
+            .chunks_exact(8)
+            .map(|end| u64::from_le_bytes(end.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let sorted = ends.windows(2).all(|pair| pair[0] <= pair[1]);
+        if !sorted || ends.last().copied().unwrap_or_default() != index_start - BLOCKS_PREFIX_LEN {
+            return Err(invalid_data("invalid block compressed data file index"));
+        }
+        Ok(Some(Self {
+            size,
+            block_size,
+            ends,
+        }))
+    }
+
+    /// Read and decompress the block with the given number.
+    fn read_block(&self, file: &FileHandle, block: usize) -> io::Result<Vec<u8>> {
+        let start = block.checked_sub(1).map_or(0, |prev| self.ends[prev]);
+        let end = self.ends[block];
+        let compressed = file.read_at(BLOCKS_PREFIX_LEN + start, (end - start) as usize)?;
+        let data = zstd::stream::decode_all(&compressed[..])?;
+        let expected = self
+            .size
+            .saturating_sub(block as u64 * self.block_size)
+            .min(self.block_size);
+        if data.len() as u64 != expected {
+            return Err(invalid_data(
+                "block of block compressed data has the wrong size",
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Read up to `len` bytes of the uncompressed data at `offset`.
+    fn read_at(&self, file: &FileHandle, offset: u64, len: usize) -> io::Result<Bytes> {
+        let end = self.size.min(offset.saturating_add(len as u64));
+        if offset >= end {
+            return Ok(Bytes::new());
+        }
+        let mut res = Vec::with_capacity((end - offset) as usize);
+        for block in offset / self.block_size..=(end - 1) / self.block_size {
+            let data = self.read_block(file, block as usize)?;
+            let block_start = block * self.block_size;
+            let from = offset.saturating_sub(block_start) as usize;
+            let to = (end - block_start).min(data.len() as u64) as usize;
+            res.extend_from_slice(&data[from..to]);
+        }
+        Ok(res.into())
+    }
+}
+
+/// Read the format version of the store from the meta directory.
+///
+/// A store without a version file has version 0.
+fn read_format_version(meta_path: &Path) -> anyhow::Result<u16> {
+    let path = meta_path.join(VERSION_FILE);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let data = std::fs::read(&path)?;
+    let (found, _) = parse_format_header(&data)
+        .ok_or_else(|| anyhow::anyhow!("invalid version file {}", path.display()))?;
+    anyhow::ensure!(
+        found <= FORMAT_VERSION,
+        UnsupportedDbVersion {
+            found,
+            supported: FORMAT_VERSION,
+        }
+    );
+    Ok(found)
+}
+
+/// Write the format version of the store to the meta directory.
+fn write_format_version(meta_path: &Path, version: u16) -> io::Result<()> {
+    let temp_path = meta_path.join(format!("version-{}.meta", hex::encode(new_uuid())));
+    let final_path = meta_path.join(VERSION_FILE);
+    write_atomic(&temp_path, &final_path, &format_header(version))
+}
+
+/// Migrate a store from format version `from`, returning the version after the migration.
+///
+/// Stores are only migrated to [`BASE_FORMAT_VERSION`], since the version is only bumped
+/// further when the first compressed data file is written.
+fn migrate(meta_path: &Path, from: u16) -> anyhow::Result<u16> {
+    match from {
+        0 => {
+            // headerless path files are read as is and get a header when they are rewritten,
+            // so only the version file needs to be written
+            tracing::info!(
+                "migrating database format from version {from} to {BASE_FORMAT_VERSION}"
+            );
+            write_format_version(meta_path, BASE_FORMAT_VERSION)?;
+            Ok(BASE_FORMAT_VERSION)
+        }
+        found if found <= FORMAT_VERSION => Ok(found),
+        found => Err(UnsupportedDbVersion {
+            found,
+            supported: FORMAT_VERSION,
+        }
+        .into()),
+    }
+}
+
 /// Write data to a file, and then atomically rename it to the final path.
 ///
 /// This assumes that the directories for both files already exist.
 fn write_atomic(temp_path: &Path, final_path: &Path, data: &[u8]) -> io::Result<()> {
-    let mut file = std::fs::File::create(temp_path)?;
+    let temp = TempFile::new(temp_path.to_owned());
+    let mut file = std::fs::File::create(temp.path())?;
     file.write_all(data)?;
-    std::fs::rename(temp_path, final_path)?;
+    temp.persist(final_path)?;
     Ok(())
 }
 
+/// Flush the content of a file to disk.
+fn sync_file(path: &Path) -> io::Result<()> {
+    // windows requires write access to flush a file
+    let file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.sync_all()
+}
+
+/// Flush a directory to disk, so that renames and newly created files in it are durable.
+///
+/// This is a no-op on platforms that don't support opening directories.
+fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(path)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// A temporary file that is deleted on drop unless it was moved to its final location.
+///
+/// This makes sure that failed writes, e.g. because the disk is full, don't leave
+/// half-written files around.
+struct TempFile(Option<PathBuf>);
+
+impl TempFile {
+    fn new(path: PathBuf) -> Self {
+        Self(Some(path))
+    }
+
+    fn path(&self) -> &Path {
+        self.0.as_deref().expect("temp file already persisted")
+    }
+
+    /// Move the temp file to its final location.
+    fn persist(mut self, final_path: &Path) -> io::Result<()> {
+        std::fs::rename(self.path(), final_path)?;
+        self.0 = None;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Keeps cleanup from removing the temp file of a stream import while it is being written.
+struct StreamImport {
+    store: Store,
+    path: PathBuf,
+}
+
+impl Drop for StreamImport {
+    fn drop(&mut self) {
+        let mut stream_imports = self.store.0.stream_imports.lock().unwrap();
+        stream_imports.remove(&self.path);
+    }
+}
+
 struct DD<T: fmt::Display>(T);
 
 impl<T: fmt::Display> fmt::Debug for DD<T> {
@@ -1653,6 +3297,7 @@ impl fmt::Debug for FileName {
                 .field(&DD(hex::encode(guid)))
                 .finish(),
             Self::Data(hash) => f.debug_tuple("Data").field(&DD(hash)).finish(),
+            Self::CompressedData(hash) => f.debug_tuple("CompressedData").field(&DD(hash)).finish(),
             Self::PartialOutboard(hash, guid) => f
                 .debug_tuple("PartialOutboard")
                 .field(&DD(hash))
@@ -1679,6 +3324,7 @@ impl FileName {
         match self {
             FileName::PartialData(_, _) => true,
             FileName::Data(_) => false,
+            FileName::CompressedData(_) => false,
             FileName::PartialOutboard(_, _) => true,
             FileName::Outboard(_) => false,
             FileName::Meta(_) => false,
@@ -1691,6 +3337,7 @@ impl FileName {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use iroh_bytes::util::progress::IgnoreProgressSender;
     use proptest::prelude::*;
 
     fn arb_hash() -> impl Strategy<Value = Hash> {
@@ -1700,12 +3347,15 @@ mod tests {
     fn arb_filename() -> impl Strategy<Value = FileName> {
         prop_oneof![
             arb_hash().prop_map(FileName::Data),
+            arb_hash().prop_map(FileName::CompressedData),
             arb_hash().prop_map(FileName::Outboard),
             arb_hash().prop_map(FileName::Paths),
             (arb_hash(), any::<[u8; 16]>())
                 .prop_map(|(hash, uuid)| FileName::PartialData(hash, uuid)),
             (arb_hash(), any::<[u8; 16]>())
                 .prop_map(|(hash, uuid)| FileName::PartialOutboard(hash, uuid)),
+            (arb_hash(), any::<[u8; 16]>())
+                .prop_map(|(hash, uuid)| FileName::TempPaths(hash, uuid)),
             any::<Vec<u8>>().prop_map(FileName::Meta),
         ]
     }
@@ -1719,6 +3369,884 @@ mod tests {
         assert!(FileName::from_str("1234ABDC-1234.outboard").is_err());
     }
 
+    #[test]
+    fn write_atomic_cleanup() {
+        let dir = tempfile::tempdir().unwrap();
+        let temp_path = dir.path().join("test.temp");
+        let final_path = dir.path().join("test");
+        write_atomic(&temp_path, &final_path, b"hello").unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read(&final_path).unwrap(), b"hello");
+
+        // renaming into a missing directory fails, the temp file must not be left behind
+        let final_path = dir.path().join("missing").join("test");
+        assert!(write_atomic(&temp_path, &final_path, b"hello").is_err());
+        assert!(!temp_path.exists());
+    }
+
+    #[tokio::test]
+    async fn cleanup_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let hash = Hash::from(blake3::hash(b"orphan"));
+        let uuid = new_uuid();
+        let orphans = [
+            dir.path()
+                .join(FileName::PartialData(hash, uuid).to_string()),
+            dir.path()
+                .join(FileName::PartialOutboard(hash, uuid).to_string()),
+            dir.path().join(FileName::TempPaths(hash, uuid).to_string()),
+            dir.path().join(format!("{}.temp", hex::encode(uuid))),
+        ];
+        for path in &orphans {
+            std::fs::write(path, b"data").unwrap();
+        }
+        // the files of an in-progress download must be kept
+        let partial = db
+            .get_or_create_partial(Hash::from(blake3::hash(b"partial")), 4)
+            .unwrap();
+        std::fs::write(&partial.data_path, b"data").unwrap();
+        std::fs::write(&partial.outboard_path, b"data").unwrap();
+        // as well as unrelated files
+        let unrelated = dir.path().join("unrelated.temp");
+        std::fs::write(&unrelated, b"data").unwrap();
+
+        let mut found = db.orphaned_temp_files().unwrap();
+        found.sort();
+        let mut expected = orphans.to_vec();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let stats = db.cleanup_orphans().unwrap();
+        assert_eq!(
+            stats,
+            CleanupStats {
+                files: 4,
+                bytes: 16
+            }
+        );
+        assert!(orphans.iter().all(|path| !path.exists()));
+        assert!(partial.data_path.exists());
+        assert!(partial.outboard_path.exists());
+        assert!(unrelated.exists());
+        assert!(db.orphaned_temp_files().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn import_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let temp_files = || {
+            std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension() == Some("temp".as_ref()))
+                .collect::<Vec<_>>()
+        };
+        let data = (0..100_000u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        let (mut send, recv) = futures::channel::mpsc::channel(1);
+        let task = tokio::spawn({
+            let db = db.clone();
+            async move {
+                baomap::Store::import_stream(
+                    &db,
+                    recv,
+                    BlobFormat::RAW,
+                    IgnoreProgressSender::default(),
+                )
+                .await
+            }
+        });
+        let (first, rest) = data.split_at(1000);
+        futures::SinkExt::send(&mut send, Ok(Bytes::copy_from_slice(first)))
+            .await
+            .unwrap();
+        // the data is written to a temp file as it arrives, which cleanup leaves alone
+        while temp_files().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(db.orphaned_temp_files().unwrap().is_empty());
+        futures::SinkExt::send(&mut send, Ok(Bytes::copy_from_slice(rest)))
+            .await
+            .unwrap();
+        drop(send);
+        let (tag, size) = task.await.unwrap().unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(*tag.hash(), Hash::from(blake3::hash(&data)));
+        let entry = db.get(tag.hash()).unwrap();
+        assert!(entry.is_complete());
+        let mut reader = entry.data_reader().await.unwrap();
+        assert_eq!(reader.read_at(0, data.len()).await.unwrap(), data);
+        assert!(temp_files().is_empty());
+
+        // a failing stream imports nothing and leaves no temp file behind
+        let items = vec![
+            Ok(Bytes::from_static(b"partial")),
+            Err(io::Error::new(io::ErrorKind::Other, "read failed")),
+        ];
+        let res = baomap::Store::import_stream(
+            &db,
+            futures::stream::iter(items),
+            BlobFormat::RAW,
+            IgnoreProgressSender::default(),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(db.get(&Hash::from(blake3::hash(b"partial"))).is_none());
+        assert!(temp_files().is_empty());
+    }
+
+    #[tokio::test]
+    async fn format_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let external = dir.path().join("external");
+        // small enough to not need an outboard file
+        let data = vec![3u8; 1024];
+        std::fs::write(&external, &data).unwrap();
+        let hash = Hash::from(blake3::hash(&data));
+
+        // a store from before format versions, with a headerless path file
+        let legacy_paths = postcard::to_stdvec(&BTreeSet::from([external.clone()])).unwrap();
+        assert_eq!(decode_versioned(&legacy_paths), Ok(&legacy_paths[..]));
+        let paths_path = dir.path().join(FileName::Paths(hash).to_string());
+        std::fs::write(&paths_path, &legacy_paths).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert_eq!(db.contains(&hash), EntryStatus::Complete);
+        assert_eq!(
+            read_format_version(dir.path()).unwrap(),
+            BASE_FORMAT_VERSION,
+            "loading writes the version file"
+        );
+        drop(db);
+
+        // loading the current version again is a no-op
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert_eq!(db.contains(&hash), EntryStatus::Complete);
+        // uncompressed data does not need the new format
+        baomap::Store::import_bytes(&db, vec![4u8; 1024].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_format_version(dir.path()).unwrap(),
+            BASE_FORMAT_VERSION
+        );
+        // the first compressed data file upgrades the store
+        db.set_compress_data(true);
+        baomap::Store::import_bytes(&db, vec![5u8; 1024].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_format_version(dir.path()).unwrap(),
+            COMPRESSED_DATA_FORMAT_VERSION
+        );
+        drop(db);
+
+        // a path file from the future
+        let mut future = format_header(FORMAT_VERSION + 1).to_vec();
+        future.extend_from_slice(&legacy_paths);
+        std::fs::write(&paths_path, &future).unwrap();
+        let err = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap_err();
+        let unsupported = UnsupportedDbVersion {
+            found: FORMAT_VERSION + 1,
+            supported: FORMAT_VERSION,
+        };
+        assert_eq!(err.downcast_ref(), Some(&unsupported));
+        std::fs::remove_file(&paths_path).unwrap();
+
+        // a store from the future
+        std::fs::write(
+            dir.path().join(VERSION_FILE),
+            format_header(FORMAT_VERSION + 1),
+        )
+        .unwrap();
+        let err = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&unsupported));
+    }
+
+    #[tokio::test]
+    async fn import_from_other_store() {
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let src_dir = tempfile::tempdir().unwrap();
+        let dst_dir = tempfile::tempdir().unwrap();
+        let src = Store::load(src_dir.path(), src_dir.path(), src_dir.path(), &rt)
+            .await
+            .unwrap();
+        let dst = Store::load(dst_dir.path(), dst_dir.path(), dst_dir.path(), &rt)
+            .await
+            .unwrap();
+        // one blob without and one with an outboard
+        for size in [100, 1024 * 100] {
+            let data = Bytes::from(vec![7u8; size]);
+            let tag = baomap::Store::import_bytes(&src, data.clone(), BlobFormat::RAW)
+                .await
+                .unwrap();
+            let hash = *tag.hash();
+            baomap::Store::import_from_store(&dst, &src, hash)
+                .await
+                .unwrap();
+            assert_eq!(dst.contains(&hash), EntryStatus::Complete);
+            let entry = dst.get(&hash).unwrap();
+            baomap::validate_bao(&entry).await.unwrap();
+            let mut reader = entry.data_reader().await.unwrap();
+            assert_eq!(reader.read_at(0, size).await.unwrap(), data);
+            // importing again is a no-op
+            baomap::Store::import_from_store(&dst, &src, hash)
+                .await
+                .unwrap();
+        }
+        let missing = Hash::from(blake3::hash(b"missing"));
+        let err = baomap::Store::import_from_store(&dst, &src, missing)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        // corrupted data in the source store is not imported
+        let size = 1024 * 100;
+        let tag = baomap::Store::import_bytes(&src, vec![9u8; size].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let hash = *tag.hash();
+        std::fs::write(src.owned_data_path(&hash), vec![8u8; size]).unwrap();
+        baomap::Store::import_from_store(&dst, &src, hash)
+            .await
+            .unwrap_err();
+        assert_ne!(dst.contains(&hash), EntryStatus::Complete);
+    }
+
+    #[tokio::test]
+    async fn validate_with_progress() {
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let size = 1024 * 1024 + 100;
+        let tag = baomap::Store::import_bytes(&db, vec![7u8; size].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let entry = db.get(tag.hash()).unwrap();
+
+        // progress is reported after each batch, and finally with the size
+        let mut offsets = Vec::new();
+        baomap::validate_bao_with_progress(&entry, 64 * 1024, |offset| {
+            offsets.push(offset);
+            futures::future::ready(Ok(()))
+        })
+        .await
+        .unwrap();
+        let expected = (1..=16)
+            .map(|i| i * 64 * 1024)
+            .chain([size as u64])
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, expected);
+
+        // an error from the progress callback stops reading after the current batch
+        let mut calls = 0;
+        let err = baomap::validate_bao_with_progress(&entry, 64 * 1024, |_| {
+            calls += 1;
+            futures::future::ready(Err(io::Error::new(io::ErrorKind::Interrupted, "stop")))
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn verify_merges() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert!(!db.verify_merges());
+
+        let data = Bytes::from(vec![1u8; 1024 * 64]);
+        let external = dir.path().join("external");
+        std::fs::write(&external, &data).unwrap();
+        let (tag, _) = baomap::Store::import(
+            &db,
+            external.clone(),
+            ImportMode::TryReference,
+            BlobFormat::RAW,
+            IgnoreProgressSender::default(),
+        )
+        .await
+        .unwrap();
+        let hash = *tag.hash();
+
+        // corrupt the external copy, adding the same data again must not trust it
+        std::fs::write(&external, vec![2u8; 1024 * 64]).unwrap();
+        db.set_verify_merges(true);
+        let err = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // without verification the new location is merged
+        db.set_verify_merges(false);
+        let tag2 = baomap::Store::import_bytes(&db, data, BlobFormat::RAW)
+            .await
+            .unwrap();
+        assert_eq!(*tag2.hash(), hash);
+    }
+
+    #[tokio::test]
+    async fn load_progress_and_cancel() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        for i in 0..3u8 {
+            baomap::Store::import_bytes(&db, Bytes::from(vec![i; 100]), BlobFormat::RAW)
+                .await
+                .unwrap();
+        }
+        drop(db);
+
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let db = Store::load_with_progress(
+            dir.path(),
+            dir.path(),
+            dir.path(),
+            &rt,
+            {
+                let updates = updates.clone();
+                move |progress| updates.lock().unwrap().push(progress)
+            },
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.blobs().count(), 3);
+        let updates = updates.lock().unwrap().clone();
+        assert_eq!(updates.first(), Some(&LoadProgress { done: 0, total: 3 }));
+        assert_eq!(updates.last(), Some(&LoadProgress { done: 3, total: 3 }));
+        assert_eq!(updates.last().unwrap().percent(), 100);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let res =
+            Store::load_with_progress(dir.path(), dir.path(), dir.path(), &rt, |_| {}, cancel)
+                .await;
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn durable_insert() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert_eq!(db.durability(), DurabilityMode::Buffered);
+        db.set_durability(DurabilityMode::Immediate);
+        assert_eq!(db.durability(), DurabilityMode::Immediate);
+
+        let data = Bytes::from(vec![1u8; 1024 * 64]);
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let hash = *tag.hash();
+        assert_eq!(std::fs::read(db.owned_data_path(&hash)).unwrap(), data);
+        assert!(db.owned_outboard_path(&hash).exists());
+
+        // completing a partial entry, as done by downloads
+        let data = vec![2u8; 1024 * 64];
+        let (outboard, hash) = bao_tree::io::outboard(&data, IROH_BLOCK_SIZE);
+        let partial = db
+            .get_or_create_partial(hash.into(), data.len() as u64)
+            .unwrap();
+        std::fs::write(&partial.data_path, &data).unwrap();
+        std::fs::write(&partial.outboard_path, &outboard).unwrap();
+        db.insert_complete(partial).await.unwrap();
+        assert_eq!(db.get(&hash.into()).unwrap().size(), data.len() as u64);
+        assert_eq!(
+            std::fs::read(db.owned_data_path(&hash.into())).unwrap(),
+            data
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_available_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+
+        let data = vec![3u8; 1024 * 64];
+        let (outboard, hash) = bao_tree::io::outboard(&data, IROH_BLOCK_SIZE);
+        let partial = db
+            .get_or_create_partial(hash.into(), data.len() as u64)
+            .unwrap();
+        // nothing was written yet
+        assert!(partial.available_ranges().await.unwrap().is_empty());
+
+        // only whole chunk groups of the written data are available
+        std::fs::write(&partial.outboard_path, &outboard).unwrap();
+        std::fs::write(&partial.data_path, &data[..1024 * 40]).unwrap();
+        let expected = RangeSet2::from(..ChunkNum(32));
+        assert_eq!(partial.available_ranges().await.unwrap(), expected);
+        let entry = db.get(&hash.into()).unwrap();
+        assert!(!entry.is_complete());
+        assert_eq!(entry.available_ranges().await.unwrap(), expected);
+
+        std::fs::write(&partial.data_path, &data).unwrap();
+        assert_eq!(partial.available_ranges().await.unwrap(), RangeSet2::all());
+        db.insert_complete(partial).await.unwrap();
+        let entry = db.get(&hash.into()).unwrap();
+        assert_eq!(entry.available_ranges().await.unwrap(), RangeSet2::all());
+    }
+
+    #[tokio::test]
+    async fn compressed_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert!(!db.compress_data());
+        db.set_compress_data(true);
+
+        // small and text heavy, like a collection
+        let data = Bytes::from("iroh collection entry\n".repeat(256));
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let hash = *tag.hash();
+        let compressed_path = db.0.options.compressed_data_path(&hash);
+        let compressed_len = std::fs::metadata(&compressed_path).unwrap().len();
+        assert!(compressed_len < data.len() as u64);
+        assert!(!db.owned_data_path(&hash).exists());
+
+        // large data is compressed in blocks and read at offsets
+        let large: Bytes = (0..1024 * 600)
+            .map(|i| (i / 1000) as u8)
+            .collect::<Vec<_>>()
+            .into();
+        let large_tag = baomap::Store::import_bytes(&db, large.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let large_hash = *large_tag.hash();
+        let large_compressed_path = db.0.options.compressed_data_path(&large_hash);
+        assert!(!db.owned_data_path(&large_hash).exists());
+        assert!(std::fs::metadata(&large_compressed_path).unwrap().len() < large.len() as u64);
+        let entry = db.get(&large_hash).unwrap();
+        let mut reader = entry.data_reader().await.unwrap();
+        let offset = COMPRESSION_BLOCK_SIZE - 100;
+        assert_eq!(
+            reader.read_at(offset, 200_000).await.unwrap(),
+            large.slice(offset as usize..offset as usize + 200_000)
+        );
+        assert_eq!(reader.len().await.unwrap(), large.len() as u64);
+        baomap::validate_bao(&entry).await.unwrap();
+        drop(db);
+        // a new store loads both, regardless of the setting
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert!(!db.compress_data());
+        let entry = db.get(&hash).unwrap();
+        assert_eq!(entry.size(), data.len() as u64);
+        let mut reader = entry.data_reader().await.unwrap();
+        assert_eq!(reader.read_at(0, data.len()).await.unwrap(), data);
+        let entry = db.get(&large_hash).unwrap();
+        assert_eq!(entry.size(), large.len() as u64);
+        let mut reader = entry.data_reader().await.unwrap();
+        assert_eq!(reader.read_at(0, large.len()).await.unwrap(), large);
+        baomap::validate_bao(&entry).await.unwrap();
+        let large_target = dir.path().join("export-large");
+        db.export(
+            large_hash,
+            large_target.clone(),
+            ExportMode::TryReference,
+            |_| Ok(()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&large_target).unwrap(), large);
+        assert!(large_compressed_path.exists());
+
+        // adding the data again keeps the compressed file
+        baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        assert!(compressed_path.exists());
+        assert!(!db.owned_data_path(&hash).exists());
+
+        let target = dir.path().join("export");
+        db.export(hash, target.clone(), ExportMode::TryReference, |_| Ok(()))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), data);
+
+        baomap::Store::delete(&db, &hash).await.unwrap();
+        assert!(!compressed_path.exists());
+    }
+
+    #[tokio::test]
+    async fn pins_expire_and_persist() {
+        use baomap::Store as _;
+        use futures::StreamExt;
+        use iroh_bytes::collection::LinkSeqCollectionParser;
+
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let mut hashes = Vec::new();
+        for i in 0..3u8 {
+            let tag = db
+                .import_bytes(vec![i; 16].into(), BlobFormat::RAW)
+                .await
+                .unwrap();
+            hashes.push(*tag.hash());
+        }
+        let now = SystemTime::now();
+        let permanent = HashAndFormat(hashes[0], BlobFormat::RAW);
+        let expiring = HashAndFormat(hashes[1], BlobFormat::RAW);
+        let expired = HashAndFormat(hashes[2], BlobFormat::RAW);
+        db.pin(permanent, None).await.unwrap();
+        db.pin(expiring, Some(now + Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        db.pin(expired, Some(now - Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        // pins survive a restart, expired pins are not reported
+        drop(db);
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let pins = db.pins().collect::<BTreeMap<_, _>>();
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[&permanent], None);
+        let ttl = pins[&expiring].unwrap();
+        assert!(ttl > Duration::from_secs(3500) && ttl <= Duration::from_secs(3600));
+
+        // gc only keeps the content of pins that did not expire
+        let mut events = db.gc_mark(LinkSeqCollectionParser::default(), None);
+        while events.next().await.is_some() {}
+        drop(events);
+        let mut events = db.gc_sweep();
+        while events.next().await.is_some() {}
+        drop(events);
+        assert!(db.get(&hashes[0]).is_some());
+        assert!(db.get(&hashes[1]).is_some());
+        assert!(db.get(&hashes[2]).is_none());
+
+        db.unpin(permanent).await.unwrap();
+        assert_eq!(db.pins().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn data_readers_share_bounded_file_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let data = Bytes::from(vec![7u8; 1024 * 64]);
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let entry = db.get(tag.hash()).unwrap();
+        let (MemOrFile::Shared(mut a), MemOrFile::Shared(b)) = (
+            entry.data_reader().await.unwrap(),
+            entry.data_reader().await.unwrap(),
+        ) else {
+            panic!("complete data files are read through shared handles");
+        };
+        assert!(Arc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a.read_at(0, usize::MAX).await.unwrap(), data);
+        assert_eq!(a.read_at(1024, 10).await.unwrap(), data.slice(1024..1034));
+        assert!(a.read_at(data.len() as u64, 10).await.unwrap().is_empty());
+
+        // the number of open files is bounded, idle handles are closed to make room
+        let handles = FileHandles::new(2);
+        let mut paths = Vec::new();
+        for i in 0..3u8 {
+            let path = dir.path().join(format!("file-{i}"));
+            std::fs::write(&path, [i; 16]).unwrap();
+            paths.push(path);
+        }
+        let first = handles.open(paths[0].clone()).await.unwrap();
+        let second = handles.open(paths[1].clone()).await.unwrap();
+        assert_eq!(handles.permits.available_permits(), 0);
+        drop(second);
+        let third = handles.open(paths[2].clone()).await.unwrap();
+        assert_eq!(handles.cache.lock().unwrap().len(), 2);
+        assert_eq!(handles.permits.available_permits(), 0);
+        // a handle that is in use is not closed when it is evicted
+        handles.invalidate(&paths[0]);
+        let mut first = first;
+        assert_eq!(
+            first.read_at(0, 16).await.unwrap(),
+            Bytes::from(vec![0u8; 16])
+        );
+        drop(first);
+        drop(third);
+        assert_eq!(handles.permits.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn outboard_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let cached = |db: &Store| {
+            let inner = db.0.outboards.0.lock().unwrap();
+            (inner.cache.len(), inner.size)
+        };
+        let mut hashes = Vec::new();
+        for i in 0..2u8 {
+            let data = Bytes::from(vec![i; 1024 * 1024]);
+            let tag = baomap::Store::import_bytes(&db, data, BlobFormat::RAW)
+                .await
+                .unwrap();
+            hashes.push(*tag.hash());
+            tag.leak();
+        }
+        let outboard = |db: &Store, hash: &Hash| {
+            let entry = db.get(hash).unwrap();
+            async move {
+                let mut outboard = entry.outboard().await.unwrap().data;
+                outboard.read_at(0, usize::MAX).await.unwrap()
+            }
+        };
+        let expected = outboard(&db, &hashes[0]).await;
+        let len = expected.len() as u64;
+        assert_eq!(cached(&db), (2, 2 * len));
+
+        // the least recently used outboard is dropped, and read again when needed
+        db.set_outboard_cache_size(len);
+        assert_eq!(db.outboard_cache_size(), len);
+        assert_eq!(cached(&db), (1, len));
+        assert_eq!(outboard(&db, &hashes[0]).await, expected);
+        assert_eq!(cached(&db), (1, len));
+        assert!(db
+            .0
+            .outboards
+            .0
+            .lock()
+            .unwrap()
+            .cache
+            .contains_key(&hashes[0]));
+
+        // outboards larger than the limit are not cached
+        db.set_outboard_cache_size(len - 1);
+        assert_eq!(cached(&db), (0, 0));
+        assert_eq!(outboard(&db, &hashes[0]).await, expected);
+        assert_eq!(cached(&db), (0, 0));
+        let entry = db.get(&hashes[0]).unwrap();
+        let reader = entry.outboard().await.unwrap().data;
+        assert!(matches!(reader, MemOrFile::File(_)));
+
+        // outboards are not read on load, but when they are first needed
+        drop(db);
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        assert_eq!(cached(&db), (0, 0));
+
+        // concurrent readers of the same outboard share one load
+        let outboards = futures::future::join_all((0..8).map(|_| outboard(&db, &hashes[0]))).await;
+        assert!(outboards.iter().all(|outboard| *outboard == expected));
+        assert_eq!(cached(&db), (1, len));
+        assert!(db.0.outboards.0.lock().unwrap().loading.is_empty());
+        baomap::Store::delete(&db, &hashes[0]).await.unwrap();
+        assert_eq!(cached(&db), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn ensure_space() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let available = baomap::Store::available_space(&db).unwrap().unwrap();
+        assert!(available > 0);
+        baomap::ensure_space(&db, 0).unwrap();
+        let err = baomap::ensure_space(&db, u64::MAX).unwrap_err();
+        let err = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<baomap::InsufficientSpace>())
+            .unwrap();
+        assert_eq!(err.required, u64::MAX);
+        assert!(err.available < u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn collection_total_size() {
+        use iroh_bytes::collection::{LinkSeq, LinkSeqCollectionParser};
+
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let mut tags = Vec::new();
+        for size in [10, 1000, 100_000] {
+            let tag = baomap::Store::import_bytes(&db, vec![1u8; size].into(), BlobFormat::RAW)
+                .await
+                .unwrap();
+            tags.push(tag);
+        }
+        let links: LinkSeq = tags.iter().map(|tag| *tag.hash()).collect();
+        let collection =
+            baomap::Store::import_bytes(&db, links.into_inner(), BlobFormat::COLLECTION)
+                .await
+                .unwrap();
+        let cp = LinkSeqCollectionParser::default();
+        let size = db
+            .collection_total_size(cp.clone(), *collection.hash())
+            .await;
+        assert_eq!(size, Some(101_010));
+
+        // with a child missing the size is unknown
+        let links: LinkSeq = [*tags[0].hash(), Hash::from(blake3::hash(b"missing"))]
+            .into_iter()
+            .collect();
+        let collection =
+            baomap::Store::import_bytes(&db, links.into_inner(), BlobFormat::COLLECTION)
+                .await
+                .unwrap();
+        let size = db
+            .collection_total_size(cp.clone(), *collection.hash())
+            .await;
+        assert_eq!(size, None);
+
+        // as is the size of a collection that is not in the store
+        let size = db
+            .collection_total_size(cp, Hash::from(blake3::hash(b"missing")))
+            .await;
+        assert_eq!(size, None);
+    }
+
+    #[tokio::test]
+    async fn collections_containing() {
+        use iroh_bytes::collection::{LinkSeq, LinkSeqCollectionParser};
+
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+        let child = baomap::Store::import_bytes(&db, vec![1u8; 1000].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let other = baomap::Store::import_bytes(&db, vec![2u8; 1000].into(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let import_collection = |links: LinkSeq| {
+            baomap::Store::import_bytes(&db, links.into_inner(), BlobFormat::COLLECTION)
+        };
+        let tagged = import_collection([*child.hash()].into_iter().collect())
+            .await
+            .unwrap();
+        baomap::Store::set_tag(
+            &db,
+            Tag::from(String::from("tagged")),
+            Some(*tagged.inner()),
+        )
+        .await
+        .unwrap();
+        let without_child = import_collection([*other.hash()].into_iter().collect())
+            .await
+            .unwrap();
+        baomap::Store::set_tag(
+            &db,
+            Tag::from(String::from("other")),
+            Some(*without_child.inner()),
+        )
+        .await
+        .unwrap();
+        // only kept alive by a temp tag, e.g. like the collections of documents
+        let untagged = import_collection([*child.hash(), *other.hash()].into_iter().collect())
+            .await
+            .unwrap();
+
+        let cp = LinkSeqCollectionParser::default();
+        let found = db.collections_containing(cp.clone(), *child.hash()).await;
+        assert_eq!(found, vec![*tagged.hash()]);
+        assert!(!found.contains(untagged.hash()));
+        let found = db.collections_containing(cp, *other.hash()).await;
+        assert_eq!(found, vec![*without_child.hash()]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[tokio::test]
+    async fn export_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let rt = iroh_bytes::util::runtime::Handle::from_current(1).unwrap();
+        let db = Store::load(dir.path(), dir.path(), dir.path(), &rt)
+            .await
+            .unwrap();
+
+        // large blobs are mapped from their data file
+        let data = Bytes::from(vec![3u8; 1024 * 1024]);
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let mapped = db.export_mmap(*tag.hash()).await.unwrap();
+        assert!(matches!(mapped, baomap::MappedBlob::Mmap(_)));
+        assert_eq!(&mapped[..], &data[..]);
+
+        // small blobs are kept in memory
+        let data = Bytes::from_static(b"small");
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        let mapped = db.export_mmap(*tag.hash()).await.unwrap();
+        assert!(matches!(mapped, baomap::MappedBlob::Bytes(_)));
+        assert_eq!(&mapped[..], &data[..]);
+
+        // compressed data can not be mapped, it is decoded instead
+        db.set_compress_data(true);
+        let data = Bytes::from(
+            (0..1024 * 1024)
+                .map(|i| (i % 251) as u8)
+                .collect::<Vec<_>>(),
+        );
+        let tag = baomap::Store::import_bytes(&db, data.clone(), BlobFormat::RAW)
+            .await
+            .unwrap();
+        assert!(db.0.state.read().unwrap().complete[tag.hash()].compressed);
+        let mapped = db.export_mmap(*tag.hash()).await.unwrap();
+        assert!(matches!(mapped, baomap::MappedBlob::Bytes(_)));
+        assert_eq!(&mapped[..], &data[..]);
+
+        let err = db
+            .export_mmap(Hash::from(blake3::hash(b"missing")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
     proptest! {
         #[test]
         fn filename_roundtrip(name in arb_filename()) {
@@ -10,9 +10,11 @@ use std::ops::DerefMut;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::RwLock;
+use std::time::Duration;
 use std::time::SystemTime;
 
 use super::flatten_to_io;
+use super::live_pins;
 use bao_tree::blake3;
 use bao_tree::io::fsm::Outboard;
 use bao_tree::io::outboard::PreOrderOutboard;
@@ -25,6 +27,8 @@ use bytes::BytesMut;
 use derive_more::From;
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use futures::Stream;
+use futures::StreamExt;
 use iroh_bytes::baomap;
 use iroh_bytes::baomap::range_collections::RangeSet2;
 use iroh_bytes::baomap::EntryStatus;
@@ -35,7 +39,9 @@ use iroh_bytes::baomap::LivenessTracker;
 use iroh_bytes::baomap::PartialMap;
 use iroh_bytes::baomap::PartialMapEntry;
 use iroh_bytes::baomap::TempTag;
+use iroh_bytes::baomap::ValidateOptions;
 use iroh_bytes::baomap::ValidateProgress;
+use iroh_bytes::baomap::DATA_POLL_INTERVAL;
 use iroh_bytes::baomap::{Map, MapEntry, ReadableStore};
 use iroh_bytes::util::progress::IdGenerator;
 use iroh_bytes::util::progress::IgnoreProgressSender;
@@ -47,7 +53,7 @@ use iroh_bytes::util::Tag;
 use iroh_bytes::{Hash, IROH_BLOCK_SIZE};
 use iroh_io::AsyncSliceReader;
 use iroh_io::AsyncSliceWriter;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 
 /// A mutable file like object that can be used for partial entries.
 #[derive(Debug, Clone, Default)]
@@ -60,6 +66,16 @@ impl MutableMemFile {
         Self(Arc::new(RwLock::new(BytesMut::with_capacity(capacity))))
     }
 
+    /// A copy of the current content
+    fn snapshot(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.0.read().unwrap())
+    }
+
+    /// The current length of the content
+    fn current_len(&self) -> u64 {
+        self.0.read().unwrap().len() as u64
+    }
+
     /// Freeze the data, returning the content
     ///
     /// Note that this will clear other references to the data.
@@ -125,6 +141,24 @@ pub enum MemFile {
     Mutable(MutableMemFile),
 }
 
+impl MemFile {
+    /// A copy of the current content
+    fn snapshot(&self) -> Bytes {
+        match self {
+            Self::Immutable(data) => data.clone(),
+            Self::Mutable(data) => data.snapshot(),
+        }
+    }
+
+    /// The current length of the content
+    fn current_len(&self) -> u64 {
+        match self {
+            Self::Immutable(data) => data.len() as u64,
+            Self::Mutable(data) => data.current_len(),
+        }
+    }
+}
+
 impl AsyncSliceReader for MemFile {
     type ReadAtFuture<'a> = <BytesMut as AsyncSliceReader>::ReadAtFuture<'a>;
 
@@ -197,6 +231,8 @@ pub struct Store(Arc<Inner>);
 struct Inner {
     rt: runtime::Handle,
     state: RwLock<State>,
+    /// Notified whenever data is written to a partial entry, or an entry is completed.
+    data_written: Notify,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -205,6 +241,7 @@ struct State {
     partial: BTreeMap<Hash, (MutableMemFile, PreOrderOutboard<MutableMemFile>)>,
     tags: BTreeMap<Tag, HashAndFormat>,
     temp: BTreeMap<HashAndFormat, u64>,
+    pins: BTreeMap<HashAndFormat, Option<SystemTime>>,
     live: BTreeSet<Hash>,
 }
 
@@ -223,7 +260,17 @@ impl MapEntry<Store> for Entry {
     }
 
     fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<ChunkNum>>> {
-        futures::future::ok(RangeSet2::all()).boxed()
+        if self.is_complete {
+            return futures::future::ok(RangeSet2::all()).boxed();
+        }
+        let data = self.outboard.data.snapshot();
+        let outboard = PreOrderOutboard {
+            root: self.outboard.root,
+            tree: self.outboard.tree,
+            data: &data[..],
+        };
+        let data_size = self.data.current_len();
+        futures::future::ready(super::partial_available_ranges(&outboard, data_size)).boxed()
     }
 
     fn size(&self) -> u64 {
@@ -257,7 +304,14 @@ impl MapEntry<Store> for PartialEntry {
     }
 
     fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<bao_tree::ChunkNum>>> {
-        futures::future::ok(RangeSet2::all()).boxed()
+        let data = self.outboard.data.snapshot();
+        let outboard = PreOrderOutboard {
+            root: self.outboard.root,
+            tree: self.outboard.tree,
+            data: &data[..],
+        };
+        let data_size = self.data.current_len();
+        futures::future::ready(super::partial_available_ranges(&outboard, data_size)).boxed()
     }
 
     fn size(&self) -> u64 {
@@ -327,6 +381,16 @@ impl Map for Store {
             EntryStatus::NotFound
         }
     }
+
+    fn data_written(&self, _hash: &Hash) -> BoxFuture<'static, ()> {
+        let inner = self.0.clone();
+        async move {
+            tokio::time::timeout(DATA_POLL_INTERVAL, inner.data_written.notified())
+                .await
+                .ok();
+        }
+        .boxed()
+    }
 }
 
 impl ReadableStore for Store {
@@ -370,8 +434,36 @@ impl ReadableStore for Store {
         Box::new(tags.into_iter())
     }
 
-    fn validate(&self, _tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>> {
-        futures::future::err(anyhow::anyhow!("validate not implemented")).boxed()
+    fn pins(
+        &self,
+    ) -> Box<dyn Iterator<Item = (HashAndFormat, Option<Duration>)> + Send + Sync + 'static> {
+        let pins = live_pins(&self.0.state.read().unwrap().pins, SystemTime::now());
+        Box::new(pins.into_iter())
+    }
+
+    fn validate(&self, tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.validate_with_options(Default::default(), tx)
+    }
+
+    fn validate_with_concurrency(
+        &self,
+        concurrency: usize,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let options = ValidateOptions {
+            concurrency,
+            ..Default::default()
+        };
+        self.validate_with_options(options, tx)
+    }
+
+    fn validate_with_options(
+        &self,
+        options: ValidateOptions,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let hashes = self.blobs().collect();
+        baomap::validate_blobs(self, hashes, options, self.0.rt.local_pool(), tx).boxed()
     }
 
     fn partial_blobs(&self) -> Box<dyn Iterator<Item = Hash> + Send + Sync + 'static> {
@@ -458,10 +550,16 @@ impl PartialMap for Store {
             };
             state.partial.remove(&hash);
             state.complete.insert(hash, (data, outboard));
+            drop(state);
+            self.0.data_written.notify_waiters();
             Ok(())
         }
         .boxed()
     }
+
+    fn notify_data_written(&self, _hash: &Hash) {
+        self.0.data_written.notify_waiters();
+    }
 }
 
 impl baomap::Store for Store {
@@ -509,6 +607,39 @@ impl baomap::Store for Store {
             .boxed()
     }
 
+    fn import_stream(
+        &self,
+        mut data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> BoxFuture<'_, io::Result<(TempTag, u64)>> {
+        let this = self.clone();
+        async move {
+            let id = progress.new_id();
+            // the data ends up in memory anyway
+            let mut bytes = BytesMut::new();
+            while let Some(chunk) = data.next().await {
+                bytes.extend_from_slice(&chunk?);
+                progress
+                    .send(ImportProgress::CopyProgress {
+                        id,
+                        offset: bytes.len() as u64,
+                    })
+                    .await?;
+            }
+            let bytes = bytes.freeze();
+            let size = bytes.len() as u64;
+            progress.send(ImportProgress::Size { id, size }).await?;
+            let rt = this.0.rt.main().clone();
+            let tag = rt
+                .spawn_blocking(move || this.import_bytes_sync(id, bytes, format, progress))
+                .map(flatten_to_io)
+                .await?;
+            Ok((tag, size))
+        }
+        .boxed()
+    }
+
     fn set_tag(&self, name: Tag, value: Option<HashAndFormat>) -> BoxFuture<'_, io::Result<()>> {
         let mut state = self.0.state.write().unwrap();
         if let Some(value) = value {
@@ -530,6 +661,22 @@ impl baomap::Store for Store {
         TempTag::new(tag, Some(self.0.clone()))
     }
 
+    fn pin(
+        &self,
+        value: HashAndFormat,
+        expiry: Option<SystemTime>,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        let mut state = self.0.state.write().unwrap();
+        state.pins.insert(value, expiry);
+        futures::future::ok(()).boxed()
+    }
+
+    fn unpin(&self, value: HashAndFormat) -> BoxFuture<'_, io::Result<()>> {
+        let mut state = self.0.state.write().unwrap();
+        state.pins.remove(&value);
+        futures::future::ok(()).boxed()
+    }
+
     fn clear_live(&self) {
         let mut state = self.0.state.write().unwrap();
         state.live.clear();
@@ -579,6 +726,7 @@ impl Store {
         Self(Arc::new(Inner {
             rt,
             state: RwLock::new(State::default()),
+            data_written: Notify::new(),
         }))
     }
 
@@ -6,6 +6,7 @@ use std::{
     io,
     path::PathBuf,
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use bao_tree::{
@@ -19,7 +20,7 @@ use bao_tree::{
 use bytes::{Bytes, BytesMut};
 use futures::{
     future::{self, BoxFuture},
-    FutureExt,
+    FutureExt, Stream,
 };
 use iroh_bytes::{
     baomap::{
@@ -254,6 +255,12 @@ impl ReadableStore for Store {
         Box::new(std::iter::empty())
     }
 
+    fn pins(
+        &self,
+    ) -> Box<dyn Iterator<Item = (HashAndFormat, Option<Duration>)> + Send + Sync + 'static> {
+        Box::new(std::iter::empty())
+    }
+
     fn validate(
         &self,
         _tx: mpsc::Sender<ValidateProgress>,
@@ -338,6 +345,16 @@ impl baomap::Store for Store {
         async move { Err(io::Error::new(io::ErrorKind::Other, "not implemented")) }.boxed()
     }
 
+    fn import_stream(
+        &self,
+        data: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+        format: BlobFormat,
+        progress: impl ProgressSender<Msg = ImportProgress> + IdGenerator,
+    ) -> BoxFuture<'_, io::Result<(TempTag, u64)>> {
+        let _ = (data, format, progress);
+        async move { Err(io::Error::new(io::ErrorKind::Other, "not implemented")) }.boxed()
+    }
+
     fn clear_live(&self) {}
 
     fn set_tag(&self, _name: Tag, _hash: Option<HashAndFormat>) -> BoxFuture<'_, io::Result<()>> {
@@ -352,6 +369,18 @@ impl baomap::Store for Store {
         TempTag::new(inner, None)
     }
 
+    fn pin(
+        &self,
+        _value: HashAndFormat,
+        _expiry: Option<SystemTime>,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        async move { Err(io::Error::new(io::ErrorKind::Other, "not implemented")) }.boxed()
+    }
+
+    fn unpin(&self, _value: HashAndFormat) -> BoxFuture<'_, io::Result<()>> {
+        async move { Err(io::Error::new(io::ErrorKind::Other, "not implemented")) }.boxed()
+    }
+
     fn add_live(&self, _live: impl IntoIterator<Item = Hash>) {}
 
     fn delete(&self, _hash: &Hash) -> BoxFuture<'_, io::Result<()>> {
@@ -0,0 +1,431 @@
+//! A database for iroh-bytes that serves blobs from several backing stores.
+//!
+//! Main entry point is [Store].
+//!
+//! The backing stores are ordered from fastest to slowest, e.g. a small store on an SSD in
+//! front of a large archival store. Lookups check the tiers in order, so the fastest copy of a
+//! blob is served. Blobs that are read often can be promoted to the first tier, see
+//! [`Store::with_promotion`].
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bao_tree::{blake3, ChunkNum};
+use futures::{future::BoxFuture, FutureExt};
+use iroh_bytes::{
+    baomap::{
+        self, range_collections::RangeSet2, EntryStatus, ExportMode, Map, MapEntry, ReadableStore,
+        ValidateOptions, ValidateProgress, ValidateProgressPolicy,
+    },
+    util::{runtime, HashAndFormat, Tag},
+    Hash,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// A database that composes several backing stores of the same type.
+///
+/// This implements [Map] and [ReadableStore] by delegating to the tiers. Modifications
+/// have to be done on the tiers directly, see [`Store::tiers`].
+#[derive(Debug, Clone)]
+pub struct Store<D> {
+    inner: Arc<Inner<D>>,
+    /// Whether [`Map::get`] counts as a read for promotion, see [`Store::external_reads`].
+    external: bool,
+}
+
+#[derive(derive_more::Debug)]
+struct Inner<D> {
+    tiers: Vec<D>,
+    #[debug("{:?}", promotion.as_ref().map(|p| p.after))]
+    promotion: Option<Promotion>,
+}
+
+/// Copies hot blobs to the first tier.
+struct Promotion {
+    /// Number of accesses after which a blob is promoted.
+    after: u64,
+    /// Number of accesses of blobs that are not in the first tier.
+    hits: Mutex<HashMap<Hash, u64>>,
+    /// Blobs that are being copied to the first tier.
+    in_flight: Arc<Mutex<HashSet<Hash>>>,
+    /// Copies a blob from the given tier to the first tier, in the background, and removes it
+    /// from `in_flight` when done.
+    promote: Box<dyn Fn(Hash, usize) + Send + Sync>,
+}
+
+impl<D: Map> Store<D> {
+    /// Creates a store from tiers, ordered from fastest to slowest.
+    pub fn new(tiers: impl IntoIterator<Item = D>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                tiers: tiers.into_iter().collect(),
+                promotion: None,
+            }),
+            external: false,
+        }
+    }
+
+    /// The tiers of this store, ordered from fastest to slowest.
+    pub fn tiers(&self) -> &[D] {
+        &self.inner.tiers
+    }
+
+    /// A handle to this store whose [`Map::get`] counts as a read for promotion.
+    ///
+    /// Reads through the store itself, e.g. by garbage collection or validation, are not
+    /// counted. Hand this handle to the code that serves blobs to clients, so that only their
+    /// reads make a blob hot.
+    pub fn external_reads(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            external: true,
+        }
+    }
+
+    /// The tier that has the entry for `hash`, and the entry.
+    ///
+    /// Complete entries are preferred over partial entries in a faster tier.
+    fn find(&self, hash: &Hash) -> Option<(usize, D::Entry)> {
+        let mut partial = None;
+        for (tier, db) in self.inner.tiers.iter().enumerate() {
+            match db.contains(hash) {
+                EntryStatus::Complete => return db.get(hash).map(|entry| (tier, entry)),
+                EntryStatus::Partial if partial.is_none() => partial = Some(tier),
+                _ => {}
+            }
+        }
+        let tier = partial?;
+        self.inner.tiers[tier].get(hash).map(|entry| (tier, entry))
+    }
+
+    /// Counts an access of a complete entry in a slower tier, and promotes it if it is hot.
+    fn on_access(&self, hash: &Hash, tier: usize) {
+        let Some(promotion) = &self.inner.promotion else {
+            return;
+        };
+        if tier == 0 {
+            return;
+        }
+        let mut in_flight = promotion.in_flight.lock().unwrap();
+        if in_flight.contains(hash) {
+            return;
+        }
+        let mut hits = promotion.hits.lock().unwrap();
+        let count = hits.entry(*hash).or_default();
+        *count += 1;
+        if *count >= promotion.after {
+            // start counting again, in case the copy fails or is removed from the first tier
+            hits.remove(hash);
+            in_flight.insert(*hash);
+            (promotion.promote)(*hash, tier);
+        }
+    }
+}
+
+impl<D: baomap::Store> Store<D> {
+    /// Creates a store from tiers that copies blobs to the first tier once they were
+    /// read `after` times in a slower tier through [`Store::external_reads`].
+    ///
+    /// The copy is done in the background on the local pool of `rt`, until then the blob is
+    /// served from the slower tier. Reads during the copy are not counted. Promoted blobs are not tagged in the first tier, so its garbage collection
+    /// can remove them again. They are still available from the slower tiers then.
+    pub fn with_promotion(
+        tiers: impl IntoIterator<Item = D>,
+        after: u64,
+        rt: &runtime::Handle,
+    ) -> Self {
+        let tiers: Vec<D> = tiers.into_iter().collect();
+        let in_flight: Arc<Mutex<HashSet<Hash>>> = Default::default();
+        let promote = {
+            let tiers = tiers.clone();
+            let rt = rt.clone();
+            let in_flight = in_flight.clone();
+            move |hash: Hash, tier: usize| {
+                let from = tiers[tier].clone();
+                let to = tiers[0].clone();
+                let in_flight = in_flight.clone();
+                // the readers and writers of the stores are not necessarily Send
+                rt.local_pool().spawn_pinned(move || async move {
+                    match to.import_from_store(&from, hash).await {
+                        Ok(()) => tracing::debug!("promoted {} from tier {}", hash, tier),
+                        Err(err) => tracing::warn!("failed to promote {}: {}", hash, err),
+                    }
+                    in_flight.lock().unwrap().remove(&hash);
+                });
+            }
+        };
+        let promotion = (tiers.len() > 1).then(|| Promotion {
+            after: after.max(1),
+            hits: Default::default(),
+            in_flight,
+            promote: Box::new(promote),
+        });
+        Self {
+            inner: Arc::new(Inner { tiers, promotion }),
+            external: false,
+        }
+    }
+}
+
+/// The [MapEntry] implementation for [Store].
+#[derive(Debug, Clone)]
+pub struct Entry<E> {
+    inner: E,
+    tier: usize,
+}
+
+impl<E> Entry<E> {
+    /// The index of the tier this entry is served from.
+    pub fn tier(&self) -> usize {
+        self.tier
+    }
+}
+
+impl<D: Map> MapEntry<Store<D>> for Entry<D::Entry> {
+    fn hash(&self) -> blake3::Hash {
+        self.inner.hash()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.inner.is_complete()
+    }
+
+    fn available_ranges(&self) -> BoxFuture<'_, io::Result<RangeSet2<ChunkNum>>> {
+        self.inner.available_ranges()
+    }
+
+    fn outboard(&self) -> BoxFuture<'_, io::Result<D::Outboard>> {
+        self.inner.outboard()
+    }
+
+    fn data_reader(&self) -> BoxFuture<'_, io::Result<D::DataReader>> {
+        self.inner.data_reader()
+    }
+}
+
+impl<D: Map> Map for Store<D> {
+    type Outboard = D::Outboard;
+    type DataReader = D::DataReader;
+    type Entry = Entry<D::Entry>;
+
+    fn get(&self, hash: &Hash) -> Option<Self::Entry> {
+        let (tier, inner) = self.find(hash)?;
+        if self.external && inner.is_complete() {
+            self.on_access(hash, tier);
+        }
+        Some(Entry { inner, tier })
+    }
+
+    fn contains(&self, hash: &Hash) -> EntryStatus {
+        let mut res = EntryStatus::NotFound;
+        for db in &self.inner.tiers {
+            match db.contains(hash) {
+                EntryStatus::Complete => return EntryStatus::Complete,
+                EntryStatus::Partial => res = EntryStatus::Partial,
+                EntryStatus::NotFound => {}
+            }
+        }
+        res
+    }
+
+    fn data_written(&self, hash: &Hash) -> BoxFuture<'static, ()> {
+        match self.find(hash) {
+            Some((tier, _)) => self.inner.tiers[tier].data_written(hash),
+            None => tokio::time::sleep(baomap::DATA_POLL_INTERVAL).boxed(),
+        }
+    }
+}
+
+impl<D: ReadableStore> ReadableStore for Store<D> {
+    fn blobs(&self) -> Box<dyn Iterator<Item = Hash> + Send + Sync + 'static> {
+        let blobs: BTreeSet<Hash> = self.inner.tiers.iter().flat_map(|db| db.blobs()).collect();
+        Box::new(blobs.into_iter())
+    }
+
+    fn tags(&self) -> Box<dyn Iterator<Item = (Tag, HashAndFormat)> + Send + Sync + 'static> {
+        // faster tiers win if a tag is set in several tiers
+        let mut tags = BTreeMap::new();
+        for db in self.inner.tiers.iter().rev() {
+            tags.extend(db.tags());
+        }
+        Box::new(tags.into_iter())
+    }
+
+    fn temp_tags(&self) -> Box<dyn Iterator<Item = HashAndFormat> + Send + Sync + 'static> {
+        let temp_tags: Vec<_> = self
+            .inner
+            .tiers
+            .iter()
+            .flat_map(|db| db.temp_tags())
+            .collect();
+        Box::new(temp_tags.into_iter())
+    }
+
+    fn pins(
+        &self,
+    ) -> Box<dyn Iterator<Item = (HashAndFormat, Option<Duration>)> + Send + Sync + 'static> {
+        let pins: Vec<_> = self.inner.tiers.iter().flat_map(|db| db.pins()).collect();
+        Box::new(pins.into_iter())
+    }
+
+    fn validate(&self, tx: mpsc::Sender<ValidateProgress>) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.validate_with_options(Default::default(), tx)
+    }
+
+    fn validate_with_concurrency(
+        &self,
+        concurrency: usize,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        let options = ValidateOptions {
+            concurrency,
+            ..Default::default()
+        };
+        self.validate_with_options(options, tx)
+    }
+
+    /// Validates the tiers one after the other.
+    ///
+    /// Only the [`ValidateProgress::AllDone`] of the last tier is forwarded.
+    fn validate_with_options(
+        &self,
+        options: ValidateOptions,
+        tx: mpsc::Sender<ValidateProgress>,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let last = self.inner.tiers.len().saturating_sub(1);
+            for (tier, db) in self.inner.tiers.iter().enumerate() {
+                let (tier_tx, mut tier_rx) = mpsc::channel(16);
+                let forward = async {
+                    while let Some(msg) = tier_rx.recv().await {
+                        let sent = match msg {
+                            ValidateProgress::AllDone if tier != last => continue,
+                            ValidateProgress::Progress { .. }
+                                if options.progress == ValidateProgressPolicy::Coalesce =>
+                            {
+                                !matches!(tx.try_send(msg), Err(TrySendError::Closed(_)))
+                            }
+                            msg => tx.send(msg).await.is_ok(),
+                        };
+                        if !sent {
+                            break;
+                        }
+                    }
+                };
+                let (res, ()) =
+                    futures::future::join(db.validate_with_options(options, tier_tx), forward)
+                        .await;
+                res?;
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn partial_blobs(&self) -> Box<dyn Iterator<Item = Hash> + Send + Sync + 'static> {
+        let partial: BTreeSet<Hash> = self
+            .inner
+            .tiers
+            .iter()
+            .flat_map(|db| db.partial_blobs())
+            .filter(|hash| self.contains(hash) == EntryStatus::Partial)
+            .collect();
+        Box::new(partial.into_iter())
+    }
+
+    fn export(
+        &self,
+        hash: Hash,
+        target: PathBuf,
+        mode: ExportMode,
+        progress: impl Fn(u64) -> io::Result<()> + Send + Sync + 'static,
+    ) -> BoxFuture<'_, io::Result<()>> {
+        async move {
+            let (tier, _) = self
+                .find(&hash)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "hash not found"))?;
+            self.inner.tiers[tier]
+                .export(hash, target, mode, progress)
+                .await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(all(test, feature = "mem-db"))]
+mod tests {
+    use anyhow::Context;
+    use bytes::Bytes;
+    use iroh_bytes::{baomap::Store as _, util::BlobFormat};
+    use iroh_io::AsyncSliceReaderExt;
+
+    use super::*;
+    use crate::baomap::mem;
+
+    /// Reads a blob, returning the tier it was served from and its data.
+    async fn read(db: &Store<mem::Store>, hash: &Hash) -> anyhow::Result<(usize, Bytes)> {
+        let entry = db.get(hash).context("blob not found")?;
+        let mut reader = MapEntry::<Store<mem::Store>>::data_reader(&entry).await?;
+        Ok((entry.tier(), reader.read_to_end().await?))
+    }
+
+    #[tokio::test]
+    async fn tiered_get_and_promote() -> anyhow::Result<()> {
+        let rt = runtime::Handle::from_current(1)?;
+        let fast = mem::Store::new(rt.clone());
+        let slow = mem::Store::new(rt.clone());
+        let data = Bytes::from(vec![7u8; 1024 * 100]);
+        let tag = slow.import_bytes(data.clone(), BlobFormat::RAW).await?;
+        let hash = *tag.hash();
+        let small = fast
+            .import_bytes(Bytes::from_static(b"small"), BlobFormat::RAW)
+            .await?;
+
+        let db = Store::with_promotion([fast.clone(), slow.clone()], 2, &rt);
+        assert_eq!(db.contains(&hash), EntryStatus::Complete);
+        assert_eq!(db.contains(&Hash::new(b"missing")), EntryStatus::NotFound);
+        assert_eq!(db.get(small.hash()).unwrap().tier(), 0);
+        let blobs = db.blobs().collect::<BTreeSet<_>>();
+        assert_eq!(blobs, [hash, *small.hash()].into_iter().collect());
+
+        // internal reads are not counted
+        for _ in 0..3 {
+            assert_eq!(read(&db, &hash).await?, (1, data.clone()));
+        }
+        assert_eq!(fast.contains(&hash), EntryStatus::NotFound);
+
+        // the first external read is served from the slow tier
+        let external = db.external_reads();
+        assert_eq!(read(&external, &hash).await?, (1, data.clone()));
+        assert_eq!(fast.contains(&hash), EntryStatus::NotFound);
+
+        // the second external read promotes the blob to the fast tier, reads during the copy
+        // do not start another one
+        external.get(&hash).unwrap();
+        for _ in 0..3 {
+            external.get(&hash).unwrap();
+        }
+        let promotion = db.inner.promotion.as_ref().unwrap();
+        assert!(promotion.hits.lock().unwrap().is_empty());
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while fast.contains(&hash) != EntryStatus::Complete
+                || !promotion.in_flight.lock().unwrap().is_empty()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        assert_eq!(read(&db, &hash).await?, (0, data));
+        let ranges = baomap::validate_bao::<mem::Store>(&fast.get(&hash).unwrap()).await;
+        assert!(ranges.is_ok(), "promoted entry is invalid: {ranges:?}");
+        Ok(())
+    }
+}
@@ -8,32 +8,50 @@ use std::path::PathBuf;
 use std::pin::Pin;
 use std::result::Result as StdResult;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use futures::stream::BoxStream;
-use futures::{Stream, StreamExt, TryStreamExt};
+use futures::{FutureExt, SinkExt, Stream, StreamExt, TryStreamExt};
 use iroh_bytes::baomap::ValidateProgress;
+use iroh_bytes::protocol::RevokedToken;
 use iroh_bytes::provider::AddProgress;
 use iroh_bytes::util::{SetTagOption, Tag};
 use iroh_bytes::Hash;
-use iroh_net::{key::PublicKey, magic_endpoint::ConnectionInfo, PeerAddr};
-use iroh_sync::{store::GetFilter, AuthorId, Entry, NamespaceId};
+use iroh_net::{
+    key::PublicKey,
+    magic_endpoint::{ConnectionInfo, DerpRegionInfo},
+    PeerAddr,
+};
+use iroh_sync::{store::GetFilter, AuthorId, ContentStatus, Entry, NamespaceId};
 use quic_rpc::{RpcClient, ServiceConnection};
 use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
 use tokio_util::io::StreamReader;
 
 use crate::rpc_protocol::{
-    AuthorCreateRequest, AuthorListRequest, BlobAddPathRequest, BlobDeleteBlobRequest,
-    BlobDownloadRequest, BlobListCollectionsRequest, BlobListCollectionsResponse,
-    BlobListIncompleteRequest, BlobListIncompleteResponse, BlobListRequest, BlobListResponse,
-    BlobReadResponse, BlobValidateRequest, BytesGetRequest, CounterStats, DeleteTagRequest,
-    DocCreateRequest, DocGetManyRequest, DocGetOneRequest, DocImportRequest, DocInfoRequest,
-    DocListRequest, DocSetRequest, DocShareRequest, DocStartSyncRequest, DocStopSyncRequest,
-    DocSubscribeRequest, DocTicket, GetProgress, ListTagsRequest, ListTagsResponse,
-    NodeConnectionInfoRequest, NodeConnectionInfoResponse, NodeConnectionsRequest,
-    NodeShutdownRequest, NodeStatsRequest, NodeStatusRequest, NodeStatusResponse, ProviderService,
-    ShareMode, WrapOption,
+    AuthorCreateRequest, AuthorImportRequest, AuthorListRequest, AuthorRemoveRequest,
+    BlobAddPathRequest, BlobAddStreamRequest, BlobAddStreamResponse, BlobAddStreamUpdate,
+    BlobDeleteBlobRequest, BlobDownloadRequest, BlobListCollectionsRequest,
+    BlobListCollectionsResponse, BlobListIncompleteRequest, BlobListIncompleteResponse,
+    BlobListRequest, BlobListResponse, BlobListUnreferencedRequest, BlobListUnreferencedResponse,
+    BlobReadResponse, BlobServeStats, BlobStatsRequest, BlobTouchRequest, BlobTreeRequest,
+    BlobValidateCollectionRequest, BlobValidateOneRequest, BlobValidateRequest, BytesGetRequest,
+    CancelTransferRequest, CollectionListingRequest, CollectionListingResponse, ConnectionStats,
+    ConnectionTransfersEntry, ContentResolution, CounterStats, DeleteTagRequest, DerpStatusRequest,
+    DocCreateRequest, DocExportTarRequest, DocGetDefaultAuthorRequest, DocGetKeysRequest,
+    DocGetManyRequest, DocGetOneRequest, DocGetRetentionRequest, DocImportRequest, DocInfoRequest,
+    DocListRequest, DocMoveRequest, DocSetDefaultAuthorRequest, DocSetGossipAuthRequest,
+    DocSetRequest, DocSetRetentionRequest, DocSetStreamRequest, DocSetStreamResponse,
+    DocSetStreamUpdate, DocShareRequest, DocStartSyncRequest, DocStopSyncRequest,
+    DocSubscribeRequest, DocTicket, DocsPauseRequest, DocsResumeRequest, GetProgress, KeyBytes,
+    KeyKind, ListRevokedRequest, ListTagsRequest, ListTagsResponse, ListTransfersRequest,
+    ListingFormat, NodeConfigRequest, NodeConfigResponse, NodeConnectionInfoRequest,
+    NodeConnectionInfoResponse, NodeConnectionsRequest, NodeEventsRequest, NodeEventsResponse,
+    NodeHealthRequest, NodeHealthResponse, NodePeerStatsRequest, NodePeerStatsResponse,
+    NodeReadyRequest, NodeReadyResponse, NodeShutdownRequest, NodeStatsRequest, NodeStatusRequest,
+    NodeStatusResponse, ProviderService, RevokeTokenRequest, ShareMode, TransferListEntry,
+    TreeInfo, WrapOption,
 };
 use crate::sync_engine::{LiveEvent, LiveStatus};
 
@@ -70,6 +88,37 @@ where
             tags: TagsClient { rpc },
         }
     }
+
+    /// Import a file or directory from a filesystem path on the node.
+    ///
+    /// The returned stream yields a [`AddProgress::Found`] and [`AddProgress::Done`] event for
+    /// every file, and ends with either [`AddProgress::AllDone`] or [`AddProgress::Abort`].
+    /// Directories are imported as a collection, single files as a raw blob.
+    ///
+    /// See [`BlobsClient::add_from_path`] for more options.
+    pub async fn import(&self, path: PathBuf) -> Result<impl Stream<Item = Result<AddProgress>>> {
+        self.blobs
+            .add_from_path(path, false, SetTagOption::Auto, WrapOption::NoWrap, false)
+            .await
+    }
+
+    /// Import a file or directory and wait for the import to finish.
+    ///
+    /// Returns the hash of the imported blob or collection. An aborted import is returned as
+    /// an error.
+    pub async fn import_blocking(&self, path: PathBuf) -> Result<Hash> {
+        let stream = self.import(path).await?;
+        tokio::pin!(stream);
+        while let Some(item) = stream.next().await {
+            match item? {
+                AddProgress::AllDone { hash, .. } => return Ok(hash),
+                AddProgress::Abort(e) => return Err(anyhow!("import aborted: {e}")),
+                AddProgress::DiskFull => return Err(anyhow!("import aborted: the disk is full")),
+                _ => {}
+            }
+        }
+        Err(anyhow!("import ended without a result"))
+    }
 }
 
 /// Iroh node client.
@@ -88,12 +137,77 @@ where
         Ok(res.stats)
     }
 
+    /// Revoke a request token before its expiry.
+    ///
+    /// Returns whether the revoked tokens of the node changed. Fails if the authorization
+    /// handler of the node does not support revoking tokens.
+    pub async fn revoke_token(&self, token: RevokedToken) -> Result<bool> {
+        let res = self.rpc.rpc(RevokeTokenRequest { token }).await??;
+        Ok(res.revoked)
+    }
+
+    /// List the revoked request tokens that have not expired yet.
+    pub async fn revoked_tokens(&self) -> Result<Vec<RevokedToken>> {
+        let res = self.rpc.rpc(ListRevokedRequest).await??;
+        Ok(res.tokens)
+    }
+
+    /// List the transfers the node is currently serving to other nodes.
+    ///
+    /// The transfers are ordered by connection id and request id.
+    pub async fn transfers(&self) -> Result<Vec<TransferListEntry>> {
+        let res = self.rpc.rpc(ListTransfersRequest).await?;
+        Ok(res.transfers)
+    }
+
+    /// List the number of transfers in flight per connection.
+    ///
+    /// The connections are ordered by connection id, connections without transfers in flight
+    /// are not listed.
+    pub async fn transfers_per_connection(&self) -> Result<Vec<ConnectionTransfersEntry>> {
+        let res = self.rpc.rpc(ListTransfersRequest).await?;
+        Ok(res.connections)
+    }
+
+    /// Cancel a transfer listed by [`Self::transfers`].
+    ///
+    /// Returns `false` if the transfer is not in flight anymore.
+    pub async fn cancel_transfer(&self, connection_id: u64, request_id: u64) -> Result<bool> {
+        let res = self
+            .rpc
+            .rpc(CancelTransferRequest {
+                connection_id,
+                request_id,
+            })
+            .await?;
+        Ok(res.cancelled)
+    }
+
     /// Get information about the different connections we have made
     pub async fn connections(&self) -> Result<impl Stream<Item = Result<ConnectionInfo>>> {
         let stream = self.rpc.server_streaming(NodeConnectionsRequest {}).await?;
         Ok(flatten(stream).map_ok(|res| res.conn_info))
     }
 
+    /// Subscribe to the events of the node, e.g. transfers and changes of the DERP status.
+    ///
+    /// Pass the sequence number after the last event seen and the instance of the node it
+    /// was seen on to catch up on the events that were missed while disconnected, or `None`
+    /// to only get new events. If the node restarted in between, all events since the
+    /// restart are sent. Events that are no longer buffered by the node are reported as
+    /// [`NodeEventsResponse::Gap`].
+    pub async fn events(
+        &self,
+        since: Option<u64>,
+        instance: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<NodeEventsResponse>>> {
+        let stream = self
+            .rpc
+            .server_streaming(NodeEventsRequest { since, instance })
+            .await?;
+        Ok(stream.map_err(anyhow::Error::from))
+    }
+
     /// Get connection information about a node
     pub async fn connection_info(&self, node_id: PublicKey) -> Result<Option<ConnectionInfo>> {
         let NodeConnectionInfoResponse { conn_info } = self
@@ -103,12 +217,58 @@ where
         Ok(conn_info)
     }
 
+    /// Get the QUIC statistics of the connections to a node.
+    ///
+    /// Only connections that are currently in use are included, i.e. incoming blob and gossip
+    /// connections and outgoing connections of running downloads.
+    pub async fn peer_stats(&self, node_id: PublicKey) -> Result<Vec<ConnectionStats>> {
+        let NodePeerStatsResponse { connections } =
+            self.rpc.rpc(NodePeerStatsRequest { node_id }).await?;
+        Ok(connections)
+    }
+
+    /// Get the status of the DERP regions configured for the node, ordered by region id.
+    ///
+    /// This includes the latency to each region, whether it is the home region of the node and
+    /// whether the node is currently connected to it.
+    pub async fn derp_status(&self) -> Result<Vec<DerpRegionInfo>> {
+        let res = self.rpc.rpc(DerpStatusRequest).await??;
+        Ok(res.regions)
+    }
+
     /// Get status information about a node
     pub async fn status(&self) -> Result<NodeStatusResponse> {
         let response = self.rpc.rpc(NodeStatusRequest).await??;
         Ok(response)
     }
 
+    /// Get the configuration and limits of the node.
+    ///
+    /// Clients can use this to check e.g. the size of a value before setting it in a
+    /// document.
+    pub async fn config(&self) -> Result<NodeConfigResponse> {
+        let response = self.rpc.rpc(NodeConfigRequest).await??;
+        Ok(response)
+    }
+
+    /// Check that the node is alive and answering rpc requests.
+    ///
+    /// This does not touch the store or the network, so it is cheap enough to
+    /// be used as a liveness probe.
+    pub async fn health(&self) -> Result<NodeHealthResponse> {
+        let response = self.rpc.rpc(NodeHealthRequest).await?;
+        Ok(response)
+    }
+
+    /// Check whether the node is ready to serve data.
+    ///
+    /// Unlike [`Self::health`], this requires the endpoint to know at least one
+    /// address at which the node can be reached.
+    pub async fn ready(&self) -> Result<NodeReadyResponse> {
+        let response = self.rpc.rpc(NodeReadyRequest).await??;
+        Ok(response)
+    }
+
     /// Shutdown the node.
     ///
     /// If `force` is true, the node will be killed instantly without waiting for things to
@@ -155,6 +315,23 @@ where
         Ok(flatten(stream).map_ok(|res| res.id))
     }
 
+    /// Pause syncing of all documents.
+    ///
+    /// While paused, the node does not start syncs with peers and does not broadcast new
+    /// entries, which saves battery and bandwidth e.g. while a mobile app is in the background.
+    pub async fn pause(&self) -> Result<()> {
+        self.rpc.rpc(DocsPauseRequest {}).await??;
+        Ok(())
+    }
+
+    /// Resume syncing of all documents after [`Self::pause`].
+    ///
+    /// The documents are synced again with all known peers, to catch up on missed changes.
+    pub async fn resume(&self) -> Result<()> {
+        self.rpc.rpc(DocsResumeRequest {}).await??;
+        Ok(())
+    }
+
     /// Get a [`Doc`] client for a single document. Return None if the document cannot be found.
     pub async fn get(&self, id: NamespaceId) -> Result<Option<Doc<C>>> {
         if let Err(_err) = self.rpc.rpc(DocInfoRequest { doc_id: id }).await? {
@@ -184,11 +361,31 @@ where
         Ok(res.author_id)
     }
 
+    /// Import a document author from its key.
+    ///
+    /// Authors can only be imported from their secret key. Passing a public key only
+    /// succeeds if the node already has the secret key for it.
+    pub async fn import(&self, key: KeyBytes, kind: KeyKind) -> Result<AuthorId> {
+        let res = self.rpc.rpc(AuthorImportRequest { key, kind }).await??;
+        Ok(res.author_id)
+    }
+
     /// List document authors for which we have a secret key.
     pub async fn list(&self) -> Result<impl Stream<Item = Result<AuthorId>>> {
         let stream = self.rpc.server_streaming(AuthorListRequest {}).await?;
         Ok(flatten(stream).map_ok(|res| res.author_id))
     }
+
+    /// Remove a document author.
+    ///
+    /// This fails if the author has written entries to any document, unless `force` is set.
+    /// Entries written by a removed author stay valid, but can no longer be updated.
+    pub async fn remove(&self, author_id: AuthorId, force: bool) -> Result<()> {
+        self.rpc
+            .rpc(AuthorRemoveRequest { author_id, force })
+            .await??;
+        Ok(())
+    }
 }
 
 /// Iroh tags client.
@@ -249,12 +446,16 @@ where
     /// the node runs.
     /// If `in_place` is true, Iroh will assume that the data will not change and will share it in
     /// place without copying to the Iroh data directory.
+    /// If `verify_on_import` is true, every imported file is read back and verified against its
+    /// hash before its [`AddProgress::Done`] is reported, and the import is aborted if it does not
+    /// match.
     pub async fn add_from_path(
         &self,
         path: PathBuf,
         in_place: bool,
         tag: SetTagOption,
         wrap: WrapOption,
+        verify_on_import: bool,
     ) -> Result<impl Stream<Item = Result<AddProgress>>> {
         let stream = self
             .rpc
@@ -263,21 +464,107 @@ where
                 in_place,
                 tag,
                 wrap,
+                verify_on_import,
             })
             .await?;
         Ok(stream.map_err(anyhow::Error::from))
     }
 
+    /// Add many small blobs in one request.
+    ///
+    /// The blobs are sent to the node as `(name, data)` pairs from `blobs`, and imported one by
+    /// one as they arrive. The returned stream reports the result for every blob, a blob that
+    /// can not be added is reported as [`BlobAddStreamResponse::Failed`] without aborting the
+    /// others.
+    ///
+    /// If `collection` is set, a collection of the added blobs is created once `blobs` ends and
+    /// reported with [`BlobAddStreamResponse::Collection`]. Otherwise every blob is tagged with
+    /// an automatically created tag once `blobs` ends. If sending the blobs fails, nothing is
+    /// tagged.
+    pub async fn add_stream(
+        &self,
+        mut blobs: impl Stream<Item = (String, Bytes)> + Send + Unpin + 'static,
+        collection: Option<SetTagOption>,
+    ) -> Result<impl Stream<Item = Result<BlobAddStreamResponse>>> {
+        let (mut sink, responses) = self.rpc.bidi(BlobAddStreamRequest { collection }).await?;
+        let send = async move {
+            while let Some((name, data)) = blobs.next().await {
+                sink.send(BlobAddStreamUpdate::Blob { name, data })
+                    .await
+                    .map_err(|err| anyhow!("failed to send blob: {err}"))?;
+            }
+            sink.send(BlobAddStreamUpdate::Finish)
+                .await
+                .map_err(|err| anyhow!("failed to send blob: {err}"))?;
+            anyhow::Ok(())
+        };
+        // sending runs as part of the returned stream, which only yields its errors
+        let send = send
+            .into_stream()
+            .filter_map(|res| async move { res.err().map(Err) })
+            .boxed();
+        let responses = responses.map_err(anyhow::Error::from);
+        Ok(futures::stream::select(responses, send))
+    }
+
     /// Validate hashes on the running node.
     ///
     /// If `repair` is true, repair the store by removing invalid data.
     pub async fn validate(
         &self,
         repair: bool,
+    ) -> Result<impl Stream<Item = Result<ValidateProgress>>> {
+        self.validate_with_concurrency(repair, None).await
+    }
+
+    /// Like [`Self::validate`], with at most `concurrency` blobs validated at the same time.
+    ///
+    /// If `concurrency` is `None`, the node uses the number of its CPUs.
+    pub async fn validate_with_concurrency(
+        &self,
+        repair: bool,
+        concurrency: Option<usize>,
+    ) -> Result<impl Stream<Item = Result<ValidateProgress>>> {
+        let stream = self
+            .rpc
+            .server_streaming(BlobValidateRequest {
+                repair,
+                concurrency,
+            })
+            .await?;
+        Ok(stream.map_err(anyhow::Error::from))
+    }
+
+    /// Validate a collection and all its children on the running node.
+    ///
+    /// The stream ends with a [`ValidateProgress::CollectionSummary`] of the children.
+    pub async fn validate_collection(
+        &self,
+        hash: Hash,
+    ) -> Result<impl Stream<Item = Result<ValidateProgress>>> {
+        let stream = self
+            .rpc
+            .server_streaming(BlobValidateCollectionRequest { hash })
+            .await?;
+        Ok(stream.map_err(anyhow::Error::from))
+    }
+
+    /// Validate a single blob on the running node.
+    ///
+    /// A [`ValidateProgress::Progress`] update is sent after every `progress_interval` bytes,
+    /// the total size is in the preceding [`ValidateProgress::Entry`]. Dropping the stream
+    /// stops the validation.
+    pub async fn validate_one(
+        &self,
+        hash: Hash,
+        progress_interval: Option<u64>,
     ) -> Result<impl Stream<Item = Result<ValidateProgress>>> {
         let stream = self
             .rpc
-            .server_streaming(BlobValidateRequest { repair })
+            .server_streaming(BlobValidateOneRequest {
+                hash,
+                progress_interval,
+            })
             .await?;
         Ok(stream.map_err(anyhow::Error::from))
     }
@@ -291,7 +578,9 @@ where
         Ok(stream.map_err(anyhow::Error::from))
     }
 
-    /// List all complete blobs.
+    /// List all blobs, including partial blobs.
+    ///
+    /// Partial blobs can be told apart by their [`BlobListResponse::available`] ranges.
     pub async fn list(&self) -> Result<impl Stream<Item = Result<BlobListResponse>>> {
         let stream = self.rpc.server_streaming(BlobListRequest).await?;
         Ok(stream.map_err(anyhow::Error::from))
@@ -305,6 +594,19 @@ where
         Ok(stream.map_err(anyhow::Error::from))
     }
 
+    /// List all blobs that are not reachable from any tag, pin or document entry.
+    ///
+    /// These are the blobs that the next garbage collection would delete.
+    pub async fn list_unreferenced(
+        &self,
+    ) -> Result<impl Stream<Item = Result<BlobListUnreferencedResponse>>> {
+        let stream = self
+            .rpc
+            .server_streaming(BlobListUnreferencedRequest)
+            .await?;
+        Ok(flatten(stream))
+    }
+
     /// List all collections.
     pub async fn list_collections(
         &self,
@@ -316,11 +618,46 @@ where
         Ok(stream.map_err(anyhow::Error::from))
     }
 
+    /// Get a listing of the children of a collection, encoded in `format`.
+    ///
+    /// The listing is an array of [`CollectionListingEntry`](crate::rpc_protocol::CollectionListingEntry), use [`ListingFormat::decode`]
+    /// to read it.
+    pub async fn collection_listing(&self, hash: Hash, format: ListingFormat) -> Result<Bytes> {
+        let CollectionListingResponse { listing } = self
+            .rpc
+            .rpc(CollectionListingRequest { hash, format })
+            .await??;
+        Ok(listing)
+    }
+
     /// Delete a blob.
     pub async fn delete_blob(&self, hash: Hash) -> Result<()> {
         self.rpc.rpc(BlobDeleteBlobRequest { hash }).await??;
         Ok(())
     }
+
+    /// Mark blobs as recently accessed, without transferring them.
+    ///
+    /// This keeps content that will be needed soon from being garbage collected.
+    pub async fn touch(&self, hashes: Vec<Hash>) -> Result<()> {
+        self.rpc.rpc(BlobTouchRequest { hashes }).await??;
+        Ok(())
+    }
+
+    /// Get the `limit` most served blobs of the node, with their serve counts.
+    pub async fn stats(&self, limit: usize) -> Result<Vec<(Hash, BlobServeStats)>> {
+        let res = self.rpc.rpc(BlobStatsRequest { limit }).await??;
+        Ok(res.blobs)
+    }
+
+    /// Inspect the outboard tree of a blob.
+    ///
+    /// This is meant for debugging verification failures. If `levels` is true,
+    /// the hash pairs of all nodes are included, level by level from the root.
+    pub async fn tree_info(&self, hash: Hash, levels: bool) -> Result<TreeInfo> {
+        let info = self.rpc.rpc(BlobTreeRequest { hash, levels }).await??;
+        Ok(info)
+    }
 }
 
 /// Data reader for a single blob.
@@ -420,7 +757,24 @@ where
             .rpc
             .rpc(DocSetRequest {
                 doc_id: self.id,
-                author_id,
+                author_id: Some(author_id),
+                key,
+                value,
+            })
+            .await??;
+        Ok(res.entry.content_hash())
+    }
+
+    /// Set the content of a key to a byte array, with the default author of this document.
+    ///
+    /// If the document has no default author yet, a new author is created and set as default.
+    /// See [`Self::set_default_author`].
+    pub async fn set_bytes_default_author(&self, key: Vec<u8>, value: Vec<u8>) -> Result<Hash> {
+        let res = self
+            .rpc
+            .rpc(DocSetRequest {
+                doc_id: self.id,
+                author_id: None,
                 key,
                 value,
             })
@@ -428,6 +782,111 @@ where
         Ok(res.entry.content_hash())
     }
 
+    /// Set the content of a key to a value that is streamed to the node in chunks.
+    ///
+    /// Use this for large values, the node imports them without ever holding them in memory.
+    /// With `None` as author, the default author of this document is used, see
+    /// [`Self::set_default_author`].
+    ///
+    /// The returned stream reports how many bytes the node received so far, and ends with
+    /// [`DocSetStreamResponse::Done`] once the value is imported and the entry is created.
+    pub async fn set_stream(
+        &self,
+        author_id: Option<AuthorId>,
+        key: Vec<u8>,
+        mut value: impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+    ) -> Result<impl Stream<Item = Result<DocSetStreamResponse>>> {
+        let (mut sink, progress) = self
+            .rpc
+            .bidi(DocSetStreamRequest {
+                doc_id: self.id,
+                author_id,
+                key,
+            })
+            .await?;
+        let send = async move {
+            while let Some(chunk) = value.next().await {
+                sink.send(DocSetStreamUpdate::Chunk(chunk?))
+                    .await
+                    .map_err(|err| anyhow!("failed to send value: {err}"))?;
+            }
+            sink.send(DocSetStreamUpdate::Finish)
+                .await
+                .map_err(|err| anyhow!("failed to send value: {err}"))?;
+            anyhow::Ok(())
+        };
+        // sending runs as part of the returned stream, which only yields its errors
+        let send = send
+            .into_stream()
+            .filter_map(|res| async move { res.err().map(Err) })
+            .boxed();
+        Ok(futures::stream::select(flatten(progress), send))
+    }
+
+    /// Set the default author of this document, or remove it with `None`.
+    ///
+    /// The default author is used by [`Self::set_bytes_default_author`]. Changing it only
+    /// affects new writes, the entries of the previous default author are kept.
+    pub async fn set_default_author(&self, author_id: Option<AuthorId>) -> Result<()> {
+        let _res = self
+            .rpc
+            .rpc(DocSetDefaultAuthorRequest {
+                doc_id: self.id,
+                author_id,
+            })
+            .await??;
+        Ok(())
+    }
+
+    /// Get the default author of this document, if set.
+    pub async fn default_author(&self) -> Result<Option<AuthorId>> {
+        let res = self
+            .rpc
+            .rpc(DocGetDefaultAuthorRequest { doc_id: self.id })
+            .await??;
+        Ok(res.author_id)
+    }
+
+    /// Set how long the entries of this document are kept, or keep them forever with `None`.
+    ///
+    /// The node periodically deletes entries that are older than the retention, and syncs the
+    /// deletions to other peers.
+    pub async fn set_retention(&self, retention: Option<Duration>) -> Result<()> {
+        let _res = self
+            .rpc
+            .rpc(DocSetRetentionRequest {
+                doc_id: self.id,
+                retention,
+            })
+            .await??;
+        Ok(())
+    }
+
+    /// Get how long the entries of this document are kept, if set.
+    pub async fn retention(&self) -> Result<Option<Duration>> {
+        let res = self
+            .rpc
+            .rpc(DocGetRetentionRequest { doc_id: self.id })
+            .await??;
+        Ok(res.retention)
+    }
+
+    /// Move the entry of `author_id` at key `from` to key `to`.
+    ///
+    /// The content is not copied, and the entry at `from` is deleted. This is not atomic under
+    /// concurrent edits, see [`iroh_sync::store::Store::move_key`].
+    pub async fn move_key(&self, author_id: AuthorId, from: Vec<u8>, to: Vec<u8>) -> Result<()> {
+        self.rpc
+            .rpc(DocMoveRequest {
+                doc_id: self.id,
+                author_id,
+                from,
+                to,
+            })
+            .await??;
+        Ok(())
+    }
+
     /// Read the content of an [`Entry`] as a streaming [`BlobReader`].
     pub async fn read(&self, entry: &Entry) -> Result<BlobReader> {
         BlobReader::from_rpc(&self.rpc, entry.content_hash()).await
@@ -455,17 +914,72 @@ where
     }
 
     /// Get entries.
+    ///
+    /// Entries are returned whether or not their content is available locally, see
+    /// [`Self::get_many_with_content`] to handle missing content.
     pub async fn get_many(&self, filter: GetFilter) -> Result<impl Stream<Item = Result<Entry>>> {
+        let stream = self
+            .get_many_with_content(filter, ContentResolution::IncludeMissing)
+            .await?;
+        Ok(stream.map_ok(|(entry, _content_status)| entry))
+    }
+
+    /// Get entries together with the [`ContentStatus`] of their content.
+    ///
+    /// `content` decides whether entries with missing content are returned, skipped, or
+    /// whether their content is downloaded first.
+    pub async fn get_many_with_content(
+        &self,
+        filter: GetFilter,
+        content: ContentResolution,
+    ) -> Result<impl Stream<Item = Result<(Entry, ContentStatus)>>> {
         let stream = self
             .rpc
             .server_streaming(DocGetManyRequest {
                 doc_id: self.id,
                 filter,
+                content,
+            })
+            .await?;
+        Ok(flatten(stream).map_ok(|res| (res.entry.into(), res.content_status)))
+    }
+
+    /// Get the entries for many keys in a single request.
+    ///
+    /// If `author` is set, only the entries of that author are returned, otherwise the entries
+    /// of all authors. Keys without entries are skipped.
+    pub async fn get_keys(
+        &self,
+        keys: Vec<Vec<u8>>,
+        author: Option<AuthorId>,
+    ) -> Result<impl Stream<Item = Result<Entry>>> {
+        let stream = self
+            .rpc
+            .server_streaming(DocGetKeysRequest {
+                doc_id: self.id,
+                keys,
+                author,
             })
             .await?;
         Ok(flatten(stream).map_ok(|res| res.entry.into()))
     }
 
+    /// Get the content of the latest entries for all keys starting with `prefix` as a tar
+    /// archive, with the keys as paths.
+    ///
+    /// Content that is missing locally is downloaded first. Wrap the stream in a
+    /// [`StreamReader`] to read the archive with the `tar` crate or write it to a file.
+    pub async fn export_tar(&self, prefix: Vec<u8>) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let stream = self
+            .rpc
+            .server_streaming(DocExportTarRequest {
+                doc_id: self.id,
+                prefix,
+            })
+            .await?;
+        Ok(flatten(stream).map_ok(|res| res.chunk))
+    }
+
     /// Share this document with peers over a ticket.
     pub async fn share(&self, mode: ShareMode) -> anyhow::Result<DocTicket> {
         let res = self
@@ -499,6 +1013,21 @@ where
         Ok(())
     }
 
+    /// Enable or disable authentication of gossip messages for this document.
+    ///
+    /// When enabled, tickets for this document carry a shared secret, and gossip messages
+    /// without a valid MAC are dropped. See [`iroh_sync::sync::GossipSecret`] for details.
+    pub async fn set_gossip_auth(&self, enabled: bool) -> Result<()> {
+        let _res = self
+            .rpc
+            .rpc(DocSetGossipAuthRequest {
+                doc_id: self.id,
+                enabled,
+            })
+            .await??;
+        Ok(())
+    }
+
     /// Subscribe to events for this document.
     pub async fn subscribe(&self) -> anyhow::Result<impl Stream<Item = anyhow::Result<LiveEvent>>> {
         let stream = self
@@ -1,17 +1,26 @@
 //! The collection type used by iroh
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use bao_tree::blake3;
 use bytes::Bytes;
-use iroh_bytes::baomap::{MapEntry, TempTag};
+use futures::{Stream, StreamExt};
+use iroh_bytes::baomap::{ImportProgress, MapEntry, TempTag};
 use iroh_bytes::collection::LinkSeq;
 use iroh_bytes::get::fsm::EndBlobNext;
 use iroh_bytes::get::Stats;
+use iroh_bytes::provider::AddProgress;
+use iroh_bytes::util::progress::{IdGenerator, ProgressSender};
 use iroh_bytes::util::BlobFormat;
 use iroh_bytes::{baomap, Hash};
 use iroh_io::AsyncSliceReaderExt;
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use crate::dial::Ticket;
+use crate::util::fs::canonicalize_path;
 
 /// A collection of blobs
 ///
@@ -219,6 +228,186 @@ impl Collection {
     pub fn total_entries(&self) -> u64 {
         self.blobs.len() as u64
     }
+
+    /// The hash of this collection, i.e. the hash of the root blob of [`Self::to_blobs`].
+    pub fn hash(&self) -> Hash {
+        let root = self.to_blobs().last().expect("to_blobs is never empty");
+        blake3::hash(&root).into()
+    }
+}
+
+/// Compute the canonical hash of a directory tree.
+///
+/// Every directory becomes a collection. Regular files are linked by the hash of their
+/// content, subdirectories by the hash of their own collection, with the name suffixed
+/// by a `/` to tell them apart from files. Entries are sorted by name. Symlinks to
+/// directories are followed, anything else that is neither a regular file nor a directory,
+/// e.g. a symlink to a file, is skipped. The result only depends on names and content, so
+/// the same tree produces the same hash on any machine.
+///
+/// Subdirectories may be nested at most `max_depth` levels below `path`, deeper trees fail
+/// with an error. A symlink that leads back to a directory that is currently being hashed
+/// also fails, instead of recursing forever.
+///
+/// Returns the hash of the root directory and the collections of all directories, each
+/// after the collections of its subdirectories, so the collection of the root directory is
+/// the last one. The total blob size of each collection includes the files in all its
+/// subdirectories.
+///
+/// Nothing is added to a store. This is a blocking function, since it reads all files.
+///
+/// Note that the provider, the getter and gc only follow the links of the collection that
+/// is requested or tagged, its children are treated as raw blobs. So every returned
+/// collection has to be stored and tagged, see [`Collection::store`], otherwise gc deletes
+/// the collections of subdirectories while the root is still tagged.
+pub fn hash_directory(
+    path: impl AsRef<Path>,
+    max_depth: usize,
+) -> anyhow::Result<(Hash, Vec<Collection>)> {
+    let mut collections = Vec::new();
+    directory_collection(path.as_ref(), max_depth, &mut Vec::new(), &mut collections)?;
+    let root = collections
+        .last()
+        .expect("the root collection is added last");
+    Ok((root.hash(), collections))
+}
+
+/// Import a collection from a stream of named readers.
+///
+/// Each reader is streamed into `db` as a raw blob with [`baomap::Store::import_stream`],
+/// then a collection of all children is stored. Progress is reported per child as
+/// [`AddProgress::Found`] once the size is known, followed by [`AddProgress::Progress`]
+/// while the outboard is computed and [`AddProgress::Done`]. [`AddProgress::AllDone`] is
+/// left to the caller, since it needs a tag.
+///
+/// Since the size of a child is not known up front, the available space of the store is
+/// checked for every chunk that is read, see [`baomap::ensure_space`].
+///
+/// Returns the root hash of the collection as a [`TempTag`]. Children are only protected
+/// by the collection, so the caller should tag it before dropping the temp tag.
+pub async fn import_collection<D, S, R>(
+    db: &D,
+    entries: S,
+    progress: impl ProgressSender<Msg = AddProgress> + IdGenerator,
+) -> anyhow::Result<TempTag>
+where
+    D: baomap::Store,
+    S: Stream<Item = (String, R)>,
+    R: AsyncRead + Send + 'static,
+{
+    tokio::pin!(entries);
+    let mut blobs = Vec::new();
+    let mut child_tags = Vec::new();
+    let mut total_blobs_size = 0;
+    while let Some((name, reader)) = entries.next().await {
+        let found = name.clone();
+        let import_progress = progress.clone().with_filter_map(move |msg| match msg {
+            ImportProgress::Size { id, size } => Some(AddProgress::Found {
+                id,
+                name: found.clone(),
+                size,
+            }),
+            ImportProgress::OutboardProgress { id, offset } => {
+                Some(AddProgress::Progress { id, offset })
+            }
+            ImportProgress::OutboardDone { id, hash } => Some(AddProgress::Done { id, hash }),
+            _ => None,
+        });
+        let (tag, size) = db
+            .import_stream(
+                check_space(db.clone(), reader),
+                BlobFormat::RAW,
+                import_progress,
+            )
+            .await
+            .with_context(|| format!("failed to import {name}"))?;
+        total_blobs_size += size;
+        blobs.push(Blob {
+            name,
+            hash: *tag.hash(),
+        });
+        child_tags.push(tag);
+    }
+    let collection = Collection::new(blobs, total_blobs_size)?;
+    let tag = collection.store(db).await?;
+    drop(child_tags);
+    Ok(tag)
+}
+
+/// Stream the data of `reader`, failing as soon as `db` has no space left for the next chunk
+/// and the part of the outboard that covers it.
+fn check_space<D: baomap::Store>(
+    db: D,
+    reader: impl AsyncRead + Send + 'static,
+) -> impl Stream<Item = std::io::Result<Bytes>> + Send + Unpin + 'static {
+    let mut offset = 0;
+    ReaderStream::new(Box::pin(reader)).map(move |chunk| {
+        let chunk = chunk?;
+        let end = offset + chunk.len() as u64;
+        baomap::ensure_space(&db, baomap::blob_space(end) - baomap::blob_space(offset))?;
+        offset = end;
+        Ok(chunk)
+    })
+}
+
+/// Build the collection of the directory at `path`, and add it to `collections` after the
+/// collections of its subdirectories.
+///
+/// `ancestors` holds the canonical paths of the directories that are currently being
+/// hashed, to detect symlink cycles.
+fn directory_collection(
+    path: &Path,
+    max_depth: usize,
+    ancestors: &mut Vec<PathBuf>,
+    collections: &mut Vec<Collection>,
+) -> anyhow::Result<(Hash, u64)> {
+    let canonical = std::fs::canonicalize(path)
+        .with_context(|| format!("failed to resolve directory {}", path.display()))?;
+    anyhow::ensure!(
+        !ancestors.contains(&canonical),
+        "symlink cycle at {}",
+        path.display()
+    );
+    ancestors.push(canonical);
+    let mut blobs = Vec::new();
+    let mut total_blobs_size = 0;
+    for entry in std::fs::read_dir(path)
+        .with_context(|| format!("failed to read directory {}", path.display()))?
+    {
+        let entry = entry?;
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            // only follow symlinks to directories, a dangling symlink is skipped
+            match std::fs::metadata(entry.path()) {
+                Ok(metadata) if metadata.is_dir() => file_type = metadata.file_type(),
+                _ => continue,
+            }
+        }
+        let name = canonicalize_path(entry.file_name())?;
+        if file_type.is_file() {
+            let mut file = std::fs::File::open(entry.path())?;
+            let mut hasher = blake3::Hasher::new();
+            total_blobs_size += std::io::copy(&mut file, &mut hasher)?;
+            let hash = hasher.finalize().into();
+            blobs.push(Blob { name, hash });
+        } else if file_type.is_dir() {
+            anyhow::ensure!(
+                ancestors.len() <= max_depth,
+                "directory {} is nested deeper than {max_depth} levels",
+                entry.path().display()
+            );
+            let (hash, size) =
+                directory_collection(&entry.path(), max_depth, ancestors, collections)?;
+            total_blobs_size += size;
+            let name = format!("{name}/");
+            blobs.push(Blob { name, hash });
+        }
+    }
+    ancestors.pop();
+    let collection = Collection::new(blobs, total_blobs_size)?;
+    let hash = collection.hash();
+    collections.push(collection);
+    Ok((hash, total_blobs_size))
 }
 
 /// A blob entry of a collection
@@ -230,6 +419,47 @@ pub struct Blob {
     pub hash: Hash,
 }
 
+/// A ticket for a collection together with a manifest of its children.
+///
+/// The manifest is small and lets a recipient present the contents of a collection and
+/// pick the children it wants before downloading anything. Each child can then be
+/// fetched on its own with a ticket from [`ShareBundle::child_ticket`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareBundle {
+    /// Ticket to download the entire collection
+    pub ticket: Ticket,
+    /// The children of the collection
+    pub manifest: Vec<ManifestEntry>,
+}
+
+impl ShareBundle {
+    /// Create a ticket to download just the child with the given name.
+    pub fn child_ticket(&self, name: &str) -> anyhow::Result<Ticket> {
+        let entry = self
+            .manifest
+            .iter()
+            .find(|entry| entry.name == name)
+            .context("no child with this name in the collection")?;
+        Ticket::new(
+            self.ticket.node_addr().clone(),
+            entry.hash,
+            BlobFormat::RAW,
+            self.ticket.token().cloned(),
+        )
+    }
+}
+
+/// A child of a collection listed in a [`ShareBundle`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// The name of the child
+    pub name: String,
+    /// The hash of the child
+    pub hash: Hash,
+    /// The size of the child, if it is known to the sharing node
+    pub size: Option<u64>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,4 +481,113 @@ mod tests {
         let deserialize_b: Blob = postcard::from_bytes(&buf).unwrap();
         assert_eq!(b, deserialize_b);
     }
+
+    #[test]
+    fn hash_directory_is_canonical() {
+        let a = tempfile::tempdir().unwrap();
+        std::fs::write(a.path().join("b.txt"), b"b").unwrap();
+        std::fs::write(a.path().join("a.txt"), b"a").unwrap();
+        std::fs::create_dir_all(a.path().join("sub").join("empty")).unwrap();
+        std::fs::write(a.path().join("sub").join("c.txt"), b"c").unwrap();
+
+        // same tree, created in a different order
+        let b = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(b.path().join("sub").join("empty")).unwrap();
+        std::fs::write(b.path().join("sub").join("c.txt"), b"c").unwrap();
+        std::fs::write(b.path().join("a.txt"), b"a").unwrap();
+        std::fs::write(b.path().join("b.txt"), b"b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(b.path().join("a.txt"), b.path().join("link")).unwrap();
+
+        let (hash, collections) = hash_directory(a.path(), 8).unwrap();
+        assert_eq!(hash_directory(b.path(), 8).unwrap().0, hash);
+        let collection = collections.last().unwrap();
+        assert_eq!(collection.hash(), hash);
+        assert_eq!(collection.total_blobs_size(), 3);
+
+        // the collections of subdirectories come before their parents
+        let (sub_hash, sub_collections) = hash_directory(a.path().join("sub"), 8).unwrap();
+        assert_eq!(collections.len(), 3);
+        assert_eq!(collections[..2], sub_collections[..]);
+        assert_eq!(collections[1].hash(), sub_hash);
+        let names = collection
+            .blobs()
+            .iter()
+            .map(|blob| blob.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.txt", "b.txt", "sub/"]);
+        assert_eq!(collection.blobs()[0].hash, blake3::hash(b"a").into());
+        assert_eq!(collection.blobs()[2].hash, sub_hash);
+
+        // content changes anywhere in the tree change the root hash
+        std::fs::write(b.path().join("sub").join("c.txt"), b"changed").unwrap();
+        assert_ne!(hash_directory(b.path(), 8).unwrap().0, hash);
+    }
+
+    #[test]
+    fn hash_directory_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a").join("b")).unwrap();
+        std::fs::write(dir.path().join("a").join("b").join("c.txt"), b"c").unwrap();
+
+        assert!(hash_directory(dir.path(), 2).is_ok());
+        let err = hash_directory(dir.path(), 1).unwrap_err();
+        assert!(err.to_string().contains("nested deeper than 1 levels"));
+        assert!(hash_directory(dir.path().join("a"), 1).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hash_directory_symlink_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a").join("b")).unwrap();
+        std::fs::write(dir.path().join("a").join("b").join("c.txt"), b"c").unwrap();
+
+        // a symlink to a sibling directory is followed and hashes like a copy of it
+        std::os::unix::fs::symlink(dir.path().join("a").join("b"), dir.path().join("d")).unwrap();
+        let (_, collections) = hash_directory(dir.path(), 8).unwrap();
+        let collection = collections.last().unwrap();
+        let a_b = hash_directory(dir.path().join("a").join("b"), 8).unwrap().0;
+        assert_eq!(collection.blobs()[1].name, "d/");
+        assert_eq!(collection.blobs()[1].hash, a_b);
+
+        // a symlink back to an ancestor is a cycle
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("a").join("b").join("up")).unwrap();
+        let err = hash_directory(dir.path(), usize::MAX).unwrap_err();
+        assert!(err.to_string().contains("symlink cycle"));
+    }
+
+    #[cfg(feature = "mem-db")]
+    #[tokio::test]
+    async fn import_collection_from_readers() {
+        use iroh_bytes::util::progress::FlumeProgressSender;
+        use iroh_bytes::util::runtime;
+
+        let rt = runtime::Handle::from_current(1).unwrap();
+        let db = crate::baomap::mem::Store::new(rt);
+        let entries = futures::stream::iter([
+            ("b.txt".to_string(), &b"world"[..]),
+            ("a.txt".to_string(), &b"hello"[..]),
+        ]);
+        let (tx, rx) = flume::unbounded();
+        let tag = import_collection(&db, entries, FlumeProgressSender::new(tx))
+            .await
+            .unwrap();
+
+        let collection = Collection::load(&db, tag.hash()).await.unwrap();
+        assert_eq!(*tag.hash(), collection.hash());
+        assert_eq!(collection.total_blobs_size(), 10);
+        assert_eq!(collection.blobs()[0].name, "a.txt");
+        assert_eq!(collection.blobs()[0].hash, blake3::hash(b"hello").into());
+
+        let events = rx
+            .drain()
+            .filter(|event| !matches!(event, AddProgress::Progress { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], AddProgress::Found { name, size: 5, .. } if name == "b.txt"));
+        assert!(matches!(events[1], AddProgress::Done { .. }));
+        assert!(matches!(&events[2], AddProgress::Found { name, size: 5, .. } if name == "a.txt"));
+        assert!(matches!(events[3], AddProgress::Done { .. }));
+    }
 }
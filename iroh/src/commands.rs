@@ -2,13 +2,13 @@ use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use bytes::Bytes;
 use clap::{Args, Parser, Subcommand};
 use comfy_table::presets::NOTHING;
 use comfy_table::{Cell, Table};
 use console::style;
-use futures::{Stream, StreamExt};
+use futures::{Stream, StreamExt, TryStreamExt};
 use human_time::ToHumanTimeString;
 use indicatif::{
     HumanBytes, HumanDuration, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle,
@@ -21,7 +21,7 @@ use iroh_bytes::{protocol::RequestToken, util::runtime, Hash};
 use iroh_net::PeerAddr;
 use iroh_net::{
     key::{PublicKey, SecretKey},
-    magic_endpoint::ConnectionInfo,
+    magic_endpoint::{ConnectionInfo, DerpRegionInfo},
 };
 
 use crate::commands::sync::fmt_short;
@@ -150,6 +150,9 @@ pub enum FullCommands {
         /// Pass "random" to generate a random token, or base32-encoded bytes to use as a token
         #[clap(long)]
         request_token: Option<RequestTokenOptions>,
+        /// Remove temporary files left behind by a previous crash before starting
+        #[clap(long)]
+        cleanup_orphans: bool,
 
         /// Add data when starting the node
         #[clap(flatten)]
@@ -211,6 +214,7 @@ impl FullCommands {
                 addr,
                 rpc_port,
                 request_token,
+                cleanup_orphans,
                 add_options,
             } => {
                 let request_token = match request_token {
@@ -226,6 +230,13 @@ impl FullCommands {
                         keylog,
                         request_token,
                         derp_map: config.derp_map()?,
+                        cleanup_orphans,
+                        read_ahead: config.read_ahead,
+                        auto_download: config.auto_download,
+                        compress_data: config.compress_data,
+                        outboard_cache_size: config.outboard_cache_size,
+                        docs_wal: config.docs_wal,
+                        shard_policy: config.shard_policy()?,
                     },
                     add_options,
                 )
@@ -321,10 +332,35 @@ pub enum NodeCommands {
     Connections,
     /// Get connection information about a particular node
     Connection { node_id: PublicKey },
+    /// Get the status of the DERP regions the node is configured with.
+    ///
+    /// Shows the latency to each region, which one is the home region and
+    /// whether the node is currently connected to it.
+    Derp,
     /// Get status of the running node.
     Status,
+    /// Get the configuration and limits of the running node.
+    Config,
     /// Get statistics and metrics from the running node.
     Stats,
+    /// Print the events of the running node as they happen.
+    Events {
+        /// Sequence number of the first event to print.
+        ///
+        /// Events that happened before are printed as well, as long as the node still
+        /// buffers them.
+        #[clap(long)]
+        since: Option<u64>,
+        /// The instance of the node that `since` belongs to.
+        ///
+        /// If the node restarted since, all events since the restart are printed.
+        #[clap(long, requires = "since")]
+        instance: Option<u64>,
+    },
+    /// Check whether the running node is ready to serve data.
+    ///
+    /// Exits with an error if it is not, so this can be used as a readiness probe.
+    Ready,
     /// Shutdown the running node.
     Shutdown {
         /// Shutdown mode.
@@ -350,6 +386,10 @@ impl NodeCommands {
                     None => println!("Not Found"),
                 }
             }
+            Self::Derp => {
+                let regions = iroh.node.derp_status().await?;
+                println!("{}", fmt_derp_status(regions));
+            }
             Self::Shutdown { force } => {
                 iroh.node.shutdown(force).await?;
             }
@@ -362,11 +402,59 @@ impl NodeCommands {
                     );
                 }
             }
+            Self::Events { since, instance } => {
+                let mut events = iroh.node.events(since, instance).await?;
+                while let Some(event) = events.try_next().await? {
+                    match event {
+                        NodeEventsResponse::Event {
+                            instance,
+                            seq,
+                            event,
+                        } => println!("{instance}/{seq}: {event:?}"),
+                        NodeEventsResponse::Gap { instance, from, to } => {
+                            println!("missed events {instance}/{from}..{to}")
+                        }
+                    }
+                }
+            }
             Self::Status => {
                 let response = iroh.node.status().await?;
                 println!("Listening addresses: {:#?}", response.listen_addrs);
                 println!("Node public key: {}", response.addr.peer_id);
                 println!("Version: {}", response.version);
+                println!("Progress operations: {}", response.progress_operations);
+                println!(
+                    "Transfer memory in use: {}",
+                    HumanBytes(response.transfer_memory_in_use)
+                );
+            }
+            Self::Config => {
+                let config = iroh.node.config().await?;
+                let fmt_limit = |limit: Option<u64>| match limit {
+                    Some(limit) => HumanBytes(limit).to_string(),
+                    None => "unlimited".to_string(),
+                };
+                println!("Max value size: {}", fmt_limit(config.max_value_size));
+                println!("Max blob size: {}", fmt_limit(config.max_blob_size));
+                println!("Quota: {}", fmt_limit(config.quota));
+                println!("Key export allowed: {}", config.allow_key_export);
+                println!("Supported ALPNs: {}", config.supported_alpns.join(", "));
+                println!("Version: {}", config.version);
+                println!(
+                    "Max progress operations: {}",
+                    config.max_progress_operations
+                );
+                println!("Max concurrent streams: {}", config.max_concurrent_streams);
+            }
+            Self::Ready => {
+                let response = iroh.node.ready().await?;
+                println!("Node public key: {}", response.addr.peer_id);
+                println!("DERP region: {:?}", response.addr.info.derp_region);
+                println!(
+                    "Direct addresses: {:#?}",
+                    response.addr.info.direct_addresses
+                );
+                ensure!(response.ready, "node is not ready");
             }
         }
         Ok(())
@@ -466,6 +554,10 @@ pub struct BlobAddOptions {
     /// Do not print the all-in-one ticket to get the added data from this node.
     #[clap(long)]
     no_ticket: bool,
+
+    /// Read back every added file and verify it against its hash before reporting success.
+    #[clap(long)]
+    verify: bool,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -525,8 +617,20 @@ pub enum BlobCommands {
     /// Validate hashes on the running node.
     Validate {
         /// Repair the store by removing invalid data
-        #[clap(long, default_value_t = false)]
+        #[clap(long, default_value_t = false, conflicts_with = "collection")]
         repair: bool,
+        /// How many blobs to validate at the same time, defaults to the number of CPUs
+        #[clap(long, conflicts_with = "collection")]
+        concurrency: Option<usize>,
+        /// Only validate this collection and its children
+        #[clap(long)]
+        collection: Option<Hash>,
+        /// Only validate this blob, with progress after every `progress-interval` bytes
+        #[clap(long, conflicts_with_all = ["repair", "concurrency", "collection"])]
+        blob: Option<Hash>,
+        /// Number of bytes between progress updates when validating a single blob
+        #[clap(long, requires = "blob")]
+        progress_interval: Option<u64>,
     },
     /// Delete content on the node.
     #[clap(subcommand)]
@@ -596,7 +700,23 @@ impl BlobCommands {
             }
             Self::List(cmd) => cmd.run(iroh).await,
             Self::Delete(cmd) => cmd.run(iroh).await,
-            Self::Validate { repair } => self::validate::run(iroh, repair).await,
+            Self::Validate {
+                repair,
+                concurrency,
+                collection,
+                blob,
+                progress_interval,
+            } => {
+                let target = match (collection, blob) {
+                    (Some(hash), _) => self::validate::Target::Collection(hash),
+                    (None, Some(hash)) => self::validate::Target::Blob(hash, progress_interval),
+                    (None, None) => self::validate::Target::Store {
+                        repair,
+                        concurrency,
+                    },
+                };
+                self::validate::run(iroh, target).await
+            }
             Self::Add(opts) => {
                 // TODO: This is where we are missing the request token from the running
                 // node (last argument to run_with_opts).
@@ -683,6 +803,32 @@ fn fmt_connection(info: ConnectionInfo) -> String {
     )
 }
 
+fn fmt_derp_status(regions: Vec<DerpRegionInfo>) -> String {
+    let mut table = Table::new();
+    table.load_preset(NOTHING).set_header(
+        vec!["region", "code", "latency", "home", "connected"]
+            .into_iter()
+            .map(bold_cell),
+    );
+    for region in regions {
+        table.add_row(vec![
+            region.region_id.to_string(),
+            region.region_code,
+            fmt_latency(region.latency),
+            fmt_yes(region.home),
+            fmt_yes(region.connected),
+        ]);
+    }
+    table.to_string()
+}
+
+fn fmt_yes(value: bool) -> String {
+    match value {
+        true => String::from("yes"),
+        false => String::new(),
+    }
+}
+
 fn fmt_addrs(addrs: Vec<(SocketAddr, Option<Duration>)>) -> String {
     let mut table = Table::new();
     table
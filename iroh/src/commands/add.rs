@@ -69,7 +69,7 @@ pub async fn run_with_opts<C: ServiceConnection<ProviderService>>(
         (false, Some(_)) => bail!("`--filename` may not be used without `--wrap`"),
     };
 
-    run(client, source, tag, ticket, wrap).await
+    run(client, source, tag, ticket, wrap, opts.verify).await
 }
 
 /// Add data to iroh, either from a path or, if path is `None`, from STDIN.
@@ -79,6 +79,7 @@ pub async fn run<C: ServiceConnection<ProviderService>>(
     tag: SetTagOption,
     ticket: TicketOption,
     wrap: WrapOption,
+    verify: bool,
 ) -> Result<()> {
     let (path, in_place) = match source {
         BlobSource::LocalFs { path, in_place } => {
@@ -100,7 +101,7 @@ pub async fn run<C: ServiceConnection<ProviderService>>(
     // tell the node to add the data
     let stream = client
         .blobs
-        .add_from_path(path, in_place, tag, wrap)
+        .add_from_path(path, in_place, tag, wrap, verify)
         .await?;
     let (hash, format, entries) = aggregate_add_response(stream).await?;
     print_add_response(hash, format, entries);
@@ -168,6 +169,12 @@ pub async fn aggregate_add_response(
                 }
                 anyhow::bail!("Error while adding data: {e}");
             }
+            AddProgress::DiskFull => {
+                if let Some(mp) = mp.take() {
+                    mp.error();
+                }
+                anyhow::bail!("Error while adding data: the disk is full");
+            }
         }
     }
     let HashAndFormat(hash, format) =
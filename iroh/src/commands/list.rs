@@ -12,6 +12,10 @@ pub enum Commands {
     IncompleteBlobs,
     /// List the available collections on the running provider.
     Collections,
+    /// List the blobs that are not referenced by any tag, pin or document entry.
+    ///
+    /// These are the blobs that the next garbage collection would delete.
+    UnreferencedBlobs,
 }
 
 impl Commands {
@@ -21,7 +25,17 @@ impl Commands {
                 let mut response = iroh.blobs.list().await?;
                 while let Some(item) = response.next().await {
                     let item = item?;
-                    println!("{} {} ({})", item.path, item.hash, HumanBytes(item.size),);
+                    let partial = match item.available {
+                        Some(available) if !available.is_all() => " partial",
+                        _ => "",
+                    };
+                    println!(
+                        "{} {} ({}){}",
+                        item.path,
+                        item.hash,
+                        HumanBytes(item.size),
+                        partial
+                    );
                 }
             }
             Commands::IncompleteBlobs => {
@@ -31,6 +45,13 @@ impl Commands {
                     println!("{} {}", item.hash, item.size);
                 }
             }
+            Commands::UnreferencedBlobs => {
+                let mut response = iroh.blobs.list_unreferenced().await?;
+                while let Some(item) = response.next().await {
+                    let item = item?;
+                    println!("{} ({})", item.hash, HumanBytes(item.size));
+                }
+            }
             Commands::Collections => {
                 let mut response = iroh.blobs.list_collections().await?;
                 while let Some(res) = response.next().await {
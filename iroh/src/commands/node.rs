@@ -1,23 +1,31 @@
 use std::{
     fmt,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use anyhow::{anyhow, ensure, Context, Result};
 use iroh::{
-    baomap::flat::{self, Store as BaoFsStore},
+    baomap::flat::{self, LoadProgress, Store as BaoFsStore},
     client::quic::RPC_ALPN,
     node::{Node, StaticTokenAuthHandler},
     rpc_protocol::{ProviderRequest, ProviderResponse, ProviderService},
+    shard::ShardPolicy,
 };
 use iroh_bytes::{baomap::Store as BaoStore, protocol::RequestToken, util::runtime};
-use iroh_net::{derp::DerpMap, key::SecretKey};
-use iroh_sync::store::{fs::Store as DocFsStore, Store as DocStore};
+use iroh_net::{derp::DerpMap, key::SecretKey, util::AbortingJoinHandle};
+use iroh_sync::store::{
+    fs::{Store as DocFsStore, WalSync},
+    Store as DocStore,
+};
 use quic_rpc::{transport::quinn::QuinnServerEndpoint, ServiceEndpoint};
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
 use tracing::{info_span, Instrument};
 
 use crate::{commands::add, config::IrohPaths};
@@ -31,6 +39,13 @@ pub struct StartOptions {
     pub keylog: bool,
     pub request_token: Option<RequestToken>,
     pub derp_map: Option<DerpMap>,
+    pub cleanup_orphans: bool,
+    pub read_ahead: usize,
+    pub auto_download: bool,
+    pub compress_data: bool,
+    pub outboard_cache_size: u64,
+    pub docs_wal: Option<WalSync>,
+    pub shard_policy: Option<ShardPolicy>,
 }
 
 pub async fn run(rt: &runtime::Handle, opts: StartOptions, add_opts: BlobAddOptions) -> Result<()> {
@@ -90,14 +105,79 @@ async fn start_daemon_node(
     let partial_blob_dir = IrohPaths::BaoFlatStorePartial.with_env()?;
     let meta_dir = IrohPaths::BaoFlatStoreMeta.with_env()?;
     let peer_data_path = IrohPaths::PeerData.with_env()?;
+    let serve_stats_path = IrohPaths::ServeStats.with_env()?;
     tokio::fs::create_dir_all(&blob_dir).await?;
     tokio::fs::create_dir_all(&partial_blob_dir).await?;
-    let bao_store = flat::Store::load(&blob_dir, &partial_blob_dir, &meta_dir, rt)
+    let bao_store = load_store(rt, &blob_dir, &partial_blob_dir, &meta_dir)
         .await
         .with_context(|| format!("Failed to load iroh database from {}", blob_dir.display()))?;
+    bao_store.set_compress_data(opts.compress_data);
+    bao_store.set_outboard_cache_size(opts.outboard_cache_size);
+    if opts.cleanup_orphans {
+        let store = bao_store.clone();
+        let stats = rt
+            .main()
+            .spawn_blocking(move || store.cleanup_orphans())
+            .await?
+            .context("Failed to clean up orphaned temp files")?;
+        tracing::info!(
+            "removed {} orphaned temp files ({} bytes)",
+            stats.files,
+            stats.bytes
+        );
+    }
     let key = Some(IrohPaths::SecretKey.with_env()?);
-    let doc_store = iroh_sync::store::fs::Store::new(IrohPaths::DocsDatabase.with_env()?)?;
-    spawn_daemon_node(rt, bao_store, doc_store, key, peer_data_path, opts).await
+    let docs_path = IrohPaths::DocsDatabase.with_env()?;
+    let doc_store = match opts.docs_wal {
+        Some(sync) => DocFsStore::with_wal(docs_path, sync)?,
+        None => DocFsStore::new(docs_path)?,
+    };
+    spawn_daemon_node(
+        rt,
+        bao_store,
+        doc_store,
+        key,
+        peer_data_path,
+        serve_stats_path,
+        opts,
+    )
+    .await
+}
+
+/// Loads the flat store, showing the progress for large stores.
+///
+/// Loading can be aborted with ctrl-c.
+async fn load_store(
+    rt: &runtime::Handle,
+    blob_dir: &Path,
+    partial_blob_dir: &Path,
+    meta_dir: &Path,
+) -> Result<BaoFsStore> {
+    let cancel = CancellationToken::new();
+    let _abort = {
+        let cancel = cancel.clone();
+        AbortingJoinHandle(tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+            }
+        }))
+    };
+    let last_percent = AtomicU64::new(0);
+    let progress = move |progress: LoadProgress| {
+        // only large stores take long enough to be worth showing progress
+        if progress.total < 10_000 {
+            return;
+        }
+        let percent = progress.percent();
+        if percent == 0 || last_percent.swap(percent, Ordering::Relaxed) != percent {
+            eprint!("\rloading store: {percent}%");
+            if progress.done == progress.total {
+                eprintln!();
+            }
+        }
+    };
+    flat::Store::load_with_progress(blob_dir, partial_blob_dir, meta_dir, rt, progress, cancel)
+        .await
 }
 
 async fn spawn_daemon_node<B: BaoStore, D: DocStore>(
@@ -106,6 +186,7 @@ async fn spawn_daemon_node<B: BaoStore, D: DocStore>(
     doc_store: D,
     key: Option<PathBuf>,
     peers_data_path: PathBuf,
+    serve_stats_path: PathBuf,
     opts: StartOptions,
 ) -> Result<Node<B, D>> {
     let secret_key = get_secret_key(key).await?;
@@ -113,11 +194,20 @@ async fn spawn_daemon_node<B: BaoStore, D: DocStore>(
     let mut builder = Node::builder(bao_store, doc_store)
         .custom_auth_handler(Arc::new(StaticTokenAuthHandler::new(opts.request_token)))
         .peers_data_path(peers_data_path)
+        .serve_stats_path(serve_stats_path)
+        .read_ahead(opts.read_ahead)
+        .auto_download(opts.auto_download)
         .keylog(opts.keylog);
     if let Some(dm) = opts.derp_map {
         builder = builder.enable_derp(dm);
     }
+    if let Some(policy) = opts.shard_policy {
+        builder = builder.shard_policy(policy);
+    }
     let builder = builder.bind_addr(opts.addr).runtime(rt);
+    // use the sockets passed in by systemd, if any, instead of binding to `opts.addr`
+    #[cfg(all(unix, feature = "socket-activation"))]
+    let builder = builder.socket_activation()?;
 
     let provider = if let Some(rpc_port) = opts.rpc_port.into() {
         let rpc_endpoint = make_rpc_endpoint(&secret_key, rpc_port)?;
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use anyhow::{anyhow, bail, Context, Result};
 use clap::Parser;
 use futures::{StreamExt, TryStreamExt};
@@ -8,6 +10,7 @@ use iroh::{
     sync_engine::{LiveEvent, Origin},
 };
 use iroh_sync::{store::GetFilter, AuthorId, Entry, NamespaceId};
+use tokio::io::AsyncWriteExt;
 
 use crate::config::ConsoleEnv;
 
@@ -98,6 +101,23 @@ pub enum DocCommands {
         /// Optional key prefix (parsed as UTF-8 string)
         prefix: Option<String>,
     },
+    /// Export the content of a document to a tar archive, with the keys as paths.
+    ///
+    /// Only the latest entry of each key is exported. Content that is missing locally is
+    /// downloaded from the peers of the document first.
+    ExportTar {
+        /// Document to operate on.
+        ///
+        /// Required unless the document is set through the IROH_DOC environment variable.
+        /// Within the Iroh console, the active document can also set with `doc switch`.
+        #[clap(short, long)]
+        doc: Option<NamespaceId>,
+        /// Optional key prefix (parsed as UTF-8 string)
+        #[clap(short, long)]
+        prefix: Option<String>,
+        /// Path of the archive to write.
+        out: PathBuf,
+    },
     /// Watch for changes and events on a document
     Watch {
         /// Document to operate on.
@@ -122,6 +142,16 @@ pub enum AuthorCommands {
     /// List authors.
     #[clap(alias = "ls")]
     List,
+    /// Remove an author.
+    ///
+    /// Fails if the author has written entries to any document, unless --force is set.
+    #[clap(alias = "rm")]
+    Remove {
+        author: AuthorId,
+        /// Remove the author even if it has written entries.
+        #[clap(long)]
+        force: bool,
+    },
 }
 
 impl DocCommands {
@@ -178,6 +208,15 @@ impl DocCommands {
                 let author = env.author(author)?;
                 let key = key.as_bytes().to_vec();
                 let value = value.as_bytes().to_vec();
+                if let Some(max) = iroh.node.config().await?.max_value_size {
+                    if value.len() as u64 > max {
+                        eprintln!(
+                            "Warning: the value is {} bytes, which exceeds the node's max value size of {} bytes",
+                            value.len(),
+                            max
+                        );
+                    }
+                }
                 let hash = doc.set_bytes(author, key, value).await?;
                 println!("{}", hash);
             }
@@ -223,6 +262,21 @@ impl DocCommands {
                     println!("{}", fmt_entry(&entry));
                 }
             }
+            Self::ExportTar { doc, prefix, out } => {
+                let doc = get_doc(iroh, env, doc).await?;
+                let prefix = prefix.map(String::into_bytes).unwrap_or_default();
+                let mut stream = doc.export_tar(prefix).await?;
+                let mut file = tokio::fs::File::create(&out)
+                    .await
+                    .with_context(|| format!("failed to create {}", out.display()))?;
+                let mut size = 0;
+                while let Some(chunk) = stream.try_next().await? {
+                    size += chunk.len() as u64;
+                    file.write_all(&chunk).await?;
+                }
+                file.sync_all().await?;
+                println!("wrote {} to {}", HumanBytes(size), out.display());
+            }
             Self::Watch { doc } => {
                 let doc = get_doc(iroh, env, doc).await?;
                 let mut stream = doc.subscribe().await?;
@@ -299,6 +353,10 @@ impl AuthorCommands {
                     println!("{}", author_id);
                 }
             }
+            Self::Remove { author, force } => {
+                iroh.authors.remove(author, force).await?;
+                println!("Removed author {}", fmt_short(author.as_bytes()));
+            }
             Self::New { switch } => {
                 if switch && !env.is_console() {
                     bail!("The --switch flag is only supported within the Iroh console.");
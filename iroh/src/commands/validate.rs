@@ -7,9 +7,37 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use iroh::client::quic::Iroh;
 use iroh_bytes::{baomap::ValidateProgress, Hash};
 
-pub async fn run(iroh: &Iroh, repair: bool) -> Result<()> {
+/// What to validate.
+pub enum Target {
+    /// All blobs of the store.
+    Store {
+        repair: bool,
+        concurrency: Option<usize>,
+    },
+    /// A collection and its children.
+    Collection(Hash),
+    /// A single blob, with the number of bytes between progress updates.
+    Blob(Hash, Option<u64>),
+}
+
+pub async fn run(iroh: &Iroh, target: Target) -> Result<()> {
     let mut state = ValidateProgressState::new();
-    let mut response = iroh.blobs.validate(repair).await?;
+    let mut response = match target {
+        Target::Store {
+            repair,
+            concurrency,
+        } => iroh
+            .blobs
+            .validate_with_concurrency(repair, concurrency)
+            .await?
+            .boxed(),
+        Target::Collection(hash) => iroh.blobs.validate_collection(hash).await?.boxed(),
+        Target::Blob(hash, progress_interval) => iroh
+            .blobs
+            .validate_one(hash, progress_interval)
+            .await?
+            .boxed(),
+    };
 
     while let Some(item) = response.next().await {
         match item? {
@@ -34,6 +62,16 @@ pub async fn run(iroh: &Iroh, repair: bool) -> Result<()> {
                 state.abort(error.to_string());
                 break;
             }
+            ValidateProgress::CollectionSummary {
+                children,
+                present,
+                valid,
+                missing,
+            } => {
+                state.summary(format!(
+                    "{children} children: {present} present, {valid} valid, {missing} missing"
+                ));
+            }
             ValidateProgress::AllDone => {
                 break;
             }
@@ -103,6 +141,12 @@ impl ValidateProgressState {
         }
     }
 
+    fn summary(&self, summary: String) {
+        let summary_line = self.mp.add(ProgressBar::new(0));
+        summary_line.set_style(ProgressStyle::default_bar().template("{msg}").unwrap());
+        summary_line.finish_with_message(summary);
+    }
+
     fn abort(self, error: String) {
         let error_line = self.mp.add(ProgressBar::new(0));
         error_line.set_style(ProgressStyle::default_bar().template("{msg}").unwrap());
@@ -10,12 +10,13 @@ use std::{
 
 use anyhow::{anyhow, bail, Context, Result};
 use config::{Environment, File, Value};
-use iroh::node::GcPolicy;
+use iroh::{node::GcPolicy, shard::ShardPolicy};
 use iroh_net::{
     defaults::{default_eu_derp_region, default_na_derp_region},
     derp::{DerpMap, DerpRegion},
+    key::PublicKey,
 };
-use iroh_sync::{AuthorId, NamespaceId};
+use iroh_sync::{store::fs::WalSync, AuthorId, NamespaceId};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use tracing::debug;
@@ -61,6 +62,9 @@ pub enum IrohPaths {
     #[strum(serialize = "peers.postcard")]
     /// Path to store known peer data.
     PeerData,
+    /// Path to store the serve counters of the blobs, see [`iroh::serve_stats`].
+    #[strum(serialize = "serve-stats.postcard")]
+    ServeStats,
 }
 
 impl AsRef<Path> for IrohPaths {
@@ -158,6 +162,23 @@ pub struct NodeConfig {
     pub derp_regions: Vec<DerpRegion>,
     /// How often to run garbage collection.
     pub gc_policy: GcPolicy,
+    /// Read-ahead window in bytes when serving blobs, 0 to disable.
+    pub read_ahead: usize,
+    /// Whether to download the content of document entries received from peers.
+    pub auto_download: bool,
+    /// Whether to compress the data files of new blobs in the store.
+    pub compress_data: bool,
+    /// Maximum total size in bytes of the blob outboards kept in memory.
+    pub outboard_cache_size: u64,
+    /// Write-ahead log for the records of the document store, and when it is synced.
+    ///
+    /// `None` writes the records to the database directly.
+    pub docs_wal: Option<WalSync>,
+    /// The other nodes of the provider cluster this node shares the content of documents
+    /// with, as base32 encoded peer ids.
+    ///
+    /// If empty, this node holds all content. See [`ShardPolicy`].
+    pub shard_peers: Vec<String>,
 }
 
 impl Default for NodeConfig {
@@ -166,6 +187,12 @@ impl Default for NodeConfig {
             // TODO(ramfox): this should probably just be a derp map
             derp_regions: [default_na_derp_region(), default_eu_derp_region()].into(),
             gc_policy: GcPolicy::Disabled,
+            read_ahead: 0,
+            auto_download: true,
+            compress_data: false,
+            outboard_cache_size: iroh::baomap::flat::DEFAULT_OUTBOARD_CACHE_SIZE,
+            docs_wal: None,
+            shard_peers: Vec::new(),
         }
     }
 }
@@ -244,6 +271,21 @@ impl NodeConfig {
         }
         Some(DerpMap::from_regions(self.derp_regions.iter().cloned())).transpose()
     }
+
+    /// Constructs a `ShardPolicy` based on the current configuration.
+    pub fn shard_policy(&self) -> Result<Option<ShardPolicy>> {
+        if self.shard_peers.is_empty() {
+            return Ok(None);
+        }
+        let peers = self
+            .shard_peers
+            .iter()
+            .map(|peer| {
+                PublicKey::from_str(peer).with_context(|| format!("invalid shard peer {peer}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(ShardPolicy::new(peers)))
+    }
 }
 
 /// Environment for CLI and REPL
@@ -472,6 +514,7 @@ pub fn iroh_cache_path(file_name: &Path) -> Result<PathBuf> {
 
 #[cfg(test)]
 mod tests {
+    use iroh_net::key::SecretKey;
     use strum::IntoEnumIterator;
 
     use super::*;
@@ -481,6 +524,24 @@ mod tests {
         let config = NodeConfig::load(&[][..], "__FOO", HashMap::<String, String>::new()).unwrap();
 
         assert_eq!(config.derp_regions.len(), 2);
+        assert!(config.shard_policy().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_shard_policy() {
+        let peer = SecretKey::generate().public();
+        let config = NodeConfig {
+            shard_peers: vec![peer.to_string()],
+            ..Default::default()
+        };
+        let policy = config.shard_policy().unwrap().unwrap();
+        assert_eq!(policy.peers(), &[peer]);
+
+        let config = NodeConfig {
+            shard_peers: vec!["not a peer id".to_string()],
+            ..Default::default()
+        };
+        assert!(config.shard_policy().is_err());
     }
 
     #[test]
@@ -0,0 +1,190 @@
+//! QUIC statistics of the open connections of a node, per peer.
+//!
+//! Connections are tracked while they are used by the node, i.e. incoming connections for
+//! the iroh-bytes and gossip protocols while they are handled, and outgoing connections of
+//! the [`Downloader`](crate::downloader::Downloader) while a download is running on them.
+//! The statistics are read from the underlying [`quinn::Connection`] when they are
+//! requested, so they are always current.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh_net::key::PublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Whether a connection was opened by the peer or by this node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionDirection {
+    /// The peer opened the connection.
+    Incoming,
+    /// This node opened the connection.
+    Outgoing,
+}
+
+/// Statistics of the current path of a QUIC connection.
+///
+/// The bytes in flight are not exposed by quinn, the congestion window together with the
+/// lost packets is the best indication of a lossy or congested path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuicStats {
+    /// Current estimate of the round trip time.
+    pub rtt: Duration,
+    /// Current congestion window in bytes.
+    pub cwnd: u64,
+    /// Number of congestion events.
+    pub congestion_events: u64,
+    /// Number of packets sent.
+    pub sent_packets: u64,
+    /// Number of packets lost.
+    pub lost_packets: u64,
+    /// Number of bytes lost.
+    pub lost_bytes: u64,
+    /// Number of bytes sent in UDP datagrams.
+    pub bytes_sent: u64,
+    /// Number of bytes received in UDP datagrams.
+    pub bytes_received: u64,
+}
+
+impl QuicStats {
+    /// Reads the current statistics of `connection`.
+    pub fn of(connection: &quinn::Connection) -> Self {
+        let stats = connection.stats();
+        Self {
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            congestion_events: stats.path.congestion_events,
+            sent_packets: stats.path.sent_packets,
+            lost_packets: stats.path.lost_packets,
+            lost_bytes: stats.path.lost_bytes,
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+        }
+    }
+
+    /// The fraction of sent packets that were lost, between 0 and 1.
+    pub fn loss_rate(&self) -> f64 {
+        if self.sent_packets == 0 {
+            0.0
+        } else {
+            self.lost_packets as f64 / self.sent_packets as f64
+        }
+    }
+}
+
+/// The statistics of a single connection to a peer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    /// The stable id of the connection, as in the events of the provider.
+    pub connection_id: u64,
+    /// The ALPN protocol of the connection.
+    pub alpn: String,
+    /// Who opened the connection.
+    pub direction: ConnectionDirection,
+    /// The QUIC statistics of the connection.
+    pub stats: QuicStats,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    alpn: String,
+    direction: ConnectionDirection,
+    connection: quinn::Connection,
+    // number of guards for this connection, a connection can be used by several downloads
+    count: usize,
+}
+
+/// The connections of a node that are currently in use, by peer.
+#[derive(Debug, Clone, Default)]
+pub struct Connections {
+    peers: Arc<Mutex<HashMap<PublicKey, BTreeMap<usize, Tracked>>>>,
+}
+
+impl Connections {
+    /// Tracks `connection` to `peer` until the returned guard is dropped.
+    ///
+    /// The registry keeps a handle to the connection only while a guard for it is alive,
+    /// so tracking does not keep a connection open after its user is done with it.
+    pub fn track(
+        &self,
+        peer: PublicKey,
+        alpn: &[u8],
+        direction: ConnectionDirection,
+        connection: &quinn::Connection,
+    ) -> TrackGuard {
+        let id = connection.stable_id();
+        let mut peers = self.peers.lock().unwrap();
+        let tracked = peers.entry(peer).or_default().entry(id).or_insert(Tracked {
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            direction,
+            connection: connection.clone(),
+            count: 0,
+        });
+        tracked.count += 1;
+        TrackGuard {
+            connections: self.clone(),
+            peer,
+            id,
+        }
+    }
+
+    /// The statistics of the connections to `peer`, ordered by connection id.
+    pub fn get(&self, peer: &PublicKey) -> Vec<ConnectionStats> {
+        let peers = self.peers.lock().unwrap();
+        let Some(connections) = peers.get(peer) else {
+            return Vec::new();
+        };
+        connections
+            .iter()
+            .map(|(id, tracked)| ConnectionStats {
+                connection_id: *id as u64,
+                alpn: tracked.alpn.clone(),
+                direction: tracked.direction,
+                stats: QuicStats::of(&tracked.connection),
+            })
+            .collect()
+    }
+
+    /// The peer of the tracked connection with the stable id `connection_id`.
+    pub fn peer(&self, connection_id: u64) -> Option<PublicKey> {
+        let peers = self.peers.lock().unwrap();
+        peers
+            .iter()
+            .find(|(_, connections)| connections.contains_key(&(connection_id as usize)))
+            .map(|(peer, _)| *peer)
+    }
+
+    /// The peers with tracked connections.
+    pub fn peers(&self) -> Vec<PublicKey> {
+        self.peers.lock().unwrap().keys().copied().collect()
+    }
+
+    fn untrack(&self, peer: &PublicKey, id: usize) {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(connections) = peers.get_mut(peer) else {
+            return;
+        };
+        if let Some(tracked) = connections.get_mut(&id) {
+            tracked.count -= 1;
+            if tracked.count == 0 {
+                connections.remove(&id);
+            }
+        }
+        if connections.is_empty() {
+            peers.remove(peer);
+        }
+    }
+}
+
+/// Stops tracking a connection when dropped, see [`Connections::track`].
+#[derive(Debug)]
+pub struct TrackGuard {
+    connections: Connections,
+    peer: PublicKey,
+    id: usize,
+}
+
+impl Drop for TrackGuard {
+    fn drop(&mut self) {
+        self.connections.untrack(&self.peer, self.id);
+    }
+}
@@ -33,7 +33,11 @@ use std::{
     num::NonZeroUsize,
 };
 
-use futures::{future::LocalBoxFuture, stream::FuturesUnordered, FutureExt, StreamExt};
+use futures::{
+    future::{BoxFuture, LocalBoxFuture},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
 use iroh_bytes::{
     baomap::{range_collections::RangeSet2, Store},
     collection::CollectionParser,
@@ -45,6 +49,8 @@ use tokio::sync::{mpsc, oneshot};
 use tokio_util::{sync::CancellationToken, time::delay_queue};
 use tracing::{debug, trace};
 
+use crate::connection_stats::Connections;
+
 mod get;
 mod invariants;
 mod test;
@@ -87,8 +93,8 @@ pub enum FailureAction {
     RetryLater(anyhow::Error),
 }
 
-/// Future of a get request.
-type GetFut = LocalBoxFuture<'static, Result<(), FailureAction>>;
+/// Future of a get request, resolving to the number of bytes downloaded.
+type GetFut = LocalBoxFuture<'static, Result<u64, FailureAction>>;
 
 /// Trait modelling performing a single request over a connection. This allows for IO-less testing.
 pub trait Getter {
@@ -171,9 +177,9 @@ impl DownloadKind {
     }
 }
 
-// For readability. In the future we might care about some data reporting on a successful download
-// or kind of failure in the error case.
-type DownloadResult = anyhow::Result<()>;
+// For readability. A successful download reports the number of bytes downloaded. In the future we
+// might care about the kind of failure in the error case.
+type DownloadResult = anyhow::Result<u64>;
 
 /// Handle to interact with a download request.
 #[derive(Debug)]
@@ -204,6 +210,48 @@ impl std::future::Future for DownloadHandle {
     }
 }
 
+/// Summary of a batch of downloads, see [`Downloader::push_batch`].
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    /// Downloads that succeeded.
+    pub succeeded: Vec<DownloadKind>,
+    /// Downloads that failed, with the reason.
+    pub failed: Vec<(DownloadKind, anyhow::Error)>,
+    /// Number of bytes downloaded by the successful downloads.
+    pub total_bytes: u64,
+}
+
+/// Handle to a batch of downloads, resolving to a [`BatchSummary`] once all downloads
+/// terminated.
+#[derive(derive_more::Debug)]
+pub struct BatchHandle {
+    #[debug("FuturesUnordered<BoxFuture<(DownloadKind, DownloadResult)>>")]
+    pending: FuturesUnordered<BoxFuture<'static, (DownloadKind, DownloadResult)>>,
+    summary: BatchSummary,
+}
+
+impl std::future::Future for BatchHandle {
+    type Output = BatchSummary;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        use std::task::Poll::*;
+        loop {
+            match self.pending.poll_next_unpin(cx) {
+                Ready(Some((kind, Ok(bytes)))) => {
+                    self.summary.succeeded.push(kind);
+                    self.summary.total_bytes += bytes;
+                }
+                Ready(Some((kind, Err(err)))) => self.summary.failed.push((kind, err)),
+                Ready(None) => return Ready(std::mem::take(&mut self.summary)),
+                Pending => return Pending,
+            }
+        }
+    }
+}
+
 /// Handle for the download services.
 #[derive(Debug)]
 pub struct Downloader {
@@ -215,11 +263,16 @@ pub struct Downloader {
 
 impl Downloader {
     /// Create a new Downloader.
+    ///
+    /// Downloads of blobs for which peers announce a size larger than `max_blob_size` fail.
+    /// The connections are tracked in `connections` while a download runs on them.
     pub async fn new<S, C>(
         store: S,
         collection_parser: C,
+        max_blob_size: u64,
         endpoint: MagicEndpoint,
         rt: iroh_bytes::util::runtime::Handle,
+        connections: Connections,
     ) -> Self
     where
         S: Store,
@@ -233,6 +286,8 @@ impl Downloader {
             let getter = get::IoGetter {
                 store,
                 collection_parser,
+                max_blob_size,
+                connections,
             };
 
             let service = Service::new(getter, dialer, concurrency_limits, msg_rx);
@@ -269,6 +324,25 @@ impl Downloader {
         handle
     }
 
+    /// Queue a batch of downloads.
+    ///
+    /// The returned [`BatchHandle`] resolves to a [`BatchSummary`] once all downloads of the
+    /// batch succeeded or failed.
+    pub async fn push_batch(
+        &mut self,
+        items: impl IntoIterator<Item = (DownloadKind, Vec<PeerInfo>)>,
+    ) -> BatchHandle {
+        let pending = FuturesUnordered::new();
+        for (kind, peers) in items {
+            let handle = self.queue(kind.clone(), peers).await;
+            pending.push(handle.map(|res| (kind, res)).boxed());
+        }
+        BatchHandle {
+            pending,
+            summary: BatchSummary::default(),
+        }
+    }
+
     /// Cancel a download.
     // NOTE: receiving the handle ensures an intent can't be cancelled twice
     pub async fn cancel(&mut self, handle: DownloadHandle) {
@@ -437,7 +511,7 @@ enum PeerState {
 }
 
 /// Type of future that performs a download request.
-type DownloadFut = LocalBoxFuture<'static, (DownloadKind, Result<(), FailureAction>)>;
+type DownloadFut = LocalBoxFuture<'static, (DownloadKind, Result<u64, FailureAction>)>;
 
 #[derive(Debug)]
 struct Service<G: Getter, D: Dialer> {
@@ -812,7 +886,7 @@ impl<G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D> {
         self.start_download(kind, peer, conn, remaining_retries, intents);
     }
 
-    fn on_download_completed(&mut self, kind: DownloadKind, result: Result<(), FailureAction>) {
+    fn on_download_completed(&mut self, kind: DownloadKind, result: Result<u64, FailureAction>) {
         // first remove the request
         let info = self
             .current_requests
@@ -848,10 +922,10 @@ impl<G: Getter<Connection = D::Connection>, D: Dialer> Service<G, D> {
         let hash = *kind.hash();
 
         let peer_ready = match result {
-            Ok(()) => {
-                debug!(%peer, ?kind, "download completed");
+            Ok(bytes) => {
+                debug!(%peer, ?kind, bytes, "download completed");
                 for sender in intents.into_values() {
-                    let _ = sender.send(Ok(()));
+                    let _ = sender.send(Ok(bytes));
                 }
                 true
             }
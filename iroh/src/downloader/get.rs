@@ -18,9 +18,13 @@ use iroh_bytes::{
 };
 #[cfg(feature = "metrics")]
 use iroh_metrics::{inc, inc_by};
+use iroh_net::magic_endpoint::get_peer_id;
 use tracing::trace;
 
-use crate::get::{get_missing_ranges_blob, get_missing_ranges_collection, BlobInfo};
+use crate::connection_stats::{ConnectionDirection, Connections};
+use crate::get::{
+    check_blob_size, get_missing_ranges_blob, get_missing_ranges_collection, BlobInfo,
+};
 #[cfg(feature = "metrics")]
 use crate::metrics::Metrics;
 use crate::util::progress::ProgressSliceWriter2;
@@ -31,6 +35,10 @@ use super::{DownloadKind, FailureAction, GetFut, Getter};
 pub(crate) struct IoGetter<S: Store, C: CollectionParser> {
     pub store: S,
     pub collection_parser: C,
+    /// Maximum size of a blob accepted from a peer.
+    pub max_blob_size: u64,
+    /// Registry of the connections in use, for their statistics.
+    pub connections: Connections,
 }
 
 impl<S: Store, C: CollectionParser> Getter for IoGetter<S, C> {
@@ -39,30 +47,46 @@ impl<S: Store, C: CollectionParser> Getter for IoGetter<S, C> {
     fn get(&mut self, kind: DownloadKind, conn: Self::Connection) -> GetFut {
         let store = self.store.clone();
         let collection_parser = self.collection_parser.clone();
+        let max_blob_size = self.max_blob_size;
+        let connections = self.connections.clone();
         let fut = async move {
+            let _guard = match get_peer_id(&conn).await {
+                Ok(peer) => Some(connections.track(
+                    peer,
+                    &iroh_bytes::protocol::ALPN,
+                    ConnectionDirection::Outgoing,
+                    &conn,
+                )),
+                Err(err) => {
+                    trace!("not tracking connection without peer id: {err:#}");
+                    None
+                }
+            };
             let get = match kind {
-                DownloadKind::Blob { hash } => get(&store, &collection_parser, conn, hash, false),
+                DownloadKind::Blob { hash } => {
+                    get(&store, &collection_parser, conn, hash, false, max_blob_size)
+                }
                 DownloadKind::Collection { hash } => {
-                    get(&store, &collection_parser, conn, hash, true)
+                    get(&store, &collection_parser, conn, hash, true, max_blob_size)
                 }
             };
 
             let res = get.await;
             match res {
-                Ok(_stats) => {
+                Ok(stats) => {
                     #[cfg(feature = "metrics")]
                     {
                         let Stats {
                             bytes_written,
                             bytes_read: _,
                             elapsed,
-                        } = _stats;
+                        } = stats;
 
                         inc!(Metrics, downloads_success);
                         inc_by!(Metrics, download_bytes_total, bytes_written);
                         inc_by!(Metrics, download_time_total, elapsed.as_millis() as u64);
                     }
-                    Ok(())
+                    Ok(stats.bytes_read)
                 }
                 Err(e) => {
                     // record metrics according to the error
@@ -162,6 +186,10 @@ impl From<iroh_bytes::get::fsm::ConnectedNextError> for FailureAction {
                 // request will never be sent, drop it
                 FailureAction::AbortRequest(e.into())
             }
+            e @ UnsupportedRequest => {
+                // live and partial requests are not handled by the state machine
+                FailureAction::AbortRequest(e.into())
+            }
             Write(e) => e.into(),
             Read(e) => e.into(),
             e @ CustomRequestTooBig => {
@@ -210,6 +238,7 @@ impl From<iroh_bytes::get::fsm::DecodeError> for FailureAction {
             e @ NotFound => FailureAction::RetryLater(e.into()),
             e @ ParentNotFound(_) => FailureAction::RetryLater(e.into()),
             e @ LeafNotFound(_) => FailureAction::RetryLater(e.into()),
+            e @ Truncated { .. } => FailureAction::RetryLater(e.into()),
             e @ ParentHashMismatch(_) => {
                 // TODO(@divma): did the peer sent wrong data? is it corrupted? did we sent a wrong
                 // request?
@@ -241,11 +270,12 @@ pub async fn get<D: Store, C: CollectionParser>(
     conn: quinn::Connection,
     hash: Hash,
     recursive: bool,
+    max_blob_size: u64,
 ) -> Result<Stats, FailureAction> {
     let res = if recursive {
-        get_collection(db, collection_parser, conn, &hash).await
+        get_collection(db, collection_parser, conn, &hash, max_blob_size).await
     } else {
-        get_blob(db, conn, &hash).await
+        get_blob(db, conn, &hash, max_blob_size).await
     };
     if let Err(e) = res.as_ref() {
         tracing::error!("get failed: {e:?}");
@@ -261,6 +291,7 @@ pub async fn get_blob<D: Store>(
     db: &D,
     conn: quinn::Connection,
     hash: &Hash,
+    max_blob_size: u64,
 ) -> Result<Stats, FailureAction> {
     let end = if let Some(entry) = db.get_partial(hash) {
         trace!("got partial data for {}", hash,);
@@ -284,7 +315,7 @@ pub async fn get_blob<D: Store>(
         let header = start.next();
         // do the ceremony of getting the blob and adding it to the database
 
-        get_blob_inner_partial(db, header, entry).await?
+        get_blob_inner_partial(db, header, entry, max_blob_size).await?
     } else {
         // full request
         let request = get::fsm::start(
@@ -302,7 +333,7 @@ pub async fn get_blob<D: Store>(
         // move to the header
         let header = start.next();
         // do the ceremony of getting the blob and adding it to the database
-        get_blob_inner(db, header).await?
+        get_blob_inner(db, header, max_blob_size).await?
     };
 
     // we have requested a single hash, so we must be at closing
@@ -324,12 +355,19 @@ pub async fn get_blob<D: Store>(
 async fn get_blob_inner<D: Store>(
     db: &D,
     header: AtBlobHeader,
+    max_blob_size: u64,
 ) -> Result<AtEndBlob, FailureAction> {
     use iroh_io::AsyncSliceWriter;
 
     let hash = header.hash();
     // read the size
     let (content, size) = header.next().await?;
+    // a peer announcing a size this large is likely malicious
+    check_blob_size(hash, size, max_blob_size).map_err(FailureAction::DropPeer)?;
+    // fail before writing anything if the blob does not fit into the store, other peers
+    // would not change that
+    iroh_bytes::baomap::ensure_space(db, iroh_bytes::baomap::blob_space(size))
+        .map_err(|e| FailureAction::AbortRequest(e.into()))?;
     // create the temp file pair
     let entry = db.get_or_create_partial(hash, size)?;
     // open the data file in any case
@@ -368,13 +406,16 @@ async fn get_blob_inner_partial<D: Store>(
     db: &D,
     header: AtBlobHeader,
     entry: D::PartialEntry,
+    max_blob_size: u64,
 ) -> Result<AtEndBlob, FailureAction> {
-    // TODO: the data we get is validated at this point, but we need to check
-    // that it actually contains the requested ranges. Or DO WE?
+    // the data we get is validated at this point, and write_all_with_outboard checks that it
+    // contains all requested ranges
     use iroh_io::AsyncSliceWriter;
 
+    let hash = header.hash();
     // read the size
     let (content, size) = header.next().await?;
+    check_blob_size(hash, size, max_blob_size).map_err(FailureAction::DropPeer)?;
     // open the data file in any case
     let df = entry.data_writer().await?;
     let mut of = if needs_outboard(size) {
@@ -408,6 +449,7 @@ pub async fn get_collection<D: Store, C: CollectionParser>(
     collection_parser: &C,
     conn: quinn::Connection,
     root_hash: &Hash,
+    max_blob_size: u64,
 ) -> Result<Stats, FailureAction> {
     use tracing::info as log;
     let finishing = if let Some(entry) = db.get(root_hash) {
@@ -473,9 +515,9 @@ pub async fn get_collection<D: Store, C: CollectionParser>(
             );
             let header = start.next(child_hash);
             let end_blob = match info {
-                BlobInfo::Missing => get_blob_inner(db, header).await?,
+                BlobInfo::Missing => get_blob_inner(db, header, max_blob_size).await?,
                 BlobInfo::Partial { entry, .. } => {
-                    get_blob_inner_partial(db, header, entry.clone()).await?
+                    get_blob_inner_partial(db, header, entry.clone(), max_blob_size).await?
                 }
                 BlobInfo::Complete => {
                     return Err(FailureAction::DropPeer(anyhow::anyhow!(
@@ -503,7 +545,7 @@ pub async fn get_collection<D: Store, C: CollectionParser>(
         // move to the header
         let header = start.next();
         // read the blob and add it to the database
-        let end_root = get_blob_inner(db, header).await?;
+        let end_root = get_blob_inner(db, header, max_blob_size).await?;
         // read the collection fully for now
         let entry = db.get(root_hash).context("just downloaded").map_err(|_| {
             FailureAction::RetryLater(anyhow::anyhow!("data just downloaded was not found"))
@@ -541,7 +583,7 @@ pub async fn get_collection<D: Store, C: CollectionParser>(
                 None => break start.finish(),
             };
             let header = start.next(child_hash);
-            let end_blob = get_blob_inner(db, header).await?;
+            let end_blob = get_blob_inner(db, header, max_blob_size).await?;
             next = end_blob.next();
         }
     };
@@ -252,3 +252,35 @@ async fn peer_role_provider() {
     getter.assert_history(&[(kind, peer_provider)]);
     dialer.assert_history(&[peer_provider]);
 }
+
+/// Tests that a batch resolves to a summary of all its downloads.
+#[tokio::test]
+async fn batch_summary() {
+    let dialer = dialer::TestingDialer::default();
+    let getter = getter::TestingGetter::default();
+    getter.set_request_size(1024);
+    let failing = Hash::new([3u8; 32]);
+    getter.set_failing(failing);
+    let concurrency_limits = ConcurrencyLimits::default();
+
+    let mut downloader =
+        Downloader::spawn_for_test(dialer.clone(), getter.clone(), concurrency_limits);
+
+    let peer = SecretKey::generate().public();
+    let items = (0..5).map(|i| {
+        let kind = DownloadKind::Blob {
+            hash: Hash::new([i; 32]),
+        };
+        (kind, vec![(peer, PeerRole::Provider).into()])
+    });
+    let summary = downloader.push_batch(items).await.await;
+
+    assert_eq!(summary.succeeded.len(), 4);
+    assert_eq!(summary.total_bytes, 4 * 1024);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, DownloadKind::Blob { hash: failing });
+
+    // an empty batch resolves right away
+    let summary = downloader.push_batch([]).await.await;
+    assert!(summary.succeeded.is_empty() && summary.failed.is_empty());
+}
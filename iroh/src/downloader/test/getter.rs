@@ -1,6 +1,6 @@
 //! Implementation of [`super::Getter`] used for testing.
 
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
 use parking_lot::RwLock;
 
@@ -13,6 +13,10 @@ pub(super) struct TestingGetter(Arc<RwLock<TestingGetterInner>>);
 struct TestingGetterInner {
     /// How long requests take.
     request_duration: Duration,
+    /// Number of bytes successful requests report as downloaded.
+    request_size: u64,
+    /// Hashes for which requests fail.
+    failing: HashSet<Hash>,
     /// History of requests performed by the [`Getter`] and if they were successful.
     request_history: Vec<(DownloadKind, PublicKey)>,
 }
@@ -24,11 +28,15 @@ impl Getter for TestingGetter {
 
     fn get(&mut self, kind: DownloadKind, peer: PublicKey) -> GetFut {
         let mut inner = self.0.write();
-        inner.request_history.push((kind, peer));
         let request_duration = inner.request_duration;
+        let res = match inner.failing.contains(kind.hash()) {
+            true => Err(FailureAction::AbortRequest(anyhow::anyhow!("failing hash"))),
+            false => Ok(inner.request_size),
+        };
+        inner.request_history.push((kind, peer));
         async move {
             tokio::time::sleep(request_duration).await;
-            Ok(())
+            res
         }
         .boxed_local()
     }
@@ -38,6 +46,13 @@ impl TestingGetter {
     pub(super) fn set_request_duration(&self, request_duration: Duration) {
         self.0.write().request_duration = request_duration;
     }
+    pub(super) fn set_request_size(&self, request_size: u64) {
+        self.0.write().request_size = request_size;
+    }
+    /// Make requests for `hash` fail without retries.
+    pub(super) fn set_failing(&self, hash: Hash) {
+        self.0.write().failing.insert(hash);
+    }
     /// Verify that the request history is as expected
     #[track_caller]
     pub(super) fn assert_history(&self, history: &[(DownloadKind, PublicKey)]) {
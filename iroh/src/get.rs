@@ -27,19 +27,38 @@ use tracing::trace;
 
 use crate::util::progress::ProgressSliceWriter2;
 
+/// Default for the maximum size of a blob that is accepted from a peer.
+///
+/// Peers announce the size of a blob before sending it, and storage for the blob is allocated
+/// based on that size, so downloads of blobs that claim to be larger are rejected.
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 1 << 40;
+
+/// Check the size announced by a peer for a blob against `max_blob_size`.
+pub(crate) fn check_blob_size(hash: Hash, size: u64, max_blob_size: u64) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        size <= max_blob_size,
+        "peer announced size {size} for blob {hash}, more than the maximum of {max_blob_size}"
+    );
+    Ok(())
+}
+
 /// Get a blob or collection
+///
+/// Blobs that are larger than `max_blob_size` are rejected before any storage is allocated
+/// for them.
 pub async fn get<D: BaoStore, C: CollectionParser>(
     db: &D,
     collection_parser: &C,
     conn: quinn::Connection,
     hash: Hash,
     recursive: bool,
+    max_blob_size: u64,
     sender: impl ProgressSender<Msg = GetProgress> + IdGenerator,
 ) -> anyhow::Result<Stats> {
     let res = if recursive {
-        get_collection(db, collection_parser, conn, &hash, sender).await
+        get_collection(db, collection_parser, conn, &hash, max_blob_size, sender).await
     } else {
-        get_blob(db, conn, &hash, sender).await
+        get_blob(db, conn, &hash, max_blob_size, sender).await
     };
     if let Err(e) = res.as_ref() {
         tracing::error!("get failed: {}", e);
@@ -55,6 +74,7 @@ pub async fn get_blob<D: BaoStore>(
     db: &D,
     conn: quinn::Connection,
     hash: &Hash,
+    max_blob_size: u64,
     progress: impl ProgressSender<Msg = GetProgress> + IdGenerator,
 ) -> anyhow::Result<Stats> {
     let end = if let Some(entry) = db.get_partial(hash) {
@@ -77,7 +97,7 @@ pub async fn get_blob<D: BaoStore>(
         let header = start.next();
         // do the ceremony of getting the blob and adding it to the database
 
-        get_blob_inner_partial(db, header, entry, progress).await?
+        get_blob_inner_partial(db, header, entry, max_blob_size, progress).await?
     } else {
         // full request
         let request = get::fsm::start(
@@ -93,7 +113,7 @@ pub async fn get_blob<D: BaoStore>(
         // move to the header
         let header = start.next();
         // do the ceremony of getting the blob and adding it to the database
-        get_blob_inner(db, header, progress).await?
+        get_blob_inner(db, header, max_blob_size, progress).await?
     };
 
     // we have requested a single hash, so we must be at closing
@@ -138,6 +158,7 @@ pub(crate) async fn get_missing_ranges_blob<D: PartialMap>(
 async fn get_blob_inner<D: BaoStore>(
     db: &D,
     header: AtBlobHeader,
+    max_blob_size: u64,
     sender: impl ProgressSender<Msg = GetProgress> + IdGenerator,
 ) -> anyhow::Result<AtEndBlob> {
     use iroh_io::AsyncSliceWriter;
@@ -145,6 +166,9 @@ async fn get_blob_inner<D: BaoStore>(
     let hash = header.hash();
     // read the size
     let (content, size) = header.next().await?;
+    check_blob_size(hash, size, max_blob_size)?;
+    // fail before writing anything if the blob does not fit into the store
+    iroh_bytes::baomap::ensure_space(db, iroh_bytes::baomap::blob_space(size))?;
     // create the temp file pair
     let entry = db.get_or_create_partial(hash, size)?;
     // open the data file in any case
@@ -158,6 +182,7 @@ async fn get_blob_inner<D: BaoStore>(
     let id = sender.new_id();
     sender.send(GetProgress::Found { id, hash, size }).await?;
     let sender2 = sender.clone();
+    let db2 = db.clone();
     let on_write = move |offset: u64, _length: usize| {
         // if try send fails it means that the receiver has been dropped.
         // in that case we want to abort the write_all_with_outboard.
@@ -167,6 +192,9 @@ async fn get_blob_inner<D: BaoStore>(
                 tracing::info!("aborting download of {}", hash);
                 e
             })?;
+        // this is called before each write, so the previous write is done by now.
+        // the last write is covered by insert_complete.
+        db2.notify_data_written(&hash);
         Ok(())
     };
     let mut pw = ProgressSliceWriter2::new(df, on_write);
@@ -198,6 +226,7 @@ async fn get_blob_inner_partial<D: BaoStore>(
     db: &D,
     header: AtBlobHeader,
     entry: D::PartialEntry,
+    max_blob_size: u64,
     sender: impl ProgressSender<Msg = GetProgress> + IdGenerator,
 ) -> anyhow::Result<AtEndBlob> {
     // TODO: the data we get is validated at this point, but we need to check
@@ -207,6 +236,7 @@ async fn get_blob_inner_partial<D: BaoStore>(
     let hash = header.hash();
     // read the size
     let (content, size) = header.next().await?;
+    check_blob_size(hash, size, max_blob_size)?;
     // open the data file in any case
     let df = entry.data_writer().await?;
     let mut of = if needs_outboard(size) {
@@ -218,6 +248,7 @@ async fn get_blob_inner_partial<D: BaoStore>(
     let id = sender.new_id();
     sender.send(GetProgress::Found { id, hash, size }).await?;
     let sender2 = sender.clone();
+    let db2 = db.clone();
     let on_write = move |offset: u64, _length: usize| {
         // if try send fails it means that the receiver has been dropped.
         // in that case we want to abort the write_all_with_outboard.
@@ -227,6 +258,9 @@ async fn get_blob_inner_partial<D: BaoStore>(
                 tracing::info!("aborting download of {}", hash);
                 e
             })?;
+        // this is called before each write, so the previous write is done by now.
+        // the last write is covered by insert_complete.
+        db2.notify_data_written(&hash);
         Ok(())
     };
     let mut pw = ProgressSliceWriter2::new(df, on_write);
@@ -286,6 +320,7 @@ pub async fn get_collection<D: BaoStore, C: CollectionParser>(
     collection_parser: &C,
     conn: quinn::Connection,
     root_hash: &Hash,
+    max_blob_size: u64,
     sender: impl ProgressSender<Msg = GetProgress> + IdGenerator,
 ) -> anyhow::Result<Stats> {
     use tracing::info as log;
@@ -344,9 +379,12 @@ pub async fn get_collection<D: BaoStore, C: CollectionParser>(
             );
             let header = start.next(child_hash);
             let end_blob = match info {
-                BlobInfo::Missing => get_blob_inner(db, header, sender.clone()).await?,
+                BlobInfo::Missing => {
+                    get_blob_inner(db, header, max_blob_size, sender.clone()).await?
+                }
                 BlobInfo::Partial { entry, .. } => {
-                    get_blob_inner_partial(db, header, entry.clone(), sender.clone()).await?
+                    get_blob_inner_partial(db, header, entry.clone(), max_blob_size, sender.clone())
+                        .await?
                 }
                 BlobInfo::Complete => anyhow::bail!("got data we have not requested"),
             };
@@ -368,7 +406,7 @@ pub async fn get_collection<D: BaoStore, C: CollectionParser>(
         // move to the header
         let header = start.next();
         // read the blob and add it to the database
-        let end_root = get_blob_inner(db, header, sender.clone()).await?;
+        let end_root = get_blob_inner(db, header, max_blob_size, sender.clone()).await?;
         // read the collection fully for now
         let entry = db.get(root_hash).context("just downloaded")?;
         let reader = entry.data_reader().await?;
@@ -398,7 +436,7 @@ pub async fn get_collection<D: BaoStore, C: CollectionParser>(
                 None => break start.finish(),
             };
             let header = start.next(child_hash);
-            let end_blob = get_blob_inner(db, header, sender.clone()).await?;
+            let end_blob = get_blob_inner(db, header, max_blob_size, sender.clone()).await?;
             next = end_blob.next();
         }
     };
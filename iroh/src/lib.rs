@@ -9,12 +9,16 @@ pub mod baomap;
 pub mod client;
 #[cfg(feature = "iroh-collection")]
 pub mod collection;
+pub mod connection_stats;
 pub mod dial;
 pub mod downloader;
 pub mod get;
 pub mod node;
 pub mod rpc_protocol;
+pub mod serve_stats;
+pub mod shard;
 pub mod sync_engine;
+pub mod touched;
 pub mod util;
 
 /// Expose metrics module
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use iroh_metrics::{
-    core::{Counter, Metric},
+    core::{Counter, Gauge, Metric},
     struct_iterable::Iterable,
 };
 
@@ -19,6 +19,10 @@ pub struct Metrics {
     pub downloads_success: Counter,
     pub downloads_error: Counter,
     pub downloads_notfound: Counter,
+    pub gossip_duplicates_dropped: Counter,
+    pub gossip_unauthenticated_dropped: Counter,
+    pub gossip_broadcasts_sent: Counter,
+    pub gossip_entries_coalesced: Counter,
 }
 
 impl Default for Metrics {
@@ -32,6 +36,18 @@ impl Default for Metrics {
             downloads_success: Counter::new("Total number of successfull downloads"),
             downloads_error: Counter::new("Total number of downloads failed with error"),
             downloads_notfound: Counter::new("Total number of downloads failed with not found"),
+            gossip_duplicates_dropped: Counter::new(
+                "Number of duplicate document entries received via gossip and dropped",
+            ),
+            gossip_unauthenticated_dropped: Counter::new(
+                "Number of gossip messages dropped because their MAC was missing or invalid",
+            ),
+            gossip_broadcasts_sent: Counter::new(
+                "Number of gossip messages broadcast for local document entries",
+            ),
+            gossip_entries_coalesced: Counter::new(
+                "Number of local document entries broadcast together with an earlier entry",
+            ),
         }
     }
 }
@@ -48,6 +64,7 @@ impl Metric for Metrics {
 pub fn try_init_metrics_collection() -> std::io::Result<()> {
     iroh_metrics::core::Core::try_init(|reg, metrics| {
         metrics.insert(crate::metrics::Metrics::new(reg));
+        metrics.insert(iroh_bytes::metrics::Metrics::new(reg));
         metrics.insert(iroh_sync::metrics::Metrics::new(reg));
         metrics.insert(iroh_net::metrics::MagicsockMetrics::new(reg));
         metrics.insert(iroh_net::metrics::NetcheckMetrics::new(reg));
@@ -63,6 +80,10 @@ pub fn get_metrics() -> anyhow::Result<HashMap<String, CounterStats>> {
     let mut map = HashMap::new();
     let core =
         iroh_metrics::core::Core::get().ok_or_else(|| anyhow::anyhow!("metrics are disabled"))?;
+    collect(
+        core.get_collector::<iroh_bytes::metrics::Metrics>(),
+        &mut map,
+    );
     collect(
         core.get_collector::<iroh_sync::metrics::Metrics>(),
         &mut map,
@@ -96,6 +117,11 @@ fn collect(metrics: Option<&impl Iterable>, map: &mut HashMap<String, CounterSta
             let value = counter.get();
             let description = counter.description.to_string();
             map.insert(name.to_string(), CounterStats { value, description });
+        } else if let Some(gauge) = counter.downcast_ref::<Gauge>() {
+            // gauges that are never decreased below zero, like sizes, fit the counter stats
+            let value = gauge.get().max(0) as u64;
+            let description = gauge.description.to_string();
+            map.insert(name.to_string(), CounterStats { value, description });
         }
     }
 }
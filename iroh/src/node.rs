@@ -5,6 +5,7 @@
 //! You can monitor what is happening in the node using [`Node::subscribe`].
 //!
 //! To shut down the node, call [`Node::shutdown`].
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt::Debug;
 use std::future::Future;
 use std::io;
@@ -13,31 +14,34 @@ use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Poll;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use bytes::Bytes;
 use futures::future::{BoxFuture, Shared};
 use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
 use iroh_bytes::baomap::{
-    ExportMode, GcMarkEvent, GcSweepEvent, Map, MapEntry, ReadableStore, Store as BaoStore,
-    ValidateProgress,
+    EntryStatus, ExportMode, GcMarkEvent, GcSweepEvent, Map, MapEntry, ReadableStore,
+    Store as BaoStore, TreeInfo, ValidateOptions, ValidateProgress,
+    DEFAULT_VALIDATE_PROGRESS_INTERVAL,
 };
 use iroh_bytes::collection::{CollectionParser, LinkSeqCollectionParser};
-use iroh_bytes::protocol::GetRequest;
+use iroh_bytes::protocol::{GetRequest, RangeSpec};
 use iroh_bytes::provider::GetProgress;
 use iroh_bytes::util::progress::{FlumeProgressSender, IdGenerator, ProgressSender};
-use iroh_bytes::util::{BlobFormat, HashAndFormat, RpcResult, SetTagOption};
+use iroh_bytes::util::{BlobFormat, HashAndFormat, RpcResult, SetTagOption, Tag};
 use iroh_bytes::{
-    protocol::{Closed, Request, RequestToken},
-    provider::{AddProgress, CustomGetHandler, RequestAuthorizationHandler},
+    protocol::{Closed, Request, RequestToken, RevocationList, RevokedToken, StructuredToken},
+    provider::{
+        AddProgress, CustomGetHandler, RequestAuthorizationHandler, TransferMemory, Transfers,
+    },
     util::runtime,
     util::Hash,
 };
 use iroh_gossip::net::{Gossip, GOSSIP_ALPN};
 use iroh_io::AsyncSliceReader;
 use iroh_net::defaults::default_derp_map;
-use iroh_net::magic_endpoint::get_alpn;
+use iroh_net::magic_endpoint::{get_alpn, get_peer_id, DerpStatus};
 use iroh_net::util::AbortingJoinHandle;
 use iroh_net::{
     config::Endpoint,
@@ -46,37 +50,66 @@ use iroh_net::{
     tls, MagicEndpoint, PeerAddr,
 };
 use iroh_sync::store::Store as DocStore;
-use quic_rpc::server::RpcChannel;
+use quic_rpc::server::{RpcChannel, RpcServerError};
 use quic_rpc::transport::flume::FlumeConnection;
 use quic_rpc::transport::misc::DummyServerEndpoint;
 use quic_rpc::{RpcClient, RpcServer, ServiceEndpoint};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, RwLock, Semaphore};
 use tokio::task::JoinError;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
 
+use crate::connection_stats::{ConnectionDirection, Connections, TrackGuard};
 use crate::dial::Ticket;
 use crate::downloader::Downloader;
+use crate::get::DEFAULT_MAX_BLOB_SIZE;
 use crate::rpc_protocol::{
-    BlobAddPathRequest, BlobDeleteBlobRequest, BlobDownloadRequest, BlobListCollectionsRequest,
+    BlobAddPathRequest, BlobAddStreamRequest, BlobAddStreamResponse, BlobAddStreamUpdate,
+    BlobDeleteBlobRequest, BlobDownloadRequest, BlobListCollectionsRequest,
     BlobListCollectionsResponse, BlobListIncompleteRequest, BlobListIncompleteResponse,
-    BlobListRequest, BlobListResponse, BlobReadResponse, BlobValidateRequest, BytesGetRequest,
-    DeleteTagRequest, DownloadLocation, ListTagsRequest, ListTagsResponse,
-    NodeConnectionInfoRequest, NodeConnectionInfoResponse, NodeConnectionsRequest,
-    NodeConnectionsResponse, NodeShutdownRequest, NodeStatsRequest, NodeStatsResponse,
-    NodeStatusRequest, NodeStatusResponse, NodeWatchRequest, NodeWatchResponse, ProviderRequest,
-    ProviderResponse, ProviderService,
+    BlobListRequest, BlobListResponse, BlobListUnreferencedRequest, BlobListUnreferencedResponse,
+    BlobReadResponse, BlobStatsRequest, BlobStatsResponse, BlobTouchRequest, BlobTreeRequest,
+    BlobValidateCollectionRequest, BlobValidateOneRequest, BlobValidateRequest, BytesGetRequest,
+    CancelTransferRequest, CancelTransferResponse, CollectionListingEntry,
+    CollectionListingRequest, CollectionListingResponse, ConnectionTransfersEntry,
+    DeleteTagRequest, DerpStatusRequest, DerpStatusResponse, DownloadLocation, ListRevokedRequest,
+    ListRevokedResponse, ListTagsRequest, ListTagsResponse, ListTransfersRequest,
+    ListTransfersResponse, NodeConfigRequest, NodeConfigResponse, NodeConnectionInfoRequest,
+    NodeConnectionInfoResponse, NodeConnectionsRequest, NodeConnectionsResponse, NodeEventsRequest,
+    NodeEventsResponse, NodeHealthRequest, NodeHealthResponse, NodePeerStatsRequest,
+    NodePeerStatsResponse, NodeReadyRequest, NodeReadyResponse, NodeShutdownRequest,
+    NodeStatsRequest, NodeStatsResponse, NodeStatusRequest, NodeStatusResponse, NodeWatchRequest,
+    NodeWatchResponse, ProviderRequest, ProviderResponse, ProviderService, RevokeTokenRequest,
+    RevokeTokenResponse, TransferListEntry,
 };
-use crate::sync_engine::{SyncEngine, SYNC_ALPN};
+use crate::serve_stats::ServeStats;
+use crate::shard::ShardPolicy;
+use crate::sync_engine::{
+    BroadcastPolicy, Discovery, NoDiscovery, SyncEngine, DEFAULT_GOSSIP_DEDUP_CAPACITY, SYNC_ALPN,
+};
+use crate::touched::TouchedBlobs;
+use crate::util::idle::IdleTimer;
 
 const MAX_CONNECTIONS: u32 = 1024;
-const MAX_STREAMS: u64 = 10;
+/// Default limit on the number of bidirectional QUIC streams a peer can have open.
+const MAX_STREAMS: u32 = 10;
+/// Default limit on the number of iroh-bytes requests handled concurrently.
+const MAX_CONCURRENT_REQUESTS: usize = 1024;
+/// Default limit on the number of iroh-bytes requests handled concurrently per connection.
+///
+/// This equals [`MAX_STREAMS`], so by default excess requests are queued by the stream limit
+/// of the transport instead of being rejected.
+const MAX_CONNECTION_REQUESTS: usize = MAX_STREAMS as usize;
+/// Default limit on the number of rpc operations that stream progress at the same time.
+const MAX_PROGRESS_OPERATIONS: usize = 64;
+/// Default budget for the buffers of transfers to other nodes, 64 MiB.
+const MAX_TRANSFER_MEMORY: usize = 64 * 1024 * 1024;
 const HEALTH_POLL_WAIT: Duration = Duration::from_secs(1);
 
 /// Default bind address for the node.
 /// 11204 is "iroh" in leetspeak <https://simple.wikipedia.org/wiki/Leet>
-pub const DEFAULT_BIND_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::LOCALHOST, 11204);
+pub const DEFAULT_BIND_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::UNSPECIFIED, 11204);
 
 /// How long we wait at most for some endpoints to be discovered.
 const ENDPOINT_WAIT: Duration = Duration::from_secs(5);
@@ -85,6 +118,14 @@ const ENDPOINT_WAIT: Duration = Duration::from_secs(5);
 const RPC_BLOB_GET_CHUNK_SIZE: usize = 1024 * 64;
 /// Channel cap for getting blobs over RPC
 const RPC_BLOB_GET_CHANNEL_CAP: usize = 2;
+/// How long a touched blob is kept by garbage collection, see [`Builder::gc_touch_ttl`].
+const DEFAULT_TOUCH_TTL: Duration = Duration::from_secs(60 * 60);
+/// How often the serve stats are saved, if they are persisted.
+const SERVE_STATS_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often expired entries are pruned from docs with a retention.
+const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// Number of node events kept for consumers that reconnect, see [`NodeEventsRequest`].
+const EVENT_LOG_CAPACITY: usize = 1024;
 
 /// Policy for garbage collection.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -118,19 +159,39 @@ pub struct Builder<
     C: CollectionParser,
 {
     bind_addr: SocketAddr,
+    /// Already bound sockets to use instead of binding to `bind_addr`.
+    sockets: Vec<std::net::UdpSocket>,
     secret_key: SecretKey,
     rpc_endpoint: E,
     db: D,
     keylog: bool,
     custom_get_handler: Arc<dyn CustomGetHandler>,
     auth_handler: Arc<dyn RequestAuthorizationHandler>,
+    discovery: Arc<dyn Discovery>,
     derp_map: Option<DerpMap>,
     collection_parser: C,
     gc_policy: GcPolicy,
+    gc_keep_most_served: usize,
+    gc_touch_ttl: Duration,
+    max_concurrent_requests: usize,
+    max_connection_requests: usize,
+    max_concurrent_streams: u32,
+    max_progress_operations: usize,
+    read_ahead: usize,
+    max_transfer_memory: usize,
+    max_blob_size: u64,
+    auto_download: bool,
+    gossip_dedup_capacity: usize,
+    shard_policy: Option<ShardPolicy>,
+    broadcast_policy: BroadcastPolicy,
+    migration: bool,
     rt: Option<runtime::Handle>,
     docs: S,
     /// Path to store peer data. If `None`, peer data will not be persisted.
     peers_data_path: Option<PathBuf>,
+    /// Path to store the serve stats. If `None`, they will not be persisted.
+    serve_stats_path: Option<PathBuf>,
+    idle_shutdown: Option<Duration>,
 }
 
 const PROTOCOLS: [&[u8]; 3] = [&iroh_bytes::protocol::ALPN, GOSSIP_ALPN, SYNC_ALPN];
@@ -179,6 +240,7 @@ impl<D: Map, S: DocStore> Builder<D, S> {
     fn with_db_and_store(db: D, docs: S) -> Self {
         Self {
             bind_addr: DEFAULT_BIND_ADDR.into(),
+            sockets: Vec::new(),
             secret_key: SecretKey::generate(),
             db,
             keylog: false,
@@ -186,11 +248,28 @@ impl<D: Map, S: DocStore> Builder<D, S> {
             rpc_endpoint: Default::default(),
             custom_get_handler: Arc::new(NoopCustomGetHandler),
             auth_handler: Arc::new(NoopRequestAuthorizationHandler),
-            collection_parser: LinkSeqCollectionParser,
+            discovery: Arc::new(NoDiscovery),
+            collection_parser: LinkSeqCollectionParser::default(),
             gc_policy: GcPolicy::Disabled,
+            gc_keep_most_served: 0,
+            gc_touch_ttl: DEFAULT_TOUCH_TTL,
+            max_concurrent_requests: MAX_CONCURRENT_REQUESTS,
+            max_connection_requests: MAX_CONNECTION_REQUESTS,
+            max_concurrent_streams: MAX_STREAMS,
+            max_progress_operations: MAX_PROGRESS_OPERATIONS,
+            read_ahead: 0,
+            max_transfer_memory: MAX_TRANSFER_MEMORY,
+            max_blob_size: DEFAULT_MAX_BLOB_SIZE,
+            auto_download: false,
+            gossip_dedup_capacity: DEFAULT_GOSSIP_DEDUP_CAPACITY,
+            shard_policy: None,
+            broadcast_policy: Default::default(),
+            migration: true,
             rt: None,
             docs,
             peers_data_path: None,
+            serve_stats_path: None,
+            idle_shutdown: None,
         }
     }
 }
@@ -210,22 +289,44 @@ where
         // we can't use ..self here because the return type is different
         Builder {
             bind_addr: self.bind_addr,
+            sockets: self.sockets,
             secret_key: self.secret_key,
             db: self.db,
             keylog: self.keylog,
             custom_get_handler: self.custom_get_handler,
             auth_handler: self.auth_handler,
+            discovery: self.discovery,
             rpc_endpoint: value,
             derp_map: self.derp_map,
             collection_parser: self.collection_parser,
             gc_policy: self.gc_policy,
+            gc_keep_most_served: self.gc_keep_most_served,
+            gc_touch_ttl: self.gc_touch_ttl,
+            max_concurrent_requests: self.max_concurrent_requests,
+            max_connection_requests: self.max_connection_requests,
+            max_concurrent_streams: self.max_concurrent_streams,
+            max_progress_operations: self.max_progress_operations,
+            read_ahead: self.read_ahead,
+            max_transfer_memory: self.max_transfer_memory,
+            max_blob_size: self.max_blob_size,
+            auto_download: self.auto_download,
+            gossip_dedup_capacity: self.gossip_dedup_capacity,
+            shard_policy: self.shard_policy,
+            broadcast_policy: self.broadcast_policy,
+            migration: self.migration,
             rt: self.rt,
             docs: self.docs,
             peers_data_path: self.peers_data_path,
+            serve_stats_path: self.serve_stats_path,
+            idle_shutdown: self.idle_shutdown,
         }
     }
 
     /// Configure the collection parser, changing the type of the builder to the new collection parser type.
+    ///
+    /// To serve collections of several formats, use a
+    /// [`CollectionParserRegistry`](iroh_bytes::collection::CollectionParserRegistry), which
+    /// selects the parser by the format marker of a collection.
     pub fn collection_parser<C2: CollectionParser>(
         self,
         collection_parser: C2,
@@ -234,17 +335,35 @@ where
         Builder {
             collection_parser,
             bind_addr: self.bind_addr,
+            sockets: self.sockets,
             secret_key: self.secret_key,
             db: self.db,
             keylog: self.keylog,
             custom_get_handler: self.custom_get_handler,
             auth_handler: self.auth_handler,
+            discovery: self.discovery,
             rpc_endpoint: self.rpc_endpoint,
             derp_map: self.derp_map,
             gc_policy: self.gc_policy,
+            gc_keep_most_served: self.gc_keep_most_served,
+            gc_touch_ttl: self.gc_touch_ttl,
+            max_concurrent_requests: self.max_concurrent_requests,
+            max_connection_requests: self.max_connection_requests,
+            max_concurrent_streams: self.max_concurrent_streams,
+            max_progress_operations: self.max_progress_operations,
+            read_ahead: self.read_ahead,
+            max_transfer_memory: self.max_transfer_memory,
+            max_blob_size: self.max_blob_size,
+            auto_download: self.auto_download,
+            gossip_dedup_capacity: self.gossip_dedup_capacity,
+            shard_policy: self.shard_policy,
+            broadcast_policy: self.broadcast_policy,
+            migration: self.migration,
             rt: self.rt,
             docs: self.docs,
             peers_data_path: self.peers_data_path,
+            serve_stats_path: self.serve_stats_path,
+            idle_shutdown: self.idle_shutdown,
         }
     }
 
@@ -256,6 +375,167 @@ where
         self
     }
 
+    /// Keeps the `n` most served blobs during garbage collection, even if nothing else
+    /// references them.
+    ///
+    /// Blobs are ranked by their serve counts, see [`Node::serve_stats`]. The default is 0,
+    /// so garbage collection does not take the serve counts into account.
+    pub fn gc_keep_most_served(mut self, n: usize) -> Self {
+        self.gc_keep_most_served = n;
+        self
+    }
+
+    /// Sets how long garbage collection keeps a blob after it was touched.
+    ///
+    /// See [`BlobTouchRequest`]. The default is one hour.
+    pub fn gc_touch_ttl(mut self, ttl: Duration) -> Self {
+        self.gc_touch_ttl = ttl;
+        self
+    }
+
+    /// Sets the maximum number of iroh-bytes requests handled concurrently.
+    ///
+    /// Requests arriving while this many are in flight are rejected with
+    /// [`Closed::RateLimited`] instead of being queued, so an overloaded node sheds
+    /// load rather than running out of memory.
+    ///
+    /// Defaults to 1024.
+    pub fn max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests;
+        self
+    }
+
+    /// Sets the maximum number of iroh-bytes requests handled concurrently on one connection.
+    ///
+    /// This bounds the resources a single peer can use independent of
+    /// [`max_concurrent_requests`]. Streams opened while this many requests of the same
+    /// connection are in flight are rejected with [`Closed::RateLimited`]. This only has an
+    /// effect if it is below [`max_concurrent_streams`], since a peer can not have more
+    /// requests in flight than open streams.
+    ///
+    /// Defaults to 10, the default stream limit, so by default no requests are rejected by
+    /// this limit.
+    ///
+    /// [`max_concurrent_requests`]: Builder::max_concurrent_requests
+    /// [`max_concurrent_streams`]: Builder::max_concurrent_streams
+    pub fn max_connection_requests(mut self, max_connection_requests: usize) -> Self {
+        self.max_connection_requests = max_connection_requests;
+        self
+    }
+
+    /// Sets the maximum number of bidirectional QUIC streams a peer can have open on one
+    /// connection.
+    ///
+    /// This is enforced by the transport. When a peer reaches the limit, opening another
+    /// stream waits until one of its streams is finished, the request is queued on the peer's
+    /// side. A peer that ignores the limit and opens more streams anyway violates the QUIC
+    /// flow control and its connection is closed with a `STREAM_LIMIT_ERROR`.
+    ///
+    /// The limit is part of the transport config of the endpoint, so it applies to all
+    /// connections of the node, including document sync and gossip connections, not only to
+    /// iroh-bytes requests.
+    ///
+    /// Compare [`max_connection_requests`], which rejects excess requests by resetting their
+    /// streams. The transfers in flight per connection can be observed with
+    /// [`Transfers::transfers_per_connection`](iroh_bytes::provider::Transfers::transfers_per_connection).
+    ///
+    /// Defaults to 10.
+    ///
+    /// [`max_connection_requests`]: Builder::max_connection_requests
+    pub fn max_concurrent_streams(mut self, max_concurrent_streams: u32) -> Self {
+        self.max_concurrent_streams = max_concurrent_streams;
+        self
+    }
+
+    /// Sets the maximum number of rpc operations that stream progress at the same time.
+    ///
+    /// Adding, downloading and validating blobs stream their progress to the client. An
+    /// operation counts against the limit until it finished and its progress was consumed
+    /// or the client went away. New operations are rejected with an abort in their progress
+    /// stream while the limit is reached, so clients that start operations and never consume
+    /// their progress can not exhaust the node.
+    ///
+    /// Defaults to 64.
+    pub fn max_progress_operations(mut self, max_progress_operations: usize) -> Self {
+        self.max_progress_operations = max_progress_operations;
+        self
+    }
+
+    /// Sets the read-ahead window for serving blobs, in bytes.
+    ///
+    /// When serving a complete blob, its data is read in reads of at least this size and
+    /// buffered, instead of one read per chunk group. This improves throughput for stores
+    /// with a high latency per read, e.g. on spinning disks or network storage, at the cost
+    /// of a buffer of this size per request.
+    ///
+    /// Defaults to 0, which disables the read-ahead.
+    pub fn read_ahead(mut self, read_ahead: usize) -> Self {
+        self.read_ahead = read_ahead;
+        self
+    }
+
+    /// Sets the memory budget for the buffers of transfers to other nodes, in bytes.
+    ///
+    /// Each transfer acquires memory for its read buffers from this budget before they are
+    /// allocated, at least one chunk group or the [read-ahead](Self::read_ahead) window per
+    /// blob that is read. Transfers wait while the budget is exhausted, so the memory used
+    /// by transfers is bounded no matter how many run at the same time.
+    ///
+    /// Defaults to 64 MiB.
+    pub fn max_transfer_memory(mut self, max_transfer_memory: usize) -> Self {
+        self.max_transfer_memory = max_transfer_memory;
+        self
+    }
+
+    /// Sets the maximum size of a blob that is downloaded from other peers.
+    ///
+    /// Downloads of blobs that peers announce to be larger fail before any storage is
+    /// allocated for them. Values set in documents through the rpc are limited to the same
+    /// size, since peers could not download larger ones. Defaults to [`DEFAULT_MAX_BLOB_SIZE`].
+    pub fn max_blob_size(mut self, max_blob_size: u64) -> Self {
+        self.max_blob_size = max_blob_size;
+        self
+    }
+
+    /// Sets whether content of document entries received from peers is downloaded automatically.
+    ///
+    /// If enabled, missing content is downloaded as soon as an entry is received. Disabled by
+    /// default, content can then be downloaded on demand with
+    /// [`crate::client::Doc::get_many_with_content`].
+    pub fn auto_download(mut self, auto_download: bool) -> Self {
+        self.auto_download = auto_download;
+        self
+    }
+
+    /// Sets how many recently received document entries are remembered to drop duplicates.
+    ///
+    /// Entries are usually relayed by several neighbors in the gossip swarm. Duplicates of
+    /// remembered entries are dropped without verifying them again. A capacity of 0 disables
+    /// this. Defaults to [`DEFAULT_GOSSIP_DEDUP_CAPACITY`].
+    pub fn gossip_dedup_capacity(mut self, capacity: usize) -> Self {
+        self.gossip_dedup_capacity = capacity;
+        self
+    }
+
+    /// Sets the part of the content of a cluster that this node holds.
+    ///
+    /// With a [`ShardPolicy`] the node only downloads the content of document entries it is
+    /// responsible for, and garbage collection does not keep the content of document entries
+    /// it is not responsible for. By default the node holds all content.
+    pub fn shard_policy(mut self, shard_policy: ShardPolicy) -> Self {
+        self.shard_policy = Some(shard_policy);
+        self
+    }
+
+    /// Sets how entries inserted into documents on this node are broadcast to peers.
+    ///
+    /// In large swarms, batching local inserts and limiting the rate of broadcasts reduces the
+    /// gossip traffic. By default every entry is broadcast on its own, right away.
+    pub fn broadcast_policy(mut self, broadcast_policy: BroadcastPolicy) -> Self {
+        self.broadcast_policy = broadcast_policy;
+        self
+    }
+
     /// Enables using DERP servers to assist in establishing connectivity.
     ///
     /// DERP servers are used to discover other nodes by [`PublicKey`] and also help
@@ -300,14 +580,50 @@ where
         }
     }
 
+    /// Configures how peers for documents are discovered.
+    ///
+    /// By default documents are only synced with peers that are passed in explicitly.
+    pub fn discovery(self, discovery: Arc<dyn Discovery>) -> Self {
+        Self { discovery, ..self }
+    }
+
     /// Binds the node service to a different socket.
     ///
-    /// By default it binds to `127.0.0.1:11204`.
+    /// By default it binds to all interfaces on port `11204`.  A specific IP address
+    /// restricts the node to that address, an IPv6 address also moves the IPv4 socket
+    /// to loopback.
     pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
         self.bind_addr = addr;
         self
     }
 
+    /// Uses already bound UDP sockets instead of binding to the [`bind_addr`].
+    ///
+    /// Takes one IPv4 socket and optionally one IPv6 socket. The sockets are kept for the
+    /// lifetime of the node and never re-bound.
+    ///
+    /// [`bind_addr`]: Builder::bind_addr
+    pub fn sockets(mut self, sockets: Vec<std::net::UdpSocket>) -> Self {
+        self.sockets = sockets;
+        self
+    }
+
+    /// Uses the UDP sockets passed in by systemd socket activation, if there are any.
+    ///
+    /// This lets systemd start the node on demand and keep its port across restarts. See
+    /// [`iroh_net::net::socket_activation`] for the file descriptor passing contract. When the
+    /// process was not socket activated, the node binds to the [`bind_addr`] as usual.
+    ///
+    /// [`bind_addr`]: Builder::bind_addr
+    #[cfg(all(unix, feature = "socket-activation"))]
+    pub fn socket_activation(self) -> Result<Self> {
+        let sockets = iroh_net::net::socket_activation::listen_udp_sockets()?;
+        if sockets.is_empty() {
+            return Ok(self);
+        }
+        Ok(self.sockets(sockets))
+    }
+
     /// Uses the given [`SecretKey`] for the [`PublicKey`] instead of a newly generated one.
     pub fn secret_key(mut self, secret_key: SecretKey) -> Self {
         self.secret_key = secret_key;
@@ -324,12 +640,48 @@ where
         self
     }
 
+    /// Whether to allow peers to migrate their connections to a new network path.
+    ///
+    /// This keeps transfers to roaming peers alive when their address changes, instead of
+    /// restarting them. Enabled by default.
+    ///
+    /// See [`MagicEndpointBuilder::migration`](iroh_net::magic_endpoint::MagicEndpointBuilder::migration).
+    pub fn migration(mut self, migration: bool) -> Self {
+        self.migration = migration;
+        self
+    }
+
     /// Set the path where known peer data is loaded on start-up and later persisted.
     pub fn peers_data_path(mut self, path: PathBuf) -> Self {
         self.peers_data_path = Some(path);
         self
     }
 
+    /// Set the path where the serve stats of the blobs are loaded on start-up and later
+    /// persisted.
+    ///
+    /// The stats are saved periodically while they change, and when the node shuts down.
+    /// See [`Node::serve_stats`].
+    pub fn serve_stats_path(mut self, path: PathBuf) -> Self {
+        self.serve_stats_path = Some(path);
+        self
+    }
+
+    /// Shuts the node down once it was idle for `timeout`, or never with `None`.
+    ///
+    /// The node is idle while no RPC requests are handled, no connections from peers,
+    /// including transfers of blobs, are open, and no downloads, syncs with peers or joins
+    /// of gossip swarms that this node started are in progress. Joining a gossip swarm only
+    /// counts while its peers are dialed, not while waiting for peers that can not be
+    /// reached. Any activity restarts the timeout. The
+    /// shutdown is the same as with [`Node::shutdown`]. This is meant for short lived nodes,
+    /// e.g. of command line tools, that should not be left running in the background. By
+    /// default the node never shuts down on its own. See [`Node::idle_remaining`].
+    pub fn idle_shutdown(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_shutdown = timeout;
+        self
+    }
+
     /// Sets the tokio runtime to use.
     ///
     /// If not set, the current runtime will be picked up.
@@ -364,9 +716,10 @@ where
         crate::metrics::try_init_metrics_collection().ok();
 
         let (endpoints_update_s, endpoints_update_r) = flume::bounded(1);
+        let (derp_status_s, derp_status_r) = flume::unbounded();
         let mut transport_config = quinn::TransportConfig::default();
         transport_config
-            .max_concurrent_bidi_streams(MAX_STREAMS.try_into()?)
+            .max_concurrent_bidi_streams(self.max_concurrent_streams.into())
             .max_concurrent_uni_streams(0u32.into());
 
         let endpoint = MagicEndpoint::builder()
@@ -375,10 +728,14 @@ where
             .keylog(self.keylog)
             .transport_config(transport_config)
             .concurrent_connections(MAX_CONNECTIONS)
+            .migration(self.migration)
             .on_endpoints(Box::new(move |eps| {
                 if !eps.is_empty() {
                     endpoints_update_s.send(eps.to_vec()).ok();
                 }
+            }))
+            .on_derp_status(Box::new(move |status| {
+                derp_status_s.send(status).ok();
             }));
         let endpoint = match self.peers_data_path {
             Some(path) => endpoint.peers_data_path(path),
@@ -388,6 +745,13 @@ where
             Some(derp_map) => endpoint.enable_derp(derp_map),
             None => endpoint,
         };
+        let endpoint = if !self.sockets.is_empty() {
+            endpoint.sockets(self.sockets)
+        } else if self.bind_addr.ip().is_unspecified() {
+            endpoint
+        } else {
+            endpoint.bind_addrs(vec![self.bind_addr])
+        };
         let endpoint = endpoint.bind(self.bind_addr.port()).await?;
         trace!("created quinn endpoint");
 
@@ -399,12 +763,19 @@ where
         // initialize the gossip protocol
         let gossip = Gossip::from_endpoint(endpoint.clone(), Default::default());
 
+        let idle = self
+            .idle_shutdown
+            .map(|timeout| Arc::new(IdleTimer::new(timeout)));
+
         // spawn the sync engine
+        let connections = Connections::default();
         let downloader = Downloader::new(
             self.db.clone(),
             self.collection_parser.clone(),
+            self.max_blob_size,
             endpoint.clone(),
             rt.clone(),
+            connections.clone(),
         )
         .await;
         let ds = self.docs.clone();
@@ -415,15 +786,55 @@ where
             self.docs,
             self.db.clone(),
             downloader,
+            self.auto_download,
+            self.discovery,
+            self.gossip_dedup_capacity,
+            self.shard_policy.clone(),
+            self.broadcast_policy,
+            idle.clone(),
         );
 
+        let retention_task = {
+            let ds = ds.clone();
+            let task = rt.main().spawn(async move {
+                let start = tokio::time::Instant::now() + RETENTION_SWEEP_INTERVAL;
+                let mut ticker = tokio::time::interval_at(start, RETENTION_SWEEP_INTERVAL);
+                loop {
+                    ticker.tick().await;
+                    // pruning scans all entries of the docs, keep it off the async runtime
+                    let ds = ds.clone();
+                    match tokio::task::spawn_blocking(move || prune_expired_docs(&ds)).await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => warn!("failed to prune expired doc entries: {err:?}"),
+                        Err(err) => warn!("pruning expired doc entries panicked: {err:?}"),
+                    }
+                }
+            });
+            AbortingJoinHandle(task)
+        };
+        let serve_stats = match &self.serve_stats_path {
+            Some(path) => ServeStats::load(path)?,
+            None => ServeStats::default(),
+        };
+        let touched = TouchedBlobs::new(self.gc_touch_ttl);
+        let gc_roots = GcRoots {
+            ds: ds.clone(),
+            shard: self
+                .shard_policy
+                .clone()
+                .map(|policy| (policy, self.secret_key.public())),
+            serve_stats: serve_stats.clone(),
+            keep_most_served: self.gc_keep_most_served,
+            touched: touched.clone(),
+        };
         let gc_task = if let GcPolicy::Interval(gc_period) = self.gc_policy {
             tracing::info!("Starting GC task with interval {}s", gc_period.as_secs());
             let db = self.db.clone();
             let cp = self.collection_parser.clone();
+            let roots = gc_roots.clone();
             let task = rt
                 .local_pool()
-                .spawn_pinned(move || Self::gc_loop(db, ds, cp, gc_period));
+                .spawn_pinned(move || Self::gc_loop(db, cp, gc_period, roots));
             Some(AbortingJoinHandle(task))
         } else {
             None
@@ -432,6 +843,45 @@ where
         let rt2 = rt.clone();
         let rt3 = rt.clone();
         let callbacks = Callbacks::default();
+        let events = Arc::new(EventLog::new(EVENT_LOG_CAPACITY));
+        {
+            let events = events.clone();
+            callbacks
+                .push(Box::new(move |event| {
+                    events.push(event);
+                    async {}.boxed()
+                }))
+                .await;
+        }
+        // forward the changes of the DERP status, until the endpoint is closed
+        {
+            let callbacks = callbacks.clone();
+            rt.main().spawn(async move {
+                while let Ok(status) = derp_status_r.recv_async().await {
+                    callbacks.send(Event::DerpStatus(status)).await;
+                }
+            });
+        }
+        {
+            let serve_stats = serve_stats.clone();
+            callbacks
+                .push(Box::new(move |event| {
+                    if let Event::ByteProvide(iroh_bytes::provider::Event::BlobServed {
+                        hash,
+                        bytes_sent,
+                        ..
+                    }) = event
+                    {
+                        serve_stats.record(hash, bytes_sent);
+                    }
+                    async {}.boxed()
+                }))
+                .await;
+        }
+        // the node task finishes once the serve stats were saved a last time
+        let save_serve_stats = self.serve_stats_path.map(|path| {
+            Self::save_serve_stats_loop(serve_stats.clone(), path, cancel_token.clone())
+        });
         let inner = Arc::new(NodeInner {
             db: self.db,
             endpoint: endpoint.clone(),
@@ -441,8 +891,24 @@ where
             callbacks: callbacks.clone(),
             cb_sender,
             gc_task,
+            retention_task,
             rt: rt.clone(),
+            request_limit: Arc::new(Semaphore::new(self.max_concurrent_requests)),
+            max_connection_requests: self.max_connection_requests,
+            max_concurrent_streams: self.max_concurrent_streams,
+            progress_limit: ProgressLimit::new(self.max_progress_operations),
+            read_ahead: self.read_ahead,
+            transfer_memory: TransferMemory::new(self.max_transfer_memory),
+            transfers: Transfers::new(),
+            max_blob_size: self.max_blob_size,
+            serve_stats,
+            touched,
+            gc_roots,
+            events,
             sync,
+            idle,
+            auth_handler: self.auth_handler.clone(),
+            connections,
         });
         let task = {
             let gossip = gossip.clone();
@@ -451,7 +917,7 @@ where
                 collection_parser: self.collection_parser.clone(),
             };
             rt2.main().spawn(async move {
-                Self::run(
+                let run = Self::run(
                     endpoint,
                     callbacks,
                     cb_receiver,
@@ -463,8 +929,13 @@ where
                     self.collection_parser,
                     rt3,
                     gossip,
-                )
-                .await
+                );
+                match save_serve_stats {
+                    Some(save) => {
+                        futures::future::join(run, save).await;
+                    }
+                    None => run.await,
+                }
             })
         };
         let node = Node {
@@ -492,6 +963,12 @@ where
             .await
             .context("waiting for endpoint")??;
 
+        // the idle timeout starts once the node is ready
+        if let Some(idle) = &node.inner.idle {
+            rt.main()
+                .spawn(idle.clone().run(node.inner.cancel_token.clone()));
+        }
+
         Ok(node)
     }
 
@@ -573,7 +1050,9 @@ where
                     let custom_get_handler = custom_get_handler.clone();
                     let auth_handler = auth_handler.clone();
                     let sync = handler.inner.sync.clone();
+                    let activity = handler.inner.idle.as_ref().map(|idle| idle.activity());
                     rt.main().spawn(async move {
+                        let _activity = activity;
                         if let Err(err) = handle_connection(connecting, alpn, inner, gossip, sync, collection_parser, custom_get_handler, auth_handler).await {
                             warn!("Handling incoming connection ended with error: {err}");
                         }
@@ -598,35 +1077,43 @@ where
             .ok();
     }
 
-    async fn gc_loop(db: D, ds: S, cp: C, gc_period: Duration) {
+    /// Saves `serve_stats` to `path` periodically, and a last time once the node is cancelled.
+    async fn save_serve_stats_loop(
+        serve_stats: ServeStats,
+        path: PathBuf,
+        cancel_token: CancellationToken,
+    ) {
+        let start = tokio::time::Instant::now() + SERVE_STATS_SAVE_INTERVAL;
+        let mut ticker = tokio::time::interval_at(start, SERVE_STATS_SAVE_INTERVAL);
+        loop {
+            let done = tokio::select! {
+                biased;
+                _ = cancel_token.cancelled() => true,
+                _ = ticker.tick() => false,
+            };
+            if let Err(err) = serve_stats.save(&path).await {
+                warn!("failed to save serve stats: {err:?}");
+            }
+            if done {
+                break;
+            }
+        }
+    }
+
+    async fn gc_loop(db: D, cp: C, gc_period: Duration, roots: GcRoots<S>) {
         'outer: loop {
             // do delay before the two phases of GC
             tokio::time::sleep(gc_period).await;
             db.clear_live();
-            let doc_hashes = match ds.content_hashes() {
+            // the live set was cleared, so the roots have to be added again on every run
+            let hashes = match roots.hashes() {
                 Ok(hashes) => hashes,
                 Err(err) => {
-                    tracing::error!("Error getting doc hashes: {}", err);
+                    tracing::error!("Error getting gc roots, skipping GC to be safe: {}", err);
                     continue 'outer;
                 }
             };
-            let mut doc_db_error = false;
-            let doc_hashes = doc_hashes.filter_map(|e| {
-                let hash = match e {
-                    Ok(e) => e,
-                    Err(err) => {
-                        tracing::error!("Error getting doc hash: {}", err);
-                        doc_db_error = true;
-                        return None;
-                    }
-                };
-                Some(hash)
-            });
-            db.add_live(doc_hashes);
-            if doc_db_error {
-                tracing::error!("Error getting doc hashes, skipping GC to be safe");
-                continue 'outer;
-            }
+            db.add_live(hashes);
 
             tracing::info!("Starting GC mark phase");
             let mut stream = db.gc_mark(cp.clone(), None);
@@ -660,8 +1147,63 @@ where
                     }
                 }
             }
+            // forget the serve counts of the removed blobs
+            roots
+                .serve_stats
+                .retain(|hash| db.contains(hash) != EntryStatus::NotFound);
+        }
+    }
+}
+
+/// The blobs that are kept by the gc in addition to the tagged and pinned ones.
+#[derive(Debug, Clone)]
+struct GcRoots<S> {
+    ds: S,
+    /// The shard policy and our own id, if content is sharded across a cluster.
+    shard: Option<(ShardPolicy, PublicKey)>,
+    serve_stats: ServeStats,
+    /// The number of most served blobs to keep, see [`Builder::gc_keep_most_served`].
+    keep_most_served: usize,
+    touched: TouchedBlobs,
+}
+
+impl<S: DocStore> GcRoots<S> {
+    /// The hashes of the roots: the content of document entries, the most served blobs and
+    /// the recently touched blobs.
+    ///
+    /// The content of document entries that another node of the cluster is responsible for is
+    /// not kept.
+    fn hashes(&self) -> Result<Vec<Hash>> {
+        let mut hashes = Vec::new();
+        for hash in self.ds.content_hashes()? {
+            let hash = hash?;
+            if self
+                .shard
+                .as_ref()
+                .map_or(true, |(policy, me)| policy.responsible_for(&hash, me))
+            {
+                hashes.push(hash);
+            }
+        }
+        if self.keep_most_served > 0 {
+            let most_served = self.serve_stats.top(self.keep_most_served);
+            hashes.extend(most_served.into_iter().map(|(hash, _)| hash));
+        }
+        hashes.extend(self.touched.live());
+        Ok(hashes)
+    }
+}
+
+/// Prunes the expired entries of all docs that have a retention.
+fn prune_expired_docs<S: DocStore>(ds: &S) -> Result<()> {
+    let namespaces = ds.list_namespaces()?.collect::<Result<Vec<_>>>()?;
+    for namespace in namespaces {
+        let pruned = ds.prune_expired(&namespace)?;
+        if pruned > 0 {
+            debug!("pruned {pruned} expired entries from doc {namespace}");
         }
     }
+    Ok(())
 }
 
 // TODO: Restructure this code to not take all these arguments.
@@ -677,17 +1219,35 @@ async fn handle_connection<D: BaoStore, S: DocStore, C: CollectionParser>(
     auth_handler: Arc<dyn RequestAuthorizationHandler>,
 ) -> Result<()> {
     match alpn.as_bytes() {
-        GOSSIP_ALPN => gossip.handle_connection(connecting.await?).await?,
+        GOSSIP_ALPN => {
+            let connection = connecting.await?;
+            let guard = track_incoming(&node.connections, GOSSIP_ALPN, &connection).await;
+            gossip.handle_connection(connection.clone()).await?;
+            // gossip keeps using the connection in its actor, track it until it is closed
+            // without holding on to this task, which counts as activity of the node
+            node.rt.main().spawn(async move {
+                let _guard = guard;
+                connection.closed().await;
+            });
+        }
         SYNC_ALPN => sync.handle_connection(connecting).await?,
         alpn if alpn == iroh_bytes::protocol::ALPN => {
-            iroh_bytes::provider::handle_connection(
-                connecting,
+            let connection = connecting.await?;
+            let _guard = track_incoming(&node.connections, alpn, &connection).await;
+            iroh_bytes::provider::handle_established_connection(
+                connection,
                 node.db.clone(),
                 node.callbacks.clone(),
                 collection_parser,
                 custom_get_handler,
                 auth_handler,
                 node.rt.clone(),
+                node.request_limit.clone(),
+                node.max_connection_requests,
+                node.read_ahead,
+                node.transfer_memory.clone(),
+                node.transfers.clone(),
+                node.cancel_token.child_token(),
             )
             .await
         }
@@ -696,6 +1256,57 @@ async fn handle_connection<D: BaoStore, S: DocStore, C: CollectionParser>(
     Ok(())
 }
 
+/// Lists the children of the collection `hash` with their sizes in `db`.
+///
+/// Collections in the iroh collection format are listed with the names of their children.
+/// Any other collection is walked with `collection_parser` and its children are named by
+/// their index.
+async fn collection_listing<D: BaoStore, C: CollectionParser>(
+    db: D,
+    collection_parser: C,
+    hash: Hash,
+) -> Result<Vec<CollectionListingEntry>> {
+    let entry = |name: String, hash: Hash| CollectionListingEntry {
+        name,
+        hash: hash.to_string(),
+        size: db.get(&hash).map(|entry| entry.size()),
+    };
+    #[cfg(feature = "iroh-collection")]
+    if let Ok(collection) = crate::collection::Collection::load(&db, &hash).await {
+        return Ok(collection
+            .into_inner()
+            .into_iter()
+            .map(|blob| entry(blob.name, blob.hash))
+            .collect());
+    }
+    let reader = db
+        .get(&hash)
+        .context("collection not found")?
+        .data_reader()
+        .await?;
+    let (mut links, _stats) = collection_parser.parse(reader).await?;
+    let mut entries = Vec::new();
+    while let Some(hash) = links.next().await? {
+        entries.push(entry(entries.len().to_string(), hash));
+    }
+    Ok(entries)
+}
+
+/// Tracks an incoming connection in `connections`, if the peer id of the remote is known.
+async fn track_incoming(
+    connections: &Connections,
+    alpn: &[u8],
+    connection: &quinn::Connection,
+) -> Option<TrackGuard> {
+    match get_peer_id(connection).await {
+        Ok(peer) => Some(connections.track(peer, alpn, ConnectionDirection::Incoming, connection)),
+        Err(err) => {
+            debug!("not tracking connection without peer id: {err:#}");
+            None
+        }
+    }
+}
+
 type EventCallback = Box<dyn Fn(Event) -> BoxFuture<'static, ()> + 'static + Sync + Send>;
 
 #[derive(Default, derive_more::Debug, Clone)]
@@ -706,7 +1317,6 @@ impl Callbacks {
         self.0.write().await.push(cb);
     }
 
-    #[allow(dead_code)]
     async fn send(&self, event: Event) {
         let cbs = self.0.read().await;
         for cb in &*cbs {
@@ -756,15 +1366,163 @@ struct NodeInner<D, S: DocStore> {
     callbacks: Callbacks,
     #[allow(dead_code)]
     gc_task: Option<AbortingJoinHandle<()>>,
+    #[allow(dead_code)]
+    retention_task: AbortingJoinHandle<()>,
     rt: runtime::Handle,
+    request_limit: Arc<Semaphore>,
+    /// Limit on the requests handled concurrently per connection.
+    max_connection_requests: usize,
+    /// Limit on the open QUIC streams per connection.
+    max_concurrent_streams: u32,
+    progress_limit: ProgressLimit,
+    read_ahead: usize,
+    transfer_memory: TransferMemory,
+    transfers: Transfers,
+    max_blob_size: u64,
+    serve_stats: ServeStats,
+    touched: TouchedBlobs,
+    gc_roots: GcRoots<S>,
+    events: Arc<EventLog>,
     pub(crate) sync: SyncEngine<S>,
+    idle: Option<Arc<IdleTimer>>,
+    auth_handler: Arc<dyn RequestAuthorizationHandler>,
+    connections: Connections,
+}
+
+/// Limits the number of rpc operations that stream progress, see
+/// [`Builder::max_progress_operations`].
+#[derive(Debug)]
+struct ProgressLimit {
+    permits: Arc<Semaphore>,
+    max: usize,
+}
+
+impl ProgressLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max)),
+            max,
+        }
+    }
+
+    /// Admit a new operation, which counts against the limit until the permit is dropped.
+    fn acquire(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().map_err(|_| {
+            anyhow::anyhow!(
+                "too many operations with progress, at most {} can run at the same time",
+                self.max
+            )
+        })
+    }
+
+    /// The number of running operations.
+    fn in_use(&self) -> usize {
+        self.max - self.permits.available_permits()
+    }
+}
+
+/// The most recent node events, numbered by sequence, see [`NodeEventsRequest`].
+#[derive(Debug)]
+struct EventLog {
+    /// Random id of this log, so consumers can tell when the sequence numbers start over.
+    instance: u64,
+    capacity: usize,
+    state: std::sync::Mutex<EventLogState>,
+    notify: tokio::sync::Notify,
+}
+
+#[derive(Debug, Default)]
+struct EventLogState {
+    /// Sequence number of the next event.
+    next_seq: u64,
+    /// The buffered events, the last one has sequence number `next_seq - 1`.
+    events: VecDeque<Event>,
+}
+
+impl EventLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            instance: rand::random(),
+            capacity,
+            state: Default::default(),
+            notify: Default::default(),
+        }
+    }
+
+    fn push(&self, event: Event) {
+        let mut state = self.state.lock().unwrap();
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+        state.next_seq += 1;
+        drop(state);
+        self.notify.notify_waiters();
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.state.lock().unwrap().next_seq
+    }
+
+    /// The events from sequence number `since` on, and the sequence number to continue from.
+    ///
+    /// Starts with a gap if some of the events are no longer buffered.
+    fn read(&self, since: u64) -> (Vec<NodeEventsResponse>, u64) {
+        let state = self.state.lock().unwrap();
+        let first = state.next_seq - state.events.len() as u64;
+        let since = if since > state.next_seq { first } else { since };
+        let mut res = Vec::new();
+        if since < first {
+            res.push(NodeEventsResponse::Gap {
+                instance: self.instance,
+                from: since,
+                to: first,
+            });
+        }
+        let skip = since.saturating_sub(first) as usize;
+        res.extend(
+            state
+                .events
+                .iter()
+                .enumerate()
+                .skip(skip)
+                .map(|(i, event)| NodeEventsResponse::Event {
+                    instance: self.instance,
+                    seq: first + i as u64,
+                    event: event.clone(),
+                }),
+        );
+        (res, state.next_seq)
+    }
+
+    /// Streams the events from sequence number `since` on, and then new events as they come.
+    fn subscribe(self: Arc<Self>, since: u64) -> impl Stream<Item = NodeEventsResponse> {
+        futures::stream::unfold((self, since), |(log, since)| async move {
+            loop {
+                // created before reading, so no event pushed in between is missed
+                let notified = log.notify.notified();
+                let (events, next) = log.read(since);
+                if !events.is_empty() {
+                    drop(notified);
+                    return Some((futures::stream::iter(events), (log, next)));
+                }
+                notified.await;
+            }
+        })
+        .flatten()
+    }
 }
 
 /// Events emitted by the [`Node`] informing about the current status.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Event {
     /// Events from the iroh-bytes transfer protocol.
     ByteProvide(iroh_bytes::provider::Event),
+    /// The connection to the home DERP region changed.
+    ///
+    /// While the home DERP region is unavailable, peers that can not connect directly can not
+    /// reach this node.
+    DerpStatus(DerpStatus),
 }
 
 impl<D: ReadableStore, S: DocStore> Node<D, S> {
@@ -811,6 +1569,38 @@ impl<D: ReadableStore, S: DocStore> Node<D, S> {
         Ok(())
     }
 
+    /// Calls `cb` every `interval` with the roots of the store, so they can be re-announced
+    /// to an external content discovery system.
+    ///
+    /// The roots are the tagged hashes of the store, see [`ReadableStore::tags`], so this
+    /// does not scan the blobs. The first call happens one `interval` after this is called,
+    /// and calls stop when the node shuts down.
+    ///
+    /// Ticks are skipped while the callback is still running.
+    pub fn on_reprovide_tick<F>(&self, interval: Duration, cb: F)
+    where
+        F: Fn(Vec<HashAndFormat>) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+    {
+        let db = self.inner.db.clone();
+        let cancel_token = self.inner.cancel_token.clone();
+        self.inner.rt.main().spawn(async move {
+            let start = tokio::time::Instant::now() + interval;
+            let mut ticker = tokio::time::interval_at(start, interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = cancel_token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        let roots: BTreeSet<_> = db.tags().map(|(_, root)| root).collect();
+                        debug!("reprovide tick with {} roots", roots.len());
+                        cb(roots.into_iter().collect()).await;
+                    }
+                }
+            }
+        });
+    }
+
     /// Returns a handle that can be used to do RPC calls to the node internally.
     pub fn controller(&self) -> crate::client::mem::RpcClient {
         RpcClient::new(self.inner.controller.clone())
@@ -831,6 +1621,26 @@ impl<D: ReadableStore, S: DocStore> Node<D, S> {
         Ticket::new(me, hash, format, None)
     }
 
+    /// Return a ticket for a collection together with a manifest of its children.
+    ///
+    /// The collection must be stored on this node. Sizes of children which are not
+    /// stored on this node are omitted from the manifest.
+    #[cfg(feature = "iroh-collection")]
+    pub async fn share_collection(&self, root: Hash) -> Result<crate::collection::ShareBundle> {
+        let collection = crate::collection::Collection::load(&self.inner.db, &root).await?;
+        let manifest = collection
+            .into_inner()
+            .into_iter()
+            .map(|blob| crate::collection::ManifestEntry {
+                size: self.inner.db.get(&blob.hash).map(|entry| entry.size()),
+                name: blob.name,
+                hash: blob.hash,
+            })
+            .collect();
+        let ticket = self.ticket(root, BlobFormat::COLLECTION).await?;
+        Ok(crate::collection::ShareBundle { ticket, manifest })
+    }
+
     /// Return the [`PeerAddr`] for this node.
     pub async fn my_addr(&self) -> Result<PeerAddr> {
         self.inner.endpoint.my_addr().await
@@ -852,42 +1662,174 @@ impl<D: ReadableStore, S: DocStore> Node<D, S> {
         self.inner.cancel_token.cancel();
     }
 
+    /// The time left until the node shuts down because it is idle.
+    ///
+    /// Returns `None` if idle shutdown is disabled, see [`Builder::idle_shutdown`]. While RPC
+    /// requests or connections are in progress, this is the full idle timeout.
+    pub fn idle_remaining(&self) -> Option<Duration> {
+        self.inner.idle.as_ref().map(|idle| idle.remaining())
+    }
+
     /// Returns a token that can be used to cancel the node.
     pub fn cancel_token(&self) -> CancellationToken {
         self.inner.cancel_token.clone()
     }
-}
 
-impl<D: Map, S: DocStore> NodeInner<D, S> {
-    async fn local_endpoints(&self) -> Result<Vec<Endpoint>> {
-        self.endpoint.local_endpoints().await
+    /// Returns the counters of how often the blobs of this node were served.
+    pub fn serve_stats(&self) -> &ServeStats {
+        &self.inner.serve_stats
     }
 
-    async fn local_endpoint_addresses(&self) -> Result<Vec<SocketAddr>> {
-        let endpoints = self.local_endpoints().await?;
-        Ok(endpoints.into_iter().map(|x| x.addr).collect())
+    /// Returns the connections of this node that are currently in use, by peer.
+    ///
+    /// See [`Connections::get`] for the QUIC statistics of the connections to a peer.
+    pub fn connections(&self) -> &Connections {
+        &self.inner.connections
     }
 
-    fn local_address(&self) -> Result<Vec<SocketAddr>> {
-        let (v4, v6) = self.endpoint.local_addr()?;
-        let mut addrs = vec![v4];
-        if let Some(v6) = v6 {
-            addrs.push(v6);
-        }
-        Ok(addrs)
+    /// Resolves an alias set with [`Node::set_alias`].
+    pub fn resolve_alias(&self, name: &str) -> Option<HashAndFormat> {
+        resolve_alias(&self.inner.db, name)
     }
-}
 
-/// The future completes when the spawned tokio task finishes.
-impl<D: Map, S: DocStore> Future for Node<D, S> {
-    type Output = Result<(), Arc<JoinError>>;
+    /// Lists all aliases with their targets.
+    ///
+    /// These are the tags of the store in the alias namespace, see [`Node::set_alias`].
+    pub fn list_aliases(&self) -> Vec<(String, HashAndFormat)> {
+        self.inner
+            .db
+            .tags()
+            .filter_map(|(tag, value)| {
+                let name = tag.0.strip_prefix(ALIAS_TAG_PREFIX)?;
+                Some((String::from_utf8(name.to_vec()).ok()?, value))
+            })
+            .collect()
+    }
+}
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
-        Pin::new(&mut self.task).poll(cx)
+impl<D: BaoStore, S: DocStore> Node<D, S> {
+    /// Points the alias `name` to `value`, replacing its previous target.
+    ///
+    /// Aliases give content a human friendly name, e.g. `latest.tar.gz`, without the need
+    /// for a document. They are stored as tags of the store named `alias/<name>`, so they are
+    /// persisted, protect their target from garbage collection and can be removed by deleting
+    /// the tag. Other tags are never resolved as aliases. Peers can get the content of an alias
+    /// with a custom get request if the node uses an [`AliasGetHandler`].
+    pub async fn set_alias(&self, name: impl AsRef<str>, value: HashAndFormat) -> Result<()> {
+        self.inner
+            .db
+            .set_tag(alias_tag(name.as_ref()), Some(value))
+            .await?;
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+/// The prefix of the tags that store aliases, see [`Node::set_alias`].
+const ALIAS_TAG_PREFIX: &[u8] = b"alias/";
+
+/// The tag that stores the alias `name`.
+fn alias_tag(name: &str) -> Tag {
+    let mut tag = ALIAS_TAG_PREFIX.to_vec();
+    tag.extend_from_slice(name.as_bytes());
+    Tag(tag.into())
+}
+
+/// Finds the target of the alias `name` in the alias tags of `db`.
+fn resolve_alias<D: ReadableStore>(db: &D, name: &str) -> Option<HashAndFormat> {
+    let tag = alias_tag(name);
+    db.tags().find(|(t, _)| t == &tag).map(|(_, value)| value)
+}
+
+/// Reads back the just imported blob `hash` from `db` and checks it against its hash.
+async fn verify_import<D: BaoStore>(db: &D, hash: Hash) -> Result<()> {
+    let entry = db.get(&hash).context("imported blob not found")?;
+    iroh_bytes::baomap::validate_bao(&entry)
+        .await
+        .with_context(|| format!("verification of imported blob {hash} failed"))
+}
+
+/// Validates `db` and removes the blobs that fail validation.
+///
+/// The progress of the validation is forwarded to `tx`. The invalid blobs are removed before
+/// the final [`ValidateProgress::AllDone`] is sent.
+async fn validate_and_repair<D: BaoStore>(
+    db: &D,
+    concurrency: usize,
+    tx: mpsc::Sender<ValidateProgress>,
+) -> Result<()> {
+    let (validate_tx, mut validate_rx) = mpsc::channel(16);
+    let forward = {
+        let tx = tx.clone();
+        async move {
+            let mut hashes = BTreeMap::new();
+            let mut invalid = Vec::new();
+            let mut all_done = false;
+            while let Some(msg) = validate_rx.recv().await {
+                match &msg {
+                    ValidateProgress::Entry { id, hash, .. } => {
+                        hashes.insert(*id, *hash);
+                    }
+                    ValidateProgress::Done { id, error: Some(_) } => {
+                        invalid.extend(hashes.get(id).copied())
+                    }
+                    ValidateProgress::AllDone => {
+                        all_done = true;
+                        continue;
+                    }
+                    _ => {}
+                }
+                tx.send(msg).await?;
+            }
+            anyhow::Ok((invalid, all_done))
+        }
+    };
+    let (res, forwarded) = futures::future::join(
+        db.validate_with_concurrency(concurrency, validate_tx),
+        forward,
+    )
+    .await;
+    res?;
+    let (invalid, all_done) = forwarded?;
+    for hash in invalid {
+        db.delete(&hash).await?;
+        info!("removed invalid blob {hash}");
+    }
+    if all_done {
+        tx.send(ValidateProgress::AllDone).await?;
+    }
+    Ok(())
+}
+
+impl<D: Map, S: DocStore> NodeInner<D, S> {
+    async fn local_endpoints(&self) -> Result<Vec<Endpoint>> {
+        self.endpoint.local_endpoints().await
+    }
+
+    async fn local_endpoint_addresses(&self) -> Result<Vec<SocketAddr>> {
+        let endpoints = self.local_endpoints().await?;
+        Ok(endpoints.into_iter().map(|x| x.addr).collect())
+    }
+
+    fn local_address(&self) -> Result<Vec<SocketAddr>> {
+        let (v4, v6) = self.endpoint.local_addr()?;
+        let mut addrs = vec![v4];
+        if let Some(v6) = v6 {
+            addrs.push(v6);
+        }
+        Ok(addrs)
+    }
+}
+
+/// The future completes when the spawned tokio task finishes.
+impl<D: Map, S: DocStore> Future for Node<D, S> {
+    type Output = Result<(), Arc<JoinError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task).poll(cx)
+    }
+}
+
+#[derive(Debug, Clone)]
 struct RpcHandler<D, S: DocStore, C> {
     inner: Arc<NodeInner<D, S>>,
     collection_parser: C,
@@ -905,14 +1847,21 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         use bao_tree::io::fsm::Outboard;
 
         let db = self.inner.db.clone();
-        futures::stream::iter(db.blobs()).filter_map(move |hash| {
+        let hashes = db.blobs().chain(db.partial_blobs());
+        futures::stream::iter(hashes).filter_map(move |hash| {
             let db = db.clone();
             async move {
                 let entry = db.get(&hash)?;
                 let hash = entry.hash().into();
                 let size = entry.outboard().await.ok()?.tree().size().0;
+                let available = entry.available_ranges().await.ok().map(RangeSpec::new);
                 let path = "".to_owned();
-                Some(BlobListResponse { hash, size, path })
+                Some(BlobListResponse {
+                    hash,
+                    size,
+                    path,
+                    available,
+                })
             }
         })
     }
@@ -942,6 +1891,51 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         })
     }
 
+    fn blob_list_unreferenced(
+        self,
+        _msg: BlobListUnreferencedRequest,
+    ) -> impl Stream<Item = RpcResult<BlobListUnreferencedResponse>> + Send + 'static {
+        let (tx, rx) = flume::bounded(32);
+        let local = self.inner.rt.local_pool().clone();
+        // gc traversal is not Send
+        local.spawn_pinned(move || async move {
+            if let Err(err) = self.blob_list_unreferenced0(&tx).await {
+                tx.send_async(Err(err.into())).await.ok();
+            }
+        });
+        rx.into_stream()
+    }
+
+    async fn blob_list_unreferenced0(
+        self,
+        tx: &flume::Sender<RpcResult<BlobListUnreferencedResponse>>,
+    ) -> anyhow::Result<()> {
+        let db = &self.inner.db;
+        // the gc adds these to the live set, here they are passed as extra roots
+        let roots = self
+            .inner
+            .gc_roots
+            .hashes()?
+            .into_iter()
+            .map(|hash| Ok(HashAndFormat(hash, BlobFormat::RAW)));
+        let hashes = db
+            .unreferenced_blobs(self.collection_parser.clone(), roots)
+            .await?;
+        for hash in hashes {
+            let Some(entry) = db.get(&hash) else {
+                continue;
+            };
+            let res = BlobListUnreferencedResponse {
+                hash,
+                size: entry.size(),
+            };
+            if tx.send_async(Ok(res)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn blob_list_collections(
         self,
         _msg: BlobListCollectionsRequest,
@@ -959,9 +1953,13 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
                 }
                 let entry = db.get(&hash)?;
                 let stats = local
-                    .spawn_pinned(|| async move {
+                    .spawn_pinned(move || async move {
                         let reader = entry.data_reader().await.ok()?;
-                        let (_collection, stats) = cp.parse(reader).await.ok()?;
+                        let (_collection, mut stats) = cp.parse(reader).await.ok()?;
+                        // not all collection formats record the size of their children
+                        if stats.total_blob_size.is_none() {
+                            stats.total_blob_size = db.collection_total_size(cp, hash).await;
+                        }
                         Some(stats)
                     })
                     .await
@@ -976,6 +1974,23 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         })
     }
 
+    async fn blob_collection_listing(
+        self,
+        msg: CollectionListingRequest,
+    ) -> RpcResult<CollectionListingResponse> {
+        let CollectionListingRequest { hash, format } = msg;
+        let db = self.inner.db.clone();
+        let cp = self.collection_parser.clone();
+        let entries = self
+            .rt()
+            .local_pool()
+            .spawn_pinned(move || collection_listing(db, cp, hash))
+            .await
+            .map_err(|_| anyhow!("collection listing task failed"))??;
+        let listing = format.encode(&entries)?;
+        Ok(CollectionListingResponse { listing })
+    }
+
     async fn blob_delete_tag(self, msg: DeleteTagRequest) -> RpcResult<()> {
         self.inner.db.set_tag(msg.name, None).await?;
         Ok(())
@@ -983,9 +1998,38 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
 
     async fn blob_delete_blob(self, msg: BlobDeleteBlobRequest) -> RpcResult<()> {
         self.inner.db.delete(&msg.hash).await?;
+        self.inner.serve_stats.remove(&msg.hash);
+        Ok(())
+    }
+
+    async fn blob_touch(self, msg: BlobTouchRequest) -> RpcResult<()> {
+        for hash in msg.hashes {
+            self.inner.touched.touch(hash);
+        }
         Ok(())
     }
 
+    async fn blob_stats(self, msg: BlobStatsRequest) -> RpcResult<BlobStatsResponse> {
+        let blobs = self.inner.serve_stats.top(msg.limit);
+        Ok(BlobStatsResponse { blobs })
+    }
+
+    async fn blob_tree(self, msg: BlobTreeRequest) -> RpcResult<TreeInfo> {
+        let entry = self
+            .inner
+            .db
+            .get(&msg.hash)
+            .ok_or_else(|| anyhow!("Blob not found"))?;
+        let info = self
+            .inner
+            .rt
+            .local_pool()
+            .spawn_pinned(move || async move { entry.tree_info(msg.levels).await })
+            .await
+            .map_err(|e| anyhow!("tree info task failed: {e}"))??;
+        Ok(info)
+    }
+
     fn blob_list_tags(
         self,
         _msg: ListTagsRequest,
@@ -1005,14 +2049,83 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
     /// Invoke validate on the database and stream out the result
     fn blob_validate(
         self,
-        _msg: BlobValidateRequest,
+        msg: BlobValidateRequest,
     ) -> impl Stream<Item = ValidateProgress> + Send + 'static {
         let (tx, rx) = mpsc::channel(1);
+        let permit = match self.inner.progress_limit.acquire() {
+            Ok(permit) => permit,
+            Err(e) => {
+                tx.try_send(ValidateProgress::Abort(e.into())).ok();
+                return tokio_stream::wrappers::ReceiverStream::new(rx);
+            }
+        };
         let tx2 = tx.clone();
         let db = self.inner.db.clone();
+        let concurrency = msg.concurrency.unwrap_or_else(num_cpus::get);
         self.rt().main().spawn(async move {
-            if let Err(e) = db.validate(tx).await {
-                tx2.send(ValidateProgress::Abort(e.into())).await.unwrap();
+            let _permit = permit;
+            let res = if msg.repair {
+                validate_and_repair(&db, concurrency, tx).await
+            } else {
+                db.validate_with_concurrency(concurrency, tx).await
+            };
+            if let Err(e) = res {
+                tx2.send(ValidateProgress::Abort(e.into())).await.ok();
+            }
+        });
+        tokio_stream::wrappers::ReceiverStream::new(rx)
+    }
+
+    /// Validate a collection and its children and stream out the result
+    fn blob_validate_collection(
+        self,
+        msg: BlobValidateCollectionRequest,
+    ) -> impl Stream<Item = ValidateProgress> + Send + 'static {
+        let (tx, rx) = mpsc::channel(1);
+        let permit = match self.inner.progress_limit.acquire() {
+            Ok(permit) => permit,
+            Err(e) => {
+                tx.try_send(ValidateProgress::Abort(e.into())).ok();
+                return tokio_stream::wrappers::ReceiverStream::new(rx);
+            }
+        };
+        let tx2 = tx.clone();
+        self.rt().local_pool().spawn_pinned(move || async move {
+            let _permit = permit;
+            let db = &self.inner.db;
+            let cp = self.collection_parser.clone();
+            if let Err(e) = iroh_bytes::baomap::validate_collection(db, msg.hash, cp, tx).await {
+                tx2.send(ValidateProgress::Abort(e.into())).await.ok();
+            }
+        });
+        tokio_stream::wrappers::ReceiverStream::new(rx)
+    }
+
+    /// Validate a single blob and stream out fine grained progress
+    fn blob_validate_one(
+        self,
+        msg: BlobValidateOneRequest,
+    ) -> impl Stream<Item = ValidateProgress> + Send + 'static {
+        let (tx, rx) = mpsc::channel(1);
+        let permit = match self.inner.progress_limit.acquire() {
+            Ok(permit) => permit,
+            Err(e) => {
+                tx.try_send(ValidateProgress::Abort(e.into())).ok();
+                return tokio_stream::wrappers::ReceiverStream::new(rx);
+            }
+        };
+        let tx2 = tx.clone();
+        let options = ValidateOptions {
+            progress_interval: msg
+                .progress_interval
+                .unwrap_or(DEFAULT_VALIDATE_PROGRESS_INTERVAL),
+            ..Default::default()
+        };
+        self.rt().local_pool().spawn_pinned(move || async move {
+            let _permit = permit;
+            let db = &self.inner.db;
+            if let Err(e) = iroh_bytes::baomap::validate_one(db, msg.hash, options, tx).await {
+                tx2.send(ValidateProgress::Abort(e.into())).await.ok();
             }
         });
         tokio_stream::wrappers::ReceiverStream::new(rx)
@@ -1021,15 +2134,151 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
     fn blob_add_from_path(self, msg: BlobAddPathRequest) -> impl Stream<Item = AddProgress> {
         // provide a little buffer so that we don't slow down the sender
         let (tx, rx) = flume::bounded(32);
+        let permit = match self.inner.progress_limit.acquire() {
+            Ok(permit) => permit,
+            Err(e) => {
+                tx.try_send(AddProgress::Abort(e.into())).ok();
+                return rx.into_stream();
+            }
+        };
         let tx2 = tx.clone();
         self.rt().local_pool().spawn_pinned(|| async move {
+            let _permit = permit;
             if let Err(e) = self.blob_add_from_path0(msg, tx).await {
-                tx2.send_async(AddProgress::Abort(e.into())).await.ok();
+                let disk_full = e
+                    .chain()
+                    .filter_map(|cause| cause.downcast_ref::<io::Error>())
+                    .any(iroh_bytes::util::is_disk_full);
+                let msg = if disk_full {
+                    AddProgress::DiskFull
+                } else {
+                    AddProgress::Abort(e.into())
+                };
+                tx2.send_async(msg).await.ok();
+            }
+        });
+        rx.into_stream()
+    }
+
+    fn blob_add_stream(
+        self,
+        msg: BlobAddStreamRequest,
+        updates: impl Stream<Item = BlobAddStreamUpdate> + Send + Unpin + 'static,
+    ) -> impl Stream<Item = BlobAddStreamResponse> {
+        // provide a little buffer so that we don't slow down the sender
+        let (tx, rx) = flume::bounded(32);
+        let permit = match self.inner.progress_limit.acquire() {
+            Ok(permit) => permit,
+            Err(e) => {
+                tx.try_send(BlobAddStreamResponse::Abort(e.into())).ok();
+                return rx.into_stream();
+            }
+        };
+        self.rt().local_pool().spawn_pinned(|| async move {
+            let _permit = permit;
+            if let Err(e) = self.blob_add_stream0(msg, updates, &tx).await {
+                tx.send_async(BlobAddStreamResponse::Abort(e.into()))
+                    .await
+                    .ok();
             }
         });
         rx.into_stream()
     }
 
+    async fn blob_add_stream0(
+        self,
+        msg: BlobAddStreamRequest,
+        mut updates: impl Stream<Item = BlobAddStreamUpdate> + Unpin,
+        tx: &flume::Sender<BlobAddStreamResponse>,
+    ) -> anyhow::Result<()> {
+        let db = &self.inner.db;
+        let collection = msg.collection;
+        anyhow::ensure!(
+            cfg!(feature = "iroh-collection") || collection.is_none(),
+            "collections not supported"
+        );
+        // the added blobs, kept alive by their temp tags until the batch is finished
+        let mut blobs = BTreeMap::new();
+        let mut untagged = Vec::new();
+        loop {
+            let (name, data) = match updates.next().await {
+                Some(BlobAddStreamUpdate::Blob { name, data }) => (name, data),
+                Some(BlobAddStreamUpdate::Finish) => break,
+                None => anyhow::bail!("blob stream closed before it was finished"),
+            };
+            let size = data.len() as u64;
+            let res = async {
+                anyhow::ensure!(!blobs.contains_key(&name), "duplicate name {name}");
+                let temp_tag = db.import_bytes(data, BlobFormat::RAW).await?;
+                anyhow::Ok(temp_tag)
+            }
+            .await;
+            let response = match res {
+                Ok(temp_tag) => {
+                    let hash = *temp_tag.hash();
+                    if collection.is_some() {
+                        blobs.insert(name.clone(), (hash, size, temp_tag));
+                    } else {
+                        untagged.push(temp_tag);
+                    }
+                    BlobAddStreamResponse::Added { name, hash, size }
+                }
+                Err(e) => BlobAddStreamResponse::Failed {
+                    name,
+                    error: e.into(),
+                },
+            };
+            tx.send_async(response)
+                .await
+                .map_err(|_| anyhow::anyhow!("client went away"))?;
+        }
+        let Some(tag) = collection else {
+            for temp_tag in untagged {
+                db.create_tag(*temp_tag.inner()).await?;
+            }
+            return Ok(());
+        };
+        #[cfg(feature = "iroh-collection")]
+        {
+            use crate::collection::{Blob, Collection};
+
+            let total_blobs_size = blobs.values().map(|(_, size, _)| *size).sum();
+            let children = blobs
+                .iter()
+                .map(|(name, (hash, _, _))| Blob {
+                    name: name.clone(),
+                    hash: *hash,
+                })
+                .collect();
+            let temp_tag = Collection::new(children, total_blobs_size)?
+                .store(db)
+                .await?;
+            let HashAndFormat(hash, format) = *temp_tag.inner();
+            let tag = match tag {
+                SetTagOption::Named(tag) => {
+                    db.set_tag(tag.clone(), Some(*temp_tag.inner())).await?;
+                    tag
+                }
+                SetTagOption::Auto => db.create_tag(*temp_tag.inner()).await?,
+            };
+            tx.send_async(BlobAddStreamResponse::Collection {
+                hash,
+                tag: tag.clone(),
+            })
+            .await
+            .ok();
+            self.inner
+                .callbacks
+                .send(Event::ByteProvide(
+                    iroh_bytes::provider::Event::TaggedBlobAdded { hash, format, tag },
+                ))
+                .await;
+        }
+        #[cfg(not(feature = "iroh-collection"))]
+        let _ = (tag, blobs);
+        Ok(())
+    }
+
     async fn blob_export(
         self,
         out: String,
@@ -1094,6 +2343,7 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         self,
         msg: BlobDownloadRequest,
         progress: impl ProgressSender<Msg = GetProgress> + IdGenerator,
+        permit: OwnedSemaphorePermit,
     ) -> anyhow::Result<()> {
         let local = self.inner.rt.local_pool().clone();
         let hash = msg.hash;
@@ -1113,6 +2363,7 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         let db = self.inner.db.clone();
         let db2 = db.clone();
         let collection_parser = self.collection_parser.clone();
+        let max_blob_size = self.inner.max_blob_size;
         let download = local.spawn_pinned(move || async move {
             crate::get::get(
                 &db2,
@@ -1120,13 +2371,19 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
                 conn,
                 hash,
                 msg.format.is_collection(),
+                max_blob_size,
                 progress2,
             )
             .await
         });
 
         let this = self.clone();
+        // the download keeps the node busy, even if the progress is no longer received
+        let activity = self.inner.idle.as_ref().map(|idle| idle.activity());
         let _export = local.spawn_pinned(move || async move {
+            // the operation counts against the limit until the download is done
+            let _permit = permit;
+            let _activity = activity;
             let stats = download.await.unwrap()?;
             progress
                 .send(GetProgress::NetworkDone {
@@ -1161,8 +2418,15 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
     fn blob_download(self, msg: BlobDownloadRequest) -> impl Stream<Item = GetProgress> {
         async move {
             let (sender, receiver) = flume::bounded(1024);
+            let permit = match self.inner.progress_limit.acquire() {
+                Ok(permit) => permit,
+                Err(e) => {
+                    sender.try_send(GetProgress::Abort(e.into())).ok();
+                    return receiver.into_stream();
+                }
+            };
             let sender = FlumeProgressSender::new(sender);
-            if let Err(cause) = self.blob_download0(msg, sender.clone()).await {
+            if let Err(cause) = self.blob_download0(msg, sender.clone(), permit).await {
                 sender.send(GetProgress::Abort(cause.into())).await.unwrap();
             };
             receiver.into_stream()
@@ -1182,10 +2446,20 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         };
         use futures::TryStreamExt;
         use iroh_bytes::baomap::{ImportMode, ImportProgress, TempTag};
-        use std::{collections::BTreeMap, sync::Mutex};
+        use std::sync::Mutex;
 
+        let BlobAddPathRequest {
+            wrap,
+            path: root,
+            in_place,
+            tag,
+            verify_on_import,
+        } = msg;
         let progress = FlumeProgressSender::new(progress);
         let names = Arc::new(Mutex::new(BTreeMap::new()));
+        // ids of imported files whose `Done` is held back until they are verified
+        let unverified = Arc::new(Mutex::new(BTreeMap::<Hash, Vec<u64>>::new()));
+        let unverified2 = unverified.clone();
         // convert import progress to provide progress
         let import_progress = progress.clone().with_filter_map(move |x| match x {
             ImportProgress::Found { id, path, .. } => {
@@ -1203,15 +2477,38 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
             ImportProgress::OutboardProgress { id, offset } => {
                 Some(AddProgress::Progress { id, offset })
             }
+            ImportProgress::OutboardDone { hash, id } if verify_on_import => {
+                unverified2
+                    .lock()
+                    .unwrap()
+                    .entry(hash)
+                    .or_default()
+                    .push(id);
+                None
+            }
             ImportProgress::OutboardDone { hash, id } => Some(AddProgress::Done { hash, id }),
             _ => None,
         });
-        let BlobAddPathRequest {
-            wrap,
-            path: root,
-            in_place,
-            tag,
-        } = msg;
+        // read back an imported file and report it as done if it matches its hash
+        let verify = |db: D, hash: Hash| {
+            let unverified = unverified.clone();
+            let progress = progress.clone();
+            async move {
+                if !verify_on_import {
+                    return anyhow::Ok(());
+                }
+                verify_import(&db, hash).await?;
+                let id = unverified
+                    .lock()
+                    .unwrap()
+                    .get_mut(&hash)
+                    .and_then(|ids| ids.pop());
+                if let Some(id) = id {
+                    progress.send(AddProgress::Done { hash, id }).await?;
+                }
+                Ok(())
+            }
+        };
         // Check that the path is absolute and exists.
         anyhow::ensure!(root.is_absolute(), "path must be absolute");
         anyhow::ensure!(root.exists(), "path must exist");
@@ -1226,14 +2523,27 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
             WrapOption::NoWrap => root.is_dir(),
         };
 
+        // space needed to import a file, in place imports only write the outboard
+        let required_space = |size: u64| match import_mode {
+            ImportMode::Copy => iroh_bytes::baomap::blob_space(size),
+            ImportMode::TryReference => iroh_bytes::baomap::blob_space(size) - size,
+        };
+
         let temp_tag = if create_collection {
             // import all files below root recursively
             let data_sources = crate::util::fs::scan_path(root, wrap)?;
+            // fail before importing anything if the files do not fit into the store
+            let mut required = 0;
+            for source in &data_sources {
+                required += required_space(source.path().metadata()?.len());
+            }
+            iroh_bytes::baomap::ensure_space(&self.inner.db, required)?;
             const IO_PARALLELISM: usize = 4;
             let result: Vec<(Blob, u64, TempTag)> = futures::stream::iter(data_sources)
                 .map(|source| {
                     let import_progress = import_progress.clone();
                     let db = self.inner.db.clone();
+                    let verify = &verify;
                     async move {
                         let name = source.name().to_string();
                         let (tag, size) = db
@@ -1245,8 +2555,9 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
                             )
                             .await?;
                         let hash = *tag.hash();
+                        verify(db, hash).await?;
                         let blob = Blob { hash, name };
-                        io::Result::Ok((blob, size, tag))
+                        anyhow::Ok((blob, size, tag))
                     }
                 })
                 .buffered(IO_PARALLELISM)
@@ -1262,11 +2573,14 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
             collection.store(&self.inner.db).await?
         } else {
             // import a single file
+            let required = required_space(root.metadata()?.len());
+            iroh_bytes::baomap::ensure_space(&self.inner.db, required)?;
             let (tag, _size) = self
                 .inner
                 .db
                 .import(root, import_mode, BlobFormat::RAW, import_progress)
                 .await?;
+            verify(self.inner.db.clone(), *tag.hash()).await?;
             tag
         };
 
@@ -1329,8 +2643,89 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
                 .await
                 .unwrap_or_default(),
             version: env!("CARGO_PKG_VERSION").to_string(),
+            progress_operations: self.inner.progress_limit.in_use() as u64,
+            transfer_memory_in_use: self.inner.transfer_memory.in_use() as u64,
+        })
+    }
+
+    /// There is no quota on the blob store and no rpc to export secret keys, so this only
+    /// reports what the node actually enforces.
+    async fn node_config(self, _: NodeConfigRequest) -> RpcResult<NodeConfigResponse> {
+        Ok(NodeConfigResponse {
+            // values are stored as blobs, so they are limited like blobs
+            max_value_size: Some(self.inner.max_blob_size),
+            max_blob_size: Some(self.inner.max_blob_size),
+            quota: None,
+            allow_key_export: false,
+            supported_alpns: PROTOCOLS
+                .iter()
+                .map(|alpn| String::from_utf8_lossy(alpn).into_owned())
+                .collect(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            max_progress_operations: self.inner.progress_limit.max as u64,
+            max_concurrent_streams: self.inner.max_concurrent_streams as u64,
         })
     }
+
+    async fn node_health(self, _: NodeHealthRequest) -> NodeHealthResponse {
+        NodeHealthResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// The store is fully loaded before the node starts serving rpc requests, so
+    /// readiness only depends on the endpoint and on whether we are shutting down.
+    async fn node_ready(self, _: NodeReadyRequest) -> RpcResult<NodeReadyResponse> {
+        let addr = self.inner.endpoint.my_addr().await?;
+        let ready = !self.inner.cancel_token.is_cancelled() && !addr.info.is_empty();
+        Ok(NodeReadyResponse { ready, addr })
+    }
+
+    async fn node_revoke_token(self, msg: RevokeTokenRequest) -> RpcResult<RevokeTokenResponse> {
+        let revoked = self.inner.auth_handler.revoke(msg.token)?;
+        Ok(RevokeTokenResponse { revoked })
+    }
+
+    async fn node_list_revoked(self, _msg: ListRevokedRequest) -> RpcResult<ListRevokedResponse> {
+        let tokens = self.inner.auth_handler.revoked();
+        Ok(ListRevokedResponse { tokens })
+    }
+
+    async fn node_list_transfers(self, _msg: ListTransfersRequest) -> ListTransfersResponse {
+        let transfers = self
+            .inner
+            .transfers
+            .list()
+            .into_iter()
+            .map(|transfer| TransferListEntry {
+                peer: self.inner.connections.peer(transfer.connection_id),
+                transfer,
+            })
+            .collect();
+        let connections = self
+            .inner
+            .transfers
+            .transfers_per_connection()
+            .into_iter()
+            .map(|connection| ConnectionTransfersEntry {
+                peer: self.inner.connections.peer(connection.connection_id),
+                connection,
+            })
+            .collect();
+        ListTransfersResponse {
+            transfers,
+            connections,
+        }
+    }
+
+    async fn node_cancel_transfer(self, msg: CancelTransferRequest) -> CancelTransferResponse {
+        let cancelled = self
+            .inner
+            .transfers
+            .cancel(msg.connection_id, msg.request_id);
+        CancelTransferResponse { cancelled }
+    }
+
     async fn node_shutdown(self, request: NodeShutdownRequest) {
         if request.force {
             info!("hard shutdown requested");
@@ -1354,6 +2749,17 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         })
     }
 
+    fn node_events(self, req: NodeEventsRequest) -> impl Stream<Item = NodeEventsResponse> {
+        let events = self.inner.events.clone();
+        let since = match (req.since, req.instance) {
+            // the node restarted, so the sequence number is meaningless
+            (Some(_), Some(instance)) if instance != events.instance => 0,
+            (Some(since), _) => since,
+            (None, _) => events.next_seq(),
+        };
+        events.subscribe(since)
+    }
+
     fn blob_read(
         self,
         req: BytesGetRequest,
@@ -1430,6 +2836,16 @@ impl<D: BaoStore, S: DocStore, C: CollectionParser> RpcHandler<D, S, C> {
         let conn_info = self.inner.endpoint.connection_info(node_id).await?;
         Ok(NodeConnectionInfoResponse { conn_info })
     }
+
+    async fn node_peer_stats(self, req: NodePeerStatsRequest) -> NodePeerStatsResponse {
+        let connections = self.inner.connections.get(&req.node_id);
+        NodePeerStatsResponse { connections }
+    }
+
+    async fn node_derp_status(self, _: DerpStatusRequest) -> RpcResult<DerpStatusResponse> {
+        let regions = self.inner.endpoint.derp_regions().await?;
+        Ok(DerpStatusResponse { regions })
+    }
 }
 
 fn handle_rpc_request<
@@ -1444,17 +2860,36 @@ fn handle_rpc_request<
     rt: &runtime::Handle,
 ) {
     let handler = handler.clone();
+    let activity = handler.inner.idle.as_ref().map(|idle| idle.activity());
     rt.main().spawn(async move {
         use ProviderRequest::*;
+        let _activity = activity;
         debug!("handling rpc request: {msg}");
         match msg {
             NodeWatch(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::node_watch)
                     .await
             }
+            NodeEvents(msg) => {
+                chan.server_streaming(msg, handler, RpcHandler::node_events)
+                    .await
+            }
             NodeStatus(msg) => chan.rpc(msg, handler, RpcHandler::node_status).await,
+            NodeConfig(msg) => chan.rpc(msg, handler, RpcHandler::node_config).await,
+            NodeHealth(msg) => chan.rpc(msg, handler, RpcHandler::node_health).await,
+            NodeReady(msg) => chan.rpc(msg, handler, RpcHandler::node_ready).await,
             NodeShutdown(msg) => chan.rpc(msg, handler, RpcHandler::node_shutdown).await,
             NodeStats(msg) => chan.rpc(msg, handler, RpcHandler::node_stats).await,
+            NodeRevokeToken(msg) => chan.rpc(msg, handler, RpcHandler::node_revoke_token).await,
+            NodeListRevoked(msg) => chan.rpc(msg, handler, RpcHandler::node_list_revoked).await,
+            NodeListTransfers(msg) => {
+                chan.rpc(msg, handler, RpcHandler::node_list_transfers)
+                    .await
+            }
+            NodeCancelTransfer(msg) => {
+                chan.rpc(msg, handler, RpcHandler::node_cancel_transfer)
+                    .await
+            }
             NodeConnections(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::node_connections)
                     .await
@@ -1463,6 +2898,8 @@ fn handle_rpc_request<
                 chan.rpc(msg, handler, RpcHandler::node_connection_info)
                     .await
             }
+            NodePeerStats(msg) => chan.rpc(msg, handler, RpcHandler::node_peer_stats).await,
+            NodeDerpStatus(msg) => chan.rpc(msg, handler, RpcHandler::node_derp_status).await,
             BlobList(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_list)
                     .await
@@ -1471,20 +2908,36 @@ fn handle_rpc_request<
                 chan.server_streaming(msg, handler, RpcHandler::blob_list_incomplete)
                     .await
             }
+            BlobListUnreferenced(msg) => {
+                chan.server_streaming(msg, handler, RpcHandler::blob_list_unreferenced)
+                    .await
+            }
             BlobListCollections(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_list_collections)
                     .await
             }
+            BlobCollectionListing(msg) => {
+                chan.rpc(msg, handler, RpcHandler::blob_collection_listing)
+                    .await
+            }
             ListTags(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_list_tags)
                     .await
             }
             DeleteTag(msg) => chan.rpc(msg, handler, RpcHandler::blob_delete_tag).await,
             BlobDeleteBlob(msg) => chan.rpc(msg, handler, RpcHandler::blob_delete_blob).await,
+            BlobTouch(msg) => chan.rpc(msg, handler, RpcHandler::blob_touch).await,
+            BlobTree(msg) => chan.rpc(msg, handler, RpcHandler::blob_tree).await,
+            BlobStats(msg) => chan.rpc(msg, handler, RpcHandler::blob_stats).await,
             BlobAddPath(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_add_from_path)
                     .await
             }
+            BlobAddStream(msg) => {
+                chan.bidi_streaming(msg, handler, RpcHandler::blob_add_stream)
+                    .await
+            }
+            BlobAddStreamUpdate(_) => Err(RpcServerError::UnexpectedStartMessage),
             BlobDownload(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_download)
                     .await
@@ -1493,6 +2946,14 @@ fn handle_rpc_request<
                 chan.server_streaming(msg, handler, RpcHandler::blob_validate)
                     .await
             }
+            BlobValidateCollection(msg) => {
+                chan.server_streaming(msg, handler, RpcHandler::blob_validate_collection)
+                    .await
+            }
+            BlobValidateOne(msg) => {
+                chan.server_streaming(msg, handler, RpcHandler::blob_validate_one)
+                    .await
+            }
             BlobRead(msg) => {
                 chan.server_streaming(msg, handler, RpcHandler::blob_read)
                     .await
@@ -1509,8 +2970,17 @@ fn handle_rpc_request<
                 })
                 .await
             }
-            AuthorImport(_msg) => {
-                todo!()
+            AuthorImport(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.author_import(req)
+                })
+                .await
+            }
+            AuthorRemove(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.author_remove(req)
+                })
+                .await
             }
             DocInfo(msg) => {
                 chan.rpc(msg, handler, |handler, req| async move {
@@ -1539,23 +3009,61 @@ fn handle_rpc_request<
             DocSet(msg) => {
                 let bao_store = handler.inner.db.clone();
                 chan.rpc(msg, handler, |handler, req| async move {
-                    handler.inner.sync.doc_set(&bao_store, req).await
+                    let max_value_size = handler.inner.max_blob_size;
+                    handler
+                        .inner
+                        .sync
+                        .doc_set(&bao_store, req, max_value_size)
+                        .await
                 })
                 .await
             }
-            DocGet(msg) => {
-                chan.server_streaming(msg, handler, |handler, req| {
-                    handler.inner.sync.doc_get_many(req)
+            DocSetStream(msg) => {
+                let bao_store = handler.inner.db.clone();
+                chan.bidi_streaming(msg, handler, |handler, req, updates| {
+                    let max_value_size = handler.inner.max_blob_size;
+                    handler
+                        .inner
+                        .sync
+                        .doc_set_stream(bao_store, req, updates, max_value_size)
                 })
                 .await
             }
-            DocGetOne(msg) => {
+            DocSetStreamUpdate(_) => Err(RpcServerError::UnexpectedStartMessage),
+            DocMove(msg) => {
                 chan.rpc(msg, handler, |handler, req| async move {
-                    handler.inner.sync.doc_get_one(req).await
+                    handler.inner.sync.doc_move(req)
                 })
                 .await
             }
-            DocStartSync(msg) => {
+            DocGet(msg) => {
+                let bao_store = handler.inner.db.clone();
+                chan.server_streaming(msg, handler, |handler, req| {
+                    handler.inner.sync.doc_get_many(bao_store, req)
+                })
+                .await
+            }
+            DocGetOne(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_get_one(req).await
+                })
+                .await
+            }
+            DocGetKeys(msg) => {
+                let bao_store = handler.inner.db.clone();
+                chan.server_streaming(msg, handler, |handler, req| {
+                    handler.inner.sync.doc_get_keys(bao_store, req)
+                })
+                .await
+            }
+            DocExportTar(msg) => {
+                let bao_store = handler.inner.db.clone();
+                chan.server_streaming(msg, handler, |handler, req| {
+                    handler.inner.sync.doc_export_tar(bao_store, req)
+                })
+                .await
+            }
+            DocStartSync(msg) => {
                 chan.rpc(msg, handler, |handler, req| async move {
                     handler.inner.sync.doc_start_sync(req).await
                 })
@@ -1567,6 +3075,36 @@ fn handle_rpc_request<
                 })
                 .await
             }
+            DocSetGossipAuth(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_set_gossip_auth(req).await
+                })
+                .await
+            }
+            DocSetDefaultAuthor(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_set_default_author(req).await
+                })
+                .await
+            }
+            DocGetDefaultAuthor(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_get_default_author(req).await
+                })
+                .await
+            }
+            DocSetRetention(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_set_retention(req).await
+                })
+                .await
+            }
+            DocGetRetention(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.doc_get_retention(req).await
+                })
+                .await
+            }
             DocShare(msg) => {
                 chan.rpc(msg, handler, |handler, req| async move {
                     handler.inner.sync.doc_share(req).await
@@ -1579,6 +3117,18 @@ fn handle_rpc_request<
                 })
                 .await
             }
+            DocsPause(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.docs_pause(req).await
+                })
+                .await
+            }
+            DocsResume(msg) => {
+                chan.rpc(msg, handler, |handler, req| async move {
+                    handler.inner.sync.docs_resume(req).await
+                })
+                .await
+            }
         }
     });
 }
@@ -1656,12 +3206,153 @@ impl RequestAuthorizationHandler for StaticTokenAuthHandler {
     }
 }
 
+/// Authorize requests with [`StructuredToken`]s signed with a secret key.
+///
+/// Every request must carry a token that was signed with the key of this handler. The well
+/// known claims of the token, e.g. its expiry, are checked against the request, see
+/// [`StructuredToken::check_claims`]. Tokens can be issued with [`StructuredToken::issue`].
+///
+/// Tokens with an id can be revoked before they expire. The revoked tokens are kept in a
+/// [`RevocationList`], which is bounded in size and drops tokens past their expiry.
+#[derive(Debug, Clone)]
+pub struct StructuredTokenAuthHandler {
+    key: [u8; 32],
+    revoked: Arc<std::sync::Mutex<RevocationList>>,
+    revoked_path: Option<PathBuf>,
+}
+
+impl StructuredTokenAuthHandler {
+    /// Creates a new handler that accepts tokens signed with `key`.
+    ///
+    /// Revoked tokens are only kept in memory.
+    pub fn new(key: [u8; 32]) -> Self {
+        Self {
+            key,
+            revoked: Default::default(),
+            revoked_path: None,
+        }
+    }
+
+    /// Creates a new handler that accepts tokens signed with `key`, and persists the revoked
+    /// tokens to `path`.
+    ///
+    /// The revoked tokens are loaded from `path` if it exists.
+    pub fn persistent(key: [u8; 32], path: PathBuf) -> Result<Self> {
+        let revoked = if path.exists() {
+            let data = std::fs::read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            postcard::from_bytes(&data)
+                .with_context(|| format!("invalid revoked tokens in {}", path.display()))?
+        } else {
+            RevocationList::default()
+        };
+        Ok(Self {
+            key,
+            revoked: Arc::new(std::sync::Mutex::new(revoked)),
+            revoked_path: Some(path),
+        })
+    }
+
+    /// Writes the revoked tokens to a temporary file, which is then renamed.
+    fn save_revoked(&self, revoked: &RevocationList) -> Result<()> {
+        let Some(path) = &self.revoked_path else {
+            return Ok(());
+        };
+        let data = postcard::to_stdvec(revoked)?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data).with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("failed to rename {}", tmp.display()))?;
+        Ok(())
+    }
+}
+
+impl RequestAuthorizationHandler for StructuredTokenAuthHandler {
+    fn authorize(
+        &self,
+        token: Option<RequestToken>,
+        request: &Request,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        let res = (|| {
+            let token = token.context("no token provided")?;
+            let token = StructuredToken::from_request_token(&token)?;
+            token.verify(&self.key)?;
+            if let Some(id) = token.id() {
+                ensure!(
+                    !self.revoked.lock().unwrap().is_revoked(id),
+                    "token revoked"
+                );
+            }
+            token.check_claims(request, SystemTime::now())
+        })();
+        futures::future::ready(res).boxed()
+    }
+
+    /// Revokes the token, and persists the revoked tokens if they changed.
+    ///
+    /// The token stays revoked in memory if persisting fails.
+    fn revoke(&self, token: RevokedToken) -> Result<bool> {
+        let mut revoked = self.revoked.lock().unwrap();
+        let changed = revoked.revoke(token, SystemTime::now())?;
+        if changed {
+            self.save_revoked(&revoked)?;
+        }
+        Ok(changed)
+    }
+
+    fn revoked(&self) -> Vec<RevokedToken> {
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.prune(SystemTime::now());
+        revoked.iter().collect()
+    }
+}
+
+/// Handle custom get requests for the aliases set with [`Node::set_alias`].
+///
+/// The data of the custom request is the utf8 name of the alias. The target of the alias is
+/// sent completely, for collections including all children.
+#[derive(Debug, Clone)]
+pub struct AliasGetHandler<D> {
+    db: D,
+}
+
+impl<D: ReadableStore + Debug> AliasGetHandler<D> {
+    /// Creates a new handler that resolves aliases in the alias tags of `db`.
+    pub fn new(db: D) -> Self {
+        Self { db }
+    }
+}
+
+impl<D: ReadableStore + Debug> CustomGetHandler for AliasGetHandler<D> {
+    fn handle(
+        &self,
+        token: Option<RequestToken>,
+        request: Bytes,
+    ) -> BoxFuture<'static, anyhow::Result<GetRequest>> {
+        let res = (|| {
+            let name = std::str::from_utf8(&request).context("alias is not valid utf8")?;
+            let HashAndFormat(hash, format) =
+                resolve_alias(&self.db, name).with_context(|| format!("unknown alias {name}"))?;
+            let request = if format.is_raw() {
+                GetRequest::single(hash)
+            } else {
+                GetRequest::all(hash)
+            };
+            Ok(request.with_token(token))
+        })();
+        futures::future::ready(res).boxed()
+    }
+}
+
 #[cfg(all(test, feature = "flat-db"))]
 mod tests {
     use anyhow::bail;
-    use futures::StreamExt;
+    use futures::{StreamExt, TryStreamExt};
+    use iroh_bytes::baomap::PartialMap;
+    use iroh_sync::store::memory::Store as MemDocStore;
     use std::net::Ipv4Addr;
     use std::path::Path;
+    use tokio_util::sync::DropGuard;
 
     use crate::rpc_protocol::WrapOption;
 
@@ -1673,6 +3364,32 @@ mod tests {
         runtime::Handle::from_current(1).unwrap()
     }
 
+    /// A new in-memory blob store.
+    fn mem_store() -> crate::baomap::mem::Store {
+        crate::baomap::mem::Store::new(test_runtime())
+    }
+
+    /// Spawn a node for `db` with an in-memory doc store.
+    ///
+    /// The node is shut down when the returned guard is dropped.
+    async fn spawn_node<D: BaoStore>(db: D) -> Result<(Node<D, MemDocStore>, DropGuard)> {
+        spawn_node_with(db, |builder| builder).await
+    }
+
+    /// Like [`spawn_node`], with the builder customized by `configure`.
+    async fn spawn_node_with<D: BaoStore>(
+        db: D,
+        configure: impl FnOnce(Builder<D>) -> Builder<D>,
+    ) -> Result<(Node<D, MemDocStore>, DropGuard)> {
+        let doc_store = MemDocStore::default();
+        let builder = Node::builder(db, doc_store)
+            .bind_addr((Ipv4Addr::UNSPECIFIED, 0).into())
+            .runtime(&test_runtime());
+        let node = configure(builder).spawn().await?;
+        let drop_guard = node.cancel_token().drop_guard();
+        Ok((node, drop_guard))
+    }
+
     #[tokio::test]
     async fn test_ticket_multiple_addrs() {
         let rt = test_runtime();
@@ -1691,6 +3408,155 @@ mod tests {
         assert!(!ticket.node_addr().info.direct_addresses.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_node_health_ready() -> Result<()> {
+        let rt = test_runtime();
+        let (db, _hashes) = crate::baomap::readonly_mem::Store::new([("test", b"hello")]);
+        let doc_store = iroh_sync::store::memory::Store::default();
+        let node = Node::builder(db, doc_store)
+            .bind_addr((Ipv4Addr::UNSPECIFIED, 0).into())
+            .runtime(&rt)
+            .spawn()
+            .await?;
+        let _drop_guard = node.cancel_token().drop_guard();
+        let client = node.client();
+        let health = client.node.health().await?;
+        assert_eq!(health.version, env!("CARGO_PKG_VERSION"));
+        let ready = client.node.ready().await?;
+        assert!(ready.ready);
+        assert_eq!(ready.addr.peer_id, node.peer_id());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_node_config() -> Result<()> {
+        let rt = test_runtime();
+        let (db, _hashes) = crate::baomap::readonly_mem::Store::new([("test", b"hello")]);
+        let doc_store = iroh_sync::store::memory::Store::default();
+        let node = Node::builder(db, doc_store)
+            .bind_addr((Ipv4Addr::UNSPECIFIED, 0).into())
+            .runtime(&rt)
+            .spawn()
+            .await?;
+        let _drop_guard = node.cancel_token().drop_guard();
+        let config = node.client().node.config().await?;
+        assert_eq!(config.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(config.max_value_size, Some(DEFAULT_MAX_BLOB_SIZE));
+        assert_eq!(config.max_blob_size, Some(DEFAULT_MAX_BLOB_SIZE));
+        assert!(!config.allow_key_export);
+        assert_eq!(config.supported_alpns.len(), PROTOCOLS.len());
+        assert!(config
+            .supported_alpns
+            .iter()
+            .any(|alpn| alpn.as_bytes() == SYNC_ALPN));
+        assert_eq!(
+            config.max_progress_operations,
+            MAX_PROGRESS_OPERATIONS as u64
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_value_size() -> Result<()> {
+        let (node, _drop_guard) =
+            spawn_node_with(mem_store(), |builder| builder.max_blob_size(4)).await?;
+        let client = node.client();
+        assert_eq!(client.node.config().await?.max_value_size, Some(4));
+        let doc = client.docs.create().await?;
+        let author = client.authors.create().await?;
+        doc.set_bytes(author, b"key".to_vec(), b"four".to_vec())
+            .await?;
+        assert!(doc
+            .set_bytes(author, b"key".to_vec(), b"five!".to_vec())
+            .await
+            .is_err());
+
+        // streamed values fail once they get too large
+        let value = futures::stream::iter([
+            Ok(Bytes::from_static(b"fi")),
+            Ok(Bytes::from_static(b"ve!")),
+        ]);
+        let res = doc
+            .set_stream(Some(author), b"key".to_vec(), value)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_progress_operations_limit() -> Result<()> {
+        let (db, _hashes) = crate::baomap::readonly_mem::Store::new([("test", b"hello")]);
+        let (node, _drop_guard) =
+            spawn_node_with(db, |builder| builder.max_progress_operations(0)).await?;
+        let client = node.client();
+        let progress = client
+            .blobs
+            .validate(false)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(progress[..], [ValidateProgress::Abort(_)]));
+        let status = client.node.status().await?;
+        assert_eq!(status.progress_operations, 0);
+        assert_eq!(status.transfer_memory_in_use, 0);
+        Ok(())
+    }
+
+    /// An authorization handler that never answers, so requests stall.
+    #[derive(Debug)]
+    struct StallingAuthHandler;
+
+    impl RequestAuthorizationHandler for StallingAuthHandler {
+        fn authorize(
+            &self,
+            _token: Option<RequestToken>,
+            _request: &Request,
+        ) -> BoxFuture<'static, anyhow::Result<()>> {
+            futures::future::pending().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_operations_limit_download() -> Result<()> {
+        let (provider_db, hashes) = crate::baomap::readonly_mem::Store::new([("test", b"hello")]);
+        let (provider, _provider_guard) = spawn_node_with(provider_db, |builder| {
+            builder.custom_auth_handler(Arc::new(StallingAuthHandler))
+        })
+        .await?;
+        let (node, _drop_guard) =
+            spawn_node_with(mem_store(), |builder| builder.max_progress_operations(1)).await?;
+        let client = node.client();
+        let mut download = client
+            .blobs
+            .download(BlobDownloadRequest {
+                hash: hashes["test"].into(),
+                format: BlobFormat::RAW,
+                peer: provider.my_addr().await?,
+                token: None,
+                tag: SetTagOption::Auto,
+                out: DownloadLocation::Internal,
+            })
+            .await?;
+
+        // the download stalls after connecting, and keeps counting against the limit
+        assert!(matches!(
+            download.try_next().await?,
+            Some(GetProgress::Connected)
+        ));
+        let status = client.node.status().await?;
+        assert_eq!(status.progress_operations, 1);
+        let progress = client
+            .blobs
+            .validate(false)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(progress[..], [ValidateProgress::Abort(_)]));
+        Ok(())
+    }
+
     #[cfg(feature = "mem-db")]
     #[tokio::test]
     async fn test_node_add_tagged_blob_event() -> Result<()> {
@@ -1731,6 +3597,7 @@ mod tests {
                     in_place: false,
                     tag: SetTagOption::Auto,
                     wrap: WrapOption::NoWrap,
+                    verify_on_import: false,
                 })
                 .await?;
 
@@ -1756,4 +3623,693 @@ mod tests {
 
         Ok(())
     }
+
+    #[cfg(feature = "iroh-collection")]
+    #[tokio::test]
+    async fn test_add_stream() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+        let client = node.client();
+
+        let blobs = |items: &[(&str, &'static [u8])]| {
+            let items = items
+                .iter()
+                .map(|(name, data)| (name.to_string(), Bytes::from_static(data)))
+                .collect::<Vec<_>>();
+            futures::stream::iter(items)
+        };
+
+        // without a collection, every blob gets a tag
+        let responses = client
+            .blobs
+            .add_stream(blobs(&[("a", b"a"), ("b", b"b")]), None)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(responses.len(), 2);
+        let tagged = db.tags().map(|(_, value)| value.0).collect::<BTreeSet<_>>();
+        assert!(tagged.contains(&Hash::new(b"a")));
+        assert!(tagged.contains(&Hash::new(b"b")));
+
+        // a batch that is not finished is not tagged
+        let (mut sink, responses) = node
+            .controller()
+            .bidi(BlobAddStreamRequest { collection: None })
+            .await?;
+        let update = BlobAddStreamUpdate::Blob {
+            name: "c".to_string(),
+            data: Bytes::from_static(b"c"),
+        };
+        futures::SinkExt::send(&mut sink, update)
+            .await
+            .map_err(|err| anyhow::anyhow!("failed to send blob: {err}"))?;
+        drop(sink);
+        let responses = responses.collect::<Vec<_>>().await;
+        assert!(matches!(
+            responses.last(),
+            Some(Ok(BlobAddStreamResponse::Abort(_)))
+        ));
+        let tagged = db.tags().map(|(_, value)| value.0).collect::<BTreeSet<_>>();
+        assert!(!tagged.contains(&Hash::new(b"c")));
+
+        // a duplicate name fails, but does not abort the batch
+        let tag = Tag::from("batch".to_string());
+        let responses = client
+            .blobs
+            .add_stream(
+                blobs(&[("x", b"x"), ("x", b"y"), ("z", b"z")]),
+                Some(SetTagOption::Named(tag.clone())),
+            )
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        let added = responses
+            .iter()
+            .filter_map(|r| match r {
+                BlobAddStreamResponse::Added { name, hash, .. } => Some((name.as_str(), *hash)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(added, vec![("x", Hash::new(b"x")), ("z", Hash::new(b"z"))]);
+        assert!(matches!(
+            &responses[1],
+            BlobAddStreamResponse::Failed { name, .. } if name == "x"
+        ));
+        let BlobAddStreamResponse::Collection { hash, tag: got_tag } = &responses[3] else {
+            panic!("expected a collection, got {:?}", responses[3]);
+        };
+        assert_eq!(got_tag, &tag);
+        let collection = crate::collection::Collection::load(&db, hash).await?;
+        let names = collection
+            .blobs()
+            .iter()
+            .map(|blob| blob.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["x", "z"]);
+        Ok(())
+    }
+
+    #[cfg(feature = "iroh-collection")]
+    #[tokio::test]
+    async fn test_add_verify_on_import() -> Result<()> {
+        let (node, _drop_guard) = spawn_node(mem_store()).await?;
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a"), vec![1u8; 1024 * 100])?;
+        std::fs::write(dir.path().join("b"), b"small")?;
+        let events = node
+            .client()
+            .blobs
+            .add_from_path(
+                dir.path().to_owned(),
+                false,
+                SetTagOption::Auto,
+                WrapOption::NoWrap,
+                true,
+            )
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        let mut done = events
+            .iter()
+            .filter_map(|e| match e {
+                AddProgress::Done { hash, .. } => Some(*hash),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        done.sort();
+        let mut expected = vec![Hash::new(vec![1u8; 1024 * 100]), Hash::new(b"small")];
+        expected.sort();
+        assert_eq!(done, expected);
+        assert!(matches!(events.last(), Some(AddProgress::AllDone { .. })));
+        Ok(())
+    }
+
+    #[cfg(feature = "mem-db")]
+    #[tokio::test]
+    async fn test_client_import() -> Result<()> {
+        let (node, _drop_guard) = spawn_node(mem_store()).await?;
+        let client = node.client();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md");
+        let events = client
+            .import(path.clone())
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(events[0], AddProgress::Found { id: 0, .. }));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, AddProgress::Done { id: 0, .. })));
+        let Some(AddProgress::AllDone { hash, .. }) = events.last() else {
+            panic!("import did not finish: {events:?}");
+        };
+
+        assert_eq!(client.import_blocking(path).await?, *hash);
+        let missing = Path::new(env!("CARGO_MANIFEST_DIR")).join("does-not-exist");
+        assert!(client.import_blocking(missing).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_node_events() -> Result<()> {
+        // without DERP, so that no status changes come in between
+        let (node, _drop_guard) = spawn_node_with(mem_store(), |b| b.disable_derp()).await?;
+        let client = node.client();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md");
+        let hash = client.import_blocking(path.clone()).await?;
+
+        // a consumer that reconnects catches up on the events it missed
+        let mut events = client.node.events(Some(0), None).await?;
+        let Some(NodeEventsResponse::Event {
+            instance,
+            seq: 0,
+            event:
+                Event::ByteProvide(iroh_bytes::provider::Event::TaggedBlobAdded { hash: added, .. }),
+        }) = events.try_next().await?
+        else {
+            bail!("missed the tagged blob event");
+        };
+        assert_eq!(added, hash);
+
+        // and then gets new events as they happen
+        client.import_blocking(path).await?;
+        let next = tokio::time::timeout(Duration::from_secs(5), events.try_next()).await??;
+        assert!(matches!(
+            next,
+            Some(NodeEventsResponse::Event { seq: 1, .. })
+        ));
+
+        // changes of the DERP status are reported as well
+        let status = DerpStatus::Unavailable(1);
+        node.inner.callbacks.send(Event::DerpStatus(status)).await;
+        let next = tokio::time::timeout(Duration::from_secs(5), events.try_next()).await??;
+        assert!(matches!(
+            next,
+            Some(NodeEventsResponse::Event {
+                seq: 2,
+                event: Event::DerpStatus(s),
+                ..
+            }) if s == status
+        ));
+
+        // resuming with the instance of the node continues at the sequence number
+        let mut events = client.node.events(Some(2), Some(instance)).await?;
+        assert!(matches!(
+            events.try_next().await?,
+            Some(NodeEventsResponse::Event { seq: 2, .. })
+        ));
+        // a sequence number of another instance, e.g. from before a restart, starts over
+        let mut events = client
+            .node
+            .events(Some(2), Some(instance.wrapping_add(1)))
+            .await?;
+        assert!(matches!(
+            events.try_next().await?,
+            Some(NodeEventsResponse::Event { instance: i, seq: 0, .. }) if i == instance
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_log_gap() {
+        let log = EventLog::new(2);
+        for connection_id in 0..3 {
+            log.push(Event::ByteProvide(
+                iroh_bytes::provider::Event::ClientConnected { connection_id },
+            ));
+        }
+        let (events, next) = log.read(0);
+        assert_eq!(next, 3);
+        assert!(matches!(
+            events[..],
+            [
+                NodeEventsResponse::Gap { from: 0, to: 1, .. },
+                NodeEventsResponse::Event { seq: 1, .. },
+                NodeEventsResponse::Event { seq: 2, .. },
+            ]
+        ));
+        assert!(log.read(3).0.is_empty());
+        // a sequence number from before a restart gets all buffered events
+        assert_eq!(log.read(10).0.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_blob_list_available() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let tag = db
+            .import_bytes(Bytes::from_static(b"complete"), BlobFormat::RAW)
+            .await?;
+        // a partial blob of which nothing was downloaded yet
+        let partial = Hash::new(b"partial");
+        db.get_or_create_partial(partial, 7)?;
+        let blobs = node
+            .client()
+            .blobs
+            .list()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        let blob = blobs
+            .iter()
+            .find(|blob| blob.hash == *tag.hash())
+            .context("blob not listed")?;
+        assert!(blob.available.as_ref().is_some_and(RangeSpec::is_all));
+        let blob = blobs
+            .iter()
+            .find(|blob| blob.hash == partial)
+            .context("partial blob not listed")?;
+        assert!(blob.available.as_ref().is_some_and(RangeSpec::is_empty));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blob_list_unreferenced() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+        let client = node.client();
+
+        let tagged = db
+            .import_bytes(Bytes::from_static(b"tagged"), BlobFormat::RAW)
+            .await?;
+        db.set_tag(Tag::from("tagged".to_string()), Some(*tagged.inner()))
+            .await?;
+        let orphan = *db
+            .import_bytes(Bytes::from_static(b"orphan"), BlobFormat::RAW)
+            .await?
+            .hash();
+        let doc = client.docs.create().await?;
+        let author = client.authors.create().await?;
+        let in_doc = doc
+            .set_bytes(author, b"key".to_vec(), b"in doc".to_vec())
+            .await?;
+
+        let unreferenced = client
+            .blobs
+            .list_unreferenced()
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        let hashes = unreferenced
+            .iter()
+            .map(|blob| blob.hash)
+            .collect::<Vec<_>>();
+        assert_eq!(hashes, vec![orphan]);
+        assert_eq!(unreferenced[0].size, 6);
+        // listing does not delete anything
+        assert!(db.get(&orphan).is_some());
+        assert!(db.get(tagged.hash()).is_some());
+        assert!(db.get(&in_doc).is_some());
+        Ok(())
+    }
+
+    #[cfg(feature = "iroh-collection")]
+    #[tokio::test]
+    async fn test_share_collection() -> Result<()> {
+        use crate::collection::{Blob, Collection, ManifestEntry, ShareBundle};
+
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let a = db
+            .import_bytes(Bytes::from_static(b"child a"), BlobFormat::RAW)
+            .await?;
+        let missing = Hash::new(b"missing");
+        let blobs = vec![
+            Blob {
+                name: "a".into(),
+                hash: *a.hash(),
+            },
+            Blob {
+                name: "b".into(),
+                hash: missing,
+            },
+        ];
+        let root = Collection::new(blobs, 7)?.store(&db).await?;
+
+        let bundle = node.share_collection(*root.hash()).await?;
+        assert_eq!(bundle.ticket.hash(), *root.hash());
+        assert_eq!(bundle.ticket.format(), BlobFormat::COLLECTION);
+        assert_eq!(
+            bundle.manifest,
+            vec![
+                ManifestEntry {
+                    name: "a".into(),
+                    hash: *a.hash(),
+                    size: Some(7),
+                },
+                ManifestEntry {
+                    name: "b".into(),
+                    hash: missing,
+                    size: None,
+                },
+            ]
+        );
+
+        // a child can be fetched on its own from the same node
+        let child = bundle.child_ticket("a")?;
+        assert_eq!(child.hash(), *a.hash());
+        assert_eq!(child.format(), BlobFormat::RAW);
+        assert_eq!(child.node_addr(), bundle.ticket.node_addr());
+        assert!(bundle.child_ticket("c").is_err());
+
+        let bytes = postcard::to_stdvec(&bundle)?;
+        assert_eq!(postcard::from_bytes::<ShareBundle>(&bytes)?, bundle);
+
+        // a collection we don't have
+        assert!(node.share_collection(missing).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_collection() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let a = db
+            .import_bytes(Bytes::from_static(b"child a"), BlobFormat::RAW)
+            .await?;
+        let b = db
+            .import_bytes(Bytes::from_static(b"child b"), BlobFormat::RAW)
+            .await?;
+        let missing = Hash::new(b"missing");
+        let links = [*a.hash(), missing, *b.hash()]
+            .into_iter()
+            .collect::<iroh_bytes::collection::LinkSeq>();
+        let collection = db
+            .import_bytes(links.into_inner(), BlobFormat::COLLECTION)
+            .await?;
+
+        let events = node
+            .client()
+            .blobs
+            .validate_collection(*collection.hash())
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(events[0], ValidateProgress::Starting { total: 4 }));
+        let errors = events
+            .iter()
+            .filter_map(|e| match e {
+                ValidateProgress::Done { id, error } => Some((*id, error.is_some())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![(0, false), (1, false), (2, true), (3, false)]);
+        assert!(matches!(
+            events[events.len() - 2],
+            ValidateProgress::CollectionSummary {
+                children: 3,
+                present: 2,
+                valid: 2,
+                missing: 1
+            }
+        ));
+        assert!(matches!(events.last(), Some(ValidateProgress::AllDone)));
+
+        // a collection we don't have
+        let events = node
+            .client()
+            .blobs
+            .validate_collection(missing)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(events[..], [ValidateProgress::Abort(_)]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gc_keeps_touched_blobs() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node_with(db.clone(), |builder| {
+            builder.gc_policy(GcPolicy::Interval(Duration::from_millis(50)))
+        })
+        .await?;
+        let touched = db
+            .import_bytes(Bytes::from_static(b"touched"), BlobFormat::RAW)
+            .await?;
+        let untouched = db
+            .import_bytes(Bytes::from_static(b"untouched"), BlobFormat::RAW)
+            .await?;
+        let (touched_hash, untouched_hash) = (*touched.hash(), *untouched.hash());
+        node.client().blobs.touch(vec![touched_hash]).await?;
+        drop((touched, untouched));
+
+        // neither blob is tagged, so the untouched one is collected by the next gc run
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while db.contains(&untouched_hash) != EntryStatus::NotFound {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("untouched blob was not collected")?;
+        // wait for another run, which cleared the live set again
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(db.contains(&touched_hash), EntryStatus::Complete);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_unreferenced_uses_gc_roots() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) =
+            spawn_node_with(db.clone(), |builder| builder.gc_keep_most_served(1)).await?;
+        let mut hashes = Vec::new();
+        for data in [&b"served"[..], b"touched", b"unreferenced"] {
+            let tag = db.import_bytes(Bytes::from(data), BlobFormat::RAW).await?;
+            hashes.push(*tag.hash());
+        }
+        let [served, touched, unreferenced] = hashes[..] else {
+            unreachable!()
+        };
+        node.serve_stats().record(served, 6);
+        node.client().blobs.touch(vec![touched]).await?;
+
+        // the blobs that gc keeps are not listed
+        let listed = node
+            .client()
+            .blobs
+            .list_unreferenced()
+            .await?
+            .map_ok(|res| res.hash)
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(listed, vec![unreferenced]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idle_shutdown() -> Result<()> {
+        let (node, drop_guard) = spawn_node(mem_store()).await?;
+        assert_eq!(node.idle_remaining(), None);
+        drop(drop_guard);
+
+        let timeout = Duration::from_millis(1500);
+        let (node, _drop_guard) =
+            spawn_node_with(mem_store(), |builder| builder.idle_shutdown(Some(timeout))).await?;
+        // the clock only moves when advanced, or when the runtime has nothing else to do
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_millis(1000)).await;
+        assert!(node.idle_remaining().unwrap() <= Duration::from_millis(500));
+        // an rpc request restarts the timeout
+        node.client().node.status().await?;
+        assert!(node.idle_remaining().unwrap() > Duration::from_millis(1000));
+        tokio::time::advance(Duration::from_millis(1000)).await;
+        assert!(!node.cancel_token().is_cancelled());
+
+        tokio::time::timeout(Duration::from_secs(5), node.clone())
+            .await
+            .context("node did not shut down")?
+            .ok();
+        assert!(node.cancel_token().is_cancelled());
+        assert_eq!(node.idle_remaining(), Some(Duration::ZERO));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blob_validate_concurrency() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let mut tags = Vec::new();
+        for i in 0..5u8 {
+            let data = Bytes::from(vec![i; 1024 * (i as usize + 1)]);
+            tags.push(db.import_bytes(data, BlobFormat::RAW).await?);
+        }
+
+        for concurrency in [Some(1), Some(3), None] {
+            let events = node
+                .client()
+                .blobs
+                .validate_with_concurrency(false, concurrency)
+                .await?
+                .try_collect::<Vec<_>>()
+                .await?;
+            assert!(matches!(events[0], ValidateProgress::Starting { total: 5 }));
+            let mut done = events
+                .iter()
+                .filter_map(|e| match e {
+                    ValidateProgress::Done { id, error } => Some((*id, error.is_some())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            done.sort();
+            assert_eq!(done, (0..5).map(|id| (id, false)).collect::<Vec<_>>());
+            assert!(matches!(events.last(), Some(ValidateProgress::AllDone)));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "flat-db")]
+    #[tokio::test]
+    async fn test_verify_import_mismatch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db =
+            crate::baomap::flat::Store::load(dir.path(), dir.path(), dir.path(), &test_runtime())
+                .await?;
+
+        // large enough to not be kept in memory
+        let tag = db
+            .import_bytes(Bytes::from(vec![1u8; 1024 * 100]), BlobFormat::RAW)
+            .await?;
+        verify_import(&db, *tag.hash()).await?;
+
+        // the data on disk no longer matches the hash
+        let data_path = dir.path().join(format!("{}.data", hex::encode(tag.hash())));
+        std::fs::write(&data_path, vec![2u8; 1024 * 100])?;
+        let err = verify_import(&db, *tag.hash()).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("verification of imported blob {} failed", tag.hash())
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "flat-db")]
+    #[tokio::test]
+    async fn test_blob_validate_repair() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db =
+            crate::baomap::flat::Store::load(dir.path(), dir.path(), dir.path(), &test_runtime())
+                .await?;
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        // large enough to not be kept in memory
+        let valid = db
+            .import_bytes(Bytes::from(vec![1u8; 1024 * 100]), BlobFormat::RAW)
+            .await?;
+        let invalid = db
+            .import_bytes(Bytes::from(vec![2u8; 1024 * 100]), BlobFormat::RAW)
+            .await?;
+        let data_path = dir
+            .path()
+            .join(format!("{}.data", hex::encode(invalid.hash())));
+        std::fs::write(&data_path, vec![3u8; 1024 * 100])?;
+
+        // without repair, the invalid blob is only reported
+        let events = node
+            .client()
+            .blobs
+            .validate(false)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        let errors = events
+            .iter()
+            .filter(|e| matches!(e, ValidateProgress::Done { error: Some(_), .. }))
+            .count();
+        assert_eq!(errors, 1);
+        assert!(db.get(invalid.hash()).is_some());
+
+        let events = node
+            .client()
+            .blobs
+            .validate(true)
+            .await?
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert!(matches!(events.last(), Some(ValidateProgress::AllDone)));
+        assert!(db.get(invalid.hash()).is_none());
+        assert!(db.get(valid.hash()).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aliases() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let blob = db
+            .import_bytes(Bytes::from_static(b"v1"), BlobFormat::RAW)
+            .await?;
+        assert_eq!(node.resolve_alias("latest"), None);
+        // tags outside of the alias namespace are no aliases
+        db.set_tag(Tag::from(String::from("latest")), Some(*blob.inner()))
+            .await?;
+        assert_eq!(node.resolve_alias("latest"), None);
+        assert!(node.list_aliases().is_empty());
+        node.set_alias("latest", *blob.inner()).await?;
+        assert_eq!(node.resolve_alias("latest"), Some(*blob.inner()));
+        assert_eq!(
+            node.list_aliases(),
+            vec![("latest".to_string(), *blob.inner())]
+        );
+
+        let handler = AliasGetHandler::new(db.clone());
+        let request = handler.handle(None, Bytes::from_static(b"latest")).await?;
+        assert_eq!(request, GetRequest::single(*blob.hash()));
+        db.set_tag(Tag::from(String::from("other")), Some(*blob.inner()))
+            .await?;
+        assert!(handler
+            .handle(None, Bytes::from_static(b"other"))
+            .await
+            .is_err());
+        assert!(handler
+            .handle(None, Bytes::from_static(b"unknown"))
+            .await
+            .is_err());
+
+        let collection = HashAndFormat(*blob.hash(), BlobFormat::COLLECTION);
+        node.set_alias("latest", collection).await?;
+        let request = handler.handle(None, Bytes::from_static(b"latest")).await?;
+        assert_eq!(request, GetRequest::all(*blob.hash()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reprovide_tick() -> Result<()> {
+        let db = mem_store();
+        let (node, _drop_guard) = spawn_node(db.clone()).await?;
+
+        let (tx, mut rx) = mpsc::channel(16);
+        node.on_reprovide_tick(Duration::from_millis(50), move |roots| {
+            let tx = tx.clone();
+            async move {
+                tx.send(roots).await.ok();
+            }
+            .boxed()
+        });
+        assert!(rx.recv().await.unwrap().is_empty());
+
+        let tag = db
+            .import_bytes(Bytes::from_static(b"reprovide me"), BlobFormat::RAW)
+            .await?;
+        let expected = *tag.inner();
+        db.set_tag(Tag::from("root".to_string()), Some(expected))
+            .await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !rx.recv().await.unwrap().contains(&expected) {}
+        })
+        .await?;
+
+        node.shutdown();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while rx.recv().await.is_some() {}
+        })
+        .await?;
+        Ok(())
+    }
 }
@@ -7,31 +7,44 @@
 //! response, while others like provide have a stream of responses.
 //!
 //! Note that this is subject to change. The RPC protocol is not yet stable.
-use std::{collections::HashMap, fmt, net::SocketAddr, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap, fmt, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration,
+};
 
 use bytes::Bytes;
 use derive_more::{From, TryInto};
-use iroh_bytes::util::{BlobFormat, SetTagOption, Tag};
-pub use iroh_bytes::{protocol::RequestToken, provider::GetProgress, Hash};
+use iroh_bytes::util::{BlobFormat, RpcError, SetTagOption, Tag};
+pub use iroh_bytes::{
+    protocol::{RangeSpec, RequestToken, RevokedToken},
+    provider::GetProgress,
+    Hash,
+};
 use iroh_gossip::proto::util::base32;
 use iroh_net::{
     key::PublicKey,
-    magic_endpoint::{ConnectionInfo, PeerAddr},
+    magic_endpoint::{ConnectionInfo, DerpRegionInfo, PeerAddr},
 };
 
 use iroh_sync::{
     store::GetFilter,
-    sync::{NamespaceId, SignedEntry},
-    AuthorId,
+    sync::{Capability, GossipSecret, NamespaceId, SignedEntry},
+    AuthorId, ContentStatus,
 };
 use quic_rpc::{
-    message::{Msg, RpcMsg, ServerStreaming, ServerStreamingMsg},
+    message::{BidiStreaming, BidiStreamingMsg, Msg, RpcMsg, ServerStreaming, ServerStreamingMsg},
     Service,
 };
 use serde::{Deserialize, Serialize};
 
-pub use iroh_bytes::{baomap::ValidateProgress, provider::AddProgress, util::RpcResult};
+pub use iroh_bytes::{
+    baomap::{TreeInfo, ValidateProgress},
+    provider::{AddProgress, ConnectionTransfers, Event as ProviderEvent, TransferInfo},
+    util::RpcResult,
+};
 
+pub use crate::connection_stats::ConnectionStats;
+pub use crate::node::Event as NodeEvent;
+pub use crate::serve_stats::BlobServeStats;
 use crate::sync_engine::{LiveEvent, LiveStatus};
 
 /// A 32-byte key or token
@@ -55,6 +68,9 @@ pub struct BlobAddPathRequest {
     pub tag: SetTagOption,
     /// Whether to wrap the added data in a collection
     pub wrap: WrapOption,
+    /// If true, read back every imported file and verify it against its hash before
+    /// reporting it as done.
+    pub verify_on_import: bool,
 }
 
 /// Whether to wrap the added data in a collection.
@@ -77,6 +93,78 @@ impl ServerStreamingMsg<ProviderService> for BlobAddPathRequest {
     type Response = AddProgress;
 }
 
+/// A request to the node to add many small blobs in one request.
+///
+/// After this request, the blobs are sent as [`BlobAddStreamUpdate::Blob`] updates, followed
+/// by [`BlobAddStreamUpdate::Finish`]. The node imports each blob as soon as it arrives and
+/// reports the result with a [`BlobAddStreamResponse`], so neither side has to hold the whole
+/// batch in memory. A blob that fails to import is reported with
+/// [`BlobAddStreamResponse::Failed`] and does not abort the batch. The blobs are only tagged
+/// once the batch is finished.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobAddStreamRequest {
+    /// If set, a collection of all added blobs is created once the batch is finished, and
+    /// tagged with this option. The names of the blobs must be unique in this case.
+    ///
+    /// If not set, every added blob is tagged with an automatically created tag.
+    pub collection: Option<SetTagOption>,
+}
+
+impl Msg<ProviderService> for BlobAddStreamRequest {
+    type Pattern = BidiStreaming;
+}
+
+impl BidiStreamingMsg<ProviderService> for BlobAddStreamRequest {
+    type Update = BlobAddStreamUpdate;
+    type Response = BlobAddStreamResponse;
+}
+
+/// Update for a [`BlobAddStreamRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlobAddStreamUpdate {
+    /// A single blob to add.
+    Blob {
+        /// The name of the blob, used to match the response and as the name in the collection.
+        name: String,
+        /// The content of the blob.
+        data: Bytes,
+    },
+    /// All blobs were sent.
+    ///
+    /// If the update stream ends without this, the request fails and nothing is tagged.
+    Finish,
+}
+
+/// Response to [`BlobAddStreamRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlobAddStreamResponse {
+    /// A blob was added.
+    Added {
+        /// The name of the blob.
+        name: String,
+        /// The hash of the blob.
+        hash: Hash,
+        /// The size of the blob.
+        size: u64,
+    },
+    /// A blob could not be added.
+    Failed {
+        /// The name of the blob.
+        name: String,
+        /// The reason why the blob could not be added.
+        error: RpcError,
+    },
+    /// The collection of all added blobs was created.
+    Collection {
+        /// The hash of the collection.
+        hash: Hash,
+        /// The tag of the collection.
+        tag: Tag,
+    },
+    /// The request was aborted, blobs added before are kept.
+    Abort(RpcError),
+}
+
 /// A request to the node to download and share the data specified by the hash.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlobDownloadRequest {
@@ -128,6 +216,10 @@ impl ServerStreamingMsg<ProviderService> for BlobDownloadRequest {
 pub struct BlobValidateRequest {
     /// If true, remove invalid data
     pub repair: bool,
+    /// How many blobs to validate at the same time
+    ///
+    /// Defaults to the number of CPUs of the node.
+    pub concurrency: Option<usize>,
 }
 
 impl Msg<ProviderService> for BlobValidateRequest {
@@ -138,7 +230,42 @@ impl ServerStreamingMsg<ProviderService> for BlobValidateRequest {
     type Response = ValidateProgress;
 }
 
-/// List all blobs, including collections
+/// A request to the node to validate a collection and all its children
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobValidateCollectionRequest {
+    /// The hash of the collection
+    pub hash: Hash,
+}
+
+impl Msg<ProviderService> for BlobValidateCollectionRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for BlobValidateCollectionRequest {
+    type Response = ValidateProgress;
+}
+
+/// A request to the node to validate a single blob, with fine grained progress
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobValidateOneRequest {
+    /// The hash of the blob
+    pub hash: Hash,
+    /// The number of bytes between progress updates
+    ///
+    /// Rounded down to whole chunk groups. Defaults to
+    /// [`DEFAULT_VALIDATE_PROGRESS_INTERVAL`](iroh_bytes::baomap::DEFAULT_VALIDATE_PROGRESS_INTERVAL).
+    pub progress_interval: Option<u64>,
+}
+
+impl Msg<ProviderService> for BlobValidateOneRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for BlobValidateOneRequest {
+    type Response = ValidateProgress;
+}
+
+/// List all blobs, including collections and partial blobs
 #[derive(Debug, Serialize, Deserialize)]
 pub struct BlobListRequest;
 
@@ -151,6 +278,10 @@ pub struct BlobListResponse {
     pub hash: Hash,
     /// The size of the blob
     pub size: u64,
+    /// The ranges of the blob that are available, if known
+    ///
+    /// This is [`RangeSpec::all`] for complete blobs.
+    pub available: Option<RangeSpec>,
 }
 
 impl Msg<ProviderService> for BlobListRequest {
@@ -184,6 +315,30 @@ impl ServerStreamingMsg<ProviderService> for BlobListIncompleteRequest {
     type Response = BlobListIncompleteResponse;
 }
 
+/// List all blobs that are not reachable from any tag, pin or document entry
+///
+/// These are the blobs that the next garbage collection would delete. Listing them does not
+/// delete anything.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobListUnreferencedRequest;
+
+/// A response to a list unreferenced blobs request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobListUnreferencedResponse {
+    /// The hash of the blob
+    pub hash: Hash,
+    /// The size of the blob
+    pub size: u64,
+}
+
+impl Msg<ProviderService> for BlobListUnreferencedRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for BlobListUnreferencedRequest {
+    type Response = RpcResult<BlobListUnreferencedResponse>;
+}
+
 /// List all collections
 ///
 /// Lists all collections that have been explicitly added to the database.
@@ -216,6 +371,72 @@ impl ServerStreamingMsg<ProviderService> for BlobListCollectionsRequest {
     type Response = BlobListCollectionsResponse;
 }
 
+/// The encoding of a collection listing, see [`CollectionListingRequest`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListingFormat {
+    /// A JSON array
+    #[default]
+    Json,
+    /// A MessagePack array, with the fields of the entries as maps
+    MsgPack,
+}
+
+impl ListingFormat {
+    /// Encode the entries of a collection listing in this format
+    pub fn encode(self, entries: &[CollectionListingEntry]) -> anyhow::Result<Bytes> {
+        let bytes = match self {
+            ListingFormat::Json => serde_json::to_vec(entries)?,
+            ListingFormat::MsgPack => rmp_serde::to_vec_named(entries)?,
+        };
+        Ok(bytes.into())
+    }
+
+    /// Decode the entries of a collection listing in this format
+    pub fn decode(self, bytes: &[u8]) -> anyhow::Result<Vec<CollectionListingEntry>> {
+        let entries = match self {
+            ListingFormat::Json => serde_json::from_slice(bytes)?,
+            ListingFormat::MsgPack => rmp_serde::from_slice(bytes)?,
+        };
+        Ok(entries)
+    }
+}
+
+/// A child of a collection in a collection listing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionListingEntry {
+    /// The name of the child
+    ///
+    /// For collections without names, this is the index of the child.
+    pub name: String,
+    /// The hash of the child, in the string encoding used for tickets
+    pub hash: String,
+    /// The size of the child, if it is stored on the node
+    pub size: Option<u64>,
+}
+
+/// Get a listing of the children of a collection
+///
+/// The listing is encoded in a format that can be read without knowing the collection
+/// format, for consumers which are not written in Rust.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionListingRequest {
+    /// Hash of the collection
+    pub hash: Hash,
+    /// The encoding of the listing
+    pub format: ListingFormat,
+}
+
+/// A response to a collection listing request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionListingResponse {
+    /// The encoded array of [`CollectionListingEntry`]
+    pub listing: Bytes,
+}
+
+impl RpcMsg<ProviderService> for CollectionListingRequest {
+    type Response = RpcResult<CollectionListingResponse>;
+}
+
 /// List all collections
 ///
 /// Lists all collections that have been explicitly added to the database.
@@ -252,6 +473,51 @@ impl RpcMsg<ProviderService> for BlobDeleteBlobRequest {
     type Response = RpcResult<()>;
 }
 
+/// Mark blobs as recently accessed, without transferring them
+///
+/// This keeps the blobs from being garbage collected for a while, see
+/// [`Builder::gc_touch_ttl`](crate::node::Builder::gc_touch_ttl).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobTouchRequest {
+    /// Hashes of the blobs to touch
+    pub hashes: Vec<Hash>,
+}
+
+impl RpcMsg<ProviderService> for BlobTouchRequest {
+    type Response = RpcResult<()>;
+}
+
+/// Inspect the outboard tree of a blob, for debugging
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobTreeRequest {
+    /// Hash of the blob to inspect
+    pub hash: Hash,
+    /// Whether to include the hash pairs of all nodes, level by level
+    pub levels: bool,
+}
+
+impl RpcMsg<ProviderService> for BlobTreeRequest {
+    type Response = RpcResult<TreeInfo>;
+}
+
+/// Get the most served blobs of the node
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobStatsRequest {
+    /// The maximum number of blobs to return
+    pub limit: usize,
+}
+
+impl RpcMsg<ProviderService> for BlobStatsRequest {
+    type Response = RpcResult<BlobStatsResponse>;
+}
+
+/// Response to [`BlobStatsRequest`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlobStatsResponse {
+    /// The most served blobs, ordered by serve count and then by bytes sent
+    pub blobs: Vec<(Hash, BlobServeStats)>,
+}
+
 /// Delete a tag
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeleteTagRequest {
@@ -303,6 +569,39 @@ impl RpcMsg<ProviderService> for NodeConnectionInfoRequest {
     type Response = RpcResult<NodeConnectionInfoResponse>;
 }
 
+/// Get the QUIC statistics of the connections to a specific node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodePeerStatsRequest {
+    /// The node identifier
+    pub node_id: PublicKey,
+}
+
+/// A response to a peer stats request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NodePeerStatsResponse {
+    /// The statistics of the connections in use with the node, empty if there are none
+    pub connections: Vec<ConnectionStats>,
+}
+
+impl RpcMsg<ProviderService> for NodePeerStatsRequest {
+    type Response = NodePeerStatsResponse;
+}
+
+/// Get the status of the DERP regions configured for the node
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerpStatusRequest;
+
+/// A response to a DERP status request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerpStatusResponse {
+    /// The status of each configured region, ordered by region id
+    pub regions: Vec<DerpRegionInfo>,
+}
+
+impl RpcMsg<ProviderService> for DerpStatusRequest {
+    type Response = RpcResult<DerpStatusResponse>;
+}
+
 /// A request to shutdown the node
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NodeShutdownRequest {
@@ -324,6 +623,61 @@ impl RpcMsg<ProviderService> for NodeStatusRequest {
     type Response = RpcResult<NodeStatusResponse>;
 }
 
+/// Subscribe to the events of the node
+///
+/// The node keeps the most recent events in a ring buffer, so a consumer that reconnects can
+/// catch up on the events it missed by passing the sequence number of the next event it
+/// expects, together with the instance of the node it got the sequence number from.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeEventsRequest {
+    /// Sequence number of the first event to send. `None` only sends new events.
+    ///
+    /// Sequence numbers start at zero when the node starts. If this is ahead of the node,
+    /// all buffered events are sent.
+    pub since: Option<u64>,
+    /// The instance of the node that `since` belongs to, see [`NodeEventsResponse`].
+    ///
+    /// If the node has a different instance, it restarted since, and the sequence number
+    /// refers to events of the previous instance. All events of the current instance are
+    /// sent then, starting with a gap from zero if some are no longer buffered.
+    pub instance: Option<u64>,
+}
+
+impl Msg<ProviderService> for NodeEventsRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for NodeEventsRequest {
+    type Response = NodeEventsResponse;
+}
+
+/// The response to a [`NodeEventsRequest`]
+///
+/// Every response carries the instance of the node, a random number that is chosen when the
+/// node starts. Sequence numbers are only unique within an instance, so a changed instance
+/// tells a consumer that the node restarted and the sequence numbers started over.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum NodeEventsResponse {
+    /// An event of the node
+    Event {
+        /// The instance of the node
+        instance: u64,
+        /// The sequence number of the event
+        seq: u64,
+        /// The event
+        event: NodeEvent,
+    },
+    /// Events that are no longer buffered and were dropped for this subscription
+    Gap {
+        /// The instance of the node
+        instance: u64,
+        /// Sequence number of the first dropped event
+        from: u64,
+        /// Sequence number after the last dropped event
+        to: u64,
+    },
+}
+
 /// The response to a version request
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NodeStatusResponse {
@@ -333,6 +687,82 @@ pub struct NodeStatusResponse {
     pub listen_addrs: Vec<SocketAddr>,
     /// The version of the node
     pub version: String,
+    /// The number of running operations that stream progress, e.g. adding or validating
+    /// blobs
+    pub progress_operations: u64,
+    /// The bytes currently held by the buffers of transfers to other nodes
+    pub transfer_memory_in_use: u64,
+}
+
+/// A request to get the configuration and limits of the node
+///
+/// See [`NodeConfigResponse`] for the response.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeConfigRequest;
+
+impl RpcMsg<ProviderService> for NodeConfigRequest {
+    type Response = RpcResult<NodeConfigResponse>;
+}
+
+/// The response to a config request
+///
+/// Limits that are `None` are not enforced by the node.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeConfigResponse {
+    /// The maximum size of a value that can be set in a document, in bytes
+    pub max_value_size: Option<u64>,
+    /// The maximum size of a blob that can be added or downloaded, in bytes
+    pub max_blob_size: Option<u64>,
+    /// The maximum total size of the blob store, in bytes
+    pub quota: Option<u64>,
+    /// Whether the secret keys of authors and documents can be exported
+    pub allow_key_export: bool,
+    /// The ALPN protocols the node accepts connections for
+    pub supported_alpns: Vec<String>,
+    /// The version of the node
+    pub version: String,
+    /// The maximum number of operations that stream progress at the same time
+    pub max_progress_operations: u64,
+    /// The maximum number of bidirectional streams a peer can have open per connection
+    pub max_concurrent_streams: u64,
+}
+
+/// A cheap liveness probe
+///
+/// This is answered directly by the rpc handler without touching the store
+/// or the network, so it is suitable for frequent polling by load balancers.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeHealthRequest;
+
+impl RpcMsg<ProviderService> for NodeHealthRequest {
+    type Response = NodeHealthResponse;
+}
+
+/// The response to a health request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeHealthResponse {
+    /// The version of the node
+    pub version: String,
+}
+
+/// A readiness probe
+///
+/// See [`NodeReadyResponse`] for the response.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeReadyRequest;
+
+impl RpcMsg<ProviderService> for NodeReadyRequest {
+    type Response = RpcResult<NodeReadyResponse>;
+}
+
+/// The response to a ready request
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeReadyResponse {
+    /// True if the node is not shutting down and has at least one address
+    /// at which it can be reached.
+    pub ready: bool,
+    /// The peer id and currently known addresses of this node.
+    pub addr: PeerAddr,
 }
 
 /// A request to watch for the node status
@@ -397,11 +827,29 @@ pub struct AuthorCreateResponse {
     pub author_id: AuthorId,
 }
 
-/// Import author from secret key
+/// The kind of an ed25519 key passed as [`KeyBytes`]
+///
+/// Secret and public keys can not be told apart from their bytes alone, so the kind has
+/// to be given explicitly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// A secret key, which allows to write
+    Secret,
+    /// A public key, which only identifies the key pair
+    Public,
+}
+
+/// Import author from its key
+///
+/// Authors can only be imported from their secret key. Importing a public key succeeds only
+/// if the node already has the secret key for it, and fails with an explanatory error
+/// otherwise.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AuthorImportRequest {
-    /// The secret key for the author
+    /// The key for the author
     pub key: KeyBytes,
+    /// Whether `key` is a secret or a public key
+    pub kind: KeyKind,
 }
 
 impl RpcMsg<ProviderService> for AuthorImportRequest {
@@ -415,13 +863,38 @@ pub struct AuthorImportResponse {
     pub author_id: AuthorId,
 }
 
+/// Remove an author key from the node
+///
+/// This fails if the author has written entries to any document, unless `force` is set.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthorRemoveRequest {
+    /// The id of the author to remove
+    pub author_id: AuthorId,
+    /// Remove the author even if it has written entries
+    pub force: bool,
+}
+
+impl RpcMsg<ProviderService> for AuthorRemoveRequest {
+    type Response = RpcResult<AuthorRemoveResponse>;
+}
+
+/// Response to [`AuthorRemoveRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthorRemoveResponse {}
+
 /// Intended capability for document share tickets
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum ShareMode {
     /// Read-only access
+    ///
+    /// The ticket only contains the [`NamespaceId`]. It allows to sync and verify entries of
+    /// the document, but not to create new ones.
     Read,
     /// Write access
+    ///
+    /// The ticket contains the namespace secret key. Anyone holding the ticket can write to the
+    /// document and share write access further, and this cannot be revoked.
     Write,
 }
 
@@ -481,18 +954,39 @@ pub struct DocCreateResponse {
     pub id: NamespaceId,
 }
 
-/// Contains both a key (either secret or public) to a document, and a list of peers to join.
+/// Contains both a capability (either the secret key or the id) for a document, and a list of
+/// peers to join.
+///
+/// See [`ShareMode`] for what each capability allows.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DocTicket {
-    /// either a public or private key
-    pub key: KeyBytes,
+    /// either a write or a read-only capability
+    pub capability: Capability,
     /// a list of peers
     pub peers: Vec<PeerAddr>,
+    /// the secret to authenticate gossip messages with, if enabled for the document
+    ///
+    /// See [`GossipSecret`] for what this protects against. Tickets created before gossip
+    /// secrets were added don't have this field, they are still parsed by
+    /// [`DocTicket::from_bytes`].
+    pub gossip_secret: Option<GossipSecret>,
+}
+
+/// The format of [`DocTicket`]s before they had a gossip secret.
+#[derive(Deserialize)]
+struct DocTicketV0 {
+    capability: Capability,
+    peers: Vec<PeerAddr>,
 }
+
 impl DocTicket {
     /// Create a new doc ticket
-    pub fn new(key: KeyBytes, peers: Vec<PeerAddr>) -> Self {
-        Self { key, peers }
+    pub fn new(capability: Capability, peers: Vec<PeerAddr>) -> Self {
+        Self {
+            capability,
+            peers,
+            gossip_secret: None,
+        }
     }
     /// Serialize the ticket to a byte array.
     pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
@@ -500,9 +994,16 @@ impl DocTicket {
         Ok(bytes)
     }
     /// Parse ticket from a byte array.
+    ///
+    /// Tickets without a gossip secret, from before the field was added, are parsed as well.
     pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
-        let slf = postcard::from_bytes(bytes)?;
-        Ok(slf)
+        match postcard::from_bytes(bytes) {
+            Ok(slf) => Ok(slf),
+            Err(err) => match postcard::from_bytes::<DocTicketV0>(bytes) {
+                Ok(DocTicketV0 { capability, peers }) => Ok(Self::new(capability, peers)),
+                Err(_) => Err(err.into()),
+            },
+        }
     }
 }
 impl FromStr for DocTicket {
@@ -604,13 +1105,140 @@ impl RpcMsg<ProviderService> for DocStopSyncRequest {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocStopSyncResponse {}
 
+/// Set the default author of a doc, which is used for writes without an explicit author.
+///
+/// Changing the default author rotates it for new writes only: the entries of the previous
+/// default author are kept, and it can still be used explicitly.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetDefaultAuthorRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// The new default author, or `None` to remove the default author
+    pub author_id: Option<AuthorId>,
+}
+
+impl RpcMsg<ProviderService> for DocSetDefaultAuthorRequest {
+    type Response = RpcResult<DocSetDefaultAuthorResponse>;
+}
+
+/// Response to [`DocSetDefaultAuthorRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetDefaultAuthorResponse {}
+
+/// Get the default author of a doc.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetDefaultAuthorRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+}
+
+impl RpcMsg<ProviderService> for DocGetDefaultAuthorRequest {
+    type Response = RpcResult<DocGetDefaultAuthorResponse>;
+}
+
+/// Response to [`DocGetDefaultAuthorRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetDefaultAuthorResponse {
+    /// The default author, if set
+    pub author_id: Option<AuthorId>,
+}
+
+/// Set how long the entries of a doc are kept.
+///
+/// The node periodically deletes entries that are older than the retention, and the deletions
+/// are synced to other peers. See [`iroh_sync::store::Store::prune_expired`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetRetentionRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// The new retention, or `None` to keep entries forever
+    pub retention: Option<Duration>,
+}
+
+impl RpcMsg<ProviderService> for DocSetRetentionRequest {
+    type Response = RpcResult<DocSetRetentionResponse>;
+}
+
+/// Response to [`DocSetRetentionRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetRetentionResponse {}
+
+/// Get how long the entries of a doc are kept.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetRetentionRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+}
+
+impl RpcMsg<ProviderService> for DocGetRetentionRequest {
+    type Response = RpcResult<DocGetRetentionResponse>;
+}
+
+/// Response to [`DocGetRetentionRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetRetentionResponse {
+    /// The retention, if set
+    pub retention: Option<Duration>,
+}
+
+/// Enable or disable authentication of the gossip messages of a doc.
+///
+/// When enabled, a [`GossipSecret`] is created for the doc if it has none yet. It is included
+/// in tickets created with [`DocShareRequest`], and gossip messages without a valid MAC are
+/// dropped. Disabling removes the secret.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetGossipAuthRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Whether gossip messages are authenticated
+    pub enabled: bool,
+}
+
+impl RpcMsg<ProviderService> for DocSetGossipAuthRequest {
+    type Response = RpcResult<DocSetGossipAuthResponse>;
+}
+
+/// Response to [`DocSetGossipAuthRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetGossipAuthResponse {}
+
+/// Pause syncing of all documents
+///
+/// See [`crate::sync_engine::LiveSync::pause`] for details.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocsPauseRequest {}
+
+impl RpcMsg<ProviderService> for DocsPauseRequest {
+    type Response = RpcResult<DocsPauseResponse>;
+}
+
+/// Response to [`DocsPauseRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocsPauseResponse {}
+
+/// Resume syncing of all documents
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocsResumeRequest {}
+
+impl RpcMsg<ProviderService> for DocsResumeRequest {
+    type Response = RpcResult<DocsResumeResponse>;
+}
+
+/// Response to [`DocsResumeRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocsResumeResponse {}
+
 /// Set an entry in a document
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocSetRequest {
     /// The document id
     pub doc_id: NamespaceId,
     /// Author of this entry.
-    pub author_id: AuthorId,
+    ///
+    /// If `None`, the default author of the document is used, see
+    /// [`DocSetDefaultAuthorRequest`]. If the document has no default author yet, a new
+    /// author is created and set as default.
+    pub author_id: Option<AuthorId>,
     /// Key of this entry.
     pub key: Vec<u8>,
     /// Value of this entry.
@@ -630,6 +1258,102 @@ pub struct DocSetResponse {
     pub entry: SignedEntry,
 }
 
+/// Set an entry in a document, with the value streamed in chunks.
+///
+/// After this request, the value is sent as [`DocSetStreamUpdate::Chunk`] updates, followed by
+/// [`DocSetStreamUpdate::Finish`]. The node imports the value into the blob store without
+/// holding it in memory, and reports its progress while receiving it. Use this instead of
+/// [`DocSetRequest`] for values that are too large for a single message.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocSetStreamRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Author of this entry.
+    ///
+    /// If `None`, the default author of the document is used, as for [`DocSetRequest`].
+    pub author_id: Option<AuthorId>,
+    /// Key of this entry.
+    pub key: Vec<u8>,
+}
+
+impl Msg<ProviderService> for DocSetStreamRequest {
+    type Pattern = BidiStreaming;
+}
+
+impl BidiStreamingMsg<ProviderService> for DocSetStreamRequest {
+    type Update = DocSetStreamUpdate;
+    type Response = RpcResult<DocSetStreamResponse>;
+}
+
+/// Update for a [`DocSetStreamRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DocSetStreamUpdate {
+    /// The next chunk of the value.
+    Chunk(Bytes),
+    /// The value is complete.
+    ///
+    /// If the update stream ends without this, the request fails and no entry is created.
+    Finish,
+}
+
+/// Response to [`DocSetStreamRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum DocSetStreamResponse {
+    /// The node received the value up to this offset.
+    Progress {
+        /// The number of bytes received so far.
+        offset: u64,
+    },
+    /// The value was imported and the entry was created.
+    Done {
+        /// The newly-created entry.
+        entry: SignedEntry,
+    },
+}
+
+/// Move an entry in a document to a different key
+///
+/// See [`iroh_sync::store::Store::move_key`] for details.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocMoveRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Author of the entry.
+    pub author_id: AuthorId,
+    /// Current key of the entry.
+    pub from: Vec<u8>,
+    /// New key of the entry.
+    pub to: Vec<u8>,
+}
+
+impl RpcMsg<ProviderService> for DocMoveRequest {
+    type Response = RpcResult<DocMoveResponse>;
+}
+
+/// Response to [`DocMoveRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocMoveResponse {
+    /// The entry at the new key.
+    pub entry: SignedEntry,
+}
+
+/// How entries whose content is not available locally are handled when listing entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ContentResolution {
+    /// Return all entries, with their [`ContentStatus`]
+    #[default]
+    IncludeMissing,
+    /// Only return entries whose content is complete locally
+    SkipMissing,
+    /// Download missing content from the peers of the document, waiting up to `timeout` for
+    /// each entry. Entries whose content is still missing are returned with their
+    /// [`ContentStatus`].
+    FetchMissing {
+        /// How long to wait for the content of a single entry
+        timeout: Duration,
+    },
+}
+
 /// Get entries from a document
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DocGetManyRequest {
@@ -637,6 +1361,8 @@ pub struct DocGetManyRequest {
     pub doc_id: NamespaceId,
     /// Filter entries by this [`GetFilter`]
     pub filter: GetFilter,
+    /// How to handle entries whose content is not available locally
+    pub content: ContentResolution,
 }
 
 impl Msg<ProviderService> for DocGetManyRequest {
@@ -652,6 +1378,59 @@ impl ServerStreamingMsg<ProviderService> for DocGetManyRequest {
 pub struct DocGetManyResponse {
     /// The document entry
     pub entry: SignedEntry,
+    /// Whether the content of the entry is available locally
+    pub content_status: ContentStatus,
+}
+
+/// Get the entries for a list of keys from a document
+///
+/// The response streams a [`DocGetManyResponse`] for each entry found, keys without entries
+/// are skipped.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocGetKeysRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// The keys to look up
+    pub keys: Vec<Vec<u8>>,
+    /// Only return entries of this author, otherwise return the entries of all authors
+    pub author: Option<AuthorId>,
+}
+
+impl Msg<ProviderService> for DocGetKeysRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for DocGetKeysRequest {
+    type Response = RpcResult<DocGetManyResponse>;
+}
+
+/// Get the content of the entries of a document as a tar archive
+///
+/// The archive contains a file for the latest entry of each key that starts with `prefix`,
+/// with the key as path. Deleted entries and keys that are not valid relative paths are
+/// skipped. Content that is missing locally is downloaded from the peers of the document
+/// first. The response streams the archive in chunks.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocExportTarRequest {
+    /// The document id
+    pub doc_id: NamespaceId,
+    /// Only export entries whose key starts with this prefix
+    pub prefix: Vec<u8>,
+}
+
+impl Msg<ProviderService> for DocExportTarRequest {
+    type Pattern = ServerStreaming;
+}
+
+impl ServerStreamingMsg<ProviderService> for DocExportTarRequest {
+    type Response = RpcResult<DocExportTarResponse>;
+}
+
+/// Response to [`DocExportTarRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DocExportTarResponse {
+    /// The next chunk of the tar archive
+    pub chunk: Bytes,
 }
 
 /// Get entries from a document
@@ -732,6 +1511,98 @@ pub struct NodeStatsResponse {
     pub stats: HashMap<String, CounterStats>,
 }
 
+/// Revoke a request token before its expiry
+///
+/// Fails if the authorization handler of the node does not support revoking tokens.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevokeTokenRequest {
+    /// The token to revoke
+    pub token: RevokedToken,
+}
+
+impl RpcMsg<ProviderService> for RevokeTokenRequest {
+    type Response = RpcResult<RevokeTokenResponse>;
+}
+
+/// Response to [`RevokeTokenRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RevokeTokenResponse {
+    /// Whether the revoked tokens changed, false if the token was already revoked or expired
+    pub revoked: bool,
+}
+
+/// List the revoked request tokens that have not expired yet
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListRevokedRequest;
+
+impl RpcMsg<ProviderService> for ListRevokedRequest {
+    type Response = RpcResult<ListRevokedResponse>;
+}
+
+/// Response to [`ListRevokedRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListRevokedResponse {
+    /// The revoked tokens
+    pub tokens: Vec<RevokedToken>,
+}
+
+/// List the transfers the node is currently serving to other nodes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListTransfersRequest;
+
+impl RpcMsg<ProviderService> for ListTransfersRequest {
+    type Response = ListTransfersResponse;
+}
+
+/// Response to [`ListTransfersRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListTransfersResponse {
+    /// The transfers in flight, ordered by connection id and request id
+    pub transfers: Vec<TransferListEntry>,
+    /// The number of transfers in flight per connection, ordered by connection id
+    pub connections: Vec<ConnectionTransfersEntry>,
+}
+
+/// A transfer in a [`ListTransfersResponse`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransferListEntry {
+    /// The transfer
+    pub transfer: TransferInfo,
+    /// The node the data is sent to, if its node id is known
+    pub peer: Option<PublicKey>,
+}
+
+/// The transfers in flight on a connection in a [`ListTransfersResponse`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionTransfersEntry {
+    /// The transfers in flight on the connection
+    pub connection: ConnectionTransfers,
+    /// The node at the other end of the connection, if its node id is known
+    pub peer: Option<PublicKey>,
+}
+
+/// Cancel a transfer the node is serving, as listed by [`ListTransfersRequest`]
+///
+/// The stream of the transfer is reset and a transfer aborted event is emitted.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CancelTransferRequest {
+    /// The id of the connection of the transfer
+    pub connection_id: u64,
+    /// The id of the request of the transfer
+    pub request_id: u64,
+}
+
+impl RpcMsg<ProviderService> for CancelTransferRequest {
+    type Response = CancelTransferResponse;
+}
+
+/// Response to [`CancelTransferRequest`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CancelTransferResponse {
+    /// Whether the transfer was cancelled, false if it was not in flight (anymore)
+    pub cancelled: bool,
+}
+
 /// The RPC service for the iroh provider process.
 #[derive(Debug, Clone)]
 pub struct ProviderService;
@@ -741,20 +1612,39 @@ pub struct ProviderService;
 #[derive(strum::Display, Debug, Serialize, Deserialize, From, TryInto)]
 pub enum ProviderRequest {
     NodeStatus(NodeStatusRequest),
+    NodeConfig(NodeConfigRequest),
+    NodeHealth(NodeHealthRequest),
+    NodeReady(NodeReadyRequest),
     NodeStats(NodeStatsRequest),
     NodeShutdown(NodeShutdownRequest),
     NodeConnections(NodeConnectionsRequest),
     NodeConnectionInfo(NodeConnectionInfoRequest),
+    NodePeerStats(NodePeerStatsRequest),
+    NodeDerpStatus(DerpStatusRequest),
     NodeWatch(NodeWatchRequest),
+    NodeEvents(NodeEventsRequest),
+    NodeRevokeToken(RevokeTokenRequest),
+    NodeListRevoked(ListRevokedRequest),
+    NodeListTransfers(ListTransfersRequest),
+    NodeCancelTransfer(CancelTransferRequest),
 
     BlobRead(BytesGetRequest),
     BlobAddPath(BlobAddPathRequest),
+    BlobAddStream(BlobAddStreamRequest),
+    BlobAddStreamUpdate(BlobAddStreamUpdate),
     BlobDownload(BlobDownloadRequest),
     BlobList(BlobListRequest),
     BlobListIncomplete(BlobListIncompleteRequest),
+    BlobListUnreferenced(BlobListUnreferencedRequest),
     BlobListCollections(BlobListCollectionsRequest),
+    BlobCollectionListing(CollectionListingRequest),
     BlobDeleteBlob(BlobDeleteBlobRequest),
     BlobValidate(BlobValidateRequest),
+    BlobValidateCollection(BlobValidateCollectionRequest),
+    BlobValidateOne(BlobValidateOneRequest),
+    BlobTouch(BlobTouchRequest),
+    BlobTree(BlobTreeRequest),
+    BlobStats(BlobStatsRequest),
 
     DeleteTag(DeleteTagRequest),
     ListTags(ListTagsRequest),
@@ -764,16 +1654,29 @@ pub enum ProviderRequest {
     DocCreate(DocCreateRequest),
     DocImport(DocImportRequest),
     DocSet(DocSetRequest),
+    DocSetStream(DocSetStreamRequest),
+    DocSetStreamUpdate(DocSetStreamUpdate),
+    DocMove(DocMoveRequest),
     DocGet(DocGetManyRequest),
     DocGetOne(DocGetOneRequest),
+    DocGetKeys(DocGetKeysRequest),
+    DocExportTar(DocExportTarRequest),
     DocStartSync(DocStartSyncRequest),
     DocStopSync(DocStopSyncRequest),
+    DocSetGossipAuth(DocSetGossipAuthRequest),
+    DocSetDefaultAuthor(DocSetDefaultAuthorRequest),
+    DocGetDefaultAuthor(DocGetDefaultAuthorRequest),
+    DocSetRetention(DocSetRetentionRequest),
+    DocGetRetention(DocGetRetentionRequest),
     DocShare(DocShareRequest),
     DocSubscribe(DocSubscribeRequest),
+    DocsPause(DocsPauseRequest),
+    DocsResume(DocsResumeRequest),
 
     AuthorList(AuthorListRequest),
     AuthorCreate(AuthorCreateRequest),
     AuthorImport(AuthorImportRequest),
+    AuthorRemove(AuthorRemoveRequest),
 }
 
 /// The response enum, listing all possible responses.
@@ -781,19 +1684,34 @@ pub enum ProviderRequest {
 #[derive(Debug, Serialize, Deserialize, From, TryInto)]
 pub enum ProviderResponse {
     NodeStatus(RpcResult<NodeStatusResponse>),
+    NodeConfig(RpcResult<NodeConfigResponse>),
+    NodeHealth(NodeHealthResponse),
+    NodeReady(RpcResult<NodeReadyResponse>),
     NodeStats(RpcResult<NodeStatsResponse>),
     NodeConnections(RpcResult<NodeConnectionsResponse>),
     NodeConnectionInfo(RpcResult<NodeConnectionInfoResponse>),
+    NodePeerStats(NodePeerStatsResponse),
+    NodeDerpStatus(RpcResult<DerpStatusResponse>),
     NodeShutdown(()),
     NodeWatch(NodeWatchResponse),
+    NodeEvents(NodeEventsResponse),
+    NodeRevokeToken(RpcResult<RevokeTokenResponse>),
+    NodeListRevoked(RpcResult<ListRevokedResponse>),
+    NodeListTransfers(ListTransfersResponse),
+    NodeCancelTransfer(CancelTransferResponse),
 
     BlobRead(RpcResult<BlobReadResponse>),
     BlobAddPath(AddProgress),
+    BlobAddStream(BlobAddStreamResponse),
     BlobDownload(GetProgress),
     BlobList(BlobListResponse),
     BlobListIncomplete(BlobListIncompleteResponse),
+    BlobListUnreferenced(RpcResult<BlobListUnreferencedResponse>),
     BlobListCollections(BlobListCollectionsResponse),
+    BlobCollectionListing(RpcResult<CollectionListingResponse>),
     BlobValidate(ValidateProgress),
+    BlobTree(RpcResult<TreeInfo>),
+    BlobStats(RpcResult<BlobStatsResponse>),
 
     ListTags(ListTagsResponse),
     DeleteTag(RpcResult<()>),
@@ -803,16 +1721,27 @@ pub enum ProviderResponse {
     DocCreate(RpcResult<DocCreateResponse>),
     DocImport(RpcResult<DocImportResponse>),
     DocSet(RpcResult<DocSetResponse>),
+    DocSetStream(RpcResult<DocSetStreamResponse>),
+    DocMove(RpcResult<DocMoveResponse>),
     DocGet(RpcResult<DocGetManyResponse>),
+    DocExportTar(RpcResult<DocExportTarResponse>),
     DocGetOne(RpcResult<DocGetOneResponse>),
     DocShare(RpcResult<DocShareResponse>),
     DocStartSync(RpcResult<DocStartSyncResponse>),
     DocStopSync(RpcResult<DocStopSyncResponse>),
+    DocSetGossipAuth(RpcResult<DocSetGossipAuthResponse>),
+    DocSetDefaultAuthor(RpcResult<DocSetDefaultAuthorResponse>),
+    DocGetDefaultAuthor(RpcResult<DocGetDefaultAuthorResponse>),
+    DocSetRetention(RpcResult<DocSetRetentionResponse>),
+    DocGetRetention(RpcResult<DocGetRetentionResponse>),
     DocSubscribe(RpcResult<DocSubscribeResponse>),
+    DocsPause(RpcResult<DocsPauseResponse>),
+    DocsResume(RpcResult<DocsResumeResponse>),
 
     AuthorList(RpcResult<AuthorListResponse>),
     AuthorCreate(RpcResult<AuthorCreateResponse>),
     AuthorImport(RpcResult<AuthorImportResponse>),
+    AuthorRemove(RpcResult<AuthorRemoveResponse>),
 }
 
 impl Service for ProviderService {
@@ -0,0 +1,174 @@
+//! Statistics about how often the blobs of a node are served to other nodes.
+//!
+//! The counters are kept in memory and updated for every blob sent by the iroh-bytes
+//! protocol, see [`Event::BlobServed`](iroh_bytes::provider::Event::BlobServed). They can
+//! be persisted to a file, which is only rewritten if the counters changed since the last
+//! save.
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use iroh_bytes::Hash;
+use serde::{Deserialize, Serialize};
+
+/// How often a single blob was served, and how many bytes were sent for it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobServeStats {
+    /// The number of times the blob was sent.
+    pub count: u64,
+    /// The number of bytes sent, including the size header and the outboard data.
+    pub bytes: u64,
+}
+
+/// Per blob serve counters, shared between the tasks of a node.
+#[derive(Debug, Clone, Default)]
+pub struct ServeStats {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    blobs: Mutex<HashMap<Hash, BlobServeStats>>,
+    /// Whether the counters changed since they were last loaded or saved.
+    dirty: AtomicBool,
+}
+
+impl ServeStats {
+    /// Loads the counters from `path`, or starts with empty counters if the file does not
+    /// exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let blobs = if path.exists() {
+            let data = std::fs::read(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let blobs: Vec<(Hash, BlobServeStats)> = postcard::from_bytes(&data)
+                .with_context(|| format!("invalid serve stats in {}", path.display()))?;
+            blobs.into_iter().collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            inner: Arc::new(Inner {
+                blobs: Mutex::new(blobs),
+                dirty: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Saves the counters to `path`, if they changed since the last save.
+    ///
+    /// The counters are written to a temporary file first, which is then renamed, so the
+    /// file is never left half written. Returns whether the file was written.
+    pub async fn save(&self, path: &Path) -> Result<bool> {
+        if !self.inner.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let blobs: Vec<(Hash, BlobServeStats)> = {
+            let blobs = self.inner.blobs.lock().unwrap();
+            blobs.iter().map(|(hash, stats)| (*hash, *stats)).collect()
+        };
+        let res = async {
+            let data = postcard::to_stdvec(&blobs)?;
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, data)
+                .await
+                .with_context(|| format!("failed to write {}", tmp.display()))?;
+            tokio::fs::rename(&tmp, path)
+                .await
+                .with_context(|| format!("failed to rename {}", tmp.display()))?;
+            anyhow::Ok(())
+        }
+        .await;
+        if res.is_err() {
+            // try again on the next save
+            self.inner.dirty.store(true, Ordering::SeqCst);
+        }
+        res.map(|_| true)
+    }
+
+    /// Records that `bytes` were sent for the blob `hash`.
+    pub fn record(&self, hash: Hash, bytes: u64) {
+        let mut blobs = self.inner.blobs.lock().unwrap();
+        let stats = blobs.entry(hash).or_default();
+        stats.count += 1;
+        stats.bytes += bytes;
+        self.inner.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Removes the counters of the blob `hash`, e.g. because it was deleted.
+    pub fn remove(&self, hash: &Hash) {
+        if self.inner.blobs.lock().unwrap().remove(hash).is_some() {
+            self.inner.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Keeps only the counters of the blobs for which `f` returns true.
+    ///
+    /// This is used to drop the counters of blobs that garbage collection removed.
+    pub fn retain(&self, mut f: impl FnMut(&Hash) -> bool) {
+        let mut blobs = self.inner.blobs.lock().unwrap();
+        let len = blobs.len();
+        blobs.retain(|hash, _| f(hash));
+        if blobs.len() != len {
+            self.inner.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The counters of a single blob, if it was ever served.
+    pub fn get(&self, hash: &Hash) -> Option<BlobServeStats> {
+        self.inner.blobs.lock().unwrap().get(hash).copied()
+    }
+
+    /// The `n` most served blobs, ordered by serve count and then by bytes sent.
+    pub fn top(&self, n: usize) -> Vec<(Hash, BlobServeStats)> {
+        let mut blobs: Vec<_> = {
+            let blobs = self.inner.blobs.lock().unwrap();
+            blobs.iter().map(|(hash, stats)| (*hash, *stats)).collect()
+        };
+        blobs.sort_unstable_by(|(a_hash, a), (b_hash, b)| {
+            (b.count, b.bytes, a_hash).cmp(&(a.count, a.bytes, b_hash))
+        });
+        blobs.truncate(n);
+        blobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serve_stats_top_and_persist() -> Result<()> {
+        let stats = ServeStats::default();
+        let a = Hash::new(b"a");
+        let b = Hash::new(b"b");
+        let c = Hash::new(b"c");
+        stats.record(a, 10);
+        stats.record(b, 100);
+        stats.record(b, 100);
+        stats.record(c, 5);
+        stats.record(c, 5);
+        let served = |count, bytes| BlobServeStats { count, bytes };
+        assert_eq!(stats.get(&a), Some(served(1, 10)));
+        assert_eq!(stats.get(&Hash::new(b"d")), None);
+        assert_eq!(stats.top(2), vec![(b, served(2, 200)), (c, served(2, 10))]);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("serve-stats.postcard");
+        assert!(ServeStats::load(&path)?.top(10).is_empty());
+        assert!(stats.save(&path).await?);
+        // nothing changed, so nothing is written
+        assert!(!stats.save(&path).await?);
+        let loaded = ServeStats::load(&path)?;
+        assert_eq!(loaded.top(10), stats.top(10));
+
+        // removing counters is saved as well
+        stats.remove(&a);
+        stats.retain(|hash| hash != &c);
+        assert_eq!(stats.top(10), vec![(b, served(2, 200))]);
+        assert!(stats.save(&path).await?);
+        assert_eq!(ServeStats::load(&path)?.top(10), stats.top(10));
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Deterministic assignment of content to the nodes of a cluster.
+//!
+//! A cluster of provider nodes can share the content of a namespace, e.g. a document, so
+//! that each node only holds a part of it. Every hash is assigned to one node with
+//! rendezvous hashing (highest random weight): each node gets a score for the hash, and the
+//! node with the highest score is responsible for it.
+//!
+//! The assignment only depends on the hash and the set of nodes, so all nodes agree on it
+//! without coordination. When a node joins, it only takes over hashes from the other nodes,
+//! and when a node leaves, only its hashes are moved to the other nodes.
+use bao_tree::blake3;
+use iroh_bytes::Hash;
+use iroh_net::key::PublicKey;
+
+/// Returns true if `me` is responsible for `hash` in a cluster of `me` and `peers`.
+///
+/// `peers` may or may not contain `me`.
+pub fn responsible_for<'a>(
+    hash: &Hash,
+    me: &PublicKey,
+    peers: impl IntoIterator<Item = &'a PublicKey>,
+) -> bool {
+    let mine = score(hash, me);
+    peers
+        .into_iter()
+        .filter(|peer| *peer != me)
+        .all(|peer| (score(hash, peer), peer.as_bytes()) < (mine, me.as_bytes()))
+}
+
+/// The score of `peer` for `hash`, the peer with the highest score is responsible.
+fn score(hash: &Hash, peer: &PublicKey) -> u64 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(peer.as_bytes());
+    hasher.update(hash.as_bytes());
+    let score = hasher.finalize();
+    u64::from_be_bytes(score.as_bytes()[..8].try_into().unwrap())
+}
+
+/// Which part of the content of a cluster a node holds.
+///
+/// When set on a node with [`Builder::shard_policy`](crate::node::Builder::shard_policy), or
+/// with the `shard_peers` option in the config of `iroh start`, the node only downloads the
+/// content of document entries it is responsible for, and the garbage collector does not
+/// keep the content of document entries that it is not responsible for. Content that is
+/// tagged is kept regardless of this policy.
+#[derive(Debug, Clone)]
+pub struct ShardPolicy {
+    peers: Vec<PublicKey>,
+}
+
+impl ShardPolicy {
+    /// Creates a policy for a cluster of the given nodes.
+    ///
+    /// The node that uses the policy is always part of the cluster, whether it is contained
+    /// in `peers` or not.
+    pub fn new(peers: impl IntoIterator<Item = PublicKey>) -> Self {
+        let mut res = Self { peers: Vec::new() };
+        for peer in peers {
+            res.add_peer(peer);
+        }
+        res
+    }
+
+    /// Adds a node to the cluster.
+    pub fn add_peer(&mut self, peer: PublicKey) {
+        if !self.peers.contains(&peer) {
+            self.peers.push(peer);
+        }
+    }
+
+    /// Removes a node from the cluster.
+    pub fn remove_peer(&mut self, peer: &PublicKey) {
+        self.peers.retain(|p| p != peer);
+    }
+
+    /// The nodes of the cluster.
+    pub fn peers(&self) -> &[PublicKey] {
+        &self.peers
+    }
+
+    /// Returns true if `me` is responsible for `hash`.
+    pub fn responsible_for(&self, hash: &Hash, me: &PublicKey) -> bool {
+        responsible_for(hash, me, &self.peers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use iroh_net::key::SecretKey;
+
+    use super::*;
+
+    fn peers(n: usize) -> Vec<PublicKey> {
+        (0..n).map(|_| SecretKey::generate().public()).collect()
+    }
+
+    fn hashes(n: u32) -> Vec<Hash> {
+        (0..n).map(|i| Hash::new(i.to_be_bytes())).collect()
+    }
+
+    /// The index of the peer responsible for `hash`, checking that there is exactly one.
+    fn owner(hash: &Hash, peers: &[PublicKey]) -> usize {
+        let owners = peers
+            .iter()
+            .enumerate()
+            .filter(|(_, peer)| responsible_for(hash, peer, peers))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        assert_eq!(owners.len(), 1, "{hash} has owners {owners:?}");
+        owners[0]
+    }
+
+    #[test]
+    fn shard_assignment_is_stable_and_balanced() {
+        let peers = peers(5);
+        let hashes = hashes(5000);
+        let mut counts = [0usize; 5];
+        for hash in &hashes {
+            let index = owner(hash, &peers);
+            counts[index] += 1;
+            // the order of the peers does not matter
+            let mut reversed = peers.clone();
+            reversed.reverse();
+            assert_eq!(reversed[owner(hash, &reversed)], peers[index]);
+        }
+        for count in counts {
+            assert!((800..1200).contains(&count), "unbalanced: {counts:?}");
+        }
+    }
+
+    #[test]
+    fn shard_assignment_join_and_leave() {
+        let mut peers = peers(4);
+        let hashes = hashes(2000);
+        let before = hashes
+            .iter()
+            .map(|hash| peers[owner(hash, &peers)])
+            .collect::<Vec<_>>();
+
+        // a joining node only takes over hashes, about 1/5 of them
+        let new = SecretKey::generate().public();
+        peers.push(new);
+        let mut moved = 0;
+        for (hash, old) in hashes.iter().zip(&before) {
+            let now = peers[owner(hash, &peers)];
+            if now != *old {
+                assert_eq!(now, new);
+                moved += 1;
+            }
+        }
+        assert!((300..500).contains(&moved), "moved {moved}");
+
+        // a leaving node only gives away its own hashes
+        let left = peers.remove(0);
+        peers.pop();
+        for (hash, old) in hashes.iter().zip(&before) {
+            let now = peers[owner(hash, &peers)];
+            if *old != left {
+                assert_eq!(now, *old);
+            }
+        }
+
+        let policy = ShardPolicy::new(peers.clone());
+        for hash in &hashes {
+            let owner = peers[owner(hash, &peers)];
+            assert!(policy.responsible_for(hash, &owner));
+        }
+    }
+}
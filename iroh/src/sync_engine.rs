@@ -2,6 +2,8 @@
 //!
 //! [`iroh_sync::Replica`] is also called documents here.
 
+use std::sync::Arc;
+
 use anyhow::anyhow;
 use iroh_bytes::{baomap::Store as BaoStore, util::runtime::Handle};
 use iroh_gossip::net::Gossip;
@@ -12,10 +14,14 @@ use iroh_sync::{
 };
 
 use crate::downloader::Downloader;
+use crate::shard::ShardPolicy;
+use crate::util::idle::IdleTimer;
 
+mod discovery;
 mod live;
 pub mod rpc;
 
+pub use discovery::{Discovery, NoDiscovery};
 pub use iroh_sync::net::SYNC_ALPN;
 pub use live::*;
 
@@ -38,8 +44,21 @@ impl<S: Store> SyncEngine<S> {
     /// engine with [`Self::start_sync`], then new entries inserted locally will be sent to peers
     /// through iroh-gossip.
     ///
-    /// The engine will also register for [`Replica::subscribe`] events to download content for new
-    /// entries from peers.
+    /// If `auto_download` is true, the engine will also register for [`Replica::subscribe`] events
+    /// to download content for new entries from peers.
+    ///
+    /// `discovery` is asked for peers of documents that have no known peers, or for which the
+    /// sync with all known peers failed. Pass [`NoDiscovery`] to only use explicitly given peers.
+    ///
+    /// Up to `gossip_dedup_capacity` recently received gossip entries are remembered, so that
+    /// duplicates relayed by other neighbors are dropped without verifying them again.
+    ///
+    /// If a `shard_policy` is given, only content this node is responsible for is downloaded.
+    ///
+    /// Local entries are broadcast according to `broadcast_policy`.
+    ///
+    /// Syncs, downloads and joins of gossip swarms keep `idle` from shutting the node down.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn<B: BaoStore>(
         rt: Handle,
         endpoint: MagicEndpoint,
@@ -47,6 +66,12 @@ impl<S: Store> SyncEngine<S> {
         store: S,
         bao_store: B,
         downloader: Downloader,
+        auto_download: bool,
+        discovery: Arc<dyn Discovery>,
+        gossip_dedup_capacity: usize,
+        shard_policy: Option<ShardPolicy>,
+        broadcast_policy: BroadcastPolicy,
+        idle: Option<Arc<IdleTimer>>,
     ) -> Self {
         let live = LiveSync::spawn(
             rt.clone(),
@@ -55,6 +80,12 @@ impl<S: Store> SyncEngine<S> {
             gossip,
             bao_store,
             downloader,
+            auto_download,
+            discovery,
+            gossip_dedup_capacity,
+            shard_policy,
+            broadcast_policy,
+            idle,
         );
         Self {
             live,
@@ -68,6 +99,7 @@ impl<S: Store> SyncEngine<S> {
     ///
     /// If `peers` is non-empty, it will both do an initial set-reconciliation sync with each peer,
     /// and join an iroh-gossip swarm with these peers to receive and broadcast document updates.
+    /// If `peers` is empty, the [`Discovery`] of the engine is asked for peers.
     pub async fn start_sync(
         &self,
         namespace: NamespaceId,
@@ -83,6 +115,18 @@ impl<S: Store> SyncEngine<S> {
         Ok(())
     }
 
+    /// Pause syncing of all documents, see [`LiveSync::pause`].
+    pub async fn pause(&self) -> anyhow::Result<()> {
+        self.live.pause().await?;
+        Ok(())
+    }
+
+    /// Resume syncing of all documents, see [`LiveSync::resume`].
+    pub async fn resume(&self) -> anyhow::Result<()> {
+        self.live.resume().await?;
+        Ok(())
+    }
+
     /// Shutdown the sync engine.
     pub async fn shutdown(&self) -> anyhow::Result<()> {
         self.live.shutdown().await?;
@@ -103,6 +147,14 @@ impl<S: Store> SyncEngine<S> {
             .ok_or_else(|| anyhow!("author not found"))
     }
 
+    /// Get the default [`Author`] of a replica.
+    ///
+    /// If the replica has no default author yet, a new author is created and set as default.
+    pub fn default_author(&self, id: &NamespaceId) -> anyhow::Result<Author> {
+        self.store
+            .get_or_create_default_author(id, &mut rand::rngs::OsRng {})
+    }
+
     /// Handle an incoming iroh-sync connection.
     pub async fn handle_connection(&self, conn: quinn::Connecting) -> anyhow::Result<()> {
         self.live.handle_connection(conn).await
@@ -0,0 +1,39 @@
+//! Finding peers for documents without a ticket.
+
+use std::fmt::Debug;
+
+use futures::{future::BoxFuture, FutureExt};
+use iroh_net::PeerAddr;
+use iroh_sync::sync::NamespaceId;
+
+/// A source of peers for documents.
+///
+/// The sync engine asks the discovery for peers when a document is synced without any
+/// known peers, or when the sync with all known peers of a document failed. This allows to
+/// bootstrap a document from e.g. a DNS TXT record, a DHT or a rendezvous server instead of a
+/// ticket.
+pub trait Discovery: Send + Sync + Debug + 'static {
+    /// Find peers that are likely to have the document `namespace`.
+    ///
+    /// Peers that we already failed to sync with are ignored.
+    fn find_peers(
+        &self,
+        namespace: NamespaceId,
+    ) -> BoxFuture<'static, anyhow::Result<Vec<PeerAddr>>>;
+}
+
+/// A [`Discovery`] that never finds any peers.
+///
+/// This is the default, documents can then only be synced with peers that are passed in
+/// explicitly, e.g. from a ticket.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDiscovery;
+
+impl Discovery for NoDiscovery {
+    fn find_peers(
+        &self,
+        _namespace: NamespaceId,
+    ) -> BoxFuture<'static, anyhow::Result<Vec<PeerAddr>>> {
+        futures::future::ok(Vec::new()).boxed()
+    }
+}
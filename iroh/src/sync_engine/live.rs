@@ -1,11 +1,18 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    num::NonZeroU32,
     sync::{atomic::AtomicU64, Arc},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-use crate::downloader::{DownloadKind, Downloader, PeerRole};
+use crate::downloader::{DownloadKind, Downloader, PeerInfo, PeerRole};
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::shard::ShardPolicy;
+use crate::sync_engine::Discovery;
+use crate::util::idle::{IdleGuard, IdleTimer};
 use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
 use flume::r#async::RecvStream;
 use futures::{
     future::{BoxFuture, Shared},
@@ -18,21 +25,30 @@ use iroh_bytes::{
     Hash,
 };
 use iroh_gossip::{
-    net::{Event, Gossip},
+    net::{Event, Gossip, MAX_MESSAGE_SIZE},
     proto::TopicId,
 };
+#[cfg(feature = "metrics")]
+use iroh_metrics::{inc, inc_by, observe};
 use iroh_net::{key::PublicKey, MagicEndpoint, PeerAddr};
+#[cfg(feature = "metrics")]
+use iroh_sync::metrics::Metrics as SyncMetrics;
 use iroh_sync::{
     net::{
         connect_and_sync, handle_connection, AbortReason, AcceptError, AcceptOutcome, ConnectError,
     },
     store,
-    sync::{Entry, InsertOrigin, NamespaceId, Replica, SignedEntry},
+    sync::{
+        Entry, GossipSecret, InsertError, InsertOrigin, NamespaceId, RecordIdentifier, Replica,
+        SignedEntry, ValidationFailure,
+    },
 };
+use lru_cache::LruCache;
 use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{self, mpsc, oneshot},
     task::JoinError,
+    time::Instant,
 };
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, debug_span, error, warn, Instrument};
@@ -40,16 +56,239 @@ use tracing::{debug, debug_span, error, warn, Instrument};
 pub use iroh_sync::ContentStatus;
 
 const CHANNEL_CAP: usize = 8;
+/// Maximum number of content downloads queued with the downloader at the same time.
+///
+/// Further downloads are kept in a backlog until earlier ones complete.
+const MAX_PENDING_DOWNLOADS: usize = 128;
+/// Default number of recently received gossip entries remembered to drop duplicates.
+///
+/// The same entry is usually relayed to us by several neighbors. Duplicates are dropped before
+/// their signatures are verified again. The least recently received entries are evicted when
+/// the capacity is reached, so memory use is bounded on busy documents. Should an evicted
+/// entry be delivered again, inserting it is a no-op, as the replica already has it.
+pub const DEFAULT_GOSSIP_DEDUP_CAPACITY: usize = 1024;
+/// Maximum size of the encoded entries of a batched broadcast.
+///
+/// Leaves room for the framing of iroh-gossip and the MAC of the message.
+const MAX_BATCH_SIZE: usize = MAX_MESSAGE_SIZE - 256;
+/// How long the entries of a transaction are held back at most, waiting for its last entry.
+///
+/// The insert events of a transaction are emitted right after each other, so this is only
+/// reached if the event of the last entry is lost.
+const MAX_TRANSACTION_HOLD: Duration = Duration::from_secs(1);
+/// How long joining a gossip swarm keeps the node from being idle, see
+/// [`crate::node::Builder::idle_shutdown`].
+const GOSSIP_JOIN_ACTIVITY: Duration = Duration::from_secs(30);
+
+/// How entries inserted locally are broadcast to the gossip swarm of a document.
+///
+/// The default broadcasts every entry on its own, right after it was inserted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastPolicy {
+    /// Entries inserted within this window after the first pending entry are combined into
+    /// a single [`Op::PutMany`] message.
+    pub batch_window: Duration,
+    /// Maximum number of broadcast messages per second and document.
+    ///
+    /// Entries inserted while the limit is reached are not dropped, but combined into the
+    /// next message.
+    pub max_rate: Option<NonZeroU32>,
+}
+
+impl BroadcastPolicy {
+    fn min_interval(&self) -> Duration {
+        match self.max_rate {
+            Some(rate) => Duration::from_secs(1) / rate.get(),
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Local entries of a document waiting to be broadcast.
+///
+/// Holds at most one entry per key and author, the latest one.
+#[derive(Debug, Default)]
+struct PendingBroadcast {
+    entries: Vec<SignedEntry>,
+    /// The record identifiers of `entries`.
+    ids: BTreeSet<RecordIdentifier>,
+    /// When the oldest of `entries` was inserted.
+    since: Option<Instant>,
+    /// Earliest time for the next message, according to [`BroadcastPolicy::max_rate`].
+    next_allowed: Option<Instant>,
+    /// Whether more entries of a transaction are to come, which are sent together.
+    in_transaction: bool,
+}
+
+impl PendingBroadcast {
+    /// Queue an entry, replacing a pending entry with the same key and author.
+    fn push(&mut self, entry: SignedEntry) {
+        self.since.get_or_insert_with(Instant::now);
+        if self.ids.insert(entry.entry().id().clone()) {
+            self.entries.push(entry);
+        } else if let Some(pending) = self
+            .entries
+            .iter_mut()
+            .find(|pending| pending.entry().id() == entry.entry().id())
+        {
+            *pending = entry;
+        }
+    }
+
+    /// When the pending entries are to be sent, if there are any.
+    fn due(&self, policy: &BroadcastPolicy) -> Option<Instant> {
+        if self.in_transaction {
+            return Some(self.since? + MAX_TRANSACTION_HOLD);
+        }
+        let due = self.since? + policy.batch_window;
+        Some(match self.next_allowed {
+            Some(next_allowed) => due.max(next_allowed),
+            None => due,
+        })
+    }
+
+    /// Take the oldest entries that fit into one message.
+    fn take_batch(&mut self) -> Vec<SignedEntry> {
+        let mut size = 0;
+        let mut len = 0;
+        for entry in &self.entries {
+            size += postcard::to_stdvec(entry).map_or(MAX_BATCH_SIZE, |data| data.len());
+            if len > 0 && size > MAX_BATCH_SIZE {
+                break;
+            }
+            len += 1;
+        }
+        let batch: Vec<_> = self.entries.drain(..len).collect();
+        for entry in &batch {
+            self.ids.remove(entry.entry().id());
+        }
+        if self.entries.is_empty() {
+            self.since = None;
+        }
+        batch
+    }
+}
+
+/// How far the clocks of other nodes are ahead of ours, learned from the send times of their
+/// gossip messages.
+///
+/// A message can not arrive before it was sent, so a send time ahead of our clock shows that
+/// the clock of the sender is ahead by at least the difference. Clocks that are behind ours can
+/// not be told apart from network delay, and are not corrected.
+#[derive(Debug, Default)]
+struct ClockSkew(HashMap<PublicKey, u64>);
+
+impl ClockSkew {
+    /// Returns the delay of a message that `from` sent at `sent_at` and that arrived at `now`,
+    /// corrected by how far the clock of `from` is ahead.
+    ///
+    /// All times are in microseconds, `sent_at` according to the clock of `from`.
+    fn delay(&mut self, from: PublicKey, sent_at: u64, now: u64) -> u64 {
+        let ahead = sent_at.saturating_sub(now);
+        let skew = self.0.entry(from).or_default();
+        // Clocks get adjusted, so earlier observations fade out.
+        *skew = ahead.max(*skew - *skew / 8);
+        (now + *skew).saturating_sub(sent_at)
+    }
+}
+
+/// Returns `time` in microseconds since the Unix epoch.
+fn system_time_micros(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_micros() as u64)
+}
+
+/// Version of the [`Op::PutMany`] messages.
+const PUT_MANY_VERSION: u8 = 1;
+
+/// The node that broadcast an [`Op::PutMany`] message, and when.
+///
+/// This is appended to the encoded op, inside the MAC of a [`GossipSecret`]. Peers that do
+/// not know about it decode the op and ignore the rest of the message, so it can be sent
+/// without knowing the versions of the peers in the swarm.
+///
+/// The send time lets peers measure how long entries take to propagate. It is in
+/// microseconds since the Unix epoch, according to the clock of `from`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SentBy {
+    /// The node that broadcast the entries.
+    from: PublicKey,
+    /// When the entries were broadcast.
+    sent_at: u64,
+}
 
 /// An iroh-sync operation
 ///
 /// This is the message that is broadcast over iroh-gossip.
+///
+/// New operations are only ever appended. Messages that can not be decoded, e.g. operations
+/// of newer versions of iroh, are dropped, and the entries are exchanged in the next sync.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Op {
     /// A new entry was inserted into the document.
+    ///
+    /// Only sent by older versions, new entries are broadcast as [`Op::PutMany`].
     Put(SignedEntry),
     /// A peer now has content available for a hash.
     ContentReady(Hash),
+    /// Several new entries were inserted into the document, see [`BroadcastPolicy`].
+    ///
+    /// Peers drop messages with a `version` they do not know.
+    PutMany {
+        /// The version of the message, currently 1.
+        version: u8,
+        /// The entries.
+        entries: Vec<SignedEntry>,
+    },
+}
+
+impl Op {
+    /// Encode the op as gossip message, followed by `sent` if given.
+    ///
+    /// If the namespace has a [`GossipSecret`], a MAC of the encoded op is appended.
+    fn to_gossip_message(
+        &self,
+        sent: Option<&SentBy>,
+        secret: Option<&GossipSecret>,
+    ) -> Result<Bytes> {
+        let mut message = postcard::to_stdvec(self)?;
+        if let Some(sent) = sent {
+            message = postcard::to_extend(sent, message)?;
+        }
+        if let Some(secret) = secret {
+            let mac = secret.mac(&message);
+            message.extend_from_slice(&mac);
+        }
+        Ok(message.into())
+    }
+
+    /// Decode a gossip message, and the [`SentBy`] that follows the op, if there is one.
+    ///
+    /// If the namespace has a [`GossipSecret`], the MAC is checked before the op is decoded,
+    /// and `None` is returned if it is missing or invalid.
+    fn from_gossip_message(
+        message: &[u8],
+        secret: Option<&GossipSecret>,
+    ) -> Result<Option<(Self, Option<SentBy>)>> {
+        let op = match secret {
+            None => message,
+            Some(secret) => {
+                let Some(len) = message.len().checked_sub(GossipSecret::MAC_LEN) else {
+                    return Ok(None);
+                };
+                let (op, mac) = message.split_at(len);
+                let mac = mac.try_into().expect("MAC_LEN bytes");
+                if !secret.verify(op, mac) {
+                    return Ok(None);
+                }
+                op
+            }
+        };
+        let (op, rest) = postcard::take_from_bytes(op)?;
+        // whatever follows is ignored if it can not be decoded, e.g. from a newer version
+        let sent = postcard::from_bytes(rest).ok();
+        Ok(Some((op, sent)))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +307,8 @@ pub struct LiveStatus {
     pub active: bool,
     /// Number of event listeners registered
     pub subscriptions: u64,
+    /// Whether syncing is paused, see [`LiveSync::pause`]
+    pub paused: bool,
 }
 
 #[derive(derive_more::Debug)]
@@ -88,6 +329,11 @@ enum ToActor<S: store::Store> {
     StopSync {
         namespace: NamespaceId,
     },
+    GossipSecretChanged {
+        namespace: NamespaceId,
+    },
+    Pause,
+    Resume,
     Shutdown,
     Subscribe {
         namespace: NamespaceId,
@@ -108,6 +354,11 @@ enum ToActor<S: store::Store> {
         peer: PublicKey,
         reply: sync::oneshot::Sender<AcceptOutcome<S>>,
     },
+    DownloadContent {
+        namespace: NamespaceId,
+        hash: Hash,
+        reply: sync::oneshot::Sender<bool>,
+    },
 }
 
 /// Whether to keep a live event callback active.
@@ -154,7 +405,7 @@ pub enum LiveEvent {
     SyncFinished(SyncEvent),
 }
 
-fn entry_to_content_status(entry: EntryStatus) -> ContentStatus {
+pub(crate) fn entry_to_content_status(entry: EntryStatus) -> ContentStatus {
     match entry {
         EntryStatus::Complete => ContentStatus::Complete,
         EntryStatus::Partial => ContentStatus::Incomplete,
@@ -174,6 +425,15 @@ impl<S: store::Store> LiveSync<S> {
     ///
     /// This spawn a background actor to handle gossip events and forward operations over broadcast
     /// messages.
+    ///
+    /// If `auto_download` is true, the content of entries received from peers is downloaded
+    /// automatically if it is missing and this node is responsible for it according to
+    /// `shard_policy`.
+    ///
+    /// Local entries are broadcast according to `broadcast_policy`.
+    ///
+    /// Syncs, downloads and joins of gossip swarms keep `idle` from shutting the node down.
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn<B: baomap::Store>(
         rt: Handle,
         endpoint: MagicEndpoint,
@@ -181,6 +441,12 @@ impl<S: store::Store> LiveSync<S> {
         gossip: Gossip,
         bao_store: B,
         downloader: Downloader,
+        auto_download: bool,
+        discovery: Arc<dyn Discovery>,
+        gossip_dedup_capacity: usize,
+        shard_policy: Option<ShardPolicy>,
+        broadcast_policy: BroadcastPolicy,
+        idle: Option<Arc<IdleTimer>>,
     ) -> Self {
         let (to_actor_tx, to_actor_rx) = mpsc::channel(CHANNEL_CAP);
         let me = base32::fmt_short(endpoint.peer_id());
@@ -189,6 +455,12 @@ impl<S: store::Store> LiveSync<S> {
             gossip,
             bao_store,
             downloader,
+            auto_download,
+            discovery,
+            gossip_dedup_capacity,
+            shard_policy,
+            broadcast_policy,
+            idle,
             replica_store,
             to_actor_rx,
             to_actor_tx.clone(),
@@ -246,6 +518,35 @@ impl<S: store::Store> LiveSync<S> {
         Ok(())
     }
 
+    /// Reload the gossip secret of a document, after it was changed in the store.
+    pub async fn gossip_secret_changed(&self, namespace: NamespaceId) -> Result<()> {
+        self.to_actor_tx
+            .send(ToActor::<S>::GossipSecretChanged { namespace })
+            .await?;
+        Ok(())
+    }
+
+    /// Pause syncing of all documents.
+    ///
+    /// While paused, no syncs with peers are started and new local entries are not broadcast.
+    /// The latest entry for each key and author is broadcast on resume.
+    /// The gossip swarms are not left, so incoming gossip messages and sync requests are still
+    /// handled. This is useful to save battery and bandwidth, e.g. while a mobile app is in the
+    /// background.
+    pub async fn pause(&self) -> Result<()> {
+        self.to_actor_tx.send(ToActor::<S>::Pause).await?;
+        Ok(())
+    }
+
+    /// Resume syncing after [`Self::pause`].
+    ///
+    /// To catch up on the changes missed while paused, all syncing documents are synced again
+    /// with the peers we know of.
+    pub async fn resume(&self) -> Result<()> {
+        self.to_actor_tx.send(ToActor::<S>::Resume).await?;
+        Ok(())
+    }
+
     /// Subscribes `cb` to events on this `namespace`.
     pub async fn subscribe<F>(&self, namespace: NamespaceId, cb: F) -> Result<RemovalToken>
     where
@@ -288,6 +589,22 @@ impl<S: store::Store> LiveSync<S> {
         Ok(status)
     }
 
+    /// Download the content `hash` of an entry of `namespace`, if it is not available locally.
+    ///
+    /// The content is requested from all peers we synced the document with. Returns whether
+    /// the content is available after the download.
+    pub async fn download_content(&self, namespace: NamespaceId, hash: Hash) -> Result<bool> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.to_actor_tx
+            .send(ToActor::<S>::DownloadContent {
+                namespace,
+                hash,
+                reply,
+            })
+            .await?;
+        Ok(reply_rx.await?)
+    }
+
     /// Handle an incoming iroh-sync connection.
     pub async fn handle_connection(&self, conn: quinn::Connecting) -> anyhow::Result<()> {
         self.to_actor_tx
@@ -304,11 +621,31 @@ struct Actor<S: store::Store, B: baomap::Store> {
     bao_store: B,
     downloader: Downloader,
     replica_store: S,
+    /// Whether to download missing content of entries received from peers.
+    auto_download: bool,
+    /// Which content to download, if this node only holds a part of the content.
+    shard_policy: Option<ShardPolicy>,
+    /// Source of peers for replicas without working peers.
+    discovery: Arc<dyn Discovery>,
+    /// Hashes of recently received gossip messages, to drop duplicates before verifying them.
+    recent_gossip: LruCache<Hash, ()>,
+    /// How far the clocks of the nodes we receive gossip from are ahead of ours.
+    clock_skew: ClockSkew,
+    /// How local entries are broadcast.
+    broadcast_policy: BroadcastPolicy,
+    /// Local entries waiting to be broadcast, by replica.
+    pending_broadcasts: HashMap<NamespaceId, PendingBroadcast>,
+    /// Gossip secrets of the syncing replicas, loaded from the store on first use.
+    gossip_secrets: HashMap<NamespaceId, Option<GossipSecret>>,
+    /// Shuts the node down when it is idle, if enabled.
+    idle: Option<Arc<IdleTimer>>,
 
     /// Set of replicas that we opened for sync or event subscriptions.
     open_replicas: HashSet<NamespaceId>,
     /// Set of replicas that are actively syncing.
     syncing_replicas: HashSet<NamespaceId>,
+    /// Whether syncing is paused, see [`LiveSync::pause`].
+    paused: bool,
 
     /// Events from replicas.
     replica_events: futures::stream::SelectAll<RecvStream<'static, (InsertOrigin, SignedEntry)>>,
@@ -334,9 +671,23 @@ struct Actor<S: store::Store, B: baomap::Store> {
     running_sync_accept:
         FuturesUnordered<BoxFuture<'static, Result<(NamespaceId, PublicKey), AcceptError>>>,
     /// Runnning download futures.
-    pending_downloads: FuturesUnordered<BoxFuture<'static, Option<(NamespaceId, Hash)>>>,
+    pending_downloads: FuturesUnordered<BoxFuture<'static, (Hash, bool)>>,
+    /// Hashes of the running downloads.
+    pending_download_hashes: HashSet<Hash>,
+    /// Replicas that requested each running or queued download.
+    download_namespaces: HashMap<Hash, HashSet<NamespaceId>>,
+    /// Downloads waiting for a free slot, in the order they were requested.
+    download_backlog: VecDeque<Hash>,
+    /// Peers to download from for each download in the backlog.
+    download_backlog_peers: HashMap<Hash, Vec<PeerInfo>>,
+    /// Requests waiting for a download to finish, see [`LiveSync::download_content`].
+    download_waiters: HashMap<Hash, Vec<sync::oneshot::Sender<bool>>>,
     /// Running gossip join futures.
     pending_joins: FuturesUnordered<BoxFuture<'static, (NamespaceId, Result<()>)>>,
+    /// Running peer discovery futures.
+    pending_discovery: FuturesUnordered<BoxFuture<'static, (NamespaceId, Result<Vec<PeerAddr>>)>>,
+    /// Replicas for which a peer discovery is running.
+    discovering: HashSet<NamespaceId>,
 
     /// External subscriptions to replica events.
     event_subscriptions: HashMap<NamespaceId, HashMap<u64, OnLiveEventCallback>>,
@@ -349,11 +700,18 @@ struct Actor<S: store::Store, B: baomap::Store> {
 pub struct RemovalToken(u64);
 
 impl<S: store::Store, B: baomap::Store> Actor<S, B> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         endpoint: MagicEndpoint,
         gossip: Gossip,
         bao_store: B,
         downloader: Downloader,
+        auto_download: bool,
+        discovery: Arc<dyn Discovery>,
+        gossip_dedup_capacity: usize,
+        shard_policy: Option<ShardPolicy>,
+        broadcast_policy: BroadcastPolicy,
+        idle: Option<Arc<IdleTimer>>,
         replica_store: S,
         to_actor_rx: mpsc::Receiver<ToActor<S>>,
         to_actor_tx: mpsc::Sender<ToActor<S>>,
@@ -366,7 +724,17 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
             bao_store,
             downloader,
             replica_store,
+            auto_download,
+            shard_policy,
+            discovery,
+            recent_gossip: LruCache::new(gossip_dedup_capacity),
+            clock_skew: ClockSkew::default(),
+            broadcast_policy,
+            idle,
+            pending_broadcasts: Default::default(),
+            gossip_secrets: Default::default(),
             syncing_replicas: Default::default(),
+            paused: false,
             open_replicas: Default::default(),
             to_actor_rx,
             to_actor_tx,
@@ -374,16 +742,24 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
             running_sync_connect: Default::default(),
             running_sync_accept: Default::default(),
             pending_joins: Default::default(),
+            pending_discovery: Default::default(),
+            discovering: Default::default(),
             replica_events: Default::default(),
             gossip_events,
             event_subscriptions: Default::default(),
             event_removal_id: Default::default(),
             pending_downloads: Default::default(),
+            pending_download_hashes: Default::default(),
+            download_namespaces: Default::default(),
+            download_backlog: Default::default(),
+            download_backlog_peers: Default::default(),
+            download_waiters: Default::default(),
         }
     }
 
     async fn run(&mut self) -> Result<()> {
         loop {
+            let broadcast_due = self.next_broadcast_due();
             tokio::select! {
                 biased;
                 msg = self.to_actor_rx.recv() => {
@@ -401,9 +777,22 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
                         Some(ToActor::StopSync { namespace }) => {
                             self.stop_sync(namespace).await?;
                         }
+                        Some(ToActor::GossipSecretChanged { namespace }) => {
+                            self.gossip_secrets.remove(&namespace);
+                        }
                         Some(ToActor::JoinPeers { namespace, peers }) => {
                             self.join_peers(namespace, peers).await?;
                         },
+                        Some(ToActor::Pause) => {
+                            debug!("pause");
+                            self.paused = true;
+                        },
+                        Some(ToActor::Resume) => {
+                            self.resume();
+                            if let Err(err) = self.flush_broadcasts().await {
+                                error!("Failed to broadcast entries: {err:?}");
+                            }
+                        },
                         Some(ToActor::Subscribe { namespace, cb, s }) => {
                             let result = self.subscribe(namespace, cb).await;
                             s.send(result).ok();
@@ -423,6 +812,9 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
                             let outcome = self.accept_sync_request(namespace, peer);
                             reply.send(outcome).ok();
                         },
+                        Some(ToActor::DownloadContent { namespace, hash, reply }) => {
+                            self.download_content(namespace, hash, reply).await;
+                        },
                     };
                 }
                 // new gossip message
@@ -437,6 +829,11 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
                         error!("Failed to process replica event: {err:?}");
                     }
                 }
+                _ = sleep_until(broadcast_due) => {
+                    if let Err(err) = self.flush_broadcasts().await {
+                        error!("Failed to broadcast entries: {err:?}");
+                    }
+                }
                 Some((namespace, peer, reason, res)) = self.running_sync_connect.next() => {
                     self.on_sync_via_connect_finished(namespace, peer, reason, res).await;
 
@@ -452,19 +849,13 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
                     }
                     // TODO: maintain some join state
                 }
-                Some(res) = self.pending_downloads.next() => {
-                    if let Some((namespace, hash)) = res {
-                        if let Some(subs) = self.event_subscriptions.get_mut(&namespace) {
-                            let event = LiveEvent::ContentReady { hash };
-                            notify_all(subs, event).await;
-                        }
-
-                        // Inform our neighbors that we have new content ready.
-                        let op = Op::ContentReady(hash);
-                        let message = postcard::to_stdvec(&op)?.into();
-                        self.gossip.broadcast_neighbors(namespace.into(), message).await?;
+                Some((namespace, res)) = self.pending_discovery.next() => {
+                    if let Err(err) = self.on_discovery_finished(namespace, res).await {
+                        error!(?namespace, ?err, "failed to join discovered peers");
                     }
-
+                }
+                Some((hash, success)) = self.pending_downloads.next() => {
+                    self.on_download_finished(hash, success).await?;
                 }
             }
         }
@@ -499,6 +890,13 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         let Some(replica) = self.get_replica_if_syncing(&namespace) else {
             return;
         };
+        // Remember the peer, so that we sync with it once we are resumed.
+        if self.paused {
+            self.sync_state
+                .entry((namespace, peer))
+                .or_insert(SyncState::None);
+            return;
+        }
         // Do not initiate the sync if we are already syncing or did previously sync successfully.
         // TODO: Track finished time and potentially re-run sync on finished state if enough time
         // passed.
@@ -514,7 +912,9 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         let fut = {
             let endpoint = self.endpoint.clone();
             let replica = replica.clone();
+            let activity = self.activity();
             async move {
+                let _activity = activity;
                 debug!(?peer, ?namespace, ?reason, "sync[dial]: start");
                 let fut = connect_and_sync::<S>(&endpoint, &replica, PeerAddr::new(peer));
                 let res = tokio::select! {
@@ -529,6 +929,39 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         self.running_sync_connect.push(fut);
     }
 
+    /// Sync again with all peers we know of, to catch up on changes we missed while paused.
+    fn resume(&mut self) {
+        if !std::mem::replace(&mut self.paused, false) {
+            return;
+        }
+        debug!("resume");
+        let peers = self
+            .sync_state
+            .iter()
+            .filter(|(_, state)| {
+                matches!(
+                    state,
+                    SyncState::None | SyncState::Finished | SyncState::Failed
+                )
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for (namespace, peer) in peers {
+            self.set_sync_state(namespace, peer, SyncState::None);
+            self.sync_with_peer(namespace, peer, SyncReason::Resume);
+        }
+        // documents for which the discovery was skipped while paused
+        let without_peers = self
+            .syncing_replicas
+            .iter()
+            .filter(|namespace| !self.sync_state.keys().any(|(n, _)| n == *namespace))
+            .copied()
+            .collect::<Vec<_>>();
+        for namespace in without_peers {
+            self.discover_peers(namespace);
+        }
+    }
+
     async fn shutdown(&mut self) -> anyhow::Result<()> {
         for namespace in self.open_replicas.drain() {
             self.syncing_replicas.remove(&namespace);
@@ -559,16 +992,63 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         Some(LiveStatus {
             active,
             subscriptions,
+            paused: self.paused,
         })
     }
 
     async fn start_sync(&mut self, namespace: NamespaceId, peers: Vec<PeerAddr>) -> Result<()> {
         self.ensure_open(namespace)?;
         self.syncing_replicas.insert(namespace);
+        // the secret might have been set when the document was imported
+        self.gossip_secrets.remove(&namespace);
+        if peers.is_empty() {
+            self.discover_peers(namespace);
+        }
         self.join_peers(namespace, peers).await?;
         Ok(())
     }
 
+    /// Ask the [`Discovery`] for peers of a replica, unless a discovery is running already.
+    ///
+    /// Nothing is done while paused, the discovery is retried on resume instead.
+    fn discover_peers(&mut self, namespace: NamespaceId) {
+        if self.paused || !self.discovering.insert(namespace) {
+            return;
+        }
+        debug!(?namespace, "discovery: start");
+        let fut = self.discovery.find_peers(namespace);
+        self.pending_discovery
+            .push(async move { (namespace, fut.await) }.boxed());
+    }
+
+    async fn on_discovery_finished(
+        &mut self,
+        namespace: NamespaceId,
+        res: Result<Vec<PeerAddr>>,
+    ) -> Result<()> {
+        self.discovering.remove(&namespace);
+        if !self.syncing_replicas.contains(&namespace) {
+            return Ok(());
+        }
+        let me = self.endpoint.peer_id();
+        // skip peers that failed already, so that we don't retry them in a loop
+        let peers = res?
+            .into_iter()
+            .filter(|peer| {
+                peer.peer_id != me
+                    && !matches!(
+                        self.get_sync_state(namespace, peer.peer_id),
+                        SyncState::Failed
+                    )
+            })
+            .collect::<Vec<_>>();
+        debug!(?namespace, peers = peers.len(), "discovery: done");
+        if !peers.is_empty() {
+            self.join_peers(namespace, peers).await?;
+        }
+        Ok(())
+    }
+
     /// Open a replica, if not yet in our set of open replicas.
     fn ensure_open(&mut self, namespace: NamespaceId) -> anyhow::Result<()> {
         if !self.open_replicas.contains(&namespace) {
@@ -639,8 +1119,22 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         false
     }
 
+    /// The gossip secret of a replica, read from the store only if it is not cached.
+    fn gossip_secret(&mut self, namespace: NamespaceId) -> Result<Option<GossipSecret>> {
+        if let Some(secret) = self.gossip_secrets.get(&namespace) {
+            return Ok(*secret);
+        }
+        let secret = self.replica_store.gossip_secret(&namespace)?;
+        if self.syncing_replicas.contains(&namespace) {
+            self.gossip_secrets.insert(namespace, secret);
+        }
+        Ok(secret)
+    }
+
     async fn stop_sync(&mut self, namespace: NamespaceId) -> anyhow::Result<()> {
         if self.syncing_replicas.remove(&namespace) {
+            self.pending_broadcasts.remove(&namespace);
+            self.gossip_secrets.remove(&namespace);
             self.gossip.quit(namespace.into()).await?;
             self.sync_state.retain(|(n, _peer), _value| *n != namespace);
             self.maybe_close_replica(namespace);
@@ -667,10 +1161,21 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         self.pending_joins.push({
             let peer_ids = peer_ids.clone();
             let gossip = self.gossip.clone();
+            let activity = self.activity();
             async move {
                 match gossip.join(namespace.into(), peer_ids).await {
                     Err(err) => (namespace, Err(err)),
-                    Ok(fut) => (namespace, fut.await),
+                    Ok(fut) => {
+                        // Dialing the peers keeps the node busy, waiting for peers that can
+                        // not be reached does not.
+                        tokio::pin!(fut);
+                        if let Ok(res) = tokio::time::timeout(GOSSIP_JOIN_ACTIVITY, &mut fut).await
+                        {
+                            return (namespace, res);
+                        }
+                        drop(activity);
+                        (namespace, fut.await)
+                    }
                 }
             }
             .boxed()
@@ -775,6 +1280,15 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
             Err(_) => SyncState::Failed,
         };
         self.set_sync_state(namespace, peer, state);
+        // look for other peers if we failed to sync with all the peers we know
+        let all_failed = self
+            .sync_state
+            .iter()
+            .filter(|((n, _peer), _state)| *n == namespace)
+            .all(|(_, state)| matches!(state, SyncState::Failed));
+        if all_failed && self.syncing_replicas.contains(&namespace) {
+            self.discover_peers(namespace);
+        }
         let event = SyncEvent {
             namespace,
             peer,
@@ -796,34 +1310,14 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         match event {
             // We received a gossip message. Try to insert it into our replica.
             Event::Received(msg) => {
-                let op: Op = postcard::from_bytes(&msg.content)?;
-                match op {
-                    Op::Put(entry) => {
-                        debug!(peer = ?msg.delivered_from, ?namespace, "received entry via gossip");
-                        // Insert the entry into our replica.
-                        // If the message was broadcast with neighbor scope, or is received
-                        // directly from the author, we assume that the content is available at
-                        // that peer. Otherwise we don't.
-                        // The download is not triggered here, but in the `on_replica_event`
-                        // handler for the `InsertRemote` event.
-                        let content_status = match msg.scope.is_direct() {
-                            true => ContentStatus::Complete,
-                            false => ContentStatus::Missing,
-                        };
-                        replica.insert_remote_entry(
-                            entry,
-                            *msg.delivered_from.as_bytes(),
-                            content_status,
-                        )?
-                    }
-                    Op::ContentReady(hash) => {
-                        // Inform the downloader that we now know that this peer has the content
-                        // for this hash.
-                        self.downloader
-                            .peers_have(hash, vec![(msg.delivered_from, PeerRole::Provider).into()])
-                            .await;
-                    }
-                }
+                self.on_gossip_message(
+                    namespace,
+                    replica,
+                    msg.content,
+                    msg.delivered_from,
+                    msg.scope.is_direct(),
+                )
+                .await?;
             }
             // A new neighbor appeared in the gossip swarm. Try to sync with it directly.
             // [Self::sync_with_peer] will check to not resync with peers synced previously in the
@@ -845,26 +1339,121 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         Ok(())
     }
 
+    /// Handle a gossip message for `namespace`, received from `delivered_from`.
+    ///
+    /// `direct` is whether the message was received from the node that broadcast it.
+    async fn on_gossip_message(
+        &mut self,
+        namespace: NamespaceId,
+        replica: Replica<S::Instance>,
+        content: Bytes,
+        delivered_from: PublicKey,
+        direct: bool,
+    ) -> Result<()> {
+        let secret = self.gossip_secret(namespace)?;
+        let (op, sent) = match Op::from_gossip_message(&content, secret.as_ref()) {
+            Ok(Some(res)) => res,
+            Ok(None) => {
+                debug!(peer = ?delivered_from, ?namespace, "dropping unauthenticated gossip message");
+                #[cfg(feature = "metrics")]
+                inc!(Metrics, gossip_unauthenticated_dropped);
+                return Ok(());
+            }
+            Err(err) => {
+                debug!(peer = ?delivered_from, ?namespace, "dropping gossip message that can not be decoded: {err}");
+                return Ok(());
+            }
+        };
+        let entries = match op {
+            Op::Put(entry) => vec![entry],
+            Op::PutMany { version, entries } if version == PUT_MANY_VERSION => entries,
+            Op::PutMany { version, .. } => {
+                debug!(peer = ?delivered_from, ?namespace, version, "dropping gossip message of unknown version");
+                return Ok(());
+            }
+            Op::ContentReady(hash) => {
+                // Inform the downloader that we now know that this peer has the content
+                // for this hash.
+                self.downloader
+                    .peers_have(hash, vec![(delivered_from, PeerRole::Provider).into()])
+                    .await;
+                return Ok(());
+            }
+        };
+        // The same entry is often relayed by several neighbors. The message
+        // contains the signatures, so identical messages carry identical entries.
+        let key = Hash::new(&content);
+        if self.recent_gossip.contains_key(&key) {
+            debug!(peer = ?delivered_from, ?namespace, "dropping duplicate entry from gossip");
+            #[cfg(feature = "metrics")]
+            inc!(Metrics, gossip_duplicates_dropped);
+            return Ok(());
+        }
+        self.recent_gossip.insert(key, ());
+        // How long the entries took from the node that broadcast them, in microseconds.
+        let now = system_time_micros(SystemTime::now());
+        let delay = sent.map(|sent| self.clock_skew.delay(sent.from, sent.sent_at, now));
+        debug!(peer = ?delivered_from, ?namespace, entries = entries.len(), ?delay, "received entries via gossip");
+        // Insert the entries into our replica.
+        // If the message was broadcast with neighbor scope, or is received
+        // directly from the author, we assume that the content is available at
+        // that peer. Otherwise we don't.
+        // The download is not triggered here, but in the `on_replica_event`
+        // handler for the `InsertRemote` event.
+        let content_status = match direct {
+            true => ContentStatus::Complete,
+            false => ContentStatus::Missing,
+        };
+        for entry in entries {
+            let res =
+                replica.insert_remote_entry(entry, *delivered_from.as_bytes(), content_status);
+            #[cfg(feature = "metrics")]
+            if let (Ok(()), Some(delay)) = (&res, delay) {
+                observe!(SyncMetrics, sync_propagation_ms, delay as f64 / 1000.);
+            }
+            match res {
+                Ok(()) => {}
+                // An entry that is already stored, e.g. a duplicate that was
+                // evicted from `recent_gossip`, or an outdated entry. Nothing to do.
+                Err(InsertError::Validation(ValidationFailure::OlderThanExisting)) => {
+                    debug!(peer = ?delivered_from, ?namespace, "ignoring gossip entry that is not newer than the stored entry");
+                }
+                // An entry older than the retention of the doc, which was pruned.
+                Err(InsertError::Validation(ValidationFailure::Expired)) => {
+                    debug!(peer = ?delivered_from, ?namespace, "ignoring expired gossip entry");
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
     async fn on_replica_event(
         &mut self,
         origin: InsertOrigin,
         signed_entry: SignedEntry,
     ) -> Result<()> {
         let namespace = signed_entry.namespace();
-        let topic = TopicId::from_bytes(*namespace.as_bytes());
-        let subs = self.event_subscriptions.get_mut(&namespace);
         match origin {
-            InsertOrigin::Local => {
+            InsertOrigin::Local | InsertOrigin::Transaction { .. } => {
                 let entry = signed_entry.entry().clone();
 
-                // A new entry was inserted locally. Broadcast a gossip message.
-                let op = Op::Put(signed_entry);
-                let message = postcard::to_stdvec(&op)?.into();
-                debug!(?namespace, "broadcast new entry");
-                self.gossip.broadcast(topic, message).await?;
+                // A new entry was inserted locally. Queue it for broadcast, it is sent right
+                // away unless the broadcast policy batches or throttles broadcasts.
+                // Entries of a transaction are held back until its last entry arrived, so that
+                // they are combined into one message.
+                // While paused, the entries are held back until resumed.
+                let pending = self.pending_broadcasts.entry(namespace).or_default();
+                pending.push(signed_entry);
+                // Entries inserted on their own between the entries of a transaction do not
+                // end it.
+                if let InsertOrigin::Transaction { remaining } = origin {
+                    pending.in_transaction = remaining > 0;
+                }
+                self.flush_broadcasts().await?;
 
                 // Notify subscribers about the event
-                if let Some(subs) = subs {
+                if let Some(subs) = self.event_subscriptions.get_mut(&namespace) {
                     let event = LiveEvent::InsertLocal {
                         entry: entry.clone(),
                     };
@@ -882,26 +1471,20 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
                 // A new entry was inserted from initial sync or gossip. Queue downloading the
                 // content.
                 let entry_status = self.bao_store.contains(&hash);
-                if matches!(entry_status, EntryStatus::NotFound | EntryStatus::Partial) {
+                if self.auto_download
+                    && matches!(entry_status, EntryStatus::NotFound | EntryStatus::Partial)
+                    && self.is_responsible_for(&hash)
+                {
                     let role = match content_status {
                         ContentStatus::Complete => PeerRole::Provider,
                         _ => PeerRole::Candidate,
                     };
-                    let handle = self
-                        .downloader
-                        .queue(DownloadKind::Blob { hash }, vec![(from, role).into()])
+                    self.queue_download(namespace, hash, (from, role).into())
                         .await;
-                    let fut = async move {
-                        // NOTE: this ignores the result for now, simply keeping the option
-                        let res = handle.await.ok();
-                        res.map(|_| (namespace, hash))
-                    }
-                    .boxed();
-                    self.pending_downloads.push(fut);
                 }
 
                 // Notify subscribers about the event
-                if let Some(subs) = subs {
+                if let Some(subs) = self.event_subscriptions.get_mut(&namespace) {
                     let event = LiveEvent::InsertRemote {
                         from,
                         entry: entry.clone(),
@@ -915,6 +1498,186 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
         Ok(())
     }
 
+    /// Marks the start of an activity that keeps the node from being idle.
+    fn activity(&self) -> Option<IdleGuard> {
+        self.idle.as_ref().map(|idle| idle.activity())
+    }
+
+    /// The earliest time at which pending local entries are to be broadcast.
+    fn next_broadcast_due(&self) -> Option<Instant> {
+        if self.paused {
+            return None;
+        }
+        self.pending_broadcasts
+            .values()
+            .filter_map(|pending| pending.due(&self.broadcast_policy))
+            .min()
+    }
+
+    /// Broadcast the pending local entries that are due, according to the broadcast policy.
+    ///
+    /// At most one message is sent per replica and call while the rate is limited, the
+    /// remaining entries are sent once the limit allows it. Nothing is sent while paused.
+    async fn flush_broadcasts(&mut self) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let policy = self.broadcast_policy;
+        let min_interval = policy.min_interval();
+        let mut messages = Vec::new();
+        for (namespace, pending) in self.pending_broadcasts.iter_mut() {
+            while pending.due(&policy).is_some_and(|due| due <= now) {
+                // A transaction that is still pending now timed out, see MAX_TRANSACTION_HOLD.
+                pending.in_transaction = false;
+                let batch = pending.take_batch();
+                #[cfg(feature = "metrics")]
+                inc_by!(Metrics, gossip_entries_coalesced, batch.len() as u64 - 1);
+                let op = Op::PutMany {
+                    version: PUT_MANY_VERSION,
+                    entries: batch,
+                };
+                let sent = SentBy {
+                    from: self.endpoint.peer_id(),
+                    sent_at: system_time_micros(SystemTime::now()),
+                };
+                messages.push((*namespace, op, sent));
+                if !min_interval.is_zero() {
+                    pending.next_allowed = Some(now + min_interval);
+                }
+            }
+        }
+        self.pending_broadcasts
+            .retain(|_, pending| pending.since.is_some() || pending.next_allowed > Some(now));
+        for (namespace, op, sent) in messages {
+            let secret = self.gossip_secret(namespace)?;
+            let message = op.to_gossip_message(Some(&sent), secret.as_ref())?;
+            debug!(?namespace, "broadcast new entries");
+            #[cfg(feature = "metrics")]
+            inc!(Metrics, gossip_broadcasts_sent);
+            let topic = TopicId::from_bytes(*namespace.as_bytes());
+            self.gossip.broadcast(topic, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether this node should hold the content `hash`, according to the shard policy.
+    fn is_responsible_for(&self, hash: &Hash) -> bool {
+        match &self.shard_policy {
+            Some(policy) => policy.responsible_for(hash, &self.endpoint.peer_id()),
+            None => true,
+        }
+    }
+
+    /// Download the content for `hash` from `peer`.
+    ///
+    /// Only a single download is started per hash. If a download for the hash is already
+    /// running or waiting in the backlog, `peer` is added to its sources instead. Either way,
+    /// `namespace` is notified once the content is ready.
+    async fn queue_download(&mut self, namespace: NamespaceId, hash: Hash, peer: PeerInfo) {
+        self.download_namespaces
+            .entry(hash)
+            .or_default()
+            .insert(namespace);
+        if self.pending_download_hashes.contains(&hash) {
+            self.downloader.peers_have(hash, vec![peer]).await;
+        } else if let Some(peers) = self.download_backlog_peers.get_mut(&hash) {
+            peers.push(peer);
+        } else {
+            self.download_backlog_peers.insert(hash, vec![peer]);
+            self.download_backlog.push_back(hash);
+            self.start_backlog_downloads().await;
+        }
+    }
+
+    /// Download the content for `hash` from the peers we synced `namespace` with, and reply
+    /// once the content is available or the download failed.
+    async fn download_content(
+        &mut self,
+        namespace: NamespaceId,
+        hash: Hash,
+        reply: sync::oneshot::Sender<bool>,
+    ) {
+        if self.bao_store.contains(&hash) == EntryStatus::Complete {
+            reply.send(true).ok();
+            return;
+        }
+        let peers = self
+            .sync_state
+            .keys()
+            .filter(|(n, _)| *n == namespace)
+            .map(|(_, peer)| PeerInfo::new(*peer, PeerRole::Candidate))
+            .collect::<Vec<_>>();
+        let queued = self.pending_download_hashes.contains(&hash)
+            || self.download_backlog_peers.contains_key(&hash);
+        if peers.is_empty() && !queued {
+            reply.send(false).ok();
+            return;
+        }
+        self.download_waiters.entry(hash).or_default().push(reply);
+        for peer in peers {
+            self.queue_download(namespace, hash, peer).await;
+        }
+    }
+
+    /// Start downloads from the backlog until [`MAX_PENDING_DOWNLOADS`] are running.
+    async fn start_backlog_downloads(&mut self) {
+        while self.pending_download_hashes.len() < MAX_PENDING_DOWNLOADS {
+            let Some(hash) = self.download_backlog.pop_front() else {
+                break;
+            };
+            let peers = self
+                .download_backlog_peers
+                .remove(&hash)
+                .unwrap_or_default();
+            let handle = self
+                .downloader
+                .queue(DownloadKind::Blob { hash }, peers)
+                .await;
+            let activity = self.activity();
+            let fut = async move {
+                let _activity = activity;
+                // NOTE: this ignores the error for now, simply keeping whether it succeeded
+                let success = handle.await.is_ok();
+                (hash, success)
+            }
+            .boxed();
+            self.pending_download_hashes.insert(hash);
+            self.pending_downloads.push(fut);
+        }
+    }
+
+    /// Reply to the waiters of a finished download and, if it succeeded, notify every replica
+    /// that requested the content.
+    async fn on_download_finished(&mut self, hash: Hash, success: bool) -> Result<()> {
+        self.pending_download_hashes.remove(&hash);
+        let namespaces = self.download_namespaces.remove(&hash).unwrap_or_default();
+        self.start_backlog_downloads().await;
+        for reply in self.download_waiters.remove(&hash).unwrap_or_default() {
+            reply.send(success).ok();
+        }
+        if !success {
+            return Ok(());
+        }
+        for namespace in namespaces {
+            if let Some(subs) = self.event_subscriptions.get_mut(&namespace) {
+                let event = LiveEvent::ContentReady { hash };
+                notify_all(subs, event).await;
+            }
+
+            // Inform our neighbors that we have new content ready.
+            if !self.paused {
+                let op = Op::ContentReady(hash);
+                let secret = self.gossip_secret(namespace)?;
+                let message = op.to_gossip_message(None, secret.as_ref())?;
+                self.gossip
+                    .broadcast_neighbors(namespace.into(), message)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn handle_connection(&mut self, conn: quinn::Connecting) {
         let to_actor_tx = self.to_actor_tx.clone();
         let request_replica_cb = move |namespace, peer| {
@@ -934,8 +1697,12 @@ impl<S: store::Store, B: baomap::Store> Actor<S, B> {
             .boxed()
         };
         debug!("sync[accept] incoming connection");
-        let fut =
-            async move { handle_connection::<S, _, _>(conn, request_replica_cb).await }.boxed();
+        let activity = self.activity();
+        let fut = async move {
+            let _activity = activity;
+            handle_connection::<S, _, _>(conn, request_replica_cb).await
+        }
+        .boxed();
         self.running_sync_accept.push(fut);
     }
 
@@ -993,6 +1760,8 @@ pub enum SyncReason {
     DirectJoin,
     /// Peer showed up as new neighbor in the gossip swarm
     NewNeighbor,
+    /// Catching up after the live sync was resumed
+    Resume,
 }
 
 /// Why we performed a sync exchange
@@ -1017,6 +1786,14 @@ async fn notify_all(subs: &mut HashMap<u64, OnLiveEventCallback>, event: LiveEve
     }
 }
 
+/// Sleep until `deadline`, or forever if there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
 /// Utilities for working with byte array identifiers
 // TODO: copy-pasted from iroh-gossip/src/proto/util.rs
 // Unify into iroh-common crate or similar
@@ -1029,3 +1806,252 @@ pub(super) mod base32 {
         text
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use iroh_net::key::SecretKey;
+
+    use super::*;
+
+    #[test]
+    fn gossip_message_auth() -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let secret = GossipSecret::new(&mut rng);
+        let other = GossipSecret::new(&mut rng);
+        let op = Op::ContentReady(Hash::new(b"hello"));
+
+        // without a secret, messages are plain ops
+        let plain = op.to_gossip_message(None, None)?;
+        assert!(Op::from_gossip_message(&plain, None)?.is_some());
+        assert!(Op::from_gossip_message(&plain, Some(&secret))?.is_none());
+
+        let sealed = op.to_gossip_message(None, Some(&secret))?;
+        assert_eq!(sealed.len(), plain.len() + GossipSecret::MAC_LEN);
+        assert!(matches!(
+            Op::from_gossip_message(&sealed, Some(&secret))?,
+            Some((Op::ContentReady(hash), None)) if hash == Hash::new(b"hello")
+        ));
+        assert!(Op::from_gossip_message(&sealed, Some(&other))?.is_none());
+        assert!(Op::from_gossip_message(&sealed[..4], Some(&secret))?.is_none());
+
+        let mut tampered = sealed.to_vec();
+        tampered[0] ^= 1;
+        assert!(Op::from_gossip_message(&tampered, Some(&secret))?.is_none());
+
+        // the sender follows the op, covered by the MAC
+        let sent = SentBy {
+            from: SecretKey::generate().public(),
+            sent_at: 1_000,
+        };
+        let sealed = op.to_gossip_message(Some(&sent), Some(&secret))?;
+        assert!(matches!(
+            Op::from_gossip_message(&sealed, Some(&secret))?,
+            Some((Op::ContentReady(_), Some(s))) if s == sent
+        ));
+        let mut tampered = sealed.to_vec();
+        tampered[plain.len()] ^= 1;
+        assert!(Op::from_gossip_message(&tampered, Some(&secret))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn gossip_message_sender_is_ignored_by_older_peers() -> Result<()> {
+        /// The ops known to peers that do not know about [`SentBy`].
+        #[derive(Debug, Deserialize)]
+        enum OlderOp {
+            #[allow(dead_code)]
+            Put(SignedEntry),
+            #[allow(dead_code)]
+            ContentReady(Hash),
+            PutMany {
+                version: u8,
+                entries: Vec<SignedEntry>,
+            },
+        }
+
+        let mut rng = rand::thread_rng();
+        let namespace = iroh_sync::sync::Namespace::new(&mut rng);
+        let author = iroh_sync::sync::Author::new(&mut rng);
+        let record = iroh_sync::sync::Record::new(Hash::new(b"value"), 5, 1);
+        let entry = SignedEntry::from_parts(&namespace, &author, "key", record);
+        let op = Op::PutMany {
+            version: PUT_MANY_VERSION,
+            entries: vec![entry.clone()],
+        };
+        let sent = SentBy {
+            from: SecretKey::generate().public(),
+            sent_at: 1_000,
+        };
+        let message = op.to_gossip_message(Some(&sent), None)?;
+        let OlderOp::PutMany { version, entries } = postcard::from_bytes(&message)? else {
+            panic!("not decoded as PutMany");
+        };
+        assert_eq!(version, PUT_MANY_VERSION);
+        assert_eq!(entries, vec![entry]);
+        Ok(())
+    }
+
+    #[test]
+    fn broadcast_batches() -> Result<()> {
+        use iroh_sync::sync::{Author, Namespace, Record};
+
+        let mut rng = rand::thread_rng();
+        let namespace = Namespace::new(&mut rng);
+        let author = Author::new(&mut rng);
+        let entry = |i: u64| {
+            let record = Record::new(Hash::new(i.to_le_bytes()), 1, i);
+            SignedEntry::from_parts(&namespace, &author, format!("key-{i}"), record)
+        };
+
+        let policy = BroadcastPolicy {
+            batch_window: Duration::from_millis(100),
+            max_rate: NonZeroU32::new(2),
+        };
+        let mut pending = PendingBroadcast::default();
+        assert_eq!(pending.due(&policy), None);
+        let start = Instant::now();
+        pending.since = Some(start);
+        pending.entries.extend((0..32).map(entry));
+        assert_eq!(pending.due(&policy), Some(start + policy.batch_window));
+
+        // entries are held back until the last entry of a transaction arrived
+        pending.in_transaction = true;
+        assert_eq!(pending.due(&policy), Some(start + MAX_TRANSACTION_HOLD));
+        pending.in_transaction = false;
+
+        // a batch is limited by the size of a gossip message
+        let batch = pending.take_batch();
+        assert!(batch.len() > 1 && batch.len() < 32);
+        let sent = SentBy {
+            from: SecretKey::generate().public(),
+            sent_at: u64::MAX,
+        };
+        let message = Op::PutMany {
+            version: PUT_MANY_VERSION,
+            entries: batch,
+        }
+        .to_gossip_message(Some(&sent), None)?;
+        assert!(message.len() <= MAX_BATCH_SIZE + 64);
+        assert!(pending.since.is_some());
+
+        // the remaining entries wait for the rate limit
+        pending.next_allowed = Some(start + policy.min_interval());
+        assert_eq!(policy.min_interval(), Duration::from_millis(500));
+        assert_eq!(pending.due(&policy), Some(start + policy.min_interval()));
+        while !pending.entries.is_empty() {
+            assert!(!pending.take_batch().is_empty());
+        }
+        assert_eq!(pending.due(&policy), None);
+
+        // a newer entry for the same key and author replaces the pending one
+        let mut pending = PendingBroadcast::default();
+        pending.push(entry(1));
+        pending.push(entry(2));
+        let newer = SignedEntry::from_parts(
+            &namespace,
+            &author,
+            "key-1",
+            Record::new(Hash::new(b"newer"), 1, 3),
+        );
+        pending.push(newer.clone());
+        assert!(pending.since.is_some());
+        let batch = pending.take_batch();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0], newer);
+        assert!(pending.ids.is_empty());
+        Ok(())
+    }
+
+    #[cfg(all(feature = "metrics", feature = "mem-db"))]
+    #[tokio::test]
+    async fn gossip_duplicates_dropped() -> Result<()> {
+        use iroh_bytes::collection::LinkSeqCollectionParser;
+        use iroh_metrics::core::Core;
+        use iroh_sync::store::Store as _;
+        use iroh_sync::sync::{Author, Namespace};
+
+        use crate::connection_stats::Connections;
+        use crate::sync_engine::NoDiscovery;
+
+        crate::metrics::try_init_metrics_collection().ok();
+        let dropped = || {
+            Core::get()
+                .and_then(|core| core.get_collector::<Metrics>())
+                .unwrap()
+                .gossip_duplicates_dropped
+                .get()
+        };
+
+        let rt = Handle::from_current(1)?;
+        let endpoint = MagicEndpoint::builder().bind(0).await?;
+        let gossip = Gossip::from_endpoint(endpoint.clone(), Default::default());
+        let bao_store = crate::baomap::mem::Store::new(rt.clone());
+        let downloader = Downloader::new(
+            bao_store.clone(),
+            LinkSeqCollectionParser::default(),
+            u64::MAX,
+            endpoint.clone(),
+            rt,
+            Connections::default(),
+        )
+        .await;
+        let replica_store = store::memory::Store::default();
+        let (to_actor_tx, to_actor_rx) = mpsc::channel(CHANNEL_CAP);
+        let mut actor = Actor::new(
+            endpoint,
+            gossip,
+            bao_store,
+            downloader,
+            false,
+            Arc::new(NoDiscovery),
+            16,
+            None,
+            BroadcastPolicy::default(),
+            None,
+            replica_store.clone(),
+            to_actor_rx,
+            to_actor_tx,
+        );
+
+        let mut rng = rand::thread_rng();
+        let namespace = Namespace::new(&mut rng);
+        let author = Author::new(&mut rng);
+        let replica = replica_store.new_replica(namespace.clone())?;
+        let inserts = replica.subscribe().unwrap();
+        actor.syncing_replicas.insert(namespace.id());
+
+        let entry = SignedEntry::from_parts(
+            &namespace,
+            &author,
+            "key",
+            iroh_sync::sync::Record::new(Hash::new(b"value"), 5, 1),
+        );
+        let message = Op::Put(entry).to_gossip_message(None, None)?;
+        let from = SecretKey::generate().public();
+        let before = dropped();
+        for _ in 0..2 {
+            actor
+                .on_gossip_message(namespace.id(), replica.clone(), message.clone(), from, true)
+                .await?;
+        }
+        assert_eq!(inserts.drain().count(), 1);
+        assert_eq!(dropped() - before, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn clock_skew() {
+        let mut skew = ClockSkew::default();
+        let a = SecretKey::generate().public();
+        let b = SecretKey::generate().public();
+
+        // a clock that is behind looks like network delay
+        assert_eq!(skew.delay(a, 1_000, 5_000), 4_000);
+        // a send time ahead of our clock shows that the clock of the sender is ahead
+        assert_eq!(skew.delay(a, 10_000, 8_000), 0);
+        // later messages are corrected by the skew, which fades out
+        assert_eq!(skew.delay(a, 20_000, 19_000), 750);
+        // other nodes are not affected
+        assert_eq!(skew.delay(b, 20_000, 21_000), 1_000);
+    }
+}
@@ -1,31 +1,57 @@
 //! This module contains an impl block on [`SyncEngine`] with handlers for RPC requests
 
-use anyhow::anyhow;
-use futures::{FutureExt, Stream};
+use std::{
+    collections::{btree_map, BTreeMap},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, ensure};
+use bytes::Bytes;
+use futures::{FutureExt, Stream, StreamExt};
 use iroh_bytes::{
-    baomap::Store as BaoStore,
-    util::{BlobFormat, RpcError},
+    baomap::{ImportProgress, MapEntry, Store as BaoStore},
+    util::{
+        progress::{FlumeProgressSender, ProgressSender},
+        BlobFormat, RpcError,
+    },
+};
+use iroh_io::AsyncSliceReader;
+use iroh_sync::{
+    store::{GetFilter, Store},
+    sync::{Author, AuthorId, Capability, GossipSecret, Namespace, SignedEntry},
+    AuthorPublicKey, ContentStatus, NamespaceId,
 };
-use iroh_sync::{store::Store, sync::Namespace};
 use itertools::Itertools;
 use rand::rngs::OsRng;
+use tracing::warn;
 
 use crate::{
     rpc_protocol::{
-        AuthorCreateRequest, AuthorCreateResponse, AuthorListRequest, AuthorListResponse,
-        DocCreateRequest, DocCreateResponse, DocGetManyRequest, DocGetManyResponse,
-        DocGetOneRequest, DocGetOneResponse, DocImportRequest, DocImportResponse, DocInfoRequest,
-        DocInfoResponse, DocListRequest, DocListResponse, DocSetRequest, DocSetResponse,
-        DocShareRequest, DocShareResponse, DocStartSyncRequest, DocStartSyncResponse,
-        DocStopSyncRequest, DocStopSyncResponse, DocSubscribeRequest, DocSubscribeResponse,
-        DocTicket, RpcResult, ShareMode,
+        AuthorCreateRequest, AuthorCreateResponse, AuthorImportRequest, AuthorImportResponse,
+        AuthorListRequest, AuthorListResponse, AuthorRemoveRequest, AuthorRemoveResponse,
+        ContentResolution, DocCreateRequest, DocCreateResponse, DocExportTarRequest,
+        DocExportTarResponse, DocGetDefaultAuthorRequest, DocGetDefaultAuthorResponse,
+        DocGetKeysRequest, DocGetManyRequest, DocGetManyResponse, DocGetOneRequest,
+        DocGetOneResponse, DocGetRetentionRequest, DocGetRetentionResponse, DocImportRequest,
+        DocImportResponse, DocInfoRequest, DocInfoResponse, DocListRequest, DocListResponse,
+        DocMoveRequest, DocMoveResponse, DocSetDefaultAuthorRequest, DocSetDefaultAuthorResponse,
+        DocSetGossipAuthRequest, DocSetGossipAuthResponse, DocSetRequest, DocSetResponse,
+        DocSetRetentionRequest, DocSetRetentionResponse, DocSetStreamRequest, DocSetStreamResponse,
+        DocSetStreamUpdate, DocShareRequest, DocShareResponse, DocStartSyncRequest,
+        DocStartSyncResponse, DocStopSyncRequest, DocStopSyncResponse, DocSubscribeRequest,
+        DocSubscribeResponse, DocTicket, DocsPauseRequest, DocsPauseResponse, DocsResumeRequest,
+        DocsResumeResponse, KeyKind, RpcResult, ShareMode,
     },
-    sync_engine::{KeepCallback, LiveStatus, SyncEngine},
+    sync_engine::{live::entry_to_content_status, KeepCallback, LiveStatus, SyncEngine},
 };
 
 /// Capacity for the flume channels to forward sync store iterators to async RPC streams.
 const ITER_CHANNEL_CAP: usize = 64;
 
+/// Size of the chunks in which entry content is read for [`DocExportTarRequest`].
+const TAR_CHUNK_SIZE: usize = 1024 * 64;
+
 #[allow(missing_docs)]
 impl<S: Store> SyncEngine<S> {
     pub fn author_create(&self, _req: AuthorCreateRequest) -> RpcResult<AuthorCreateResponse> {
@@ -36,6 +62,45 @@ impl<S: Store> SyncEngine<S> {
         })
     }
 
+    pub fn author_import(&self, req: AuthorImportRequest) -> RpcResult<AuthorImportResponse> {
+        let AuthorImportRequest { key, kind } = req;
+        let author_id = match kind {
+            KeyKind::Secret => {
+                // every 32 byte string is a valid secret key, but a public key of an author we
+                // know is almost certainly passed by mistake
+                if self.store.get_author(&AuthorId::from(key))?.is_some() {
+                    return Err(anyhow!(
+                        "the key is the public key of author {}, not a secret key",
+                        AuthorId::from(key)
+                    )
+                    .into());
+                }
+                let author = Author::from_bytes(&key);
+                self.store.import_author(author.clone())?;
+                author.id()
+            }
+            KeyKind::Public => {
+                let public_key = AuthorPublicKey::from_bytes(&key)
+                    .map_err(|_| anyhow!("the key is not a valid ed25519 public key"))?;
+                let author_id = AuthorId::from(public_key);
+                if self.store.get_author(&author_id)?.is_none() {
+                    return Err(anyhow!(
+                        "no secret key for author {author_id}, \
+                         authors can only be imported from their secret key"
+                    )
+                    .into());
+                }
+                author_id
+            }
+        };
+        Ok(AuthorImportResponse { author_id })
+    }
+
+    pub fn author_remove(&self, req: AuthorRemoveRequest) -> RpcResult<AuthorRemoveResponse> {
+        self.store.remove_author(&req.author_id, req.force)?;
+        Ok(AuthorRemoveResponse {})
+    }
+
     pub fn author_list(
         &self,
         _req: AuthorListRequest,
@@ -84,6 +149,7 @@ impl<S: Store> SyncEngine<S> {
         let status = status.unwrap_or(LiveStatus {
             active: false,
             subscriptions: 0,
+            paused: false,
         });
         Ok(DocInfoResponse { status })
     }
@@ -92,17 +158,20 @@ impl<S: Store> SyncEngine<S> {
         self.start_sync(req.doc_id, vec![]).await?;
         let me = self.endpoint.my_addr().await?;
         let replica = self.get_replica(&req.doc_id)?;
-        let key = match req.mode {
-            ShareMode::Read => {
-                // TODO: support readonly docs
-                // *replica.namespace().as_bytes()
-                return Err(anyhow!("creating read-only shares is not yet supported").into());
-            }
-            ShareMode::Write => replica.secret_key(),
+        let capability = match req.mode {
+            ShareMode::Read => Capability::Read(replica.namespace()),
+            ShareMode::Write => match replica.capability() {
+                capability @ Capability::Write(_) => capability,
+                Capability::Read(_) => {
+                    return Err(anyhow!("cannot share write access to a read-only document").into())
+                }
+            },
         };
+        let gossip_secret = self.store.gossip_secret(&req.doc_id)?;
         Ok(DocShareResponse(DocTicket {
-            key,
+            capability,
             peers: vec![me],
+            gossip_secret,
         }))
     }
 
@@ -138,12 +207,16 @@ impl<S: Store> SyncEngine<S> {
     }
 
     pub async fn doc_import(&self, req: DocImportRequest) -> RpcResult<DocImportResponse> {
-        let DocImportRequest(DocTicket { key, peers }) = req;
-        // TODO: support read-only docs
-        // if let Ok(namespace) = match NamespaceId::from_bytes(&key) {};
-        let namespace = Namespace::from_bytes(&key);
-        let id = namespace.id();
-        let replica = self.store.new_replica(namespace)?;
+        let DocImportRequest(DocTicket {
+            capability,
+            peers,
+            gossip_secret,
+        }) = req;
+        let id = capability.id();
+        let replica = self.store.new_replica(capability)?;
+        if let Some(secret) = gossip_secret {
+            self.store.set_gossip_secret(&id, Some(secret))?;
+        }
         self.start_sync(replica.namespace(), peers).await?;
         Ok(DocImportResponse { doc_id: id })
     }
@@ -163,10 +236,79 @@ impl<S: Store> SyncEngine<S> {
         Ok(DocStopSyncResponse {})
     }
 
+    pub async fn doc_set_default_author(
+        &self,
+        req: DocSetDefaultAuthorRequest,
+    ) -> RpcResult<DocSetDefaultAuthorResponse> {
+        let DocSetDefaultAuthorRequest { doc_id, author_id } = req;
+        let _replica = self.get_replica(&doc_id)?;
+        if let Some(author_id) = author_id {
+            let _author = self.get_author(&author_id)?;
+        }
+        self.store.set_default_author(&doc_id, author_id)?;
+        Ok(DocSetDefaultAuthorResponse {})
+    }
+
+    pub async fn doc_get_default_author(
+        &self,
+        req: DocGetDefaultAuthorRequest,
+    ) -> RpcResult<DocGetDefaultAuthorResponse> {
+        let _replica = self.get_replica(&req.doc_id)?;
+        let author_id = self.store.default_author(&req.doc_id)?;
+        Ok(DocGetDefaultAuthorResponse { author_id })
+    }
+
+    pub async fn doc_set_retention(
+        &self,
+        req: DocSetRetentionRequest,
+    ) -> RpcResult<DocSetRetentionResponse> {
+        let DocSetRetentionRequest { doc_id, retention } = req;
+        let _replica = self.get_replica(&doc_id)?;
+        self.store.set_retention(&doc_id, retention)?;
+        Ok(DocSetRetentionResponse {})
+    }
+
+    pub async fn doc_get_retention(
+        &self,
+        req: DocGetRetentionRequest,
+    ) -> RpcResult<DocGetRetentionResponse> {
+        let _replica = self.get_replica(&req.doc_id)?;
+        let retention = self.store.retention(&req.doc_id)?;
+        Ok(DocGetRetentionResponse { retention })
+    }
+
+    pub async fn doc_set_gossip_auth(
+        &self,
+        req: DocSetGossipAuthRequest,
+    ) -> RpcResult<DocSetGossipAuthResponse> {
+        let DocSetGossipAuthRequest { doc_id, enabled } = req;
+        let _replica = self.get_replica(&doc_id)?;
+        let secret = match (enabled, self.store.gossip_secret(&doc_id)?) {
+            (false, _) => None,
+            (true, Some(secret)) => Some(secret),
+            (true, None) => Some(GossipSecret::new(&mut OsRng {})),
+        };
+        self.store.set_gossip_secret(&doc_id, secret)?;
+        self.live.gossip_secret_changed(doc_id).await?;
+        Ok(DocSetGossipAuthResponse {})
+    }
+
+    pub async fn docs_pause(&self, _req: DocsPauseRequest) -> RpcResult<DocsPauseResponse> {
+        self.pause().await?;
+        Ok(DocsPauseResponse {})
+    }
+
+    pub async fn docs_resume(&self, _req: DocsResumeRequest) -> RpcResult<DocsResumeResponse> {
+        self.resume().await?;
+        Ok(DocsResumeResponse {})
+    }
+
+    /// Set a value, failing if it is larger than `max_value_size`.
     pub async fn doc_set<B: BaoStore>(
         &self,
         bao_store: &B,
         req: DocSetRequest,
+        max_value_size: u64,
     ) -> RpcResult<DocSetResponse> {
         let DocSetRequest {
             doc_id,
@@ -174,8 +316,19 @@ impl<S: Store> SyncEngine<S> {
             key,
             value,
         } = req;
+        if value.len() as u64 > max_value_size {
+            return Err(anyhow!(
+                "value of {} bytes exceeds the max value size of {} bytes",
+                value.len(),
+                max_value_size
+            )
+            .into());
+        }
         let replica = self.get_replica(&doc_id)?;
-        let author = self.get_author(&author_id)?;
+        let author = match author_id {
+            Some(author_id) => self.get_author(&author_id)?,
+            None => self.default_author(&doc_id)?,
+        };
         let len = value.len();
         let tag = bao_store
             .import_bytes(value.into(), BlobFormat::RAW)
@@ -190,25 +343,293 @@ impl<S: Store> SyncEngine<S> {
         Ok(DocSetResponse { entry })
     }
 
-    pub fn doc_get_many(
+    /// Set a streamed value, failing once it gets larger than `max_value_size`.
+    pub fn doc_set_stream<B: BaoStore>(
+        &self,
+        bao_store: B,
+        req: DocSetStreamRequest,
+        updates: impl Stream<Item = DocSetStreamUpdate> + Send + Unpin + 'static,
+        max_value_size: u64,
+    ) -> impl Stream<Item = RpcResult<DocSetStreamResponse>> {
+        let (tx, rx) = flume::bounded(ITER_CHANNEL_CAP);
+        let this = self.clone();
+        self.rt.main().spawn(async move {
+            let res = this
+                .set_stream(&bao_store, req, updates, max_value_size, &tx)
+                .await;
+            if let Err(err) = res {
+                tx.send_async(Err(err.into())).await.ok();
+            }
+        });
+        rx.into_stream()
+    }
+
+    async fn set_stream<B: BaoStore>(
+        &self,
+        bao_store: &B,
+        req: DocSetStreamRequest,
+        updates: impl Stream<Item = DocSetStreamUpdate> + Send + Unpin + 'static,
+        max_value_size: u64,
+        tx: &flume::Sender<RpcResult<DocSetStreamResponse>>,
+    ) -> anyhow::Result<()> {
+        let DocSetStreamRequest {
+            doc_id,
+            author_id,
+            key,
+        } = req;
+        let replica = self.get_replica(&doc_id)?;
+        let author = match author_id {
+            Some(author_id) => self.get_author(&author_id)?,
+            None => self.default_author(&doc_id)?,
+        };
+        // The value is imported as it arrives, so it is never held in memory as a whole.
+        let progress = FlumeProgressSender::new(tx.clone()).with_filter_map(|msg| match msg {
+            ImportProgress::CopyProgress { offset, .. } => {
+                Some(Ok(DocSetStreamResponse::Progress { offset }))
+            }
+            _ => None,
+        });
+        let (tag, len) = bao_store
+            .import_stream(
+                value_stream(updates, max_value_size),
+                BlobFormat::RAW,
+                progress,
+            )
+            .await?;
+        replica
+            .insert(&key, &author, *tag.hash(), len)
+            .map_err(anyhow::Error::from)?;
+        let entry = self
+            .store
+            .get_one(replica.namespace(), author.id(), &key)?
+            .ok_or_else(|| anyhow!("failed to get entry after insertion"))?;
+        tx.send_async(Ok(DocSetStreamResponse::Done { entry }))
+            .await
+            .ok();
+        Ok(())
+    }
+
+    pub fn doc_move(&self, req: DocMoveRequest) -> RpcResult<DocMoveResponse> {
+        let DocMoveRequest {
+            doc_id,
+            author_id,
+            from,
+            to,
+        } = req;
+        self.store.move_key(doc_id, author_id, &from, &to)?;
+        let entry = self
+            .store
+            .get_one(doc_id, author_id, &to)?
+            .ok_or_else(|| anyhow!("failed to get entry after move"))?;
+        Ok(DocMoveResponse { entry })
+    }
+
+    pub fn doc_get_many<B: BaoStore>(
         &self,
+        bao_store: B,
         req: DocGetManyRequest,
     ) -> impl Stream<Item = RpcResult<DocGetManyResponse>> {
-        let DocGetManyRequest { doc_id, filter } = req;
+        let DocGetManyRequest {
+            doc_id,
+            filter,
+            content,
+        } = req;
         let (tx, rx) = flume::bounded(ITER_CHANNEL_CAP);
         let store = self.store.clone();
         self.rt.main().spawn_blocking(move || {
             let ite = store.get_many(doc_id, filter);
-            let ite = inline_result(ite).map_ok(|entry| DocGetManyResponse { entry });
-            for entry in ite {
+            for entry in inline_result(ite) {
                 if let Err(_err) = tx.send(entry) {
                     break;
                 }
             }
         });
+        self.resolve_content(bao_store, doc_id, content, rx.into_stream())
+    }
+
+    /// Add the content status to a stream of entries, handling missing content according to
+    /// `resolution`.
+    fn resolve_content<B: BaoStore>(
+        &self,
+        bao_store: B,
+        doc_id: NamespaceId,
+        resolution: ContentResolution,
+        entries: impl Stream<Item = RpcResult<SignedEntry>>,
+    ) -> impl Stream<Item = RpcResult<DocGetManyResponse>> {
+        let live = self.live.clone();
+        entries.filter_map(move |entry| {
+            let bao_store = bao_store.clone();
+            let live = live.clone();
+            async move {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => return Some(Err(err)),
+                };
+                let hash = entry.content_hash();
+                // deleted entries have no content to resolve
+                let mut content_status = match entry.entry().record().is_empty() {
+                    true => ContentStatus::Complete,
+                    false => entry_to_content_status(bao_store.contains(&hash)),
+                };
+                if let ContentResolution::FetchMissing { timeout } = resolution {
+                    if content_status != ContentStatus::Complete {
+                        match tokio::time::timeout(timeout, live.download_content(doc_id, hash))
+                            .await
+                        {
+                            Ok(Ok(true)) => content_status = ContentStatus::Complete,
+                            Ok(Ok(false)) => {}
+                            Ok(Err(err)) => return Some(Err(err.into())),
+                            Err(_elapsed) => {
+                                content_status = entry_to_content_status(bao_store.contains(&hash));
+                            }
+                        }
+                    }
+                }
+                if resolution == ContentResolution::SkipMissing
+                    && content_status != ContentStatus::Complete
+                {
+                    return None;
+                }
+                Some(Ok(DocGetManyResponse {
+                    entry,
+                    content_status,
+                }))
+            }
+        })
+    }
+
+    pub fn doc_get_keys<B: BaoStore>(
+        &self,
+        bao_store: B,
+        req: DocGetKeysRequest,
+    ) -> impl Stream<Item = RpcResult<DocGetManyResponse>> {
+        let DocGetKeysRequest {
+            doc_id,
+            keys,
+            author,
+        } = req;
+        let (tx, rx) = flume::bounded(ITER_CHANNEL_CAP);
+        let store = self.store.clone();
+        self.rt.main().spawn_blocking(move || {
+            for key in keys {
+                let entries = match author {
+                    Some(author) => store
+                        .get_one(doc_id, author, key)
+                        .map(|entry| entry.into_iter().collect()),
+                    None => store
+                        .get_many(doc_id, GetFilter::Key(key))
+                        .and_then(|ite| ite.collect::<anyhow::Result<Vec<_>>>()),
+                };
+                let items = match entries {
+                    Ok(entries) => entries.into_iter().map(Ok).collect(),
+                    Err(err) => vec![Err(err.into())],
+                };
+                for item in items {
+                    if let Err(_err) = tx.send(item) {
+                        return;
+                    }
+                }
+            }
+        });
+        self.resolve_content(
+            bao_store,
+            doc_id,
+            ContentResolution::IncludeMissing,
+            rx.into_stream(),
+        )
+    }
+
+    pub fn doc_export_tar<B: BaoStore>(
+        &self,
+        bao_store: B,
+        req: DocExportTarRequest,
+    ) -> impl Stream<Item = RpcResult<DocExportTarResponse>> {
+        let (tx, rx) = flume::bounded(ITER_CHANNEL_CAP);
+        let this = self.clone();
+        // the data readers of the store are not necessarily Send
+        self.rt.local_pool().spawn_pinned(move || async move {
+            if let Err(err) = this.export_tar(&bao_store, req, &tx).await {
+                tx.send_async(Err(err.into())).await.ok();
+            }
+        });
         rx.into_stream()
     }
 
+    async fn export_tar<B: BaoStore>(
+        &self,
+        bao_store: &B,
+        req: DocExportTarRequest,
+        tx: &flume::Sender<RpcResult<DocExportTarResponse>>,
+    ) -> anyhow::Result<()> {
+        let DocExportTarRequest { doc_id, prefix } = req;
+        let replica = self.get_replica(&doc_id)?;
+        // the latest entry of each key, over all authors
+        let mut latest = BTreeMap::<Vec<u8>, SignedEntry>::new();
+        for entry in self
+            .store
+            .get_many(replica.namespace(), GetFilter::Prefix(prefix))?
+        {
+            let entry = entry?;
+            match latest.entry(entry.key().to_vec()) {
+                btree_map::Entry::Vacant(slot) => {
+                    slot.insert(entry);
+                }
+                btree_map::Entry::Occupied(mut slot) => {
+                    if slot.get().timestamp() < entry.timestamp() {
+                        slot.insert(entry);
+                    }
+                }
+            }
+        }
+
+        let send = |chunk: Bytes| tx.send_async(Ok(DocExportTarResponse { chunk }));
+        let mut builder = tar::Builder::new(Vec::new());
+        for (key, entry) in latest {
+            if entry.entry().record().is_empty() {
+                continue;
+            }
+            let Some(path) = key_to_path(&key) else {
+                warn!(key = %String::from_utf8_lossy(&key), "export tar: skipping key that is not a relative path");
+                continue;
+            };
+            let hash = entry.content_hash();
+            ensure!(
+                self.live.download_content(doc_id, hash).await?,
+                "content of {} is not available",
+                path.display()
+            );
+            let content = bao_store
+                .get(&hash)
+                .ok_or_else(|| anyhow!("content of {} is not available", path.display()))?;
+            let size = content.size();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(size);
+            header.set_mode(0o644);
+            header.set_mtime(entry.timestamp() / 1_000_000);
+            // the builder only writes the headers, the content is streamed from the store
+            builder.append_data(&mut header, &path, io::empty())?;
+            send(std::mem::take(builder.get_mut()).into()).await?;
+            let mut reader = content.data_reader().await?;
+            let mut offset = 0;
+            while offset < size {
+                let chunk = reader.read_at(offset, TAR_CHUNK_SIZE).await?;
+                ensure!(
+                    !chunk.is_empty(),
+                    "content of {} is truncated",
+                    path.display()
+                );
+                offset += chunk.len() as u64;
+                send(chunk).await?;
+            }
+            let padding = (512 - size % 512) % 512;
+            if padding > 0 {
+                send(vec![0u8; padding as usize].into()).await?;
+            }
+        }
+        send(builder.into_inner()?.into()).await?;
+        Ok(())
+    }
+
     pub async fn doc_get_one(&self, req: DocGetOneRequest) -> RpcResult<DocGetOneResponse> {
         let DocGetOneRequest {
             doc_id,
@@ -229,3 +650,50 @@ fn inline_result<T>(
         Err(err) => itertools::Either::Right(Some(Err(err.into())).into_iter()),
     }
 }
+
+/// The chunks of a value sent with [`DocSetStreamUpdate`]s, which ends once the value is
+/// finished and fails if the updates end before or the value gets larger than
+/// `max_value_size`.
+fn value_stream(
+    updates: impl Stream<Item = DocSetStreamUpdate> + Send + Unpin + 'static,
+    max_value_size: u64,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static {
+    futures::stream::unfold(Some((updates, 0u64)), move |state| async move {
+        let (mut updates, len) = state?;
+        match updates.next().await {
+            Some(DocSetStreamUpdate::Chunk(chunk)) => {
+                let len = len + chunk.len() as u64;
+                if len > max_value_size {
+                    let err = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("value exceeds the max value size of {max_value_size} bytes"),
+                    );
+                    return Some((Err(err), None));
+                }
+                Some((Ok(chunk), Some((updates, len))))
+            }
+            Some(DocSetStreamUpdate::Finish) => None,
+            None => {
+                let err = io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "value stream closed before it was finished",
+                );
+                Some((Err(err), None))
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The path of the file for `key` in a tar archive, if `key` is a relative path.
+///
+/// Keys are commonly null terminated, the terminator is not part of the path.
+fn key_to_path(key: &[u8]) -> Option<PathBuf> {
+    let key = key.strip_suffix(&[0]).unwrap_or(key);
+    let path = Path::new(std::str::from_utf8(key).ok()?);
+    let relative = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    relative.then(|| path.to_path_buf())
+}
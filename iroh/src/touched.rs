@@ -0,0 +1,63 @@
+//! Blobs that were touched recently, and are kept by garbage collection for a while.
+//!
+//! Touching a blob, see [`BlobTouchRequest`](crate::rpc_protocol::BlobTouchRequest), keeps it
+//! from being collected for a fixed time, even if nothing else references it. The touched
+//! blobs are kept in memory, separate from the live set of the store, which is cleared at the
+//! start of every garbage collection run.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh_bytes::Hash;
+
+/// The blobs touched within the last time to live, shared between the tasks of a node.
+#[derive(Debug, Clone)]
+pub struct TouchedBlobs {
+    /// The touched blobs, with the time at which they expire.
+    blobs: Arc<Mutex<HashMap<Hash, Instant>>>,
+    ttl: Duration,
+}
+
+impl TouchedBlobs {
+    /// Creates an empty set, in which every touch lasts for `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            blobs: Default::default(),
+            ttl,
+        }
+    }
+
+    /// Marks `hash` as touched now, extending the time it is kept if it was touched before.
+    pub fn touch(&self, hash: Hash) {
+        let expiry = Instant::now() + self.ttl;
+        self.blobs.lock().unwrap().insert(hash, expiry);
+    }
+
+    /// The blobs whose touch has not expired yet.
+    ///
+    /// Expired touches are dropped, so the set does not grow without bounds.
+    pub fn live(&self) -> Vec<Hash> {
+        let now = Instant::now();
+        let mut blobs = self.blobs.lock().unwrap();
+        blobs.retain(|_, expiry| *expiry > now);
+        blobs.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn touched_blobs_expire() {
+        let a = Hash::new(b"a");
+        let b = Hash::new(b"b");
+        let touched = TouchedBlobs::new(Duration::from_millis(50));
+        assert!(touched.live().is_empty());
+        touched.touch(a);
+        assert_eq!(touched.live(), vec![a]);
+        std::thread::sleep(Duration::from_millis(60));
+        touched.touch(b);
+        assert_eq!(touched.live(), vec![b]);
+    }
+}
@@ -1,4 +1,5 @@
 //! utilites for io and for reporting progress
 pub mod fs;
+pub mod idle;
 pub mod io;
 pub mod progress;
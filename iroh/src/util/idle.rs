@@ -0,0 +1,89 @@
+//! Tracking the activity of a node, to shut it down once it is idle.
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Shuts a node down after a period without activity, see
+/// [`Builder::idle_shutdown`](crate::node::Builder::idle_shutdown).
+///
+/// Every component that works on behalf of the node holds an [`IdleGuard`] while it does.
+#[derive(Debug)]
+pub struct IdleTimer {
+    timeout: Duration,
+    state: Mutex<IdleState>,
+}
+
+#[derive(Debug)]
+struct IdleState {
+    /// The number of activities in progress.
+    active: usize,
+    /// When the last activity ended.
+    last_activity: tokio::time::Instant,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            state: Mutex::new(IdleState {
+                active: 0,
+                last_activity: tokio::time::Instant::now(),
+            }),
+        }
+    }
+
+    /// Marks the start of an activity, which lasts until the returned guard is dropped.
+    pub fn activity(self: &Arc<Self>) -> IdleGuard {
+        self.state.lock().unwrap().active += 1;
+        IdleGuard(self.clone())
+    }
+
+    /// The time left until the node is shut down.
+    pub(crate) fn remaining(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        if state.active > 0 {
+            self.timeout
+        } else {
+            self.timeout.saturating_sub(state.last_activity.elapsed())
+        }
+    }
+
+    /// Cancels `cancel_token` once the node was idle for the timeout, starting from now.
+    ///
+    /// The timeout starts when this is called, not when the returned future is first polled.
+    pub(crate) fn run(
+        self: Arc<Self>,
+        cancel_token: CancellationToken,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        self.state.lock().unwrap().last_activity = tokio::time::Instant::now();
+        async move {
+            loop {
+                let remaining = self.remaining();
+                if remaining.is_zero() {
+                    info!("idle for {:?}, shutting down", self.timeout);
+                    cancel_token.cancel();
+                    break;
+                }
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = tokio::time::sleep(remaining) => {}
+                }
+            }
+        }
+    }
+}
+
+/// An activity that keeps the node from being idle, see [`IdleTimer::activity`].
+#[derive(Debug)]
+pub struct IdleGuard(Arc<IdleTimer>);
+
+impl Drop for IdleGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.active -= 1;
+        state.last_activity = tokio::time::Instant::now();
+    }
+}
@@ -5,14 +5,14 @@ use std::{
     ops::Range,
     path::PathBuf,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
 use futures::{
     future::{self, BoxFuture, LocalBoxFuture},
-    FutureExt,
+    FutureExt, TryStreamExt,
 };
 use iroh::{
     collection::{Blob, Collection},
@@ -27,17 +27,18 @@ use quic_rpc::transport::misc::DummyServerEndpoint;
 use rand::RngCore;
 use range_collections::RangeSet2;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
 use bao_tree::{blake3, ChunkNum};
 use iroh_bytes::{
-    baomap::{PartialMap, Store},
+    baomap::{Map, MapEntry, PartialMap, Store},
     collection::{CollectionParser, CollectionStats, LinkSeq, LinkSeqCollectionParser, LinkStream},
     get::{
         fsm::ConnectedNext,
         fsm::{self, DecodeError},
         Stats,
     },
-    protocol::{CustomGetRequest, GetRequest, RangeSpecSeq, Request, RequestToken},
+    protocol::{CustomGetRequest, GetRequest, RangeSpec, RangeSpecSeq, Request, RequestToken},
     provider::{self, CustomGetHandler, RequestAuthorizationHandler},
     util::{runtime, BlobFormat},
     Hash,
@@ -56,7 +57,7 @@ fn test_node<D: Store>(
 ) -> Builder<D, store::memory::Store, DummyServerEndpoint, LinkSeqCollectionParser> {
     let store = iroh_sync::store::memory::Store::default();
     Node::builder(db, store)
-        .collection_parser(LinkSeqCollectionParser)
+        .collection_parser(LinkSeqCollectionParser::default())
         .enable_derp(iroh_net::defaults::default_derp_map())
         .bind_addr(addr)
 }
@@ -272,7 +273,10 @@ where
     node.subscribe(move |event| {
         let events_sender = events_sender.clone();
         async move {
-            events_sender.send(event).ok();
+            // changes of the DERP status are not part of the transfer
+            if matches!(event, Event::ByteProvide(_)) {
+                events_sender.send(event).ok();
+            }
         }
         .boxed()
     })
@@ -320,6 +324,14 @@ where
 }
 
 fn assert_events(events: Vec<Event>, num_blobs: usize) {
+    // the root and every child are served
+    let (served, events): (Vec<_>, Vec<_>) = events.into_iter().partition(|event| {
+        matches!(
+            event,
+            Event::ByteProvide(provider::Event::BlobServed { .. })
+        )
+    });
+    assert_eq!(served.len(), num_blobs + 1, "unexpected {served:#?}");
     let num_basic_events = 4;
     let num_total_events = num_basic_events + num_blobs;
     assert_eq!(
@@ -507,6 +519,24 @@ async fn test_not_found() {
     .expect("get failed");
 }
 
+#[tokio::test]
+async fn test_tree_info() -> Result<()> {
+    // 1 MiB at 16 KiB blocks gives 64 leaf blocks, so 6 levels of hash pairs
+    let data = vec![0u8; 1024 * 1024];
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("data", &data)]);
+    let hash = hashes["data"];
+    let entry = Map::get(&db, &hash.into()).context("entry not found")?;
+    let info = entry.tree_info(true).await?;
+    assert_eq!(info.root, hash.into());
+    assert_eq!(info.size, data.len() as u64);
+    assert_eq!(info.chunks, 1024);
+    assert_eq!(info.depth, 6);
+    let levels = info.levels.context("levels not loaded")?;
+    let widths = levels.iter().map(|l| l.len()).collect::<Vec<_>>();
+    assert_eq!(widths, vec![1, 2, 4, 8, 16, 32]);
+    Ok(())
+}
+
 /// Simulate a node that has just begun downloading a blob, but does not yet have any data
 #[tokio::test]
 async fn test_chunk_not_found_1() {
@@ -1045,6 +1075,7 @@ async fn test_token_passthrough() -> Result<()> {
                         events_sender.send(tok).expect("receiver dropped");
                     }
                 }
+                Event::DerpStatus(_) => {}
             }
         }
         .boxed()
@@ -1084,3 +1115,1036 @@ async fn test_token_passthrough() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_send_blob_stats() -> Result<()> {
+    let data = vec![1u8; 5000];
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("test", &data)]);
+    let hash = hashes["test"].into();
+    let cancel = CancellationToken::new();
+
+    // the whole blob
+    let mut buf = Vec::new();
+    let stats = provider::send_blob(&db, hash, &RangeSpec::all(), 0, &cancel, &mut buf).await?;
+    assert_eq!(stats.status, provider::SentStatus::Sent);
+    assert_eq!(stats.size, 5000);
+    assert_eq!(stats.chunks_sent, 5);
+    assert_eq!(stats.bytes_sent, buf.len() as u64);
+
+    // a single chunk in the middle
+    let mut buf = Vec::new();
+    let ranges = RangeSpec::new(RangeSet2::from(ChunkNum(1)..ChunkNum(2)));
+    let stats = provider::send_blob(&db, hash, &ranges, 16 * 1024, &cancel, &mut buf).await?;
+    assert_eq!(stats.chunks_sent, 1);
+    assert_eq!(stats.bytes_sent, buf.len() as u64);
+    assert!(stats.bytes_sent < 5000);
+
+    // a blob we don't have
+    let mut buf = Vec::new();
+    let stats = provider::send_blob(
+        &db,
+        Hash::new(b"missing"),
+        &RangeSpec::all(),
+        0,
+        &cancel,
+        &mut buf,
+    )
+    .await?;
+    assert_eq!(stats.status, provider::SentStatus::NotFound);
+    assert_eq!(stats.bytes_sent, 0);
+
+    // a cancelled transfer
+    cancel.cancel();
+    let mut buf = Vec::new();
+    let stats = provider::send_blob(&db, hash, &RangeSpec::all(), 0, &cancel, &mut buf).await?;
+    assert_eq!(stats.status, provider::SentStatus::Cancelled);
+    assert_eq!(stats.size, 5000);
+    assert_eq!(stats.chunks_sent, 0);
+    assert_eq!(stats.bytes_sent, buf.len() as u64);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_live_get_partial_blob() -> Result<()> {
+    use iroh::baomap::mem::MutableMemFile;
+    use iroh_bytes::{baomap::PartialMapEntry, protocol::LiveGetRequest};
+    use iroh_io::AsyncSliceWriter;
+
+    let rt = test_runtime();
+    let mut data = vec![0u8; 50_000];
+    rand::thread_rng().fill_bytes(&mut data);
+    let (outboard, hash) = bao_tree::io::outboard(&data, iroh_bytes::IROH_BLOCK_SIZE);
+    let hash = Hash::from(hash);
+
+    // a partial entry with the complete outboard, but only the first 20000 bytes of data
+    let db = iroh::baomap::mem::Store::new(rt.clone());
+    let entry = db.get_or_create_partial(hash, data.len() as u64)?;
+    let mut ob = entry.outboard_mut().await?;
+    ob.data.write_bytes_at(0, outboard.into()).await?;
+    let mut dw = entry.data_writer().await?;
+    dw.write_bytes_at(0, Bytes::copy_from_slice(&data[..20_000]))
+        .await?;
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db.clone(), addr).runtime(&rt).spawn().await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+
+    let target = MutableMemFile::default();
+    let get = tokio::task::spawn({
+        let target = target.clone();
+        async move {
+            iroh_bytes::get::get_live_blob(&connection, LiveGetRequest::all(hash), target).await
+        }
+    });
+    // the provider sends what it has and then waits for the rest
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!get.is_finished());
+
+    dw.write_bytes_at(20_000, Bytes::copy_from_slice(&data[20_000..]))
+        .await?;
+    db.insert_complete(entry).await?;
+    let size = tokio::time::timeout(Duration::from_secs(10), get).await???;
+    assert_eq!(size, Some(data.len() as u64));
+    assert_eq!(target.freeze(), data);
+
+    // a blob the provider does not have
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+    let missing = Hash::new(b"missing");
+    let size = iroh_bytes::get::get_live_blob(
+        &connection,
+        LiveGetRequest::all(missing),
+        MutableMemFile::default(),
+    )
+    .await?;
+    assert_eq!(size, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cancel_live_get() -> Result<()> {
+    use iroh::baomap::mem::MutableMemFile;
+    use iroh_bytes::{baomap::PartialMapEntry, protocol::LiveGetRequest};
+    use iroh_io::AsyncSliceWriter;
+
+    let rt = test_runtime();
+    let mut data = vec![0u8; 50_000];
+    rand::thread_rng().fill_bytes(&mut data);
+    let (outboard, hash) = bao_tree::io::outboard(&data, iroh_bytes::IROH_BLOCK_SIZE);
+    let hash = Hash::from(hash);
+
+    // a partial entry that is never completed, so the live get waits forever
+    let db = iroh::baomap::mem::Store::new(rt.clone());
+    let entry = db.get_or_create_partial(hash, data.len() as u64)?;
+    let mut ob = entry.outboard_mut().await?;
+    ob.data.write_bytes_at(0, outboard.into()).await?;
+    let mut dw = entry.data_writer().await?;
+    dw.write_bytes_at(0, Bytes::copy_from_slice(&data[..20_000]))
+        .await?;
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db.clone(), addr).runtime(&rt).spawn().await?;
+    let client = node.client();
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+    let get = tokio::task::spawn(async move {
+        let target = MutableMemFile::default();
+        iroh_bytes::get::get_live_blob(&connection, LiveGetRequest::all(hash), target).await
+    });
+
+    let transfer = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(entry) = client.node.transfers().await?.into_iter().next() {
+                return anyhow::Ok(entry.transfer);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await??;
+    assert!(
+        client
+            .node
+            .cancel_transfer(transfer.connection_id, transfer.request_id)
+            .await?
+    );
+    // the getter sees the reset stream, instead of waiting for the rest of the blob
+    let res = tokio::time::timeout(Duration::from_secs(5), get).await??;
+    assert!(res.is_err());
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !client.node.transfers().await?.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_partial_get_partial_blob() -> Result<()> {
+    use iroh::baomap::mem::MutableMemFile;
+    use iroh_bytes::{baomap::PartialMapEntry, protocol::PartialGetRequest};
+    use iroh_io::AsyncSliceWriter;
+
+    let rt = test_runtime();
+    let mut data = vec![0u8; 50_000];
+    rand::thread_rng().fill_bytes(&mut data);
+    let (outboard, hash) = bao_tree::io::outboard(&data, iroh_bytes::IROH_BLOCK_SIZE);
+    let hash = Hash::from(hash);
+
+    // a partial entry with the complete outboard, but only the first 20000 bytes of data
+    let db = iroh::baomap::mem::Store::new(rt.clone());
+    let entry = db.get_or_create_partial(hash, data.len() as u64)?;
+    let mut ob = entry.outboard_mut().await?;
+    ob.data.write_bytes_at(0, outboard.into()).await?;
+    let mut dw = entry.data_writer().await?;
+    dw.write_bytes_at(0, Bytes::copy_from_slice(&data[..20_000]))
+        .await?;
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db.clone(), addr).runtime(&rt).spawn().await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+
+    // the provider sends the first chunk group, the rest is missing
+    let target = MutableMemFile::default();
+    let request = PartialGetRequest::bytes(hash, 1000..40_000);
+    let res = iroh_bytes::get::get_partial_blob(&connection, request, target.clone())
+        .await?
+        .context("blob not found")?;
+    assert_eq!(res.size, data.len() as u64);
+    assert_eq!(res.sent, RangeSet2::from(ChunkNum(0)..ChunkNum(16)));
+    assert_eq!(res.missing, RangeSet2::from(ChunkNum(16)..ChunkNum(40)));
+    assert_eq!(target.freeze(), data[..16 * 1024]);
+
+    // once the blob is complete, everything is sent
+    dw.write_bytes_at(20_000, Bytes::copy_from_slice(&data[20_000..]))
+        .await?;
+    db.insert_complete(entry).await?;
+    let target = MutableMemFile::default();
+    let request = PartialGetRequest::bytes(hash, 0..100_000);
+    let res = iroh_bytes::get::get_partial_blob(&connection, request, target.clone())
+        .await?
+        .context("blob not found")?;
+    assert_eq!(res.sent, RangeSet2::from(ChunkNum(0)..ChunkNum(49)));
+    assert!(res.missing.is_empty());
+    assert_eq!(target.freeze(), data);
+
+    // a blob the provider does not have
+    let missing = Hash::new(b"missing");
+    let request = PartialGetRequest::bytes(missing, 0..1000);
+    let res =
+        iroh_bytes::get::get_partial_blob(&connection, request, MutableMemFile::default()).await?;
+    assert_eq!(res, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw_get() -> Result<()> {
+    use iroh::baomap::mem::MutableMemFile;
+    use iroh_bytes::protocol::RawGetRequest;
+
+    let rt = test_runtime();
+    let mut data = vec![0u8; 300_000];
+    rand::thread_rng().fill_bytes(&mut data);
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("test", data.clone())]);
+    let hash = Hash::from(*hashes.get("test").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+
+    // the data is sent without the bao encoding, and verified at the end
+    let target = MutableMemFile::default();
+    let size = iroh_bytes::get::get_raw_blob(&connection, RawGetRequest::new(hash), target.clone())
+        .await?;
+    assert_eq!(size, Some(data.len() as u64));
+    assert_eq!(target.freeze(), data);
+
+    // a blob the provider does not have
+    let missing = Hash::new(b"missing");
+    let size = iroh_bytes::get::get_raw_blob(
+        &connection,
+        RawGetRequest::new(missing),
+        MutableMemFile::default(),
+    )
+    .await?;
+    assert_eq!(size, None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_get_rejects_blobs_over_max_size() -> Result<()> {
+    use iroh_bytes::{baomap::PartialMap, util::progress::IgnoreProgressSender};
+
+    let rt = test_runtime();
+    let mut data = vec![0u8; 50_000];
+    rand::thread_rng().fill_bytes(&mut data);
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("test", data.clone())]);
+    let hash = Hash::from(*hashes.get("test").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let addrs = node.local_endpoint_addresses().await?;
+
+    // the announced size is larger than the limit, so nothing is stored
+    let target = iroh::baomap::mem::Store::new(rt.clone());
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs.clone())).await?;
+    let err = iroh::get::get_blob(
+        &target,
+        connection,
+        &hash,
+        10_000,
+        IgnoreProgressSender::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("maximum"), "{err}");
+    assert!(target.get_partial(&hash).is_none());
+
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+    iroh::get::get_blob(
+        &target,
+        connection,
+        &hash,
+        data.len() as u64,
+        IgnoreProgressSender::default(),
+    )
+    .await?;
+    assert!(target.get(&hash).is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_serve_stats() -> Result<()> {
+    use iroh_bytes::util::progress::IgnoreProgressSender;
+
+    let rt = test_runtime();
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("a", b"hello"), ("b", b"world")]);
+    let a = Hash::from(*hashes.get("a").unwrap());
+    let b = Hash::from(*hashes.get("b").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("serve-stats.postcard");
+    let node = test_node(db, addr)
+        .serve_stats_path(path.clone())
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+
+    for hash in [a, b, a] {
+        let target = iroh::baomap::mem::Store::new(rt.clone());
+        let connection = iroh::dial::dial(get_options(node.peer_id(), addrs.clone())).await?;
+        iroh::get::get_blob(
+            &target,
+            connection,
+            &hash,
+            5,
+            IgnoreProgressSender::default(),
+        )
+        .await?;
+    }
+
+    let a_stats = node.serve_stats().get(&a).context("a was not served")?;
+    assert_eq!(a_stats.count, 2);
+    assert!(a_stats.bytes > 2 * 5);
+    let top = node.client().blobs.stats(1).await?;
+    assert_eq!(top, vec![(a, a_stats)]);
+    assert_eq!(node.serve_stats().get(&b).map(|stats| stats.count), Some(1));
+
+    // the stats are saved before the node finishes shutting down
+    let top = node.serve_stats().top(10);
+    node.shutdown();
+    node.await?;
+    let saved = iroh::serve_stats::ServeStats::load(&path)?;
+    assert_eq!(saved.top(10), top);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_structured_token_auth() -> Result<()> {
+    use iroh::{baomap::mem::MutableMemFile, node::StructuredTokenAuthHandler};
+    use iroh_bytes::protocol::{
+        ClaimValue, PartialGetRequest, StructuredToken, CLAIM_EXPIRES, CLAIM_HASH_PREFIX,
+    };
+
+    let rt = test_runtime();
+    let key = [5u8; 32];
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("a", b"hello"), ("b", b"world")]);
+    let a = Hash::from(*hashes.get("a").unwrap());
+    let b = Hash::from(*hashes.get("b").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr)
+        .custom_auth_handler(Arc::new(StructuredTokenAuthHandler::new(key)))
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let get = |request: PartialGetRequest| {
+        let opts = get_options(node.peer_id(), addrs.clone());
+        async move {
+            let connection = iroh::dial::dial(opts).await?;
+            iroh_bytes::get::get_partial_blob(&connection, request, MutableMemFile::default()).await
+        }
+    };
+
+    // a token that allows fetching `a` for the next minute
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH)? + Duration::from_secs(60);
+    let claims = [
+        (
+            CLAIM_EXPIRES.to_string(),
+            ClaimValue::U64(expires.as_secs()),
+        ),
+        (
+            CLAIM_HASH_PREFIX.to_string(),
+            ClaimValue::Bytes(Bytes::copy_from_slice(a.as_bytes())),
+        ),
+    ];
+    let token = StructuredToken::sign(claims.clone().into(), &key).to_request_token()?;
+    let request = |hash, token| PartialGetRequest {
+        token,
+        ..PartialGetRequest::bytes(hash, 0..5)
+    };
+    assert!(get(request(a, Some(token.clone()))).await?.is_some());
+
+    // other hashes, missing tokens and tokens signed with another key are rejected, the
+    // provider closes the stream without sending anything
+    assert!(!matches!(get(request(b, Some(token))).await, Ok(Some(_))));
+    assert!(!matches!(get(request(a, None)).await, Ok(Some(_))));
+    let forged = StructuredToken::sign(claims.into(), &[6u8; 32]).to_request_token()?;
+    assert!(!matches!(get(request(a, Some(forged))).await, Ok(Some(_))));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_revoke_token() -> Result<()> {
+    use iroh::{baomap::mem::MutableMemFile, node::StructuredTokenAuthHandler};
+    use iroh_bytes::protocol::{
+        ClaimValue, PartialGetRequest, RevokedToken, StructuredToken, CLAIM_EXPIRES,
+    };
+
+    let rt = test_runtime();
+    let dir = tempfile::tempdir()?;
+    let revoked_path = dir.path().join("revoked");
+    let key = [5u8; 32];
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("a", b"hello")]);
+    let a = Hash::from(*hashes.get("a").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let handler = StructuredTokenAuthHandler::persistent(key, revoked_path.clone())?;
+    let node = test_node(db, addr)
+        .custom_auth_handler(Arc::new(handler))
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let get = |token: StructuredToken| {
+        let opts = get_options(node.peer_id(), addrs.clone());
+        async move {
+            let request = PartialGetRequest {
+                token: Some(token.to_request_token()?),
+                ..PartialGetRequest::bytes(a, 0..5)
+            };
+            let connection = iroh::dial::dial(opts).await?;
+            iroh_bytes::get::get_partial_blob(&connection, request, MutableMemFile::default()).await
+        }
+    };
+
+    let expires = SystemTime::now().duration_since(UNIX_EPOCH)? + Duration::from_secs(60);
+    let claims = BTreeMap::from([(
+        CLAIM_EXPIRES.to_string(),
+        ClaimValue::U64(expires.as_secs()),
+    )]);
+    let token = StructuredToken::issue(claims.clone(), &key);
+    let other = StructuredToken::issue(claims, &key);
+    assert!(get(token.clone()).await?.is_some());
+
+    // a revoked token is rejected, other tokens are still accepted
+    let revoked = RevokedToken::of(&token).unwrap();
+    let client = node.client();
+    assert!(client.node.revoke_token(revoked.clone()).await?);
+    assert!(!client.node.revoke_token(revoked.clone()).await?);
+    assert!(!matches!(get(token).await, Ok(Some(_))));
+    assert!(get(other).await?.is_some());
+    assert_eq!(client.node.revoked_tokens().await?, vec![revoked.clone()]);
+
+    // the revoked tokens are persisted
+    let handler = StructuredTokenAuthHandler::persistent(key, revoked_path)?;
+    assert_eq!(handler.revoked(), vec![revoked]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_collection_listing() -> Result<()> {
+    use iroh::rpc_protocol::{CollectionListingEntry, ListingFormat};
+    use iroh_bytes::collection::LinkSeq;
+
+    let rt = test_runtime();
+    let (mut db, hashes) =
+        iroh::baomap::readonly_mem::Store::new([("a", &b"hello"[..]), ("b", &b"world!"[..])]);
+    let a = Hash::from(*hashes.get("a").unwrap());
+    let b = Hash::from(*hashes.get("b").unwrap());
+    let missing = Hash::new(b"missing");
+    let collection = Collection::new(
+        vec![
+            Blob {
+                name: "a".to_string(),
+                hash: a,
+            },
+            Blob {
+                name: "b".to_string(),
+                hash: b,
+            },
+        ],
+        11,
+    )?;
+    let named = db.insert_many(collection.to_blobs()).unwrap();
+    let links = db.insert([b, missing].into_iter().collect::<LinkSeq>().into_inner());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let client = node.client();
+
+    // children of an iroh collection are listed by name
+    let listing = client
+        .blobs
+        .collection_listing(named, ListingFormat::Json)
+        .await?;
+    let entries = ListingFormat::Json.decode(&listing)?;
+    let expected = vec![
+        CollectionListingEntry {
+            name: "a".to_string(),
+            hash: a.to_string(),
+            size: Some(5),
+        },
+        CollectionListingEntry {
+            name: "b".to_string(),
+            hash: b.to_string(),
+            size: Some(6),
+        },
+    ];
+    assert_eq!(entries, expected);
+    let json: serde_json::Value = serde_json::from_slice(&listing)?;
+    assert_eq!(json[1]["name"], "b");
+    assert_eq!(json[1]["hash"], b.to_string());
+    assert_eq!(json[1]["size"], 6);
+
+    let listing = client
+        .blobs
+        .collection_listing(named, ListingFormat::MsgPack)
+        .await?;
+    assert_eq!(ListingFormat::MsgPack.decode(&listing)?, expected);
+
+    // children of other collections are listed by index, missing children have no size
+    let listing = client
+        .blobs
+        .collection_listing(links, ListingFormat::Json)
+        .await?;
+    let entries = ListingFormat::Json.decode(&listing)?;
+    assert_eq!(
+        entries,
+        vec![
+            CollectionListingEntry {
+                name: "0".to_string(),
+                hash: b.to_string(),
+                size: Some(6),
+            },
+            CollectionListingEntry {
+                name: "1".to_string(),
+                hash: missing.to_string(),
+                size: None,
+            },
+        ]
+    );
+
+    assert!(client
+        .blobs
+        .collection_listing(missing, ListingFormat::Json)
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_stats() -> Result<()> {
+    use iroh::{baomap::mem::MutableMemFile, connection_stats::ConnectionDirection};
+    use iroh_bytes::protocol::PartialGetRequest;
+
+    let rt = test_runtime();
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("a", b"hello")]);
+    let a = Hash::from(*hashes.get("a").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let client = node.client();
+    let opts = get_options(node.peer_id(), node.local_endpoint_addresses().await?);
+    let peer = opts.secret_key.public();
+    assert!(client.node.peer_stats(peer).await?.is_empty());
+
+    // the connection is tracked while it is open
+    let connection = iroh::dial::dial(opts).await?;
+    let request = PartialGetRequest::bytes(a, 0..5);
+    iroh_bytes::get::get_partial_blob(&connection, request, MutableMemFile::default())
+        .await?
+        .context("blob not found")?;
+    let stats = client.node.peer_stats(peer).await?;
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].alpn.as_bytes(), iroh_bytes::protocol::ALPN);
+    assert_eq!(stats[0].direction, ConnectionDirection::Incoming);
+    assert!(stats[0].stats.sent_packets > 0);
+    assert!(stats[0].stats.rtt > Duration::ZERO);
+    assert_eq!(node.connections().peers(), vec![peer]);
+
+    // and no longer after it was closed
+    connection.close(0u32.into(), b"done");
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !client.node.peer_stats(peer).await?.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_list_and_cancel_transfers() -> Result<()> {
+    use iroh_bytes::protocol::Closed;
+
+    let rt = test_runtime();
+    // large enough to stall on flow control while the getter does not read
+    let data = make_test_data(16 * 1024 * 1024);
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("large", &data)]);
+    let hash = Hash::from(*hashes.get("large").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let client = node.client();
+    assert!(client.node.transfers().await?.is_empty());
+
+    let opts = get_options(node.peer_id(), node.local_endpoint_addresses().await?);
+    let peer = opts.secret_key.public();
+    let connection = iroh::dial::dial(opts).await?;
+    let (mut send, mut recv) = connection.open_bi().await?;
+    let request: Request = GetRequest::single(hash).into();
+    send.write_all(&postcard::to_stdvec(&request)?).await?;
+    send.finish().await?;
+
+    let transfer = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let transfers = client.node.transfers().await?;
+            if let Some(entry) = transfers.into_iter().next() {
+                if entry.transfer.bytes_sent > 0 {
+                    return anyhow::Ok(entry);
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await??;
+    assert_eq!(transfer.peer, Some(peer));
+    assert_eq!(transfer.transfer.hash, Some(hash));
+    assert!(transfer.transfer.bytes_sent < data.len() as u64);
+    assert!(transfer.transfer.started_at <= SystemTime::now());
+
+    // the transfer is counted for its connection
+    let connections = client.node.transfers_per_connection().await?;
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0].peer, Some(peer));
+    assert_eq!(
+        connections[0].connection.connection_id,
+        transfer.transfer.connection_id
+    );
+    assert_eq!(connections[0].connection.transfers, 1);
+
+    // cancelling resets the stream, and the transfer is no longer listed
+    let (connection_id, request_id) = (
+        transfer.transfer.connection_id,
+        transfer.transfer.request_id,
+    );
+    assert!(
+        client
+            .node
+            .cancel_transfer(connection_id, request_id)
+            .await?
+    );
+    let err = recv.read_to_end(data.len() * 2).await.unwrap_err();
+    assert!(matches!(
+        err,
+        quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)) if code == Closed::Cancelled.into()
+    ));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !client.node.transfers().await?.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    assert!(client.node.transfers_per_connection().await?.is_empty());
+    assert!(
+        !client
+            .node
+            .cancel_transfer(connection_id, request_id)
+            .await?
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_requests_limit() -> Result<()> {
+    use iroh_bytes::protocol::Closed;
+
+    let rt = test_runtime();
+    // large enough to stall on flow control while the getter does not read
+    let data = make_test_data(16 * 1024 * 1024);
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("large", &data)]);
+    let hash = Hash::from(*hashes.get("large").unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr)
+        .max_connection_requests(2)
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let opts = get_options(node.peer_id(), node.local_endpoint_addresses().await?);
+    let connection = iroh::dial::dial(opts).await?;
+    let request: Request = GetRequest::single(hash).into();
+    let request = postcard::to_stdvec(&request)?;
+    let mut streams = Vec::new();
+    for _ in 0..3 {
+        let (mut send, recv) = connection.open_bi().await?;
+        // the rejected stream is stopped by the node, so writing to it may fail
+        send.write_all(&request).await.ok();
+        send.finish().await.ok();
+        streams.push(recv);
+    }
+
+    // the first two requests stall while their responses are not read, the third one is
+    // rejected right away
+    let mut rejected = streams.pop().unwrap();
+    let err = tokio::time::timeout(Duration::from_secs(5), rejected.read_to_end(data.len() * 2))
+        .await?
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        quinn::ReadToEndError::Read(quinn::ReadError::Reset(code)) if code == Closed::RateLimited.into()
+    ));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_ahead() -> Result<()> {
+    let rt = test_runtime();
+    let (db, hash) = create_test_db([
+        ("small", make_test_data(100)),
+        ("large", make_test_data(1024 * 1024 + 100)),
+    ]);
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr)
+        .read_ahead(64 * 1024)
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let request = GetRequest::all(hash).into();
+    let (collection, children, _stats) =
+        run_collection_get_request(get_options(node.peer_id(), addrs), request).await?;
+    validate_children(collection, children)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_transfer_memory() -> Result<()> {
+    let memory = provider::TransferMemory::new(32 * 1024);
+    let first = memory.acquire(16 * 1024).await;
+    assert_eq!(memory.in_use(), 16 * 1024);
+    // more than the whole budget waits until it has the budget to itself
+    let whole = memory.acquire(1024 * 1024);
+    tokio::pin!(whole);
+    assert!(tokio::time::timeout(Duration::from_millis(50), &mut whole)
+        .await
+        .is_err());
+    drop(first);
+    let whole = whole.await;
+    assert_eq!(whole.bytes(), 32 * 1024);
+    drop(whole);
+    assert_eq!(memory.in_use(), 0);
+
+    // a transfer that needs more than the budget of the node still completes
+    let rt = test_runtime();
+    let (db, hash) = create_test_db([
+        ("small", make_test_data(100)),
+        ("large", make_test_data(1024 * 1024 + 100)),
+    ]);
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr)
+        .read_ahead(64 * 1024)
+        .max_transfer_memory(16 * 1024)
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let request = GetRequest::all(hash).into();
+    let (collection, children, _stats) =
+        run_collection_get_request(get_options(node.peer_id(), addrs), request).await?;
+    validate_children(collection, children)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_blob_set_diff() -> Result<()> {
+    use iroh_bytes::protocol::BlobSetDiffRequest;
+
+    let rt = test_runtime();
+    let (db, hashes) =
+        iroh::baomap::readonly_mem::Store::new([("a", b"a"), ("b", b"b"), ("c", b"c")]);
+    let hash = |name: &str| Hash::from(*hashes.get(name).unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr).runtime(&rt).spawn().await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+
+    // we have b and a blob the provider does not have
+    let request = BlobSetDiffRequest::new([hash("b"), Hash::new(b"d")]);
+    let mut missing = iroh_bytes::get::get_blob_set_diff(&connection, request)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    missing.sort();
+    let mut expected = vec![hash("a"), hash("c")];
+    expected.sort();
+    assert_eq!(missing, expected);
+
+    // nothing is missing if we have everything
+    let request = BlobSetDiffRequest::new(["a", "b", "c"].map(hash));
+    let missing = iroh_bytes::get::get_blob_set_diff(&connection, request)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(missing.is_empty());
+
+    // requests with too many prefixes are rejected without listing anything
+    let too_many = (0..=iroh_bytes::protocol::MAX_BLOB_SET_DIFF_PREFIXES as u64)
+        .map(|i| Hash::new(i.to_le_bytes()));
+    let request = BlobSetDiffRequest::new(too_many);
+    let missing = iroh_bytes::get::get_blob_set_diff(&connection, request)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await;
+    assert!(missing.map_or(true, |missing| missing.is_empty()));
+    Ok(())
+}
+
+/// An authorization handler that allows all requests, but hides one blob from listings.
+#[derive(Debug)]
+struct HideBlobAuthHandler(Hash);
+
+impl RequestAuthorizationHandler for HideBlobAuthHandler {
+    fn authorize(
+        &self,
+        _token: Option<RequestToken>,
+        _request: &iroh_bytes::protocol::Request,
+    ) -> BoxFuture<'static, Result<()>> {
+        async move { Ok(()) }.boxed()
+    }
+
+    fn list_blob(&self, _token: Option<&RequestToken>, hash: &Hash) -> bool {
+        *hash != self.0
+    }
+}
+
+#[tokio::test]
+async fn test_blob_set_diff_hidden() -> Result<()> {
+    use iroh_bytes::protocol::BlobSetDiffRequest;
+
+    let rt = test_runtime();
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("a", b"a"), ("b", b"b")]);
+    let hash = |name: &str| Hash::from(*hashes.get(name).unwrap());
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let node = test_node(db, addr)
+        .custom_auth_handler(Arc::new(HideBlobAuthHandler(hash("a"))))
+        .runtime(&rt)
+        .spawn()
+        .await?;
+    let addrs = node.local_endpoint_addresses().await?;
+    let connection = iroh::dial::dial(get_options(node.peer_id(), addrs)).await?;
+
+    let request = BlobSetDiffRequest::new([]);
+    let missing = iroh_bytes::get::get_blob_set_diff(&connection, request)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(missing, vec![hash("b")]);
+    Ok(())
+}
+
+/// An event sender that collects the events, for tests that drive the provider directly.
+#[derive(Clone, Debug, Default)]
+struct EventCollector(Arc<std::sync::Mutex<Vec<provider::Event>>>);
+
+impl provider::EventSender for EventCollector {
+    fn send(&self, event: provider::Event) -> BoxFuture<'_, ()> {
+        self.0.lock().unwrap().push(event);
+        future::ready(()).boxed()
+    }
+}
+
+/// Send `request` to the provider over an in-process stream and return the raw response.
+async fn duplex_request(
+    db: iroh::baomap::readonly_mem::Store,
+    request: Request,
+    custom_get_handler: Arc<dyn CustomGetHandler>,
+    events: EventCollector,
+) -> Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut client_send, server_recv) = tokio::io::duplex(64 * 1024);
+    let (server_send, mut client_recv) = tokio::io::duplex(64 * 1024);
+    let writer = provider::ResponseWriter::new(
+        server_send,
+        events,
+        0,
+        0,
+        0,
+        Default::default(),
+        CancellationToken::new(),
+    );
+    let server = provider::handle_stream(
+        db,
+        server_recv,
+        writer,
+        custom_get_handler,
+        Arc::new(CustomAuthHandler),
+        LinkSeqCollectionParser::default(),
+    );
+    let client = async {
+        client_send
+            .write_all(&postcard::to_stdvec(&request)?)
+            .await?;
+        client_send.shutdown().await?;
+        let mut response = Vec::new();
+        client_recv.read_to_end(&mut response).await?;
+        anyhow::Ok(response)
+    };
+    let (server, response) = tokio::join!(server, client);
+    server?;
+    response
+}
+
+#[tokio::test]
+async fn test_duplex_get() -> Result<()> {
+    let data = vec![1u8; 1024];
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("test", &data)]);
+    let hash = Hash::from(hashes["test"]);
+    let token = Some(RequestToken::new(vec![1, 2, 3, 4, 5, 6])?);
+    let custom_get_handler = Arc::new(BlobCustomHandler { hash });
+
+    // a blob that fits into a single chunk group is sent as its size and data
+    let mut expected = (data.len() as u64).to_le_bytes().to_vec();
+    expected.extend_from_slice(&data);
+    let events = EventCollector::default();
+    let request = Request::Get(GetRequest::single(hash).with_token(token.clone()));
+    let response = duplex_request(
+        db.clone(),
+        request,
+        custom_get_handler.clone(),
+        events.clone(),
+    )
+    .await?;
+    assert_eq!(response, expected);
+    assert!(matches!(
+        events.0.lock().unwrap().last(),
+        Some(provider::Event::TransferCollectionCompleted { .. })
+    ));
+
+    // a custom request is answered with the get request, followed by the response to it
+    let request = Request::CustomGet(CustomGetRequest {
+        token: token.clone(),
+        data: Bytes::new(),
+    });
+    let response = duplex_request(
+        db.clone(),
+        request,
+        custom_get_handler.clone(),
+        EventCollector::default(),
+    )
+    .await?;
+    assert!(response.ends_with(&expected));
+    assert!(response.len() > expected.len());
+
+    // a blob we don't have
+    let events = EventCollector::default();
+    let missing = GetRequest::single(Hash::new(b"missing")).with_token(token);
+    let response = duplex_request(
+        db.clone(),
+        Request::Get(missing),
+        custom_get_handler.clone(),
+        events.clone(),
+    )
+    .await?;
+    assert!(response.is_empty());
+    assert!(matches!(
+        events.0.lock().unwrap().last(),
+        Some(provider::Event::TransferAborted { .. })
+    ));
+
+    // unauthorized requests are rejected
+    let request = Request::Get(GetRequest::single(hash));
+    let res = duplex_request(db, request, custom_get_handler, EventCollector::default()).await;
+    assert!(res.is_err());
+    Ok(())
+}
+
+/// A provider that closes the stream in the middle of a blob must not produce a successful get
+///
+/// The bao decoder already fails when the stream ends early, so this never gets to the
+/// [`DecodeError::Truncated`] check at the end of the blob.
+#[tokio::test]
+async fn test_get_truncated() -> Result<()> {
+    let _guard = iroh_test::logging::setup();
+    let data = (0..1024 * 64).map(|i| i as u8).collect::<Vec<_>>();
+    let (db, hashes) = iroh::baomap::readonly_mem::Store::new([("test", &data)]);
+    let hash = Hash::from(hashes["test"]);
+    let token = Some(RequestToken::new(vec![1, 2, 3, 4, 5, 6])?);
+    let request = Request::Get(GetRequest::single(hash).with_token(token));
+
+    // the complete response, as a well-behaved provider would send it
+    let response = duplex_request(
+        db,
+        request.clone(),
+        Arc::new(BlobCustomHandler { hash }),
+        EventCollector::default(),
+    )
+    .await?;
+
+    let server = MagicEndpoint::builder()
+        .disable_derp()
+        .alpns(vec![iroh_bytes::protocol::ALPN.to_vec()])
+        .bind_addrs(vec![(Ipv4Addr::LOCALHOST, 0).into()])
+        .bind(0)
+        .await?;
+    let server_addr = PeerAddr::from_parts(server.peer_id(), None, vec![server.local_addr()?.0]);
+    let server_task = tokio::spawn(async move {
+        let conn = server.accept().await.context("no connection")?.await?;
+        let (mut send, mut recv) = conn.accept_bi().await?;
+        recv.read_to_end(1024).await?;
+        // send the size header and part of the first chunk group only
+        send.write_all(&response[..response.len() / 2]).await?;
+        send.finish().await?;
+        conn.closed().await;
+        anyhow::Ok(())
+    });
+
+    let client = MagicEndpoint::builder().disable_derp().bind(0).await?;
+    let conn = client
+        .connect(server_addr, &iroh_bytes::protocol::ALPN)
+        .await?;
+    let connected = fsm::start(conn.clone(), request).next().await?;
+    let ConnectedNext::StartRoot(start) = connected.next().await? else {
+        bail!("request did not include the root");
+    };
+    let res = start.next().concatenate_into_vec().await;
+    assert!(matches!(
+        res,
+        Err(DecodeError::LeafNotFound(_) | DecodeError::ParentNotFound(_))
+    ));
+    conn.close(0u32.into(), b"done");
+    server_task.await??;
+    Ok(())
+}
@@ -1,16 +1,16 @@
 #![cfg(feature = "mem-db")]
 
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, bail, Result};
-use futures::{Stream, StreamExt, TryStreamExt};
+use futures::{future::BoxFuture, FutureExt, Stream, StreamExt, TryStreamExt};
 use iroh::{
     client::mem::Doc,
     node::{Builder, Node},
-    rpc_protocol::ShareMode,
-    sync_engine::{LiveEvent, SyncEvent},
+    rpc_protocol::{ContentResolution, DocSetStreamResponse, DocTicket, KeyKind, ShareMode},
+    sync_engine::{Discovery, LiveEvent, SyncEvent},
 };
-use iroh_net::key::PublicKey;
+use iroh_net::{key::PublicKey, PeerAddr};
 use quic_rpc::transport::misc::DummyServerEndpoint;
 use tracing::{debug, info};
 use tracing_subscriber::{prelude::*, EnvFilter};
@@ -39,6 +39,7 @@ fn test_node(
         .enable_derp(iroh_net::defaults::default_derp_map())
         .runtime(&rt)
         .bind_addr(addr)
+        .auto_download(true)
 }
 
 async fn spawn_node(
@@ -113,6 +114,230 @@ async fn sync_simple() -> Result<()> {
     Ok(())
 }
 
+/// Two documents with the same content both report the content as ready, although it is only
+/// downloaded once.
+#[tokio::test]
+async fn sync_content_ready_shared_content() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let nodes = spawn_nodes(rt, 2).await?;
+    let clients = nodes.iter().map(|node| node.client()).collect::<Vec<_>>();
+
+    let author0 = clients[0].authors.create().await?;
+    let mut tickets = Vec::new();
+    let mut hash = None;
+    for _ in 0..2 {
+        let doc0 = clients[0].docs.create().await?;
+        hash = Some(
+            doc0.set_bytes(author0, b"k1".to_vec(), b"shared".to_vec())
+                .await?,
+        );
+        tickets.push(doc0.share(ShareMode::Write).await?);
+    }
+    let hash = hash.unwrap();
+
+    let mut events = Vec::new();
+    for ticket in tickets {
+        let doc1 = clients[1].docs.import(ticket).await?;
+        events.push(doc1.subscribe().await?);
+    }
+    for events in events.iter_mut() {
+        // The content is ready once it is downloaded or, if the download for the other
+        // document finished first, when the entry is inserted.
+        wait_for(events, |e| match e {
+            LiveEvent::ContentReady { hash: h } => *h == hash,
+            LiveEvent::InsertRemote { content_status, .. } => {
+                *content_status == ContentStatus::Complete
+            }
+            _ => false,
+        })
+        .await?;
+    }
+
+    for node in nodes {
+        node.shutdown();
+    }
+    Ok(())
+}
+
+/// Without auto download, content of entries from peers is only fetched on request.
+#[tokio::test]
+async fn sync_no_auto_download() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node0 = spawn_node(rt.clone(), 0).await?;
+    let node1 = test_node(rt, "127.0.0.1:0".parse()?)
+        .auto_download(false)
+        .spawn()
+        .await?;
+    let client0 = node0.client();
+    let client1 = node1.client();
+
+    let peer0 = node0.peer_id();
+    let author0 = client0.authors.create().await?;
+    let doc0 = client0.docs.create().await?;
+    let doc_id = doc0.id();
+    doc0.set_bytes(author0, b"k1".to_vec(), b"v1".to_vec())
+        .await?;
+    let ticket = doc0.share(ShareMode::Write).await?;
+
+    let doc1 = client1.docs.import(ticket).await?;
+    let mut events1 = doc1.subscribe().await?;
+    wait_for(&mut events1, |e| match_sync_finished(e, peer0, doc_id)).await?;
+
+    let get = |content| {
+        let doc1 = doc1.clone();
+        async move {
+            doc1.get_many_with_content(GetFilter::All, content)
+                .await?
+                .map_ok(|(entry, status)| (entry.key().to_vec(), status))
+                .try_collect::<Vec<_>>()
+                .await
+        }
+    };
+    assert_eq!(
+        get(ContentResolution::IncludeMissing).await?,
+        vec![(b"k1".to_vec(), ContentStatus::Missing)]
+    );
+    assert_eq!(
+        get(ContentResolution::FetchMissing { timeout: LIMIT }).await?,
+        vec![(b"k1".to_vec(), ContentStatus::Complete)]
+    );
+    assert_latest(&doc1, b"k1", b"v1").await;
+
+    node0.shutdown();
+    node1.shutdown();
+    Ok(())
+}
+
+/// A [`Discovery`] that always returns the same peers, and records which documents it was
+/// asked for.
+#[derive(Debug)]
+struct StaticDiscovery {
+    peers: Vec<PeerAddr>,
+    requests: flume::Sender<NamespaceId>,
+}
+
+impl Discovery for StaticDiscovery {
+    fn find_peers(&self, namespace: NamespaceId) -> BoxFuture<'static, Result<Vec<PeerAddr>>> {
+        self.requests.send(namespace).ok();
+        futures::future::ok(self.peers.clone()).boxed()
+    }
+}
+
+/// Test that the discovery is asked for peers when importing a doc from a ticket without peers.
+#[tokio::test]
+async fn sync_discovery() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node0 = spawn_node(rt.clone(), 0).await?;
+    let (requests_tx, requests_rx) = flume::unbounded();
+    let discovery = StaticDiscovery {
+        peers: vec![node0.my_addr().await?],
+        requests: requests_tx,
+    };
+    let node1 = test_node(rt, "127.0.0.1:0".parse()?)
+        .discovery(Arc::new(discovery))
+        .spawn()
+        .await?;
+
+    let doc0 = node0.client().docs.create().await?;
+    let mut ticket = doc0.share(ShareMode::Read).await?;
+    ticket.peers.clear();
+    let doc1 = node1.client().docs.import(ticket).await?;
+    let namespace = tokio::time::timeout(LIMIT, requests_rx.recv_async()).await??;
+    assert_eq!(namespace, doc1.id());
+
+    // the doc was started with peers this time, so no discovery is needed
+    let doc2 = node1.client().docs.create().await?;
+    doc2.start_sync(vec![node0.my_addr().await?]).await?;
+    assert!(requests_rx.try_recv().is_err());
+
+    node0.shutdown();
+    node1.shutdown();
+    Ok(())
+}
+
+/// Test that no peers are looked up while paused, and that this is caught up on resume.
+#[tokio::test]
+async fn sync_pause_resume() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node0 = spawn_node(rt.clone(), 0).await?;
+    let (requests_tx, requests_rx) = flume::unbounded();
+    let discovery = StaticDiscovery {
+        peers: vec![node0.my_addr().await?],
+        requests: requests_tx,
+    };
+    let node1 = test_node(rt, "127.0.0.1:0".parse()?)
+        .discovery(Arc::new(discovery))
+        .spawn()
+        .await?;
+    let client1 = node1.client();
+    client1.docs.pause().await?;
+
+    let doc0 = node0.client().docs.create().await?;
+    let mut ticket = doc0.share(ShareMode::Read).await?;
+    ticket.peers.clear();
+    let doc1 = client1.docs.import(ticket).await?;
+    assert!(doc1.status().await?.paused);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(requests_rx.try_recv().is_err());
+
+    client1.docs.resume().await?;
+    assert!(!doc1.status().await?.paused);
+    let namespace = tokio::time::timeout(LIMIT, requests_rx.recv_async()).await??;
+    assert_eq!(namespace, doc1.id());
+
+    node0.shutdown();
+    node1.shutdown();
+    Ok(())
+}
+
+/// Test that the gossip secret of a doc is shared with tickets and stored on import.
+#[tokio::test]
+async fn sync_gossip_auth_ticket() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let nodes = spawn_nodes(rt, 2).await?;
+    let clients = nodes.iter().map(|node| node.client()).collect::<Vec<_>>();
+
+    let doc0 = clients[0].docs.create().await?;
+    assert!(doc0.share(ShareMode::Read).await?.gossip_secret.is_none());
+    doc0.set_gossip_auth(true).await?;
+    let ticket = doc0.share(ShareMode::Read).await?;
+    let secret = ticket.gossip_secret.expect("gossip secret is shared");
+    // enabling again keeps the secret
+    doc0.set_gossip_auth(true).await?;
+    assert_eq!(
+        doc0.share(ShareMode::Read).await?.gossip_secret,
+        Some(secret)
+    );
+
+    let ticket = ticket.to_string().parse()?;
+    let doc1 = clients[1].docs.import(ticket).await?;
+    assert_eq!(
+        doc1.share(ShareMode::Read).await?.gossip_secret,
+        Some(secret)
+    );
+
+    doc0.set_gossip_auth(false).await?;
+    assert!(doc0.share(ShareMode::Read).await?.gossip_secret.is_none());
+
+    // tickets from before gossip secrets were added can still be parsed
+    let shared = doc0.share(ShareMode::Read).await?;
+    let legacy = postcard::to_stdvec(&(&shared.capability, &shared.peers))?;
+    let parsed = DocTicket::from_bytes(&legacy)?;
+    assert_eq!(parsed.capability.id(), doc0.id());
+    assert_eq!(parsed.peers, shared.peers);
+    assert!(parsed.gossip_secret.is_none());
+
+    for node in nodes {
+        node.shutdown();
+    }
+    Ok(())
+}
+
 /// Test subscribing to replica events (without sync)
 #[tokio::test]
 async fn sync_subscribe() -> Result<()> {
@@ -309,6 +534,288 @@ async fn sync_subscribe_stop() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn doc_get_keys() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    let alice = client.authors.create().await?;
+    let bob = client.authors.create().await?;
+    doc.set_bytes(alice, b"a".to_vec(), b"1".to_vec()).await?;
+    doc.set_bytes(alice, b"b".to_vec(), b"2".to_vec()).await?;
+    doc.set_bytes(bob, b"b".to_vec(), b"3".to_vec()).await?;
+
+    let keys = vec![b"a".to_vec(), b"b".to_vec(), b"missing".to_vec()];
+    let entries = doc
+        .get_keys(keys.clone(), Some(alice))
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let found = entries.iter().map(|e| e.key().to_vec()).collect::<Vec<_>>();
+    assert_eq!(found, vec![b"a".to_vec(), b"b".to_vec()]);
+    assert!(entries.iter().all(|e| e.author() == alice));
+
+    let entries = doc
+        .get_keys(keys, None)
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert_eq!(entries.len(), 3);
+
+    node.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_move_key() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    let author = client.authors.create().await?;
+    let hash = doc
+        .set_bytes(author, b"old".to_vec(), b"content".to_vec())
+        .await?;
+    doc.move_key(author, b"old".to_vec(), b"new".to_vec())
+        .await?;
+
+    assert_latest(&doc, b"new", b"content").await;
+    let entry = doc.get_one(author, b"new".to_vec()).await?.unwrap();
+    assert_eq!(entry.content_hash(), hash);
+    let old = doc.get_one(author, b"old".to_vec()).await?.unwrap();
+    assert!(old.record().is_empty());
+
+    // the old key is deleted now, so it can't be moved again
+    assert!(doc
+        .move_key(author, b"old".to_vec(), b"other".to_vec())
+        .await
+        .is_err());
+
+    node.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_export_tar() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    let alice = client.authors.create().await?;
+    let bob = client.authors.create().await?;
+    doc.set_bytes(alice, b"files/a".to_vec(), b"1".to_vec())
+        .await?;
+    doc.set_bytes(alice, b"files/dir/b".to_vec(), vec![2u8; 1000])
+        .await?;
+    doc.set_bytes(bob, b"files/dir/b".to_vec(), b"3".to_vec())
+        .await?;
+    doc.set_bytes(alice, b"files/old".to_vec(), b"4".to_vec())
+        .await?;
+    doc.move_key(alice, b"files/old".to_vec(), b"other".to_vec())
+        .await?;
+    doc.set_bytes(alice, b"files/../evil".to_vec(), b"5".to_vec())
+        .await?;
+
+    let archive = doc
+        .export_tar(b"files/".to_vec())
+        .await?
+        .try_fold(Vec::new(), |mut archive, chunk| async move {
+            archive.extend_from_slice(&chunk);
+            Ok(archive)
+        })
+        .await?;
+    let mut files = Vec::new();
+    for file in tar::Archive::new(&archive[..]).entries()? {
+        let mut file = file?;
+        let path = file.path()?.to_string_lossy().to_string();
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut file, &mut content)?;
+        files.push((path, content));
+    }
+    // the latest entry of each key, without deleted entries and keys that are not paths
+    assert_eq!(
+        files,
+        vec![
+            ("files/a".to_string(), b"1".to_vec()),
+            ("files/dir/b".to_string(), b"3".to_vec()),
+        ]
+    );
+
+    node.shutdown();
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_get_many_with_content() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    let author = client.authors.create().await?;
+    doc.set_bytes(author, b"a".to_vec(), b"1".to_vec()).await?;
+    let hash = doc.set_bytes(author, b"b".to_vec(), b"2".to_vec()).await?;
+    client.blobs.delete_blob(hash).await?;
+
+    let get = |content| {
+        let doc = doc.clone();
+        async move {
+            doc.get_many_with_content(GetFilter::All, content)
+                .await?
+                .map_ok(|(entry, status)| (entry.key().to_vec(), status))
+                .try_collect::<Vec<_>>()
+                .await
+        }
+    };
+    let all = vec![
+        (b"a".to_vec(), ContentStatus::Complete),
+        (b"b".to_vec(), ContentStatus::Missing),
+    ];
+    assert_eq!(get(ContentResolution::IncludeMissing).await?, all);
+    assert_eq!(
+        get(ContentResolution::SkipMissing).await?,
+        vec![(b"a".to_vec(), ContentStatus::Complete)]
+    );
+    // without peers the missing content can not be fetched, the entry is still returned
+    let timeout = Duration::from_secs(1);
+    assert_eq!(get(ContentResolution::FetchMissing { timeout }).await?, all);
+
+    node.shutdown();
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_default_author() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    assert_eq!(doc.default_author().await?, None);
+
+    // the first write without an author creates the default author
+    doc.set_bytes_default_author(b"k".to_vec(), b"1".to_vec())
+        .await?;
+    let first = doc
+        .default_author()
+        .await?
+        .expect("default author is created");
+    assert!(doc.get_one(first, b"k".to_vec()).await?.is_some());
+
+    // rotating the default author keeps the entries of the previous one
+    let second = client.authors.create().await?;
+    doc.set_default_author(Some(second)).await?;
+    assert_eq!(doc.default_author().await?, Some(second));
+    doc.set_bytes_default_author(b"k".to_vec(), b"2".to_vec())
+        .await?;
+    let entry = doc.get_one(first, b"k".to_vec()).await?.unwrap();
+    assert_eq!(entry.content_hash(), iroh_bytes::Hash::new(b"1"));
+    let entry = doc.get_one(second, b"k".to_vec()).await?.unwrap();
+    assert_eq!(entry.content_hash(), iroh_bytes::Hash::new(b"2"));
+
+    // unknown authors are rejected
+    let unknown = iroh_sync::Author::new(&mut rand::thread_rng()).id();
+    assert!(doc.set_default_author(Some(unknown)).await.is_err());
+
+    doc.set_default_author(None).await?;
+    assert_eq!(doc.default_author().await?, None);
+
+    node.shutdown();
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_retention() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    let doc = client.docs.create().await?;
+    let other = client.docs.create().await?;
+    assert_eq!(doc.retention().await?, None);
+
+    let retention = Duration::from_secs(60 * 60);
+    doc.set_retention(Some(retention)).await?;
+    assert_eq!(doc.retention().await?, Some(retention));
+    assert_eq!(other.retention().await?, None);
+
+    doc.set_retention(None).await?;
+    assert_eq!(doc.retention().await?, None);
+
+    node.shutdown();
+    Ok(())
+}
+
+#[tokio::test]
+async fn doc_set_stream() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+    let doc = client.docs.create().await?;
+    let author = client.authors.create().await?;
+
+    let chunks = (0..4u8)
+        .map(|i| Ok(bytes::Bytes::from(vec![i; 100_000])))
+        .collect::<Vec<std::io::Result<_>>>();
+    let value = chunks
+        .iter()
+        .flat_map(|chunk| chunk.as_ref().unwrap().to_vec())
+        .collect::<Vec<_>>();
+    let responses = doc
+        .set_stream(Some(author), b"big".to_vec(), futures::stream::iter(chunks))
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+    let (last, progress) = responses.split_last().unwrap();
+    let offsets = progress
+        .iter()
+        .map(|res| match res {
+            DocSetStreamResponse::Progress { offset } => *offset,
+            res => panic!("unexpected response {res:?}"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(offsets, vec![100_000, 200_000, 300_000, 400_000]);
+    let DocSetStreamResponse::Done { entry } = last else {
+        panic!("unexpected response {last:?}");
+    };
+    assert_eq!(entry.author_bytes(), author);
+    assert_eq!(entry.content_len(), value.len() as u64);
+    assert_latest(&doc, b"big", &value).await;
+
+    // a value that fails to be read does not create an entry
+    let chunks: Vec<std::io::Result<bytes::Bytes>> = vec![
+        Ok(b"partial".to_vec().into()),
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "read failed",
+        )),
+    ];
+    let res = doc
+        .set_stream(None, b"failed".to_vec(), futures::stream::iter(chunks))
+        .await?
+        .try_collect::<Vec<_>>()
+        .await;
+    assert!(res.is_err());
+    assert!(get_latest(&doc, b"failed").await.is_err());
+
+    node.shutdown();
+    Ok(())
+}
+
 async fn assert_latest(doc: &Doc, key: &[u8], value: &[u8]) {
     let content = get_latest(doc, key).await.unwrap();
     assert_eq!(content, value.to_vec());
@@ -344,6 +851,25 @@ async fn next<T: std::fmt::Debug>(mut stream: impl Stream<Item = Result<T>> + Un
     event
 }
 
+/// Wait until the stream yields an element matching `f`, skipping all other elements.
+///
+/// If [`LIMIT`] is exceeded before a matching element is found an error is returned.
+async fn wait_for<T: std::fmt::Debug>(
+    stream: &mut (impl Stream<Item = Result<T>> + Unpin),
+    f: impl Fn(&T) -> bool,
+) -> Result<T> {
+    tokio::time::timeout(LIMIT, async {
+        loop {
+            let event = next(&mut *stream).await;
+            if f(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("no matching element in {LIMIT:?}"))
+}
+
 /// Collect the next n elements of a [`TryStream`]
 ///
 /// If `timeout` is exceeded before n elements are collected an error is returned.
@@ -421,3 +947,64 @@ fn match_sync_finished(event: &LiveEvent, peer: PublicKey, namespace: NamespaceI
         finished: e.finished,
     }
 }
+
+#[tokio::test]
+async fn author_import() -> Result<()> {
+    setup_logging();
+    let rt = test_runtime();
+    let node = spawn_node(rt, 0).await?;
+    let client = node.client();
+
+    // import from a secret key
+    let author = iroh_sync::Author::new(&mut rand::thread_rng());
+    let author_id = client
+        .authors
+        .import(author.to_bytes(), KeyKind::Secret)
+        .await?;
+    assert_eq!(author_id, author.id());
+    let authors = client.authors.list().await?.try_collect::<Vec<_>>().await?;
+    assert!(authors.contains(&author_id));
+
+    // a public key passed as secret key
+    let err = client
+        .authors
+        .import(author_id.to_bytes(), KeyKind::Secret)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("not a secret key"), "{err}");
+
+    // a public key of an author we have the secret key for
+    let id = client
+        .authors
+        .import(author_id.to_bytes(), KeyKind::Public)
+        .await?;
+    assert_eq!(id, author_id);
+
+    // a public key of an unknown author
+    let unknown = iroh_sync::Author::new(&mut rand::thread_rng());
+    let err = client
+        .authors
+        .import(unknown.id().to_bytes(), KeyKind::Public)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("no secret key"), "{err}");
+
+    // bytes that are not a point on the curve
+    let invalid = (0..=u8::MAX)
+        .map(|i| [i; 32])
+        .find(|bytes| iroh_sync::AuthorPublicKey::from_bytes(bytes).is_err())
+        .expect("invalid point");
+    let err = client
+        .authors
+        .import(invalid, KeyKind::Public)
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("not a valid ed25519 public key"),
+        "{err}"
+    );
+
+    node.shutdown();
+
+    Ok(())
+}